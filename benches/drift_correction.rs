@@ -0,0 +1,31 @@
+//! BGMのずれの補正方式（`playback.drift_correction`）ごとのCPU負荷のベンチマーク
+//!
+//! 計測は `metrics_system::drift_correction_bench` にある（配備先では `--benchmark-drift-correction` で
+//! 同じ計測を行い、結果をメトリクスに記録できる）。補正中の速度（1.02倍）にした音声10秒分を、同期なしで
+//! 流し切るまでの時間を計測する。10秒に対する割合が、その方式で再生したときの1コアあたりのCPU使用率の
+//! 目安になる（例: 200msなら約2%）。配備先と同じハードウェアで実行して比べる。
+//! 要素のプラグインが無い方式は飛ばす。
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use gstreamer as gst;
+use tsukimi_speaker::config_system::config_main::DriftCorrection;
+use tsukimi_speaker::metrics_system::drift_correction_bench::{is_available, play_through};
+
+fn cpu_per_backend(c: &mut Criterion) {
+    gst::init().unwrap();
+    let mut group = c.benchmark_group("drift_correction/10s_of_audio");
+    group.sample_size(10);
+    for mode in DriftCorrection::ALL {
+        if !is_available(mode) {
+            eprintln!("Skipping {:?}: element not available", mode);
+            continue;
+        }
+        group.bench_function(format!("{:?}", mode), |b| {
+            b.iter_custom(|iters| (0..iters).map(|_| play_through(mode).unwrap()).sum())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, cpu_per_backend);
+criterion_main!(benches);
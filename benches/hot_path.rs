@@ -0,0 +1,115 @@
+//! BLE→音声の経路で、ビーコンの広告ごとに走る処理のベンチマーク
+//!
+//! Pi Zero級のハードウェアでの性能の劣化を配備前に見つけるため、合成したデバイスの流れで計測する。
+//! （展示室の想定: 場所のビーコン8台 + 来場者のスマートフォン64台、それぞれ毎秒10回程度の広告）
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tsukimi_speaker::audio_system::bgm_selection::{select_bgm, BgmChoice};
+use tsukimi_speaker::bluetooth_system::address::Address;
+use tsukimi_speaker::bluetooth_system::throttle::ThrottleCache;
+use tsukimi_speaker::clock_system::time_source::MockTimeSource;
+use tsukimi_speaker::connect_system::sound_map::SoundMap;
+use tsukimi_speaker::forwarding_system::coalescer::Coalescer;
+use tsukimi_speaker::messages::DeviceInfo;
+
+const LOCATION_BEACONS: usize = 8;
+const VISITOR_PHONES: usize = 64;
+/// 1回の計測で流す広告の数
+const STREAM_LEN: usize = 1_000;
+
+fn address(index: usize) -> Address {
+    format!("AA:BB:CC:DD:{:02X}:{:02X}", index / 256, index % 256).parse().unwrap()
+}
+
+fn device(index: usize, rssi: i16) -> DeviceInfo {
+    let now = Instant::now();
+    DeviceInfo {
+        address: address(index),
+        rssi,
+        last_seen: now,
+        received_at: now,
+        forwarded_at: None,
+        visitor_token: None,
+        local_name: None,
+        tx_power: None,
+        manufacturer_data: HashMap::new(),
+        service_data: HashMap::new(),
+    }
+}
+
+/// 合成したRSSIの揺らぎ（-95〜-40dBm、同じ値が続くこともある）
+fn rssi_at(step: usize, index: usize) -> i16 {
+    -40 - ((step * 7 + index * 13) % 56) as i16
+}
+
+/// 場所のビーコンと来場者のスマートフォンが順に広告する流れ
+fn device_stream() -> Vec<(usize, i16)> {
+    (0..STREAM_LEN).map(|step| {
+        let index = step % (LOCATION_BEACONS + VISITOR_PHONES);
+        (index, rssi_at(step / 3, index))
+    }).collect()
+}
+
+fn sound_map() -> SoundMap {
+    SoundMap {
+        sounds: (0..LOCATION_BEACONS).map(|i| (address(i), format!("location_{}.mp3", i))).collect(),
+        ..Default::default()
+    }
+}
+
+fn throttle_cache(c: &mut Criterion) {
+    let stream = device_stream();
+    let addresses: Vec<Address> = (0..LOCATION_BEACONS + VISITOR_PHONES).map(address).collect();
+    c.bench_function("throttle_cache/stream", |b| {
+        b.iter_batched(
+            || (ThrottleCache::new(), MockTimeSource::new()),
+            |(mut cache, time)| {
+                let mut sent = 0;
+                for &(index, rssi) in &stream {
+                    // 広告はおよそ1ミリ秒ごとに届く
+                    time.advance(Duration::from_millis(1));
+                    if cache.should_send(&addresses[index], rssi, &time) {
+                        sent += 1;
+                    }
+                }
+                black_box(sent)
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+fn coalescer(c: &mut Criterion) {
+    let devices: Vec<Arc<DeviceInfo>> = device_stream().into_iter().map(|(index, rssi)| Arc::new(device(index, rssi))).collect();
+    c.bench_function("coalescer/stream_then_snapshot", |b| {
+        b.iter_batched(
+            || (Coalescer::new(), devices.clone()),
+            |(mut coalescer, devices)| {
+                // 配信周期（100ms）ごとにスナップショットを取り出す
+                for chunk in devices.chunks(100) {
+                    for device_info in chunk {
+                        coalescer.push(Arc::clone(device_info));
+                    }
+                    black_box(coalescer.take_snapshot(Instant::now()));
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+}
+
+fn bgm_selection(c: &mut Criterion) {
+    let sound_map = sound_map();
+    let detected: HashMap<Address, Arc<DeviceInfo>> = (0..LOCATION_BEACONS + VISITOR_PHONES)
+        .map(|i| (address(i), Arc::new(device(i, rssi_at(0, i)))))
+        .collect();
+    c.bench_function("bgm_selection/select", |b| {
+        b.iter(|| matches!(select_bgm(black_box("location_0.mp3"), &detected, &sound_map), BgmChoice::Switch { .. }))
+    });
+}
+
+criterion_group!(benches, throttle_cache, coalescer, bgm_selection);
+criterion_main!(benches);
@@ -1,18 +1,32 @@
 use std::env;
-use tonic_prost_build::configure;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // 環境変数 `SKIP_PROTOC` が設定されている場合は、何もしないで終了
-    if env::var("SKIP_PROTOC").is_ok() {
-        println!("cargo:warning=Skipping protoc compilation because SKIP_PROTOC is set.");
+    println!("cargo::rustc-check-cfg=cfg(pregenerated_proto)");
+    println!("cargo:rerun-if-changed=proto");
+    println!("cargo:rerun-if-env-changed=SKIP_PROTOC");
+
+    // `server` フィーチャが無いスタンドアロン構成ではprotoを使わない
+    if env::var_os("CARGO_FEATURE_SERVER").is_none() {
+        return Ok(());
+    }
+
+    // `pregenerated-proto` フィーチャ、または環境変数 `SKIP_PROTOC` が設定されている場合は
+    // protocを呼ばずに、コミット済みの src/proto/proto.rs を使う
+    // （.protoを変更した場合は protoc のある環境で src/proto/proto.rs も再生成すること）
+    if env::var_os("CARGO_FEATURE_PREGENERATED_PROTO").is_some() || env::var("SKIP_PROTOC").is_ok() {
+        println!("cargo:warning=Using pre-generated proto stubs because protoc compilation is disabled.");
+        println!("cargo:rustc-cfg=pregenerated_proto");
         return Ok(());
     }
 
-    configure()
-        .out_dir("src/proto")
-        .compile_protos(
-            &["../TSUKIMKORO-2025/TSUKIMI_Backend/proto/device.proto", "../TSUKIMKORO-2025/TSUKIMI_Backend/proto/time.proto"],
-            &["../TSUKIMKORO-2025/TSUKIMI_Backend/proto"],
-        )?;
+    // リポジトリ内の proto/ からOUT_DIRへ生成する
+    // ステータスAPI・ログ・セッションの記録に人が読めるJSONで出せるよう、serdeのderiveを付ける
+    // （省略したフィールドはprotoと同じく既定値として読む）
+    tonic_prost_build::configure()
+        .type_attribute(".proto", "#[derive(serde::Serialize, serde::Deserialize)]")
+        .message_attribute(".proto", "#[serde(default)]")
+        .type_attribute(".proto.StreamDeviceInfoResponse.event", "#[serde(rename_all = \"snake_case\")]")
+        .type_attribute(".proto.FleetCommand.command", "#[serde(rename_all = \"snake_case\")]")
+        .compile_protos(&["proto/device.proto", "proto/time.proto"], &["proto"])?;
     Ok(())
-}
\ No newline at end of file
+}
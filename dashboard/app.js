@@ -0,0 +1,138 @@
+// ダッシュボード: /api/status を定期的に取得して表示する
+const POLL_INTERVAL_MS = 1000;
+
+// RSSIバーの表示範囲（dBm）
+const RSSI_MIN = -100;
+const RSSI_MAX = -30;
+
+function formatPosition(ms) {
+  const totalSeconds = Math.floor(ms / 1000);
+  const minutes = Math.floor(totalSeconds / 60);
+  const seconds = String(totalSeconds % 60).padStart(2, "0");
+  return `${minutes}:${seconds}`;
+}
+
+function setConnection(ok) {
+  const badge = document.getElementById("connection");
+  badge.textContent = ok ? "接続" : "切断";
+  badge.className = ok ? "badge ok" : "badge error";
+}
+
+// サーバーとの接続状態（/api/status の server・server_connection イベントの state）
+const SERVER_STATES = {
+  connecting: "接続試行中",
+  connected: "接続",
+  degraded: "接続 (WebSocket)",
+  offline: "長時間切断",
+};
+
+function renderServer(state) {
+  document.getElementById("server").textContent = state ? SERVER_STATES[state] || state : "なし";
+}
+
+function renderBeacons(beacons) {
+  const list = document.getElementById("beacons");
+  list.replaceChildren(
+    ...beacons.map((beacon) => {
+      const item = document.createElement("li");
+      const label = document.createElement("div");
+      label.className = "beacon-label";
+      const name = document.createElement("span");
+      const id = beacon.name ? `${beacon.name} ${beacon.address}` : beacon.address;
+      name.textContent = beacon.sound ? `${id} (${beacon.sound})` : id;
+      const rssi = document.createElement("span");
+      rssi.textContent = `${beacon.rssi} dBm`;
+      label.append(name, rssi);
+
+      const bar = document.createElement("div");
+      bar.className = "bar";
+      const fill = document.createElement("div");
+      fill.className = "bar-fill";
+      const ratio = (beacon.rssi - RSSI_MIN) / (RSSI_MAX - RSSI_MIN);
+      fill.style.width = `${Math.min(Math.max(ratio, 0), 1) * 100}%`;
+      bar.append(fill);
+
+      item.append(label, bar);
+      return item;
+    }),
+  );
+}
+
+function render(status) {
+  document.getElementById("sound").textContent = status.sound || "-";
+  document.getElementById("position").textContent = formatPosition(status.position_ms);
+  document.getElementById("drift").textContent = `${status.drift_ms.toFixed(1)} ms`;
+  document.getElementById("state").textContent = status.state;
+  document.getElementById("enabled").textContent = status.enabled
+    ? "有効"
+    : `無効 (全体: ${status.global_enabled ? "有効" : "無効"}, 個別: ${status.device_enabled ? "有効" : "無効"})`;
+  document.getElementById("volume").textContent = status.muted
+    ? "ミュート"
+    : `${Math.round(status.volume * 100)}%`;
+  renderServer(status.server);
+  renderBeacons(status.beacons);
+}
+
+async function refresh() {
+  try {
+    const response = await fetch("/api/status");
+    if (!response.ok) {
+      throw new Error(`HTTP ${response.status}`);
+    }
+    render(await response.json());
+    setConnection(true);
+  } catch (e) {
+    console.warn("Failed to fetch status", e);
+    setConnection(false);
+  }
+}
+
+// イベントログに残す件数
+const MAX_EVENTS = 50;
+
+// WebSocketが切れたときに再接続するまでの時間
+const RECONNECT_DELAY_MS = 3000;
+
+function appendEvent(message) {
+  const { type, event, ...fields } = message;
+  const item = document.createElement("li");
+  const time = new Date().toLocaleTimeString();
+  item.textContent = `${time} ${event} ${JSON.stringify(fields)}`;
+  const list = document.getElementById("events");
+  list.prepend(item);
+  while (list.children.length > MAX_EVENTS) {
+    list.lastChild.remove();
+  }
+}
+
+// /ws/events からローカルイベント（切り替え・SE・接続状態など）を受け取って表示する
+function connectEvents() {
+  const protocol = location.protocol === "https:" ? "wss:" : "ws:";
+  const socket = new WebSocket(`${protocol}//${location.host}/ws/events`);
+  socket.addEventListener("message", (e) => {
+    const message = JSON.parse(e.data);
+    if (message.type === "event") {
+      appendEvent(message);
+      // 次のポーリングを待たずに反映する
+      if (message.event === "server_connection") {
+        renderServer(message.state);
+      }
+    }
+  });
+  socket.addEventListener("close", () => setTimeout(connectEvents, RECONNECT_DELAY_MS));
+}
+
+for (const button of document.querySelectorAll("button[data-action]")) {
+  button.addEventListener("click", async () => {
+    try {
+      await fetch(button.dataset.action, { method: "POST" });
+    } catch (e) {
+      console.warn("Action failed", e);
+    }
+    refresh();
+  });
+}
+
+refresh();
+setInterval(refresh, POLL_INTERVAL_MS);
+connectEvents();
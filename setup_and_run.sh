@@ -107,6 +107,12 @@ sudo apt-get install -y \
     bluetooth \
     pi-bluetooth 2>&1 | tee -a "$LOG_FILE"
 
+# BGMのずれの補正にGStreamer 1.18以降の機能（INSTANT_RATE_CHANGEのシーク）を使う
+if ! pkg-config --atleast-version=1.18 gstreamer-1.0; then
+    log "エラー: GStreamer 1.18以降が必要です（現在: $(pkg-config --modversion gstreamer-1.0)）。Raspberry Pi OS bullseye以降を使ってください。"
+    exit 1
+fi
+
 # 2. Rustのインストール
 log "Step 2: Rustのインストール"
 if ! command -v rustc &> /dev/null; then
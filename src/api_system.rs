@@ -0,0 +1,3 @@
+pub mod api_main;
+pub mod dashboard;
+pub mod event_feed;
\ No newline at end of file
@@ -0,0 +1,402 @@
+use crate::api_system::{dashboard, event_feed};
+use crate::audio_system::audio_main::activation_se_request;
+use crate::messages::{ConnectionState, DeviceInfo, DeviceSnapshot, EnabledState, MixerCommand, SePlayRequest};
+use crate::audio_system::master_volume::MasterVolume;
+use crate::audio_system::playback_status::PlaybackStatus;
+use crate::audio_system::loudness::LoudnessSnapshot;
+use crate::audio_system::sync_quality::SyncQualitySnapshot;
+use crate::audio_system::test_tone::{play_test_tone, TestToneRequest};
+use crate::config_system::config_main::{ActivationSeConfig, ApiConfig};
+use crate::config_system::config_store::{ConfigLayer, ConfigSnapshot, ConfigStore};
+#[cfg(feature = "server")]
+use crate::connect_system::calibration::{CalibratedBeacon, CalibrationStore, DEFAULT_SAMPLE_DURATION, MAX_SAMPLE_DURATION};
+use crate::connect_system::enable_state;
+#[cfg(feature = "server")]
+use crate::connect_system::server_messages::LastServerMessages;
+use crate::bluetooth_system::address::Address;
+use crate::connect_system::location_context::LocationContext;
+use crate::connect_system::sound_map::SharedSoundMap;
+use crate::metrics_system::metrics_main::{metrics, LagReceiver};
+use crate::event_system::event_bus::EventBus;
+use crate::survey_system::survey_main::{Survey, SurveyReport};
+use anyhow::{Context, Result};
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, watch};
+use tracing::{info, instrument, warn};
+
+/// この時間受信していないビーコンはダッシュボードに表示しない
+const BEACON_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// APIのハンドラが参照する共有状態
+#[derive(Clone)]
+pub struct ApiState {
+    pub status_rx: watch::Receiver<PlaybackStatus>,
+    pub location_rx: watch::Receiver<LocationContext>,
+    /// サーバーとの接続状態（サーバー無しの構成では `None`）
+    pub connection_rx: watch::Receiver<Option<ConnectionState>>,
+    pub enabled_tx: watch::Sender<EnabledState>,
+    pub volume_tx: watch::Sender<MasterVolume>,
+    pub se_tx: mpsc::Sender<SePlayRequest>,
+    pub mixer_tx: mpsc::Sender<MixerCommand>,
+    /// テスト再生に使うSE
+    pub activation_se: ActivationSeConfig,
+    /// テストトーンを鳴らす出力先（BGMと同じシンクの記述）
+    pub test_tone_sink: String,
+    pub sound_map: Arc<SharedSoundMap>,
+    /// アドレスごとの最新のビーコン受信状況
+    pub beacons: Arc<Mutex<HashMap<Address, Arc<DeviceInfo>>>>,
+    /// イベントフィードの接続ごとに購読する
+    pub snapshot_tx: broadcast::Sender<Arc<DeviceSnapshot>>,
+    pub events: EventBus,
+    /// 調査モードの状態（調査モードでなければ `None`）
+    pub survey: Option<Arc<Survey>>,
+    /// 層を重ねた設定（ローカルAPIでの変更は上書きのファイルに保存する）
+    pub config_store: Arc<ConfigStore>,
+    /// インタラクションの閾値のキャリブレーション結果
+    #[cfg(feature = "server")]
+    pub calibration: Arc<CalibrationStore>,
+    /// サーバーから最後に受け取った設定系のメッセージ
+    #[cfg(feature = "server")]
+    pub server_messages_rx: watch::Receiver<LastServerMessages>,
+}
+
+/// ビーコン1つの受信状況
+#[derive(Debug, Serialize)]
+struct BeaconStatus {
+    address: Address,
+    rssi: i16,
+    age_ms: u64,
+    /// ビーコンに割り当てられた音源（未登録なら `None`）
+    sound: Option<String>,
+    name: Option<String>,
+    tx_power: Option<i16>,
+    /// カンパニーID -> データ（16進数）
+    manufacturer_data: BTreeMap<u16, String>,
+    /// サービスUUID -> データ（16進数）
+    service_data: BTreeMap<String, String>,
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// `/api/status` のレスポンス
+#[derive(Debug, Serialize)]
+struct StatusResponse {
+    sound: String,
+    position_ms: u64,
+    drift_ms: f64,
+    /// 同期の品質（スコアの低いユニットから手当てする）
+    sync_quality: SyncQualitySnapshot,
+    /// 出力のラウドネス（LUFSの近似。会場のエンジニアがユニット間のレベルを揃える用）
+    loudness: LoudnessSnapshot,
+    state: String,
+    enabled: bool,
+    global_enabled: bool,
+    device_enabled: bool,
+    volume: f64,
+    muted: bool,
+    /// 最も近いロケーション
+    location: LocationContext,
+    /// サーバーとの接続状態
+    server: Option<ConnectionState>,
+    /// RSSIの強い順
+    beacons: Vec<BeaconStatus>,
+    /// すべての層を重ねた設定（層ごとの内容は `/api/config`）
+    config: serde_json::Value,
+    /// サーバーから最後に受け取ったSoundSetting・LocationUpdate・MoonlightUpdate（protoと同じJSON）
+    #[cfg(feature = "server")]
+    server_messages: LastServerMessages,
+}
+
+async fn status(State(state): State<ApiState>) -> Json<StatusResponse> {
+    let playback = state.status_rx.borrow().clone();
+    let enabled = *state.enabled_tx.borrow();
+    let volume = *state.volume_tx.borrow();
+
+    let mut beacons: Vec<BeaconStatus> = {
+        let beacons = state.beacons.lock().unwrap();
+        let sound_map = state.sound_map.load();
+        beacons
+            .values()
+            .filter(|device| device.last_seen.elapsed() < BEACON_TIMEOUT)
+            .map(|device| BeaconStatus {
+                address: device.address.clone(),
+                rssi: device.rssi,
+                age_ms: device.last_seen.elapsed().as_millis() as u64,
+                sound: sound_map.sounds.get(&device.address).cloned(),
+                name: device.local_name.clone(),
+                tx_power: device.tx_power,
+                manufacturer_data: device.manufacturer_data.iter().map(|(id, data)| (*id, hex(data))).collect(),
+                service_data: device.service_data.iter().map(|(uuid, data)| (uuid.to_string(), hex(data))).collect(),
+            })
+            .collect()
+    };
+    beacons.sort_by_key(|beacon| std::cmp::Reverse(beacon.rssi));
+
+    Json(StatusResponse {
+        sound: playback.sound,
+        position_ms: playback.position_ns / 1_000_000,
+        drift_ms: playback.drift_ns as f64 / 1e6,
+        sync_quality: playback.sync_quality,
+        loudness: playback.loudness,
+        state: format!("{:?}", playback.state),
+        enabled: enabled.enabled,
+        global_enabled: enabled.global_enabled,
+        device_enabled: enabled.device_enabled,
+        volume: volume.volume,
+        muted: volume.muted,
+        location: state.location_rx.borrow().clone(),
+        server: *state.connection_rx.borrow(),
+        beacons,
+        config: state.config_store.effective_value(),
+        #[cfg(feature = "server")]
+        server_messages: state.server_messages_rx.borrow().clone(),
+    })
+}
+
+async fn toggle_enabled(State(state): State<ApiState>) -> StatusCode {
+    info!("Dashboard: toggling device enabled flag");
+    enable_state::toggle_device(&state.enabled_tx);
+    StatusCode::NO_CONTENT
+}
+
+async fn toggle_mute(State(state): State<ApiState>) -> StatusCode {
+    info!("Dashboard: toggling mute");
+    state.volume_tx.send_modify(|volume| volume.muted = !volume.muted);
+    StatusCode::NO_CONTENT
+}
+
+async fn test_se(State(state): State<ApiState>) -> StatusCode {
+    info!("Dashboard: playing test SE");
+    let request = activation_se_request(&state.activation_se, false);
+    match state.se_tx.send(request).await {
+        Ok(()) => StatusCode::NO_CONTENT,
+        Err(e) => {
+            warn!("Failed to send test SE request: {}", e);
+            StatusCode::SERVICE_UNAVAILABLE
+        }
+    }
+}
+
+/// 配線・ゲインの確認用にテストトーンを鳴らし、鳴り終わってから返す
+async fn test_tone(State(state): State<ApiState>, request: Option<Json<TestToneRequest>>) -> Result<StatusCode, (StatusCode, String)> {
+    // ボタン代わりに本文なしで呼べるようにする
+    let Json(request) = request.unwrap_or_default();
+    info!(?request, "Dashboard: playing test tone");
+    let sink = state.test_tone_sink.clone();
+    match tokio::task::spawn_blocking(move || play_test_tone(&request, &sink)).await {
+        Ok(Ok(())) => Ok(StatusCode::NO_CONTENT),
+        Ok(Err(e)) => {
+            warn!("Test tone failed: {:?}", e);
+            Err((StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e)))
+        }
+        Err(e) => Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    }
+}
+
+async fn play_se(State(state): State<ApiState>, Json(request): Json<SePlayRequest>) -> StatusCode {
+    info!(?request, "Dashboard: playing SE");
+    match state.se_tx.send(request).await {
+        Ok(()) => StatusCode::NO_CONTENT,
+        Err(e) => {
+            warn!("Failed to send SE request: {}", e);
+            StatusCode::SERVICE_UNAVAILABLE
+        }
+    }
+}
+
+async fn enabled(State(state): State<ApiState>) -> Json<EnabledState> {
+    Json(*state.enabled_tx.borrow())
+}
+
+async fn set_mixer(State(state): State<ApiState>, Json(command): Json<MixerCommand>) -> StatusCode {
+    info!(?command, "Dashboard: changing mixer bus");
+    match state.mixer_tx.send(command).await {
+        Ok(()) => StatusCode::NO_CONTENT,
+        Err(e) => {
+            warn!("Failed to send mixer command: {}", e);
+            StatusCode::SERVICE_UNAVAILABLE
+        }
+    }
+}
+
+/// `/api/survey/waypoint` のリクエスト
+#[derive(Debug, Deserialize)]
+struct WaypointRequest {
+    /// 地点の名前（`null` で解除）
+    label: Option<String>,
+}
+
+async fn survey_report(State(state): State<ApiState>) -> Result<Json<SurveyReport>, StatusCode> {
+    let survey = state.survey.as_ref().ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(survey.report(&state.sound_map)))
+}
+
+async fn set_waypoint(State(state): State<ApiState>, Json(request): Json<WaypointRequest>) -> StatusCode {
+    let Some(survey) = &state.survey else {
+        return StatusCode::NOT_FOUND;
+    };
+    info!(label = ?request.label, "Dashboard: setting survey waypoint");
+    survey.set_waypoint(request.label);
+    StatusCode::NO_CONTENT
+}
+
+async fn config_layers(State(state): State<ApiState>) -> Json<ConfigSnapshot> {
+    Json(state.config_store.snapshot())
+}
+
+/// 設定のローカルの層を変更して保存する（`null` の項目は上書きをやめる。多くの項目は次回の起動から効く）
+async fn update_config(State(state): State<ApiState>, Json(patch): Json<serde_json::Value>) -> Result<Json<ConfigSnapshot>, (StatusCode, String)> {
+    info!(%patch, "Dashboard: updating config");
+    match state.config_store.update(ConfigLayer::Local, &patch) {
+        Ok(()) => Ok(Json(state.config_store.snapshot())),
+        Err(e) => {
+            warn!("Config update rejected: {:?}", e);
+            Err((StatusCode::BAD_REQUEST, format!("{:#}", e)))
+        }
+    }
+}
+
+/// `/api/calibration` のリクエスト
+#[cfg(feature = "server")]
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct CalibrationRequest {
+    /// 対象のビーコン（省略時はRSSIが最も強いインタラクションできる場所）
+    address: Option<String>,
+    /// RSSIを集める時間（ミリ秒。省略時は10秒）
+    duration_ms: Option<u64>,
+}
+
+#[cfg(feature = "server")]
+async fn calibrated_beacons(State(state): State<ApiState>) -> Json<Vec<CalibratedBeacon>> {
+    Json(state.calibration.beacons())
+}
+
+/// インタラクションする位置に立った状態で呼ぶと、RSSIを集め終えるまで待ってから結果を返す
+#[cfg(feature = "server")]
+async fn calibrate(
+    State(state): State<ApiState>,
+    request: Option<Json<CalibrationRequest>>,
+) -> Result<Json<CalibratedBeacon>, (StatusCode, String)> {
+    // ボタン代わりに本文なしで呼べるようにする
+    let Json(request) = request.unwrap_or_default();
+    let address = match request.address.as_deref().map(str::parse::<Address>).transpose() {
+        Ok(address) => address,
+        Err(e) => return Err((StatusCode::BAD_REQUEST, e.to_string())),
+    };
+    let duration = request.duration_ms.map_or(DEFAULT_SAMPLE_DURATION, Duration::from_millis).min(MAX_SAMPLE_DURATION);
+    info!(?address, duration_ms = duration.as_millis() as u64, "Dashboard: calibrating interaction threshold");
+    match state.calibration.calibrate(state.snapshot_tx.subscribe(), &state.sound_map, address, duration).await {
+        Ok(beacon) => Ok(Json(beacon)),
+        Err(e) => {
+            warn!("Calibration failed: {:?}", e);
+            Err((StatusCode::CONFLICT, format!("{:#}", e)))
+        }
+    }
+}
+
+/// DeviceSnapshotからビーコンごとの最新の受信状況を集計する
+async fn track_beacons(mut rx: broadcast::Receiver<Arc<DeviceSnapshot>>, beacons: Arc<Mutex<HashMap<Address, Arc<DeviceInfo>>>>) {
+    loop {
+        match rx.recv().await {
+            Ok(snapshot) => {
+                let mut beacons = beacons.lock().unwrap();
+                // 来場者の端末はビーコンではないため表示しない
+                for device in snapshot.devices.iter().filter(|device| device.visitor_token.is_none()) {
+                    beacons.insert(device.address.clone(), Arc::clone(device));
+                }
+                beacons.retain(|_, device| device.last_seen.elapsed() < BEACON_TIMEOUT);
+            }
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                metrics().record_lag(LagReceiver::Api, skipped);
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+/// 現地でのデバッグ用に、状態取得・操作のHTTP APIとダッシュボードを提供するタスク
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip_all)]
+pub async fn api_main(
+    config: ApiConfig,
+    activation_se: ActivationSeConfig,
+    test_tone_sink: String,
+    snapshot_tx: broadcast::Sender<Arc<DeviceSnapshot>>,
+    events: EventBus,
+    status_rx: watch::Receiver<PlaybackStatus>,
+    location_rx: watch::Receiver<LocationContext>,
+    connection_rx: watch::Receiver<Option<ConnectionState>>,
+    enabled_tx: watch::Sender<EnabledState>,
+    volume_tx: watch::Sender<MasterVolume>,
+    se_tx: mpsc::Sender<SePlayRequest>,
+    mixer_tx: mpsc::Sender<MixerCommand>,
+    sound_map: Arc<SharedSoundMap>,
+    survey: Option<Arc<Survey>>,
+    config_store: Arc<ConfigStore>,
+    #[cfg(feature = "server")] calibration: Arc<CalibrationStore>,
+    #[cfg(feature = "server")] server_messages_rx: watch::Receiver<LastServerMessages>,
+) -> Result<()> {
+    let beacons = Arc::new(Mutex::new(HashMap::new()));
+    let rx = snapshot_tx.subscribe();
+    let state = ApiState {
+        status_rx,
+        location_rx,
+        connection_rx,
+        enabled_tx,
+        volume_tx,
+        se_tx,
+        mixer_tx,
+        activation_se,
+        test_tone_sink,
+        sound_map,
+        beacons: Arc::clone(&beacons),
+        snapshot_tx,
+        events,
+        survey,
+        config_store,
+        #[cfg(feature = "server")]
+        calibration,
+        #[cfg(feature = "server")]
+        server_messages_rx,
+    };
+
+    let app = Router::new()
+        .route("/", get(dashboard::index))
+        .route("/assets/{*path}", get(dashboard::asset))
+        .route("/api/status", get(status))
+        .route("/api/enabled", get(enabled))
+        .route("/api/enabled/toggle", post(toggle_enabled))
+        .route("/api/mute/toggle", post(toggle_mute))
+        .route("/api/test-se", post(test_se))
+        .route("/api/test-tone", post(test_tone))
+        .route("/api/se", post(play_se))
+        .route("/api/mixer", post(set_mixer))
+        .route("/api/survey", get(survey_report))
+        .route("/api/survey/waypoint", post(set_waypoint))
+        .route("/api/config", get(config_layers).patch(update_config))
+        .route("/ws/events", get(event_feed::events));
+    #[cfg(feature = "server")]
+    let app = app.route("/api/calibration", get(calibrated_beacons).post(calibrate));
+    let app = app.with_state(state);
+
+    let listener = tokio::net::TcpListener::bind(&config.bind)
+        .await
+        .with_context(|| format!("Failed to bind local API: {}", config.bind))?;
+    info!(bind = %config.bind, "Local API and dashboard started");
+
+    tokio::select! {
+        result = axum::serve(listener, app) => result.context("Local API server failed")?,
+        _ = track_beacons(rx, beacons) => {}
+    }
+    Ok(())
+}
@@ -0,0 +1,36 @@
+use axum::extract::Path;
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use rust_embed::RustEmbed;
+
+/// ダッシュボードの静的ファイル（ビルド時にバイナリへ埋め込む）
+#[derive(RustEmbed)]
+#[folder = "dashboard/"]
+struct Assets;
+
+/// 拡張子からContent-Typeを決める（ダッシュボードで使う種類のみ）
+fn content_type(path: &str) -> &'static str {
+    match path.rsplit('.').next() {
+        Some("html") => "text/html; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        _ => "application/octet-stream",
+    }
+}
+
+fn serve(path: &str) -> Response {
+    match Assets::get(path) {
+        Some(file) => ([(header::CONTENT_TYPE, content_type(path))], file.data).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+pub async fn index() -> Response {
+    serve("index.html")
+}
+
+pub async fn asset(Path(path): Path<String>) -> Response {
+    serve(&path)
+}
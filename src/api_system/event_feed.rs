@@ -0,0 +1,127 @@
+use crate::api_system::api_main::ApiState;
+use crate::audio_system::playback_status::PlaybackStatus;
+use crate::event_system::event_bus::LocalEvent;
+use crate::messages::DeviceSnapshot;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::State;
+use axum::response::Response;
+use serde::Serialize;
+use std::sync::Arc;
+use tokio::sync::{broadcast, watch};
+use tracing::{debug, info, warn};
+
+/// イベントフィードで送るデバイス1つの受信状況
+#[derive(Debug, Serialize)]
+struct FeedDevice {
+    address: String,
+    rssi: i16,
+    /// 来場者の端末（コンパニオンアプリ）かどうか
+    visitor: bool,
+}
+
+/// `/ws/events` で送るメッセージ（`type` で種類を区別するJSON）
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum FeedMessage {
+    /// DeviceSnapshot（前回以降に更新があったデバイスのみ）
+    Devices { devices: Vec<FeedDevice> },
+    /// 再生状況（オーディオループが公開するたび）
+    Playback {
+        sound: String,
+        position_ms: u64,
+        drift_ms: f64,
+        state: String,
+        enabled: bool,
+    },
+    /// BGM切り替え・SE再生・サーバー接続などのローカルイベント
+    Event {
+        #[serde(flatten)]
+        event: LocalEvent,
+    },
+    /// 受信が追いつかず読み飛ばしたメッセージ数
+    Lagged { skipped: u64 },
+}
+
+impl FeedMessage {
+    fn devices(snapshot: &DeviceSnapshot) -> Self {
+        let devices = snapshot
+            .devices
+            .iter()
+            .map(|device| FeedDevice {
+                address: device.address.to_string(),
+                rssi: device.rssi,
+                visitor: device.visitor_token.is_some(),
+            })
+            .collect();
+        FeedMessage::Devices { devices }
+    }
+
+    fn playback(status: &PlaybackStatus) -> Self {
+        FeedMessage::Playback {
+            sound: status.sound.clone(),
+            position_ms: status.position_ns / 1_000_000,
+            drift_ms: status.drift_ns as f64 / 1e6,
+            state: format!("{:?}", status.state),
+            enabled: status.enabled,
+        }
+    }
+}
+
+/// 内部イベントをJSONで流し続けるWebSocketのエンドポイント
+pub async fn events(ws: WebSocketUpgrade, State(state): State<ApiState>) -> Response {
+    // アップグレード前に購読し、接続直後のイベントも取りこぼさないようにする
+    let snapshot_rx = state.snapshot_tx.subscribe();
+    let event_rx = state.events.subscribe("event_feed");
+    let status_rx = state.status_rx.clone();
+    ws.on_upgrade(move |socket| feed(socket, snapshot_rx, event_rx, status_rx))
+}
+
+async fn feed(
+    mut socket: WebSocket,
+    mut snapshot_rx: broadcast::Receiver<Arc<DeviceSnapshot>>,
+    mut event_rx: broadcast::Receiver<LocalEvent>,
+    mut status_rx: watch::Receiver<PlaybackStatus>,
+) {
+    info!("Event feed client connected");
+    let mut pending = Some(FeedMessage::playback(&status_rx.borrow_and_update()));
+
+    loop {
+        let message = match pending.take() {
+            Some(message) => message,
+            None => tokio::select! {
+                snapshot = snapshot_rx.recv() => match snapshot {
+                    Ok(snapshot) => FeedMessage::devices(&snapshot),
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => FeedMessage::Lagged { skipped },
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                event = event_rx.recv() => match event {
+                    Ok(event) => FeedMessage::Event { event },
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => FeedMessage::Lagged { skipped },
+                    Err(broadcast::error::RecvError::Closed) => break,
+                },
+                changed = status_rx.changed() => match changed {
+                    Ok(()) => FeedMessage::playback(&status_rx.borrow_and_update()),
+                    Err(_) => break,
+                },
+                // クライアントからのメッセージは使わないが、切断を検知するために読む
+                incoming = socket.recv() => match incoming {
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => continue,
+                },
+            },
+        };
+
+        let text = match serde_json::to_string(&message) {
+            Ok(text) => text,
+            Err(e) => {
+                warn!("Failed to serialize event feed message: {}", e);
+                continue;
+            }
+        };
+        if let Err(e) = socket.send(Message::Text(text.into())).await {
+            debug!("Event feed send failed: {}", e);
+            break;
+        }
+    }
+    info!("Event feed client disconnected");
+}
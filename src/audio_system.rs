@@ -1 +1,26 @@
-pub(crate) mod audio_main;
\ No newline at end of file
+pub mod asset_variant;
+pub mod audio_main;
+pub mod audio_sink;
+pub mod audio_supervisor;
+pub mod bgm_override;
+pub mod bgm_selection;
+pub mod connection_cue;
+pub mod drift_correction;
+pub mod error_tone;
+pub mod live_stream;
+pub mod loudness;
+pub mod master_volume;
+pub mod mixer;
+pub mod pipeline_builder;
+pub mod pipeline_controller;
+pub mod playback_fsm;
+pub mod playback_status;
+pub mod preset;
+pub mod remote_source;
+pub mod se_player;
+pub mod sink_hotplug;
+pub mod sink_suspend;
+pub mod sound_switch;
+pub mod sync_quality;
+pub mod test_tone;
+pub mod watchdog;
\ No newline at end of file
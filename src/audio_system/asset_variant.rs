@@ -0,0 +1,26 @@
+use std::path::Path;
+use tokio::sync::watch;
+
+/// サーバーから指定された言語を配信するwatchチャンネルを作成する（`None` は設定ファイルの言語）
+pub fn channel() -> (watch::Sender<Option<String>>, watch::Receiver<Option<String>>) {
+    watch::channel(None)
+}
+
+/// 言語ごとの音源のバリアント（`tsukimi-main_1.ja.mp3` など）を選ぶ
+///
+/// バリアントのファイルがなければ元のファイルを使う。URL・ライブ配信の音源はそのまま。
+pub fn localized(sound: &str, language: Option<&str>) -> String {
+    let Some(language) = language.filter(|language| !language.is_empty() && !sound.contains("://")) else {
+        return sound.to_string();
+    };
+    let path = Path::new(sound);
+    let (Some(stem), Some(extension)) = (path.file_stem(), path.extension()) else {
+        return sound.to_string();
+    };
+    let variant = path.with_file_name(format!("{}.{}.{}", stem.to_string_lossy(), language, extension.to_string_lossy()));
+    if variant.exists() {
+        variant.to_string_lossy().into_owned()
+    } else {
+        sound.to_string()
+    }
+}
@@ -1,38 +1,137 @@
-use crate::proto::proto::SoundSetting;
-use crate::DeviceInfo;
+use crate::audio_system::asset_variant::localized;
+use crate::audio_system::bgm_override::BgmOverride;
+use crate::audio_system::bgm_selection::{select_bgm, BgmChoice};
+use crate::audio_system::connection_cue::ConnectionCue;
+use crate::audio_system::drift_correction::{self, TempoControl};
+use crate::audio_system::pipeline_controller::PipelineController;
+use crate::audio_system::watchdog::AudioHeartbeat;
+use crate::audio_system::error_tone::{spawn_error_tone, FatalSignal};
+use crate::audio_system::master_volume::MasterVolume;
+use crate::audio_system::pipeline_builder::PipelineBuilder;
+use crate::audio_system::playback_fsm::{PlaybackFsm, PlaybackState, SeState};
+use crate::audio_system::playback_status::PlaybackStatus;
+use crate::audio_system::preset::preset_description;
+use crate::audio_system::mixer::Mixer;
+use crate::audio_system::live_stream::{is_live, live_source_description};
+use crate::audio_system::loudness::{LoudnessMeter, LEVEL_ELEMENT};
+use crate::audio_system::remote_source::{is_remote, RemoteCache};
+use crate::audio_system::se_player::SePlayer;
+use crate::audio_system::sink_hotplug::SinkRouting;
+use crate::audio_system::sink_suspend::SuspendDetector;
+use crate::audio_system::sound_switch::SoundSwitch;
+use crate::audio_system::sync_quality::SyncQuality;
+use crate::bluetooth_system::address::Address;
+use crate::clock_system::clock_main::{Clock, ShowTime};
+use crate::clock_system::time_source::SharedTimeSource;
+use crate::config_system::config_main::{ActivationSeConfig, AudioPreset, ChimeConfig, DisableMode, DriftCorrection, LiveStreamConfig, MixBus, PlaybackConfig};
+use crate::messages::{BgmOverrideRequest, CommandFailure, CommandKind, CommandOutcome, DeviceInfo, DeviceSnapshot, EnabledState, MixerCommand, SePlayRequest};
+use crate::connect_system::sound_map::{SharedSoundMap, SoundMap};
+use crate::metrics_system::metrics_main::{metrics, LagReceiver};
+#[cfg(feature = "server")]
+pub use crate::proto::proto::SoundSetting;
+use crate::schedule_system::schedule_main::ProgramOverride;
+use crate::shutdown_system::shutdown_controller::{ShutdownController, ShutdownPhase};
+use crate::event_system::event_bus::{EventBus, LocalEvent};
 use anyhow::{anyhow, Result};
-use glib::object::ObjectExt;
 use gstreamer as gst;
 use gstreamer::prelude::*;
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
-use tokio::sync::{broadcast, mpsc};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::error::TryRecvError;
+use tokio::sync::{broadcast, mpsc, watch};
 use tracing::{debug, error, info, instrument, warn};
 
-// SE再生リクエスト
-#[derive(Debug, Clone)]
-pub struct SePlayRequest {
-    pub file_path: String,
-}
+/// スタンドアロン構成（`server` フィーチャーなし）ではサウンド設定が届くことはない
+#[cfg(not(feature = "server"))]
+#[derive(Debug)]
+pub enum SoundSetting {}
 
 // 音源切り替えリクエスト
 struct SwitchRequest {
+    /// 切り替え判断ごとに増える世代番号（古い判断の結果を破棄するため）
+    generation: u64,
     desired_sound: String,
+    /// 切り替え先のロケーションの音質の調整
+    preset: AudioPreset,
     seek_position_ns: u64,
+    /// 切り替えを判断した時刻（レイテンシ計測用）
+    requested_at: Instant,
+    /// RSSIによる切り替えの場合、きっかけになった広告の時刻
+    reaction: Option<ReactionTrace>,
+    span: tracing::Span,
+}
+
+// 構築ワーカーで準備が完了した切り替え先パイプライン
+struct PreparedSwitch {
+    pipeline: PipelineState,
+    requested_at: Instant,
+    reaction: Option<ReactionTrace>,
+    span: tracing::Span,
+}
+
+/// 広告を受け取ってから切り替えを判断するまでの各時刻（広告から音が変わるまでの遅延の計測用）
+#[derive(Debug, Clone, Copy)]
+struct ReactionTrace {
+    /// BLEのイベントを受け取った時刻
+    received_at: Instant,
+    /// 転送タスクが配信した時刻
+    forwarded_at: Instant,
+    /// 切り替えを判断した時刻
+    decided_at: Instant,
+}
+
+impl ReactionTrace {
+    fn new(device_info: &DeviceInfo, decided_at: Instant) -> Option<Self> {
+        Some(Self { received_at: device_info.received_at, forwarded_at: device_info.forwarded_at?, decided_at })
+    }
+
+    /// 新しい音源の再生を始めた時点で、全体と段階ごとの遅延を記録する
+    fn observe(&self, playing_at: Instant) {
+        let forward = self.forwarded_at.saturating_duration_since(self.received_at);
+        let select = self.decided_at.saturating_duration_since(self.forwarded_at);
+        let switch = playing_at.saturating_duration_since(self.decided_at);
+        let total = playing_at.saturating_duration_since(self.received_at);
+        metrics().reaction_forward.observe(forward);
+        metrics().reaction_select.observe(select);
+        metrics().reaction_total.observe(total);
+        info!(
+            forward_ms = forward.as_millis() as u64,
+            select_ms = select.as_millis() as u64,
+            switch_ms = switch.as_millis() as u64,
+            total_ms = total.as_millis() as u64,
+            "Advertisement-to-audio reaction latency"
+        );
+    }
+}
+
+/// 設定された有効化SEを再生するリクエストを作る
+pub fn activation_se_request(config: &ActivationSeConfig, priority: bool) -> SePlayRequest {
+    SePlayRequest { file_path: config.file.clone(), priority, gain: Some(config.gain), command_id: None }
 }
 
-// 再生状態を管理するためのenum
-enum PlaybackState {
-    WaitingForFirstSync,
-    Playing,
+/// 切り替え判断から再生開始までの目標時間
+const SWITCH_LATENCY_BUDGET: Duration = Duration::from_millis(300);
+
+/// 状態遷移・シークを待つ間、バスのエラーや経過時間を確かめる間隔
+const STATE_WAIT_SLICE: Duration = Duration::from_millis(20);
+
+/// 時計自体の誤差を理由に補正を見送るずれの上限（これを超えるずれは時計の誤差が大きくても補正する）
+const MAX_CLOCK_TOLERANCE_NS: u64 = 100_000_000;
+
+/// 補正せずに等速で再生するずれの範囲（ナノ秒）
+///
+/// 設定の不感帯と、時計自体の誤差（システムのNTPでは±20ms）の大きい方。時計の誤差は同期が
+/// 古くなるほど大きくなるため `MAX_CLOCK_TOLERANCE_NS` で頭打ちにし、ずれが放置され続けないようにする。
+fn drift_tolerance_ns(deadband_ns: u64, clock_uncertainty_ns: Option<u64>) -> u64 {
+    deadband_ns.max(clock_uncertainty_ns.unwrap_or(0).min(MAX_CLOCK_TOLERANCE_NS))
 }
 
 struct PipelineState {
     pipeline: gst::Pipeline,
     bus: gst::Bus,
-    pitch: Option<gst::Element>,
-    volume: gst::Element,
+    control: PipelineController,
 }
 
 impl Drop for PipelineState {
@@ -45,26 +144,54 @@ impl Drop for PipelineState {
     }
 }
 
-fn sink_name() -> &'static str {
-    #[cfg(target_os = "linux")]
-    { "pulsesink" }
-    #[cfg(not(target_os = "linux"))]
-    { "autoaudiosink" }
+/// BGMの音源の種類（ローカルファイル・URL・ライブ配信）に応じてソース要素を選ぶ
+#[derive(Clone)]
+struct SoundSources {
+    remote: RemoteCache,
+    live: LiveStreamConfig,
+    /// BGMの出力先（設定したデバイスが抜けている間は既定の出力）
+    sink: Arc<SinkRouting>,
+    /// サーバー時刻とのずれの補正方式
+    drift_correction: DriftCorrection,
+    /// 構築したパイプラインを監視側に登録する
+    heartbeat: Arc<AudioHeartbeat>,
+    /// durationの問い合わせの期限に使う時刻
+    time: SharedTimeSource,
 }
 
-fn build_pipeline(sound_path: &str) -> Result<PipelineState> {
-    // ファイルの存在確認
-    if !std::path::Path::new(sound_path).exists() {
+impl SoundSources {
+    fn description(&self, sound: &str) -> String {
+        if is_live(sound) {
+            live_source_description(sound, &self.live)
+        } else {
+            self.remote.source_description(sound)
+        }
+    }
+}
+
+/// 音源を割り当てているロケーションのplace_typeのプリセット（見つからなければ調整なし）
+fn preset_for(sound: &str, sound_map: &SoundMap, presets: &BTreeMap<String, AudioPreset>) -> AudioPreset {
+    sound_map
+        .address_of(sound)
+        .and_then(|address| sound_map.place_types.get(address))
+        .and_then(|place_type| presets.get(place_type))
+        .cloned()
+        .unwrap_or_default()
+}
+
+fn build_pipeline(sound_path: &str, preset: &AudioPreset, sources: &SoundSources) -> Result<PipelineState> {
+    // ファイルの存在確認（URL・ライブ配信の音源は再生時に取得する）
+    if is_missing_local_asset(sound_path) {
         return Err(anyhow!("Audio file not found: {}", sound_path));
     }
 
-    let sink = sink_name();
-    // pitchプラグインの前にqueueを追加して、十分なバッファサイズを確保
-    // これによりSoundTouchライブラリのFIRFilterのアサーションエラーを回避
     let pipeline_str = format!(
-        "filesrc name=src location={} ! decodebin ! audioconvert ! audioresample ! volume name=vol ! audioconvert ! capsfilter caps=\"audio/x-raw,format=F32LE,rate=44100,channels=2\" ! queue max-size-buffers=100 max-size-time=1000000000 ! pitch name=pch ! audioconvert ! audioresample ! queue2 max-size-buffers=0 max-size-bytes=0 max-size-time=200000000 use-buffering=true ! {}",
-        sound_path,
-        sink
+        "{} ! audioconvert ! audioresample ! volume name=vol ! audioconvert ! capsfilter caps=\"audio/x-raw,format=F32LE,rate=44100,channels=2\"{}{} ! audioconvert ! audioresample ! queue2 max-size-buffers=0 max-size-bytes=0 max-size-time=200000000 use-buffering=true{} ! {}",
+        sources.description(sound_path),
+        preset_description(preset),
+        drift_correction::element_description(sources.drift_correction),
+        LEVEL_ELEMENT,
+        sources.sink.description()
     );
 
     debug!("Building pipeline: {}", pipeline_str);
@@ -74,7 +201,9 @@ fn build_pipeline(sound_path: &str) -> Result<PipelineState> {
         .map_err(|_| anyhow!("Failed to downcast to Pipeline"))?;
     let bus = pipeline.bus().ok_or_else(|| anyhow!("Failed to get bus from pipeline"))?;
     let volume = pipeline.by_name("vol").ok_or_else(|| anyhow!("volume not found"))?;
-    let pitch = pipeline.by_name("pch");
+    sources.heartbeat.register(&pipeline);
+    let tempo = TempoControl::new(sources.drift_correction, &pipeline);
+    let control = PipelineController::new(pipeline.clone(), volume, tempo, sources.time.clone());
 
     // バスからエラーメッセージをチェック
     if let Some(msg) = bus.timed_pop_filtered(gst::ClockTime::ZERO, &[gst::MessageType::Error]) {
@@ -83,15 +212,19 @@ fn build_pipeline(sound_path: &str) -> Result<PipelineState> {
         }
     }
 
-    Ok(PipelineState { pipeline, bus, pitch, volume })
+    Ok(PipelineState { pipeline, bus, control })
 }
 
-fn wait_for_state(pipeline: &gst::Pipeline, target: gst::State, timeout: Duration, label: &str) -> bool {
+/// パイプラインが目的の状態になるまで待つ（オーディオスレッド・構築ワーカー上で呼ぶこと）
+///
+/// 状態遷移の完了はGStreamerの `get_state` で待つため、遷移し終えた時点ですぐに戻る。
+pub(crate) fn wait_for_state(pipeline: &gst::Pipeline, target: gst::State, timeout: Duration, label: &str) -> bool {
     let start = Instant::now();
     let bus = pipeline.bus();
 
     loop {
-        if Instant::now().duration_since(start) > timeout {
+        let elapsed = start.elapsed();
+        if elapsed > timeout {
             error!(?target, label, "Timeout waiting for state");
 
             // バスからエラーメッセージを確認
@@ -121,14 +254,16 @@ fn wait_for_state(pipeline: &gst::Pipeline, target: gst::State, timeout: Duratio
             }
         }
 
-        let (ret, current, pending) = pipeline.state(gst::ClockTime::from_mseconds(0));
+        // 遷移中はエラーを確かめられるよう、短い間隔で区切って待つ
+        let wait = STATE_WAIT_SLICE.min(timeout - elapsed);
+        let (ret, current, pending) = pipeline.state(gst::ClockTime::from_nseconds(wait.as_nanos() as u64));
         match (ret, current, pending) {
             (Ok(_), c, gst::State::VoidPending) if c == target => {
                 debug!(?target, label, "Reached target state");
                 return true;
             }
             (Ok(_), _c, _p) => {
-                // 状態遷移中、ポーリング間隔を短縮
+                // 状態遷移中（次の区切りまで待ち続ける）
             }
             (Err(e), c, p) => {
                 error!(?e, ?c, ?p, label, "Error while waiting for state");
@@ -150,7 +285,6 @@ fn wait_for_state(pipeline: &gst::Pipeline, target: gst::State, timeout: Duratio
                 return false;
             }
         }
-        std::thread::sleep(Duration::from_millis(20)); // 50ms → 20ms に短縮
     }
 }
 
@@ -163,7 +297,7 @@ fn seek_to_server_time(pipeline: &gst::Pipeline, bus: &gst::Bus, server_time_ns:
                 let seek_time_ns = server_time_ns % duration.nseconds();
                 let seek_time = gst::ClockTime::from_nseconds(seek_time_ns);
                 pipeline.seek_simple(gst::SeekFlags::FLUSH | gst::SeekFlags::ACCURATE, seek_time)?;
-                if let Some(_) = bus.timed_pop_filtered(Some(gst::ClockTime::from_seconds(5)), &[gst::MessageType::AsyncDone]) {
+                if bus.timed_pop_filtered(Some(gst::ClockTime::from_seconds(5)), &[gst::MessageType::AsyncDone]).is_some() {
                     debug!(?seek_time, "Seek completed");
                     // FLUSHシーク後の待機時間を短縮
                     std::thread::sleep(Duration::from_millis(50)); // 100ms → 50ms
@@ -177,29 +311,224 @@ fn seek_to_server_time(pipeline: &gst::Pipeline, bus: &gst::Bus, server_time_ns:
             warn!("Duration unavailable for seek (timeout)");
             return Ok(());
         }
-        std::thread::sleep(Duration::from_millis(20)); // 50ms → 20ms
+        // 長さが分かった時点で届くメッセージを待つ（届かなくても区切りごとに問い合わせ直す）
+        let _ = bus.timed_pop_filtered(
+            Some(gst::ClockTime::from_nseconds(STATE_WAIT_SLICE.as_nanos() as u64)),
+            &[gst::MessageType::DurationChanged, gst::MessageType::AsyncDone],
+        );
+    }
+}
+
+/// ミキサーの状態をBGM・SEのチェーンに反映する
+fn apply_mixer(mixer: &Mixer, active: Option<&PipelineState>, se_player: &SePlayer) {
+    if let Some(act) = active {
+        act.control.set_volume(mixer.gain(MixBus::Bgm));
+    }
+    if let Some(bus) = se_player.bus() {
+        se_player.set_gain(mixer.gain(bus));
+    }
+}
+
+/// ローカルの音源ファイルが見つからないか（URL・ライブ配信の音源は再生時に取得するので対象外）
+fn is_missing_local_asset(sound_path: &str) -> bool {
+    !is_remote(sound_path) && !is_live(sound_path) && !std::path::Path::new(sound_path).exists()
+}
+
+/// パイプライン構築の失敗に対応するエラートーン（音源が無いのか出力できないのかを鳴らし分ける）
+fn build_failure_signal(sound_path: &str) -> FatalSignal {
+    if is_missing_local_asset(sound_path) {
+        FatalSignal::AssetMissing
+    } else {
+        FatalSignal::NoSink
+    }
+}
+
+/// パイプライン構築の失敗をエラートーンで知らせる（オーディオループを止めないよう別スレッドで鳴らす）
+fn signal_build_failure(sound_path: &str) {
+    spawn_error_tone(build_failure_signal(sound_path));
+}
+
+/// 構築ワーカーから届いた切り替えの結果のうち、最新の判断（世代番号）のものだけを返す
+///
+/// 新しい判断で置き換えられた切り替えの結果は破棄する（パイプラインはDropで停止）。
+fn latest_switch_result<T>(received: Result<(u64, Result<T>), TryRecvError>, latest: u64) -> Option<Result<T>> {
+    match received {
+        Ok((generation, result)) if generation == latest => Some(result),
+        Ok((generation, result)) => {
+            info!(generation, latest, succeeded = result.is_ok(), "Discarding stale switch result");
+            None
+        }
+        Err(_) => None,
+    }
+}
+
+/// 切り替え先のパイプラインを構築し、Paused状態でシークしてメインスレッドに送る（構築ワーカー上で実行）
+fn prepare_switch(request: SwitchRequest, sources: &SoundSources, switch_tx: &mpsc::Sender<(u64, Result<PreparedSwitch>)>) {
+    let _switch_span = request.span.enter();
+    info!("📦 非同期で新しいパイプラインを構築中...");
+
+    let build_start = Instant::now();
+    match build_pipeline(&request.desired_sound, &request.preset, sources) {
+        Ok(next) => {
+            let build_time = build_start.elapsed();
+            metrics().switch_build.observe(build_time);
+            let seek_start = Instant::now();
+
+            next.control.set_volume(1.0);
+            next.control.set_tempo(1.0);
+
+            if is_live(&request.desired_sound) {
+                // ライブ配信はシークできないため、そのまま再生を始める
+                info!(build_ms = build_time.as_millis() as u64, "📡 ライブ配信のためシークを省略");
+            } else {
+                info!("⏸️  Paused状態で独自シーク位置 {} ns にシーク", request.seek_position_ns);
+                let _ = next.pipeline.set_state(gst::State::Paused);
+                wait_for_state(&next.pipeline, gst::State::Paused, Duration::from_secs(3), "async_switch_pause");
+
+                let seek_position = gst::ClockTime::from_nseconds(request.seek_position_ns);
+                let _ = next.pipeline.seek_simple(
+                    gst::SeekFlags::FLUSH | gst::SeekFlags::ACCURATE,
+                    seek_position
+                );
+                let _ = next.bus.timed_pop_filtered(
+                    Some(gst::ClockTime::from_mseconds(500)),
+                    &[gst::MessageType::AsyncDone]
+                );
+                let seek_time = seek_start.elapsed();
+                metrics().switch_seek.observe(seek_time);
+                info!(
+                    build_ms = build_time.as_millis() as u64,
+                    seek_ms = seek_time.as_millis() as u64,
+                    "✓ シーク完了"
+                );
+            }
+
+            // 🔥 重要：Paused状態のままメインスレッドに送信
+            // メインスレッドで古いパイプラインを停止してからPlayingに切り替える
+            info!("⏸️  パイプラインをPaused状態で準備完了、メインスレッドに送信");
+
+            // 完成したパイプラインをメインスレッドに送信（Paused状態のまま）
+            let prepared = PreparedSwitch {
+                pipeline: next,
+                requested_at: request.requested_at,
+                reaction: request.reaction,
+                span: request.span.clone(),
+            };
+            if let Err(e) = switch_tx.blocking_send((request.generation, Ok(prepared))) {
+                error!("Failed to send new pipeline: {}", e);
+            }
+        }
+        Err(e) => {
+            error!("Failed to build pipeline: {}", e);
+            // メインスレッドをSwitching状態から解放するために失敗も通知する
+            let _ = switch_tx.blocking_send((request.generation, Err(e)));
+        }
+    }
+}
+
+
+
+/// オーディオループの世代をまたいで共有するmpscの受信側
+///
+/// 止まったオーディオスレッドを見捨てて新しいループを始めた場合も、同じ送信側からのメッセージを受け取り続けられるようにする。
+/// 止まったスレッドがロックを持ち続けないよう、受信はロックを短く取る `try_recv` だけにする。
+pub struct SharedReceiver<T>(Arc<Mutex<mpsc::Receiver<T>>>);
+
+impl<T> SharedReceiver<T> {
+    pub fn new(rx: mpsc::Receiver<T>) -> Self {
+        Self(Arc::new(Mutex::new(rx)))
+    }
+
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner).try_recv()
     }
 }
 
-fn set_volume(volume: &gst::Element, v: f64) {
-    volume.set_property("volume", v);
+impl<T> Clone for SharedReceiver<T> {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
 }
 
+/// オーディオスレッドが受信・送信するチャンネル
+///
+/// パニックから再開しても同じチャンネルを使い続けられるよう、`audio_main` の外で持つ。
+pub struct AudioChannels {
+    pub rx: broadcast::Receiver<Arc<DeviceSnapshot>>,
+    pub sound_setting_rx: SharedReceiver<SoundSetting>,
+    pub se_rx: SharedReceiver<SePlayRequest>,
+    pub bgm_override_rx: SharedReceiver<BgmOverrideRequest>,
+    pub enabled_rx: watch::Receiver<EnabledState>,
+    pub volume_rx: watch::Receiver<MasterVolume>,
+    pub mixer_rx: SharedReceiver<MixerCommand>,
+    pub program_rx: watch::Receiver<ProgramOverride>,
+    pub language_rx: watch::Receiver<Option<String>>,
+    pub status_tx: watch::Sender<PlaybackStatus>,
+    /// サーバーとの接続状態などのローカルイベント
+    pub event_rx: broadcast::Receiver<LocalEvent>,
+    pub shutdown: Arc<ShutdownController>,
+}
 
+impl AudioChannels {
+    /// 止まったオーディオスレッドの代わりに始めるループ用に複製する
+    ///
+    /// broadcastは複製した時点以降のメッセージから受け取る（止まっている間の古いスナップショットは使わない）。
+    pub fn for_restart(&self) -> Self {
+        Self {
+            rx: self.rx.resubscribe(),
+            sound_setting_rx: self.sound_setting_rx.clone(),
+            se_rx: self.se_rx.clone(),
+            bgm_override_rx: self.bgm_override_rx.clone(),
+            enabled_rx: self.enabled_rx.clone(),
+            volume_rx: self.volume_rx.clone(),
+            mixer_rx: self.mixer_rx.clone(),
+            program_rx: self.program_rx.clone(),
+            language_rx: self.language_rx.clone(),
+            status_tx: self.status_tx.clone(),
+            event_rx: self.event_rx.resubscribe(),
+            shutdown: Arc::clone(&self.shutdown),
+        }
+    }
+}
 
-#[instrument(skip(rx, time_offset, sound_map, se_rx, system_enabled_rx))]
-pub fn audio_main(
-    mut rx: broadcast::Receiver<Arc<DeviceInfo>>,
-    time_offset: Arc<Mutex<i64>>,
-    mut sound_setting_rx: mpsc::Receiver<SoundSetting>,
-    mut se_rx: mpsc::Receiver<SePlayRequest>,
-    mut system_enabled_rx: broadcast::Receiver<crate::connect_system::connect_main::SystemEnabledState>,
-    sound_map: Arc<Mutex<HashMap<String, String>>>,
-    my_address: Arc<Mutex<Option<String>>>,
-    current_points: Arc<Mutex<i32>>,
-) -> Result<()> {
-    info!("Audio system main loop started.");
+/// オーディオループが使う時計・共有状態・設定
+///
+/// 再開しても同じものを使い続けるため、`AudioChannels` と同じく `audio_main` の外で持ち、世代ごとに複製して渡す。
+#[derive(Clone)]
+pub struct AudioContext {
+    pub clock: Arc<Clock>,
+    pub events: EventBus,
+    pub heartbeat: Arc<AudioHeartbeat>,
+    pub sound_map: Arc<SharedSoundMap>,
+    pub current_points: Arc<Mutex<i32>>,
+    pub chime: ChimeConfig,
+    pub config: PlaybackConfig,
+}
 
+/// BGM・SEを再生するメインループ
+///
+/// `resume` はパニックから再開する場合の直前の再生状況（その音源・位置から再生を続ける）。
+/// `abandoned` が立った場合（止まっている間にスーパーバイザーが別のループを始めた場合）は、戻り次第ループを抜ける。
+#[instrument(skip_all)]
+pub fn audio_main(channels: &mut AudioChannels, abandoned: &AtomicBool, context: AudioContext, resume: Option<PlaybackStatus>) -> Result<()> {
+    info!(resume = ?resume.as_ref().map(|status| &status.sound), "Audio system main loop started.");
+    let AudioContext { clock, events, heartbeat, sound_map, current_points, chime, config } = context;
+    let AudioChannels {
+        rx,
+        sound_setting_rx,
+        se_rx,
+        bgm_override_rx,
+        enabled_rx,
+        volume_rx,
+        mixer_rx,
+        program_rx,
+        language_rx,
+        status_tx,
+        event_rx,
+        shutdown,
+    } = channels;
+
+    #[cfg(feature = "server")]
     let sound_setting = Arc::new(Mutex::new(SoundSetting {
         id: "default".to_string(),
         max_volume_rssi: 0.0,
@@ -210,16 +539,21 @@ pub fn audio_main(
     }));
 
     // システム有効化状態を追跡
-    let mut system_enabled = true;
+    let mut system_enabled = enabled_rx.borrow_and_update().enabled;
+    // マスター音量とバスごとの音量
+    let mut mixer = Mixer::new(config.mixer.clone(), volume_rx.borrow_and_update().gain());
 
     gst::init()?;
     info!("GStreamer initialized successfully.");
 
     // 準備
-    let mut playback_state = PlaybackState::WaitingForFirstSync;
+    let mut fsm = PlaybackFsm::new();
     let default_sound = "tsukimi-main_1.mp3".to_string();
-    let mut current_sound: String = default_sound.clone();
-    let mut detected_devices: HashMap<String, Arc<DeviceInfo>> = HashMap::new();
+    // パニックから再開した場合は直前の音源から（再生位置はサーバー時刻に同期できなかった場合に使う）
+    let mut resume_position_ns = resume.as_ref().map_or(0, |status| status.position_ns);
+    let mut sound_switch = SoundSwitch::new(resume.map(|status| status.sound).filter(|sound| !sound.is_empty()).unwrap_or_else(|| default_sound.clone()));
+    let mut detected_devices: HashMap<Address, Arc<DeviceInfo>> = HashMap::new();
+    let mut bgm_override = BgmOverride::new(clock.time_source());
     let mut last_cleanup = Instant::now();
     const CLEANUP_INTERVAL: Duration = Duration::from_secs(5);
 
@@ -228,23 +562,42 @@ pub fn audio_main(
     let mut standby: Option<PipelineState> = None;
 
     // SE再生用のパイプライン（独立して管理）
-    let mut se_pipeline: Option<gst::Pipeline> = None;
-
-    // SE再生中フラグ（音源切り替え時の音量管理に使用）
-    let mut is_se_playing = false;
-
-    // システム有効化時のSE再生フラグ
-    let mut should_play_activation_se = false;
-
-    // 音源切り替え用のチャネル
-    let (switch_tx, mut switch_rx) = mpsc::channel::<PipelineState>(1);
+    let mut se_player = SePlayer::new(config.se_output.clone());
+    // サーバーへの再接続を知らせるSE（鳴らすまで保持する）
+    let mut connection_cue = ConnectionCue::new(&chime);
+    let mut pending_cue: Option<SePlayRequest> = None;
+
+    // 音源の言語（サーバーからの指定を設定ファイルより優先）
+    let mut language = language_rx.borrow_and_update().clone().or_else(|| config.language.clone());
+    se_player.set_language(language.clone());
+    // 言語の変更で再生中のBGMのバリアントが変わり、作り直しが必要
+    let mut variant_changed = false;
+
+    let idle_teardown = Duration::from_millis(config.idle_teardown_ms);
+
+    // 音源切り替え用のチャネル（構築失敗も通知してSwitching状態から抜けられるようにする）
+    // 世代番号付きで返し、より新しい判断で置き換えられた結果はメインスレッドで破棄する
+    let (switch_tx, mut switch_rx) = mpsc::channel::<(u64, Result<PreparedSwitch>)>(1);
+    // 最新の切り替え判断の世代番号
+    let mut switch_generation: u64 = 0;
+    // パイプライン構築は専用ワーカー1本で順番に行う
+    let sources = SoundSources {
+        remote: RemoteCache::new(&config.remote.cache_dir),
+        live: config.live.clone(),
+        sink: SinkRouting::new(&config),
+        drift_correction: config.drift_correction,
+        heartbeat: Arc::clone(&heartbeat),
+        time: clock.time_source(),
+    };
+    metrics().set_drift_correction(config.drift_correction);
+    let builder_sources = sources.clone();
+    let pipeline_builder = PipelineBuilder::spawn(move |request: SwitchRequest| prepare_switch(request, &builder_sources, &switch_tx));
 
     // 同期関連
     let mut playback_start_time = Instant::now();
     let mut initial_server_time_ns = 0u64;
     let mut last_server_time_ns: Option<u64> = None;
-    // スイッチング中/直後のシーク抑止用ガード
-    let mut switching = false;
+    // スイッチング直後のシーク抑止用ガード
     let mut last_switch_end: Option<Instant> = None;
     const SWITCH_GUARD_WINDOW: Duration = Duration::from_millis(400);
 
@@ -255,66 +608,201 @@ pub fn audio_main(
     let sync_wait_start = Instant::now();
     const SYNC_TIMEOUT: Duration = Duration::from_secs(5);
 
-    // 最適化: durationのキャッシュ
-    let mut cached_duration_ns: Option<u64> = None;
-    let mut last_duration_query = Instant::now();
-    const DURATION_QUERY_INTERVAL: Duration = Duration::from_secs(1);
+    // パイプラインエラー後、再構築を試みるまでの待機時間
+    const RECOVERY_BACKOFF: Duration = Duration::from_secs(2);
+
+    // このずれ未満はテンポを変えない
+    let drift_deadband_ns = config.drift_deadband_ms.saturating_mul(1_000_000);
+
+    // 出力先のサスペンドからの復帰の検知（バスのメッセージで分かった場合はフラグを立てる）
+    let mut suspend_detector = SuspendDetector::new(Duration::from_millis(config.sink_stall_ms));
+    let mut sink_resumed = false;
+
+    // 再生状況の公開（サーバーへの報告用）
+    let mut last_drift_ns: i64 = 0;
+    let mut sync_quality = SyncQuality::new(clock.time_source());
+    let mut loudness = LoudnessMeter::default();
+    let mut last_show_time: Option<ShowTime> = None;
+    let mut last_status_publish = Instant::now();
+    const STATUS_PUBLISH_INTERVAL: Duration = Duration::from_secs(1);
+
+    // 終了時のフェードアウトを始めた時刻
+    let mut fade_started: Option<Instant> = None;
+    let shutdown_fade = Duration::from_millis(config.shutdown_fade_ms);
 
     'main_loop: loop {
-        // システム有効化状態のチェック
-        if let Ok(state) = system_enabled_rx.try_recv() {
-            // 自分向けのイベントか確認
-            let my_addr_guard = my_address.lock().unwrap();
-            if my_addr_guard.as_ref() == Some(&state.target_device_id) {
-                info!(enabled = state.enabled, target = %state.target_device_id, "Received SystemEnabledState for me");
+        // 止まっている間に別のループが始まっていれば、チャンネル・出力先を取り合わないよう抜ける
+        if abandoned.load(Ordering::Relaxed) {
+            warn!("Audio loop was replaced after a stall - exiting");
+            break 'main_loop;
+        }
+        heartbeat.beat();
+
+        // 終了手順: フェードアウトしてから、パイプラインの破棄の段階でループを抜ける
+        let phase = shutdown.phase();
+        if phase >= ShutdownPhase::DropPipelines {
+            info!("🛑 Shutting down - dropping audio pipelines");
+            break 'main_loop;
+        } else if phase >= ShutdownPhase::FadeAudio {
+            let started = *fade_started.get_or_insert_with(|| {
+                info!(fade_ms = shutdown_fade.as_millis() as u64, "🔉 Shutting down - fading out audio");
+                Instant::now()
+            });
+            let progress = if shutdown_fade.is_zero() { 1.0 } else { started.elapsed().as_secs_f64() / shutdown_fade.as_secs_f64() };
+            mixer.set_fade(1.0 - progress);
+            apply_mixer(&mixer, active.as_ref(), &se_player);
+            if progress >= 1.0 {
+                shutdown.complete(ShutdownPhase::FadeAudio);
+            }
+        }
+
+        // ソークテスト: 障害が注入されたらループを止め、GStreamerのバスのメッセージを滞留させる
+        #[cfg(feature = "chaos")]
+        if crate::chaos_system::chaos_main::triggered(crate::chaos_system::chaos_main::Fault::DelayAudioBus) {
+            std::thread::sleep(crate::chaos_system::chaos_main::audio_bus_delay());
+        }
+
+        // システム有効化状態のチェック（watchなので常に最新値のみを見る）
+        if enabled_rx.has_changed().unwrap_or(false) {
+            let state = *enabled_rx.borrow_and_update();
+            if state.enabled != system_enabled {
+                info!(enabled = state.enabled, "Received EnabledState");
                 system_enabled = state.enabled;
 
                 if !system_enabled {
-                    // システムが無効化された場合、すべてのパイプラインを停止
-                    info!("🛑 System disabled - stopping all audio pipelines");
+                    match (config.disable_mode, active.as_ref()) {
+                        (DisableMode::Pause, Some(act)) => {
+                            // 再有効化時にすぐ再開できるよう、アクティブは一時停止・ミュートして保持する
+                            info!("⏸️  System disabled - pausing and muting active pipeline");
+                            act.control.set_volume(0.0);
+                            let _ = act.pipeline.set_state(gst::State::Paused);
+                            fsm.hold_paused();
+                        }
+                        _ => {
+                            fsm.release_paused();
+                            // システムが無効化された場合、すべてのパイプラインを停止
+                            info!("🛑 System disabled - stopping all audio pipelines");
 
-                    if let Some(_act) = active.take() {
-                        info!("Stopped active pipeline");
+                            if let Some(_act) = active.take() {
+                                info!("Stopped active pipeline");
+                            }
+                        }
                     }
 
+                    // 構築中の切り替えは無効化後に届いても使わない
+                    switch_generation += 1;
+                    sound_switch.abandon();
+
                     if let Some(_st) = standby.take() {
                         info!("Stopped standby pipeline");
                     }
 
-                    if let Some(_se) = se_pipeline.take() {
-                        info!("Stopped SE pipeline");
-                    }
-
-                    is_se_playing = false;
+                    se_player.stop("se_cleanup_on_disable");
+                    mixer.set_sounding_se(None);
 
-                    // 再生状態を初期化に戻す
-                    playback_state = PlaybackState::WaitingForFirstSync;
+                    fsm.reset_se();
+                    fsm.transition(PlaybackState::Disabled, "system disabled");
                     info!("Audio system paused, waiting for system to be re-enabled");
                 } else {
                     // システムが再有効化された場合
                     info!("✅ My system is re-enabled - resuming audio system");
-                    playback_state = PlaybackState::WaitingForFirstSync;
 
-                    // 有効化SEを再生するフラグを立てる
-                    should_play_activation_se = true;
+                    // 有効化SEを優先して再生し、その後BGMを開始する
+                    // （一時停止して保持しているアクティブも有効化SEを待ってから再開する）
+                    if config.activation_se.enabled {
+                        fsm.request_activation_se();
+                    }
+                    fsm.transition(PlaybackState::SePriority, "system re-enabled");
                 }
-            } else {
-                // 他人向けのイベントは無視
-                debug!(
-                    my_addr = ?*my_addr_guard,
-                    target_addr = %state.target_device_id,
-                    "Ignoring SystemEnabledState for another device"
-                );
             }
         }
 
+        // 再生状況を一定間隔で公開
+        if last_status_publish.elapsed() > STATUS_PUBLISH_INTERVAL {
+            let quality = sync_quality.snapshot();
+            metrics().set_sync_quality(&quality);
+            status_tx.send_replace(PlaybackStatus {
+                sound: sound_switch.current().to_string(),
+                position_ns: current_seek_position_ns,
+                drift_ns: last_drift_ns,
+                sync_quality: quality,
+                loudness: loudness.snapshot(),
+                enabled: system_enabled,
+                state: fsm.state(),
+                clock: last_show_time,
+            });
+            last_status_publish = Instant::now();
+            #[cfg(feature = "chaos")]
+            crate::chaos_system::chaos_main::recovered(crate::chaos_system::chaos_main::Fault::DelayAudioBus);
+        }
+
+        // BGM上書きリクエスト（無効化中に届いたものも受け取り、期限は届いた時点から数える）
+        while let Ok(request) = bgm_override_rx.try_recv() {
+            bgm_override.apply(request);
+        }
+
         // システムが無効化されている場合はスキップ
         if !system_enabled {
+            // 無効化が長引いた場合は保持していたパイプラインも破棄する
+            if fsm.idle_teardown_due(idle_teardown) {
+                info!(idle_ms = config.idle_teardown_ms, "🛑 Disabled for too long - tearing down paused pipeline");
+                active = None;
+                fsm.release_paused();
+            }
             std::thread::sleep(Duration::from_millis(100));
             continue;
         }
 
+        // マスター音量の変更を即座にBGM・SEへ反映
+        if volume_rx.has_changed().unwrap_or(false) {
+            let master_volume = *volume_rx.borrow_and_update();
+            info!(?master_volume, gain = master_volume.gain(), "🔊 Master volume changed");
+            mixer.set_master_gain(master_volume.gain());
+            apply_mixer(&mixer, active.as_ref(), &se_player);
+        }
+
+        // 言語の変更（BGMは同じ位置から別のバリアントに切り替える）
+        if language_rx.has_changed().unwrap_or(false) {
+            let next = language_rx.borrow_and_update().clone().or_else(|| config.language.clone());
+            if next != language {
+                info!(from = ?language, to = ?next, "🌐 Language changed");
+                variant_changed |= localized(sound_switch.current(), language.as_deref()) != localized(sound_switch.current(), next.as_deref());
+                language = next;
+                se_player.set_language(language.clone());
+            }
+        }
+
+        // バスごとの音量・ミュートの変更を反映
+        let mut mixer_changed = false;
+        if program_rx.has_changed().unwrap_or(false) {
+            let silenced = *program_rx.borrow_and_update() == ProgramOverride::Silence;
+            mixer.set_bgm_silenced(silenced);
+            mixer_changed = true;
+        }
+        while let Ok(command) = mixer_rx.try_recv() {
+            info!(?command, "🎚️  Mixer command received");
+            mixer.apply(command);
+            mixer_changed = true;
+        }
+        if mixer_changed {
+            apply_mixer(&mixer, active.as_ref(), &se_player);
+        }
+
+        // サーバーとの接続状態の変化（読み飛ばしが発生しても、次の変化から追従する）
+        loop {
+            match event_rx.try_recv() {
+                Ok(LocalEvent::ServerConnection { state, .. }) => {
+                    if let Some(request) = connection_cue.update(state) {
+                        pending_cue = Some(request);
+                    }
+                }
+                Ok(_) | Err(broadcast::error::TryRecvError::Lagged(_)) => {}
+                Err(_) => break,
+            }
+        }
+
         // バス処理（アクティブ優先、スタンバイも確認）- タイムアウトを適切に調整
+        let mut active_failed = false;
         if let Some(ref act) = active {
             // 10msに変更：メッセージ処理の余裕を持たせる
             while let Some(msg) = act.bus.timed_pop(gst::ClockTime::from_mseconds(10)) {
@@ -326,7 +814,8 @@ pub fn audio_main(
                     }
                     MessageView::Error(err) => {
                         error!(error=%err.error(), debug=?err.debug(), src=?err.src().map(|s| s.name()), "Active pipeline error");
-                        break 'main_loop;
+                        active_failed = true;
+                        break;
                     }
                     MessageView::Buffering(buffering_msg) => {
                         let percent = buffering_msg.percent();
@@ -334,350 +823,397 @@ pub fn audio_main(
                             debug!(?percent, "Pipeline buffering");
                         }
                     }
+                    // 出力先が一時停止・再開された（サスペンドからの復帰後はクロックを選び直して合わせ直す）
+                    MessageView::ClockLost(_) if act.pipeline.current_state() == gst::State::Playing => {
+                        warn!("Active pipeline lost its clock, restarting clock selection");
+                        let _ = act.pipeline.set_state(gst::State::Paused);
+                        let _ = act.pipeline.set_state(gst::State::Playing);
+                        sink_resumed = true;
+                    }
+                    // 出力のラウドネスの計測
+                    MessageView::Element(element) => {
+                        if let Some(structure) = element.structure().filter(|s| s.name() == "level") {
+                            let values = |field: &str| -> Vec<f64> {
+                                structure
+                                    .get::<glib::ValueArray>(field)
+                                    .map(|array| array.iter().filter_map(|value| value.get::<f64>().ok()).collect())
+                                    .unwrap_or_default()
+                            };
+                            loudness.observe(&values("rms"), &values("peak"));
+                        }
+                    }
+                    MessageView::RequestState(request) => {
+                        let state = request.requested_state();
+                        info!(?state, src = ?msg.src().map(|s| s.name()), "Sink requested state change");
+                        let _ = act.pipeline.set_state(state);
+                        sink_resumed |= state == gst::State::Playing;
+                    }
                     _ => {}
                 }
             }
         }
+        if active_failed {
+            // パイプラインを破棄し、一定時間後に再構築する
+            active = None;
+            standby = None;
+            sources.sink.on_pipeline_error();
+            fsm.transition(PlaybackState::Recovering, "active pipeline error");
+        }
+        // 設定した出力デバイスが戻ったら、元の出力でパイプラインを作り直す（再構築時にサーバー時刻へシークする）
+        if sources.sink.take_restored() && active.is_some() {
+            active = None;
+            standby = None;
+            switch_generation += 1;
+            sound_switch.abandon();
+            fsm.transition(PlaybackState::Recovering, "audio device returned");
+        }
         if let Some(ref stdb) = standby {
             // スタンバイは1msで十分
             while let Some(msg) = stdb.bus.timed_pop(gst::ClockTime::from_mseconds(1)) {
-                use gst::MessageView;
-                match msg.view() {
-                    MessageView::Error(err) => {
-                        warn!(error=%err.error(), debug=?err.debug(), src=?err.src().map(|s| s.name()), "Standby pipeline error");
-                    }
-                    _ => {}
+                if let gst::MessageView::Error(err) = msg.view() {
+                    warn!(error=%err.error(), debug=?err.debug(), src=?err.src().map(|s| s.name()), "Standby pipeline error");
                 }
             }
         }
 
-        // 最新サーバー時間をtime_offsetから計算
-        let current_offset = *time_offset.lock().unwrap();
-        if current_offset != 0 { // オフセットが初期値(0)でなければ同期済みとみなす
-            let client_now_ns = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_nanos() as i64;
-            let estimated_server_time_ns = (client_now_ns + current_offset) as u64;
-            last_server_time_ns = Some(estimated_server_time_ns);
+        // 最新のショー時刻（推定サーバー時刻）を取得（未同期の場合はNone）
+        if let Some(show_time) = clock.now() {
+            last_server_time_ns = Some(show_time.show_time_ns);
+            last_show_time = Some(show_time);
         }
 
-        // システム有効化時のSE再生処理
-        if should_play_activation_se && !is_se_playing {
+        // SE再生リクエストの処理
+        // システム有効化時のSEも通常のSEリクエストとして再生する
+        let activation_request = fsm.take_activation_se().then(|| {
             info!("🎵 システム有効化SE再生開始");
-            should_play_activation_se = false;
-
-            // SE再生中フラグを立てる
-            is_se_playing = true;
-
-            // 既存のSEパイプラインがあれば停止
-            if let Some(old_se) = se_pipeline.take() {
-                info!("🛑 既存のSEパイプラインを停止してクリーンアップ");
-                if old_se.set_state(gst::State::Null).is_ok() {
-                    wait_for_state(&old_se, gst::State::Null, Duration::from_millis(500), "se_cleanup_on_activation");
-                }
-            }
-
-            // 新しいSEパイプラインを作成（システム有効化SE）
-            let sink = sink_name();
-            let se_file = "se-activation.mp3"; // システム有効化音
-
-            // PulseAudioの場合は明示的にストリーム名とclient名を設定
-            let se_pipeline_str = if cfg!(target_os = "linux") {
-                format!(
-                    "filesrc location={} ! decodebin ! audioconvert ! audioresample ! volume name=se_vol volume=3.0 ! pulsesink client-name=\"tsukimi-se\" stream-properties=\"properties,media.role=event\"",
-                    se_file
-                )
-            } else {
-                format!(
-                    "filesrc location={} ! decodebin ! audioconvert ! audioresample ! volume name=se_vol volume=3.0 ! {}",
-                    se_file, sink
-                )
-            };
-
-            info!("🎵 システム有効化SEパイプライン構築開始: pipeline={}", se_pipeline_str);
-
-            match gst::parse::launch(&se_pipeline_str) {
-                Ok(pipeline) => {
-                    if let Ok(se_pipe) = pipeline.downcast::<gst::Pipeline>() {
-                        info!("✅ システム有効化SEパイプライン作成成功");
-                        info!("▶️  システム有効化SE再生開始: {}", se_file);
-                        let _ = se_pipe.set_state(gst::State::Playing);
-                        se_pipeline = Some(se_pipe);
-                    } else {
-                        error!("❌ システム有効化SEパイプラインのダウンキャストに失敗");
-                        is_se_playing = false;
+            activation_se_request(&config.activation_se, false)
+        });
+        // 優先SEの再生中に届いた通常のSEは破棄する
+        let se_request = activation_request.or_else(|| {
+            pending_cue.take().or_else(|| se_rx.try_recv().ok()).filter(|req| {
+                let accepted = fsm.accepts_se(req.priority);
+                if !accepted {
+                    info!("⏭️  優先SE再生中のため通常SEをスキップ: file={}", req.file_path);
+                    if let Some(command_id) = &req.command_id {
+                        let outcome = CommandOutcome::failed(command_id.clone(), CommandKind::SePlay, CommandFailure::Busy, "priority SE is playing");
+                        events.publish(LocalEvent::CommandExecuted { outcome });
                     }
                 }
-                Err(e) => {
-                    error!("❌ システム有効化SEパイプラインの構築に失敗: error={}", e);
-                    is_se_playing = false;
-                }
-            }
-        }
-
-        // SE再生リクエストの処理
-        if let Ok(se_request) = se_rx.try_recv() {
-            info!("🔔 SE再生リクエスト受信: file={}", se_request.file_path);
-
-            // SE再生中フラグを立てる
-            is_se_playing = true;
-
-
-            // 既存のSEパイプラインがあれば停止
-            if let Some(old_se) = se_pipeline.take() {
-                info!("🛑 既存のSEパイプラインを停止してクリーンアップ");
-                if old_se.set_state(gst::State::Null).is_ok() {
-                    wait_for_state(&old_se, gst::State::Null, Duration::from_millis(500), "se_cleanup_on_new_request");
-                }
-            }
-
-            // 新しいSEパイプラインを作成（シンプルなワンショット再生）
-            let sink = sink_name();
-
-            // PulseAudioの場合は明示的にストリーム名とclient名を設定
-            let se_pipeline_str = if cfg!(target_os = "linux") {
-                format!(
-                    "filesrc location={} ! decodebin ! audioconvert ! audioresample ! volume name=se_vol volume=3.0 ! pulsesink client-name=\"tsukimi-se\" stream-properties=\"properties,media.role=event\"",
-                    se_request.file_path
-                )
-            } else {
-                format!(
-                    "filesrc location={} ! decodebin ! audioconvert ! audioresample ! volume name=se_vol volume=3.0 ! {}",
-                    se_request.file_path, sink
-                )
-            };
-
-            info!("🎵 SEパイプライン構築開始: pipeline={}", se_pipeline_str);
-
-            match gst::parse::launch(&se_pipeline_str) {
-                Ok(pipeline) => {
-                    if let Ok(se_pipe) = pipeline.downcast::<gst::Pipeline>() {
-                        info!("✅ SEパイプライン作成成功: file={}", se_request.file_path);
-                        info!("▶️  SE再生開始: {}", se_request.file_path);
-                        let _ = se_pipe.set_state(gst::State::Playing);
-                        se_pipeline = Some(se_pipe);
-                    } else {
-                        error!("❌ SEパイプラインのダウンキャストに失敗: file={}", se_request.file_path);
+                accepted
+            })
+        });
+        if let Some(se_request) = se_request {
+            info!("🔔 SE再生リクエスト受信: file={}, priority={}", se_request.file_path, se_request.priority);
+
+            let se_state = if se_request.priority { SeState::Priority } else { SeState::Playing };
+            fsm.set_se_state(se_state, "SE request");
+
+            let bus = if se_request.priority { MixBus::Announce } else { MixBus::Se };
+            let trim = se_request.gain.unwrap_or(1.0);
+            let result = se_player.play(&se_request.file_path, bus, trim, mixer.gain(bus));
+            // 鳴っているSEのバスに応じてBGMをダッキングする
+            mixer.set_sounding_se(se_player.bus());
+            apply_mixer(&mixer, active.as_ref(), &se_player);
+            // サーバーのコマンドで届いたSEは再生できたかどうかを報告する
+            if let Some(command_id) = se_request.command_id.clone() {
+                let outcome = match &result {
+                    Ok(()) => CommandOutcome::ok(command_id, CommandKind::SePlay),
+                    Err(e) => {
+                        let failure = if is_missing_local_asset(&se_request.file_path) {
+                            CommandFailure::AssetMissing
+                        } else {
+                            CommandFailure::PipelineError
+                        };
+                        CommandOutcome::failed(command_id, CommandKind::SePlay, failure, e.to_string())
                     }
+                };
+                events.publish(LocalEvent::CommandExecuted { outcome });
+            }
+            match result {
+                Ok(()) => {
+                    events.publish(LocalEvent::SePlayed { file: se_request.file_path.clone() });
                 }
                 Err(e) => {
-                    error!("❌ SEパイプラインの構築に失敗: file={}, error={}", se_request.file_path, e);
+                    error!("❌ SEの再生に失敗: file={}, error={:?}", se_request.file_path, e);
+                    fsm.set_se_state(SeState::Idle, "SE build failed");
                 }
             }
         }
 
         // SE再生の完了チェック（EOSメッセージを確認）
-        if let Some(ref se_pipe) = se_pipeline {
-            if let Some(bus) = se_pipe.bus() {
-                let mut should_clear = false;
-                while let Some(msg) = bus.timed_pop(gst::ClockTime::from_mseconds(1)) {
-                    use gst::MessageView;
-                    match msg.view() {
-                        MessageView::Eos(_) => {
-                            info!("🎵 SE再生完了 (EOS受信) - パイプラインを終了します");
-                            should_clear = true;
-                        }
-                        MessageView::Error(err) => {
-                            error!("❌ SEパイプラインエラー: error={}, debug={:?}", err.error(), err.debug());
-                            should_clear = true;
-                        }
-                        MessageView::StateChanged(state_changed) => {
-                            if let Some(src) = state_changed.src() {
-                                if src == &se_pipe.clone().upcast::<gst::Object>() {
-                                    let old = state_changed.old();
-                                    let new = state_changed.current();
-                                    let pending = state_changed.pending();
-                                    info!("🔄 SEパイプライン状態変更: {:?} -> {:?} (pending: {:?})", old, new, pending);
-                                }
-                            }
-                        }
-                        MessageView::StreamStart(_) => {
-                            info!("�� SEストリーム開始");
-                        }
-                        _ => {}
-                    }
-                }
-                if should_clear {
-                    info!("🧹 SEパイプラインをクリーンアップして解放");
-                    if let Some(se_pipe) = se_pipeline.take() {
-                        if se_pipe.set_state(gst::State::Null).is_ok() {
-                            wait_for_state(&se_pipe, gst::State::Null, Duration::from_millis(500), "se_cleanup_on_eos");
-                        }
-                    }
-                    // SE再生中フラグをリセット
-                    is_se_playing = false;
-                }
-            }
+        if se_player.poll() {
+            fsm.set_se_state(SeState::Idle, "SE finished");
+            mixer.set_sounding_se(None);
+            apply_mixer(&mixer, active.as_ref(), &se_player);
         }
 
-        match playback_state {
-            PlaybackState::WaitingForFirstSync => {
+        match fsm.state() {
+            PlaybackState::WaitingForSync => {
                 if let Some(server_time_ns) = last_server_time_ns {
                     // 初回アクティブを作成
-                    let act = build_pipeline(&current_sound)?;
+                    let preset = preset_for(sound_switch.current(), &sound_map.load(), &config.presets);
+                    let act = match build_pipeline(&localized(sound_switch.current(), language.as_deref()), &preset, &sources) {
+                        Ok(act) => act,
+                        Err(e) => {
+                            error!("Failed to build initial pipeline: {:?}", e);
+                            if chime.error_tone {
+                                signal_build_failure(sound_switch.current());
+                            }
+                            sources.sink.on_pipeline_error();
+                            fsm.transition(PlaybackState::Recovering, "initial pipeline build failed");
+                            continue;
+                        }
+                    };
                     let _ = act.pipeline.set_state(gst::State::Paused);
                     wait_for_state(&act.pipeline, gst::State::Paused, Duration::from_secs(10), "initial_pause");
                     let _ = seek_to_server_time(&act.pipeline, &act.bus, server_time_ns);
-                    if let Some(ref p) = act.pitch { p.set_property("tempo", 1.0f32); }
-                    set_volume(&act.volume, 1.0);
+                    act.control.set_tempo(1.0);
+                    act.control.set_volume(mixer.gain(MixBus::Bgm));
                     let _ = act.pipeline.set_state(gst::State::Playing);
 
                     // durationをキャッシュ
-                    if let Some(duration) = act.pipeline.query_duration::<gst::ClockTime>() {
-                        cached_duration_ns = Some(duration.nseconds());
-                        current_seek_position_ns = server_time_ns % duration.nseconds();
+                    if let Some(duration_ns) = act.control.refresh_duration() {
+                        current_seek_position_ns = server_time_ns % duration_ns;
                     }
 
                     active = Some(act);
                     last_position_update = Instant::now();
-                    last_duration_query = Instant::now();
+                    suspend_detector.reset();
 
                     playback_start_time = Instant::now();
                     initial_server_time_ns = server_time_ns;
-                    playback_state = PlaybackState::Playing;
+                    fsm.transition(PlaybackState::Playing, "initial sync");
                 } else if Instant::now().duration_since(sync_wait_start) > SYNC_TIMEOUT {
                     // 同期なしフォールバック
-                    let act = build_pipeline(&current_sound)?;
-                    let _ = act.pipeline.set_state(gst::State::Playing);
-                    set_volume(&act.volume, 1.0);
-
-                    if let Some(duration) = act.pipeline.query_duration::<gst::ClockTime>() {
-                        cached_duration_ns = Some(duration.nseconds());
+                    let preset = preset_for(sound_switch.current(), &sound_map.load(), &config.presets);
+                    let act = match build_pipeline(&localized(sound_switch.current(), language.as_deref()), &preset, &sources) {
+                        Ok(act) => act,
+                        Err(e) => {
+                            error!("Failed to build fallback pipeline: {:?}", e);
+                            if chime.error_tone {
+                                signal_build_failure(sound_switch.current());
+                            }
+                            sources.sink.on_pipeline_error();
+                            fsm.transition(PlaybackState::Recovering, "fallback pipeline build failed");
+                            continue;
+                        }
+                    };
+                    if resume_position_ns > 0 {
+                        let _ = act.pipeline.set_state(gst::State::Paused);
+                        wait_for_state(&act.pipeline, gst::State::Paused, Duration::from_secs(10), "resume_pause");
+                        let _ = seek_to_server_time(&act.pipeline, &act.bus, resume_position_ns);
                     }
+                    let _ = act.pipeline.set_state(gst::State::Playing);
+                    act.control.set_volume(mixer.gain(MixBus::Bgm));
+                    act.control.refresh_duration();
 
                     active = Some(act);
+                    suspend_detector.reset();
 
-                    current_seek_position_ns = 0;
+                    current_seek_position_ns = std::mem::take(&mut resume_position_ns);
                     last_position_update = Instant::now();
-                    last_duration_query = Instant::now();
 
                     playback_start_time = Instant::now();
                     initial_server_time_ns = 0;
-                    playback_state = PlaybackState::Playing;
+                    fsm.transition(PlaybackState::Playing, "sync timeout fallback");
                 }
             }
-            PlaybackState::Playing => {
-                // 独自シーク位置を経過時間で更新
-                let elapsed_since_update = last_position_update.elapsed();
-                current_seek_position_ns += elapsed_since_update.as_nanos() as u64;
-                last_position_update = Instant::now();
-
-                // durationのクエリを削減：1秒に1回のみ
-                if Instant::now().duration_since(last_duration_query) > DURATION_QUERY_INTERVAL {
-                    if let Some(ref act) = active {
-                        if let Some(duration) = act.pipeline.query_duration::<gst::ClockTime>() {
-                            if duration.nseconds() > 0 {
-                                cached_duration_ns = Some(duration.nseconds());
+            PlaybackState::Disabled => {
+                // 有効化を待つ
+            }
+            PlaybackState::SePriority => {
+                // 有効化SEの発行（設定によっては再生終了）を待って、一時停止して保持していたアクティブを
+                // 再開するか、BGMの同期待ちに進む
+                if fsm.activation_se_done(config.activation_se.wait_for_finish) {
+                    match (fsm.after_activation_se(), active.as_ref()) {
+                        (PlaybackState::Playing, Some(act)) => {
+                            // 一時停止していたアクティブをサーバー時刻に合わせて再開する
+                            let paused_ms = fsm.release_paused().unwrap_or_default().as_millis() as u64;
+                            info!(paused_ms, "▶️  Resuming paused pipeline");
+                            if let Some(show_time) = clock.now() {
+                                let server_time_ns = show_time.show_time_ns;
+                                let _ = seek_to_server_time(&act.pipeline, &act.bus, server_time_ns);
+                                if let Some(duration_ns) = act.control.refresh_duration() {
+                                    current_seek_position_ns = server_time_ns % duration_ns;
+                                }
+                                playback_start_time = Instant::now();
+                                initial_server_time_ns = server_time_ns;
                             }
+                            act.control.set_tempo(1.0);
+                            // 鳴っているSE（待たずに進んだ有効化SEなど）に応じてダッキングした音量で戻す
+                            mixer.set_sounding_se(se_player.bus());
+                            apply_mixer(&mixer, Some(act), &se_player);
+                            let _ = act.pipeline.set_state(gst::State::Playing);
+                            // 停止中の時間を再生位置に加算しない
+                            last_position_update = Instant::now();
+                            suspend_detector.reset();
+                            fsm.transition(PlaybackState::Playing, "paused pipeline resumed");
+                        }
+                        _ => {
+                            fsm.release_paused();
+                            let reason = if config.activation_se.wait_for_finish { "activation SE finished" } else { "activation SE dispatched" };
+                            fsm.transition(PlaybackState::WaitingForSync, reason);
                         }
                     }
-                    last_duration_query = Instant::now();
                 }
+            }
+            PlaybackState::Recovering => {
+                if fsm.time_in_state() > RECOVERY_BACKOFF {
+                    fsm.transition(PlaybackState::WaitingForSync, "recovery backoff elapsed");
+                }
+            }
+            PlaybackState::Playing | PlaybackState::Switching => {
+                // 独自シーク位置を経過時間で更新
+                let elapsed_since_update = last_position_update.elapsed();
+                current_seek_position_ns += elapsed_since_update.as_nanos() as u64;
+                last_position_update = Instant::now();
 
-                // キャッシュされたdurationでループ
-                if let Some(duration_ns) = cached_duration_ns {
-                    if duration_ns > 0 {
-                        current_seek_position_ns %= duration_ns;
-                    }
+                // キャッシュされたdurationでループ（問い合わせは1秒に1回のみ）
+                if let Some(duration_ns) = active.as_ref().and_then(|act| act.control.duration_ns()) {
+                    current_seek_position_ns %= duration_ns;
                 }
 
                 // 設定更新
                 if let Ok(new_setting) = sound_setting_rx.try_recv() {
                     info!(?new_setting, "Received new sound setting");
-                    *sound_setting.lock().unwrap() = new_setting;
+                    #[cfg(feature = "server")]
+                    {
+                        *sound_setting.lock().unwrap() = new_setting;
+                    }
                 }
                 // デバイス更新
-                while let Ok(device_info) = rx.try_recv() {
-                    detected_devices.insert(device_info.address.clone(), device_info);
+                loop {
+                    match rx.try_recv() {
+                        Ok(snapshot) => {
+                            for device_info in &snapshot.devices {
+                                let seen = detected_devices.insert(device_info.address.clone(), Arc::clone(device_info)).is_none();
+                                // BeaconLostと対になるよう、来場者の端末は通知しない
+                                if seen && device_info.visitor_token.is_none() {
+                                    events.publish(LocalEvent::DeviceSeen { address: device_info.address.to_string(), rssi: device_info.rssi });
+                                }
+                            }
+                        }
+                        Err(broadcast::error::TryRecvError::Lagged(skipped)) => {
+                            metrics().record_lag(LagReceiver::Audio, skipped);
+                        }
+                        Err(broadcast::error::TryRecvError::Empty) => break,
+                        Err(broadcast::error::TryRecvError::Closed) => {
+                            info!("Device snapshot channel closed - stopping audio system");
+                            break 'main_loop;
+                        }
+                    }
                 }
                 if Instant::now().duration_since(last_cleanup) > CLEANUP_INTERVAL {
                     let initial_count = detected_devices.len();
-                    detected_devices.retain(|_, d| Instant::now().duration_since(d.last_seen) < CLEANUP_INTERVAL);
+                    detected_devices.retain(|address, d| {
+                        let alive = Instant::now().duration_since(d.last_seen) < CLEANUP_INTERVAL;
+                        // 来場者の端末はビーコンではないため通知しない
+                        if !alive && d.visitor_token.is_none() {
+                            events.publish(LocalEvent::BeaconLost { address: address.to_string() });
+                        }
+                        alive
+                    });
                     if initial_count != detected_devices.len() { debug!("Cleaned up old devices."); }
                     last_cleanup = Instant::now();
                 }
 
                 // ドリフト補正（アクティブ側のみ）
-                if let (Some(server_time_ns), Some(ref act)) = (last_server_time_ns, active.as_ref()) {
+                if let (Some(server_time_ns), Some(act)) = (last_server_time_ns, active.as_ref()) {
                     // 切替中と直後のウィンドウはシークを行わない
-                    let in_switch_guard = switching || last_switch_end.map_or(false, |t| Instant::now().duration_since(t) < SWITCH_GUARD_WINDOW);
-                    if initial_server_time_ns != 0 && !in_switch_guard && server_time_ns >= initial_server_time_ns {
+                    let in_switch_guard = fsm.is_switching() || last_switch_end.is_some_and(|t| Instant::now().duration_since(t) < SWITCH_GUARD_WINDOW);
+                    // ライブ配信は送り手のタイミングで鳴らすため補正しない
+                    let live = is_live(sound_switch.current());
+                    if initial_server_time_ns != 0 && !in_switch_guard && !live && server_time_ns >= initial_server_time_ns {
                         let server_elapsed = (server_time_ns - initial_server_time_ns) as i64;
                         let client_elapsed = playback_start_time.elapsed().as_nanos() as i64;
                         let diff_real_ns = server_elapsed - client_elapsed;
+                        last_drift_ns = diff_real_ns;
                         let diff_abs_s = (diff_real_ns.abs() as f64) / 1e9;
                         let new_rate: f64 = if diff_abs_s > 3.0 {
                             warn!(diff_s = diff_real_ns as f64 / 1e9, "Large drift detected (>3s), seeking active.");
                             let _ = seek_to_server_time(&act.pipeline, &act.bus, server_time_ns);
+                            sync_quality.record_reseek();
                             // 独自シーク位置も更新、キャッシュされたdurationを使用
-                            if let Some(duration_ns) = cached_duration_ns {
-                                if duration_ns > 0 {
-                                    current_seek_position_ns = server_time_ns % duration_ns;
-                                }
+                            if let Some(duration_ns) = act.control.duration_ns() {
+                                current_seek_position_ns = server_time_ns % duration_ns;
                             }
                             1.0
+                        } else if diff_real_ns.unsigned_abs() < drift_tolerance_ns(drift_deadband_ns, last_show_time.map(|t| t.uncertainty_ns)) {
+                            // わずかなずれ・時計自体の誤差範囲内のずれは補正しない
+                            1.0
                         } else {
                             let diff_s = diff_real_ns as f64 / 1e9;
                             const CORRECTION_TIME_S: f64 = 2.0;
                             (1.0 + diff_s / CORRECTION_TIME_S).clamp(0.9, 1.1)
                         };
-                        if let Some(ref p) = act.pitch { p.set_property("tempo", new_rate as f32); }
+                        act.control.set_tempo(new_rate);
+                        sync_quality.observe(diff_real_ns, new_rate);
                         playback_start_time = Instant::now();
                         initial_server_time_ns = server_time_ns;
                     }
                 }
 
-                let desired_sound = {
-                    let sound_map_guard = sound_map.lock().unwrap();
-
-                    // 1. 現在のロケーションのRSSIを取得
-                    let current_location_rssi = {
-                        let current_device_addr = sound_map_guard.iter()
-                            .find(|(_, sound_file)| **sound_file == current_sound)
-                            .map(|(addr, _)| addr.clone());
-
-                        if let Some(addr) = current_device_addr {
-                            // 現在地のビーコンが見つかればそのRSSIを、見つからなければ最低値を設定
-                            detected_devices.get(&addr).map_or(i16::MIN, |d| d.rssi)
-                        } else {
-                            // 現在のサウンドがデフォルト等の場合も最低値
-                            i16::MIN
+                // 出力先のサスペンドからの復帰（ずれの計測で気付くのを待たずにすぐ合わせ直す）
+                if let Some(act) = active.as_ref().filter(|_| !fsm.is_switching() && !is_live(sound_switch.current())) {
+                    let stalled = suspend_detector.update(act.control.position_ns());
+                    let resumed = std::mem::take(&mut sink_resumed) || stalled.is_some();
+                    if let Some(server_time_ns) = last_server_time_ns.filter(|_| resumed) {
+                        info!(stalled_ms = stalled.map(|s| s.as_millis() as u64), "🔈 Audio sink resumed - resyncing to server time");
+                        let _ = seek_to_server_time(&act.pipeline, &act.bus, server_time_ns);
+                        act.control.set_tempo(1.0);
+                        if let Some(duration_ns) = act.control.duration_ns() {
+                            current_seek_position_ns = server_time_ns % duration_ns;
                         }
-                    };
+                        playback_start_time = Instant::now();
+                        initial_server_time_ns = server_time_ns;
+                        suspend_detector.reset();
+                    }
+                }
 
-                    // 2. 最もRSSIが強いデバイス（ベストロケーション）を見つける
-                    let best_location = detected_devices.values()
-                        .filter(|d| sound_map_guard.contains_key(&d.address))
-                        .max_by_key(|d| d.rssi);
-
-                    // 3. 切り替え判断
-                    if let Some(best_dev) = best_location {
-                        // ベストロケーションのRSSIが現在のRSSIを十分に上回っているか？
-                        if best_dev.rssi > current_location_rssi + 3 { // +3のヒステリシスマージン
-                            let new_sound = sound_map_guard.get(&best_dev.address).unwrap().clone();
-                            if new_sound != current_sound {
-                                info!(
-                                    current_rssi = current_location_rssi,
-                                    best_rssi = best_dev.rssi,
-                                    new_sound = %new_sound,
-                                    "Switching BGM based on stronger RSSI"
-                                );
-                                new_sound // 切り替え先のサウンドを返す
-                            } else {
-                                current_sound.clone() // 同じサウンドなので維持
-                            }
-                        } else {
-                            current_sound.clone() // RSSIが上回らないので維持
+                // 判断の途中でsound_mapが差し替わっても、このスナップショットだけを見る
+                let snapshot = sound_map.load();
+                let program_sound = match &*program_rx.borrow() {
+                    ProgramOverride::Sound(sound) => Some(sound.clone()),
+                    _ => None,
+                };
+                let mut reaction = None;
+                let desired_sound = if let Some(override_sound) = bgm_override.current() {
+                    // サーバーからの上書きはRSSIによる選択より優先
+                    override_sound.to_string()
+                } else if let Some(program_sound) = program_sound {
+                    // 番組表による上書き（開場前・閉場）はサーバーからの上書きの次に優先
+                    program_sound
+                } else {
+                    match select_bgm(sound_switch.target(), &detected_devices, &snapshot) {
+                        BgmChoice::Switch { sound, device } => {
+                            info!(
+                                current_rssi = snapshot.address_of(sound_switch.target()).and_then(|addr| detected_devices.get(addr)).map_or(i16::MIN, |d| d.rssi),
+                                best_rssi = device.rssi,
+                                new_sound = %sound,
+                                sound_map_generation = snapshot.generation,
+                                "Switching BGM based on stronger RSSI"
+                            );
+                            reaction = ReactionTrace::new(device, Instant::now());
+                            sound.to_string() // 切り替え先のサウンドを返す
                         }
-                    } else {
+                        BgmChoice::Keep => sound_switch.target().to_string(),
                         // sound_mapに登録されているデバイスが1つも検知されなかった場合、デフォルトに戻す
-                        default_sound.clone()
+                        BgmChoice::Default => default_sound.clone(),
                     }
                 };
 
                 // 非同期切り替えの完了チェック
-                if let Ok(new_pipeline) = switch_rx.try_recv() {
+                let switch_result = latest_switch_result(switch_rx.try_recv(), switch_generation);
+                if let Some(Err(e)) = &switch_result {
+                    // 構築に失敗した場合は現在のパイプラインで再生を継続し、間をあけて再試行する
+                    let retry = sound_switch.failed(Instant::now());
+                    error!(retry_ms = retry.as_millis() as u64, "Failed to prepare switch pipeline, keeping current pipeline: {:?}", e);
+                    fsm.transition(PlaybackState::Playing, "switch failed");
+                }
+                if let Some(Ok(prepared)) = switch_result {
+                    let PreparedSwitch { pipeline: new_pipeline, requested_at, reaction, span } = prepared;
+                    let _switch_span = span.enter();
+                    let apply_start = Instant::now();
                     info!("✅ Instant switch: Applying new pipeline.");
 
                     // 1. 古いパイプラインを即座に停止
@@ -691,44 +1227,65 @@ pub fn audio_main(
                     // 2. 新しいパイプラインを即座に再生
                     info!("Starting new pipeline immediately.");
                     // 音量を最大に設定
-                    set_volume(&new_pipeline.volume, 1.0);
+                    new_pipeline.control.set_volume(mixer.gain(MixBus::Bgm));
                     // 再生開始
                     let _ = new_pipeline.pipeline.set_state(gst::State::Playing);
 
                     // 新しいパイプラインをアクティブに設定
                     active = Some(new_pipeline);
+                    sound_switch.applied();
+                    suspend_detector.reset();
 
                     // durationキャッシュを更新
                     if let Some(ref act) = active {
-                        if let Some(duration) = act.pipeline.query_duration::<gst::ClockTime>() {
-                            cached_duration_ns = Some(duration.nseconds());
-                        }
+                        act.control.refresh_duration();
                     }
 
                     // 同期を再設定
                     last_position_update = Instant::now();
-                    last_duration_query = Instant::now();
                     playback_start_time = Instant::now();
                     if let Some(t) = last_server_time_ns {
                         initial_server_time_ns = t;
                     }
 
-                    switching = false;
+                    fsm.transition(PlaybackState::Playing, "switch applied");
                     last_switch_end = Some(Instant::now());
-                    info!("🎉 Instant switch completed.");
+
+                    let apply_time = apply_start.elapsed();
+                    let total_time = requested_at.elapsed();
+                    metrics().switch_apply.observe(apply_time);
+                    metrics().switch_total.observe(total_time);
+                    if let Some(reaction) = reaction {
+                        reaction.observe(Instant::now());
+                    }
+                    if total_time > SWITCH_LATENCY_BUDGET {
+                        warn!(
+                            apply_ms = apply_time.as_millis() as u64,
+                            total_ms = total_time.as_millis() as u64,
+                            budget_ms = SWITCH_LATENCY_BUDGET.as_millis() as u64,
+                            "Switch exceeded latency budget"
+                        );
+                    }
+                    info!(
+                        apply_ms = apply_time.as_millis() as u64,
+                        total_ms = total_time.as_millis() as u64,
+                        "🎉 Instant switch completed."
+                    );
+                    events.publish(LocalEvent::SoundSwitched { sound: sound_switch.current().to_string() });
                 }
 
                 // 音源切り替えリクエスト処理
-                if desired_sound != current_sound && !switching {
+                // 切り替え中でも新しい判断を受け付ける（構築ワーカーが古いリクエストを破棄する）
+                if sound_switch.needs_switch(&desired_sound, Instant::now()) || variant_changed {
+                    variant_changed = false;
                     let current_points = current_points.lock().unwrap();
                     info!(
-                        from = %current_sound,
+                        from = %sound_switch.current(),
                         to = %desired_sound,
                         current_points = *current_points,
                         "🔄 音源切り替えリクエスト送信 (ポイント情報付き)"
                     );
-                    switching = true;
-                    current_sound = desired_sound.clone();
+                    fsm.transition(PlaybackState::Switching, "switch requested");
 
                     // スタンバイパイプラインがあれば停止して破棄
                     if let Some(old_standby) = standby.take() {
@@ -736,53 +1293,20 @@ pub fn audio_main(
                     }
 
                     // 非同期切り替えリクエストを送信
+                    switch_generation += 1;
                     let request = SwitchRequest {
-                        desired_sound: desired_sound.clone(),
+                        generation: switch_generation,
+                        desired_sound: localized(&desired_sound, language.as_deref()),
+                        preset: preset_for(&desired_sound, &snapshot, &config.presets),
                         seek_position_ns: current_seek_position_ns,
+                        requested_at: Instant::now(),
+                        reaction,
+                        span: tracing::info_span!("bgm_switch", generation = switch_generation, from = %sound_switch.current(), to = %desired_sound),
                     };
 
-                    let switch_tx_clone = switch_tx.clone();
-
-                    // 別スレッドで切り替え処理を実行
-                    std::thread::spawn(move || {
-                        info!("📦 非同期で新しいパイプラインを構築中...");
-
-                        match build_pipeline(&request.desired_sound) {
-                            Ok(next) => {
-                                set_volume(&next.volume, 1.0);
-                                if let Some(ref p) = next.pitch {
-                                    p.set_property("tempo", 1.0f32);
-                                }
-
-                                info!("⏸️  Paused状態で独自シーク位置 {} ns にシーク", request.seek_position_ns);
-                                let _ = next.pipeline.set_state(gst::State::Paused);
-                                wait_for_state(&next.pipeline, gst::State::Paused, Duration::from_secs(3), "async_switch_pause");
-
-                                let seek_position = gst::ClockTime::from_nseconds(request.seek_position_ns);
-                                let _ = next.pipeline.seek_simple(
-                                    gst::SeekFlags::FLUSH | gst::SeekFlags::ACCURATE,
-                                    seek_position
-                                );
-                                let _ = next.bus.timed_pop_filtered(
-                                    Some(gst::ClockTime::from_mseconds(500)),
-                                    &[gst::MessageType::AsyncDone]
-                                );
-                                info!("✓ シーク完了");
-
-                                // 🔥 重要：Paused状態のままメインスレッドに送信
-                                // メインスレッドで古いパイプラインを停止してからPlayingに切り替える
-                                info!("⏸️  パイプラインをPaused状態で準備完了、メインスレッドに送信");
-
-                                // 完成したパイプラインをメインスレッドに送信（Paused状態のまま）
-                                if let Err(e) = switch_tx_clone.blocking_send(next) {
-                                    error!("Failed to send new pipeline: {}", e);
-                                }
-                            }
-                            Err(e) => {
-                                error!("Failed to build pipeline: {}", e);
-                            }
-                        }
-                    });
+                    // 構築ワーカーに依頼（着手前の古いリクエストは置き換えられる）
+                    pipeline_builder.submit(request);
+                    sound_switch.requested(&desired_sound);
                 }
             }
         }
@@ -797,3 +1321,76 @@ pub fn audio_main(
     if let Some(st) = standby { let _ = st.pipeline.set_state(gst::State::Null); }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared_receiver_hands_pending_messages_to_the_next_loop() {
+        let (tx, rx) = mpsc::channel(8);
+        let abandoned_loop = SharedReceiver::new(rx);
+        let next_loop = abandoned_loop.clone();
+
+        tx.try_send(1).unwrap();
+        tx.try_send(2).unwrap();
+        // 止まったループが受け取らなかったメッセージは、次のループが受け取る
+        assert_eq!(next_loop.try_recv(), Ok(1));
+        assert_eq!(abandoned_loop.try_recv(), Ok(2));
+        assert_eq!(next_loop.try_recv(), Err(TryRecvError::Empty));
+
+        drop(tx);
+        assert_eq!(next_loop.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn build_failures_are_classified_by_source_kind() {
+        let existing = std::env::temp_dir().join(format!("tsukimi-build-failure-{}.mp3", std::process::id()));
+        std::fs::write(&existing, b"").unwrap();
+        assert_eq!(build_failure_signal(existing.to_str().unwrap()), FatalSignal::NoSink);
+        std::fs::remove_file(&existing).unwrap();
+        assert_eq!(build_failure_signal(existing.to_str().unwrap()), FatalSignal::AssetMissing);
+
+        // URL・ライブ配信の音源は手元にファイルが無くても音源の欠落とはみなさない
+        for source in ["https://example.com/bgm.mp3", "http://example.com/live.m3u8", "srt://192.0.2.1:9000", "rtp://0.0.0.0:5004"] {
+            assert_eq!(build_failure_signal(source), FatalSignal::NoSink, "{}", source);
+        }
+    }
+
+    #[test]
+    fn stale_switch_results_are_dropped() {
+        let (tx, mut rx) = mpsc::channel::<(u64, Result<&str>)>(4);
+        let builder = PipelineBuilder::spawn(move |(generation, sound): (u64, &'static str)| {
+            tx.blocking_send((generation, Ok(sound))).unwrap();
+        });
+        // 世代1の結果が届く前に、世代2の判断が出ている
+        builder.submit((1, "old.mp3"));
+        let stale = rx.blocking_recv().ok_or(TryRecvError::Disconnected);
+        assert!(latest_switch_result(stale, 2).is_none());
+
+        builder.submit((2, "new.mp3"));
+        let latest = rx.blocking_recv().ok_or(TryRecvError::Disconnected);
+        assert_eq!(latest_switch_result(latest, 2).unwrap().unwrap(), "new.mp3");
+        assert!(latest_switch_result::<&str>(Err(TryRecvError::Empty), 2).is_none());
+    }
+
+    #[test]
+    fn failed_switch_of_the_latest_generation_is_reported() {
+        let failed = latest_switch_result::<()>(Ok((3, Err(anyhow!("build failed")))), 3);
+        assert!(failed.unwrap().is_err());
+        assert!(latest_switch_result::<()>(Ok((2, Err(anyhow!("build failed")))), 3).is_none());
+    }
+
+    #[test]
+    fn drift_tolerance_is_the_deadband_or_the_clock_uncertainty() {
+        const MS: u64 = 1_000_000;
+        assert_eq!(drift_tolerance_ns(50 * MS, None), 50 * MS);
+        // システムのNTP（±20ms）は不感帯に収まる
+        assert_eq!(drift_tolerance_ns(50 * MS, Some(20 * MS)), 50 * MS);
+        assert_eq!(drift_tolerance_ns(0, Some(20 * MS)), 20 * MS);
+        assert_eq!(drift_tolerance_ns(50 * MS, Some(80 * MS)), 80 * MS);
+        // 古い同期で誤差が大きくなっても、上限を超えるずれは補正する
+        assert_eq!(drift_tolerance_ns(50 * MS, Some(2_000 * MS)), MAX_CLOCK_TOLERANCE_NS);
+        assert_eq!(drift_tolerance_ns(500 * MS, Some(2_000 * MS)), 500 * MS);
+    }
+}
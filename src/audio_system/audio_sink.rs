@@ -0,0 +1,89 @@
+use crate::config_system::config_main::{BgmOutput, PlaybackConfig, SeSink};
+
+/// メディアロールをWASAPIのストリームのロールにする
+fn wasapi_role(role: &str) -> &'static str {
+    match role {
+        "communication" | "phone" => "comms",
+        "music" | "video" | "game" => "multimedia",
+        _ => "console",
+    }
+}
+
+/// 出力先を `gst-launch` 形式のシンクの記述にする
+///
+/// `client_name` はPulseAudioのクライアント名（Linuxのみ）。
+pub fn sink_description(sink: &SeSink, client_name: &str) -> String {
+    if cfg!(target_os = "linux") {
+        // PulseAudioの場合は明示的にストリーム名とclient名を設定
+        let mut description = format!(
+            "pulsesink client-name=\"{}\" stream-properties=\"properties,media.role={}\"",
+            client_name, sink.role
+        );
+        if let Some(device) = &sink.device {
+            description.push_str(&format!(" device=\"{}\"", device));
+        }
+        description
+    } else if cfg!(target_os = "windows") {
+        let mut description = format!("wasapisink role={}", wasapi_role(&sink.role));
+        if let Some(device) = &sink.device {
+            description.push_str(&format!(" device=\"{}\"", device));
+        }
+        if sink.exclusive {
+            description.push_str(" exclusive=true low-latency=true");
+        }
+        description
+    } else if cfg!(target_os = "macos") {
+        match &sink.device {
+            Some(device) => format!("osxaudiosink device={}", device),
+            None => "osxaudiosink".to_string(),
+        }
+    } else {
+        "autoaudiosink".to_string()
+    }
+}
+
+/// BGMの出力先の記述（未設定なら各プラットフォームの既定の出力）
+pub fn bgm_sink_description(sink: Option<&SeSink>) -> String {
+    match sink {
+        Some(sink) => sink_description(sink, "tsukimi-bgm"),
+        None if cfg!(target_os = "linux") => "pulsesink".to_string(),
+        None => "autoaudiosink".to_string(),
+    }
+}
+
+/// 設定したBGMの出力先の記述（複数の出力先・遅延を含む）
+pub fn configured_bgm_sink(config: &PlaybackConfig) -> String {
+    if config.bgm_outputs.is_empty() {
+        with_delay(bgm_sink_description(config.bgm_output.as_ref()), config.output_delay_ms)
+    } else {
+        multi_sink_description(&config.bgm_outputs, config.output_delay_ms)
+    }
+}
+
+/// シンクの記述に固定の遅延を付ける
+///
+/// 遅延はシンクの `ts-offset` で掛けるため、再生位置とパイプラインのクロックは変わらず、サーバー時刻への同期はそのまま働く。
+pub fn with_delay(description: String, delay_ms: u64) -> String {
+    if delay_ms == 0 {
+        return description;
+    }
+    format!("{} ts-offset={}", description, delay_ms.saturating_mul(1_000_000))
+}
+
+/// BGMを複数の出力先に同時に鳴らす記述（teeで分岐し、出力先ごとに音量と遅延を変える）
+///
+/// `unit_delay_ms` はユニット全体の遅延で、各出力先の遅延に加える。
+pub fn multi_sink_description(outputs: &[BgmOutput], unit_delay_ms: u64) -> String {
+    let mut description = "tee name=bgm_tee".to_string();
+    for (index, output) in outputs.iter().enumerate() {
+        description.push_str(&format!(
+            " bgm_tee. ! queue ! volume volume={} ! audioconvert ! {}",
+            output.gain.max(0.0),
+            with_delay(
+                sink_description(&output.output, &format!("tsukimi-bgm-{}", index + 1)),
+                unit_delay_ms.saturating_add(output.delay_ms)
+            )
+        ));
+    }
+    description
+}
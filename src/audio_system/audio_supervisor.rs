@@ -0,0 +1,115 @@
+use crate::audio_system::audio_main::{audio_main, AudioChannels, AudioContext};
+use anyhow::{anyhow, Result};
+use crate::audio_system::playback_status::PlaybackStatus;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tracing::{error, info};
+
+/// パニック後、オーディオループを再開するまでの待ち時間
+const RESTART_BACKOFF: Duration = Duration::from_secs(1);
+
+/// オーディオループの終了と、監視タスクからの停止の知らせを確かめる間隔
+const STALL_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// この時間内にこの回数パニック・停止した場合は再開を諦める（起動直後から必ずパニックする場合など）
+const RESTART_WINDOW: Duration = Duration::from_secs(60);
+const MAX_RESTARTS_IN_WINDOW: usize = 5;
+
+/// パニックのペイロードを文字列として取り出す
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "<non-string panic payload>".to_string()
+    }
+}
+
+/// 1世代のオーディオループの終わり方
+enum LoopExit {
+    Returned(Result<()>),
+    Panicked(Box<dyn std::any::Any + Send>),
+    /// 監視タスクが停止を検知した（スレッドは見捨てる）
+    Stalled,
+}
+
+/// オーディオループを別スレッドで実行し、終わるか停止を検知するまで待つ
+fn run_generation(generation: u64, mut channels: AudioChannels, stall_rx: &mut watch::Receiver<u64>, context: &AudioContext, resume: Option<PlaybackStatus>) -> LoopExit {
+    let abandoned = Arc::new(AtomicBool::new(false));
+    let (done_tx, done_rx) = std::sync::mpsc::channel();
+    let span = tracing::Span::current();
+    let spawned = std::thread::Builder::new().name(format!("audio-main-{}", generation)).spawn({
+        let abandoned = Arc::clone(&abandoned);
+        let context = context.clone();
+        move || {
+            let _span = span.entered();
+            let result = catch_unwind(AssertUnwindSafe(|| audio_main(&mut channels, &abandoned, context, resume)));
+            let _ = done_tx.send(result);
+        }
+    });
+    if let Err(e) = spawned {
+        return LoopExit::Returned(Err(anyhow!("Failed to spawn audio thread: {}", e)));
+    }
+
+    // このループを始める前の検知は数えない
+    stall_rx.mark_unchanged();
+    loop {
+        match done_rx.recv_timeout(STALL_POLL_INTERVAL) {
+            Ok(Ok(result)) => return LoopExit::Returned(result),
+            Ok(Err(payload)) => return LoopExit::Panicked(payload),
+            // catch_unwindの外でスレッドが終わることはないが、念のためパニックとして扱う
+            Err(RecvTimeoutError::Disconnected) => return LoopExit::Panicked(Box::new("audio thread exited without a result")),
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+        // 監視タスクが無い（無効な）場合は送信側が無く、検知も届かない
+        if stall_rx.has_changed().unwrap_or(false) {
+            stall_rx.mark_unchanged();
+            abandoned.store(true, Ordering::Relaxed);
+            return LoopExit::Stalled;
+        }
+    }
+}
+
+/// オーディオループを実行し、パニックした場合・止まった場合は片付けて直前の再生状況から再開する
+///
+/// オーディオループは世代ごとに別スレッドで実行する。監視タスクが停止を検知して `stall_rx` で知らせた場合は、
+/// 止まったスレッドを見捨てて（戻ってきたらループを抜けさせる）新しいループを始める。
+/// チャンネルはこの関数が持ち続けるため、他のタスクからはオーディオループが再開したことは見えない。
+/// 短時間に再開を繰り返した場合はエラーを返す（プロセスの終了後、サービスマネージャーが再起動する）。
+pub fn audio_supervisor(channels: AudioChannels, mut stall_rx: watch::Receiver<u64>, context: AudioContext) -> Result<()> {
+    let mut resume = None;
+    let mut restarts: Vec<Instant> = Vec::new();
+    let mut generation = 0;
+
+    loop {
+        generation += 1;
+        let exit = run_generation(generation, channels.for_restart(), &mut stall_rx, &context, resume.take());
+        let message = match exit {
+            LoopExit::Returned(result) => return result,
+            LoopExit::Panicked(payload) => format!("panicked: {}", panic_message(payload.as_ref())),
+            LoopExit::Stalled => "stalled".to_string(),
+        };
+
+        // 巻き戻しで破棄されなかったパイプラインも止め、ロック中のパニックで毒された状態を戻す
+        context.heartbeat.teardown();
+        context.current_points.clear_poison();
+
+        restarts.retain(|restarted| restarted.elapsed() < RESTART_WINDOW);
+        restarts.push(Instant::now());
+        if restarts.len() > MAX_RESTARTS_IN_WINDOW {
+            error!(restarts = restarts.len(), "Audio loop keeps failing - giving up: {}", message);
+            return Err(anyhow!("Audio loop failed {} times within {:?}: {}", restarts.len(), RESTART_WINDOW, message));
+        }
+
+        let status = channels.status_tx.borrow().clone();
+        error!(generation, sound = %status.sound, position_ns = status.position_ns, "Audio loop {} - restarting", message);
+        std::thread::sleep(RESTART_BACKOFF);
+        info!(generation = generation + 1, "Restarting audio loop");
+        resume = Some(status);
+    }
+}
@@ -0,0 +1,114 @@
+use crate::clock_system::time_source::SharedTimeSource;
+use crate::messages::BgmOverrideRequest;
+use std::time::Instant;
+use tracing::info;
+
+/// RSSIによるBGM選択より優先される上書きレイヤー
+///
+/// 上書き中はRSSIに関わらず指定された音源を再生し、期限が切れると
+/// 通常の選択ロジックに戻る。
+#[derive(Debug)]
+pub struct BgmOverride {
+    active: Option<(String, Instant)>,
+    time: SharedTimeSource,
+}
+
+impl BgmOverride {
+    pub fn new(time: SharedTimeSource) -> Self {
+        Self { active: None, time }
+    }
+
+    /// リクエストを適用する（新しいリクエストは既存の上書きを置き換える）
+    pub fn apply(&mut self, request: BgmOverrideRequest) {
+        match request.sound {
+            Some(sound) if !request.duration.is_zero() => {
+                info!(%sound, duration_ms = request.duration.as_millis() as u64, "BGM override started");
+                self.active = Some((sound, self.time.now() + request.duration));
+            }
+            _ => {
+                if self.active.take().is_some() {
+                    info!("BGM override cleared by server");
+                }
+            }
+        }
+    }
+
+    /// 有効な上書き音源を返す（期限切れの場合は解除して `None`）
+    pub fn current(&mut self) -> Option<&str> {
+        let now = self.time.now();
+        if self.active.as_ref().is_some_and(|(_, expires_at)| now >= *expires_at) {
+            if let Some((sound, _)) = self.active.take() {
+                info!(%sound, "BGM override expired - returning to RSSI-based selection");
+            }
+        }
+        self.active.as_ref().map(|(sound, _)| sound.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock_system::time_source::MockTimeSource;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn request(sound: Option<&str>, duration_secs: u64) -> BgmOverrideRequest {
+        BgmOverrideRequest { sound: sound.map(str::to_string), duration: Duration::from_secs(duration_secs) }
+    }
+
+    #[test]
+    fn override_lasts_until_it_expires() {
+        let time = Arc::new(MockTimeSource::new());
+        let mut bgm_override = BgmOverride::new(time.clone());
+        assert_eq!(bgm_override.current(), None);
+
+        bgm_override.apply(request(Some("finale.mp3"), 30));
+        time.advance(Duration::from_secs(29));
+        assert_eq!(bgm_override.current(), Some("finale.mp3"));
+        time.advance(Duration::from_secs(1));
+        assert_eq!(bgm_override.current(), None);
+        // 期限切れで解除された後は戻らない
+        time.advance(Duration::from_secs(1));
+        assert_eq!(bgm_override.current(), None);
+    }
+
+    #[test]
+    fn new_request_replaces_the_override_and_its_deadline() {
+        let time = Arc::new(MockTimeSource::new());
+        let mut bgm_override = BgmOverride::new(time.clone());
+        bgm_override.apply(request(Some("finale.mp3"), 10));
+        time.advance(Duration::from_secs(5));
+        bgm_override.apply(request(Some("encore.mp3"), 10));
+        time.advance(Duration::from_secs(9));
+        assert_eq!(bgm_override.current(), Some("encore.mp3"));
+    }
+
+    #[test]
+    fn no_sound_or_zero_duration_clears_the_override() {
+        let time = Arc::new(MockTimeSource::new());
+        let mut bgm_override = BgmOverride::new(time);
+        for clear in [request(None, 30), request(Some("finale.mp3"), 0)] {
+            bgm_override.apply(request(Some("finale.mp3"), 30));
+            bgm_override.apply(clear);
+            assert_eq!(bgm_override.current(), None);
+        }
+    }
+
+    #[test]
+    fn override_received_while_disabled_counts_from_its_arrival() {
+        let time = Arc::new(MockTimeSource::new());
+        let mut bgm_override = BgmOverride::new(time.clone());
+        // 無効化中に届いた上書き（オーディオループは無効化中も受け取って期限を数え始める）
+        bgm_override.apply(request(Some("finale.mp3"), 60));
+        time.advance(Duration::from_secs(45));
+        // 再有効化後は残りの時間だけ上書きする
+        assert_eq!(bgm_override.current(), Some("finale.mp3"));
+        time.advance(Duration::from_secs(15));
+        assert_eq!(bgm_override.current(), None);
+
+        // 無効化中に期限が切れた上書きは、再有効化後に使わない
+        bgm_override.apply(request(Some("finale.mp3"), 60));
+        time.advance(Duration::from_secs(120));
+        assert_eq!(bgm_override.current(), None);
+    }
+}
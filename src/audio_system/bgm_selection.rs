@@ -0,0 +1,129 @@
+use crate::bluetooth_system::address::Address;
+use crate::connect_system::sound_map::SoundMap;
+use crate::messages::DeviceInfo;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// 切り替えに必要な、現在地のビーコンとのRSSIの差（dBm。境界での行ったり来たりを防ぐ）
+pub const SWITCH_HYSTERESIS_DB: i16 = 3;
+
+/// RSSIによるBGMの選択結果
+#[derive(Debug)]
+pub enum BgmChoice<'a> {
+    /// 現在のサウンドを維持する
+    Keep,
+    /// より強いビーコンのサウンドに切り替える
+    Switch { sound: &'a str, device: &'a DeviceInfo },
+    /// sound_mapに登録されているデバイスが1つも検知されていない
+    Default,
+}
+
+/// 検知したデバイスから、再生すべきBGMを選ぶ
+pub fn select_bgm<'a>(current_sound: &str, detected_devices: &'a HashMap<Address, Arc<DeviceInfo>>, sound_map: &'a SoundMap) -> BgmChoice<'a> {
+    // 1. 現在のロケーションのRSSIを取得
+    let current_location_rssi = {
+        if let Some(addr) = sound_map.address_of(current_sound) {
+            // 現在地のビーコンが見つかればそのRSSIを、見つからなければ最低値を設定
+            detected_devices.get(addr).map_or(i16::MIN, |d| d.rssi)
+        } else {
+            // 現在のサウンドがデフォルト等の場合も最低値
+            i16::MIN
+        }
+    };
+
+    // 2. 最もRSSIが強いデバイス（ベストロケーション）を見つける
+    let best_location = detected_devices.values().filter(|d| sound_map.contains(&d.address)).max_by_key(|d| d.rssi);
+
+    // 3. 切り替え判断
+    let Some(best_dev) = best_location else {
+        return BgmChoice::Default;
+    };
+    // ベストロケーションのRSSIが現在のRSSIを十分に上回っているか？
+    if best_dev.rssi <= current_location_rssi.saturating_add(SWITCH_HYSTERESIS_DB) {
+        return BgmChoice::Keep;
+    }
+    let sound = sound_map.sounds[&best_dev.address].as_str();
+    if sound == current_sound {
+        // 同じサウンドなので維持
+        BgmChoice::Keep
+    } else {
+        BgmChoice::Switch { sound, device: best_dev }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    const FIRST: &str = "AA:BB:CC:DD:EE:01";
+    const SECOND: &str = "AA:BB:CC:DD:EE:02";
+    const PHONE: &str = "AA:BB:CC:DD:EE:99";
+
+    fn sound_map() -> SoundMap {
+        SoundMap {
+            sounds: HashMap::from([(FIRST.parse().unwrap(), "first.mp3".to_string()), (SECOND.parse().unwrap(), "second.mp3".to_string())]),
+            ..Default::default()
+        }
+    }
+
+    fn detected(readings: &[(&str, i16)]) -> HashMap<Address, Arc<DeviceInfo>> {
+        readings
+            .iter()
+            .map(|&(address, rssi)| {
+                let address: Address = address.parse().unwrap();
+                let device = DeviceInfo {
+                    address: address.clone(),
+                    rssi,
+                    last_seen: Instant::now(),
+                    received_at: Instant::now(),
+                    forwarded_at: None,
+                    visitor_token: None,
+                    local_name: None,
+                    tx_power: None,
+                    manufacturer_data: HashMap::new(),
+                    service_data: HashMap::new(),
+                };
+                (address, Arc::new(device))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn no_registered_beacon_falls_back_to_default() {
+        let sound_map = sound_map();
+        let devices = detected(&[(PHONE, -30)]);
+        assert!(matches!(select_bgm("first.mp3", &devices, &sound_map), BgmChoice::Default));
+    }
+
+    #[test]
+    fn switches_from_default_to_strongest_beacon() {
+        let sound_map = sound_map();
+        let devices = detected(&[(FIRST, -80), (SECOND, -60), (PHONE, -30)]);
+        match select_bgm("default.mp3", &devices, &sound_map) {
+            BgmChoice::Switch { sound, device } => {
+                assert_eq!(sound, "second.mp3");
+                assert_eq!(device.rssi, -60);
+            }
+            other => panic!("unexpected choice: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn hysteresis_keeps_current_location() {
+        let sound_map = sound_map();
+        let devices = detected(&[(FIRST, -60), (SECOND, -60 + SWITCH_HYSTERESIS_DB)]);
+        assert!(matches!(select_bgm("first.mp3", &devices, &sound_map), BgmChoice::Keep));
+
+        let devices = detected(&[(FIRST, -60), (SECOND, -60 + SWITCH_HYSTERESIS_DB + 1)]);
+        assert!(matches!(select_bgm("first.mp3", &devices, &sound_map), BgmChoice::Switch { sound: "second.mp3", .. }));
+    }
+
+    #[test]
+    fn current_location_out_of_range_switches_to_any_beacon() {
+        let sound_map = sound_map();
+        let devices = detected(&[(SECOND, -95)]);
+        assert!(matches!(select_bgm("first.mp3", &devices, &sound_map), BgmChoice::Switch { sound: "second.mp3", .. }));
+        assert!(matches!(select_bgm("second.mp3", &devices, &sound_map), BgmChoice::Keep));
+    }
+}
@@ -0,0 +1,37 @@
+use crate::config_system::config_main::ChimeConfig;
+use crate::messages::{ConnectionState, SePlayRequest};
+use tracing::info;
+
+/// サーバーとの接続状態の変化から、再接続を知らせるSEを鳴らすか判断する
+///
+/// 初回の接続は接続チャイム（`chime.connected`）が担うため、一度接続した後に切れて戻った場合だけ鳴らす。
+#[derive(Debug)]
+pub struct ConnectionCue {
+    file: Option<String>,
+    gain: f64,
+    /// 一度でも接続できた
+    was_connected: bool,
+    /// 接続していた後に切れている
+    lost: bool,
+}
+
+impl ConnectionCue {
+    pub fn new(chime: &ChimeConfig) -> Self {
+        Self { file: chime.reconnected.clone(), gain: chime.reconnected_gain, was_connected: false, lost: false }
+    }
+
+    /// 接続状態の変化を反映し、鳴らすSEがあれば返す
+    pub fn update(&mut self, state: ConnectionState) -> Option<SePlayRequest> {
+        if !state.is_connected() {
+            self.lost |= self.was_connected;
+            return None;
+        }
+        self.was_connected = true;
+        if !std::mem::take(&mut self.lost) {
+            return None;
+        }
+        let file_path = self.file.clone()?;
+        info!(file = %file_path, ?state, "Server reconnected - queueing reconnected SE");
+        Some(SePlayRequest { file_path, priority: false, gain: Some(self.gain), command_id: None })
+    }
+}
@@ -0,0 +1,106 @@
+use crate::config_system::config_main::DriftCorrection;
+use glib::BoolError;
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use std::cell::Cell;
+use tracing::{debug, warn};
+
+/// 再生速度の変化がこれ未満の場合は、シークを発行しない（`Scaletempo` のみ。シークはテンポの設定より重いため）
+const RATE_EPSILON: f64 = 0.005;
+
+/// 補正方式に応じたテンポ調整の要素の記述（BGMのパイプラインのcapsfilterの後ろに挿入する）
+pub fn element_description(mode: DriftCorrection) -> &'static str {
+    match mode {
+        // pitchプラグインの前にqueueを追加して、十分なバッファサイズを確保
+        // これによりSoundTouchライブラリのFIRFilterのアサーションエラーを回避
+        DriftCorrection::Pitch => " ! queue max-size-buffers=100 max-size-time=1000000000 ! pitch name=pch",
+        DriftCorrection::Scaletempo => " ! scaletempo",
+        DriftCorrection::SeekOnly => "",
+    }
+}
+
+/// 再生速度を変える要素
+enum TempoKind {
+    /// SoundTouchの `pitch` 要素の `tempo`
+    Pitch(gst::Element),
+    /// シークで再生速度を変える（`INSTANT_RATE_CHANGE`。使えない場合は現在位置へのFLUSHシーク）
+    Rate,
+    /// 速度は変えない
+    Fixed,
+}
+
+/// BGMのパイプラインの再生速度を変える手段
+///
+/// 直前に設定した速度を持ち、速度が変わらない場合は要素に触れない（SoundTouchの再処理を避けるため）。
+pub struct TempoControl {
+    kind: TempoKind,
+    current: Cell<f64>,
+}
+
+impl TempoControl {
+    /// 構築したパイプラインから補正方式に応じた手段を作る
+    pub fn new(mode: DriftCorrection, pipeline: &gst::Pipeline) -> Self {
+        let kind = match mode {
+            DriftCorrection::Pitch => pipeline.by_name("pch").map_or(TempoKind::Fixed, TempoKind::Pitch),
+            DriftCorrection::Scaletempo => TempoKind::Rate,
+            DriftCorrection::SeekOnly => TempoKind::Fixed,
+        };
+        Self { kind, current: Cell::new(1.0) }
+    }
+
+    /// 再生速度を設定する（1.0が等速）
+    ///
+    /// FLUSHシークは `Scaletempo` の速度を等速に戻すため、シークの後は1.0を設定し直すこと。
+    pub fn set(&self, pipeline: &gst::Pipeline, rate: f64) {
+        match &self.kind {
+            TempoKind::Pitch(pitch) => {
+                if self.current.get() == rate {
+                    return;
+                }
+                pitch.set_property("tempo", rate as f32);
+            }
+            TempoKind::Rate => {
+                if (self.current.get() - rate).abs() < RATE_EPSILON {
+                    return;
+                }
+                if let Err(e) = change_rate(pipeline, rate) {
+                    warn!(rate, "Failed to change playback rate: {}", e);
+                    return;
+                }
+            }
+            TempoKind::Fixed => return,
+        }
+        self.current.set(rate);
+    }
+}
+
+/// 再生位置を変えずにパイプラインの再生速度を変える
+///
+/// `INSTANT_RATE_CHANGE`（GStreamer 1.18以降）はフラッシュせずに速度だけを変えるため音が途切れない。
+/// 速度のないシークは要素によっては無視されるため使わない。`INSTANT_RATE_CHANGE` に対応していない要素がある場合は、
+/// 現在位置へのFLUSH・ACCURATEシークで変える（一瞬途切れる）。
+fn change_rate(pipeline: &gst::Pipeline, rate: f64) -> Result<(), BoolError> {
+    let instant = pipeline.seek(
+        rate,
+        gst::SeekFlags::INSTANT_RATE_CHANGE,
+        gst::SeekType::None,
+        gst::ClockTime::NONE,
+        gst::SeekType::None,
+        gst::ClockTime::NONE,
+    );
+    let Err(e) = instant else {
+        return Ok(());
+    };
+    debug!(rate, "Instant rate change not supported ({}), seeking to the current position", e);
+    let position = pipeline
+        .query_position::<gst::ClockTime>()
+        .ok_or_else(|| glib::bool_error!("Failed to query the playback position"))?;
+    pipeline.seek(
+        rate,
+        gst::SeekFlags::FLUSH | gst::SeekFlags::ACCURATE,
+        gst::SeekType::Set,
+        position,
+        gst::SeekType::None,
+        gst::ClockTime::NONE,
+    )
+}
@@ -0,0 +1,90 @@
+use anyhow::{anyhow, Result};
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tracing::{debug, info, warn};
+
+/// 設置時に耳で聞き分けるための致命的な異常の種類（種類ごとにビープ回数が異なる）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatalSignal {
+    /// Bluetoothアダプタが見つからない
+    #[cfg(feature = "ble")]
+    NoAdapter,
+    /// 音声出力（シンク）を開けない
+    NoSink,
+    /// 音源ファイルが見つからない
+    AssetMissing,
+}
+
+impl FatalSignal {
+    /// 1パターンあたりのビープ回数
+    pub fn beeps(self) -> u32 {
+        match self {
+            #[cfg(feature = "ble")]
+            FatalSignal::NoAdapter => 2,
+            FatalSignal::NoSink => 3,
+            FatalSignal::AssetMissing => 4,
+        }
+    }
+}
+
+const BEEP_ON: Duration = Duration::from_millis(150);
+const BEEP_OFF: Duration = Duration::from_millis(150);
+const TONE_FREQ_HZ: f64 = 1760.0;
+const TONE_VOLUME: f64 = 0.4;
+
+/// エラートーンを鳴らす（ブロッキング。鳴り終わるまで戻らない）
+///
+/// 音源ファイルやPulseAudioが無い状態でも鳴るよう、`audiotestsrc` と
+/// `autoaudiosink` で利用可能な出力に直接出力する。
+pub fn play_error_tone(signal: FatalSignal) -> Result<()> {
+    gst::init()?;
+    let pipeline_str = format!(
+        "audiotestsrc wave=square freq={} volume={} ! audioconvert ! audioresample ! autoaudiosink",
+        TONE_FREQ_HZ, TONE_VOLUME
+    );
+    let pipeline = gst::parse::launch(&pipeline_str)?
+        .downcast::<gst::Pipeline>()
+        .map_err(|_| anyhow!("Failed to downcast to Pipeline"))?;
+
+    info!(?signal, beeps = signal.beeps(), "🚨 Playing error tone");
+    let mut result = Ok(());
+    for _ in 0..signal.beeps() {
+        if let Err(e) = pipeline.set_state(gst::State::Playing) {
+            result = Err(anyhow!("Failed to play error tone: {}", e));
+            break;
+        }
+        std::thread::sleep(BEEP_ON);
+        let _ = pipeline.set_state(gst::State::Paused);
+        std::thread::sleep(BEEP_OFF);
+    }
+    if let Err(e) = pipeline.set_state(gst::State::Null) {
+        warn!("Failed to stop error tone pipeline: {}", e);
+    }
+    result
+}
+
+/// エラートーンを鳴らしているスレッドがあるか（失敗が続いてもトーンを重ねて鳴らさない）
+static TONE_PLAYING: AtomicBool = AtomicBool::new(false);
+
+/// エラートーンを専用のスレッドで鳴らす（鳴り終わるのを待たずに戻る）
+///
+/// オーディオスレッドなど止められないループから呼ぶためのもの。前のトーンが
+/// 鳴っている間に呼ばれた場合は鳴らさない。
+pub fn spawn_error_tone(signal: FatalSignal) {
+    if TONE_PLAYING.swap(true, Ordering::AcqRel) {
+        debug!(?signal, "Error tone already playing");
+        return;
+    }
+    let spawned = std::thread::Builder::new().name("error-tone".to_string()).spawn(move || {
+        if let Err(e) = play_error_tone(signal) {
+            warn!("Failed to play error tone: {:?}", e);
+        }
+        TONE_PLAYING.store(false, Ordering::Release);
+    });
+    if let Err(e) = spawned {
+        warn!("Failed to spawn error tone thread: {:?}", e);
+        TONE_PLAYING.store(false, Ordering::Release);
+    }
+}
@@ -0,0 +1,21 @@
+use crate::config_system::config_main::LiveStreamConfig;
+
+/// コントロールルームからのライブ配信（`srt://`・`rtp://`）かどうか
+///
+/// ライブ配信はシークできず長さもないため、サーバー時刻への同期とドリフト補正の対象外になる。
+pub fn is_live(sound: &str) -> bool {
+    sound.starts_with("srt://") || sound.starts_with("rtp://")
+}
+
+/// ライブ配信を受信してデコードするGStreamerの要素の記述
+///
+/// RTPはOpusを前提とし、`rtp://<受信アドレス>:<ポート>` で待ち受ける（マルチキャストも可）。
+pub fn live_source_description(uri: &str, config: &LiveStreamConfig) -> String {
+    match uri.strip_prefix("rtp://") {
+        Some(address) => format!(
+            "udpsrc name=src uri=udp://{} caps=\"{}\" ! rtpjitterbuffer latency={} ! rtpopusdepay ! opusdec",
+            address, config.rtp_caps, config.latency_ms
+        ),
+        None => format!("srtsrc name=src uri={} latency={} ! decodebin", uri, config.latency_ms),
+    }
+}
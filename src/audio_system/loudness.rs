@@ -0,0 +1,64 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// BGMのパイプラインに挿入するレベル計測の要素（100msごとにバスへ通知する）
+pub const LEVEL_ELEMENT: &str = " ! level name=meter interval=100000000 post-messages=true";
+
+/// EBU R128のモーメンタリー・ショートタームの窓
+const MOMENTARY_WINDOW: Duration = Duration::from_millis(400);
+const SHORT_TERM_WINDOW: Duration = Duration::from_secs(3);
+
+/// 出力のラウドネスの計測
+///
+/// `level` 要素のチャンネルごとのRMSから、EBU R128のモーメンタリー・ショートタームのラウドネスを近似する。
+/// Kウェイティングをかけていないため正確なLUFSではないが、同じ音源を鳴らしているユニット同士の比較には十分。
+/// 計測点は出力先のシンクの直前（複数の出力先の場合は分岐の前）で、ミキサー・マスター音量は反映される。
+#[derive(Debug, Default)]
+pub struct LoudnessMeter {
+    /// (計測時刻, チャンネルの平均二乗の和)
+    samples: VecDeque<(Instant, f64)>,
+    peak_db: Option<f64>,
+}
+
+/// ラウドネスのスナップショット（計測値が無い場合は `None`）
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct LoudnessSnapshot {
+    pub momentary_lufs: Option<f64>,
+    pub short_term_lufs: Option<f64>,
+    /// 直近の通知のチャンネルの最大ピーク（dBFS）
+    pub peak_db: Option<f64>,
+}
+
+impl LoudnessMeter {
+    /// `level` 要素の通知1回分（チャンネルごとのRMS・ピーク、dBFS）を反映する
+    pub fn observe(&mut self, rms_db: &[f64], peak_db: &[f64]) {
+        let now = Instant::now();
+        let power: f64 = rms_db.iter().map(|db| 10f64.powf(db / 10.0)).sum();
+        self.samples.push_back((now, power));
+        while self.samples.front().is_some_and(|(at, _)| now.duration_since(*at) > SHORT_TERM_WINDOW) {
+            self.samples.pop_front();
+        }
+        self.peak_db = peak_db.iter().copied().reduce(f64::max);
+    }
+
+    fn loudness(&self, window: Duration) -> Option<f64> {
+        let now = Instant::now();
+        let (sum, count) = self
+            .samples
+            .iter()
+            .filter(|(at, _)| now.duration_since(*at) <= window)
+            .fold((0.0, 0), |(sum, count), (_, power)| (sum + power, count + 1));
+        (count > 0).then(|| -0.691 + 10.0 * (sum / count as f64).max(f64::MIN_POSITIVE).log10())
+    }
+
+    pub fn snapshot(&self) -> LoudnessSnapshot {
+        let momentary_lufs = self.loudness(MOMENTARY_WINDOW);
+        LoudnessSnapshot {
+            momentary_lufs,
+            short_term_lufs: self.loudness(SHORT_TERM_WINDOW),
+            // 通知が途切れた（停止中）場合は古いピークを出さない
+            peak_db: momentary_lufs.and(self.peak_db),
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use tokio::sync::watch;
+
+/// サーバーから指定されるユニット全体の音量（BGM・SEの両方に適用）
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MasterVolume {
+    /// 絶対音量（0.0〜1.0）
+    pub volume: f64,
+    pub muted: bool,
+}
+
+impl Default for MasterVolume {
+    fn default() -> Self {
+        Self { volume: 1.0, muted: false }
+    }
+}
+
+impl MasterVolume {
+    /// 各チェーンの音量に掛ける倍率（ミュート時・数値でない場合は0）
+    pub fn gain(&self) -> f64 {
+        if self.muted || self.volume.is_nan() {
+            0.0
+        } else {
+            self.volume.clamp(0.0, 1.0)
+        }
+    }
+}
+
+/// マスター音量を配信するwatchチャンネルを作成する
+pub fn channel() -> (watch::Sender<MasterVolume>, watch::Receiver<MasterVolume>) {
+    watch::channel(MasterVolume::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn volume(volume: f64, muted: bool) -> MasterVolume {
+        MasterVolume { volume, muted }
+    }
+
+    #[test]
+    fn gain_follows_the_volume() {
+        assert_eq!(MasterVolume::default().gain(), 1.0);
+        assert_eq!(volume(0.25, false).gain(), 0.25);
+        assert_eq!(volume(0.0, false).gain(), 0.0);
+    }
+
+    #[test]
+    fn muted_volume_has_no_gain() {
+        assert_eq!(volume(0.8, true).gain(), 0.0);
+        assert_eq!(volume(5.0, true).gain(), 0.0);
+    }
+
+    #[test]
+    fn out_of_range_volume_is_clamped() {
+        assert_eq!(volume(-0.5, false).gain(), 0.0);
+        assert_eq!(volume(1.5, false).gain(), 1.0);
+        assert_eq!(volume(f64::INFINITY, false).gain(), 1.0);
+        assert_eq!(volume(f64::NEG_INFINITY, false).gain(), 0.0);
+        // 壊れた値で出力が数値でなくならないようにする
+        assert_eq!(volume(f64::NAN, false).gain(), 0.0);
+    }
+
+    #[test]
+    fn channel_starts_at_full_volume() {
+        let (_tx, rx) = channel();
+        assert_eq!(*rx.borrow(), MasterVolume::default());
+    }
+}
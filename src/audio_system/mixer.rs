@@ -0,0 +1,172 @@
+use crate::config_system::config_main::{BusConfig, MixBus, MixerConfig};
+use crate::messages::MixerCommand;
+
+/// BGM・SE・アナウンスのバスごとの音量を決めるミキサー
+///
+/// 各チェーンの音量は「マスター音量 × バスの音量 × 鳴っている他のバスによるダッキング」になる。
+/// BGMは常に鳴っているものとして扱い、SEは同時に1つだけ鳴る。
+pub struct Mixer {
+    config: MixerConfig,
+    master_gain: f64,
+    /// 再生中のSEのバス
+    sounding_se: Option<MixBus>,
+    /// 番組によってBGMを無音にしている
+    bgm_silenced: bool,
+    /// 終了時のフェードアウトによる全バス共通の倍率（0.0〜1.0）
+    fade: f64,
+}
+
+impl Mixer {
+    pub fn new(config: MixerConfig, master_gain: f64) -> Self {
+        Self { config, master_gain, sounding_se: None, bgm_silenced: false, fade: 1.0 }
+    }
+
+    fn bus(&self, bus: MixBus) -> &BusConfig {
+        match bus {
+            MixBus::Bgm => &self.config.bgm,
+            MixBus::Se => &self.config.se,
+            MixBus::Announce => &self.config.announce,
+        }
+    }
+
+    fn bus_mut(&mut self, bus: MixBus) -> &mut BusConfig {
+        match bus {
+            MixBus::Bgm => &mut self.config.bgm,
+            MixBus::Se => &mut self.config.se,
+            MixBus::Announce => &mut self.config.announce,
+        }
+    }
+
+    /// チェーンの `volume` 要素に設定する値
+    pub fn gain(&self, bus: MixBus) -> f64 {
+        let config = self.bus(bus);
+        if config.muted || (bus == MixBus::Bgm && self.bgm_silenced) {
+            return 0.0;
+        }
+        let duck: f64 = [Some(MixBus::Bgm), self.sounding_se]
+            .into_iter()
+            .flatten()
+            .filter(|other| *other != bus)
+            .filter_map(|other| self.bus(other).duck.get(&bus))
+            .product();
+        self.master_gain * config.gain.max(0.0) * duck * self.fade
+    }
+
+    pub fn set_master_gain(&mut self, gain: f64) {
+        self.master_gain = gain;
+    }
+
+    pub fn set_bgm_silenced(&mut self, silenced: bool) {
+        self.bgm_silenced = silenced;
+    }
+
+    pub fn set_fade(&mut self, fade: f64) {
+        self.fade = fade.clamp(0.0, 1.0);
+    }
+
+    pub fn set_sounding_se(&mut self, bus: Option<MixBus>) {
+        self.sounding_se = bus;
+    }
+
+    pub fn apply(&mut self, command: MixerCommand) {
+        let config = self.bus_mut(command.bus);
+        if let Some(gain) = command.gain {
+            config.gain = gain;
+        }
+        if let Some(muted) = command.muted {
+            config.muted = muted;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn bus(gain: f64, duck: &[(MixBus, f64)]) -> BusConfig {
+        BusConfig { gain, muted: false, duck: duck.iter().copied().collect::<BTreeMap<_, _>>() }
+    }
+
+    /// BGMは通常のSEを少し、SEはBGMを、アナウンスはBGMとSEを絞る
+    fn mixer() -> Mixer {
+        let config = MixerConfig {
+            bgm: bus(1.0, &[(MixBus::Se, 0.8)]),
+            se: bus(2.0, &[(MixBus::Bgm, 0.3)]),
+            announce: bus(3.0, &[(MixBus::Bgm, 0.5), (MixBus::Se, 0.5)]),
+        };
+        Mixer::new(config, 0.5)
+    }
+
+    fn gains(mixer: &Mixer) -> [f64; 3] {
+        [mixer.gain(MixBus::Bgm), mixer.gain(MixBus::Se), mixer.gain(MixBus::Announce)]
+    }
+
+    fn assert_gains(actual: [f64; 3], expected: [f64; 3]) {
+        for (actual, expected) in actual.iter().zip(expected) {
+            assert!((actual - expected).abs() < 1e-9, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn gain_is_master_times_bus_times_ducking() {
+        let mut mixer = mixer();
+        // BGMは常に鳴っているので、SEのバスはBGMによるダッキングを受ける
+        assert_gains(gains(&mixer), [0.5, 0.5 * 2.0 * 0.8, 0.5 * 3.0]);
+
+        mixer.set_sounding_se(Some(MixBus::Se));
+        assert_gains(gains(&mixer), [0.5 * 0.3, 0.5 * 2.0 * 0.8, 0.5 * 3.0]);
+
+        mixer.set_sounding_se(None);
+        assert_gains(gains(&mixer), [0.5, 0.8, 1.5]);
+    }
+
+    #[test]
+    fn overlapping_ducks_multiply() {
+        let mut mixer = mixer();
+        mixer.set_sounding_se(Some(MixBus::Announce));
+        // SEのバスはBGM（0.8）とアナウンス（0.5）の両方に絞られる
+        assert_gains(gains(&mixer), [0.5 * 0.5, 0.5 * 2.0 * 0.8 * 0.5, 0.5 * 3.0]);
+    }
+
+    #[test]
+    fn muted_master_silences_every_bus() {
+        let mut mixer = mixer();
+        mixer.set_master_gain(0.0);
+        mixer.set_sounding_se(Some(MixBus::Se));
+        assert_gains(gains(&mixer), [0.0; 3]);
+        mixer.set_master_gain(1.0);
+        assert_gains(gains(&mixer), [0.3, 1.6, 3.0]);
+    }
+
+    #[test]
+    fn muted_and_silenced_buses() {
+        let mut mixer = mixer();
+        mixer.set_bgm_silenced(true);
+        assert_gains(gains(&mixer), [0.0, 0.8, 1.5]);
+        mixer.set_bgm_silenced(false);
+
+        mixer.apply(MixerCommand { bus: MixBus::Se, gain: None, muted: Some(true) });
+        assert_gains(gains(&mixer), [0.5, 0.0, 1.5]);
+        // ミュートしたバスが鳴っていても他のバスのダッキングは続く
+        mixer.set_sounding_se(Some(MixBus::Se));
+        assert_eq!(mixer.gain(MixBus::Bgm), 0.5 * 0.3);
+
+        mixer.apply(MixerCommand { bus: MixBus::Se, gain: Some(1.0), muted: Some(false) });
+        assert_eq!(mixer.gain(MixBus::Se), 0.5 * 1.0 * 0.8);
+        // 負の音量は0として扱う
+        mixer.apply(MixerCommand { bus: MixBus::Announce, gain: Some(-1.0), muted: None });
+        assert_eq!(mixer.gain(MixBus::Announce), 0.0);
+    }
+
+    #[test]
+    fn fade_scales_every_bus_and_is_clamped() {
+        let mut mixer = mixer();
+        mixer.set_fade(0.5);
+        assert_gains(gains(&mixer), [0.25, 0.4, 0.75]);
+        mixer.set_fade(-1.0);
+        assert_gains(gains(&mixer), [0.0; 3]);
+        mixer.set_fade(2.0);
+        assert_gains(gains(&mixer), [0.5, 0.8, 1.5]);
+    }
+}
@@ -0,0 +1,101 @@
+use std::sync::{Arc, Condvar, Mutex};
+use tracing::{debug, error, info};
+
+struct Slot<R> {
+    /// まだ着手していない最新のリクエスト（新しいリクエストで上書きされる）
+    pending: Option<R>,
+    shutdown: bool,
+}
+
+/// パイプライン構築を担当する専用ワーカースレッド
+///
+/// 切り替えのたびにスレッドを生成する代わりに、1本のワーカーが順番に処理する。
+/// 着手前のリクエストは新しいリクエストで置き換えられるため、素早く切り替えが
+/// 続いても古い音源のパイプラインを無駄に構築しない。
+pub struct PipelineBuilder<R> {
+    shared: Arc<(Mutex<Slot<R>>, Condvar)>,
+}
+
+impl<R: Send + 'static> PipelineBuilder<R> {
+    /// ワーカースレッドを起動する。`job` はワーカースレッド上でリクエストごとに呼ばれる
+    pub fn spawn(mut job: impl FnMut(R) + Send + 'static) -> Self {
+        let shared = Arc::new((Mutex::new(Slot { pending: None, shutdown: false }), Condvar::new()));
+        let worker_shared = Arc::clone(&shared);
+        let spawned = std::thread::Builder::new()
+            .name("pipeline-builder".to_string())
+            .spawn(move || {
+                let (lock, cvar) = &*worker_shared;
+                loop {
+                    let request = {
+                        let mut slot = lock.lock().unwrap();
+                        while slot.pending.is_none() && !slot.shutdown {
+                            slot = cvar.wait(slot).unwrap();
+                        }
+                        if slot.shutdown {
+                            break;
+                        }
+                        slot.pending.take()
+                    };
+                    if let Some(request) = request {
+                        job(request);
+                    }
+                }
+                debug!("Pipeline builder worker stopped");
+            });
+        if let Err(e) = spawned {
+            error!("Failed to spawn pipeline builder thread: {}", e);
+        }
+        Self { shared }
+    }
+
+    /// リクエストを登録する。着手前のリクエストがあれば破棄して `true` を返す
+    pub fn submit(&self, request: R) -> bool {
+        let (lock, cvar) = &*self.shared;
+        let superseded = lock.lock().unwrap().pending.replace(request).is_some();
+        if superseded {
+            info!("Superseded a pending pipeline build request");
+        }
+        cvar.notify_one();
+        superseded
+    }
+}
+
+impl<R> Drop for PipelineBuilder<R> {
+    fn drop(&mut self) {
+        let (lock, cvar) = &*self.shared;
+        lock.lock().unwrap().shutdown = true;
+        cvar.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    fn pending_request_is_superseded_while_the_worker_is_busy() {
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let (built_tx, built_rx) = mpsc::channel();
+        let builder = PipelineBuilder::spawn(move |generation: u64| {
+            started_tx.send(generation).unwrap();
+            if generation == 1 {
+                release_rx.recv().unwrap();
+            }
+            built_tx.send(generation).unwrap();
+        });
+
+        assert!(!builder.submit(1));
+        assert_eq!(started_rx.recv_timeout(Duration::from_secs(5)), Ok(1));
+        // 世代1の構築中に届いた世代2は、着手前に世代3で置き換えられる
+        assert!(!builder.submit(2));
+        assert!(builder.submit(3));
+        release_tx.send(()).unwrap();
+
+        assert_eq!(built_rx.recv_timeout(Duration::from_secs(5)), Ok(1));
+        assert_eq!(built_rx.recv_timeout(Duration::from_secs(5)), Ok(3));
+        assert!(built_rx.recv_timeout(Duration::from_millis(100)).is_err());
+    }
+}
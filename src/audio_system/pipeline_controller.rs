@@ -0,0 +1,148 @@
+use crate::audio_system::drift_correction::TempoControl;
+use crate::clock_system::time_source::SharedTimeSource;
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+/// durationを問い合わせ直す間隔
+const DURATION_TTL: Duration = Duration::from_secs(1);
+
+/// BGMのパイプラインへのプロパティ設定・問い合わせをまとめる
+///
+/// メインループは毎周同じ値を設定・問い合わせるため、最後に設定した音量・テンポと
+/// 最後に得たdurationを持ち、値が変わったとき・期限が切れたときだけGStreamerを呼ぶ。
+pub struct PipelineController {
+    pipeline: gst::Pipeline,
+    volume: gst::Element,
+    tempo: TempoControl,
+    last_volume: Cell<Option<f64>>,
+    /// 最後に得たduration（ナノ秒）
+    duration: TtlCache<u64>,
+}
+
+impl PipelineController {
+    pub fn new(pipeline: gst::Pipeline, volume: gst::Element, tempo: TempoControl, time: SharedTimeSource) -> Self {
+        Self { pipeline, volume, tempo, last_volume: Cell::new(None), duration: TtlCache::new(DURATION_TTL, time) }
+    }
+
+    pub fn set_volume(&self, volume: f64) {
+        if self.last_volume.get() == Some(volume) {
+            return;
+        }
+        self.volume.set_property("volume", volume);
+        self.last_volume.set(Some(volume));
+    }
+
+    /// 再生速度を設定する（1.0が等速。変わらない場合は何もしない）
+    pub fn set_tempo(&self, rate: f64) {
+        self.tempo.set(&self.pipeline, rate);
+    }
+
+    /// 音源の長さ（ナノ秒）。問い合わせは `DURATION_TTL` に1回だけ行い、失敗した場合は最後に得た値を返す
+    pub fn duration_ns(&self) -> Option<u64> {
+        self.duration.get_or_refresh(|| self.query_duration())
+    }
+
+    /// 現在の再生位置（ナノ秒。出力先のサスペンドの検知用）
+    pub fn position_ns(&self) -> Option<u64> {
+        self.pipeline.query_position::<gst::ClockTime>().map(|position| position.nseconds())
+    }
+
+    /// 期限に関わらずdurationを問い合わせ直す（再生開始・シークの直後用）
+    pub fn refresh_duration(&self) -> Option<u64> {
+        self.duration.refresh(|| self.query_duration())
+    }
+
+    fn query_duration(&self) -> Option<u64> {
+        self.pipeline.query_duration::<gst::ClockTime>().map(|duration| duration.nseconds()).filter(|duration| *duration > 0)
+    }
+}
+
+/// 最後に得た値と、次に問い合わせ直すまでの期限
+///
+/// 問い合わせに失敗した場合は最後に得た値を使い続ける（失敗も期限まで問い合わせ直さない）。
+struct TtlCache<T: Copy> {
+    ttl: Duration,
+    time: SharedTimeSource,
+    value: Cell<Option<T>>,
+    queried_at: Cell<Option<Instant>>,
+}
+
+impl<T: Copy> TtlCache<T> {
+    fn new(ttl: Duration, time: SharedTimeSource) -> Self {
+        Self { ttl, time, value: Cell::new(None), queried_at: Cell::new(None) }
+    }
+
+    /// 期限内なら最後に得た値、期限が切れていれば問い合わせ直した値
+    fn get_or_refresh(&self, query: impl FnOnce() -> Option<T>) -> Option<T> {
+        if self.queried_at.get().is_some_and(|queried| self.time.elapsed(queried) < self.ttl) {
+            return self.value.get();
+        }
+        self.refresh(query)
+    }
+
+    /// 期限に関わらず問い合わせ直す
+    fn refresh(&self, query: impl FnOnce() -> Option<T>) -> Option<T> {
+        self.queried_at.set(Some(self.time.now()));
+        if let Some(value) = query() {
+            self.value.set(Some(value));
+        }
+        self.value.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock_system::time_source::MockTimeSource;
+    use std::sync::Arc;
+
+    fn cache() -> (TtlCache<u64>, Arc<MockTimeSource>) {
+        let time = Arc::new(MockTimeSource::new());
+        (TtlCache::new(DURATION_TTL, time.clone()), time)
+    }
+
+    #[test]
+    fn value_is_reused_until_it_expires() {
+        let (cache, time) = cache();
+        let queries = Cell::new(0);
+        let query = || {
+            queries.set(queries.get() + 1);
+            Some(queries.get() * 100)
+        };
+        assert_eq!(cache.get_or_refresh(query), Some(100));
+        time.advance(DURATION_TTL - Duration::from_millis(1));
+        assert_eq!(cache.get_or_refresh(query), Some(100));
+        assert_eq!(queries.get(), 1);
+
+        time.advance(Duration::from_millis(1));
+        assert_eq!(cache.get_or_refresh(query), Some(200));
+        assert_eq!(queries.get(), 2);
+    }
+
+    #[test]
+    fn refresh_replaces_the_value_before_it_expires() {
+        let (cache, time) = cache();
+        assert_eq!(cache.get_or_refresh(|| Some(100)), Some(100));
+        assert_eq!(cache.refresh(|| Some(250)), Some(250));
+        // 問い合わせ直した時刻から期限を数え直す
+        time.advance(DURATION_TTL / 2);
+        assert_eq!(cache.get_or_refresh(|| Some(999)), Some(250));
+        time.advance(DURATION_TTL / 2);
+        assert_eq!(cache.get_or_refresh(|| Some(300)), Some(300));
+    }
+
+    #[test]
+    fn failed_query_keeps_the_last_value() {
+        let (cache, time) = cache();
+        assert_eq!(cache.get_or_refresh(|| None), None);
+        // 失敗も期限まで問い合わせ直さない
+        assert_eq!(cache.get_or_refresh(|| Some(100)), None);
+        time.advance(DURATION_TTL);
+        assert_eq!(cache.get_or_refresh(|| Some(100)), Some(100));
+        time.advance(DURATION_TTL);
+        assert_eq!(cache.get_or_refresh(|| None), Some(100));
+        assert_eq!(cache.refresh(|| None), Some(100));
+    }
+}
@@ -0,0 +1,345 @@
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+/// BGM再生ライフサイクルの状態
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaybackState {
+    /// システム無効化中（パイプラインなし、または一時停止・ミュートして保持）
+    Disabled,
+    /// サーバー時刻の同期待ち（アクティブパイプライン未構築）
+    WaitingForSync,
+    /// BGM再生中
+    Playing,
+    /// 切り替え先パイプラインを別スレッドで構築中（再生は継続）
+    Switching,
+    /// 有効化SEを優先するため、BGMの開始を保留中
+    SePriority,
+    /// パイプラインエラー後、再構築を待っている
+    Recovering,
+}
+
+/// SE再生の状態（BGMの状態とは独立して遷移する）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeState {
+    Idle,
+    Playing,
+    /// 割り込み不可のSE（サーバーからの優先SE・アナウンス）を再生中
+    Priority,
+}
+
+/// 再生ライフサイクルの状態機械
+///
+/// 以前は `switching` / `is_se_playing` / `should_play_activation_se` などのフラグで
+/// 暗黙的に管理していた状態を明示し、遷移ごとにログを出力する。
+pub struct PlaybackFsm {
+    state: PlaybackState,
+    entered_at: Instant,
+    se_state: SeState,
+    activation_se_pending: bool,
+    /// 無効化中にアクティブを一時停止して保持し始めた時刻
+    paused_at: Option<Instant>,
+}
+
+impl Default for PlaybackFsm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PlaybackFsm {
+    pub fn new() -> Self {
+        Self {
+            state: PlaybackState::WaitingForSync,
+            entered_at: Instant::now(),
+            se_state: SeState::Idle,
+            activation_se_pending: false,
+            paused_at: None,
+        }
+    }
+
+    pub fn state(&self) -> PlaybackState {
+        self.state
+    }
+
+    /// 現在の状態に入ってからの経過時間
+    pub fn time_in_state(&self) -> Duration {
+        self.entered_at.elapsed()
+    }
+
+    pub fn is_switching(&self) -> bool {
+        self.state == PlaybackState::Switching
+    }
+
+    /// 許可された遷移かどうか
+    fn is_allowed(from: PlaybackState, to: PlaybackState) -> bool {
+        use PlaybackState::*;
+        matches!(
+            (from, to),
+            (_, Disabled)
+                | (Disabled, SePriority | WaitingForSync)
+                | (SePriority, WaitingForSync | Playing)
+                | (WaitingForSync, Playing | Recovering)
+                | (Playing, Switching | Recovering)
+                | (Switching, Playing | Recovering)
+                | (Recovering, WaitingForSync)
+        )
+    }
+
+    /// 状態を遷移させる。同一状態・許可されていない遷移の場合は何もせず `false` を返す
+    pub fn transition(&mut self, to: PlaybackState, reason: &str) -> bool {
+        let from = self.state;
+        if from == to {
+            return false;
+        }
+        if !Self::is_allowed(from, to) {
+            warn!(?from, ?to, reason, "Rejected playback state transition");
+            return false;
+        }
+        info!(?from, ?to, reason, elapsed_ms = self.time_in_state().as_millis() as u64, "Playback state transition");
+        self.state = to;
+        self.entered_at = Instant::now();
+        true
+    }
+
+    pub fn set_se_state(&mut self, to: SeState, reason: &str) {
+        if self.se_state != to {
+            debug!(from = ?self.se_state, ?to, reason, "SE state transition");
+            self.se_state = to;
+        }
+    }
+
+    /// 新しいSEリクエストを受け付けるかどうか（優先SEの再生中は通常のSEで割り込まない）
+    pub fn accepts_se(&self, priority: bool) -> bool {
+        priority || self.se_state != SeState::Priority
+    }
+
+    /// 有効化SEの再生を予約する
+    pub fn request_activation_se(&mut self) {
+        self.activation_se_pending = true;
+    }
+
+    pub fn activation_se_pending(&self) -> bool {
+        self.activation_se_pending
+    }
+
+    /// 有効化SEを発行し、その再生（または後から割り込んだSE）が終わったかどうか
+    pub fn activation_se_finished(&self) -> bool {
+        !self.activation_se_pending && self.se_state == SeState::Idle
+    }
+
+    /// 再生すべき有効化SEがあれば予約を取り消して `true` を返す（他のSE再生中は保留）
+    pub fn take_activation_se(&mut self) -> bool {
+        if self.activation_se_pending && self.se_state == SeState::Idle {
+            self.activation_se_pending = false;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// 有効化SEの発行（`wait_for_finish` の場合は再生終了）を待ち終えたかどうか
+    pub fn activation_se_done(&self, wait_for_finish: bool) -> bool {
+        if wait_for_finish {
+            self.activation_se_finished()
+        } else {
+            !self.activation_se_pending
+        }
+    }
+
+    /// 有効化SEを待ち終えた後の遷移先（一時停止して保持しているアクティブがあればそのまま再開する）
+    pub fn after_activation_se(&self) -> PlaybackState {
+        if self.paused_at.is_some() {
+            PlaybackState::Playing
+        } else {
+            PlaybackState::WaitingForSync
+        }
+    }
+
+    /// 無効化中もアクティブを破棄せず、一時停止して保持する（`DisableMode::Pause`）
+    pub fn hold_paused(&mut self) {
+        self.paused_at = Some(Instant::now());
+    }
+
+    /// 保持していたアクティブを手放し（再開・破棄時）、保持していた時間を返す
+    pub fn release_paused(&mut self) -> Option<Duration> {
+        self.paused_at.take().map(|paused_at| paused_at.elapsed())
+    }
+
+    /// 無効化が長引き、保持しているアクティブを破棄すべきかどうか
+    pub fn idle_teardown_due(&self, idle_teardown: Duration) -> bool {
+        self.state == PlaybackState::Disabled && self.paused_at.is_some_and(|paused_at| paused_at.elapsed() > idle_teardown)
+    }
+
+    /// 無効化時にSE関連の状態をすべて初期化する
+    pub fn reset_se(&mut self) {
+        self.activation_se_pending = false;
+        self.set_se_state(SeState::Idle, "reset");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use PlaybackState::*;
+
+    const ALL: [PlaybackState; 6] = [Disabled, WaitingForSync, Playing, Switching, SePriority, Recovering];
+
+    fn fsm_in(state: PlaybackState) -> PlaybackFsm {
+        let mut fsm = PlaybackFsm::new();
+        fsm.state = state;
+        fsm
+    }
+
+    #[test]
+    fn starts_waiting_for_sync() {
+        let fsm = PlaybackFsm::new();
+        assert_eq!(fsm.state(), WaitingForSync);
+        assert!(!fsm.is_switching());
+        assert!(fsm.activation_se_finished());
+    }
+
+    #[test]
+    fn normal_lifecycle() {
+        let mut fsm = PlaybackFsm::new();
+        assert!(fsm.transition(Playing, "synced"));
+        assert!(fsm.transition(Switching, "stronger beacon"));
+        assert!(fsm.is_switching());
+        assert!(fsm.transition(Playing, "switched"));
+        assert!(fsm.transition(Recovering, "pipeline error"));
+        assert!(fsm.transition(WaitingForSync, "rebuilt"));
+        assert!(fsm.transition(Playing, "synced"));
+        assert!(fsm.transition(Disabled, "disabled"));
+        assert!(fsm.transition(SePriority, "enabled"));
+        assert!(fsm.transition(WaitingForSync, "activation SE finished"));
+    }
+
+    #[test]
+    fn any_state_can_be_disabled() {
+        for from in ALL.into_iter().filter(|&s| s != Disabled) {
+            let mut fsm = fsm_in(from);
+            assert!(fsm.transition(Disabled, "disabled"), "{:?}", from);
+            assert_eq!(fsm.state(), Disabled);
+        }
+    }
+
+    #[test]
+    fn rejected_transitions_keep_the_state() {
+        let rejected = [(WaitingForSync, Switching), (Switching, WaitingForSync), (Recovering, Playing), (Disabled, Playing), (Playing, SePriority), (Disabled, Recovering)];
+        for (from, to) in rejected {
+            let mut fsm = fsm_in(from);
+            assert!(!fsm.transition(to, "test"), "{:?} -> {:?}", from, to);
+            assert_eq!(fsm.state(), from);
+        }
+    }
+
+    #[test]
+    fn transition_to_the_same_state_is_a_no_op() {
+        for state in ALL {
+            assert!(!fsm_in(state).transition(state, "test"));
+        }
+    }
+
+    #[test]
+    fn transition_restarts_the_state_timer() {
+        let mut fsm = PlaybackFsm::new();
+        fsm.entered_at = Instant::now() - Duration::from_secs(60);
+        assert!(fsm.time_in_state() >= Duration::from_secs(60));
+        fsm.transition(Playing, "synced");
+        assert!(fsm.time_in_state() < Duration::from_secs(60));
+    }
+
+    #[test]
+    fn priority_se_is_not_interrupted_by_normal_se() {
+        let mut fsm = PlaybackFsm::new();
+        assert!(fsm.accepts_se(false));
+        fsm.set_se_state(SeState::Playing, "se");
+        assert!(fsm.accepts_se(false));
+        fsm.set_se_state(SeState::Priority, "announcement");
+        assert!(!fsm.accepts_se(false));
+        assert!(fsm.accepts_se(true));
+    }
+
+    #[test]
+    fn activation_se_waits_for_playing_se() {
+        let mut fsm = PlaybackFsm::new();
+        fsm.set_se_state(SeState::Playing, "se");
+        fsm.request_activation_se();
+        assert!(fsm.activation_se_pending());
+        assert!(!fsm.take_activation_se());
+
+        fsm.set_se_state(SeState::Idle, "finished");
+        assert!(fsm.take_activation_se());
+        assert!(!fsm.activation_se_pending());
+        // 発行しただけでは終わっていない（再生が始まれば SeState が変わる）
+        fsm.set_se_state(SeState::Playing, "activation se");
+        assert!(!fsm.activation_se_finished());
+        fsm.set_se_state(SeState::Idle, "finished");
+        assert!(fsm.activation_se_finished());
+        assert!(!fsm.take_activation_se());
+    }
+
+    #[test]
+    fn reset_se_clears_pending_activation() {
+        let mut fsm = PlaybackFsm::new();
+        fsm.request_activation_se();
+        fsm.set_se_state(SeState::Priority, "announcement");
+        fsm.reset_se();
+        assert!(!fsm.activation_se_pending());
+        assert!(fsm.accepts_se(false));
+        assert!(fsm.activation_se_finished());
+    }
+
+    #[test]
+    fn paused_pipeline_resumes_only_after_the_activation_se() {
+        let mut fsm = fsm_in(Playing);
+        fsm.transition(Disabled, "disabled");
+        fsm.hold_paused();
+
+        // 再有効化時も有効化SEを待つため、直接Playingには戻らない
+        fsm.request_activation_se();
+        assert!(!fsm.transition(Playing, "re-enabled"));
+        assert!(fsm.transition(SePriority, "re-enabled"));
+        assert!(!fsm.activation_se_done(true));
+        assert!(fsm.take_activation_se());
+        fsm.set_se_state(SeState::Playing, "activation se");
+        assert!(fsm.activation_se_done(false));
+        assert!(!fsm.activation_se_done(true));
+        fsm.set_se_state(SeState::Idle, "finished");
+        assert!(fsm.activation_se_done(true));
+
+        assert_eq!(fsm.after_activation_se(), Playing);
+        assert!(fsm.transition(Playing, "paused pipeline resumed"));
+        assert!(fsm.release_paused().is_some());
+        assert!(fsm.release_paused().is_none());
+    }
+
+    #[test]
+    fn re_enable_without_a_paused_pipeline_waits_for_sync() {
+        let mut fsm = fsm_in(Disabled);
+        assert!(fsm.transition(SePriority, "re-enabled"));
+        // 有効化SEを使わない設定ではすぐに待ち終える
+        assert!(fsm.activation_se_done(true));
+        assert!(fsm.activation_se_done(false));
+        assert_eq!(fsm.after_activation_se(), WaitingForSync);
+    }
+
+    #[test]
+    fn idle_teardown_is_due_only_while_disabled_for_too_long() {
+        let idle = Duration::from_secs(60);
+        let mut fsm = fsm_in(Disabled);
+        assert!(!fsm.idle_teardown_due(idle));
+        fsm.hold_paused();
+        assert!(!fsm.idle_teardown_due(idle));
+
+        fsm.paused_at = Some(Instant::now() - Duration::from_secs(61));
+        assert!(fsm.idle_teardown_due(idle));
+        // 有効化SEを待っている間は破棄しない
+        fsm.transition(SePriority, "re-enabled");
+        assert!(!fsm.idle_teardown_due(idle));
+
+        fsm.transition(Disabled, "disabled again");
+        assert!(fsm.idle_teardown_due(idle));
+        assert!(fsm.release_paused().is_some_and(|held| held >= idle));
+        assert!(!fsm.idle_teardown_due(idle));
+    }
+}
@@ -0,0 +1,71 @@
+use crate::audio_system::loudness::LoudnessSnapshot;
+use crate::audio_system::playback_fsm::PlaybackState;
+use crate::audio_system::sync_quality::{SyncQuality, SyncQualitySnapshot};
+use crate::clock_system::clock_main::ShowTime;
+use crate::clock_system::time_source;
+use std::time::Instant;
+use tokio::sync::watch;
+
+/// オーディオループが公開する現在の再生状況（サーバーへの状態報告に使う）
+#[derive(Debug, Clone)]
+pub struct PlaybackStatus {
+    /// 再生中のサウンドファイル
+    pub sound: String,
+    /// 独自管理しているシーク位置
+    pub position_ns: u64,
+    /// 直近に計測したサーバー時刻とのずれ（正の値はクライアントが遅れている）
+    pub drift_ns: i64,
+    /// 同期の品質（ずれの平均・再シーク・テンポ補正の回数）
+    pub sync_quality: SyncQualitySnapshot,
+    /// 出力のラウドネス（会場全体でユニットのレベルを揃えるため）
+    pub loudness: LoudnessSnapshot,
+    pub enabled: bool,
+    pub state: PlaybackState,
+    /// 直近に参照したショー時刻（未同期の場合は `None`）
+    pub clock: Option<ShowTime>,
+}
+
+impl Default for PlaybackStatus {
+    fn default() -> Self {
+        Self {
+            sound: String::new(),
+            position_ns: 0,
+            drift_ns: 0,
+            sync_quality: SyncQuality::new(time_source::system()).snapshot(),
+            loudness: LoudnessSnapshot::default(),
+            enabled: true,
+            state: PlaybackState::WaitingForSync,
+            clock: None,
+        }
+    }
+}
+
+/// 直近の再生状況から現在の再生位置を推定するための基準点
+pub struct PositionAnchor {
+    pub sound: String,
+    position_ns: u64,
+    received_at: Instant,
+}
+
+impl PositionAnchor {
+    /// 再生中でなければ `None`（停止中に位置を送り続けないため）
+    pub fn from_status(status: &PlaybackStatus) -> Option<Self> {
+        // 切り替え中も古いパイプラインの再生は継続している
+        let playing = matches!(status.state, PlaybackState::Playing | PlaybackState::Switching);
+        (playing && status.enabled).then(|| Self {
+            sound: status.sound.clone(),
+            position_ns: status.position_ns,
+            received_at: Instant::now(),
+        })
+    }
+
+    /// 再生状況の公開間隔（1秒）の間は経過時間で補間する
+    pub fn position_ns(&self) -> u64 {
+        self.position_ns + self.received_at.elapsed().as_nanos() as u64
+    }
+}
+
+/// 再生状況を配信するwatchチャンネルを作成する
+pub fn channel() -> (watch::Sender<PlaybackStatus>, watch::Receiver<PlaybackStatus>) {
+    watch::channel(PlaybackStatus::default())
+}
@@ -0,0 +1,25 @@
+use crate::config_system::config_main::AudioPreset;
+
+/// `equalizer-10bands` のバンド数
+const EQ_BANDS: usize = 10;
+
+/// プリセットを `gst-launch` 形式の要素の記述にする（BGMチェーンのステレオのF32の後ろに挿入する）
+///
+/// 既定値のままの項目は要素を挿入しない。
+pub fn preset_description(preset: &AudioPreset) -> String {
+    let mut description = String::new();
+    if preset.gain != 1.0 {
+        description.push_str(&format!(" ! volume volume={}", preset.gain.max(0.0)));
+    }
+    if preset.eq.iter().any(|gain| *gain != 0.0) {
+        description.push_str(" ! equalizer-10bands");
+        for (band, gain) in preset.eq.iter().take(EQ_BANDS).enumerate() {
+            // equalizer-10bandsの範囲は-24dB〜+12dB
+            description.push_str(&format!(" band{}={}", band, gain.clamp(-24.0, 12.0)));
+        }
+    }
+    if preset.pan != 0.0 {
+        description.push_str(&format!(" ! audiopanorama panorama={}", preset.pan.clamp(-1.0, 1.0)));
+    }
+    description
+}
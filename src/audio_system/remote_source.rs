@@ -0,0 +1,135 @@
+#[cfg(feature = "server")]
+use crate::config_system::config_main::RemoteSourceConfig;
+#[cfg(feature = "server")]
+use crate::connect_system::sound_map::SharedSoundMap;
+#[cfg(feature = "server")]
+use crate::net_system::egress::http_client;
+#[cfg(feature = "server")]
+use anyhow::{Context, Result};
+use std::collections::hash_map::DefaultHasher;
+#[cfg(feature = "server")]
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+#[cfg(feature = "server")]
+use std::sync::Arc;
+#[cfg(feature = "server")]
+use std::time::Duration;
+#[cfg(feature = "server")]
+use tracing::{info, instrument, warn};
+
+/// `http(s)://` の音源（サーバーが指定した、アセット同期前の新しいミックスなど）かどうか
+pub fn is_remote(sound: &str) -> bool {
+    sound.starts_with("http://") || sound.starts_with("https://")
+}
+
+/// HLSのプレイリストかどうか（セグメントに分かれているためキャッシュしない）
+#[cfg(feature = "server")]
+fn is_hls(url: &str) -> bool {
+    url.split(['?', '#']).next().is_some_and(|path| path.ends_with(".m3u8"))
+}
+
+/// URLの音源をダウンロードして置いておくキャッシュ
+#[derive(Debug, Clone)]
+pub struct RemoteCache {
+    dir: PathBuf,
+}
+
+impl RemoteCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// URLに対応するキャッシュファイルのパス（拡張子はURLに合わせる）
+    fn path_for(&self, url: &str) -> PathBuf {
+        let mut hasher = DefaultHasher::new();
+        url.hash(&mut hasher);
+        let extension = url
+            .split(['?', '#'])
+            .next()
+            .and_then(|path| Path::new(path).extension())
+            .and_then(|extension| extension.to_str())
+            .unwrap_or("bin");
+        self.dir.join(format!("{:016x}.{}", hasher.finish(), extension))
+    }
+
+    /// キャッシュ済みならそのパス
+    pub fn cached(&self, url: &str) -> Option<PathBuf> {
+        let path = self.path_for(url);
+        path.exists().then_some(path)
+    }
+
+    /// 音源を読み込んでデコードするGStreamerの要素の記述
+    ///
+    /// URLの音源はキャッシュ済みならローカルのファイルを、なければストリーミングで再生する。
+    pub fn source_description(&self, sound: &str) -> String {
+        if !is_remote(sound) {
+            return format!("filesrc name=src location={} ! decodebin", sound);
+        }
+        match self.cached(sound) {
+            Some(path) => format!("filesrc name=src location={} ! decodebin", path.display()),
+            None => format!("uridecodebin name=src uri={}", sound),
+        }
+    }
+
+    /// URLの音源をダウンロードする（書きかけのファイルを再生しないよう一時ファイルから置き換える）
+    #[cfg(feature = "server")]
+    async fn download(&self, client: &reqwest::Client, url: &str) -> Result<PathBuf> {
+        let path = self.path_for(url);
+        let bytes = client
+            .get(url)
+            .timeout(Duration::from_secs(300))
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .with_context(|| format!("Failed to create remote cache directory: {}", self.dir.display()))?;
+        let tmp_path = path.with_extension("part");
+        tokio::fs::write(&tmp_path, &bytes)
+            .await
+            .with_context(|| format!("Failed to write remote cache: {}", tmp_path.display()))?;
+        tokio::fs::rename(&tmp_path, &path)
+            .await
+            .with_context(|| format!("Failed to replace remote cache: {}", path.display()))?;
+        Ok(path)
+    }
+}
+
+/// sound_mapに含まれるURLの音源を先読みしてキャッシュするタスク
+///
+/// キャッシュが済むまではストリーミングで再生し、次にパイプラインを作り直すときからキャッシュを使う。
+///
+/// スタンドアロン構成（`server` フィーチャーなし）では先読みせず、常にストリーミングで再生する。
+#[cfg(feature = "server")]
+#[instrument(skip_all)]
+pub async fn remote_cache_main(config: RemoteSourceConfig, sound_map: Arc<SharedSoundMap>) {
+    let cache = RemoteCache::new(&config.cache_dir);
+    let client = http_client();
+    // 失敗したURLは次の周期で取り直す
+    let mut interval = tokio::time::interval(Duration::from_millis(config.prefetch_interval_ms.max(1000)));
+
+    loop {
+        interval.tick().await;
+        let urls: HashSet<String> = sound_map
+            .load()
+            .sounds
+            .values()
+            .filter(|sound| is_remote(sound) && !is_hls(sound))
+            .cloned()
+            .collect();
+
+        for url in urls {
+            if cache.cached(&url).is_some() {
+                continue;
+            }
+            info!(%url, "Caching remote sound");
+            match cache.download(&client, &url).await {
+                Ok(path) => info!(%url, path = %path.display(), "Remote sound cached"),
+                Err(e) => warn!(%url, "Failed to cache remote sound: {:?}", e),
+            }
+        }
+    }
+}
@@ -0,0 +1,331 @@
+use crate::audio_system::asset_variant::localized;
+use crate::audio_system::audio_main::wait_for_state;
+use crate::audio_system::audio_sink::sink_description;
+use crate::config_system::config_main::{MixBus, SeOutputConfig, SeSink};
+use anyhow::{anyhow, Result};
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use std::path::Path;
+use std::time::Duration;
+use tracing::{error, info};
+
+/// SEのパイプラインの操作（テストでは鳴らさない偽物に差し替える）
+pub trait SePipelines {
+    type Pipeline;
+
+    /// パイプラインを構築して再生を始める
+    fn launch(&mut self, description: &str) -> Result<Self::Pipeline>;
+    /// `se_vol` の音量を変える
+    fn set_volume(&self, pipeline: &Self::Pipeline, volume: f64);
+    /// 止めて、止まるまで待つ
+    fn stop(&self, pipeline: &Self::Pipeline, label: &str);
+    /// 待たずに止める（破棄時）
+    fn discard(&self, pipeline: &Self::Pipeline);
+    /// バスを確認し、再生が終わった（EOS・エラー）かどうかを返す
+    fn poll(&self, pipeline: &Self::Pipeline) -> bool;
+}
+
+/// GStreamerのパイプラインでSEを鳴らす
+pub struct GstPipelines;
+
+impl SePipelines for GstPipelines {
+    type Pipeline = gst::Pipeline;
+
+    fn launch(&mut self, description: &str) -> Result<gst::Pipeline> {
+        let pipeline = gst::parse::launch(description)
+            .map_err(|e| anyhow!("Failed to build SE pipeline: {}", e))?
+            .downcast::<gst::Pipeline>()
+            .map_err(|_| anyhow!("SE pipeline is not a gst::Pipeline"))?;
+        pipeline.set_state(gst::State::Playing)?;
+        Ok(pipeline)
+    }
+
+    fn set_volume(&self, pipeline: &gst::Pipeline, volume: f64) {
+        if let Some(element) = pipeline.by_name("se_vol") {
+            element.set_property("volume", volume);
+        }
+    }
+
+    fn stop(&self, pipeline: &gst::Pipeline, label: &str) {
+        if pipeline.set_state(gst::State::Null).is_ok() {
+            wait_for_state(pipeline, gst::State::Null, Duration::from_millis(500), label);
+        }
+    }
+
+    fn discard(&self, pipeline: &gst::Pipeline) {
+        let _ = pipeline.set_state(gst::State::Null);
+    }
+
+    fn poll(&self, pipeline: &gst::Pipeline) -> bool {
+        let Some(bus) = pipeline.bus() else {
+            return false;
+        };
+        let mut finished = false;
+        while let Some(msg) = bus.timed_pop(gst::ClockTime::from_mseconds(1)) {
+            use gst::MessageView;
+            match msg.view() {
+                MessageView::Eos(_) => {
+                    info!("🎵 SE再生完了 (EOS受信) - パイプラインを終了します");
+                    finished = true;
+                }
+                MessageView::Error(err) => {
+                    error!("❌ SEパイプラインエラー: error={}, debug={:?}", err.error(), err.debug());
+                    finished = true;
+                }
+                MessageView::StateChanged(state_changed) if state_changed.src() == Some(pipeline.upcast_ref::<gst::Object>()) => {
+                    info!(
+                        "🔄 SEパイプライン状態変更: {:?} -> {:?} (pending: {:?})",
+                        state_changed.old(),
+                        state_changed.current(),
+                        state_changed.pending()
+                    );
+                }
+                MessageView::StreamStart(_) => {
+                    info!("🎵 SEストリーム開始");
+                }
+                _ => {}
+            }
+        }
+        finished
+    }
+}
+
+/// SEパイプラインのライフサイクル（構築・再生・終了後の解放・音量）を管理する
+///
+/// 同時に鳴らすSEは1つだけで、新しいSEを再生すると前のSEは止める。
+pub struct SePlayer<P: SePipelines = GstPipelines> {
+    outputs: SeOutputConfig,
+    pipelines: P,
+    pipeline: Option<P::Pipeline>,
+    /// 再生中のSEのバス
+    bus: MixBus,
+    /// 再生中のSEだけに掛ける倍率（バスの音量の変更時に掛け直す）
+    trim: f64,
+    /// SEの言語（バリアントがあればそちらを鳴らす）
+    language: Option<String>,
+}
+
+impl SePlayer {
+    pub fn new(outputs: SeOutputConfig) -> Self {
+        Self::with_pipelines(outputs, GstPipelines)
+    }
+}
+
+impl<P: SePipelines> SePlayer<P> {
+    pub fn with_pipelines(outputs: SeOutputConfig, pipelines: P) -> Self {
+        Self { outputs, pipelines, pipeline: None, bus: MixBus::Se, trim: 1.0, language: None }
+    }
+
+    /// SEの出力先を選ぶ（パスそのもの、なければファイル名で `routes` を引く）
+    fn sink_for(&self, file_path: &str) -> &SeSink {
+        let file_name = Path::new(file_path).file_name().and_then(|name| name.to_str());
+        self.outputs
+            .routes
+            .get(file_path)
+            .or_else(|| file_name.and_then(|name| self.outputs.routes.get(name)))
+            .unwrap_or(&self.outputs.default)
+    }
+
+    pub fn set_language(&mut self, language: Option<String>) {
+        self.language = language;
+    }
+
+    /// 再生中のSEのバス（再生していなければ `None`）
+    pub fn bus(&self) -> Option<MixBus> {
+        self.pipeline.as_ref().map(|_| self.bus)
+    }
+
+    /// SEを再生する（再生中のSEは止める）
+    ///
+    /// `gain` はバスの音量、`trim` はこのSEだけに掛ける倍率。
+    pub fn play(&mut self, file_path: &str, bus: MixBus, trim: f64, gain: f64) -> Result<()> {
+        self.stop("se_cleanup_on_new_request");
+
+        // シンプルなワンショット再生（出力先は言語によらず元のファイル名で選ぶ）
+        let pipeline_str = format!(
+            "filesrc location={} ! decodebin ! audioconvert ! audioresample ! volume name=se_vol volume={} ! {}",
+            localized(file_path, self.language.as_deref()),
+            trim * gain,
+            sink_description(self.sink_for(file_path), "tsukimi-se")
+        );
+        info!("🎵 SEパイプライン構築開始: pipeline={}", pipeline_str);
+
+        let pipeline = self.pipelines.launch(&pipeline_str)?;
+        info!("▶️  SE再生開始: {}", file_path);
+        self.bus = bus;
+        self.trim = trim;
+        self.pipeline = Some(pipeline);
+        Ok(())
+    }
+
+    /// 再生中のSEにバスの音量を反映する
+    pub fn set_gain(&self, gain: f64) {
+        if let Some(pipeline) = &self.pipeline {
+            self.pipelines.set_volume(pipeline, self.trim * gain);
+        }
+    }
+
+    /// 再生中のSEを止めて解放する
+    pub fn stop(&mut self, label: &str) {
+        if let Some(pipeline) = self.pipeline.take() {
+            info!("🛑 SEパイプラインを停止してクリーンアップ");
+            self.pipelines.stop(&pipeline, label);
+        }
+    }
+
+    /// バスを確認し、再生が終わった（EOS・エラー）SEを解放したら `true` を返す
+    pub fn poll(&mut self) -> bool {
+        let finished = self.pipeline.as_ref().is_some_and(|pipeline| self.pipelines.poll(pipeline));
+        if finished {
+            self.stop("se_cleanup_on_eos");
+        }
+        finished
+    }
+}
+
+impl<P: SePipelines> Drop for SePlayer<P> {
+    fn drop(&mut self) {
+        // 再生中のまま要素が破棄されないようにする
+        if let Some(pipeline) = self.pipeline.take() {
+            self.pipelines.discard(&pipeline);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, Mutex};
+
+    /// 鳴らさずに操作を記録する偽のパイプライン
+    #[derive(Default)]
+    struct Log {
+        launched: Vec<String>,
+        volumes: Vec<(usize, f64)>,
+        stopped: Vec<(usize, String)>,
+        discarded: Vec<usize>,
+        finished: Vec<usize>,
+        fail_launch: bool,
+    }
+
+    #[derive(Clone, Default)]
+    struct FakePipelines(Arc<Mutex<Log>>);
+
+    impl SePipelines for FakePipelines {
+        type Pipeline = usize;
+
+        fn launch(&mut self, description: &str) -> Result<usize> {
+            let mut log = self.0.lock().unwrap();
+            if log.fail_launch {
+                return Err(anyhow!("no such element"));
+            }
+            log.launched.push(description.to_string());
+            Ok(log.launched.len() - 1)
+        }
+
+        fn set_volume(&self, pipeline: &usize, volume: f64) {
+            self.0.lock().unwrap().volumes.push((*pipeline, volume));
+        }
+
+        fn stop(&self, pipeline: &usize, label: &str) {
+            self.0.lock().unwrap().stopped.push((*pipeline, label.to_string()));
+        }
+
+        fn discard(&self, pipeline: &usize) {
+            self.0.lock().unwrap().discarded.push(*pipeline);
+        }
+
+        fn poll(&self, pipeline: &usize) -> bool {
+            self.0.lock().unwrap().finished.contains(pipeline)
+        }
+    }
+
+    fn sink(device: &str) -> SeSink {
+        SeSink { device: Some(device.to_string()), ..Default::default() }
+    }
+
+    fn player() -> (SePlayer<FakePipelines>, FakePipelines) {
+        let outputs = SeOutputConfig {
+            default: SeSink::default(),
+            routes: BTreeMap::from([("se-point.mp3".to_string(), sink("speaker-2")), ("assets/se-bell.mp3".to_string(), sink("speaker-3"))]),
+        };
+        let pipelines = FakePipelines::default();
+        (SePlayer::with_pipelines(outputs, pipelines.clone()), pipelines)
+    }
+
+    #[test]
+    fn builds_a_pipeline_with_volume_and_default_sink() {
+        let (mut player, pipelines) = player();
+        player.play("se-activation.mp3", MixBus::Se, 0.5, 0.8).unwrap();
+        let launched = &pipelines.0.lock().unwrap().launched;
+        assert_eq!(launched.len(), 1);
+        assert!(launched[0].starts_with("filesrc location=se-activation.mp3 ! "));
+        assert!(launched[0].contains("volume name=se_vol volume=0.4 ! "));
+        assert!(launched[0].ends_with(&sink_description(&SeSink::default(), "tsukimi-se")));
+        assert_eq!(player.bus(), Some(MixBus::Se));
+    }
+
+    #[test]
+    fn routes_by_path_or_file_name() {
+        let (mut player, pipelines) = player();
+        player.play("/opt/tsukimi/se-point.mp3", MixBus::Se, 1.0, 1.0).unwrap();
+        player.play("assets/se-bell.mp3", MixBus::Se, 1.0, 1.0).unwrap();
+        let launched = &pipelines.0.lock().unwrap().launched;
+        assert!(launched[0].ends_with(&sink_description(&sink("speaker-2"), "tsukimi-se")));
+        assert!(launched[1].ends_with(&sink_description(&sink("speaker-3"), "tsukimi-se")));
+    }
+
+    #[test]
+    fn new_request_stops_the_playing_se() {
+        let (mut player, pipelines) = player();
+        player.play("se-point.mp3", MixBus::Se, 1.0, 1.0).unwrap();
+        player.play("announcement.mp3", MixBus::Announce, 1.0, 1.0).unwrap();
+        let log = pipelines.0.lock().unwrap();
+        assert_eq!(log.stopped, [(0, "se_cleanup_on_new_request".to_string())]);
+        assert_eq!(player.bus(), Some(MixBus::Announce));
+    }
+
+    #[test]
+    fn gain_changes_keep_the_trim() {
+        let (mut player, pipelines) = player();
+        player.set_gain(0.5);
+        assert!(pipelines.0.lock().unwrap().volumes.is_empty());
+        player.play("se-point.mp3", MixBus::Se, 0.5, 1.0).unwrap();
+        player.set_gain(0.5);
+        assert_eq!(pipelines.0.lock().unwrap().volumes, [(0, 0.25)]);
+    }
+
+    #[test]
+    fn eos_releases_the_pipeline() {
+        let (mut player, pipelines) = player();
+        assert!(!player.poll());
+        player.play("se-point.mp3", MixBus::Se, 1.0, 1.0).unwrap();
+        assert!(!player.poll());
+        assert_eq!(player.bus(), Some(MixBus::Se));
+
+        pipelines.0.lock().unwrap().finished.push(0);
+        assert!(player.poll());
+        assert_eq!(player.bus(), None);
+        assert_eq!(pipelines.0.lock().unwrap().stopped, [(0, "se_cleanup_on_eos".to_string())]);
+        assert!(!player.poll());
+    }
+
+    #[test]
+    fn failed_launch_leaves_nothing_playing() {
+        let (mut player, pipelines) = player();
+        player.play("se-point.mp3", MixBus::Se, 1.0, 1.0).unwrap();
+        pipelines.0.lock().unwrap().fail_launch = true;
+        assert!(player.play("se-bell.mp3", MixBus::Se, 1.0, 1.0).is_err());
+        assert_eq!(player.bus(), None);
+        assert_eq!(pipelines.0.lock().unwrap().stopped.len(), 1);
+    }
+
+    #[test]
+    fn drop_discards_the_playing_se() {
+        let (mut player, pipelines) = player();
+        player.play("se-point.mp3", MixBus::Se, 1.0, 1.0).unwrap();
+        drop(player);
+        assert_eq!(pipelines.0.lock().unwrap().discarded, [0]);
+    }
+}
@@ -0,0 +1,118 @@
+use crate::audio_system::audio_sink::{bgm_sink_description, configured_bgm_sink, sink_description, with_delay};
+use crate::config_system::config_main::{PlaybackConfig, SeSink};
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// 指定した出力デバイスがあるかを確かめる間隔
+const PROBE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// デバイスの一覧に指定した出力デバイスがあるか
+///
+/// PulseAudioのシンク名・WASAPIのデバイスIDはデバイスのプロパティに入っているため、表示名とプロパティの値のどちらかと一致すればよい。
+fn device_listed(monitor: &gst::DeviceMonitor, device: &str) -> bool {
+    monitor.devices().iter().any(|candidate| {
+        candidate.display_name() == device
+            || (candidate.has_property("internal-name", None) && candidate.property::<String>("internal-name") == device)
+            || candidate
+                .properties()
+                .is_some_and(|properties| properties.iter().any(|(_, value)| value.get::<&str>().is_ok_and(|value| value == device)))
+    })
+}
+
+/// BGMの出力先の抜き差し（USB DACなど）への追従
+///
+/// 設定したデバイスが抜けてパイプラインがエラーになったら既定の出力に切り替え、
+/// デバイスが戻ったら元の出力で作り直せるよう、デバイスの有無を別スレッドで見張る。
+/// 既定の出力にも鳴らせない場合は、パイプラインの再構築の再試行（無音）が続く。
+/// 複数の出力先に鳴らす場合は見張らず、パイプラインの再構築の再試行に任せる。
+pub struct SinkRouting {
+    configured: String,
+    fallback: String,
+    device: Option<String>,
+    /// 設定したデバイスが見えているか（見張りを始められない場合は常に `true`）
+    present: Arc<AtomicBool>,
+    fallback_active: AtomicBool,
+}
+
+impl SinkRouting {
+    pub fn new(config: &PlaybackConfig) -> Arc<Self> {
+        let configured = configured_bgm_sink(config);
+        let (fallback, device) = match (config.bgm_outputs.is_empty(), config.bgm_output.as_ref()) {
+            (true, Some(sink)) => (
+                with_delay(sink_description(&SeSink { device: None, ..sink.clone() }, "tsukimi-bgm"), config.output_delay_ms),
+                sink.device.clone(),
+            ),
+            (true, None) => (with_delay(bgm_sink_description(None), config.output_delay_ms), None),
+            (false, _) => (configured.clone(), None),
+        };
+        let routing = Arc::new(Self {
+            configured,
+            fallback,
+            device,
+            present: Arc::new(AtomicBool::new(true)),
+            fallback_active: AtomicBool::new(false),
+        });
+        if let Some(device) = routing.device.clone() {
+            let present = Arc::clone(&routing.present);
+            std::thread::Builder::new()
+                .name("sink-hotplug".to_string())
+                .spawn(move || watch_device(&device, &present))
+                .map_err(|e| warn!("Failed to spawn audio device monitor: {}", e))
+                .ok();
+        }
+        routing
+    }
+
+    /// パイプラインを構築するときのシンクの記述
+    pub fn description(&self) -> &str {
+        if self.fallback_active.load(Ordering::Relaxed) {
+            &self.fallback
+        } else {
+            &self.configured
+        }
+    }
+
+    /// BGMのパイプラインがエラーになったときに呼ぶ。設定したデバイスが無くなっていれば既定の出力に切り替える
+    pub fn on_pipeline_error(&self) {
+        let Some(device) = &self.device else {
+            return;
+        };
+        if self.fallback_active.load(Ordering::Relaxed) || self.present.load(Ordering::Relaxed) {
+            return;
+        }
+        warn!(%device, fallback = %self.fallback, "🔌 Audio device lost - falling back to the default output");
+        self.fallback_active.store(true, Ordering::Relaxed);
+    }
+
+    /// 既定の出力に切り替えている間に設定したデバイスが戻った場合に `true`（以降の構築は元の出力を使う）
+    pub fn take_restored(&self) -> bool {
+        if !self.fallback_active.load(Ordering::Relaxed) || !self.present.load(Ordering::Relaxed) {
+            return false;
+        }
+        info!(device = ?self.device, "🔌 Audio device returned - switching back to the configured output");
+        self.fallback_active.store(false, Ordering::Relaxed);
+        true
+    }
+}
+
+/// 設定したデバイスの有無を見張り続ける（見張りを始められない場合は有無が分からないため何もしない）
+fn watch_device(device: &str, present: &AtomicBool) {
+    let monitor = gst::DeviceMonitor::new();
+    monitor.add_filter(Some("Audio/Sink"), None);
+    if let Err(e) = monitor.start() {
+        warn!(%device, "Failed to start audio device monitor, hot-plug fallback disabled: {}", e);
+        return;
+    }
+    info!(%device, "Watching audio output device");
+    loop {
+        let listed = device_listed(&monitor, device);
+        if present.swap(listed, Ordering::Relaxed) != listed {
+            info!(%device, present = listed, "Audio output device changed");
+        }
+        std::thread::sleep(PROBE_INTERVAL);
+    }
+}
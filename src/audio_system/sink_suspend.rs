@@ -0,0 +1,57 @@
+use std::time::{Duration, Instant};
+
+/// 再生位置を確かめる間隔（毎周問い合わせるとpulsesinkのロックを取り合うため）
+const CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+/// 出力先のサスペンド（PulseAudioのアイドル時の停止など）からの復帰を検知する
+///
+/// サスペンド中は再生位置が進まないため、復帰するとサーバー時刻に対して一気に遅れる。
+/// ずれの計測で気付くまで数秒かかるので、再生位置が実時間に対して止まっていたことを見て
+/// 復帰した時点ですぐに合わせ直せるようにする。
+pub struct SuspendDetector {
+    /// この時間以上止まっていた場合のみ復帰とみなす（ゼロなら検知しない）
+    stall_threshold: Duration,
+    last_check: Option<(Instant, u64)>,
+    stalled_since: Option<Instant>,
+}
+
+impl SuspendDetector {
+    pub fn new(stall_threshold: Duration) -> Self {
+        Self { stall_threshold, last_check: None, stalled_since: None }
+    }
+
+    /// シーク・パイプラインの切り替えの後に呼ぶ（位置の飛びを停止と取り違えないため）
+    pub fn reset(&mut self) {
+        self.last_check = None;
+        self.stalled_since = None;
+    }
+
+    /// 現在の再生位置を反映し、止まっていた再生が再び進み始めた場合は止まっていた時間を返す
+    pub fn update(&mut self, position_ns: Option<u64>) -> Option<Duration> {
+        if self.stall_threshold.is_zero() {
+            return None;
+        }
+        let now = Instant::now();
+        if self.last_check.is_some_and(|(checked, _)| now.duration_since(checked) < CHECK_INTERVAL) {
+            return None;
+        }
+        let position_ns = position_ns?;
+        let (checked, last_position_ns) = self.last_check.replace((now, position_ns))?;
+        // ループ・後方へのシークで巻き戻った場合は計り直す
+        let Some(advanced_ns) = position_ns.checked_sub(last_position_ns) else {
+            self.stalled_since = None;
+            return None;
+        };
+
+        // テンポ補正（±10%）では半分を下回らない
+        let elapsed = now.duration_since(checked);
+        if Duration::from_nanos(advanced_ns) < elapsed / 2 {
+            self.stalled_since.get_or_insert(checked);
+            return None;
+        }
+        self.stalled_since
+            .take()
+            .map(|since| now.duration_since(since))
+            .filter(|stalled| *stalled >= self.stall_threshold)
+    }
+}
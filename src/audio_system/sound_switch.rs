@@ -0,0 +1,146 @@
+use std::time::{Duration, Instant};
+
+/// 構築に失敗した切り替えを最初に再試行するまでの待ち時間
+const RETRY_INITIAL: Duration = Duration::from_millis(500);
+/// 再試行の待ち時間の上限
+const RETRY_MAX: Duration = Duration::from_secs(30);
+
+/// 再生中のBGM音源と、構築ワーカーに依頼中の音源
+///
+/// 再生中の音源は新しいパイプラインを適用したときだけ更新する。構築に失敗した場合は
+/// 再生中の音源を残したまま、待ち時間を延ばしながら同じ切り替えを再試行する。
+#[derive(Debug)]
+pub struct SoundSwitch {
+    current: String,
+    pending: Option<String>,
+    /// 構築に失敗した音源と、次に再試行できる時刻
+    retry: Option<(String, Instant)>,
+    failures: u32,
+}
+
+impl SoundSwitch {
+    pub fn new(current: String) -> Self {
+        Self { current, pending: None, retry: None, failures: 0 }
+    }
+
+    /// 再生中の音源
+    pub fn current(&self) -> &str {
+        &self.current
+    }
+
+    /// 構築を依頼中の音源
+    pub fn pending(&self) -> Option<&str> {
+        self.pending.as_deref()
+    }
+
+    /// 切り替えの判断の基準にする音源（構築中ならその音源、なければ再生中の音源）
+    pub fn target(&self) -> &str {
+        self.pending.as_deref().unwrap_or(&self.current)
+    }
+
+    /// `desired` への切り替えを依頼すべきか
+    ///
+    /// 同じ音源を構築中の場合と、失敗した音源の再試行待ちの間は依頼しない。
+    pub fn needs_switch(&self, desired: &str, now: Instant) -> bool {
+        if desired == self.target() {
+            return false;
+        }
+        !matches!(&self.retry, Some((sound, at)) if sound == desired && now < *at)
+    }
+
+    /// 構築ワーカーに切り替えを依頼した
+    pub fn requested(&mut self, desired: &str) {
+        self.pending = Some(desired.to_string());
+    }
+
+    /// 構築したパイプラインを適用した（依頼中の音源が再生中になる）
+    pub fn applied(&mut self) {
+        if let Some(sound) = self.pending.take() {
+            self.current = sound;
+        }
+        self.retry = None;
+        self.failures = 0;
+    }
+
+    /// 構築に失敗した。再生中の音源はそのままにし、次に再試行するまでの待ち時間を返す
+    pub fn failed(&mut self, now: Instant) -> Duration {
+        let Some(sound) = self.pending.take() else {
+            return Duration::ZERO;
+        };
+        let delay = RETRY_INITIAL.saturating_mul(1 << self.failures.min(6)).min(RETRY_MAX);
+        self.failures = self.failures.saturating_add(1);
+        self.retry = Some((sound, now + delay));
+        delay
+    }
+
+    /// 依頼中の切り替えを取りやめる（構築結果は世代が古くなり破棄される）
+    pub fn abandon(&mut self) {
+        self.pending = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn current_sound_changes_only_when_applied() {
+        let now = Instant::now();
+        let mut switch = SoundSwitch::new("a.mp3".to_string());
+        assert!(!switch.needs_switch("a.mp3", now));
+        assert!(switch.needs_switch("b.mp3", now));
+
+        switch.requested("b.mp3");
+        assert_eq!(switch.current(), "a.mp3");
+        assert_eq!(switch.target(), "b.mp3");
+        assert!(!switch.needs_switch("b.mp3", now));
+        // 構築中に元の音源へ戻す判断は新しい依頼になる
+        assert!(switch.needs_switch("a.mp3", now));
+
+        switch.applied();
+        assert_eq!(switch.current(), "b.mp3");
+        assert_eq!(switch.pending(), None);
+    }
+
+    #[test]
+    fn failed_build_keeps_current_sound_and_retries_with_backoff() {
+        let now = Instant::now();
+        let mut switch = SoundSwitch::new("a.mp3".to_string());
+
+        switch.requested("b.mp3");
+        assert_eq!(switch.failed(now), RETRY_INITIAL);
+        assert_eq!(switch.current(), "a.mp3");
+        assert_eq!(switch.pending(), None);
+        // 待ち時間の間は同じ音源を依頼しないが、別の音源には切り替えられる
+        assert!(!switch.needs_switch("b.mp3", now));
+        assert!(switch.needs_switch("c.mp3", now));
+        assert!(switch.needs_switch("b.mp3", now + RETRY_INITIAL));
+
+        switch.requested("b.mp3");
+        assert_eq!(switch.failed(now), RETRY_INITIAL * 2);
+        switch.requested("b.mp3");
+        assert_eq!(switch.failed(now), RETRY_INITIAL * 4);
+        for _ in 0..10 {
+            switch.requested("b.mp3");
+            switch.failed(now);
+        }
+        switch.requested("b.mp3");
+        assert_eq!(switch.failed(now), RETRY_MAX);
+
+        switch.requested("b.mp3");
+        switch.applied();
+        assert_eq!(switch.current(), "b.mp3");
+        switch.requested("c.mp3");
+        assert_eq!(switch.failed(now), RETRY_INITIAL);
+    }
+
+    #[test]
+    fn abandoned_switch_is_requested_again() {
+        let now = Instant::now();
+        let mut switch = SoundSwitch::new("a.mp3".to_string());
+        switch.requested("b.mp3");
+        switch.abandon();
+        assert_eq!(switch.current(), "a.mp3");
+        assert!(switch.needs_switch("b.mp3", now));
+    }
+}
@@ -0,0 +1,170 @@
+use crate::clock_system::time_source::SharedTimeSource;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+
+/// ずれの絶対値・再シークの指数移動平均の時定数（補正はループのたびに行われるため、回数ではなく時間で減衰させる）
+const EMA_TIME_CONSTANT: Duration = Duration::from_secs(30);
+
+/// ずれの平均がこの値のときにスコアが50になる
+const SCORE_HALF_DRIFT_MS: f64 = 50.0;
+
+/// 再シーク1回あたりのスコアの減点（直近の再シークほど重い）
+const RESEEK_PENALTY: f64 = 20.0;
+
+/// 同期の品質の指標
+///
+/// 位相のずれを耳で探さなくても、手当てが必要なユニットを運用者が順位付けできるようにする。
+/// ずれの絶対値の指数移動平均・大きなずれによる再シークの回数・テンポ補正に入った回数を数え、
+/// それらをまとめた0〜100のスコアを出す（100が最良）。
+#[derive(Debug)]
+pub struct SyncQuality {
+    time: SharedTimeSource,
+    ema_abs_drift_ms: Option<f64>,
+    /// 再シークの指数移動平均（スコアの減点用。時間とともに減衰する）
+    recent_reseeks: f64,
+    last_observed: Option<Instant>,
+    reseeks: u64,
+    tempo_excursions: u64,
+    /// 直前の補正で等速以外の速度にしていたか
+    correcting: bool,
+}
+
+/// 同期の品質のスナップショット（状態API・メトリクス用）
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct SyncQualitySnapshot {
+    /// 0〜100（100が最良。まだ計測していない場合も100）
+    pub score: u32,
+    pub ema_abs_drift_ms: f64,
+    pub reseeks: u64,
+    pub tempo_excursions: u64,
+}
+
+impl SyncQuality {
+    pub fn new(time: SharedTimeSource) -> Self {
+        Self { time, ema_abs_drift_ms: None, recent_reseeks: 0.0, last_observed: None, reseeks: 0, tempo_excursions: 0, correcting: false }
+    }
+
+    /// ドリフト補正1回分の計測結果を反映する（`rate` は設定した再生速度）
+    pub fn observe(&mut self, drift_ns: i64, rate: f64) {
+        let now = self.time.now();
+        let alpha = self
+            .last_observed
+            .map_or(1.0, |last| 1.0 - (-now.duration_since(last).as_secs_f64() / EMA_TIME_CONSTANT.as_secs_f64()).exp());
+        self.last_observed = Some(now);
+
+        let drift_ms = drift_ns.unsigned_abs() as f64 / 1e6;
+        self.ema_abs_drift_ms = Some(match self.ema_abs_drift_ms {
+            Some(ema) => alpha * drift_ms + (1.0 - alpha) * ema,
+            None => drift_ms,
+        });
+        self.recent_reseeks *= 1.0 - alpha;
+
+        let correcting = rate != 1.0;
+        if correcting && !self.correcting {
+            self.tempo_excursions += 1;
+        }
+        self.correcting = correcting;
+    }
+
+    /// 大きなずれのため再シークしたときに呼ぶ
+    pub fn record_reseek(&mut self) {
+        self.reseeks += 1;
+        self.recent_reseeks += 1.0;
+    }
+
+    pub fn snapshot(&self) -> SyncQualitySnapshot {
+        let ema_abs_drift_ms = self.ema_abs_drift_ms.unwrap_or(0.0);
+        let score = 100.0 / (1.0 + ema_abs_drift_ms / SCORE_HALF_DRIFT_MS) - RESEEK_PENALTY * self.recent_reseeks;
+        SyncQualitySnapshot {
+            score: score.clamp(0.0, 100.0).round() as u32,
+            ema_abs_drift_ms,
+            reseeks: self.reseeks,
+            tempo_excursions: self.tempo_excursions,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock_system::time_source::MockTimeSource;
+    use std::sync::Arc;
+
+    const MS: i64 = 1_000_000;
+
+    fn quality() -> (SyncQuality, Arc<MockTimeSource>) {
+        let time = Arc::new(MockTimeSource::new());
+        (SyncQuality::new(time.clone()), time)
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-6, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn nothing_observed_is_a_perfect_score() {
+        let (quality, _) = quality();
+        let snapshot = quality.snapshot();
+        assert_eq!(snapshot.score, 100);
+        assert_eq!(snapshot.ema_abs_drift_ms, 0.0);
+        assert_eq!((snapshot.reseeks, snapshot.tempo_excursions), (0, 0));
+    }
+
+    #[test]
+    fn first_observation_sets_the_average() {
+        let (mut quality, _) = quality();
+        quality.observe(-50 * MS, 1.0);
+        let snapshot = quality.snapshot();
+        assert_close(snapshot.ema_abs_drift_ms, 50.0);
+        assert_eq!(snapshot.score, 50);
+    }
+
+    #[test]
+    fn average_decays_with_time_not_with_observations() {
+        let (mut quality, time) = quality();
+        quality.observe(100 * MS, 1.0);
+        // 同じ時刻の観測は平均を動かさない
+        quality.observe(0, 1.0);
+        assert_close(quality.snapshot().ema_abs_drift_ms, 100.0);
+
+        // 時定数1つ分で古い値の重みは1/eになる
+        time.advance(EMA_TIME_CONSTANT);
+        quality.observe(0, 1.0);
+        assert_close(quality.snapshot().ema_abs_drift_ms, 100.0 * (-1.0_f64).exp());
+
+        // 十分に時間が経てば直近の値だけになる
+        time.advance(EMA_TIME_CONSTANT * 40);
+        quality.observe(10 * MS, 1.0);
+        assert_close(quality.snapshot().ema_abs_drift_ms, 10.0);
+    }
+
+    #[test]
+    fn reseek_penalty_fades_over_time() {
+        let (mut quality, time) = quality();
+        quality.observe(0, 1.0);
+        quality.record_reseek();
+        quality.record_reseek();
+        assert_eq!(quality.snapshot().score, 60);
+        assert_eq!(quality.snapshot().reseeks, 2);
+
+        time.advance(EMA_TIME_CONSTANT * 40);
+        quality.observe(0, 1.0);
+        assert_eq!(quality.snapshot().score, 100);
+        assert_eq!(quality.snapshot().reseeks, 2);
+
+        for _ in 0..10 {
+            quality.record_reseek();
+        }
+        assert_eq!(quality.snapshot().score, 0);
+    }
+
+    #[test]
+    fn tempo_excursions_count_entries_into_correction() {
+        let (mut quality, _) = quality();
+        for rate in [1.0, 1.02, 1.02, 1.0, 0.98, 1.02, 1.0] {
+            quality.observe(0, rate);
+        }
+        // 1.0→1.02と1.0→0.98の2回（補正中の速度の変更は数えない）
+        assert_eq!(quality.snapshot().tempo_excursions, 2);
+    }
+}
@@ -0,0 +1,118 @@
+use crate::config_system::unit_identity::unit_identity;
+use anyhow::{anyhow, bail, Result};
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// 一度に鳴らせる最長の時間（APIから長時間鳴らし続けないため）
+const MAX_DURATION: Duration = Duration::from_secs(60);
+
+/// 読み上げが終わらない場合に打ち切るまでの時間
+const SPEECH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 配線・ゲインの確認に鳴らす信号
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TestSignal {
+    /// ピンクノイズ（レベル合わせ用）
+    PinkNoise,
+    /// ユニットIDとチャンネル（左・右）の読み上げ（左右の配線の確認用）
+    ChannelId,
+}
+
+impl FromStr for TestSignal {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "pink_noise" | "pink" => Ok(TestSignal::PinkNoise),
+            "channel_id" | "id" => Ok(TestSignal::ChannelId),
+            other => Err(anyhow!("Unknown test signal: {} (expected pink_noise or channel_id)", other)),
+        }
+    }
+}
+
+/// テストトーンの指定
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TestToneRequest {
+    pub signal: TestSignal,
+    /// 出力レベル（dBFS。0が最大）
+    pub level_db: f64,
+    /// ピンクノイズを鳴らす時間（ミリ秒。読み上げは読み終わるまで）
+    pub duration_ms: u64,
+}
+
+impl Default for TestToneRequest {
+    fn default() -> Self {
+        Self { signal: TestSignal::PinkNoise, level_db: -20.0, duration_ms: 5000 }
+    }
+}
+
+/// 1つのパイプラインを鳴らし、終わりまで（または `timeout` まで）待つ
+fn play(source: &str, gain: f64, sink: &str, timeout: Duration) -> Result<()> {
+    let pipeline_str = format!(
+        "{} ! audioconvert ! audioresample ! volume volume={} ! audioconvert ! capsfilter caps=\"audio/x-raw,channels=2\" ! {}",
+        source, gain, sink
+    );
+    let pipeline = gst::parse::launch(&pipeline_str)?
+        .downcast::<gst::Pipeline>()
+        .map_err(|_| anyhow!("Failed to downcast to Pipeline"))?;
+    let bus = pipeline.bus().ok_or_else(|| anyhow!("Failed to get bus from pipeline"))?;
+
+    pipeline.set_state(gst::State::Playing)?;
+    let result = match bus.timed_pop_filtered(
+        gst::ClockTime::from_nseconds(timeout.as_nanos() as u64),
+        &[gst::MessageType::Eos, gst::MessageType::Error],
+    ) {
+        Some(msg) => match msg.view() {
+            gst::MessageView::Error(err) => Err(anyhow!("Test tone pipeline error: {} (debug: {:?})", err.error(), err.debug())),
+            _ => Ok(()),
+        },
+        // 時間で止める信号（ピンクノイズ）はここで終わる
+        None => Ok(()),
+    };
+    if let Err(e) = pipeline.set_state(gst::State::Null) {
+        warn!("Failed to stop test tone pipeline: {}", e);
+    }
+    result
+}
+
+/// テストトーンを設定した出力先（`sink` はBGMと同じシンクの記述）に鳴らす（ブロッキング。鳴り終わるまで戻らない）
+///
+/// バックエンドなしで配線とゲインを確かめられるよう、BGMのパイプラインとは別に鳴らす。
+pub fn play_test_tone(request: &TestToneRequest, sink: &str) -> Result<()> {
+    gst::init()?;
+    if request.level_db > 0.0 {
+        bail!("Test tone level must be 0 dBFS or lower: {}", request.level_db);
+    }
+    let gain = 10f64.powf(request.level_db / 20.0);
+    info!(?request, sink, "🔊 Playing test tone");
+
+    match request.signal {
+        TestSignal::PinkNoise => {
+            let duration = Duration::from_millis(request.duration_ms).min(MAX_DURATION);
+            play("audiotestsrc wave=pink-noise is-live=true", gain, sink, duration)
+        }
+        TestSignal::ChannelId => {
+            // 読み上げ（espeak）が無い環境では、左右で高さの違うビープで代える
+            let speech = gst::ElementFactory::find("espeak").is_some();
+            if !speech {
+                warn!("espeak element not found - using beeps instead of speech for channel identification");
+            }
+            for (channel, panorama, freq) in [("left", -1.0, 440), ("right", 1.0, 880)] {
+                let source = if speech {
+                    format!("espeak text=\"{} {}\"", unit_identity().unit_id, channel)
+                } else {
+                    format!("audiotestsrc wave=sine freq={} num-buffers=50", freq)
+                };
+                let source = format!("{} ! audioconvert ! audiopanorama panorama={}", source, panorama);
+                play(&source, gain, sink, SPEECH_TIMEOUT)?;
+            }
+            Ok(())
+        }
+    }
+}
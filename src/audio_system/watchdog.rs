@@ -0,0 +1,103 @@
+use crate::config_system::config_main::AudioWatchdogConfig;
+use glib::WeakRef;
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tracing::{error, info, instrument, warn};
+
+/// 監視の周期
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// オーディオスレッドの生存確認（メインループが1周ごとに更新する）
+///
+/// オーディオスレッドが作ったBGMのパイプラインも弱参照で持ち、止まった場合に監視側から破棄できるようにする。
+pub struct AudioHeartbeat {
+    started: Instant,
+    /// 最後に更新した時刻（`started` からのミリ秒）
+    last_beat_ms: AtomicU64,
+    pipelines: Mutex<Vec<WeakRef<gst::Pipeline>>>,
+}
+
+impl AudioHeartbeat {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self { started: Instant::now(), last_beat_ms: AtomicU64::new(0), pipelines: Mutex::new(Vec::new()) })
+    }
+
+    pub fn beat(&self) {
+        self.last_beat_ms.store(self.started.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// 最後の更新からの経過時間
+    fn stalled_for(&self) -> Duration {
+        let last_beat = Duration::from_millis(self.last_beat_ms.load(Ordering::Relaxed));
+        self.started.elapsed().saturating_sub(last_beat)
+    }
+
+    /// 構築したパイプラインを登録する（破棄済みのものは同時に取り除く）
+    pub fn register(&self, pipeline: &gst::Pipeline) {
+        let mut pipelines = self.pipelines.lock().unwrap();
+        pipelines.retain(|pipeline| pipeline.upgrade().is_some());
+        pipelines.push(pipeline.downgrade());
+    }
+
+    /// 登録済みのパイプラインをNULL状態にする（バスの待ちで止まったスレッドを抜けさせるため）
+    pub fn teardown(&self) -> usize {
+        let pipelines: Vec<gst::Pipeline> = self.pipelines.lock().unwrap().iter().filter_map(|pipeline| pipeline.upgrade()).collect();
+        for pipeline in &pipelines {
+            if let Err(e) = pipeline.set_state(gst::State::Null) {
+                warn!(pipeline = %pipeline.name(), "Failed to tear down pipeline: {:?}", e);
+            }
+        }
+        pipelines.len()
+    }
+}
+
+/// 停止を検知したことをスーパーバイザーに知らせるチャンネルを作成する（値は検知した回数）
+pub fn stall_channel() -> (watch::Sender<u64>, watch::Receiver<u64>) {
+    watch::channel(0)
+}
+
+/// オーディオスレッドの生存を監視するタスク
+///
+/// 更新が `stall_timeout_ms` 途絶えたらパイプラインを破棄して抜けさせ、`stall_tx` でスーパーバイザーに知らせて
+/// 止まったスレッドを見捨てた新しいオーディオループを始めさせる。
+/// `restart_timeout_ms` を過ぎても戻らなければ（新しいループも動かない場合）、最後の手段として
+/// サービスマネージャー（systemd）に再起動させるためプロセスを終了する。
+#[instrument(skip_all)]
+pub async fn audio_watchdog_main(config: AudioWatchdogConfig, heartbeat: Arc<AudioHeartbeat>, stall_tx: watch::Sender<u64>) {
+    let stall_timeout = Duration::from_millis(config.stall_timeout_ms);
+    let restart_timeout = Duration::from_millis(config.restart_timeout_ms).max(stall_timeout);
+    let mut restarting = false;
+
+    loop {
+        tokio::time::sleep(CHECK_INTERVAL).await;
+        let stalled_for = heartbeat.stalled_for();
+
+        if stalled_for < stall_timeout {
+            if restarting {
+                info!("Audio loop recovered after stall");
+                restarting = false;
+            }
+            continue;
+        }
+        if !restarting {
+            error!(stalled_ms = stalled_for.as_millis() as u64, "Audio thread stalled - tearing down pipelines and restarting the audio loop");
+            // NULL状態への遷移はストリーミングスレッドの終了を待つため、非同期タスクの外で行う
+            // （破棄が終わらなくても再起動の判定は続けられるよう、完了は待たない）
+            let heartbeat = Arc::clone(&heartbeat);
+            tokio::task::spawn_blocking(move || {
+                let count = heartbeat.teardown();
+                info!(pipelines = count, "Pipelines torn down");
+            });
+            stall_tx.send_modify(|stalls| *stalls += 1);
+            restarting = true;
+        }
+        if stalled_for >= restart_timeout {
+            error!(stalled_ms = stalled_for.as_millis() as u64, "Audio loop did not recover after restart - exiting to be restarted");
+            std::process::exit(1);
+        }
+    }
+}
@@ -1 +1,6 @@
+pub mod address;
+#[cfg(feature = "ble")]
 pub mod bluetooth_main;
+pub mod device_source;
+pub mod simulation;
+pub mod throttle;
\ No newline at end of file
@@ -0,0 +1,161 @@
+#[cfg(feature = "ble")]
+use btleplug::api::BDAddr;
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+/// 正規化したビーコンのMACアドレス（大文字・コロン区切り、例: `AA:BB:CC:DD:EE:FF`）
+///
+/// btleplugとサーバーで大文字・小文字や区切り文字が異なっても同じアドレスとして比較できる。
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Address(String);
+
+/// MACアドレスとして解釈できない文字列
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidAddress(pub String);
+
+impl fmt::Display for InvalidAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "malformed address: {:?}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidAddress {}
+
+impl Address {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for Address {
+    type Err = InvalidAddress;
+
+    /// `:`・`-`・`.` 区切り、または区切りなしの12桁の16進数を受け付ける
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidAddress(text.to_string());
+        let trimmed = text.trim();
+        let digits: String = trimmed.chars().filter(|c| !matches!(c, ':' | '-' | '.')).collect();
+        if digits.len() != 12 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        // 区切りは2桁（`AA:BB:...`）か4桁（`aabb.ccdd.eeff`）ごとでなければならない
+        let groups: Vec<&str> = trimmed.split([':', '-', '.']).collect();
+        let grouped = match groups.len() {
+            1 | 3 | 6 => groups.iter().all(|group| group.len() == 12 / groups.len()),
+            _ => false,
+        };
+        if !grouped {
+            return Err(invalid());
+        }
+        let octets: Vec<String> = digits.as_bytes().chunks(2).map(|octet| String::from_utf8_lossy(octet).to_ascii_uppercase()).collect();
+        Ok(Self(octets.join(":")))
+    }
+}
+
+impl From<[u8; 6]> for Address {
+    fn from(octets: [u8; 6]) -> Self {
+        let octets: Vec<String> = octets.iter().map(|octet| format!("{:02X}", octet)).collect();
+        Self(octets.join(":"))
+    }
+}
+
+#[cfg(feature = "ble")]
+impl From<BDAddr> for Address {
+    fn from(address: BDAddr) -> Self {
+        Self::from(address.into_inner())
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Serialize for Address {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const CANONICAL: &str = "AA:BB:0C:DD:EE:FF";
+
+    fn is_canonical(text: &str) -> bool {
+        text.len() == 17
+            && text.split(':').count() == 6
+            && text.split(':').all(|octet| octet.len() == 2 && octet.chars().all(|c| c.is_ascii_digit() || ('A'..='F').contains(&c)))
+    }
+
+    /// 区切り文字と大文字・小文字を変えた表記
+    fn spelled(octets: [u8; 6], separator: &str, upper: bool, grouping: usize) -> String {
+        let hex: String = octets.iter().map(|octet| if upper { format!("{:02X}", octet) } else { format!("{:02x}", octet) }).collect();
+        let chars: Vec<char> = hex.chars().collect();
+        chars.chunks(grouping).map(|group| group.iter().collect::<String>()).collect::<Vec<_>>().join(separator)
+    }
+
+    #[test]
+    fn accepts_common_notations() {
+        for text in ["AA:BB:0C:DD:EE:FF", "aa:bb:0c:dd:ee:ff", "aa-bb-0c-dd-ee-ff", "aabb.0cdd.eeff", "AABB0CDDEEFF", "  aa:bb:0c:dd:ee:ff\n"] {
+            assert_eq!(text.parse::<Address>().unwrap().as_str(), CANONICAL, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_addresses() {
+        for text in ["", "AA:BB:CC:DD:EE", "AA:BB:CC:DD:EE:FF:00", "AA:BB:CC:DD:EE:GG", "AAB:B:CC:DD:EE:FF", "AA::BBCC:DD:EE:FF", "AA:BB:CC:DD:EE:FF:", "AABB.CCDD.EE.FF", "AA BB CC DD EE FF", "ＡＡ:BB:CC:DD:EE:FF"] {
+            let error = text.parse::<Address>().unwrap_err();
+            assert_eq!(error, InvalidAddress(text.to_string()));
+        }
+    }
+
+    #[test]
+    fn octets_and_text_agree() {
+        assert_eq!(Address::from([0xAA, 0xBB, 0x0C, 0xDD, 0xEE, 0xFF]), CANONICAL.parse().unwrap());
+    }
+
+    #[test]
+    fn serializes_as_the_canonical_string() {
+        let address: Address = "aa-bb-0c-dd-ee-ff".parse().unwrap();
+        assert_eq!(serde_json::to_value(&address).unwrap(), CANONICAL);
+    }
+
+    proptest! {
+        #[test]
+        fn parsing_never_panics_and_only_yields_canonical_addresses(text in any::<String>()) {
+            if let Ok(address) = text.parse::<Address>() {
+                prop_assert!(is_canonical(address.as_str()));
+                prop_assert_eq!(address.to_string().parse::<Address>().unwrap(), address);
+            }
+        }
+
+        #[test]
+        fn near_miss_strings_never_panic(text in "[0-9a-fA-F:.\\- ]{0,24}") {
+            if let Ok(address) = text.parse::<Address>() {
+                prop_assert!(is_canonical(address.as_str()));
+            }
+        }
+
+        #[test]
+        fn every_notation_normalizes_to_the_same_address(
+            octets in any::<[u8; 6]>(),
+            upper in any::<bool>(),
+            (separator, grouping) in prop::sample::select(vec![(":", 2), ("-", 2), (".", 4), ("", 12)]),
+        ) {
+            let text = spelled(octets, separator, upper, grouping);
+            prop_assert_eq!(text.parse::<Address>().unwrap(), Address::from(octets));
+        }
+
+        #[test]
+        fn a_non_hex_digit_is_rejected(octets in any::<[u8; 6]>(), position in 0usize..17, bad in "[g-zG-Z_#]") {
+            let mut text = Address::from(octets).to_string();
+            text.replace_range(position..position + 1, &bad);
+            prop_assert!(text.parse::<Address>().is_err());
+        }
+    }
+}
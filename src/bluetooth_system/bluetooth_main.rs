@@ -1,33 +1,131 @@
-use crate::DeviceInfo;
+use crate::audio_system::error_tone::{play_error_tone, FatalSignal};
+use crate::bluetooth_system::address::Address;
+use crate::bluetooth_system::device_source::DeviceSource;
+use crate::bluetooth_system::throttle::ThrottleCache;
+use crate::connect_system::sound_map::SharedSoundMap;
+use crate::clock_system::time_source::{SharedTimeSource, TimeSource};
+use crate::shutdown_system::shutdown_controller::{ShutdownController, ShutdownPhase};
+use crate::messages::DeviceInfo;
 use anyhow::{anyhow, Result};
-use btleplug::api::{Central, Manager as _, Peripheral, ScanFilter};
-use btleplug::platform::{Adapter, Manager, PeripheralId};
+use btleplug::api::{Central, Manager as _, Peripheral, PeripheralProperties, ScanFilter};
+use btleplug::platform::{Adapter, Manager, Peripheral as PlatformPeripheral, PeripheralId};
+use futures::future::BoxFuture;
 use futures::stream::StreamExt;
-use std::collections::HashMap;
-use tracing::{debug, error, info, instrument};
+use std::collections::{HashMap, HashSet};
+use tracing::{debug, error, info, instrument, warn};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
+use tokio::task::JoinSet;
 use tokio::time;
 
-#[cfg(target_os = "linux")]
-use tracing::warn;
-
 #[cfg(target_os = "linux")]
 use zbus::{Proxy, zvariant::OwnedObjectPath};
 
-// デバイス情報のキャッシュ構造体
-struct DeviceCache {
-    last_sent: Instant,
-    last_rssi: i16,
+/// イベントをまたいで保持するペリフェラル（BlueZへの問い合わせを減らすため）
+struct KnownPeripheral {
+    peripheral: PlatformPeripheral,
+    address: Address,
+    /// 最後にプロパティを問い合わせた時刻
+    last_query: Option<Instant>,
+    /// プロパティを問い合わせ中
+    querying: bool,
+    /// 最後にイベントを受け取った時刻（見えなくなったデバイスの掃除用）
+    last_event: Instant,
+}
+
+/// BlueZへの問い合わせ（D-Bus）の結果
+///
+/// 問い合わせのきっかけになったイベントを受け取った時刻を持ち回る（広告から音が変わるまでの遅延の計測用）。
+enum QueryResult {
+    /// 初めてのペリフェラルのハンドル（取得できなければ `None`）
+    Peripheral(PeripheralId, Instant, Option<PlatformPeripheral>),
+    /// ペリフェラルのプロパティ
+    Properties(PeripheralId, Instant, btleplug::Result<Option<PeripheralProperties>>),
+}
+
+/// イベントループだけが触るペリフェラルと、実行中の問い合わせ（ロックせずに持つ）
+///
+/// BlueZへの問い合わせ（D-Bus）は、初めてのデバイスのペリフェラル取得と、対象デバイスのプロパティ取得だけにする。
+/// 問い合わせは別タスクで行い、応答を待つ間もイベントループが次の広告を処理できるようにする。
+struct PeripheralTracker {
+    peripherals: HashMap<PeripheralId, KnownPeripheral>,
+    /// ハンドルを取得中のペリフェラル
+    resolving: HashSet<PeripheralId>,
+    queries: JoinSet<QueryResult>,
+    /// sound_mapに無いデバイスのプロパティも問い合わせる（調査モード）
+    report_all: bool,
+}
+
+/// スキャナが失敗した場合の再試行間隔
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// 同時に実行するBlueZへの問い合わせの上限（来場者のスマートフォンが多い場合にD-Busを埋め尽くさないように）
+const MAX_PENDING_QUERIES: usize = 32;
+
+/// BlueZは1回の広告でRSSI・メーカー固有データなどの変更を別々に通知するため、この間隔内の問い合わせはまとめる
+const PROPERTIES_QUERY_INTERVAL: Duration = Duration::from_millis(10);
+
+/// この時間イベントのないペリフェラルは保持しない（来場者のスマートフォンなどでキャッシュが膨らまないように）
+const PERIPHERAL_RETENTION: Duration = Duration::from_secs(60);
+
+/// btleplugでビーコンをスキャンする入力元
+///
+/// アダプタが無い等で失敗した場合も、設置中に接続されることがあるため `RETRY_INTERVAL` ごとに再試行する。
+pub struct BleScanner {
+    pub my_address: Arc<Mutex<Option<String>>>,
+    pub sound_map: Arc<SharedSoundMap>,
+    pub time: SharedTimeSource,
+    pub rescan_rx: mpsc::Receiver<()>,
+    /// 調査モード（sound_mapに無いビーコンも転送する）
+    pub report_all: bool,
+    /// 失敗したときにエラートーンを鳴らす
+    pub error_tone: bool,
+    pub shutdown: Arc<ShutdownController>,
+}
+
+impl DeviceSource for BleScanner {
+    fn name(&self) -> &'static str {
+        "bluetooth"
+    }
+
+    fn run(self: Box<Self>, tx: mpsc::Sender<Arc<DeviceInfo>>) -> BoxFuture<'static, ()> {
+        let BleScanner { my_address, sound_map, time, mut rescan_rx, report_all, error_tone, shutdown } = *self;
+        Box::pin(async move {
+            loop {
+                match bluetooth_scanner(tx.clone(), Arc::clone(&my_address), Arc::clone(&sound_map), Arc::clone(&time), &mut rescan_rx, report_all, Arc::clone(&shutdown)).await {
+                    Ok(()) => break,
+                    Err(e) => error!("Bluetooth scanner error: {:?}", e),
+                }
+                if error_tone {
+                    let tone = tokio::task::spawn_blocking(|| play_error_tone(FatalSignal::NoAdapter)).await;
+                    if let Ok(Err(e)) = tone {
+                        warn!("Failed to play error tone: {:?}", e);
+                    }
+                }
+                tokio::select! {
+                    _ = tokio::time::sleep(RETRY_INTERVAL) => {}
+                    _ = shutdown.reached(ShutdownPhase::StopBle) => break,
+                }
+            }
+        })
+    }
 }
 
 /// Bluetoothデバイスをスキャンする非同期関数
-#[instrument(skip(tx, my_address))]
+///
+/// `report_all` の場合（調査モード）はsound_mapに無いビーコンも転送する。
+/// `rescan_rx` にリクエストが届くとスキャンを止めてキャッシュを捨て、スキャンをやり直す。
+/// 終了手順がBLEの停止に達したらスキャンを止めて `Ok` を返す。
+#[instrument(skip(tx, my_address, time, rescan_rx, shutdown))]
 pub async fn bluetooth_scanner(
     tx: mpsc::Sender<Arc<DeviceInfo>>,
     my_address: Arc<Mutex<Option<String>>>,
-    sound_map: Arc<Mutex<HashMap<String, String>>>,
+    sound_map: Arc<SharedSoundMap>,
+    time: SharedTimeSource,
+    rescan_rx: &mut mpsc::Receiver<()>,
+    report_all: bool,
+    shutdown: Arc<ShutdownController>,
 ) -> Result<()> {
     info!("Starting Bluetooth scanner...");
     let manager = Manager::new().await?;
@@ -35,7 +133,7 @@ pub async fn bluetooth_scanner(
     let adapters = manager.adapters().await?;
     let central = adapters
         .into_iter()
-        .nth(0)
+        .next()
         .ok_or_else(|| anyhow!("Bluetooth adapter not found"))?;
 
     // 自身のBluetoothアドレスを取得
@@ -159,7 +257,7 @@ pub async fn bluetooth_scanner(
     }
 
     // デバイスキャッシュを作成（頻繁な送信を抑制しつつ、重要な更新は通知）
-    let device_cache: Arc<Mutex<HashMap<String, DeviceCache>>> = Arc::new(Mutex::new(HashMap::new()));
+    let device_cache = Arc::new(Mutex::new(ThrottleCache::new()));
 
     let mut events = central.events().await?;
     info!("Scanning for BLE devices...");
@@ -167,7 +265,7 @@ pub async fn bluetooth_scanner(
     // スキャンフィルタの設定（空のフィルタで全デバイスをスキャン）
     let scan_filter = ScanFilter::default();
 
-    if let Err(e) = central.start_scan(scan_filter).await {
+    if let Err(e) = central.start_scan(scan_filter.clone()).await {
         error!("Failed to start scan: {:?}", e);
         return Err(e.into());
     }
@@ -175,15 +273,18 @@ pub async fn bluetooth_scanner(
     time::sleep(Duration::from_secs(2)).await;
 
     info!("Started listening for BLE events.");
+    #[cfg(feature = "chaos")]
+    crate::chaos_system::chaos_main::recovered(crate::chaos_system::chaos_main::Fault::AdapterLoss);
 
     // 定期的にキャッシュをクリーンアップするタスク
     let cache_clone = Arc::clone(&device_cache);
+    let cleanup_time = Arc::clone(&time);
     tokio::spawn(async move {
         loop {
             time::sleep(Duration::from_secs(30)).await;
             let mut cache = cache_clone.lock().unwrap();
             let before = cache.len();
-            cache.retain(|_, v| v.last_sent.elapsed() < Duration::from_secs(60));
+            cache.retain_recent(Duration::from_secs(60), cleanup_time.as_ref());
             let after = cache.len();
             if before != after {
                 debug!("Cache cleanup: {} -> {} entries", before, after);
@@ -191,11 +292,64 @@ pub async fn bluetooth_scanner(
         }
     });
 
-    while let Some(event) = events.next().await {
-        if let btleplug::api::CentralEvent::DeviceDiscovered(id)
-        | btleplug::api::CentralEvent::DeviceUpdated(id) = event
-        {
-            on_event_receive(&central, &id, tx.clone(), Arc::clone(&sound_map), Arc::clone(&device_cache)).await;
+    let mut tracker = PeripheralTracker::new(report_all);
+    let mut last_peripheral_cleanup = time.now();
+
+    let stop = shutdown.reached(ShutdownPhase::StopBle);
+    tokio::pin!(stop);
+
+    loop {
+        tokio::select! {
+            event = events.next() => {
+                let Some(event) = event else {
+                    break;
+                };
+                // ソークテスト: 障害が注入されたらアダプタを失ったものとして失敗する（呼び出し側が再試行する）
+                #[cfg(feature = "chaos")]
+                if crate::chaos_system::chaos_main::triggered(crate::chaos_system::chaos_main::Fault::AdapterLoss) {
+                    return Err(anyhow!("Simulated adapter loss"));
+                }
+                // ManufacturerDataAdvertisement・ServiceDataAdvertisementはRSSIを含まないため、問い合わせのきっかけにしない
+                if let btleplug::api::CentralEvent::DeviceDiscovered(id)
+                | btleplug::api::CentralEvent::DeviceUpdated(id) = event
+                {
+                    tracker.on_event(&central, &id, Instant::now(), &sound_map, time.as_ref());
+                }
+                if time.elapsed(last_peripheral_cleanup) >= Duration::from_secs(30) {
+                    tracker.cleanup(time.as_ref());
+                    last_peripheral_cleanup = time.now();
+                }
+            }
+            Some(result) = tracker.queries.join_next() => {
+                let result = match result {
+                    Ok(result) => result,
+                    Err(e) => {
+                        if !e.is_cancelled() {
+                            warn!("BlueZ query task failed: {}", e);
+                        }
+                        continue;
+                    }
+                };
+                if let Some((address, received_at, props)) = tracker.on_result(result, &sound_map, time.as_ref()) {
+                    forward_properties(address, received_at, props, report_all, &tx, &sound_map, &device_cache, time.as_ref()).await;
+                }
+            }
+            Some(()) = rescan_rx.recv() => {
+                info!("🔄 Rescan requested - restarting BLE scan");
+                if let Err(e) = central.stop_scan().await {
+                    warn!("Failed to stop scan: {:?}", e);
+                }
+                device_cache.lock().unwrap().clear();
+                tracker.clear();
+                central.start_scan(scan_filter.clone()).await?;
+            }
+            _ = &mut stop => {
+                info!("Stopping BLE scan for shutdown");
+                if let Err(e) = central.stop_scan().await {
+                    warn!("Failed to stop scan: {:?}", e);
+                }
+                return Ok(());
+            }
         }
     }
     Ok(())
@@ -205,7 +359,7 @@ pub async fn bluetooth_scanner(
 #[cfg(target_os = "linux")]
 #[allow(dead_code)]
 async fn optimize_linux_scan_parameters(proxy: &Proxy<'_>) -> Result<()> {
-    use zbus::zvariant::{Dict, Value, Type};
+    use zbus::zvariant::Value;
     use std::collections::HashMap;
 
     // スキャンパラメータの設定
@@ -241,72 +395,186 @@ async fn optimize_linux_scan_parameters(_proxy: &()) -> Result<()> {
     Ok(())
 }
 
-/// Bluetoothイベント受信時の処理
-#[instrument(skip(central, sender, device_cache))]
-async fn on_event_receive(
-    central: &Adapter,
-    id: &PeripheralId,
-    sender: mpsc::Sender<Arc<DeviceInfo>>,
-    sound_map: Arc<Mutex<HashMap<String, String>>>,
-    device_cache: Arc<Mutex<HashMap<String, DeviceCache>>>,
-) {
-    // 最初にアドレスを取得（軽量な操作）
-    if let Ok(p) = central.peripheral(&id).await {
-        let address = p.address().to_string();
+/// コンパニオンアプリが来場者トークンを載せるサービスデータの16bit UUID
+#[cfg(feature = "visitor-tokens")]
+const VISITOR_TOKEN_SERVICE_UUID16: u16 = 0x7453;
+
+/// 来場者トークンの最大長（バイト）
+#[cfg(feature = "visitor-tokens")]
+const VISITOR_TOKEN_MAX_LEN: usize = 32;
+
+/// 広告のサービスデータから来場者トークンを取り出す（ASCIIの英数字と `-` `_` のみ受け付ける）
+#[cfg(feature = "visitor-tokens")]
+fn visitor_token(props: &PeripheralProperties) -> Option<String> {
+    let uuid = btleplug::api::bleuuid::uuid_from_u16(VISITOR_TOKEN_SERVICE_UUID16);
+    let data = props.service_data.get(&uuid)?;
+    let token = std::str::from_utf8(data).ok()?.trim_end_matches('\0');
+    let valid = !token.is_empty()
+        && token.len() <= VISITOR_TOKEN_MAX_LEN
+        && token.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+    if !valid {
+        debug!(?data, "Ignoring malformed visitor token");
+        return None;
+    }
+    Some(token.to_string())
+}
+
+#[cfg(not(feature = "visitor-tokens"))]
+fn visitor_token(_props: &PeripheralProperties) -> Option<String> {
+    None
+}
+
+impl PeripheralTracker {
+    fn new(report_all: bool) -> Self {
+        Self { peripherals: HashMap::new(), resolving: HashSet::new(), queries: JoinSet::new(), report_all }
+    }
+
+    /// DeviceDiscovered・DeviceUpdatedを受け取った
+    fn on_event(&mut self, central: &Adapter, id: &PeripheralId, received_at: Instant, sound_map: &SharedSoundMap, time: &dyn TimeSource) {
+        // アドレスはペリフェラルのハンドルと一緒に保持し、2回目以降は問い合わせない
+        if let Some(known) = self.peripherals.get_mut(id) {
+            known.last_event = time.now();
+            self.query_properties(id, received_at, sound_map, time);
+            return;
+        }
+        if self.queries.len() >= MAX_PENDING_QUERIES || !self.resolving.insert(id.clone()) {
+            return;
+        }
+        let central = central.clone();
+        let id = id.clone();
+        self.queries.spawn(async move {
+            let peripheral = central.peripheral(&id).await.ok();
+            QueryResult::Peripheral(id, received_at, peripheral)
+        });
+    }
+
+    /// 対象デバイスのプロパティの問い合わせを始める
+    fn query_properties(&mut self, id: &PeripheralId, received_at: Instant, sound_map: &SharedSoundMap, time: &dyn TimeSource) {
+        let Some(known) = self.peripherals.get_mut(id) else {
+            return;
+        };
 
         // 早期リターン: sound_mapに含まれないデバイスは即座にスキップ
         // プロパティ取得前にフィルタリングすることでパフォーマンス向上
-        if !sound_map.lock().unwrap().contains_key(&address) {
+        // （来場者トークンはスマートフォンの広告に載るため、有効時はプロパティを見るまで判断できない）
+        if !self.report_all && !sound_map.load().contains(&known.address) && !cfg!(feature = "visitor-tokens") {
             return;
         }
 
-        // ターゲットデバイスのみプロパティを取得
-        if let Ok(Some(props)) = p.properties().await {
-            if let Some(rssi) = props.rssi {
-                // キャッシュをチェックして、送信すべきかを判定
-                let should_send = {
-                    let mut cache = device_cache.lock().unwrap();
-
-                    if let Some(cached) = cache.get_mut(&address) {
-                        let elapsed = cached.last_sent.elapsed();
-                        let rssi_diff = (rssi - cached.last_rssi).abs();
-
-                        // 以下の条件のいずれかを満たす場合に送信:
-                        // 1. 25ms以上経過している（50ms→25msに短縮でさらに高速化）
-                        // 2. RSSIが1dBm以上変化している
-                        let should_send = elapsed >= Duration::from_millis(25) || rssi_diff >= 1;
-
-                        if should_send {
-                            cached.last_sent = Instant::now();
-                            cached.last_rssi = rssi;
-                        }
-
-                        should_send
-                    } else {
-                        // 新しいデバイス - 必ず送信
-                        cache.insert(address.clone(), DeviceCache {
-                            last_sent: Instant::now(),
-                            last_rssi: rssi,
-                        });
-                        true
-                    }
-                };
+        // 1回の広告で続けて届く更新イベントはまとめて1回だけ問い合わせる
+        if known.querying || known.last_query.is_some_and(|last_query| time.elapsed(last_query) < PROPERTIES_QUERY_INTERVAL) {
+            return;
+        }
+        if self.queries.len() >= MAX_PENDING_QUERIES {
+            debug!(address = %known.address, "Too many pending BlueZ queries - skipping update");
+            return;
+        }
+        known.last_query = Some(time.now());
+        known.querying = true;
+
+        let peripheral = known.peripheral.clone();
+        let id = id.clone();
+        self.queries.spawn(async move {
+            let props = peripheral.properties().await;
+            QueryResult::Properties(id, received_at, props)
+        });
+    }
 
-                if should_send {
-                    let device_info = Arc::new(DeviceInfo {
-                        address: address.clone(),
-                        rssi,
-                        last_seen: Instant::now(),
-                    });
-                    debug!(device = ?device_info, "Device found - sending update");
-                    if let Err(e) = sender.send(device_info).await {
-                        error!("Failed to send device info through channel: {}", e);
+    /// 問い合わせの結果を反映し、転送するプロパティがあれば返す
+    fn on_result(&mut self, result: QueryResult, sound_map: &SharedSoundMap, time: &dyn TimeSource) -> Option<(Address, Instant, PeripheralProperties)> {
+        match result {
+            QueryResult::Peripheral(id, received_at, peripheral) => {
+                // 再スキャンで取り消された問い合わせの結果は使わない
+                if !self.resolving.remove(&id) {
+                    return None;
+                }
+                let peripheral = peripheral?;
+                self.peripherals.insert(
+                    id.clone(),
+                    KnownPeripheral {
+                        address: Address::from(peripheral.address()),
+                        peripheral,
+                        last_query: None,
+                        querying: false,
+                        last_event: time.now(),
+                    },
+                );
+                self.query_properties(&id, received_at, sound_map, time);
+                None
+            }
+            QueryResult::Properties(id, received_at, props) => {
+                let known = self.peripherals.get_mut(&id)?;
+                known.querying = false;
+                match props {
+                    Ok(Some(props)) => Some((known.address.clone(), received_at, props)),
+                    Ok(None) => None,
+                    Err(e) => {
+                        // BlueZ側でデバイスが消えた場合は次のイベントで取り直す
+                        debug!(address = %known.address, "Failed to get properties, dropping cached peripheral: {:?}", e);
+                        self.peripherals.remove(&id);
+                        None
                     }
-                } else {
-                    // 送信をスキップしたことをトレース（詳細ログ）
-                    debug!(address = %address, rssi = %rssi, "Skipping send (too soon or RSSI unchanged)");
                 }
             }
         }
     }
+
+    /// しばらくイベントのないペリフェラルを捨てる
+    fn cleanup(&mut self, time: &dyn TimeSource) {
+        self.peripherals.retain(|_, known| time.elapsed(known.last_event) < PERIPHERAL_RETENTION);
+    }
+
+    /// 保持しているペリフェラルを捨て、実行中の問い合わせを取り消す（再スキャン時）
+    fn clear(&mut self) {
+        self.peripherals.clear();
+        self.resolving.clear();
+        self.queries.abort_all();
+    }
+}
+
+/// 問い合わせたプロパティを見て、対象デバイスならDeviceInfoを送る
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip(received_at, props, report_all, sender, sound_map, device_cache, time))]
+async fn forward_properties(
+    address: Address,
+    received_at: Instant,
+    props: PeripheralProperties,
+    report_all: bool,
+    sender: &mpsc::Sender<Arc<DeviceInfo>>,
+    sound_map: &SharedSoundMap,
+    device_cache: &Mutex<ThrottleCache>,
+    time: &dyn TimeSource,
+) {
+    let is_location = sound_map.load().contains(&address);
+    let visitor_token = visitor_token(&props);
+    if !report_all && !is_location && visitor_token.is_none() {
+        return;
+    }
+    if let Some(rssi) = props.rssi {
+        // キャッシュをチェックして、送信すべきかを判定
+        let should_send = device_cache.lock().unwrap().should_send(&address, rssi, time);
+
+        if should_send {
+            let device_info = Arc::new(DeviceInfo {
+                address: address.clone(),
+                rssi,
+                last_seen: time.now(),
+                received_at,
+                forwarded_at: None,
+                visitor_token,
+                // 下流で改めてBlueZに問い合わせなくて済むよう、広告の内容もそのまま渡す
+                local_name: props.local_name,
+                tx_power: props.tx_power_level,
+                manufacturer_data: props.manufacturer_data,
+                service_data: props.service_data,
+            });
+            debug!(device = ?device_info, "Device found - sending update");
+            if let Err(e) = sender.send(device_info).await {
+                error!("Failed to send device info through channel: {}", e);
+            }
+        } else {
+            // 送信をスキップしたことをトレース（詳細ログ）
+            debug!(address = %address, rssi = %rssi, "Skipping send (too soon or RSSI unchanged)");
+        }
+    }
 }
@@ -0,0 +1,41 @@
+use crate::bluetooth_system::simulation::simulation_main;
+use crate::clock_system::time_source::SharedTimeSource;
+use crate::config_system::config_main::SimulationConfig;
+use crate::messages::DeviceInfo;
+use futures::future::BoxFuture;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+
+/// ビーコンの入力元（BLEスキャナ・シミュレーション）
+///
+/// 転送タスクより下流は入力元を区別しないため、`ble` 機能の無いビルドやキオスク向けの構成では
+/// 入力元だけを差し替える。
+pub trait DeviceSource: Send {
+    /// ログに出す入力元の名前
+    fn name(&self) -> &'static str;
+
+    /// 受け取ったDeviceInfoを `tx` へ流す（終了手順がBLEの停止に達するか、送信先が閉じると戻る）
+    fn run(self: Box<Self>, tx: mpsc::Sender<Arc<DeviceInfo>>) -> BoxFuture<'static, ()>;
+}
+
+/// 設定の `simulation` のビーコンを入力にする
+pub struct SimulatedSource {
+    config: SimulationConfig,
+    time: SharedTimeSource,
+}
+
+impl SimulatedSource {
+    pub fn new(config: SimulationConfig, time: SharedTimeSource) -> Self {
+        Self { config, time }
+    }
+}
+
+impl DeviceSource for SimulatedSource {
+    fn name(&self) -> &'static str {
+        "simulation"
+    }
+
+    fn run(self: Box<Self>, tx: mpsc::Sender<Arc<DeviceInfo>>) -> BoxFuture<'static, ()> {
+        Box::pin(simulation_main(self.config, tx, self.time))
+    }
+}
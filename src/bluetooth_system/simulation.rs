@@ -0,0 +1,50 @@
+use crate::bluetooth_system::address::Address;
+use crate::clock_system::time_source::SharedTimeSource;
+use crate::config_system::config_main::SimulationConfig;
+use crate::messages::DeviceInfo;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tracing::{info, instrument, warn};
+
+/// 設定したビーコンを一定間隔で受信したものとして流すタスク（BLEアダプタのない環境・キオスク向け）
+///
+/// 送信先のチャンネルが閉じると終了する。
+#[instrument(skip_all)]
+pub async fn simulation_main(config: SimulationConfig, sender: mpsc::Sender<Arc<DeviceInfo>>, time: SharedTimeSource) {
+    let beacons: Vec<(Address, i16)> = config
+        .beacons
+        .iter()
+        .filter_map(|beacon| match beacon.address.parse::<Address>() {
+            Ok(address) => Some((address, beacon.rssi)),
+            Err(e) => {
+                warn!("Ignoring simulated beacon: {}", e);
+                None
+            }
+        })
+        .collect();
+    info!(count = beacons.len(), "Simulating BLE beacons");
+
+    let mut interval = tokio::time::interval(Duration::from_millis(config.interval_ms.max(100)));
+    loop {
+        interval.tick().await;
+        for (address, rssi) in &beacons {
+            let device_info = DeviceInfo {
+                address: address.clone(),
+                rssi: *rssi,
+                last_seen: time.now(),
+                received_at: Instant::now(),
+                forwarded_at: None,
+                visitor_token: None,
+                local_name: None,
+                tx_power: None,
+                manufacturer_data: HashMap::new(),
+                service_data: HashMap::new(),
+            };
+            if sender.send(Arc::new(device_info)).await.is_err() {
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use crate::bluetooth_system::address::Address;
+use crate::clock_system::time_source::TimeSource;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// 同じデバイスの送信間隔（これより短い間隔ではRSSIが変わったときだけ送る）
+const MIN_SEND_INTERVAL: Duration = Duration::from_millis(25);
+
+/// 送信した値として扱うRSSIの変化量（dBm）
+const MIN_RSSI_CHANGE: i16 = 1;
+
+// デバイス情報のキャッシュ構造体
+struct DeviceCache {
+    last_sent: Instant,
+    last_rssi: i16,
+}
+
+/// BLEスキャナが下流へ送るDeviceInfoを間引くキャッシュ
+///
+/// BlueZは1台のビーコンについて毎秒何十回も通知するため、頻繁な送信を抑制しつつ、重要な更新（RSSIの変化）は通知する。
+#[derive(Default)]
+pub struct ThrottleCache {
+    devices: HashMap<Address, DeviceCache>,
+}
+
+impl ThrottleCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// このRSSIを送るべきかを判定し、送る場合は送信済みとして記録する
+    pub fn should_send(&mut self, address: &Address, rssi: i16, time: &dyn TimeSource) -> bool {
+        if let Some(cached) = self.devices.get_mut(address) {
+            let elapsed = time.elapsed(cached.last_sent);
+            let rssi_diff = (rssi - cached.last_rssi).abs();
+
+            // 以下の条件のいずれかを満たす場合に送信:
+            // 1. 25ms以上経過している（50ms→25msに短縮でさらに高速化）
+            // 2. RSSIが1dBm以上変化している
+            let should_send = elapsed >= MIN_SEND_INTERVAL || rssi_diff >= MIN_RSSI_CHANGE;
+
+            if should_send {
+                cached.last_sent = time.now();
+                cached.last_rssi = rssi;
+            }
+
+            should_send
+        } else {
+            // 新しいデバイス - 必ず送信
+            self.devices.insert(address.clone(), DeviceCache { last_sent: time.now(), last_rssi: rssi });
+            true
+        }
+    }
+
+    /// `max_age` 以上送っていないデバイスを忘れる（来場者のスマートフォンなどでキャッシュが膨らまないように）
+    pub fn retain_recent(&mut self, max_age: Duration, time: &dyn TimeSource) {
+        self.devices.retain(|_, v| time.elapsed(v.last_sent) < max_age);
+    }
+
+    pub fn len(&self) -> usize {
+        self.devices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.devices.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock_system::time_source::MockTimeSource;
+
+    fn beacon() -> Address {
+        "AA:BB:CC:DD:EE:01".parse().unwrap()
+    }
+
+    #[test]
+    fn first_sighting_is_always_sent() {
+        let time = MockTimeSource::new();
+        let mut cache = ThrottleCache::new();
+        assert!(cache.should_send(&beacon(), -60, &time));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn unchanged_rssi_is_held_back_until_the_interval_passes() {
+        let time = MockTimeSource::new();
+        let mut cache = ThrottleCache::new();
+        assert!(cache.should_send(&beacon(), -60, &time));
+        time.advance(Duration::from_millis(10));
+        assert!(!cache.should_send(&beacon(), -60, &time));
+        time.advance(MIN_SEND_INTERVAL);
+        assert!(cache.should_send(&beacon(), -60, &time));
+    }
+
+    #[test]
+    fn rssi_change_is_sent_immediately() {
+        let time = MockTimeSource::new();
+        let mut cache = ThrottleCache::new();
+        assert!(cache.should_send(&beacon(), -60, &time));
+        assert!(cache.should_send(&beacon(), -61, &time));
+        // 送った値（-61）からの変化で判定する
+        assert!(!cache.should_send(&beacon(), -61, &time));
+    }
+
+    #[test]
+    fn stale_devices_are_forgotten() {
+        let time = MockTimeSource::new();
+        let mut cache = ThrottleCache::new();
+        cache.should_send(&beacon(), -60, &time);
+        time.advance(Duration::from_secs(30));
+        cache.should_send(&"AA:BB:CC:DD:EE:02".parse().unwrap(), -60, &time);
+        time.advance(Duration::from_secs(40));
+        cache.retain_recent(Duration::from_secs(60), &time);
+        assert_eq!(cache.len(), 1);
+        // 忘れたデバイスは新しいデバイスとして扱う
+        assert!(cache.should_send(&beacon(), -60, &time));
+    }
+}
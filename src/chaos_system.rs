@@ -0,0 +1 @@
+pub mod chaos_main;
\ No newline at end of file
@@ -0,0 +1,192 @@
+use crate::bluetooth_system::address::Address;
+use crate::messages::{DeviceInfo, DeviceSnapshot, EnabledState};
+use anyhow::{anyhow, Result};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, mpsc, watch};
+use tracing::{error, info, instrument, warn};
+
+/// 注入する障害の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// サーバーとのストリームを切断する
+    DropStream,
+    /// オーディオのループを止めてGStreamerのバスのメッセージを滞留させる
+    DelayAudioBus,
+    /// Bluetoothアダプタが失われたものとしてスキャナを失敗させる
+    AdapterLoss,
+    /// 異常な値のDeviceInfoを流す
+    MalformedEvent,
+}
+
+const FAULTS: [Fault; 4] = [Fault::DropStream, Fault::DelayAudioBus, Fault::AdapterLoss, Fault::MalformedEvent];
+
+/// 障害の状態（`FaultState::Pending` → 各タスクが発生させて `Triggered` → 復旧して `Recovered`）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+enum FaultState {
+    Idle = 0,
+    Pending = 1,
+    Triggered = 2,
+    Recovered = 3,
+}
+
+static STATES: [AtomicU8; FAULTS.len()] = [const { AtomicU8::new(FaultState::Idle as u8) }; FAULTS.len()];
+
+/// 障害を注入してから、各タスクが発生させるまでの待ち時間の上限（対象のタスクが動いていない場合は諦める）
+const TRIGGER_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// 障害の発生から復旧までの上限（再接続の待ち5秒・スキャナの再試行10秒に余裕を持たせる）
+const RECOVERY_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn state(fault: Fault) -> &'static AtomicU8 {
+    &STATES[fault as usize]
+}
+
+fn transition(fault: Fault, from: FaultState, to: FaultState) -> bool {
+    state(fault)
+        .compare_exchange(from as u8, to as u8, Ordering::AcqRel, Ordering::Acquire)
+        .is_ok()
+}
+
+/// 注入された障害をこの場で発生させるかどうか（`true` を返すのは注入1回につき1度だけ）
+pub fn triggered(fault: Fault) -> bool {
+    let triggered = transition(fault, FaultState::Pending, FaultState::Triggered);
+    if triggered {
+        warn!(?fault, "Chaos: injecting fault");
+    }
+    triggered
+}
+
+/// 発生させた障害から正常な状態に戻ったことを知らせる（発生中でなければ何もしない）
+pub fn recovered(fault: Fault) {
+    transition(fault, FaultState::Triggered, FaultState::Recovered);
+}
+
+/// 注入された障害を発生させるまで待つ（ストリームを途中で終わらせる用）
+pub async fn wait_triggered(fault: Fault) {
+    while !triggered(fault) {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+/// オーディオのループを止める時間
+pub fn audio_bus_delay() -> Duration {
+    Duration::from_millis(rand::thread_rng().gen_range(500..5000))
+}
+
+/// 異常な値のDeviceInfo（範囲外のRSSI・巨大な広告データ・制御文字入りの名前）
+fn malformed_device(rng: &mut StdRng) -> DeviceInfo {
+    let bytes: [u8; 6] = rng.gen();
+    DeviceInfo {
+        address: Address::from(bytes),
+        rssi: [i16::MIN, i16::MAX, 0, 127][rng.gen_range(0..4)],
+        last_seen: Instant::now(),
+        received_at: Instant::now(),
+        forwarded_at: None,
+        visitor_token: Some("\u{0}\u{fffd}".repeat(rng.gen_range(0..64))),
+        local_name: Some("\u{1b}[2J\u{202e}".repeat(rng.gen_range(0..64))),
+        tx_power: Some(i16::MIN),
+        manufacturer_data: HashMap::from([(u16::MAX, vec![0xff; rng.gen_range(0..4096)])]),
+        service_data: HashMap::new(),
+    }
+}
+
+/// 異常なDeviceInfoを流し、転送タスクが止まらずに配信し続けることを確かめる
+async fn inject_malformed_event(
+    rng: &mut StdRng,
+    bt_tx: &mpsc::Sender<Arc<DeviceInfo>>,
+    snapshot_rx: &broadcast::Receiver<Arc<DeviceSnapshot>>,
+) -> Result<Duration> {
+    // 注入前の配信を読み飛ばす
+    let mut snapshot_rx = snapshot_rx.resubscribe();
+    let device = malformed_device(rng);
+    let address = device.address.clone();
+    warn!(fault = ?Fault::MalformedEvent, %address, rssi = device.rssi, "Chaos: injecting fault");
+    let triggered_at = Instant::now();
+    bt_tx.send(Arc::new(device)).await.map_err(|_| anyhow!("Device info channel is closed"))?;
+
+    tokio::time::timeout(RECOVERY_TIMEOUT, async {
+        loop {
+            match snapshot_rx.recv().await {
+                Ok(snapshot) if snapshot.devices.iter().any(|device| device.address == address) => {
+                    return Ok(triggered_at.elapsed())
+                }
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => return Err(anyhow!("Device snapshot channel is closed")),
+            }
+        }
+    })
+    .await
+    .map_err(|_| anyhow!("Malformed device info was not forwarded"))?
+}
+
+/// 各タスクに障害を注入し、上限時間内に復旧したかを確かめて復旧までの時間を返す
+///
+/// `Ok(None)` は対象のタスクが動いておらず、障害が発生しなかった。
+async fn inject(fault: Fault) -> Result<Option<Duration>> {
+    state(fault).store(FaultState::Pending as u8, Ordering::Release);
+
+    let started = Instant::now();
+    while state(fault).load(Ordering::Acquire) == FaultState::Pending as u8 {
+        if started.elapsed() > TRIGGER_TIMEOUT {
+            // 発生する前に取り消す（遅れて発生しないように）
+            if transition(fault, FaultState::Pending, FaultState::Idle) {
+                return Ok(None);
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    let triggered_at = Instant::now();
+    while state(fault).load(Ordering::Acquire) != FaultState::Recovered as u8 {
+        if triggered_at.elapsed() > RECOVERY_TIMEOUT {
+            state(fault).store(FaultState::Idle as u8, Ordering::Release);
+            return Err(anyhow!("{:?} did not recover within {:?}", fault, RECOVERY_TIMEOUT));
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    state(fault).store(FaultState::Idle as u8, Ordering::Release);
+    Ok(Some(triggered_at.elapsed()))
+}
+
+/// ソークテスト用に障害をランダムに注入し続けるタスク（`--chaos`）
+///
+/// 上限時間内に復旧しなかった場合は、ソークテストの失敗として検知できるようプロセスを終了する。
+#[instrument(skip_all)]
+pub async fn chaos_main(
+    bt_tx: mpsc::Sender<Arc<DeviceInfo>>,
+    snapshot_rx: broadcast::Receiver<Arc<DeviceSnapshot>>,
+    enabled_rx: watch::Receiver<EnabledState>,
+) {
+    let mut rng = StdRng::from_entropy();
+    let mut injected: u64 = 0;
+    warn!("Chaos mode enabled - faults will be injected at random");
+
+    loop {
+        tokio::time::sleep(Duration::from_secs(rng.gen_range(20..90))).await;
+
+        let fault = FAULTS[rng.gen_range(0..FAULTS.len())];
+        let result = match fault {
+            // 無効化中は転送されないため確かめられない
+            Fault::MalformedEvent if !enabled_rx.borrow().enabled => Ok(None),
+            Fault::MalformedEvent => inject_malformed_event(&mut rng, &bt_tx, &snapshot_rx).await.map(Some),
+            _ => inject(fault).await,
+        };
+        match result {
+            Ok(Some(recovery)) => {
+                injected += 1;
+                info!(?fault, recovery_ms = recovery.as_millis() as u64, injected, "Chaos: recovered from fault");
+            }
+            Ok(None) => info!(?fault, "Chaos: fault was not triggered (target task is not running)"),
+            Err(e) => {
+                error!(?fault, injected, "Chaos: {:?}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+}
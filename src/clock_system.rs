@@ -0,0 +1,2 @@
+pub mod clock_main;
+pub mod time_source;
\ No newline at end of file
@@ -0,0 +1,331 @@
+use crate::clock_system::time_source::SharedTimeSource;
+use crate::event_system::event_bus::{EventBus, LocalEvent};
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, instrument, warn};
+
+/// 小さな補正を徐々に反映する速度（経過時間に対する割合）
+const SLEW_RATE: f64 = 0.05;
+
+/// これより大きなずれは徐々に補正せず即座に反映する
+const STEP_THRESHOLD_NS: i64 = 500_000_000;
+
+/// 同期後に想定する水晶振動子のずれ（100ppm）
+const DRIFT_PPM: u64 = 100;
+
+/// システムのNTP同期のみを頼る場合の誤差の想定値
+const SYSTEM_NTP_UNCERTAINTY_NS: u64 = 20_000_000;
+
+/// システムのNTP同期状態を確認する間隔
+const SYSTEM_NTP_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// 「ショー時刻」（推定サーバー時刻）の拠り所
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClockSource {
+    /// まだ何とも同期していない
+    Unsynced,
+    /// システムのNTP（chrony/timesyncd）のみ。サーバーもNTP同期している前提でオフセット0とみなす
+    SystemNtp,
+    /// 近くのユニットから受け取った時刻
+    Peer,
+    /// バックエンドのTimeService
+    TimeService,
+}
+
+/// オーディオが参照するショー時刻
+#[derive(Debug, Clone, Copy)]
+pub struct ShowTime {
+    pub show_time_ns: u64,
+    /// 推定誤差（±ナノ秒）
+    pub uncertainty_ns: u64,
+    pub source: ClockSource,
+}
+
+#[derive(Debug)]
+struct ClockState {
+    source: ClockSource,
+    /// 目標のオフセット（最新の同期結果）
+    target_offset_ns: i64,
+    /// 実際に適用しているオフセット（目標へ徐々に近づける）
+    applied_offset_ns: i64,
+    /// 同期した瞬間の誤差
+    base_uncertainty_ns: u64,
+    updated_at: Instant,
+    last_slew_at: Instant,
+    backend_synced_at: Option<Instant>,
+    system_ntp_synced: bool,
+}
+
+/// TimeService・ピア・システムNTPの情報をまとめて1つのショー時刻を提供する時計
+///
+/// 以前は「オフセット0 = 未同期」として扱っていたが、同期状態と誤差を明示的に持つ。
+/// 小さな補正は徐々に反映するため、通常の運用ではショー時刻は巻き戻らない。
+#[derive(Debug)]
+pub struct Clock {
+    state: Mutex<ClockState>,
+    time: SharedTimeSource,
+    /// 拠り所の変化を通知する
+    events: EventBus,
+}
+
+impl Clock {
+    pub fn new(time: SharedTimeSource, events: EventBus) -> Self {
+        let now = time.now();
+        Self {
+            time,
+            events,
+            state: Mutex::new(ClockState {
+                source: ClockSource::Unsynced,
+                target_offset_ns: 0,
+                applied_offset_ns: 0,
+                base_uncertainty_ns: 0,
+                updated_at: now,
+                last_slew_at: now,
+                backend_synced_at: None,
+                system_ntp_synced: false,
+            }),
+        }
+    }
+
+    /// 経過時間の計算・同期に使う時刻の取得元
+    pub fn time_source(&self) -> SharedTimeSource {
+        self.time.clone()
+    }
+
+    fn set_target(&self, state: &mut ClockState, now: Instant, offset_ns: i64, uncertainty_ns: u64, source: ClockSource) {
+        // 未同期からの初回や大きなずれは即座に反映する
+        if state.source == ClockSource::Unsynced || (offset_ns - state.applied_offset_ns).abs() > STEP_THRESHOLD_NS {
+            if state.source != ClockSource::Unsynced {
+                warn!(
+                    step_ms = (offset_ns - state.applied_offset_ns) / 1_000_000,
+                    ?source,
+                    "Clock offset stepped"
+                );
+            }
+            state.applied_offset_ns = offset_ns;
+        }
+        if state.source != source {
+            info!(from = ?state.source, to = ?source, "Clock source changed");
+            self.events.publish(LocalEvent::SyncUpdated { source });
+        }
+        state.source = source;
+        state.target_offset_ns = offset_ns;
+        state.base_uncertainty_ns = uncertainty_ns;
+        state.updated_at = now;
+    }
+
+    /// TimeServiceの同期結果を反映する
+    pub fn update_from_time_service(&self, offset_ns: i64, round_trip_ns: i64) {
+        let mut state = self.state.lock().unwrap();
+        let now = self.time.now();
+        self.set_target(&mut state, now, offset_ns, (round_trip_ns.max(0) / 2) as u64, ClockSource::TimeService);
+        state.backend_synced_at = Some(now);
+    }
+
+    /// ピアから受け取った時刻を反映する（バックエンドが使えない場合のみ呼ぶ）
+    pub fn update_from_peer(&self, offset_ns: i64, uncertainty_ns: u64) {
+        let mut state = self.state.lock().unwrap();
+        self.set_target(&mut state, self.time.now(), offset_ns, uncertainty_ns, ClockSource::Peer);
+    }
+
+    /// システムのNTP同期状態を反映する（他に拠り所がない場合のみオフセット0を使う）
+    pub fn update_system_ntp(&self, synced: bool) {
+        let mut state = self.state.lock().unwrap();
+        if state.system_ntp_synced != synced {
+            info!(synced, "System NTP sync state changed");
+            state.system_ntp_synced = synced;
+        }
+        if synced && state.source == ClockSource::Unsynced {
+            self.set_target(&mut state, self.time.now(), 0, SYSTEM_NTP_UNCERTAINTY_NS, ClockSource::SystemNtp);
+        }
+    }
+
+    /// 最後にバックエンドと同期してからの経過時間
+    pub fn backend_sync_age(&self) -> Option<Duration> {
+        self.state.lock().unwrap().backend_synced_at.map(|t| self.time.elapsed(t))
+    }
+
+    /// 現在のショー時刻（未同期の場合は `None`）
+    pub fn now(&self) -> Option<ShowTime> {
+        let mut state = self.state.lock().unwrap();
+        if state.source == ClockSource::Unsynced {
+            return None;
+        }
+
+        // 目標オフセットへ経過時間に比例した量だけ近づける
+        let now = self.time.now();
+        let elapsed_ns = now.saturating_duration_since(state.last_slew_at).as_nanos() as f64;
+        state.last_slew_at = now;
+        let max_step = (elapsed_ns * SLEW_RATE) as i64;
+        let diff = state.target_offset_ns - state.applied_offset_ns;
+        state.applied_offset_ns += diff.clamp(-max_step, max_step);
+
+        let age_ns = now.saturating_duration_since(state.updated_at).as_nanos() as u64;
+        let uncertainty_ns = state.base_uncertainty_ns
+            + age_ns / 1_000_000 * DRIFT_PPM
+            + (state.target_offset_ns - state.applied_offset_ns).unsigned_abs();
+
+        Some(ShowTime {
+            show_time_ns: (self.time.unix_ns() + state.applied_offset_ns).max(0) as u64,
+            uncertainty_ns,
+            source: state.source,
+        })
+    }
+
+    /// 現在適用しているオフセット（未同期の場合は `None`）
+    pub fn offset_ns(&self) -> Option<i64> {
+        let state = self.state.lock().unwrap();
+        (state.source != ClockSource::Unsynced).then_some(state.applied_offset_ns)
+    }
+}
+
+/// システムのNTP同期状態を取得する（`timedatectl` が使えない環境では `None`）
+async fn system_ntp_synchronized() -> Option<bool> {
+    let output = tokio::process::Command::new("timedatectl")
+        .args(["show", "--property=NTPSynchronized", "--value"])
+        .output()
+        .await
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    match String::from_utf8_lossy(&output.stdout).trim() {
+        "yes" => Some(true),
+        "no" => Some(false),
+        _ => None,
+    }
+}
+
+/// システムのNTP同期状態を定期的に時計へ反映するタスク
+#[instrument(skip_all)]
+pub async fn watch_system_ntp(clock: std::sync::Arc<Clock>) {
+    loop {
+        match system_ntp_synchronized().await {
+            Some(synced) => clock.update_system_ntp(synced),
+            None => debug!("System NTP state unavailable"),
+        }
+        tokio::time::sleep(SYSTEM_NTP_POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock_system::time_source::MockTimeSource;
+    use std::sync::Arc;
+    use std::time::UNIX_EPOCH;
+
+    const START_UNIX_NS: u64 = 1_000_000_000_000;
+    const MS: i64 = 1_000_000;
+
+    fn clock() -> (Arc<MockTimeSource>, Clock, EventBus) {
+        let time = Arc::new(MockTimeSource::starting_at(UNIX_EPOCH + Duration::from_nanos(START_UNIX_NS)));
+        let events = EventBus::new(16);
+        (Arc::clone(&time), Clock::new(time, events.clone()), events)
+    }
+
+    #[test]
+    fn unsynced_clock_has_no_show_time() {
+        let (_time, clock, _events) = clock();
+        assert!(clock.now().is_none());
+        assert!(clock.offset_ns().is_none());
+        assert!(clock.backend_sync_age().is_none());
+    }
+
+    #[test]
+    fn first_sync_is_applied_immediately() {
+        let (_time, clock, _events) = clock();
+        clock.update_from_time_service(5 * MS, 2 * MS);
+        let show = clock.now().unwrap();
+        assert_eq!(show.source, ClockSource::TimeService);
+        assert_eq!(show.show_time_ns, START_UNIX_NS + 5 * MS as u64);
+        assert_eq!(show.uncertainty_ns, MS as u64);
+    }
+
+    #[test]
+    fn small_corrections_are_slewed() {
+        let (time, clock, _events) = clock();
+        clock.update_from_time_service(0, 0);
+        clock.update_from_time_service(100 * MS, 0);
+        // 反映前は残りのずれが誤差に含まれる
+        assert_eq!(clock.now().unwrap().uncertainty_ns, 100 * MS as u64);
+
+        time.advance(Duration::from_secs(1));
+        // 1秒で5%（50ms）だけ近づく
+        assert_eq!(clock.offset_ns(), Some(0));
+        clock.now();
+        assert_eq!(clock.offset_ns(), Some(50 * MS));
+
+        time.advance(Duration::from_secs(1));
+        clock.now();
+        assert_eq!(clock.offset_ns(), Some(100 * MS));
+        time.advance(Duration::from_secs(1));
+        assert_eq!(clock.offset_ns(), Some(100 * MS));
+    }
+
+    #[test]
+    fn show_time_never_goes_backwards_while_slewing() {
+        let (time, clock, _events) = clock();
+        clock.update_from_time_service(400 * MS, 0);
+        clock.update_from_time_service(0, 0);
+        let mut previous = clock.now().unwrap().show_time_ns;
+        for _ in 0..100 {
+            time.advance(Duration::from_millis(100));
+            let show = clock.now().unwrap().show_time_ns;
+            assert!(show >= previous);
+            previous = show;
+        }
+        assert_eq!(clock.offset_ns(), Some(0));
+    }
+
+    #[test]
+    fn large_corrections_are_stepped() {
+        let (_time, clock, _events) = clock();
+        clock.update_from_time_service(0, 0);
+        clock.update_from_time_service(-2_000 * MS, 0);
+        assert_eq!(clock.offset_ns(), Some(-2_000 * MS));
+    }
+
+    #[test]
+    fn uncertainty_grows_with_age() {
+        let (time, clock, _events) = clock();
+        clock.update_from_time_service(0, 2 * MS);
+        time.advance(Duration::from_secs(10));
+        // 100ppm: 10秒で1ms
+        assert_eq!(clock.now().unwrap().uncertainty_ns, 2 * MS as u64);
+        assert_eq!(clock.backend_sync_age(), Some(Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn system_ntp_is_only_a_fallback() {
+        let (_time, clock, _events) = clock();
+        clock.update_system_ntp(false);
+        assert!(clock.now().is_none());
+        clock.update_system_ntp(true);
+        let show = clock.now().unwrap();
+        assert_eq!(show.source, ClockSource::SystemNtp);
+        assert_eq!(show.uncertainty_ns, SYSTEM_NTP_UNCERTAINTY_NS);
+
+        clock.update_from_time_service(3 * MS, 0);
+        clock.update_system_ntp(true);
+        assert_eq!(clock.now().unwrap().source, ClockSource::TimeService);
+    }
+
+    #[test]
+    fn source_changes_are_published() {
+        let (_time, clock, events) = clock();
+        let mut rx = events.subscribe("test");
+        clock.update_from_peer(MS, MS as u64);
+        clock.update_from_peer(2 * MS, MS as u64);
+        clock.update_from_time_service(MS, 0);
+        let sources: Vec<ClockSource> = std::iter::from_fn(|| rx.try_recv().ok())
+            .filter_map(|event| match event {
+                LocalEvent::SyncUpdated { source } => Some(source),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(sources, [ClockSource::Peer, ClockSource::TimeService]);
+    }
+}
@@ -0,0 +1,127 @@
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// 現在時刻の取得元
+///
+/// クールダウン・キャッシュの掃除・時刻同期など時間に依存する処理は、`Instant::now()` などを
+/// 直接呼ばずにこれを経由し、テストやシミュレーションでは `MockTimeSource` に差し替えて時間を進める。
+pub trait TimeSource: Debug + Send + Sync {
+    /// 単調増加する時刻（経過時間の計算用）
+    fn now(&self) -> Instant;
+
+    /// 壁時計の時刻（再起動をまたぐ比較・サーバーとの時刻同期用）
+    fn system_now(&self) -> SystemTime;
+
+    /// `since` からの経過時間（`Instant::elapsed` の代わり）
+    fn elapsed(&self, since: Instant) -> Duration {
+        self.now().saturating_duration_since(since)
+    }
+
+    /// 壁時計のUNIX時刻（ミリ秒）
+    fn unix_ms(&self) -> u64 {
+        self.system_now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+    }
+
+    /// 壁時計のUNIX時刻（ナノ秒）
+    fn unix_ns(&self) -> i64 {
+        self.system_now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as i64
+    }
+}
+
+/// タスク間で共有する時刻の取得元
+pub type SharedTimeSource = Arc<dyn TimeSource>;
+
+/// OSの時計をそのまま使う
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemTimeSource;
+
+impl TimeSource for SystemTimeSource {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// OSの時計を使う取得元
+pub fn system() -> SharedTimeSource {
+    Arc::new(SystemTimeSource)
+}
+
+/// `advance` を呼んだときだけ進む時計（テスト・シミュレーション用）
+#[derive(Debug)]
+pub struct MockTimeSource {
+    origin: Instant,
+    system_origin: SystemTime,
+    advanced: Mutex<Duration>,
+}
+
+impl MockTimeSource {
+    /// 作成時点のOSの時刻から始める
+    pub fn new() -> Self {
+        Self::starting_at(SystemTime::now())
+    }
+
+    /// 壁時計を指定した時刻から始める
+    pub fn starting_at(system_origin: SystemTime) -> Self {
+        Self { origin: Instant::now(), system_origin, advanced: Mutex::new(Duration::ZERO) }
+    }
+
+    /// 時計を進める
+    pub fn advance(&self, duration: Duration) {
+        *self.advanced.lock().unwrap() += duration;
+    }
+}
+
+impl Default for MockTimeSource {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TimeSource for MockTimeSource {
+    fn now(&self) -> Instant {
+        self.origin + *self.advanced.lock().unwrap()
+    }
+
+    fn system_now(&self) -> SystemTime {
+        self.system_origin + *self.advanced.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mock_time_only_moves_when_advanced() {
+        let time = MockTimeSource::starting_at(UNIX_EPOCH + Duration::from_secs(1_000));
+        let start = time.now();
+        assert_eq!(time.now(), start);
+        assert_eq!(time.unix_ms(), 1_000_000);
+
+        time.advance(Duration::from_millis(1_500));
+        assert_eq!(time.elapsed(start), Duration::from_millis(1_500));
+        assert_eq!(time.unix_ms(), 1_001_500);
+        assert_eq!(time.unix_ns(), 1_001_500_000_000);
+    }
+
+    #[test]
+    fn elapsed_saturates_for_future_instants() {
+        let time = MockTimeSource::new();
+        let future = time.now() + Duration::from_secs(5);
+        assert_eq!(time.elapsed(future), Duration::ZERO);
+    }
+
+    #[test]
+    fn shared_mock_is_seen_by_every_holder() {
+        let mock = Arc::new(MockTimeSource::new());
+        let shared: SharedTimeSource = mock.clone();
+        let start = shared.now();
+        mock.advance(Duration::from_secs(3));
+        assert_eq!(shared.elapsed(start), Duration::from_secs(3));
+    }
+}
@@ -0,0 +1,3 @@
+pub mod config_main;
+pub mod config_store;
+pub mod unit_identity;
\ No newline at end of file
@@ -0,0 +1,1230 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// 設定ファイルのデフォルトパス（作業ディレクトリからの相対パス）
+const DEFAULT_CONFIG_PATH: &str = "tsukimi-config.json";
+
+/// 設定ファイルのパスを上書きする環境変数
+const CONFIG_PATH_ENV: &str = "TSUKIMI_CONFIG";
+
+/// アプリケーション全体の設定
+///
+/// 設定ファイルに書かれていない項目はすべてデフォルト値になるため、
+/// 会場ごとに変更したい項目だけを記述すればよい。
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub unit: UnitConfig,
+    pub server: ServerConfig,
+    pub proxy: ProxyConfig,
+    pub pinning: PinningConfig,
+    pub channels: ChannelConfig,
+    pub forwarding: ForwardingConfig,
+    pub upload: UploadConfig,
+    pub peer: PeerConfig,
+    pub chime: ChimeConfig,
+    pub playback: PlaybackConfig,
+    pub gpio: GpioConfig,
+    pub interaction: InteractionConfig,
+    pub webhook: WebhookConfig,
+    pub osc: OscConfig,
+    pub midi: MidiConfig,
+    pub dmx: DmxConfig,
+    pub api: ApiConfig,
+    pub log_shipping: LogShippingConfig,
+    pub crash_report: CrashReportConfig,
+    pub schedule: ScheduleConfig,
+    pub script: ScriptConfig,
+    /// 起動時のsound_map（サーバーのLocationUpdateで差し替えられる。スタンドアロン構成ではこれだけを使う）
+    pub locations: Vec<LocationConfig>,
+    pub simulation: SimulationConfig,
+    pub survey: SurveyConfig,
+    pub shutdown: ShutdownConfig,
+}
+
+/// バックエンドサーバーの接続設定
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    /// サーバーとの通信方式
+    pub transport: TransportKind,
+    /// gRPCサーバーのURL
+    pub grpc_url: String,
+    /// gRPC接続に失敗し続けた場合に使うWebSocketのURL（例: `ws://example.com/device`）
+    pub websocket_url: Option<String>,
+    /// WebSocketへ切り替えるまでのgRPC接続の連続失敗回数
+    pub websocket_fallback_after: u32,
+    /// MQTTブローカーの設定（`transport` が `mqtt` の場合のみ使用）
+    pub mqtt: MqttConfig,
+    /// プレイヤーAPIのベースURL（インタラクションの加算・ポイント数の取得に使う）
+    pub players_api_url: String,
+    /// 空・不正なアドレスを含むLocationUpdateを受け付けず、直前のsound_mapを保つ
+    pub reject_invalid_locations: bool,
+    /// DeviceService・TimeServiceで送受信したメッセージをJSON Linesで記録するファイル
+    pub record_session: Option<String>,
+    /// 記録したセッションを再生する（サーバーには接続せず、受信したメッセージを記録時の間隔で処理する）
+    pub replay_session: Option<String>,
+    pub reconnect: ReconnectConfig,
+    pub se_rate_limit: SeRateLimitConfig,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            transport: TransportKind::Grpc,
+            grpc_url: "http://34.85.68.246:50051".to_string(),
+            websocket_url: None,
+            websocket_fallback_after: 3,
+            mqtt: MqttConfig::default(),
+            players_api_url: "https://tsukimi.paon.dev/players".to_string(),
+            reject_invalid_locations: true,
+            record_session: None,
+            replay_session: None,
+            reconnect: ReconnectConfig::default(),
+            se_rate_limit: SeRateLimitConfig::default(),
+        }
+    }
+}
+
+/// ユニットの識別情報（ログ・メトリクス・状態の報告・インタラクションのリクエストに付け、会場・エリアごとにまとめて見られるようにする）
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UnitConfig {
+    /// ユニットID（未設定なら `log_shipping.unit_id`、それも無ければホスト名）
+    pub id: Option<String>,
+    /// 会場の名前
+    pub venue: Option<String>,
+    /// 会場内のエリア（ゾーン）の名前
+    pub zone: Option<String>,
+    /// ハードウェアのリビジョン（未設定ならデバイスツリーのモデル名）
+    pub hardware_revision: Option<String>,
+}
+
+/// 外向き通信のプロキシ（会場のネットワークがプロキシ経由でしか外に出られない場合）
+///
+/// gRPCのチャンネルと、HTTPの通信（インタラクション・プレイヤーAPI・音源の先読み・Webhook・ログ転送・クラッシュレポート）に使う。
+/// MQTT・WebSocketの接続はプロキシを経由しない。
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProxyConfig {
+    /// プロキシのURL（`http://[user:pass@]host:port` でHTTP CONNECT、`socks5://` または `socks5h://` でSOCKS5）
+    pub url: Option<String>,
+    /// プロキシを経由しないホスト（そのサブドメインも含む。`*` ならすべて）
+    pub no_proxy: Vec<String>,
+}
+
+/// 証明書のピンニング（会場のネットワーク機器がTLSを復号・再署名する中間者を検知するため）
+///
+/// ピンを設定したホストには、証明書チェーンのいずれかの公開鍵（SubjectPublicKeyInfo）のSHA-256が
+/// ピンのどれかと一致する場合だけ接続する。HTTPの通信と、`https` のgRPCに使う。
+/// ピンが空・読めないホストには（`report_only` でなければ）接続しない。
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PinningConfig {
+    /// ホスト -> 許可する公開鍵のハッシュ（`sha256/` + base64。鍵の更新に備えて予備の鍵も並べる）
+    pub pins: BTreeMap<String, Vec<String>>,
+    /// ステージング用: 一致しなくても警告だけ出して接続する
+    pub report_only: bool,
+}
+
+/// サーバーへの再接続の間隔（指数バックオフ + ジッター）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReconnectConfig {
+    /// 最初の待ち時間の上限（ミリ秒）
+    pub initial_delay_ms: u64,
+    /// 失敗するたびに待ち時間の上限に掛ける倍率
+    pub multiplier: f64,
+    /// 待ち時間の上限の最大値（ミリ秒）
+    pub max_delay_ms: u64,
+    /// 待ち時間を上限からランダムに縮める割合（0.0〜1.0。0.5なら上限の50〜100%）
+    pub jitter: f64,
+    /// 接続できない状態がこの時間（ミリ秒）続いたら `server_offline` イベントで通知する
+    pub alert_after_ms: u64,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self { initial_delay_ms: 2000, multiplier: 2.0, max_delay_ms: 60_000, jitter: 0.5, alert_after_ms: 5 * 60 * 1000 }
+    }
+}
+
+/// サーバーの指示で鳴らすSE（SePlay・ポイント加算のSE）の回数の上限
+///
+/// 不具合のあるバックエンドがPointUpdateなどを大量に送ってきても、SEが鳴り続けないようにする。
+/// 回数はSEのファイルごとに数え、上限を超えた分は鳴らさずに捨てる。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SeRateLimitConfig {
+    pub enabled: bool,
+    /// `window_ms` の間に同じSEを鳴らす回数の上限
+    pub max_triggers: u32,
+    pub window_ms: u64,
+}
+
+impl Default for SeRateLimitConfig {
+    fn default() -> Self {
+        Self { enabled: true, max_triggers: 3, window_ms: 5000 }
+    }
+}
+
+/// サーバーとの通信方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportKind {
+    #[default]
+    Grpc,
+    /// 外向きのMQTTしか許可されていない会場向け（時刻同期は行わない）
+    Mqtt,
+}
+
+/// MQTTブローカーの接続設定
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    /// クライアントID（空の場合はプロセスIDから生成）
+    pub client_id: String,
+    /// 送受信するトピックのプレフィックス
+    pub topic_prefix: String,
+    pub keep_alive_secs: u64,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        Self {
+            host: "localhost".to_string(),
+            port: 1883,
+            client_id: String::new(),
+            topic_prefix: "tsukimi".to_string(),
+            keep_alive_secs: 30,
+            username: None,
+            password: None,
+        }
+    }
+}
+
+/// タスク間チャンネルの容量設定
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChannelConfig {
+    /// Bluetoothスキャナ → 転送タスクのmpscチャンネル容量
+    pub device_info_capacity: usize,
+    /// 転送タスク → 各タスクのbroadcastチャンネル容量
+    pub broadcast_capacity: usize,
+    /// サウンド設定・SE再生リクエストのmpscチャンネル容量
+    pub command_capacity: usize,
+}
+
+impl Default for ChannelConfig {
+    fn default() -> Self {
+        Self {
+            device_info_capacity: 256,
+            broadcast_capacity: 1024,
+            command_capacity: 32,
+        }
+    }
+}
+
+/// 転送タスクの設定
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ForwardingConfig {
+    /// DeviceSnapshotを配信する周期（ミリ秒）
+    pub snapshot_interval_ms: u64,
+}
+
+impl Default for ForwardingConfig {
+    fn default() -> Self {
+        // 10Hz
+        Self { snapshot_interval_ms: 100 }
+    }
+}
+
+/// gRPCメッセージの圧縮方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UploadCompression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+/// サーバーへのデバイス情報アップロードの設定
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UploadConfig {
+    /// 1リクエストにまとめるスナップショットの最大数
+    pub batch_size: usize,
+    /// バッチを送信するまでの最大待ち時間（ミリ秒）
+    pub batch_interval_ms: u64,
+    /// 送信時の圧縮方式（サーバー側が対応している必要がある）
+    pub compression: UploadCompression,
+    pub adaptive: AdaptiveUploadConfig,
+}
+
+impl Default for UploadConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: 10,
+            batch_interval_ms: 500,
+            compression: UploadCompression::None,
+            adaptive: AdaptiveUploadConfig::default(),
+        }
+    }
+}
+
+/// 送信量に応じてバッチの間隔を広げる設定（会場のLTE回線は従量課金のため）
+///
+/// サーバーからのアップロード抑制イベント（UploadThrottle）は、無効でも常に従う。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdaptiveUploadConfig {
+    pub enabled: bool,
+    /// 1秒あたりのデバイス情報の更新数がこれを超えたら、超えた割合で間隔を広げる
+    pub busy_updates_per_sec: f64,
+    /// 広げた間隔の上限（ミリ秒。サーバーからの抑制はこれを超えてもよい）
+    pub max_batch_interval_ms: u64,
+    /// サーバーからの抑制で期間が指定されなかった場合に抑制を続ける時間（ミリ秒）
+    pub throttle_duration_ms: u64,
+}
+
+impl Default for AdaptiveUploadConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            busy_updates_per_sec: 20.0,
+            max_batch_interval_ms: 5000,
+            throttle_duration_ms: 60000,
+        }
+    }
+}
+
+/// ユニット間のLANピア同期の設定
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PeerConfig {
+    pub enabled: bool,
+    /// 状態を交換するUDPマルチキャストのグループとポート
+    pub multicast_addr: String,
+    /// 自ユニットの状態を送信する間隔（ミリ秒）
+    pub announce_interval_ms: u64,
+    /// バックエンドとの時刻同期がこの時間途絶えたらピアの時刻を使う（ミリ秒）
+    pub backend_stale_ms: u64,
+    /// 同じ来場者を複数のユニットが受信したときに、どのユニットが受け持つかを決める方式
+    ///
+    /// `off` 以外では来場者の広告のRSSIをピアと共有し、受け持つ来場者だけをインタラクションに紐づける。
+    pub visitor_fusion: VisitorFusionStrategy,
+    /// `sticky` で担当を移すのに必要な、担当ユニットとのRSSIの差（dB）
+    pub visitor_handover_db: i16,
+    /// ユニットが受信した来場者のRSSIを有効とみなす時間（ミリ秒。`announce_interval_ms` の2倍以上にする）
+    pub visitor_reading_ttl_ms: u64,
+}
+
+impl Default for PeerConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            multicast_addr: "239.255.42.99:45454".to_string(),
+            announce_interval_ms: 1000,
+            backend_stale_ms: 15000,
+            visitor_fusion: VisitorFusionStrategy::Off,
+            visitor_handover_db: 4,
+            visitor_reading_ttl_ms: 3000,
+        }
+    }
+}
+
+/// 来場者を受け持つユニットの選び方
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VisitorFusionStrategy {
+    /// RSSIを共有せず、各ユニットが最も近い来場者をそれぞれ紐づける
+    #[default]
+    Off,
+    /// 最もRSSIが強いユニットが受け持つ
+    StrongestRssi,
+    /// 担当ユニットより `visitor_handover_db` 以上強く受信したユニットが現れるまで担当を変えない
+    Sticky,
+}
+
+/// ロケーションへの接近（インタラクション）の判定設定
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InteractionConfig {
+    /// 平滑化したRSSIがこの値を上回っている間を「接近中」とみなす
+    pub rssi_threshold: i16,
+    /// 平滑化したRSSIがこの値を下回ったら離れたとみなし、再びインタラクションできるようにする
+    pub rearm_rssi: i16,
+    /// 接近中の状態がこの時間続いたらインタラクションする（ミリ秒）
+    pub dwell_ms: u64,
+    /// RSSIの指数移動平均の係数（0〜1、大きいほど最新の値を重視）
+    pub smoothing_alpha: f64,
+    /// 閾値をまたいだときのRSSIの傾きがこの値（dB/秒）を上回る場合のみ「近づいてきた」とみなす
+    pub approach_min_slope: f64,
+    /// インタラクションした来場者が離れたときに鳴らすSE（未設定なら鳴らさない）
+    pub goodbye_se: Option<String>,
+    /// ポイントが変化したときに鳴らすSE（合計ポイントや増減に応じて切り替える）
+    pub point_se: PointSeConfig,
+    /// place_typeごとに呼び出すバックエンドのAPI（未設定のplace_typeはポイント加算のAPIを呼ぶ）
+    pub actions: BTreeMap<String, InteractionAction>,
+}
+
+impl Default for InteractionConfig {
+    fn default() -> Self {
+        Self {
+            rssi_threshold: -45,
+            rearm_rssi: -60,
+            dwell_ms: 2000,
+            smoothing_alpha: 0.3,
+            approach_min_slope: 0.0,
+            goodbye_se: None,
+            point_se: PointSeConfig::default(),
+            actions: BTreeMap::new(),
+        }
+    }
+}
+
+/// ポイントが変化したときに鳴らすSE（PointUpdateで合計の増減に応じて選ぶ）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PointSeConfig {
+    /// どの閾値にも達しなかった場合のSE
+    pub default: String,
+    /// 合計が閾値に達したときに代わりに鳴らすSE（例: 5ポイントでファンファーレ、最大ポイントで完了のジングル）
+    pub thresholds: Vec<PointSeThreshold>,
+    /// ポイントが減ったときに鳴らすSE（未設定なら鳴らさない）
+    pub decrease: Option<String>,
+    /// ポイントが0にリセットされたときに鳴らすSE（未設定なら鳴らさない）
+    pub reset: Option<String>,
+}
+
+impl Default for PointSeConfig {
+    fn default() -> Self {
+        Self { default: "se-point.mp3".to_string(), thresholds: Vec::new(), decrease: None, reset: None }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PointSeThreshold {
+    pub points: i32,
+    pub file: String,
+}
+
+/// インタラクションしたときに呼び出すAPIの定義
+///
+/// `path` と `body` には `${user_id}`・`${place_type}`・`${address}`・`${visitor_token}` を埋め込める
+/// （Webhookのペイロードと同じ書式）。値が `null` になったボディのフィールドは送信しない。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InteractionAction {
+    /// HTTPメソッド（`POST`・`PUT` など）
+    pub method: String,
+    /// プレイヤーAPIのURLからの相対パス
+    pub path: String,
+    /// 送信するJSON（未設定ならボディなしで呼び出す）
+    pub body: Option<serde_json::Value>,
+    /// 成功とみなすHTTPステータス（空なら2xxすべて）
+    pub expected_status: Vec<u16>,
+    /// レスポンスのJSONの `success` が `true` であることも確かめる
+    pub check_success_field: bool,
+}
+
+impl Default for InteractionAction {
+    fn default() -> Self {
+        Self {
+            method: "POST".to_string(),
+            path: "${user_id}/increment".to_string(),
+            body: Some(serde_json::json!({
+                "location_type": "${place_type}",
+                "visitor_token": "${visitor_token}",
+            })),
+            expected_status: Vec::new(),
+            check_success_field: false,
+        }
+    }
+}
+
+/// ローカルイベントのWebhook通知の設定（会場の照明コントローラなどと連動させるため）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebhookConfig {
+    /// 通知先の一覧（空ならWebhookタスクを起動しない）
+    pub targets: Vec<WebhookTarget>,
+    /// 1回の送信のタイムアウト（ミリ秒）
+    pub timeout_ms: u64,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            targets: Vec::new(),
+            timeout_ms: 3000,
+        }
+    }
+}
+
+/// Webhookの通知先1つの設定
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookTarget {
+    /// POST先のURL
+    pub url: String,
+    /// 通知するイベント名（`sound_switched` など。空ならすべて）
+    #[serde(default)]
+    pub events: Vec<String>,
+    /// 送信するJSON（`${sound}` のようにイベントのフィールドを埋め込める。未設定ならイベントをそのまま送る）
+    #[serde(default)]
+    pub payload: Option<serde_json::Value>,
+}
+
+/// ショーコントロール（QLab・照明卓など）向けのOSC出力の設定
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OscConfig {
+    pub enabled: bool,
+    /// OSCメッセージの送信先
+    pub host: String,
+    pub port: u16,
+    /// アドレスの `{unit}` に埋め込むユニット名
+    pub unit: String,
+    /// 各メッセージのアドレス（`{unit}` はユニット名に置き換える）
+    pub addresses: OscAddresses,
+    /// 再生位置を送る間隔（ミリ秒）
+    pub position_interval_ms: u64,
+    /// 楽曲のテンポ（設定すると再生位置から拍を計算して送る）
+    pub bpm: Option<f64>,
+}
+
+impl Default for OscConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            host: "127.0.0.1".to_string(),
+            // QLabの既定の受信ポート
+            port: 53000,
+            unit: "speaker".to_string(),
+            addresses: OscAddresses::default(),
+            position_interval_ms: 100,
+            bpm: None,
+        }
+    }
+}
+
+/// OSCメッセージのアドレスのテンプレート
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct OscAddresses {
+    /// BGM切り替え（引数: サウンドファイル名）
+    pub bgm: String,
+    /// SE再生（引数: SEファイル名）
+    pub se: String,
+    /// 再生位置（引数: 秒数のfloat）
+    pub position: String,
+    /// 拍（引数: 曲頭からの拍番号のint）
+    pub beat: String,
+}
+
+impl Default for OscAddresses {
+    fn default() -> Self {
+        Self {
+            bgm: "/tsukimi/{unit}/bgm".to_string(),
+            se: "/tsukimi/{unit}/se".to_string(),
+            position: "/tsukimi/{unit}/position".to_string(),
+            beat: "/tsukimi/{unit}/beat".to_string(),
+        }
+    }
+}
+
+/// MIDIタイムコード・クロック出力の設定（`midi` フィーチャー有効時のみ使用）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MidiConfig {
+    pub enabled: bool,
+    /// 作成するALSAの仮想MIDIポート名
+    pub port_name: String,
+    /// 出力する同期信号の種類
+    pub mode: MidiSyncMode,
+    /// MTCのフレームレート（24・25・30のいずれか）
+    pub fps: u8,
+    /// MIDIクロックのテンポ
+    pub bpm: f64,
+}
+
+impl Default for MidiConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port_name: "tsukimi-speaker".to_string(),
+            mode: MidiSyncMode::Mtc,
+            fps: 25,
+            bpm: 120.0,
+        }
+    }
+}
+
+/// MIDIで出力する同期信号の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MidiSyncMode {
+    /// MIDIタイムコード（クォーターフレーム）
+    #[default]
+    Mtc,
+    /// MIDIクロック（24PPQN）とソングポジションポインタ
+    Clock,
+}
+
+/// 音源ごとのキューリストによるDMX照明制御の設定
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DmxConfig {
+    pub enabled: bool,
+    pub protocol: DmxProtocol,
+    /// 送信先のホスト（未設定ならArt-Netはブロードキャスト、sACNはユニバースごとのマルチキャスト）
+    pub target: Option<String>,
+    /// sACNの送信元名
+    pub source_name: String,
+    /// キューの通過を確認する間隔（ミリ秒）
+    pub tick_ms: u64,
+    /// 変化がなくても全ユニバースを送り直す間隔（ミリ秒）
+    pub refresh_ms: u64,
+}
+
+impl Default for DmxConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            protocol: DmxProtocol::ArtNet,
+            target: None,
+            source_name: "tsukimi-speaker".to_string(),
+            tick_ms: 20,
+            refresh_ms: 1000,
+        }
+    }
+}
+
+/// DMXを送るプロトコル
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DmxProtocol {
+    #[default]
+    #[serde(rename = "artnet")]
+    ArtNet,
+    /// E1.31
+    Sacn,
+}
+
+/// ローカルHTTP API・ダッシュボードの設定
+///
+/// 認証がないため、会場のLANなど信頼できるネットワークでのみ有効にすること。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ApiConfig {
+    pub enabled: bool,
+    /// 待ち受けるアドレスとポート
+    pub bind: String,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bind: "0.0.0.0:8080".to_string(),
+        }
+    }
+}
+
+/// ログの集約先への転送の設定
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogShippingConfig {
+    pub enabled: bool,
+    pub sink: LogSink,
+    /// 送信先（Lokiの場合はpush APIのURL、TCPの場合は `host:port`）
+    pub endpoint: String,
+    /// ログに付けるユニットID（`unit.id` が未設定の場合のみ使う。互換性のため残している）
+    pub unit_id: Option<String>,
+    /// 送信できるまで手元に溜めておく最大件数（超えたら古いものから捨てる）
+    pub buffer_capacity: usize,
+    /// 1回の送信にまとめる最大件数
+    pub batch_size: usize,
+    /// 送信間隔（ミリ秒）
+    pub flush_interval_ms: u64,
+    /// 送信に失敗したときの再試行間隔の上限（ミリ秒）
+    pub max_retry_interval_ms: u64,
+}
+
+impl Default for LogShippingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            sink: LogSink::Loki,
+            endpoint: "http://localhost:3100/loki/api/v1/push".to_string(),
+            unit_id: None,
+            buffer_capacity: 10000,
+            batch_size: 500,
+            flush_interval_ms: 2000,
+            max_retry_interval_ms: 60000,
+        }
+    }
+}
+
+/// ログの送信方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogSink {
+    /// Grafana Lokiのpush API（JSON）
+    #[default]
+    Loki,
+    /// 1行1レコードのJSONをTCPで送る
+    Tcp,
+}
+
+/// パニック時のクラッシュレポートの設定
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CrashReportConfig {
+    /// クラッシュレポート（`.json`）とミニダンプ（`.dmp`）を書き出すディレクトリ（作業ディレクトリからの相対パス）
+    pub dir: String,
+    /// 次回起動時に未送信のレポートとミニダンプをPOSTするURL（未設定なら送らない）
+    pub upload_url: Option<String>,
+}
+
+impl Default for CrashReportConfig {
+    fn default() -> Self {
+        Self {
+            dir: "crashes".to_string(),
+            upload_url: None,
+        }
+    }
+}
+
+/// 設置確認用のチャイム設定（モニターを繋がずに動作を耳で確認するため）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChimeConfig {
+    /// 起動時にSEとして鳴らす音源ファイル（未設定なら鳴らさない）
+    pub boot: Option<String>,
+    /// 起動後はじめてサーバーに接続できたときに鳴らす音源ファイル
+    pub connected: Option<String>,
+    /// サーバーとの接続が切れた後、再び接続できたときに鳴らす音源ファイル
+    pub reconnected: Option<String>,
+    /// 再接続の音源に掛ける倍率（来場者に気づかれにくいよう控えめにする）
+    pub reconnected_gain: f64,
+    /// 致命的な異常（アダプタ・出力・音源の欠如）をエラートーンで知らせる
+    pub error_tone: bool,
+}
+
+impl Default for ChimeConfig {
+    fn default() -> Self {
+        Self { boot: None, connected: None, reconnected: None, reconnected_gain: 0.3, error_tone: false }
+    }
+}
+
+/// BGM再生の設定
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlaybackConfig {
+    /// システム無効化時にパイプラインをどう扱うか
+    pub disable_mode: DisableMode,
+    /// `pause` で無効化が続いた場合に、パイプラインを破棄するまでの時間（ミリ秒）
+    pub idle_teardown_ms: u64,
+    pub activation_se: ActivationSeConfig,
+    pub se_output: SeOutputConfig,
+    pub mixer: MixerConfig,
+    pub remote: RemoteSourceConfig,
+    pub live: LiveStreamConfig,
+    /// このユニットで鳴らす音源の言語（`ja`・`en` など。`<名前>.<言語>.<拡張子>` のファイルがあればそちらを使う）
+    ///
+    /// サーバーから言語が指定された場合はそちらを優先する。
+    pub language: Option<String>,
+    /// place_typeごとのBGMの音質の調整（共鳴しやすい場所に置いたスピーカーなど）
+    pub presets: BTreeMap<String, AudioPreset>,
+    /// BGMの出力先（未設定なら各プラットフォームの既定の出力）
+    pub bgm_output: Option<SeSink>,
+    /// BGMを同時に鳴らす複数の出力先（メインのスピーカーと隠したサブウーファーなど。設定した場合は `bgm_output` より優先）
+    pub bgm_outputs: Vec<BgmOutput>,
+    /// このユニットのBGMをサーバー時刻から遅らせる時間（ミリ秒。客席から遠いスピーカーに揃えて、近いスピーカーを遅らせる）
+    ///
+    /// `bgm_outputs` を使う場合は、各出力先の `delay_ms` に加えて掛ける。
+    pub output_delay_ms: u64,
+    /// サーバー時刻とのずれの補正方式（Raspberry PiではSoundTouchがCPUの大半を使う）
+    pub drift_correction: DriftCorrection,
+    /// このずれ（ミリ秒）未満は補正せず等速で再生する（わずかなずれでテンポを変え続けないため）
+    ///
+    /// 時計自体の誤差がこれより大きい場合は誤差の範囲内も補正しない（ただし100msを超えるずれは補正する）。
+    pub drift_deadband_ms: u64,
+    /// 再生位置がこの時間（ミリ秒）以上止まった後に進み始めたら、出力先のサスペンドから復帰したとみなしてすぐに合わせ直す（0で無効）
+    pub sink_stall_ms: u64,
+    pub watchdog: AudioWatchdogConfig,
+    /// 終了時にBGM・SEをフェードアウトする時間（ミリ秒）
+    pub shutdown_fade_ms: u64,
+}
+
+impl Default for PlaybackConfig {
+    fn default() -> Self {
+        Self {
+            disable_mode: DisableMode::Teardown,
+            idle_teardown_ms: 10 * 60 * 1000,
+            activation_se: ActivationSeConfig::default(),
+            se_output: SeOutputConfig::default(),
+            mixer: MixerConfig::default(),
+            remote: RemoteSourceConfig::default(),
+            live: LiveStreamConfig::default(),
+            language: None,
+            presets: BTreeMap::new(),
+            bgm_output: None,
+            bgm_outputs: Vec::new(),
+            output_delay_ms: 0,
+            drift_correction: DriftCorrection::default(),
+            drift_deadband_ms: 50,
+            sink_stall_ms: 500,
+            watchdog: AudioWatchdogConfig::default(),
+            shutdown_fade_ms: 1500,
+        }
+    }
+}
+
+/// オーディオスレッドの監視の設定
+///
+/// パイプラインの構築・シークで数秒〜十数秒止まることがあるため、それより長くする。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioWatchdogConfig {
+    pub enabled: bool,
+    /// メインループの更新がこの時間（ミリ秒）途絶えたらパイプラインを破棄し、止まったスレッドを見捨ててオーディオループを再開する
+    pub stall_timeout_ms: u64,
+    /// 再開したループも含めて、この時間（ミリ秒）を過ぎても戻らなければ最後の手段としてプロセスを終了して再起動させる
+    pub restart_timeout_ms: u64,
+}
+
+impl Default for AudioWatchdogConfig {
+    fn default() -> Self {
+        Self { enabled: true, stall_timeout_ms: 30_000, restart_timeout_ms: 60_000 }
+    }
+}
+
+/// URLで指定された音源（`http(s)://`）の設定
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RemoteSourceConfig {
+    /// ダウンロードした音源を置くディレクトリ
+    pub cache_dir: String,
+    /// sound_mapを確認して未取得の音源をダウンロードする間隔（ミリ秒）
+    pub prefetch_interval_ms: u64,
+}
+
+impl Default for RemoteSourceConfig {
+    fn default() -> Self {
+        Self {
+            cache_dir: "cache/remote".to_string(),
+            prefetch_interval_ms: 30000,
+        }
+    }
+}
+
+/// BGMをそのロケーションの音源に切り替えたときに掛ける音質の調整
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioPreset {
+    /// BGMのバスの音量に対する倍率
+    pub gain: f64,
+    /// 10バンドのイコライザーのゲイン（dB、31Hz〜16kHz。足りないバンドは0）
+    pub eq: Vec<f64>,
+    /// 定位（-1.0が左、1.0が右）
+    pub pan: f64,
+}
+
+impl Default for AudioPreset {
+    fn default() -> Self {
+        Self { gain: 1.0, eq: Vec::new(), pan: 0.0 }
+    }
+}
+
+/// コントロールルームからのライブ配信（SRT・RTP/Opus）の受信設定
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LiveStreamConfig {
+    /// 受信バッファ（ミリ秒）。小さいほど遅延が減るが途切れやすくなる
+    pub latency_ms: u32,
+    /// RTPで受信するストリームのcaps
+    pub rtp_caps: String,
+}
+
+impl Default for LiveStreamConfig {
+    fn default() -> Self {
+        Self {
+            latency_ms: 200,
+            rtp_caps: "application/x-rtp,media=audio,encoding-name=OPUS,payload=96,clock-rate=48000".to_string(),
+        }
+    }
+}
+
+/// ミキサーのバスごとの設定
+///
+/// バスを記述する場合、省略した項目は `BusConfig` のデフォルト値（音量1.0）になる。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MixerConfig {
+    pub bgm: BusConfig,
+    /// 通常のSE
+    pub se: BusConfig,
+    /// 優先SE（サーバーからのアナウンス・チャイム）
+    pub announce: BusConfig,
+}
+
+impl Default for MixerConfig {
+    fn default() -> Self {
+        // SEはBGMより大きめに鳴らす
+        Self {
+            bgm: BusConfig::default(),
+            se: BusConfig { gain: 3.0, ..BusConfig::default() },
+            announce: BusConfig { gain: 3.0, ..BusConfig::default() },
+        }
+    }
+}
+
+/// ミキサーのバス
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MixBus {
+    Bgm,
+    Se,
+    Announce,
+}
+
+/// ミキサーのバス1つの設定
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BusConfig {
+    /// マスター音量に掛ける倍率
+    pub gain: f64,
+    pub muted: bool,
+    /// このバスが鳴っている間、他のバスに掛ける倍率（例: `{"bgm": 0.3}`）
+    pub duck: BTreeMap<MixBus, f64>,
+}
+
+impl Default for BusConfig {
+    fn default() -> Self {
+        Self {
+            gain: 1.0,
+            muted: false,
+            duck: BTreeMap::new(),
+        }
+    }
+}
+
+/// SEごとの出力先の設定
+///
+/// 展示物の下に仕込んだ振動スピーカーなど、SEによって別の出力に鳴らしたい場合に使う。
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SeOutputConfig {
+    /// `routes` に無いSEの出力先
+    pub default: SeSink,
+    /// SEのファイル名（`se-point.mp3` など）ごとの出力先
+    pub routes: BTreeMap<String, SeSink>,
+}
+
+/// BGMを複数の出力先に鳴らす場合の1つの出力先
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BgmOutput {
+    pub output: SeSink,
+    /// この出力先だけに掛ける音量の倍率（ミキサー・マスター音量の後に掛ける）
+    pub gain: f64,
+    /// この出力先の遅延（ミリ秒。出力先ごとの遅延や、客席からの距離の違いを揃えるため）
+    pub delay_ms: u64,
+}
+
+impl Default for BgmOutput {
+    fn default() -> Self {
+        Self { output: SeSink::default(), gain: 1.0, delay_ms: 0 }
+    }
+}
+
+/// 音声の出力先
+///
+/// LinuxはPulseAudio・PipeWire、WindowsはWASAPI、macOSはCore Audioに出力する。
+/// 他の環境では既定の出力に鳴らす。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SeSink {
+    /// ストリームのメディアロール（`event`・`music` など）
+    ///
+    /// WASAPIでは `communication`・`phone` を通信、`music`・`video`・`game` をマルチメディア、それ以外をコンソールのロールにする。
+    pub role: String,
+    /// 出力先のデバイス（未設定ならロールに応じた既定の出力）
+    ///
+    /// Linuxは `pactl list short sinks` のシンク名、WindowsはWASAPIのデバイスID、macOSはCore AudioのデバイスID（数値）。
+    pub device: Option<String>,
+    /// WASAPIの排他モードで出力する（Windowsのみ。本番機と同じくミキサーを通さない遅延で確かめる用）
+    pub exclusive: bool,
+}
+
+impl Default for SeSink {
+    fn default() -> Self {
+        Self {
+            role: "event".to_string(),
+            device: None,
+            exclusive: false,
+        }
+    }
+}
+
+/// システム有効化時に鳴らすSEの設定
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ActivationSeConfig {
+    /// 有効化時に鳴らすかどうか（ボタン・ダッシュボードからの再生には影響しない）
+    pub enabled: bool,
+    pub file: String,
+    /// SEバスの音量に掛ける倍率
+    pub gain: f64,
+    /// SEが鳴り終わるまでBGMの開始を待つ（`pause` で保持したBGMの再開は待たない）
+    pub wait_for_finish: bool,
+}
+
+impl Default for ActivationSeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            file: "se-activation.mp3".to_string(),
+            gain: 1.0,
+            wait_for_finish: false,
+        }
+    }
+}
+
+/// システム無効化時のパイプラインの扱い
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DisableMode {
+    /// すべてのパイプラインを破棄し、再有効化時に作り直す
+    #[default]
+    Teardown,
+    /// BGMを一時停止・ミュートして保持し、再有効化時にすぐ再開する
+    Pause,
+}
+
+/// BGMのサーバー時刻からのずれを補正する方式
+///
+/// 方式ごとのCPU使用率は、メトリクスの `process_cpu_by_drift_correction`（補正方式のラベル付きのゲージ。
+/// 平均 `mean_percent` と測定回数 `samples`）に出る。同じ会場・音源で方式だけを変えて十分な時間動かし、
+/// 各方式の `mean_percent` を比べて選ぶ。配備先で `--benchmark-drift-correction` を付けて起動すると、
+/// 起動前に方式ごとの目安を測ってメトリクスの `drift_correction_benchmark` にも記録する。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DriftCorrection {
+    /// SoundTouchの `pitch` 要素のテンポで補正する（音程を保ったまま細かく追従するが、最も重い）
+    #[default]
+    Pitch,
+    /// 再生速度を変え、`scaletempo` 要素で音程を保つ（SoundTouchより軽い）
+    Scaletempo,
+    /// テンポは変えず、大きくずれたときのシークだけで補正する（最も軽い）
+    SeekOnly,
+}
+
+impl DriftCorrection {
+    pub const ALL: [DriftCorrection; 3] = [DriftCorrection::Pitch, DriftCorrection::Scaletempo, DriftCorrection::SeekOnly];
+}
+
+/// 時刻による番組（開場前・ショー・閉場・無音）の切り替えの設定
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScheduleConfig {
+    pub enabled: bool,
+    /// 会場の時刻のUTCからのずれ（分）。日本時間は540
+    pub utc_offset_minutes: i32,
+    /// 番組の切り替え（順不同。その日の最初の切り替え前は前日の最後の番組が続く）
+    pub entries: Vec<ScheduleEntry>,
+    pub sounds: ProgramSounds,
+}
+
+impl Default for ScheduleConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            utc_offset_minutes: 540,
+            entries: Vec::new(),
+            sounds: ProgramSounds::default(),
+        }
+    }
+}
+
+/// 番組の切り替え1件
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduleEntry {
+    /// 切り替える時刻（`HH:MM`、会場の時刻）
+    pub at: String,
+    pub program: ShowProgram,
+}
+
+/// 時刻で切り替える番組
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShowProgram {
+    /// 開場前の環境音
+    PreOpen,
+    /// 通常のショー（RSSIによるBGM選択）
+    Show,
+    /// 閉場の音楽
+    Closing,
+    /// BGMを鳴らさない
+    Silence,
+}
+
+/// 番組ごとに再生する音源（未設定の番組は無音）
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProgramSounds {
+    pub pre_open: Option<String>,
+    pub closing: Option<String>,
+}
+
+/// 会場ごとのルールを書いたスクリプトの設定（`scripting` フィーチャー有効時のみ使用）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScriptConfig {
+    pub enabled: bool,
+    /// Rhaiのスクリプトファイルのパス
+    pub path: String,
+    /// 1回のイベント処理で実行できる命令数の上限（無限ループで他の処理を止めないため）
+    pub max_operations: u64,
+}
+
+impl Default for ScriptConfig {
+    fn default() -> Self {
+        Self { enabled: false, path: "venue.rhai".to_string(), max_operations: 100_000 }
+    }
+}
+
+/// ビーコンのアドレスと、その近くで鳴らす音源
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LocationConfig {
+    pub address: String,
+    pub sound: String,
+}
+
+/// BLEのスキャンの代わりに、設定したビーコンを受信し続けたものとして扱う
+///
+/// `ble` フィーチャーが無いビルドでは常にこちらを使う。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SimulationConfig {
+    pub enabled: bool,
+    pub beacons: Vec<SimulatedBeacon>,
+    /// 受信したものとして流す間隔（ミリ秒）
+    pub interval_ms: u64,
+}
+
+impl Default for SimulationConfig {
+    fn default() -> Self {
+        Self { enabled: false, beacons: Vec::new(), interval_ms: 1000 }
+    }
+}
+
+/// ビーコンの電波の届き方を会場を歩いて調べる調査モード（`--survey` でも有効にできる）
+///
+/// sound_mapに無いビーコンも含めてRSSIを記録し、ビーコンの配置やしきい値の調整に使う。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SurveyConfig {
+    pub enabled: bool,
+    /// 記録（CSV）と集計（JSON）を書き出すディレクトリ（作業ディレクトリからの相対パス）
+    pub dir: String,
+    /// 集計を書き出し直す間隔（ミリ秒。終了時にも書き出す）
+    pub export_interval_ms: u64,
+}
+
+impl Default for SurveyConfig {
+    fn default() -> Self {
+        Self { enabled: false, dir: "survey".to_string(), export_interval_ms: 30_000 }
+    }
+}
+
+/// 終了手順の設定
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ShutdownConfig {
+    /// 各段階の完了を待つ上限（ミリ秒）。過ぎた場合は次の段階に進み、タスクは中断する
+    pub step_timeout_ms: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self { step_timeout_ms: 5000 }
+    }
+}
+
+/// シミュレーションのビーコン
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SimulatedBeacon {
+    pub address: String,
+    pub rssi: i16,
+}
+
+/// Raspberry PiのGPIOの設定（`gpio` フィーチャー有効時のみ使用）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GpioConfig {
+    pub enabled: bool,
+    /// ステータスLEDを接続したピン（BCM番号）
+    pub status_led_pin: u8,
+    /// サーバーとの時刻同期がこの時間途絶えたら「サーバーなし」と表示する（ミリ秒）
+    pub no_server_timeout_ms: u64,
+    /// ビーコンをこの時間受信しなければ「ビーコンなし」と表示する（ミリ秒）
+    pub no_beacon_timeout_ms: u64,
+    /// 物理ボタンの割り当て（プルアップで接続し、押下でLowになること）
+    pub buttons: Vec<ButtonConfig>,
+    /// ボタンのチャタリング除去時間（ミリ秒）
+    pub button_debounce_ms: u64,
+}
+
+impl Default for GpioConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            status_led_pin: 17,
+            no_server_timeout_ms: 15000,
+            no_beacon_timeout_ms: 10000,
+            buttons: Vec::new(),
+            button_debounce_ms: 50,
+        }
+    }
+}
+
+/// 物理ボタン1つの設定
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ButtonConfig {
+    /// ボタンを接続したピン（BCM番号）
+    pub pin: u8,
+    pub action: ButtonAction,
+}
+
+/// 物理ボタンに割り当てる操作
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ButtonAction {
+    /// マスター音量のミュートを切り替える
+    ToggleMute,
+    /// 自デバイスの有効化フラグを切り替える
+    ToggleEnabled,
+    /// 有効化SEをもう一度鳴らす
+    ReplayActivationSe,
+    /// BLEスキャンをやり直す
+    ForceRescan,
+}
+
+impl Config {
+    /// 設定ファイルのパスを決定する（環境変数 `TSUKIMI_CONFIG` があれば優先）
+    pub fn path() -> PathBuf {
+        std::env::var_os(CONFIG_PATH_ENV)
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_PATH))
+    }
+}
@@ -0,0 +1,300 @@
+use crate::config_system::config_main::Config;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::{error, info, warn};
+
+/// サーバーから指定された上書きを保存するファイル（作業ディレクトリからの相対パス）
+const SERVER_OVERLAY_PATH: &str = "tsukimi-config.server.json";
+
+/// ローカルAPIで変更した上書きを保存するファイル（作業ディレクトリからの相対パス）
+const LOCAL_OVERLAY_PATH: &str = "tsukimi-config.local.json";
+
+/// 実行中に変更できる設定の層（デフォルト値・設定ファイルより優先し、ローカルの変更が最も優先）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigLayer {
+    /// サーバーから指定された上書き
+    #[cfg(feature = "server")]
+    Server,
+    /// ローカルAPIでの変更
+    Local,
+}
+
+/// 下の層に上の層を重ねる（オブジェクトは項目ごとに重ね、それ以外は置き換える）
+///
+/// 設定ファイルの `null` は従来どおり値として扱う（上書きの層には `null` を残さない）。
+fn merge(base: &mut Value, layer: &Value) {
+    match (base, layer) {
+        (Value::Object(base), Value::Object(layer)) => {
+            for (key, value) in layer {
+                merge(base.entry(key.clone()).or_insert(Value::Null), value);
+            }
+        }
+        (base, layer) => *base = layer.clone(),
+    }
+}
+
+/// 上書きの層に変更を重ねる（JSON Merge Patch。`null` の項目は上書きをやめ、下の層の値に戻す）
+fn apply_patch(layer: &mut Value, patch: &Value) {
+    if let (Value::Object(layer), Value::Object(patch)) = (&mut *layer, patch) {
+        for (key, value) in patch {
+            match value {
+                Value::Null => {
+                    layer.remove(key);
+                }
+                Value::Object(_) => {
+                    let entry = layer.entry(key.clone()).or_insert_with(|| Value::Object(Map::new()));
+                    if !entry.is_object() {
+                        *entry = Value::Object(Map::new());
+                    }
+                    apply_patch(entry, value);
+                    if entry.as_object().is_some_and(Map::is_empty) {
+                        layer.remove(key);
+                    }
+                }
+                _ => {
+                    layer.insert(key.clone(), value.clone());
+                }
+            }
+        }
+    }
+}
+
+/// 上書きのファイルを読む（無い・壊れている場合は空の上書き）
+fn load_overlay(path: &Path) -> Value {
+    let overlay = std::fs::read_to_string(path).ok().and_then(|text| match serde_json::from_str::<Value>(&text) {
+        Ok(value) if value.is_object() => Some(value),
+        Ok(_) => {
+            warn!(path = %path.display(), "Config overlay is not a JSON object - ignoring");
+            None
+        }
+        Err(e) => {
+            warn!(path = %path.display(), "Failed to parse config overlay: {}", e);
+            None
+        }
+    });
+    overlay.unwrap_or_else(|| Value::Object(Map::new()))
+}
+
+fn save_overlay(path: &Path, overlay: &Value) -> Result<()> {
+    let tmp_path = path.with_extension("json.tmp");
+    std::fs::write(&tmp_path, serde_json::to_vec_pretty(overlay)?)
+        .with_context(|| format!("Failed to write config overlay: {}", tmp_path.display()))?;
+    std::fs::rename(&tmp_path, path).with_context(|| format!("Failed to replace config overlay: {}", path.display()))?;
+    Ok(())
+}
+
+#[derive(Clone)]
+struct Layers {
+    defaults: Value,
+    file: Value,
+    server: Value,
+    local: Value,
+}
+
+impl Layers {
+    fn effective_value(&self) -> Value {
+        let mut effective = self.defaults.clone();
+        for layer in [&self.file, &self.server, &self.local] {
+            merge(&mut effective, layer);
+        }
+        effective
+    }
+}
+
+/// `/api/config` のレスポンス
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigSnapshot {
+    /// すべての層を重ねた設定
+    pub effective: Value,
+    pub file: Value,
+    pub server: Value,
+    pub local: Value,
+}
+
+/// 層を重ねた設定
+///
+/// デフォルト値 < 設定ファイル < サーバーからの上書き < ローカルAPIでの変更 の順に重ねる。
+/// サーバー・ローカルAPIの上書きはそれぞれのファイルに保存し、再起動しても失われないようにする。
+/// 多くの項目は起動時にしか読まないため、上書きは次回の起動から効く。
+pub struct ConfigStore {
+    layers: Mutex<Layers>,
+    server_path: PathBuf,
+    local_path: PathBuf,
+}
+
+impl ConfigStore {
+    /// 設定ファイルと保存済みの上書きを読み込む（重ねた結果が不正になる上書きの層は無視する）
+    pub fn load() -> Arc<Self> {
+        Self::with_paths(&Config::path(), PathBuf::from(SERVER_OVERLAY_PATH), PathBuf::from(LOCAL_OVERLAY_PATH))
+    }
+
+    /// 設定ファイルと、サーバー・ローカルAPIの上書きを保存するファイルを指定して読み込む
+    pub fn with_paths(path: &Path, server_path: PathBuf, local_path: PathBuf) -> Arc<Self> {
+        let file = if path.exists() {
+            match std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read config file: {}", path.display()))
+                .and_then(|text| {
+                    let value = serde_json::from_str::<Value>(&text).with_context(|| format!("Failed to parse config file: {}", path.display()))?;
+                    // 型の誤りはここで検出し、デフォルト値で起動する
+                    serde_json::from_value::<Config>(value.clone()).with_context(|| format!("Invalid config file: {}", path.display()))?;
+                    Ok(value)
+                }) {
+                Ok(value) => {
+                    info!(path = %path.display(), "Config loaded");
+                    value
+                }
+                Err(e) => {
+                    error!("{:?} - using defaults", e);
+                    Value::Object(Map::new())
+                }
+            }
+        } else {
+            info!(path = %path.display(), "Config file not found, using defaults");
+            Value::Object(Map::new())
+        };
+
+        let mut layers = Layers {
+            defaults: serde_json::to_value(Config::default()).unwrap_or_default(),
+            file,
+            server: load_overlay(&server_path),
+            local: Value::Object(Map::new()),
+        };
+        // 下の層から順に確かめ、不正な結果になる層だけを無視する
+        if let Err(e) = serde_json::from_value::<Config>(layers.effective_value()) {
+            error!(path = %server_path.display(), "Server config overlay produces an invalid config, ignoring it: {}", e);
+            layers.server = Value::Object(Map::new());
+        }
+        layers.local = load_overlay(&local_path);
+        if let Err(e) = serde_json::from_value::<Config>(layers.effective_value()) {
+            error!(path = %local_path.display(), "Local config overlay produces an invalid config, ignoring it: {}", e);
+            layers.local = Value::Object(Map::new());
+        }
+        info!(server = %layers.server, local = %layers.local, "Loaded config overlays");
+        Arc::new(Self { layers: Mutex::new(layers), server_path, local_path })
+    }
+
+    /// すべての層を重ねた設定
+    pub fn effective(&self) -> Config {
+        // 層を変更するときに検証しているため、ここで失敗することはない
+        serde_json::from_value(self.effective_value()).unwrap_or_default()
+    }
+
+    /// すべての層を重ねた設定（JSON。状態API用）
+    pub fn effective_value(&self) -> Value {
+        self.layers.lock().unwrap().effective_value()
+    }
+
+    pub fn snapshot(&self) -> ConfigSnapshot {
+        let layers = self.layers.lock().unwrap();
+        ConfigSnapshot {
+            effective: layers.effective_value(),
+            file: layers.file.clone(),
+            server: layers.server.clone(),
+            local: layers.local.clone(),
+        }
+    }
+
+    /// サーバー・ローカルAPIの上書きを変更して保存する（`null` の項目は上書きをやめる）
+    ///
+    /// 重ねた結果が設定として不正な場合は変更しない。
+    pub fn update(&self, layer: ConfigLayer, patch: &Value) -> Result<()> {
+        anyhow::ensure!(patch.is_object(), "Config override must be a JSON object");
+        let mut layers = self.layers.lock().unwrap();
+        let mut candidate = layers.clone();
+        let (overlay, path) = match layer {
+            #[cfg(feature = "server")]
+            ConfigLayer::Server => (&mut candidate.server, &self.server_path),
+            ConfigLayer::Local => (&mut candidate.local, &self.local_path),
+        };
+        apply_patch(overlay, patch);
+        let overlay = overlay.clone();
+        serde_json::from_value::<Config>(candidate.effective_value()).context("Config override produces an invalid config")?;
+        save_overlay(path, &overlay)?;
+        info!(?layer, %patch, "Config override updated (takes effect on next start)");
+        *layers = candidate;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// テストごとの作業ディレクトリ（設定ファイルと上書きのファイルを置く）
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tsukimi-config-store-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn store(dir: &Path) -> Arc<ConfigStore> {
+        ConfigStore::with_paths(&dir.join("config.json"), dir.join("server.json"), dir.join("local.json"))
+    }
+
+    fn write(dir: &Path, name: &str, value: Value) {
+        std::fs::write(dir.join(name), value.to_string()).unwrap();
+    }
+
+    #[test]
+    fn merge_combines_objects_and_replaces_other_values() {
+        let mut base = json!({ "unit": { "venue": "hall", "zone": "a" }, "locations": [1, 2], "x": null });
+        merge(&mut base, &json!({ "unit": { "zone": "b" }, "locations": [3], "x": { "y": 1 } }));
+        assert_eq!(base, json!({ "unit": { "venue": "hall", "zone": "b" }, "locations": [3], "x": { "y": 1 } }));
+    }
+
+    #[test]
+    fn apply_patch_follows_json_merge_patch() {
+        let mut layer = json!({ "unit": { "venue": "hall", "zone": "a" }, "playback": 1, "api": { "port": 1 } });
+        apply_patch(&mut layer, &json!({ "unit": { "zone": null }, "playback": { "language": "en" }, "api": { "port": null }, "new": null }));
+        // `null` の項目は消え、空になったオブジェクトも残さない。オブジェクト以外の値はオブジェクトに置き換える
+        assert_eq!(layer, json!({ "unit": { "venue": "hall" }, "playback": { "language": "en" } }));
+    }
+
+    #[test]
+    fn local_overrides_server_overrides_file() {
+        let dir = dir("precedence");
+        write(&dir, "config.json", json!({ "unit": { "venue": "file", "zone": "file", "id": "file" } }));
+        write(&dir, "server.json", json!({ "unit": { "zone": "server", "id": "server" } }));
+        write(&dir, "local.json", json!({ "unit": { "id": "local" } }));
+        let unit = store(&dir).effective().unit;
+        assert_eq!(unit.venue.as_deref(), Some("file"));
+        assert_eq!(unit.zone.as_deref(), Some("server"));
+        assert_eq!(unit.id.as_deref(), Some("local"));
+    }
+
+    #[test]
+    fn only_the_invalid_overlay_is_ignored() {
+        let invalid_server = dir("invalid-server");
+        write(&invalid_server, "server.json", json!({ "playback": { "idle_teardown_ms": "soon" } }));
+        write(&invalid_server, "local.json", json!({ "unit": { "id": "local" } }));
+        let snapshot = store(&invalid_server).snapshot();
+        assert_eq!(snapshot.server, json!({}));
+        assert_eq!(snapshot.local, json!({ "unit": { "id": "local" } }));
+
+        let invalid_local = dir("invalid-local");
+        write(&invalid_local, "server.json", json!({ "unit": { "id": "server" } }));
+        write(&invalid_local, "local.json", json!({ "playback": { "idle_teardown_ms": "soon" } }));
+        let snapshot = store(&invalid_local).snapshot();
+        assert_eq!(snapshot.server, json!({ "unit": { "id": "server" } }));
+        assert_eq!(snapshot.local, json!({}));
+    }
+
+    #[test]
+    fn updates_are_validated_persisted_and_removable() {
+        let dir = dir("update");
+        write(&dir, "config.json", json!({ "unit": { "id": "file" } }));
+        let config = store(&dir);
+        assert!(config.update(ConfigLayer::Local, &json!({ "playback": { "idle_teardown_ms": "soon" } })).is_err());
+        config.update(ConfigLayer::Local, &json!({ "unit": { "id": "local" } })).unwrap();
+        assert_eq!(store(&dir).effective().unit.id.as_deref(), Some("local"));
+
+        config.update(ConfigLayer::Local, &json!({ "unit": { "id": null } })).unwrap();
+        assert_eq!(config.snapshot().local, json!({}));
+        assert_eq!(store(&dir).effective().unit.id.as_deref(), Some("file"));
+    }
+}
@@ -0,0 +1,79 @@
+use crate::config_system::config_main::Config;
+use serde::Serialize;
+use std::sync::OnceLock;
+use tracing::{info, warn, Span};
+
+static UNIT_IDENTITY: OnceLock<UnitIdentity> = OnceLock::new();
+
+/// ハードウェアのリビジョンが未設定の場合に読むモデル名（Raspberry Piなど、デバイスツリーのあるボード）
+const DEVICE_TREE_MODEL_PATH: &str = "/proc/device-tree/model";
+
+/// 設定と実行環境から決めたユニットの識別情報
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UnitIdentity {
+    pub unit_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub venue: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub zone: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hardware_revision: Option<String>,
+}
+
+impl UnitIdentity {
+    fn resolve(config: &Config) -> Self {
+        let non_empty = |value: &Option<String>| value.as_deref().map(str::trim).filter(|value| !value.is_empty()).map(str::to_string);
+        let unit_id = non_empty(&config.unit.id)
+            .or_else(|| non_empty(&config.log_shipping.unit_id))
+            .or_else(sysinfo::System::host_name)
+            .unwrap_or_else(|| format!("pid-{}", std::process::id()));
+        let hardware_revision = non_empty(&config.unit.hardware_revision).or_else(|| {
+            std::fs::read_to_string(DEVICE_TREE_MODEL_PATH)
+                .ok()
+                .map(|model| model.trim_end_matches('\0').trim().to_string())
+                .filter(|model| !model.is_empty())
+        });
+        Self { unit_id, venue: non_empty(&config.unit.venue), zone: non_empty(&config.unit.zone), hardware_revision }
+    }
+
+    /// HTTPのリクエストに付けるヘッダー（設定されている項目のみ）
+    pub fn headers(&self) -> Vec<(&'static str, &str)> {
+        [
+            ("X-Tsukimi-Unit-Id", Some(self.unit_id.as_str())),
+            ("X-Tsukimi-Venue", self.venue.as_deref()),
+            ("X-Tsukimi-Zone", self.zone.as_deref()),
+            ("X-Tsukimi-Hardware-Revision", self.hardware_revision.as_deref()),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| value.map(|value| (name, value)))
+        .collect()
+    }
+
+    /// スパンのフィールドに記録する（スパンは `unit_id`・`venue`・`zone`・`hardware_revision` を宣言しておく）
+    pub fn record(&self, span: &Span) {
+        span.record("unit_id", self.unit_id.as_str());
+        if let Some(venue) = &self.venue {
+            span.record("venue", venue.as_str());
+        }
+        if let Some(zone) = &self.zone {
+            span.record("zone", zone.as_str());
+        }
+        if let Some(hardware_revision) = &self.hardware_revision {
+            span.record("hardware_revision", hardware_revision.as_str());
+        }
+    }
+}
+
+/// 設定からユニットの識別情報を決める（起動時、設定を読み込んだ直後に1回だけ呼ぶ）
+pub fn init(config: &Config) {
+    let identity = UnitIdentity::resolve(config);
+    info!(?identity, "Unit identity");
+    if UNIT_IDENTITY.set(identity).is_err() {
+        warn!("Unit identity is already initialized - ignoring");
+    }
+}
+
+/// ユニットの識別情報（初期化前は既定の設定から決める）
+pub fn unit_identity() -> &'static UnitIdentity {
+    UNIT_IDENTITY.get_or_init(|| UnitIdentity::resolve(&Config::default()))
+}
@@ -1 +1,40 @@
-pub mod connect_main;
\ No newline at end of file
+#[cfg(feature = "server")]
+pub mod calibration;
+#[cfg(feature = "server")]
+pub mod connect_main;
+pub mod enable_state;
+#[cfg(feature = "server")]
+pub mod fake_server;
+#[cfg(feature = "server")]
+pub mod fleet_command;
+#[cfg(feature = "server")]
+pub mod interaction;
+#[cfg(feature = "server")]
+pub mod interaction_action;
+#[cfg(feature = "server")]
+pub mod interaction_detector;
+pub mod location_context;
+#[cfg(feature = "server")]
+pub mod location_validation;
+#[cfg(feature = "server")]
+pub mod mqtt_transport;
+#[cfg(feature = "server")]
+pub mod points_cache;
+#[cfg(feature = "server")]
+pub mod reconnect_backoff;
+#[cfg(feature = "server")]
+pub mod se_rate_limit;
+pub mod server_link;
+#[cfg(feature = "server")]
+pub mod server_messages;
+#[cfg(feature = "server")]
+pub mod session_recording;
+#[cfg(feature = "server")]
+pub mod sound_catalog;
+pub mod sound_map;
+#[cfg(feature = "server")]
+pub mod transport;
+#[cfg(feature = "server")]
+pub mod upload_rate;
+#[cfg(feature = "server")]
+pub mod websocket_transport;
\ No newline at end of file
@@ -0,0 +1,318 @@
+use crate::bluetooth_system::address::Address;
+use crate::config_system::config_main::InteractionConfig;
+use crate::connect_system::sound_map::SharedSoundMap;
+use crate::messages::DeviceSnapshot;
+use anyhow::{bail, Context, Result};
+use arc_swap::ArcSwap;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+use tracing::{info, warn};
+
+/// ビーコンごとの閾値を保存するファイル（作業ディレクトリからの相対パス）
+pub const DEFAULT_CALIBRATION_PATH: &str = "tsukimi-calibration.json";
+
+/// キャリブレーションでRSSIを集める時間の既定値
+pub const DEFAULT_SAMPLE_DURATION: Duration = Duration::from_secs(10);
+
+/// キャリブレーションでRSSIを集める時間の上限
+pub const MAX_SAMPLE_DURATION: Duration = Duration::from_secs(60);
+
+/// 閾値を決めるのに必要な最少のサンプル数
+const MIN_SAMPLES: usize = 10;
+
+/// インタラクションする位置で測ったRSSIの下位10%から、さらにこの分だけ閾値を下げる（dB）
+const THRESHOLD_MARGIN_DB: i16 = 2;
+
+/// 閾値と再アーム閾値の差（dB）の範囲。測ったRSSIのばらつきが大きいほど広げる
+const MIN_HYSTERESIS_DB: i16 = 6;
+const MAX_HYSTERESIS_DB: i16 = 20;
+
+/// ビーコン1つのインタラクションの閾値
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BeaconThreshold {
+    /// 平滑化したRSSIがこの値を上回っている間を「接近中」とみなす
+    pub rssi_threshold: i16,
+    /// 平滑化したRSSIがこの値を下回ったら離れたとみなす
+    pub rearm_rssi: i16,
+}
+
+impl From<&InteractionConfig> for BeaconThreshold {
+    fn from(config: &InteractionConfig) -> Self {
+        Self { rssi_threshold: config.rssi_threshold, rearm_rssi: config.rearm_rssi }
+    }
+}
+
+/// 保存ファイルの1件
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CalibratedBeacon {
+    pub address: String,
+    #[serde(flatten)]
+    pub threshold: BeaconThreshold,
+    /// 測ったRSSIのサンプル数と中央値
+    pub samples: usize,
+    pub median_rssi: i16,
+    /// キャリブレーションした時刻（UNIXミリ秒）
+    pub calibrated_at_ms: u64,
+}
+
+/// キャリブレーションで決めたビーコンごとの閾値（設定の `interaction` の閾値より優先する）
+///
+/// インタラクション検知は閾値を判定のたびに読むため、キャリブレーションの結果は再起動せずに反映される。
+pub struct CalibrationStore {
+    path: PathBuf,
+    beacons: ArcSwap<HashMap<Address, CalibratedBeacon>>,
+    /// 同時に1つのキャリブレーションだけを受け付ける
+    running: tokio::sync::Mutex<()>,
+}
+
+impl CalibrationStore {
+    /// 保存済みの閾値を読み込む（無い・壊れている場合は空から始める）
+    pub fn load(path: &Path) -> Arc<Self> {
+        let entries = std::fs::read_to_string(path)
+            .ok()
+            .and_then(|text| match serde_json::from_str::<Vec<CalibratedBeacon>>(&text) {
+                Ok(entries) => Some(entries),
+                Err(e) => {
+                    warn!(path = %path.display(), "Failed to parse calibration: {}", e);
+                    None
+                }
+            })
+            .unwrap_or_default();
+        let beacons: HashMap<Address, CalibratedBeacon> = entries
+            .into_iter()
+            .filter_map(|entry| match entry.address.parse::<Address>() {
+                Ok(address) => Some((address, entry)),
+                Err(e) => {
+                    warn!("Ignoring calibrated beacon: {}", e);
+                    None
+                }
+            })
+            .collect();
+        info!(path = %path.display(), beacons = beacons.len(), "Loaded interaction calibration");
+        Arc::new(Self { path: path.to_path_buf(), beacons: ArcSwap::from_pointee(beacons), running: tokio::sync::Mutex::new(()) })
+    }
+
+    /// ビーコンの閾値（キャリブレーションしていなければ設定の値）
+    pub fn threshold(&self, address: &str, config: &InteractionConfig) -> BeaconThreshold {
+        let Ok(address) = address.parse::<Address>() else {
+            return BeaconThreshold::from(config);
+        };
+        self.beacons.load().get(&address).map_or_else(|| BeaconThreshold::from(config), |beacon| beacon.threshold)
+    }
+
+    /// キャリブレーションしたビーコンの一覧（アドレス順）
+    pub fn beacons(&self) -> Vec<CalibratedBeacon> {
+        let beacons = self.beacons.load();
+        let sorted: BTreeMap<&Address, &CalibratedBeacon> = beacons.iter().collect();
+        sorted.into_values().cloned().collect()
+    }
+
+    /// 閾値を反映して保存する（書きかけのファイルが残らないよう一時ファイルから置き換える）
+    async fn store(&self, address: Address, beacon: CalibratedBeacon) -> Result<()> {
+        let mut beacons = HashMap::clone(&self.beacons.load());
+        beacons.insert(address, beacon);
+        let sorted: BTreeMap<&Address, &CalibratedBeacon> = beacons.iter().collect();
+        let bytes = serde_json::to_vec_pretty(&sorted.into_values().collect::<Vec<_>>())?;
+        let tmp_path = self.path.with_extension("json.tmp");
+        tokio::fs::write(&tmp_path, bytes)
+            .await
+            .with_context(|| format!("Failed to write calibration: {}", tmp_path.display()))?;
+        tokio::fs::rename(&tmp_path, &self.path)
+            .await
+            .with_context(|| format!("Failed to replace calibration: {}", self.path.display()))?;
+        self.beacons.store(Arc::new(beacons));
+        Ok(())
+    }
+
+    /// インタラクションする位置でRSSIを `duration` の間集め、ビーコンの閾値を決めて保存する
+    ///
+    /// `address` を省略した場合は、インタラクションできる場所（無ければsound_mapのロケーション）のうち
+    /// 測った間のRSSIの中央値が最も強いビーコンを対象にする。
+    pub async fn calibrate(
+        &self,
+        mut rx: broadcast::Receiver<Arc<DeviceSnapshot>>,
+        sound_map: &SharedSoundMap,
+        address: Option<Address>,
+        duration: Duration,
+    ) -> Result<CalibratedBeacon> {
+        let Ok(_running) = self.running.try_lock() else {
+            bail!("Calibration is already running");
+        };
+        info!(?address, duration_ms = duration.as_millis() as u64, "Calibrating interaction threshold - stay at the interaction point");
+
+        let mut samples: HashMap<Address, Vec<i16>> = HashMap::new();
+        let deadline = tokio::time::sleep(duration);
+        tokio::pin!(deadline);
+        loop {
+            tokio::select! {
+                _ = &mut deadline => break,
+                result = rx.recv() => match result {
+                    Ok(snapshot) => {
+                        for device in snapshot.devices.iter().filter(|device| device.visitor_token.is_none()) {
+                            samples.entry(device.address.clone()).or_default().push(device.rssi);
+                        }
+                    }
+                    // 読み飛ばした分はサンプルが減るだけ
+                    Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => bail!("Device snapshot channel closed during calibration"),
+                },
+            }
+        }
+
+        let sound_map = sound_map.load();
+        let candidates: Vec<&Address> = match &address {
+            Some(address) => vec![address],
+            None if !sound_map.place_types.is_empty() => sound_map.place_types.keys().collect(),
+            None => sound_map.sounds.keys().collect(),
+        };
+        let (address, mut rssi) = candidates
+            .into_iter()
+            .filter_map(|address| samples.remove_entry(address))
+            .filter(|(_, rssi)| rssi.len() >= MIN_SAMPLES)
+            .max_by_key(|(_, rssi)| median(rssi))
+            .with_context(|| format!("Not enough samples from the target beacon (need {})", MIN_SAMPLES))?;
+
+        rssi.sort_unstable();
+        let (threshold, median_rssi) = threshold_from(&rssi).zip(median(&rssi)).context("No samples from the target beacon")?;
+        let beacon = CalibratedBeacon {
+            address: address.to_string(),
+            threshold,
+            samples: rssi.len(),
+            median_rssi,
+            calibrated_at_ms: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64,
+        };
+        self.store(address, beacon.clone()).await?;
+        info!(?beacon, "Interaction threshold calibrated");
+        Ok(beacon)
+    }
+}
+
+/// 昇順に並べたRSSIの `percent` パーセンタイル（サンプルが無ければ `None`）
+fn percentile(sorted: &[i16], percent: usize) -> Option<i16> {
+    let last = sorted.len().checked_sub(1)?;
+    Some(sorted[(sorted.len() * percent / 100).min(last)])
+}
+
+fn median(rssi: &[i16]) -> Option<i16> {
+    let mut sorted = rssi.to_vec();
+    sorted.sort_unstable();
+    percentile(&sorted, 50)
+}
+
+/// 昇順に並べたRSSIから閾値を決める
+///
+/// 下位10%から余裕を引いた値を閾値にし、上位10%との差（ばらつき）を再アーム閾値までの幅にする。
+fn threshold_from(sorted: &[i16]) -> Option<BeaconThreshold> {
+    let low = percentile(sorted, 10)?;
+    let high = percentile(sorted, 90)?;
+    let rssi_threshold = low.saturating_sub(THRESHOLD_MARGIN_DB);
+    let hysteresis = high.saturating_sub(low).clamp(MIN_HYSTERESIS_DB, MAX_HYSTERESIS_DB);
+    Some(BeaconThreshold { rssi_threshold, rearm_rssi: rssi_threshold.saturating_sub(hysteresis) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("tsukimi-calibration-{}-{}.json", std::process::id(), name))
+    }
+
+    fn beacon(address: &str, rssi_threshold: i16, rearm_rssi: i16) -> CalibratedBeacon {
+        CalibratedBeacon {
+            address: address.to_string(),
+            threshold: BeaconThreshold { rssi_threshold, rearm_rssi },
+            samples: 42,
+            median_rssi: -50,
+            calibrated_at_ms: 1_700_000_000_000,
+        }
+    }
+
+    #[test]
+    fn percentile_of_empty_and_single_samples() {
+        assert_eq!(percentile(&[], 10), None);
+        assert_eq!(median(&[]), None);
+        assert_eq!(threshold_from(&[]), None);
+        for percent in [0, 10, 50, 90, 100] {
+            assert_eq!(percentile(&[-60], percent), Some(-60));
+        }
+    }
+
+    #[test]
+    fn percentile_picks_the_sorted_sample() {
+        let sorted: Vec<i16> = (-70..-60).collect();
+        assert_eq!(percentile(&sorted, 0), Some(-70));
+        assert_eq!(percentile(&sorted, 10), Some(-69));
+        assert_eq!(percentile(&sorted, 90), Some(-61));
+        assert_eq!(percentile(&sorted, 100), Some(-61));
+        // 並べ替える前のサンプルでも中央値は同じ
+        assert_eq!(median(&[-61, -70, -65, -68, -62]), Some(-65));
+    }
+
+    #[test]
+    fn rearm_threshold_is_below_the_threshold() {
+        // ばらつきが無くても最小の幅を空ける
+        let steady = threshold_from(&[-55; 20]).unwrap();
+        assert_eq!(steady, BeaconThreshold { rssi_threshold: -57, rearm_rssi: -57 - MIN_HYSTERESIS_DB });
+
+        let sorted: Vec<i16> = (-70..-60).collect();
+        let moderate = threshold_from(&sorted).unwrap();
+        assert_eq!(moderate, BeaconThreshold { rssi_threshold: -71, rearm_rssi: -71 - 8 });
+
+        // ばらつきが大きくても幅は上限まで
+        let mut noisy: Vec<i16> = (-95..-35).collect();
+        noisy.sort_unstable();
+        let noisy = threshold_from(&noisy).unwrap();
+        assert_eq!(noisy.rssi_threshold - noisy.rearm_rssi, MAX_HYSTERESIS_DB);
+
+        // 下限に近い値でも桁あふれしない
+        let weakest = threshold_from(&[i16::MIN; 10]).unwrap();
+        assert_eq!(weakest, BeaconThreshold { rssi_threshold: i16::MIN, rearm_rssi: i16::MIN });
+        for threshold in [steady, moderate, noisy, weakest] {
+            assert!(threshold.rearm_rssi <= threshold.rssi_threshold, "{:?}", threshold);
+        }
+    }
+
+    #[tokio::test]
+    async fn stored_thresholds_survive_a_reload() {
+        let path = path("round-trip");
+        let _ = std::fs::remove_file(&path);
+        let store = CalibrationStore::load(&path);
+        let config = InteractionConfig::default();
+        assert_eq!(store.threshold("AA:BB:CC:DD:EE:01", &config), BeaconThreshold::from(&config));
+
+        let first = beacon("AA:BB:CC:DD:EE:02", -60, -70);
+        let second = beacon("AA:BB:CC:DD:EE:01", -55, -68);
+        store.store(first.address.parse().unwrap(), first.clone()).await.unwrap();
+        store.store(second.address.parse().unwrap(), second.clone()).await.unwrap();
+        assert_eq!(store.threshold("aa-bb-cc-dd-ee-02", &config), first.threshold);
+
+        // 閾値はアドレス順に、フラットな形で保存する
+        let saved: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(saved[0]["address"], "AA:BB:CC:DD:EE:01");
+        assert_eq!(saved[0]["rssi_threshold"], -55);
+        assert_eq!(saved[0]["rearm_rssi"], -68);
+        assert_eq!(saved[1]["calibrated_at_ms"], 1_700_000_000_000_u64);
+
+        let reloaded = CalibrationStore::load(&path);
+        let beacons = reloaded.beacons();
+        assert_eq!(beacons.len(), 2);
+        assert_eq!(beacons[0].threshold, second.threshold);
+        assert_eq!(beacons[1].threshold, first.threshold);
+        assert_eq!((beacons[1].samples, beacons[1].median_rssi), (42, -50));
+        assert_eq!(reloaded.threshold("AA:BB:CC:DD:EE:02", &config), first.threshold);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn broken_file_starts_empty() {
+        let path = path("broken");
+        std::fs::write(&path, "not json").unwrap();
+        assert!(CalibrationStore::load(&path).beacons().is_empty());
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -1,263 +1,244 @@
 use crate::proto::proto::device_service_client::DeviceServiceClient;
 use crate::proto::proto::stream_device_info_response::Event;
 use crate::proto::proto::time_service_client::TimeServiceClient;
-use crate::proto::proto::{LocationRssi, SoundSetting, StreamDeviceInfoRequest, SyncTimeRequest};
-use crate::DeviceInfo;
+use crate::proto::proto::{self as pb, LocationRssi, SoundSetting, StreamDeviceInfoRequest, StreamDeviceInfoResponse, SyncTimeRequest, SyncTimeResponse};
+use crate::messages::{BgmOverrideRequest, CommandFailure, CommandKind, CommandOutcome, ConnectionState, DeviceInfo, DeviceSnapshot, EnabledState, SePlayRequest};
+use crate::audio_system::master_volume::MasterVolume;
+use crate::audio_system::playback_status::PlaybackStatus;
+use crate::audio_system::live_stream::is_live;
+use crate::audio_system::remote_source::is_remote;
+use crate::connect_system::enable_state;
+use crate::connect_system::fleet_command::FleetDispatcher;
+use crate::connect_system::se_rate_limit::SeRateLimiter;
+use crate::connect_system::server_link::ServerLink;
+use crate::connect_system::server_messages::{event_json, LastServerMessages};
+use crate::clock_system::clock_main::Clock;
+use crate::clock_system::time_source::SharedTimeSource;
+use crate::config_system::config_main::{InteractionConfig, PointSeConfig, ServerConfig, TransportKind, UploadCompression, UploadConfig};
+use crate::config_system::config_store::ConfigStore;
+use crate::config_system::unit_identity::unit_identity;
+use crate::connect_system::mqtt_transport::MqttTransport;
+use crate::connect_system::transport::{GrpcTransport, Transport};
+use crate::connect_system::upload_rate::{batch_snapshots, UploadRate};
+use crate::connect_system::websocket_transport::WebSocketTransport;
+use crate::bluetooth_system::address::Address;
+use crate::connect_system::location_context::LocationContext;
+use crate::connect_system::location_validation::validate_locations;
+use crate::connect_system::interaction::{InteractionState, DEFAULT_INTERACTION_STATE_PATH};
+use crate::connect_system::interaction_action::{InteractionContext, InteractionDispatcher};
+use crate::connect_system::calibration::CalibrationStore;
+use crate::connect_system::interaction_detector::{interaction_detector_main, InteractionDetector, InteractionEvent};
+use crate::connect_system::reconnect_backoff::ReconnectBackoff;
+use crate::connect_system::points_cache::{PointsCache, DEFAULT_POINTS_CACHE_PATH};
+use crate::connect_system::session_recording::{load_session, spawn_replay, RecordingTransport, ReplayTransport, SessionEntry, SessionMessage, SessionRecorder};
+use crate::connect_system::sound_catalog::{point_se_file, SoundCatalog};
+use crate::connect_system::sound_map::{SharedSoundMap, SoundMap};
+use crate::metrics_system::metrics_main::{metrics, LagReceiver, Metrics};
+use crate::shutdown_system::shutdown_controller::{ShutdownController, ShutdownPhase};
+use crate::event_system::event_bus::{EventBus, LocalEvent};
+use crate::net_system::egress::{self, http_client};
+use crate::peer_system::visitor_fusion::VisitorFusion;
+use futures::future::BoxFuture;
+use prost::Message;
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use tokio::sync::{broadcast, mpsc};
-use tokio_stream::wrappers::BroadcastStream;
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::{BroadcastStream, IntervalStream, ReceiverStream};
 use tokio_stream::StreamExt;
+use tonic::codec::CompressionEncoding;
 use tonic::transport::{Channel, Endpoint};
 use tracing::{debug, error, info, instrument, warn};
-use serde::{Deserialize, Serialize};
+use serde::Deserialize;
 
-// システム有効化状態を管理するメッセージ
-#[derive(Debug, Clone)]
-pub struct SystemEnabledState {
-    pub enabled: bool,
-    pub target_device_id: String,
-}
+/// このクライアントが理解できるprotoスキーマのバージョン
+///
+/// サーバーが新しいイベント種別を追加した場合、古いクライアントは未知のイベントとして
+/// 読み飛ばす（ログとメトリクスに記録する）ため、混在した環境でも動作を継続できる。
+/// 2: FleetCommandとその受領の通知（`fleet_ack`）を追加
+/// 3: コマンドのIDと実行結果の報告（`command_result`）を追加
+pub const PROTO_SCHEMA_VERSION: u32 = 3;
 
-// インタラクション用の構造体
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct InteractionRequest {
-    location_type: String,
-}
+/// サーバーが最大時間を指定しなかった場合にライブ配信を続ける時間
+const LIVE_STREAM_MAX_DURATION: Duration = Duration::from_secs(2 * 60 * 60);
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct InteractionResponse {
-    success: bool,
-    message: String,
-}
+/// 再生状況をサーバーへ報告する間隔
+const STATUS_REPORT_INTERVAL: Duration = Duration::from_secs(5);
 
-// インタラクション状態管理
-struct InteractionState {
-    last_interaction_time: HashMap<String, std::time::Instant>,
-    interaction_cooldown: Duration,
-}
+/// インタラクション検知の入出力のチャンネルの容量
+const INTERACTION_CHANNEL_CAPACITY: usize = 64;
 
-impl InteractionState {
-    fn new() -> Self {
-        Self {
-            last_interaction_time: HashMap::new(),
-            interaction_cooldown: Duration::from_secs(10), // 5秒→10秒に戻す
-        }
-    }
+/// 全ユニット向けコマンドの受領の通知を送信ストリームに渡すチャンネルの容量
+const FLEET_ACK_CHANNEL_CAPACITY: usize = 16;
 
-    fn can_interact(&mut self, place_type: &str) -> bool {
-        let now = std::time::Instant::now();
-        if let Some(&last_time) = self.last_interaction_time.get(place_type) {
-            if now.duration_since(last_time) < self.interaction_cooldown {
-                return false;
-            }
-        }
-        self.last_interaction_time.insert(place_type.to_string(), now);
-        true
-    }
+// プレイヤーAPIが返すプレイヤー情報（使用するフィールドのみ）
+#[derive(Debug, Clone, Deserialize)]
+struct PlayerResponse {
+    points: i32,
 }
 
-/// place_typeに基づいてベースロケーションタイプを決定する
-fn get_base_location_type_from_place_type(place_type: &str) -> &'static str {
-    match place_type {
-        "projection_mapping" => "main",
-        "buddhas_bowl" => "hotoke",
-        "jeweled_branch" => "eda",
-        "fire_rat_robe" => "nezumi",
-        "dragons_jewel" => "ryu",
-        "swallows_cowry" => "kai",
-        _ => "main",
-    }
+/// プレイヤーAPIから正とするポイント数を取得する
+async fn fetch_player_points(players_api_url: &str, user_id: &str) -> anyhow::Result<i32> {
+    // エンドポイントURLを構築: https://tsukimi.paon.dev/players/{user_id}
+    let url = format!("{}/{}", players_api_url.trim_end_matches('/'), user_id);
+    info!(url = %url, "Fetching points from players API");
+    let response = http_client()
+        .get(&url)
+        .timeout(Duration::from_secs(5))
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(response.json::<PlayerResponse>().await?.points)
 }
 
-/// place_typeとポイント数に基づいてサウンドファイル名を生成する
-fn get_sound_file_from_place_type_and_points(place_type: &str, points: i32) -> String {
-    let base_type = get_base_location_type_from_place_type(place_type);
-    // ポイント0の場合は1として扱う
-    let effective_points = if points == 0 { 1 } else { points };
-    format!("tsukimi-{}_{}.mp3", base_type, effective_points)
+/// PointUpdateの出どころ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PointsOrigin {
+    /// サーバーのストリームで届いた
+    Stream,
+    /// 接続時にプレイヤーAPIのポイント数と突き合わせた（取りこぼしの補正）
+    Reconcile,
 }
 
-/// place_typeに基づいてSEファイル名を決定する
-fn get_se_file_from_place_type(place_type: &str) -> Option<&'static str> {
-    match place_type {
-        "fire_rat_robe" => Some("se-nezumi.mp3"),   // 火鼠の裘: 鼠のSE
-        "buddhas_bowl" => Some("se-hotoke.mp3"),    // 仏の御石の鉢: 仏のSE
-        _ => None,
+/// ポイントの変化で鳴らすSE
+///
+/// 初回の更新と、プレイヤーAPIとの突き合わせでは鳴らさない（来場者の操作による変化ではないため）。
+fn point_change_se(config: &PointSeConfig, old_points: i32, new_points: i32, initialized: bool, origin: PointsOrigin) -> Option<&str> {
+    point_se_file(config, old_points, new_points).filter(|_| initialized && origin == PointsOrigin::Stream)
+}
+
+/// 状態の報告に付けるユニットの識別情報
+fn unit_labels() -> pb::UnitLabels {
+    let unit = unit_identity();
+    pb::UnitLabels {
+        unit_id: unit.unit_id.clone(),
+        venue: unit.venue.clone().unwrap_or_default(),
+        zone: unit.zone.clone().unwrap_or_default(),
+        hardware_revision: unit.hardware_revision.clone().unwrap_or_default(),
     }
 }
 
-/// インタラクション可能なplace_typeかどうかを判定
-fn is_interactive_place_type(place_type: &str) -> bool {
-    matches!(place_type, "fire_rat_robe" | "buddhas_bowl")
+/// サーバーイベントの対象デバイスに自デバイスが含まれるか（空またはワイルドカードは全デバイス）
+fn is_target_device(devices: &[String], my_device_id: Option<&str>) -> bool {
+    devices.is_empty()
+        || devices
+            .iter()
+            .any(|d| d == enable_state::WILDCARD_DEVICE_ID || Some(d.as_str()) == my_device_id)
 }
 
-/// インタラクションAPIを呼び出す
-async fn send_interaction_request(user_id: String, place_type: String) -> anyhow::Result<()> {
-    let client = reqwest::Client::new();
-    let request = InteractionRequest {
-        location_type: place_type.clone(),
+/// command_id付きのコマンドの実行結果をイベントバスに流す（IDが空のコマンドは報告しない）
+///
+/// 結果はイベントバス経由で送信ストリームに渡るため、オーディオスレッドの再生結果と同じ経路で報告される。
+fn report_command(events: &EventBus, command_id: &str, command: CommandKind, result: Result<(), (CommandFailure, String)>) {
+    if command_id.is_empty() {
+        return;
+    }
+    let outcome = match result {
+        Ok(()) => CommandOutcome::ok(command_id.to_string(), command),
+        Err((failure, detail)) => CommandOutcome::failed(command_id.to_string(), command, failure, detail),
     };
+    events.publish(LocalEvent::CommandExecuted { outcome });
+}
 
-    // エンドポイントURLを構築: https://tsukimi.paon.dev/players/{user_id}/increment
-    let url = format!("https://tsukimi.paon.dev/players/{}/increment", user_id);
+/// コマンドの実行結果をサーバーへの報告に変換する
+fn command_result(outcome: CommandOutcome) -> pb::CommandResult {
+    let command = match outcome.command {
+        CommandKind::Enable => pb::CommandKind::Enable,
+        CommandKind::SePlay => pb::CommandKind::SePlay,
+        CommandKind::BgmOverride => pb::CommandKind::BgmOverride,
+        CommandKind::Volume => pb::CommandKind::Volume,
+    };
+    let failure = match outcome.failure {
+        None => pb::CommandFailure::None,
+        Some(CommandFailure::InvalidRequest) => pb::CommandFailure::InvalidRequest,
+        Some(CommandFailure::AssetMissing) => pb::CommandFailure::AssetMissing,
+        Some(CommandFailure::PipelineError) => pb::CommandFailure::PipelineError,
+        Some(CommandFailure::Busy) => pb::CommandFailure::Busy,
+        Some(CommandFailure::Stale) => pb::CommandFailure::Stale,
+        Some(CommandFailure::Unavailable) => pb::CommandFailure::Unavailable,
+        Some(CommandFailure::RateLimited) => pb::CommandFailure::RateLimited,
+    };
+    pb::CommandResult {
+        command_id: outcome.command_id,
+        command: command as i32,
+        ok: outcome.failure.is_none(),
+        failure: failure as i32,
+        detail: outcome.detail,
+    }
+}
 
-    info!(?request, url = %url, "Sending interaction request");
+/// インタラクション検知のタスク（接続をまたいで1組だけ動かす。破棄すると止める）
+struct InteractionTasks {
+    /// デバイスごとの最新RSSI（現在のロケーションの判定に使う）
+    latest_rssi: Arc<Mutex<HashMap<Address, i16>>>,
+    handles: Vec<JoinHandle<()>>,
+}
 
-    match client
-        .post(&url)
-        .json(&request)
-        .timeout(Duration::from_secs(5))
-        .send()
-        .await
-    {
-        Ok(response) => {
-            if response.status().is_success() {
-                match response.json::<InteractionResponse>().await {
-                    Ok(data) => {
-                        info!(?data, "Interaction request successful");
-                    }
-                    Err(e) => {
-                        warn!("Failed to parse interaction response: {}", e);
-                    }
-                }
-            } else {
-                warn!("Interaction request failed with status: {}", response.status());
-            }
-        }
-        Err(e) => {
-            error!("Failed to send interaction request: {}", e);
+impl Drop for InteractionTasks {
+    fn drop(&mut self) {
+        for handle in &self.handles {
+            handle.abort();
         }
     }
-
-    Ok(())
 }
 
-
-#[instrument(skip(client, rx, sound_map, se_tx, system_enabled_tx))]
-async fn run_device_service_client(
-    mut client: DeviceServiceClient<Channel>,
-    rx: broadcast::Receiver<Arc<DeviceInfo>>,
-    sound_setting_tx: mpsc::Sender<SoundSetting>,
-    se_tx: mpsc::Sender<crate::audio_system::audio_main::SePlayRequest>,
-    system_enabled_tx: broadcast::Sender<SystemEnabledState>,
-    sound_map: Arc<Mutex<HashMap<String, String>>>,
+/// インタラクションの検知と、検知したインタラクションに応じたSE・インタラクションAPIの呼び出しを始める
+///
+/// 再接続のたびに始めると、1回の接近で同じインタラクションが接続の回数だけ送られるため、
+/// `connect_main` で一度だけ始める。重複判定の状態もここで1つだけ読み込む。
+#[allow(clippy::too_many_arguments)]
+fn spawn_interaction_tasks(
+    players_api_url: &str,
+    interaction_config: &InteractionConfig,
+    calibration: Arc<CalibrationStore>,
+    state_path: &Path,
+    visitor_fusion: Option<Arc<VisitorFusion>>,
+    mut interaction_rx: broadcast::Receiver<Arc<DeviceSnapshot>>,
+    se_tx: mpsc::Sender<SePlayRequest>,
+    events: EventBus,
+    sound_map: Arc<SharedSoundMap>,
     my_address: Arc<Mutex<Option<String>>>,
-    current_points: Arc<Mutex<i32>>,
-    current_location_type: Arc<Mutex<String>>,
-) {
-    info!("Starting DeviceService client...");
-
-    // インタラクション状態管理
-    let interaction_state = Arc::new(Mutex::new(InteractionState::new()));
-
-    // ロケーション情報のキャッシュ（address -> place_type）
-    let location_place_types = Arc::new(Mutex::new(HashMap::<String, String>::new()));
-
-    const INTERACTION_RSSI_THRESHOLD: i16 = -45;
-
-    // ポイント初期化フラグ（起動直後の初回更新でSEを鳴らさないため）
-    let points_initialized = Arc::new(Mutex::new(false));
-
-    // デバイス情報を監視するためのRxクローン
-    let mut interaction_rx = rx.resubscribe();
+    time: SharedTimeSource,
+) -> InteractionTasks {
+    // インタラクションの重複判定（ディスクに保存し、再接続・再起動をまたいで維持する）
+    let interaction_state = InteractionState::load(state_path, Arc::clone(&time));
 
     // デバイスごとの最新RSSI値を保持するマップ
-    let latest_rssi_map = Arc::new(Mutex::new(HashMap::<String, i16>::new()));
-
-    // インタラクション検知タスクを起動
-    let my_address_for_interaction = Arc::clone(&my_address);
-    let location_place_types_for_interaction = Arc::clone(&location_place_types);
-    let interaction_state_for_task = Arc::clone(&interaction_state);
-    let se_tx_for_interaction = se_tx.clone();
+    let latest_rssi_map = Arc::new(Mutex::new(HashMap::<Address, i16>::new()));
+
+    // インタラクション検知タスクを起動（DeviceInfoを受け取り、検知したインタラクションを返す）
+    let (interaction_device_tx, interaction_device_rx) = mpsc::channel::<Arc<DeviceInfo>>(INTERACTION_CHANNEL_CAPACITY);
+    let (interaction_event_tx, mut interaction_event_rx) = mpsc::channel::<InteractionEvent>(INTERACTION_CHANNEL_CAPACITY);
+    let goodbye_se = interaction_config.goodbye_se.clone();
+    let interaction_dispatcher = Arc::new(InteractionDispatcher::new(players_api_url.to_string(), interaction_config));
+    let detector_handle = tokio::spawn(interaction_detector_main(
+        InteractionDetector::new(interaction_config.clone(), calibration, visitor_fusion, interaction_state, time),
+        interaction_device_rx,
+        Arc::clone(&sound_map),
+        interaction_event_tx,
+    ));
+
+    // DeviceInfoを共有RSSIマップに反映し、インタラクション検知に渡すタスク
     let latest_rssi_map_for_interaction = Arc::clone(&latest_rssi_map);
-
-    tokio::spawn(async move {
-        let mut last_rssi_map: HashMap<String, i16> = HashMap::new();
-
+    let feeder_handle = tokio::spawn(async move {
         loop {
             match interaction_rx.recv().await {
-                Ok(device_info) => {
-                    // 共有RSSIマップを更新
-                    {
-                        let mut rssi_map = latest_rssi_map_for_interaction.lock().unwrap();
-                        rssi_map.insert(device_info.address.clone(), device_info.rssi);
-                    }
-
-                    // is_my_device チェックはユーザーの要望により無効化。
-                    // sound_mapに登録されているデバイスであれば、RSSI閾値を超えた場合にインタラクションを試みる。
-
-
-                    // 前回のRSSIを取得
-                    let prev_rssi = last_rssi_map.get(&device_info.address).copied().unwrap_or(i16::MIN);
-                    let current_rssi = device_info.rssi;
-
-                    // RSSI閾値を上回った場合（0に近づいた = 近づいた場合）
-                    if prev_rssi <= INTERACTION_RSSI_THRESHOLD && current_rssi > INTERACTION_RSSI_THRESHOLD {
-                        info!(
-                            address = %device_info.address,
-                            rssi = current_rssi,
-                            threshold = INTERACTION_RSSI_THRESHOLD,
-                            "I came very close to a location (RSSI > {}), checking for interaction", INTERACTION_RSSI_THRESHOLD
-                        );
-
-                        // place_typeを取得
-                        let place_type = {
-                            let location_types = location_place_types_for_interaction.lock().unwrap();
-                            location_types.get(&device_info.address).cloned()
-                        };
-
-                        if let Some(place_type) = place_type {
-                            // インタラクション可能な場所かチェック
-                            if is_interactive_place_type(&place_type) {
-                                let can_interact = {
-                                    let mut state = interaction_state_for_task.lock().unwrap();
-                                    state.can_interact(&place_type)
-                                };
-
-                                if can_interact {
-                                    info!(
-                                        place_type = %place_type,
-                                        address = %device_info.address,
-                                        rssi = current_rssi,
-                                        "Triggering interaction"
-                                    );
-
-                                    // SEファイルを取得してaudio_mainに送信
-                                    if let Some(se_file) = get_se_file_from_place_type(&place_type) {
-                                        let se_request = crate::audio_system::audio_main::SePlayRequest {
-                                            file_path: se_file.to_string(),
-                                        };
-
-                                        if let Err(e) = se_tx_for_interaction.send(se_request).await {
-                                            error!("Failed to send SE play request: {}", e);
-                                        } else {
-                                            info!("SE play request sent successfully");
-                                        }
-                                    }
-
-                                    // インタラクションAPIを呼び出し
-                                    let user_id_opt = my_address_for_interaction.lock().unwrap().clone();
-                                    if let Some(user_id) = user_id_opt {
-                                        if let Err(e) = send_interaction_request(user_id, place_type).await {
-                                            error!("Failed to send interaction request: {}", e);
-                                        }
-                                    }
-                                } else {
-                                    debug!(
-                                        place_type = %place_type,
-                                        "Interaction still in cooldown"
-                                    );
-                                }
-                            }
+                Ok(snapshot) => {
+                    for device_info in &snapshot.devices {
+                        if device_info.visitor_token.is_none() {
+                            let mut rssi_map = latest_rssi_map_for_interaction.lock().unwrap();
+                            rssi_map.insert(device_info.address.clone(), device_info.rssi);
+                        }
+                        if interaction_device_tx.send(Arc::clone(device_info)).await.is_err() {
+                            return;
                         }
                     }
-
-                    last_rssi_map.insert(device_info.address.clone(), current_rssi);
                 }
                 Err(broadcast::error::RecvError::Lagged(skipped)) => {
-                    warn!(skipped, "Interaction receiver lagged");
+                    metrics().record_lag(LagReceiver::Interaction, skipped);
                 }
                 Err(broadcast::error::RecvError::Closed) => {
                     info!("Interaction receiver closed");
@@ -267,72 +248,425 @@ async fn run_device_service_client(
         }
     });
 
+    // 検知したインタラクションに応じてSE・ローカルイベント・インタラクションAPIを実行するタスク
+    let my_address_for_interaction = my_address;
+    let se_tx_for_interaction = se_tx;
+    let events_for_interaction = events;
+    let catalog = SoundCatalog::default();
+    let consumer_handle = tokio::spawn(async move {
+        while let Some(event) = interaction_event_rx.recv().await {
+            match event {
+                InteractionEvent::Triggered { place_type, address, idempotency_key, visitor_token, .. } => {
+                    events_for_interaction
+                        .publish(LocalEvent::InteractionTriggered { place_type: place_type.clone(), address: address.to_string() });
+
+                    // SEファイルを取得してaudio_mainに送信
+                    if let Some(se_file) = catalog.se_file(&place_type) {
+                        let se_request = SePlayRequest {
+                            file_path: se_file.to_string(),
+                            priority: false,
+                            gain: None,
+                            command_id: None,
+                        };
+
+                        if let Err(e) = se_tx_for_interaction.send(se_request).await {
+                            error!("Failed to send SE play request: {}", e);
+                        } else {
+                            info!("SE play request sent successfully");
+                        }
+                    }
+
+                    // インタラクションAPIを呼び出し
+                    let user_id_opt = my_address_for_interaction.lock().unwrap().clone();
+                    // 再送を待つ間も次のインタラクションを止めないよう、別タスクで送る
+                    if let Some(user_id) = user_id_opt {
+                        let context = InteractionContext { user_id, place_type, address: address.to_string(), visitor_token, idempotency_key };
+                        let interaction_dispatcher = Arc::clone(&interaction_dispatcher);
+                        tokio::spawn(async move {
+                            if let Err(e) = interaction_dispatcher.dispatch(&context).await {
+                                error!("Failed to send interaction request: {:?}", e);
+                            }
+                        });
+                    }
+                }
+                // インタラクションした場所から立ち去った場合は退出の合図を鳴らす
+                InteractionEvent::Departed { address, rssi, .. } => {
+                    let Some(goodbye_se) = goodbye_se.as_ref() else {
+                        continue;
+                    };
+                    info!(%address, rssi, "👋 Departed from location, playing goodbye SE");
+                    let se_request = SePlayRequest {
+                        file_path: goodbye_se.clone(),
+                        priority: false,
+                        gain: None,
+                        command_id: None,
+                    };
+                    if let Err(e) = se_tx_for_interaction.send(se_request).await {
+                        error!("Failed to send goodbye SE request: {}", e);
+                    }
+                }
+            }
+        }
+    });
+
+    InteractionTasks { latest_rssi: latest_rssi_map, handles: vec![detector_handle, feeder_handle, consumer_handle] }
+}
+
+/// DeviceServiceのクライアントが使う設定・チャンネル・共有状態
+///
+/// 再接続をまたいで同じものを使うため、`connect_main` で一度だけ作り、接続ごとに複製して渡す。
+#[derive(Clone)]
+struct DeviceServiceContext {
+    upload_rate: Arc<UploadRate>,
+    fleet: Arc<FleetDispatcher>,
+    se_limit: Arc<SeRateLimiter>,
+    players_api_url: String,
+    reject_invalid_locations: bool,
+    point_se: PointSeConfig,
+    points_cache: PathBuf,
+    visitor_fusion: Option<Arc<VisitorFusion>>,
+    /// 初回接続時に一度だけ鳴らすチャイム（鳴らしたら取り出す）
+    connected_chime: Arc<Mutex<Option<String>>>,
+    sound_setting_tx: mpsc::Sender<SoundSetting>,
+    se_tx: mpsc::Sender<SePlayRequest>,
+    bgm_override_tx: mpsc::Sender<BgmOverrideRequest>,
+    volume_tx: watch::Sender<MasterVolume>,
+    language_tx: watch::Sender<Option<String>>,
+    enabled_tx: watch::Sender<EnabledState>,
+    events: EventBus,
+    status_rx: watch::Receiver<PlaybackStatus>,
+    sound_map: Arc<SharedSoundMap>,
+    my_address: Arc<Mutex<Option<String>>>,
+    current_points: Arc<Mutex<i32>>,
+    location_tx: watch::Sender<LocationContext>,
+    server_messages_tx: watch::Sender<LastServerMessages>,
+    latest_rssi_map: Arc<Mutex<HashMap<Address, i16>>>,
+    shutdown: Arc<ShutdownController>,
+}
+
+#[instrument(skip_all)]
+async fn run_device_service_client(mut transport: Box<dyn Transport>, rx: broadcast::Receiver<Arc<DeviceSnapshot>>, context: DeviceServiceContext) {
+    info!("Starting DeviceService client...");
+    let DeviceServiceContext {
+        upload_rate,
+        fleet,
+        se_limit,
+        players_api_url,
+        reject_invalid_locations,
+        point_se,
+        points_cache,
+        visitor_fusion,
+        connected_chime,
+        sound_setting_tx,
+        se_tx,
+        bgm_override_tx,
+        volume_tx,
+        language_tx,
+        enabled_tx,
+        events,
+        status_rx,
+        sound_map,
+        my_address,
+        current_points,
+        location_tx,
+        server_messages_tx,
+        latest_rssi_map,
+        shutdown,
+    } = context;
+
+    // place_type・ポイント数から音源を決めるルール
+    let catalog = SoundCatalog::default();
+
+    // ポイント初期化フラグ（起動直後の初回更新でSEを鳴らさないため）
+    let points_initialized = Arc::new(Mutex::new(false));
+
     let sound_map_for_filter = Arc::clone(&sound_map);
     let my_address_for_stream = Arc::clone(&my_address);
-    let device_info_stream = BroadcastStream::new(rx)
-        .filter_map(move |result| {
-            let sound_map = Arc::clone(&sound_map_for_filter);
-            result.ok().and_then(|info| {
-                let sound_map = sound_map.lock().unwrap();
-                if sound_map.contains_key(&info.address) {
-                    Some(info)
-                } else {
-                    None
+    // 終了手順でデバイス情報の送信を締め切る（バッチ途中のものは最後のバッチとして送る）
+    let uploads_closed = {
+        let shutdown = Arc::clone(&shutdown);
+        async move { shutdown.reached(ShutdownPhase::FlushUploads).await }
+    };
+    let snapshots = futures::StreamExt::take_until(BroadcastStream::new(rx), uploads_closed).filter_map(|result| match result {
+        Ok(snapshot) => Some(snapshot),
+        Err(BroadcastStreamRecvError::Lagged(skipped)) => {
+            metrics().record_lag(LagReceiver::Upload, skipped);
+            None
+        }
+    });
+    // 送信の間隔は受信の多さ・サーバーからの抑制に応じて変わる（UploadRate）
+    let device_info_stream = batch_snapshots(snapshots, Arc::clone(&upload_rate))
+        .filter_map(move |snapshots| {
+            // バッチ内の同一アドレスはRSSIが最大のもの1件にまとめる
+            let locations: Vec<LocationRssi> = {
+                let sound_map = sound_map_for_filter.load();
+                let mut max_rssi: HashMap<&str, i16> = HashMap::new();
+                for info in snapshots.iter().flat_map(|snapshot| snapshot.devices.iter()) {
+                    if !sound_map.contains(&info.address) {
+                        continue;
+                    }
+                    max_rssi
+                        .entry(info.address.as_str())
+                        .and_modify(|rssi| *rssi = (*rssi).max(info.rssi))
+                        .or_insert(info.rssi);
                 }
-            })
-        })
-        .chunks_timeout(10, Duration::from_millis(50))
-        .map(move |infos| {
-            let locations: Vec<LocationRssi> = infos
-                .into_iter()
-                .map(|info| LocationRssi {
-                    address: info.address.clone(),
-                    rssi: info.rssi as i32,
-                })
-                .collect();
+                max_rssi
+                    .into_iter()
+                    .map(|(address, rssi)| LocationRssi {
+                        address: address.to_string(),
+                        rssi: rssi as i32,
+                    })
+                    .collect()
+            };
+            if locations.is_empty() {
+                return None;
+            }
+
+            // バッチ内で最もRSSIが強い来場者のトークン（来場者の融合が有効なら自ユニットが受け持つ来場者のみ）
+            let visitor_token = snapshots
+                .iter()
+                .flat_map(|snapshot| snapshot.devices.iter())
+                .filter_map(|info| info.visitor_token.as_ref().map(|token| (token, info.rssi)))
+                .filter(|(token, _)| visitor_fusion.as_ref().is_none_or(|fusion| fusion.owns(token)))
+                .max_by_key(|(_, rssi)| *rssi)
+                .map(|(token, _)| token.clone())
+                .unwrap_or_default();
 
             let user_id = my_address_for_stream
                 .lock()
                 .unwrap()
                 .clone()
-                .unwrap_or_else(|| "".to_string());
+                .unwrap_or_default();
 
-            info!(
+            debug!(
                 ?locations,
                 %user_id,
+                snapshots = snapshots.len(),
                 locations_count = locations.len(),
-                "Sending device info to server"
+                "Sending device info batch to server"
             );
-            StreamDeviceInfoRequest { user_id, locations }
+            Some(StreamDeviceInfoRequest { user_id, locations, visitor_token, ..Default::default() })
         });
+    // 最後のバッチをトランスポートが取り出したら、送り切ったことを報告する
+    let shutdown_for_flush = Arc::clone(&shutdown);
+    let device_info_stream = device_info_stream.chain(
+        futures::stream::once(async move {
+            info!("Device info uploads flushed");
+            shutdown_for_flush.complete(ShutdownPhase::FlushUploads);
+        })
+        .filter_map(|()| None),
+    );
+
+    // 再生状況を定期的に報告する（locationsが空のメッセージ）
+    let my_address_for_status = Arc::clone(&my_address);
+    let location_rx = location_tx.subscribe();
+    let streams_closed = {
+        let shutdown = Arc::clone(&shutdown);
+        async move { shutdown.reached(ShutdownPhase::CloseStreams).await }
+    };
+    let status_stream = futures::StreamExt::take_until(IntervalStream::new(tokio::time::interval(STATUS_REPORT_INTERVAL)), streams_closed).map(move |_| {
+        let status = status_rx.borrow().clone();
+        debug!(?status, "Reporting playback status to server");
+        StreamDeviceInfoRequest {
+            user_id: my_address_for_status.lock().unwrap().clone().unwrap_or_default(),
+            status: Some(pb::PlaybackStatus {
+                sound_id: status.sound,
+                position_ns: status.position_ns,
+                drift_ns: status.drift_ns,
+                enabled: status.enabled,
+                state: format!("{:?}", status.state),
+                clock_source: status.clock.map_or_else(|| "Unsynced".to_string(), |t| format!("{:?}", t.source)),
+                clock_uncertainty_ns: status.clock.map_or(0, |t| t.uncertainty_ns),
+                place_type: location_rx.borrow().place_type.clone(),
+                unit: Some(unit_labels()),
+                sync_score: status.sync_quality.score,
+                sync_reseeks: status.sync_quality.reseeks,
+                sync_tempo_excursions: status.sync_quality.tempo_excursions,
+            }),
+            ..Default::default()
+        }
+    });
 
-    match client.stream_device_info(device_info_stream).await {
-        Ok(response) => {
+    // 再起動直後に誤ったレベルの音源を鳴らさないよう、PointUpdateを待たずにポイント数を復元する
+    let my_id = my_address.lock().unwrap().clone();
+    if let Some(my_id) = my_id.as_deref() {
+        let mut restored = PointsCache::load(&points_cache, my_id);
+        match transport.get_points(my_id).await {
+            Ok(Some(points)) => {
+                info!(points, "Fetched points from server");
+                if let Err(e) = PointsCache::save(&points_cache, my_id, points) {
+                    warn!("{:?}", e);
+                }
+                restored = Some(points);
+            }
+            Ok(None) => debug!("GetPoints is not available, waiting for PointUpdate"),
+            Err(e) => warn!("Failed to fetch points from server: {:?}", e),
+        }
+        if let Some(points) = restored {
+            *current_points.lock().unwrap() = points;
+            events.publish(LocalEvent::PointsChanged { points });
+            // 復元できた場合は初回のPointUpdateを受信済みとして扱う（以降の増加でSEを鳴らす）
+            *points_initialized.lock().unwrap() = true;
+        }
+    }
+
+    // ストリーム開始時に、スキーマバージョンを名乗るハンドシェイクを先頭に送る
+    let handshake = StreamDeviceInfoRequest {
+        user_id: my_address.lock().unwrap().clone().unwrap_or_default(),
+        schema_version: PROTO_SCHEMA_VERSION,
+        client_version: env!("CARGO_PKG_VERSION").to_string(),
+        ..Default::default()
+    };
+    info!(schema_version = PROTO_SCHEMA_VERSION, user_id = %handshake.user_id, "Sending DeviceService handshake");
+
+    // 全ユニット向けのコマンドの受領の通知（locationsが空のメッセージ）
+    let (fleet_ack_tx, fleet_ack_rx) = mpsc::channel::<pb::FleetCommandAck>(FLEET_ACK_CHANNEL_CAPACITY);
+    let my_address_for_ack = Arc::clone(&my_address);
+    let acks_closed = {
+        let shutdown = Arc::clone(&shutdown);
+        async move { shutdown.reached(ShutdownPhase::CloseStreams).await }
+    };
+    let fleet_ack_stream = futures::StreamExt::take_until(ReceiverStream::new(fleet_ack_rx), acks_closed).map(move |ack| StreamDeviceInfoRequest {
+        user_id: my_address_for_ack.lock().unwrap().clone().unwrap_or_default(),
+        fleet_ack: Some(ack),
+        ..Default::default()
+    });
+
+    // command_id付きのコマンドの実行結果（locationsが空のメッセージ。切断中に出た結果は報告しない）
+    let my_address_for_result = Arc::clone(&my_address);
+    let results_closed = {
+        let shutdown = Arc::clone(&shutdown);
+        async move { shutdown.reached(ShutdownPhase::CloseStreams).await }
+    };
+    let command_result_stream = futures::StreamExt::take_until(BroadcastStream::new(events.subscribe("command_results")), results_closed)
+        .filter_map(move |event| match event {
+            Ok(LocalEvent::CommandExecuted { outcome }) => Some(StreamDeviceInfoRequest {
+                user_id: my_address_for_result.lock().unwrap().clone().unwrap_or_default(),
+                command_result: Some(command_result(outcome)),
+                ..Default::default()
+            }),
+            Ok(_) => None,
+            Err(BroadcastStreamRecvError::Lagged(skipped)) => {
+                warn!(skipped, "Event bus lagged - some command results were not reported");
+                None
+            }
+        });
+
+    let device_info_stream = tokio_stream::once(handshake)
+        .chain(device_info_stream.merge(status_stream).merge(fleet_ack_stream).merge(command_result_stream))
+        .map(|request| {
+            Metrics::add(&metrics().upload_bytes, request.encoded_len() as u64);
+            request
+        });
+    let mut server_schema_warned = false;
+
+    match transport.open(Box::pin(device_info_stream)).await {
+        Ok(stream) => {
             info!("DeviceService connected. Waiting for responses...");
-            let mut stream = response.into_inner();
-            while let Some(item) = stream.next().await {
+            #[cfg(feature = "chaos")]
+            crate::chaos_system::chaos_main::recovered(crate::chaos_system::chaos_main::Fault::DropStream);
+
+            // ストリームのPointUpdateを取りこぼしていても正しい音源になるよう、接続のたびに
+            // プレイヤーAPIのポイント数を取得し、PointUpdateとして同じ経路で反映する（出どころを付けてSEは鳴らさない）
+            let user_id = my_address.lock().unwrap().clone();
+            let reconcile_url = players_api_url.clone();
+            let reconcile = futures::stream::once(async move {
+                let user_id = user_id?;
+                match fetch_player_points(&reconcile_url, &user_id).await {
+                    Ok(points) => {
+                        info!(points, "Reconciling points from players API");
+                        Some(Ok(StreamDeviceInfoResponse {
+                            event: Some(Event::PointUpdate(pb::PointUpdate { user_id, points })),
+                            ..Default::default()
+                        }))
+                    }
+                    Err(e) => {
+                        warn!("Failed to fetch points from players API: {:?}", e);
+                        None
+                    }
+                }
+            })
+            .filter_map(|response| response);
+            let stream = stream
+                .map(|item| (item, PointsOrigin::Stream))
+                .merge(reconcile.map(|item| (item, PointsOrigin::Reconcile)));
+            // 終了手順でストリームを閉じる（送信側もステータス報告の終了で閉じる）
+            let streams_closed = {
+                let shutdown = Arc::clone(&shutdown);
+                async move { shutdown.reached(ShutdownPhase::CloseStreams).await }
+            };
+            let stream = futures::StreamExt::take_until(stream, streams_closed);
+            // ソークテスト: 障害が注入されたらストリームを途中で終わらせる（切断と同じく再接続する）
+            #[cfg(feature = "chaos")]
+            let stream = futures::StreamExt::take_until(
+                stream,
+                crate::chaos_system::chaos_main::wait_triggered(crate::chaos_system::chaos_main::Fault::DropStream),
+            );
+            let mut stream = Box::pin(stream);
+            let chime = connected_chime.lock().unwrap().take();
+            if let Some(file_path) = chime {
+                info!(file = %file_path, "🔔 Playing connected chime");
+                if let Err(e) = se_tx.send(SePlayRequest { file_path, priority: true, gain: None, command_id: None }).await {
+                    error!("Failed to send connected chime request: {}", e);
+                }
+            }
+            while let Some((item, origin)) = stream.next().await {
                 match item {
                     Ok(res) => {
+                        // サーバーのスキーマが新しい場合は一度だけ警告する
+                        if res.schema_version > PROTO_SCHEMA_VERSION && !server_schema_warned {
+                            warn!(
+                                server_schema_version = res.schema_version,
+                                client_schema_version = PROTO_SCHEMA_VERSION,
+                                "Server uses a newer proto schema - unknown events will be ignored"
+                            );
+                            server_schema_warned = true;
+                        }
+
                         if let Some(event) = res.event {
+                            // ジャーナルから取り出してそのまま編集・再送（フェイクサーバーの台本の `event`）できるよう、JSONで記録する
+                            // 設定系のメッセージ（SoundSetting・LocationUpdate・MoonlightUpdate）は常に記録し、状態APIでも公開する
+                            if server_messages_tx.send_if_modified(|last| last.record(&event)) {
+                                info!(event = %event_json(&event), "Server settings received");
+                            } else if tracing::enabled!(tracing::Level::DEBUG) {
+                                debug!(event = %event_json(&event), "Server event");
+                            }
                             match event {
                                 Event::LocationUpdate(location_update) => {
-                                    info!(?location_update, "LocationUpdate received");
-                                    let mut sound_map = sound_map.lock().unwrap();
+                                    info!(locations = location_update.locations.len(), "LocationUpdate received");
+
+                                    // 空・不完全な一覧でsound_mapを消してBGMの切り替えが止まらないようにする
+                                    let problems = validate_locations(&location_update.locations, &catalog);
+                                    if !problems.is_empty() {
+                                        if reject_invalid_locations {
+                                            Metrics::add(&metrics().rejected_location_updates, 1);
+                                            warn!(?problems, "Rejected invalid LocationUpdate, keeping previous sound_map");
+                                            continue;
+                                        }
+                                        warn!(?problems, "Applying LocationUpdate despite validation problems");
+                                    }
+
                                     let points = *current_points.lock().unwrap();
-                                    info!(old_sound_map_size = sound_map.len(), current_points = points, "Before updating sound_map");
+                                    info!(old_sound_map_size = sound_map.load().sounds.len(), current_points = points, "Before updating sound_map");
 
-                                    // 差分更新：新しいロケーションをマップに格納
-                                    let mut new_addresses = std::collections::HashSet::new();
+                                    // 新しいロケーションのリストから次のsound_mapを組み立てる
+                                    let mut sounds = HashMap::new();
+                                    let mut place_types = HashMap::new();
                                     for loc in &location_update.locations {
-                                        new_addresses.insert(loc.address.clone());
-                                        // ポイント数に応じたサウンドファイル名を生成
-                                        let sound_file = get_sound_file_from_place_type_and_points(&loc.place_type, points);
-
-                                        // place_typeをキャッシュ（インタラクション検知用）
-                                        {
-                                            let mut location_types = location_place_types.lock().unwrap();
-                                            location_types.insert(loc.address.clone(), loc.place_type.clone());
-                                        }
+                                        // 不正なアドレスはビーコンと照合できないため読み飛ばす（検証で拒否しない設定の場合）
+                                        let Ok(address) = loc.address.parse::<Address>() else {
+                                            continue;
+                                        };
+                                        // サーバーが音源のURLを指定した場合は優先する（アセット同期を待たずに新しいミックスを流すため）
+                                        // それ以外はポイント数に応じたサウンドファイル名を生成
+                                        let sound_file = if is_remote(&loc.sound_url) {
+                                            loc.sound_url.clone()
+                                        } else {
+                                            if !loc.sound_url.is_empty() {
+                                                warn!(address = %loc.address, sound_url = %loc.sound_url, "Ignoring sound_url that is not http(s)");
+                                            }
+                                            catalog.sound_file(&loc.place_type, points)
+                                        };
 
                                         info!(
                                             address = %loc.address,
@@ -341,38 +675,47 @@ async fn run_device_service_client(
                                             sound_file = %sound_file,
                                             "Processing location entry with points"
                                         );
-                                        sound_map.insert(loc.address.clone(), sound_file);
+                                        sounds.insert(address.clone(), sound_file);
+                                        // place_typeも保持（インタラクション検知・プリセット用）
+                                        place_types.insert(address, loc.place_type.clone());
                                     }
 
-                                    // 新しいリストに存在しないアドレスを削除
-                                    sound_map.retain(|addr, _| new_addresses.contains(addr));
-
-                                    // location_place_typesも同期
-                                    {
-                                        let mut location_types = location_place_types.lock().unwrap();
-                                        location_types.retain(|addr, _| new_addresses.contains(addr));
-                                    }
-
-                                    info!(new_sound_map_size = sound_map.len(), ?sound_map, "Updated sound_map with differential update");
+                                    // 新しいリストに存在しないアドレスも含めて一度に差し替える
+                                    let published = sound_map.update(|map| {
+                                        map.sounds = sounds.clone();
+                                        map.place_types = place_types.clone();
+                                    });
+                                    info!(
+                                        new_sound_map_size = published.sounds.len(),
+                                        generation = published.generation,
+                                        sound_map = ?published.sounds,
+                                        "Published new sound_map"
+                                    );
 
-                                    // current_location_type を更新
+                                    // 現在のロケーションを更新
                                     // 共有されている最新のRSSI情報を使って、最も近いロケーションを判断する
                                     let rssi_map = latest_rssi_map.lock().unwrap();
-                                    let closest_location = location_update.locations.iter()
-                                        .max_by_key(|loc| rssi_map.get(&loc.address).copied().unwrap_or(i16::MIN));
-
-                                    let mut current_location_type_guard = current_location_type.lock().unwrap();
-                                    if let Some(closest_location) = closest_location {
-                                        let base_type = get_base_location_type_from_place_type(&closest_location.place_type);
-                                        if *current_location_type_guard != base_type {
-                                            current_location_type_guard.clear();
-                                            current_location_type_guard.push_str(base_type);
-                                            info!(place_type = %closest_location.place_type, base_type = %base_type, rssi = %rssi_map.get(&closest_location.address).copied().unwrap_or(i16::MIN), "Updated current_location_type based on strongest RSSI");
-                                        }
+                                    let closest_location = published.place_types.iter()
+                                        .max_by_key(|(address, _)| rssi_map.get(*address).copied().unwrap_or(i16::MIN));
+
+                                    if let Some((address, place_type)) = closest_location {
+                                        let context = LocationContext {
+                                            address: Some(address.clone()),
+                                            place_type: place_type.clone(),
+                                            base_type: catalog.base_type_or_fallback(place_type).to_string(),
+                                        };
+                                        location_tx.send_if_modified(|current| {
+                                            if *current == context {
+                                                return false;
+                                            }
+                                            info!(?context, rssi = %rssi_map.get(address).copied().unwrap_or(i16::MIN), "Updated current location based on strongest RSSI");
+                                            *current = context;
+                                            true
+                                        });
                                     }
                                 }
                                 Event::PointUpdate(point_update) => {
-                                    debug!(?point_update, "PointUpdate received");
+                                    debug!(?point_update, ?origin, "PointUpdate received");
 
                                     // user_idの比較を先にして、MutexGuard��すぐに解放
                                     let is_my_address = {
@@ -382,31 +725,45 @@ async fn run_device_service_client(
 
                                     if is_my_address {
                                         let old_points = *current_points.lock().unwrap();
-                                        let new_points = point_update.points;
+                                        // 負のポイントは0として扱う（サーバー側の補正途中の値などで音源レベルを壊さない）
+                                        if point_update.points < 0 {
+                                            warn!(points = point_update.points, "Received negative points, clamping to 0");
+                                        }
+                                        let new_points = point_update.points.max(0);
 
                                         // ポイントが実際に変更された場合のみ処理
                                         if old_points != new_points {
                                             info!(user_id = %point_update.user_id, %old_points, %new_points, "Point value has changed. Updating.");
 
-                                            // 1. ポイント数を更新
+                                            // 1. ポイント数を更新（再起動に備えて保存）
                                             *current_points.lock().unwrap() = new_points;
+                                            events.publish(LocalEvent::PointsChanged { points: new_points });
+                                            if let Err(e) = PointsCache::save(&points_cache, &point_update.user_id, new_points) {
+                                                warn!("{:?}", e);
+                                            }
 
                                             // 2. sound_mapを新しいポイント数で再構築
                                             {
-                                                let mut sound_map_guard = sound_map.lock().unwrap();
-                                                let location_types_guard = location_place_types.lock().unwrap();
                                                 info!("Rebuilding sound_map with new points...");
                                                 // sound_map のキー（アドレス）はそのままに、値（サウンドファイル名）だけを更新
-                                                for (addr, sound_file) in sound_map_guard.iter_mut() {
-                                                    if let Some(place_type) = location_types_guard.get(addr) {
-                                                        *sound_file = get_sound_file_from_place_type_and_points(place_type, new_points);
+                                                // URLで指定された音源はポイント数に依らない
+                                                // 減少・リセットで存在しないレベルの音源を指さないよう、実在するファイルを選ぶ
+                                                let published = sound_map.update(|map| {
+                                                    let SoundMap { sounds, place_types, .. } = map;
+                                                    for (addr, sound_file) in sounds.iter_mut().filter(|(_, sound_file)| !is_remote(sound_file)) {
+                                                        if let Some(place_type) = place_types.get(addr) {
+                                                            match catalog.existing_sound_file(place_type, new_points) {
+                                                                Some(file) => *sound_file = file,
+                                                                None => warn!(%place_type, points = new_points, current = %sound_file, "No sound asset found for points, keeping current sound"),
+                                                            }
+                                                        }
                                                     }
-                                                }
-                                                info!(?sound_map_guard, "Rebuilt sound_map complete.");
+                                                });
+                                                info!(generation = published.generation, sound_map = ?published.sounds, "Rebuilt sound_map complete.");
                                             }
 
 
-                                            // 3. ポイント増加時のSE再生（初回は除く）
+                                            // 3. ポイント変化時のSE再生（初回は除く）
                                             let is_initialized = {
                                                 let mut initialized = points_initialized.lock().unwrap();
                                                 if !*initialized {
@@ -418,13 +775,19 @@ async fn run_device_service_client(
                                                 }
                                             };
 
-                                            if is_initialized && new_points > old_points {
-                                                info!(points_gained = new_points - old_points, "Points increased! Playing sound effect");
-                                                let se_request = crate::audio_system::audio_main::SePlayRequest {
-                                                    file_path: "se-point.mp3".to_string(),
+                                            // 合計が閾値に達した場合はファンファーレなどの特別なSEを鳴らす
+                                            // 減少・リセットは設定されたSEを鳴らす（未設定なら無音）
+                                            let se_file = point_change_se(&point_se, old_points, new_points, is_initialized, origin);
+                                            if let Some(se_file) = se_file.filter(|se_file| se_limit.allow(se_file)) {
+                                                info!(points_delta = new_points - old_points, new_points, se_file, "Points changed! Playing sound effect");
+                                                let se_request = SePlayRequest {
+                                                    file_path: se_file.to_string(),
+                                                    priority: false,
+                                                    gain: None,
+                                                    command_id: None,
                                                 };
                                                 if let Err(e) = se_tx.send(se_request).await {
-                                                    error!("Failed to send SE play request for point gain: {}", e);
+                                                    error!("Failed to send SE play request for point change: {}", e);
                                                 }
                                             }
                                         }
@@ -436,7 +799,7 @@ async fn run_device_service_client(
                                     }
                                 }
                                 Event::SoundSettingUpdate(sound_setting_update) => {
-                                    debug!(?sound_setting_update, "SoundSettingUpdate received");
+                                    debug!("SoundSettingUpdate received");
                                     if let Some(settings) = sound_setting_update.settings {
                                         if let Err(e) = sound_setting_tx.send(settings).await {
                                             error!("Failed to send sound settings: {}", e);
@@ -444,49 +807,222 @@ async fn run_device_service_client(
                                     }
                                 }
                                 Event::MoonlightUpdate(moonlight_update) => {
-                                    info!(?moonlight_update, "MoonlightUpdate received");
+                                    info!(entries = moonlight_update.moonlights.len(), "MoonlightUpdate received");
 
-                                    // 自分のデバイスのenabledフラグを確認
+                                    // 全体フラグ（ワイルドカード）と自分のデバイスのenabledフラグを確認
                                     let my_device_id = my_address.lock().unwrap().clone();
-                                    if let Some(device_id) = my_device_id {
-                                        // moonlightsリストから自分のデバイスを探す
-                                        let mut found = false;
-                                        for moonlight in &moonlight_update.moonlights {
-                                            if moonlight.device == device_id || moonlight.address == device_id {
-                                                info!(
-                                                    device = %moonlight.device,
-                                                    address = %moonlight.address,
-                                                    enabled = moonlight.enabled,
-                                                    "Found my device in MoonlightUpdate"
-                                                );
-
-                                                let state = SystemEnabledState {
-                                                    enabled: moonlight.enabled,
-                                                    target_device_id: device_id.clone(),
-                                                };
+                                    let (global, device) =
+                                        enable_state::resolve_moonlights(&moonlight_update.moonlights, my_device_id.as_deref());
+                                    let global_enabled = global.enabled;
 
-                                                if let Err(e) = system_enabled_tx.send(state) {
-                                                    error!("Failed to send system enabled state: {}", e);
-                                                } else {
-                                                    info!(enabled = moonlight.enabled, "System enabled state sent successfully");
-                                                }
-                                                found = true;
-                                                break;
-                                            }
+                                    match (&my_device_id, device) {
+                                        (Some(device_id), Some(device)) => {
+                                            info!(
+                                                my_device_id = %device_id,
+                                                enabled = device.enabled,
+                                                sequence = device.sequence,
+                                                global_enabled,
+                                                global_sequence = global.sequence,
+                                                "Found my device in MoonlightUpdate"
+                                            );
                                         }
-
-                                        if !found {
+                                        (Some(device_id), None) => {
                                             warn!(
                                                 my_device_id = %device_id,
                                                 moonlights_count = moonlight_update.moonlights.len(),
-                                                "My device not found in MoonlightUpdate - ignoring update"
+                                                global_enabled,
+                                                "My device not found in MoonlightUpdate - applying global flag only"
                                             );
                                         }
+                                        (None, _) => {
+                                            warn!(global_enabled, "Received MoonlightUpdate but my device ID is not yet set - applying global flag only");
+                                        }
+                                    }
+
+                                    if enable_state::apply_moonlights(&enabled_tx, global, device) {
+                                        info!(state = ?*enabled_tx.borrow(), "System enabled state updated");
+                                    } else {
+                                        debug!(state = ?*enabled_tx.borrow(), "System enabled state unchanged");
+                                    }
+
+                                    // 適用後の更新番号の方が新しければ、古いフラグとして無視されている
+                                    let state = *enabled_tx.borrow();
+                                    let stale = global.sequence < state.global_sequence
+                                        || device.is_some_and(|device| device.sequence < state.device_sequence);
+                                    let result = if stale {
+                                        Err((CommandFailure::Stale, format!("newer flags already applied (enabled={})", state.enabled)))
+                                    } else {
+                                        Ok(())
+                                    };
+                                    report_command(&events, &moonlight_update.command_id, CommandKind::Enable, result);
+                                }
+                                Event::SePlay(se_play) => {
+                                    info!(?se_play, "SePlay received");
+
+                                    let my_device_id = my_address.lock().unwrap().clone();
+                                    if !is_target_device(&se_play.devices, my_device_id.as_deref()) {
+                                        debug!(devices = ?se_play.devices, "SePlay is not for this device, ignoring.");
+                                        continue;
+                                    }
+
+                                    if !catalog.is_valid_sound_file(&se_play.file) {
+                                        warn!(file = %se_play.file, "Rejected SePlay with invalid file name");
+                                        let detail = format!("invalid file name: {}", se_play.file);
+                                        report_command(&events, &se_play.command_id, CommandKind::SePlay, Err((CommandFailure::InvalidRequest, detail)));
+                                        continue;
+                                    }
+
+                                    if !se_limit.allow(&se_play.file) {
+                                        let detail = format!("too many SE triggers: {}", se_play.file);
+                                        report_command(&events, &se_play.command_id, CommandKind::SePlay, Err((CommandFailure::RateLimited, detail)));
+                                        continue;
+                                    }
+
+                                    // 再生できたかどうかはオーディオスレッドが報告する
+                                    let se_request = SePlayRequest {
+                                        file_path: se_play.file,
+                                        priority: se_play.priority,
+                                        gain: None,
+                                        command_id: Some(se_play.command_id.clone()).filter(|id| !id.is_empty()),
+                                    };
+                                    if let Err(e) = se_tx.send(se_request).await {
+                                        error!("Failed to send SE play request from server: {}", e);
+                                        let detail = "audio system is not running".to_string();
+                                        report_command(&events, &se_play.command_id, CommandKind::SePlay, Err((CommandFailure::Unavailable, detail)));
+                                    }
+                                }
+                                Event::BgmOverride(bgm_override) => {
+                                    info!(?bgm_override, "BgmOverride received");
+
+                                    let my_device_id = my_address.lock().unwrap().clone();
+                                    if !is_target_device(&bgm_override.devices, my_device_id.as_deref()) {
+                                        debug!(devices = ?bgm_override.devices, "BgmOverride is not for this device, ignoring.");
+                                        continue;
+                                    }
+
+                                    // ファイル名の指定を優先し、なければplace_typeから音源を決める
+                                    let sound = if !bgm_override.file.is_empty() {
+                                        if !catalog.is_valid_sound_file(&bgm_override.file) {
+                                            warn!(file = %bgm_override.file, "Rejected BgmOverride with invalid file name");
+                                            let detail = format!("invalid file name: {}", bgm_override.file);
+                                            report_command(&events, &bgm_override.command_id, CommandKind::BgmOverride, Err((CommandFailure::InvalidRequest, detail)));
+                                            continue;
+                                        }
+                                        Some(bgm_override.file)
+                                    } else if !bgm_override.place_type.is_empty() {
+                                        let points = *current_points.lock().unwrap();
+                                        Some(catalog.sound_file(&bgm_override.place_type, points))
+                                    } else {
+                                        None
+                                    };
+
+                                    // 無い音源に切り替えると再生が止まるため、上書きせずに報告する
+                                    if bgm_override.duration_ms > 0 {
+                                        if let Some(missing) = sound.as_deref().filter(|sound| !Path::new(sound).exists()) {
+                                            warn!(sound = %missing, "Rejected BgmOverride with missing asset");
+                                            let detail = format!("asset not found: {}", missing);
+                                            report_command(&events, &bgm_override.command_id, CommandKind::BgmOverride, Err((CommandFailure::AssetMissing, detail)));
+                                            continue;
+                                        }
+                                    }
+
+                                    let request = BgmOverrideRequest {
+                                        sound,
+                                        duration: Duration::from_millis(bgm_override.duration_ms as u64),
+                                    };
+                                    let result = match bgm_override_tx.send(request).await {
+                                        Ok(()) => Ok(()),
+                                        Err(e) => {
+                                            error!("Failed to send BGM override request: {}", e);
+                                            Err((CommandFailure::Unavailable, "audio system is not running".to_string()))
+                                        }
+                                    };
+                                    report_command(&events, &bgm_override.command_id, CommandKind::BgmOverride, result);
+                                }
+                                Event::LiveStream(live_stream) => {
+                                    info!(?live_stream, "LiveStream received");
+
+                                    let my_device_id = my_address.lock().unwrap().clone();
+                                    if !is_target_device(&live_stream.devices, my_device_id.as_deref()) {
+                                        debug!(devices = ?live_stream.devices, "LiveStream is not for this device, ignoring.");
+                                        continue;
+                                    }
+
+                                    // ライブ配信はBGM上書きとして流し、終了・期限切れで通常の選択に戻る
+                                    let sound = if live_stream.uri.is_empty() {
+                                        None
+                                    } else if is_live(&live_stream.uri) {
+                                        Some(live_stream.uri)
                                     } else {
-                                        warn!("Received MoonlightUpdate but my device ID is not yet set - ignoring update");
+                                        warn!(uri = %live_stream.uri, "Rejected LiveStream with unsupported URI");
+                                        continue;
+                                    };
+                                    let duration = match live_stream.max_duration_ms {
+                                        0 => LIVE_STREAM_MAX_DURATION,
+                                        ms => Duration::from_millis(ms as u64),
+                                    };
+                                    if let Err(e) = bgm_override_tx.send(BgmOverrideRequest { sound, duration }).await {
+                                        error!("Failed to send live stream request: {}", e);
+                                    }
+                                }
+                                Event::VolumeUpdate(volume_update) => {
+                                    info!(?volume_update, "VolumeUpdate received");
+
+                                    let my_device_id = my_address.lock().unwrap().clone();
+                                    if !is_target_device(&volume_update.devices, my_device_id.as_deref()) {
+                                        debug!(devices = ?volume_update.devices, "VolumeUpdate is not for this device, ignoring.");
+                                        continue;
+                                    }
+
+                                    let master_volume = MasterVolume {
+                                        volume: volume_update.master_volume,
+                                        muted: volume_update.muted,
+                                    };
+                                    volume_tx.send_if_modified(|current| {
+                                        let changed = *current != master_volume;
+                                        *current = master_volume;
+                                        changed
+                                    });
+                                    report_command(&events, &volume_update.command_id, CommandKind::Volume, Ok(()));
+                                }
+                                Event::LanguageUpdate(language_update) => {
+                                    info!(?language_update, "LanguageUpdate received");
+
+                                    let my_device_id = my_address.lock().unwrap().clone();
+                                    if !is_target_device(&language_update.devices, my_device_id.as_deref()) {
+                                        debug!(devices = ?language_update.devices, "LanguageUpdate is not for this device, ignoring.");
+                                        continue;
+                                    }
+
+                                    // 空の場合はユニットの設定の言語に戻す
+                                    let language = Some(language_update.language).filter(|language| !language.is_empty());
+                                    language_tx.send_if_modified(|current| {
+                                        let changed = *current != language;
+                                        *current = language;
+                                        changed
+                                    });
+                                }
+                                Event::UploadThrottle(upload_throttle) => {
+                                    info!(?upload_throttle, "UploadThrottle received");
+                                    upload_rate.throttle(
+                                        Duration::from_millis(upload_throttle.batch_interval_ms as u64),
+                                        Duration::from_millis(upload_throttle.duration_ms as u64),
+                                    );
+                                }
+                                Event::FleetCommand(fleet_command) => {
+                                    info!(?fleet_command, "FleetCommand received");
+
+                                    // 全ユニット向けのため、対象デバイスの判定は行わない
+                                    let ack = fleet.dispatch(fleet_command).await;
+                                    if fleet_ack_tx.send(ack).await.is_err() {
+                                        warn!("Failed to send fleet command acknowledgement - stream is closed");
                                     }
                                 }
                             }
+                        } else {
+                            // 未知のoneofタグはデコード時に読み飛ばされ、eventがNoneになる
+                            Metrics::add(&metrics().unknown_events, 1);
+                            warn!(server_schema_version = res.schema_version, "Received unknown or empty event - ignoring");
                         }
                     }
                     Err(e) => error!("DeviceService stream error: {}", e),
@@ -499,22 +1035,53 @@ async fn run_device_service_client(
     }
 }
 
-#[instrument(skip(client, time_offset))]
+/// TimeServiceの応答から時計のオフセットを更新する（`client_receive_time` は応答を受信したUNIX時刻）
+fn apply_time_sync(clock: &Clock, res: &SyncTimeResponse, client_receive_time: i64) {
+    let client_send_time = res.client_send_time;
+    let server_receive_time = res.server_receive_time;
+    let server_send_time = res.server_send_time;
+
+    // NTPの計算式を参考にオフセットと遅延を計算
+    let round_trip_delay = (client_receive_time - client_send_time) - (server_send_time - server_receive_time);
+    let offset = ((server_receive_time - client_send_time) + (server_send_time - client_receive_time)) / 2;
+
+    clock.update_from_time_service(offset, round_trip_delay);
+
+    info!(
+        offset_ms = offset / 1_000_000,
+        delay_ms = round_trip_delay / 1_000_000,
+        "Time synchronized"
+    );
+}
+
+/// 記録したセッションのTimeServiceの応答を、記録時の受信時刻で時計に反映する
+#[instrument(skip_all)]
+async fn replay_time_sync(entries: Arc<Vec<SessionEntry>>, clock: Arc<Clock>) {
+    let mut replay = spawn_replay(entries, |message| match message {
+        SessionMessage::TimeReceived(response) => Some(*response),
+        _ => None,
+    });
+    while let Some((entry, response)) = replay.recv().await {
+        apply_time_sync(&clock, &response, entry.unix_ns);
+    }
+}
+
+#[instrument(skip(client, clock, recorder))]
 async fn run_time_sync_client(
     mut client: TimeServiceClient<Channel>,
-    time_offset: Arc<Mutex<i64>>,
+    clock: Arc<Clock>,
+    recorder: Option<Arc<SessionRecorder>>,
 ) {
     info!("Starting TimeService client for time synchronization...");
+    let time = clock.time_source();
 
     let (request_tx, request_rx) = mpsc::channel(1);
 
     // 5秒ごとにSyncTimeRequestを送信するタスク
+    let request_time = Arc::clone(&time);
     tokio::spawn(async move {
         loop {
-            let client_send_time = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_nanos() as i64;
+            let client_send_time = request_time.unix_ns();
             let request = SyncTimeRequest { client_send_time };
             if request_tx.send(request).await.is_err() {
                 error!("Failed to send time sync request, receiver is closed.");
@@ -524,7 +1091,13 @@ async fn run_time_sync_client(
         }
     });
 
-    let request_stream = tokio_stream::wrappers::ReceiverStream::new(request_rx);
+    let recorder_for_requests = recorder.clone();
+    let request_stream = tokio_stream::wrappers::ReceiverStream::new(request_rx).map(move |request| {
+        if let Some(recorder) = &recorder_for_requests {
+            recorder.record(SessionMessage::TimeSent(request));
+        }
+        request
+    });
 
     match client.sync_time(request_stream).await {
         Ok(response) => {
@@ -533,29 +1106,11 @@ async fn run_time_sync_client(
             while let Some(item) = stream.next().await {
                 match item {
                     Ok(res) => {
-                        let client_receive_time = SystemTime::now()
-                            .duration_since(UNIX_EPOCH)
-                            .unwrap()
-                            .as_nanos() as i64;
-
-                        let client_send_time = res.client_send_time;
-                        let server_receive_time = res.server_receive_time;
-                        let server_send_time = res.server_send_time;
-
-                        // NTPの計算式を参考にオフセットと遅延を計算
-                        let round_trip_delay = (client_receive_time - client_send_time) - (server_send_time - server_receive_time);
-                        let offset = ((server_receive_time - client_send_time) + (server_send_time - client_receive_time)) / 2;
-
-                        {
-                            let mut time_offset_guard = time_offset.lock().unwrap();
-                            *time_offset_guard = offset;
+                        let client_receive_time = time.unix_ns();
+                        if let Some(recorder) = &recorder {
+                            recorder.record(SessionMessage::TimeReceived(res));
                         }
-
-                        info!(
-                            offset_ms = offset / 1_000_000,
-                            delay_ms = round_trip_delay / 1_000_000,
-                            "Time synchronized"
-                        );
+                        apply_time_sync(&clock, &res, client_receive_time);
                     }
                     Err(e) => error!("TimeService stream error: {}", e),
                 }
@@ -567,106 +1122,380 @@ async fn run_time_sync_client(
     }
 }
 
-#[instrument(skip(rx, time_offset, sound_map, se_tx, system_enabled_tx))]
+/// gRPCの接続失敗が `websocket_fallback_after` 回続き、WebSocketに切り替える状態かどうか
+fn uses_websocket_fallback(server: &ServerConfig, grpc_failures: u32) -> bool {
+    server.transport == TransportKind::Grpc && server.websocket_url.is_some() && grpc_failures >= server.websocket_fallback_after
+}
+
+/// 設定された通信方式でサーバーに接続する（TimeServiceはgRPCの場合のみ）
+///
+/// gRPCの接続失敗が `websocket_fallback_after` 回続いた場合は、WebSocketに切り替える。
+async fn connect_transport(
+    server: &ServerConfig,
+    upload: &UploadConfig,
+    grpc_failures: u32,
+) -> anyhow::Result<(Box<dyn Transport>, Option<TimeServiceClient<Channel>>)> {
+    match server.transport {
+        TransportKind::Grpc => {
+            if let Some(websocket_url) = &server.websocket_url {
+                if uses_websocket_fallback(server, grpc_failures) {
+                    warn!(grpc_failures, %websocket_url, "gRPC keeps failing - falling back to WebSocket");
+                    return Ok((Box::new(WebSocketTransport::new(websocket_url.clone())), None));
+                }
+            }
+
+            let endpoint = Endpoint::from_shared(server.grpc_url.clone())?.connect_timeout(Duration::from_secs(5));
+            let channel = egress::connect_grpc(endpoint).await?;
+            info!("Successfully connected to gRPC server.");
+
+            // DeviceServiceクライアント
+            let mut device_client = DeviceServiceClient::new(channel.clone())
+                .accept_compressed(CompressionEncoding::Gzip)
+                .accept_compressed(CompressionEncoding::Zstd);
+            match upload.compression {
+                UploadCompression::None => {}
+                UploadCompression::Gzip => device_client = device_client.send_compressed(CompressionEncoding::Gzip),
+                UploadCompression::Zstd => device_client = device_client.send_compressed(CompressionEncoding::Zstd),
+            }
+
+            // TimeServiceクライアント
+            let time_client = TimeServiceClient::new(channel);
+
+            Ok((Box::new(GrpcTransport::new(device_client)), Some(time_client)))
+        }
+        TransportKind::Mqtt => {
+            // MQTTは接続をイベントループ内で確立するため、ここでは失敗しない
+            Ok((Box::new(MqttTransport::new(server.mqtt.clone())), None))
+        }
+    }
+}
+
+/// サーバーとの接続状態を公開する（変わったときだけイベントを発行する）
+struct ConnectionReporter {
+    events: EventBus,
+    state_tx: watch::Sender<Option<ConnectionState>>,
+}
+
+impl ConnectionReporter {
+    fn set(&self, state: ConnectionState) {
+        let changed = self.state_tx.send_if_modified(|current| {
+            if *current == Some(state) {
+                return false;
+            }
+            *current = Some(state);
+            true
+        });
+        if changed {
+            info!(?state, "Server connection state changed");
+            self.events.publish(LocalEvent::ServerConnection { state, connected: state.is_connected() });
+        }
+    }
+}
+
+/// 再接続まで `delay` 待つ。終了手順が始まった場合は待たずに `true` を返す
+///
+/// 未接続の間は送るストリームが無いため、デバイス情報を送り切ったものとして報告する。
+async fn wait_reconnect(shutdown: &ShutdownController, delay: Duration) -> bool {
+    tokio::select! {
+        _ = tokio::time::sleep(delay) => false,
+        _ = shutdown.reached(ShutdownPhase::FlushUploads) => {
+            info!("Shutting down while disconnected - not reconnecting");
+            shutdown.complete(ShutdownPhase::FlushUploads);
+            true
+        }
+    }
+}
+
+/// 再起動をまたいで保持する状態を保存するファイル
+#[derive(Debug, Clone)]
+pub struct StatePaths {
+    /// 最後に受信したポイント数
+    pub points_cache: PathBuf,
+    /// インタラクションの重複判定
+    pub interaction_state: PathBuf,
+}
+
+impl Default for StatePaths {
+    fn default() -> Self {
+        Self { points_cache: PathBuf::from(DEFAULT_POINTS_CACHE_PATH), interaction_state: PathBuf::from(DEFAULT_INTERACTION_STATE_PATH) }
+    }
+}
+
+/// gRPC（またはMQTT）のサーバーとの接続（`connect_main` の引数をまとめたもの）
+pub struct ServerConnection {
+    pub server: ServerConfig,
+    pub upload: UploadConfig,
+    pub interaction: InteractionConfig,
+    pub calibration: Arc<CalibrationStore>,
+    pub state_paths: StatePaths,
+    pub visitor_fusion: Option<Arc<VisitorFusion>>,
+    pub connected_chime: Option<String>,
+    pub rx: broadcast::Receiver<Arc<DeviceSnapshot>>,
+    pub clock: Arc<Clock>,
+    pub sound_setting_tx: mpsc::Sender<SoundSetting>,
+    pub se_tx: mpsc::Sender<SePlayRequest>,
+    pub bgm_override_tx: mpsc::Sender<BgmOverrideRequest>,
+    pub volume_tx: watch::Sender<MasterVolume>,
+    pub language_tx: watch::Sender<Option<String>>,
+    pub enabled_tx: watch::Sender<EnabledState>,
+    pub events: EventBus,
+    pub connection_tx: watch::Sender<Option<ConnectionState>>,
+    pub status_rx: watch::Receiver<PlaybackStatus>,
+    pub sound_map: Arc<SharedSoundMap>,
+    pub my_address: Arc<Mutex<Option<String>>>,
+    pub current_points: Arc<Mutex<i32>>,
+    pub location_tx: watch::Sender<LocationContext>,
+    pub server_messages_tx: watch::Sender<LastServerMessages>,
+    pub config_store: Arc<ConfigStore>,
+    pub shutdown: Arc<ShutdownController>,
+}
+
+impl ServerLink for ServerConnection {
+    fn name(&self) -> &'static str {
+        match self.server.transport {
+            TransportKind::Grpc => "grpc",
+            TransportKind::Mqtt => "mqtt",
+        }
+    }
+
+    fn run(self: Box<Self>) -> BoxFuture<'static, anyhow::Result<()>> {
+        let ServerConnection { server, upload, interaction, calibration, state_paths, visitor_fusion, connected_chime, rx, clock, sound_setting_tx, se_tx, bgm_override_tx, volume_tx, language_tx, enabled_tx, events, connection_tx, status_rx, sound_map, my_address, current_points, location_tx, server_messages_tx, config_store, shutdown } = *self;
+        Box::pin(connect_main(server, upload, interaction, calibration, state_paths, visitor_fusion, connected_chime, rx, clock, sound_setting_tx, se_tx, bgm_override_tx, volume_tx, language_tx, enabled_tx, events, connection_tx, status_rx, sound_map, my_address, current_points, location_tx, server_messages_tx, config_store, shutdown))
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+#[instrument(skip(calibration, visitor_fusion, rx, clock, sound_map, se_tx, enabled_tx, events, connection_tx, server_messages_tx, config_store, shutdown))]
 pub async fn connect_main(
-    rx: broadcast::Receiver<Arc<DeviceInfo>>,
-    time_offset: Arc<Mutex<i64>>,
+    server: ServerConfig,
+    upload: UploadConfig,
+    interaction: InteractionConfig,
+    calibration: Arc<CalibrationStore>,
+    state_paths: StatePaths,
+    visitor_fusion: Option<Arc<VisitorFusion>>,
+    connected_chime: Option<String>,
+    rx: broadcast::Receiver<Arc<DeviceSnapshot>>,
+    clock: Arc<Clock>,
     sound_setting_tx: mpsc::Sender<SoundSetting>,
-    se_tx: mpsc::Sender<crate::audio_system::audio_main::SePlayRequest>,
-    system_enabled_tx: broadcast::Sender<SystemEnabledState>,
-    sound_map: Arc<Mutex<HashMap<String, String>>>,
+    se_tx: mpsc::Sender<SePlayRequest>,
+    bgm_override_tx: mpsc::Sender<BgmOverrideRequest>,
+    volume_tx: watch::Sender<MasterVolume>,
+    language_tx: watch::Sender<Option<String>>,
+    enabled_tx: watch::Sender<EnabledState>,
+    events: EventBus,
+    connection_tx: watch::Sender<Option<ConnectionState>>,
+    status_rx: watch::Receiver<PlaybackStatus>,
+    sound_map: Arc<SharedSoundMap>,
     my_address: Arc<Mutex<Option<String>>>,
     current_points: Arc<Mutex<i32>>,
-    current_location_type: Arc<Mutex<String>>,
+    location_tx: watch::Sender<LocationContext>,
+    server_messages_tx: watch::Sender<LastServerMessages>,
+    config_store: Arc<ConfigStore>,
+    shutdown: Arc<ShutdownController>,
 ) -> anyhow::Result<()> {
-    let server_addr = "http://34.85.68.246:50051";
-    info!("Connecting to gRPC server at {}", server_addr);
+    match server.transport {
+        TransportKind::Grpc => info!("Connecting to gRPC server at {}", server.grpc_url),
+        TransportKind::Mqtt => info!("Connecting to MQTT broker at {}:{}", server.mqtt.host, server.mqtt.port),
+    }
+    if egress::proxy().is_some() && (server.transport == TransportKind::Mqtt || server.websocket_url.is_some()) {
+        warn!("MQTT and WebSocket connections do not go through the configured proxy");
+    }
+
+    // 接続チャイム（初回接続時に一度だけ鳴らすため、鳴らしたら取り出す）
+    let connected_chime = Arc::new(Mutex::new(connected_chime));
+
+    // gRPCの連続接続失敗回数（WebSocketへのフォールバック判定用）
+    let mut grpc_failures: u32 = 0;
+    // 再接続の待ち時間と、切断が続いている時間
+    let mut backoff = ReconnectBackoff::new(server.reconnect.clone(), clock.time_source());
+    // デバイス情報の送信間隔（サーバーからの抑制は再接続をまたいで続ける）
+    let upload_rate = UploadRate::new(upload.clone(), clock.time_source());
+    // 全ユニット向けのコマンド（実行済みのコマンドIDは再接続をまたいで覚えておく）
+    let fleet = FleetDispatcher::new(se_tx.clone(), volume_tx.clone(), config_store);
+    let se_limit = SeRateLimiter::new(server.se_rate_limit.clone(), clock.time_source());
+    let connection = ConnectionReporter { events: events.clone(), state_tx: connection_tx };
+    connection.set(ConnectionState::Connecting);
+
+    // インタラクション検知（再接続をまたいで1組だけ動かし、connect_mainを抜けると止まる）
+    let interaction_tasks = spawn_interaction_tasks(
+        &server.players_api_url,
+        &interaction,
+        Arc::clone(&calibration),
+        &state_paths.interaction_state,
+        visitor_fusion.clone(),
+        rx.resubscribe(),
+        se_tx.clone(),
+        events.clone(),
+        Arc::clone(&sound_map),
+        Arc::clone(&my_address),
+        clock.time_source(),
+    );
+
+    // セッションの記録・再生（再生する場合はサーバーに接続しない）
+    let recorder = match server.record_session.as_deref() {
+        Some(path) => match SessionRecorder::create(Path::new(path)) {
+            Ok(recorder) => Some(recorder),
+            Err(e) => {
+                warn!("Session recording disabled: {:?}", e);
+                None
+            }
+        },
+        None => None,
+    };
+    let replay = server.replay_session.as_deref().map(|path| load_session(Path::new(path))).transpose()?;
+
+    let device_service = DeviceServiceContext {
+        upload_rate,
+        fleet,
+        se_limit,
+        players_api_url: server.players_api_url.clone(),
+        reject_invalid_locations: server.reject_invalid_locations,
+        point_se: interaction.point_se.clone(),
+        points_cache: state_paths.points_cache.clone(),
+        visitor_fusion,
+        connected_chime,
+        sound_setting_tx,
+        se_tx,
+        bgm_override_tx,
+        volume_tx,
+        language_tx,
+        enabled_tx: enabled_tx.clone(),
+        events: events.clone(),
+        status_rx,
+        sound_map,
+        my_address,
+        current_points,
+        location_tx,
+        server_messages_tx,
+        latest_rssi_map: Arc::clone(&interaction_tasks.latest_rssi),
+        shutdown: Arc::clone(&shutdown),
+    };
 
     // サーバーに接続できるまでリトライ
     loop {
-        match Endpoint::from_static(server_addr)
-            .connect_timeout(Duration::from_secs(5))
-            .connect()
-            .await
+        let fallback = replay.is_none() && uses_websocket_fallback(&server, grpc_failures);
+        let connected = match &replay {
+            Some(entries) => Ok((Box::new(ReplayTransport::new(Arc::clone(entries))) as Box<dyn Transport>, None)),
+            None => connect_transport(&server, &upload, grpc_failures).await,
+        };
+        match connected
         {
-            Ok(channel) => {
-                info!("Successfully connected to gRPC server.");
-
-                // DeviceServiceクライアント
-                let device_client = DeviceServiceClient::new(channel.clone());
-
-                // TimeServiceクライアント
-                let time_client = TimeServiceClient::new(channel);
-
-                info!("Spawning gRPC client tasks...");
-                let device_service_handle = {
-                    let sound_map_clone = Arc::clone(&sound_map);
-                    let my_address_clone = Arc::clone(&my_address);
-                    let current_points_clone = Arc::clone(&current_points);
-                    let current_location_type_clone = Arc::clone(&current_location_type);
-                    let sound_setting_tx_clone = sound_setting_tx.clone();
-                    let se_tx_clone = se_tx.clone();
-                    let system_enabled_tx_clone = system_enabled_tx.clone();
-                    let rx_for_device_service = rx.resubscribe();
-                    tokio::spawn(run_device_service_client(
-                        device_client,
-                        rx_for_device_service,
-                        sound_setting_tx_clone,
-                        se_tx_clone,
-                        system_enabled_tx_clone,
-                        sound_map_clone,
-                        my_address_clone,
-                        current_points_clone,
-                        current_location_type_clone,
-                    ))
+            Ok((transport, time_client)) => {
+                let transport: Box<dyn Transport> = match &recorder {
+                    Some(recorder) => Box::new(RecordingTransport::new(transport, Arc::clone(recorder))),
+                    None => transport,
+                };
+                // フォールバックで接続した場合も、次回は再びgRPCから試す
+                grpc_failures = 0;
+                if let Some(offline) = backoff.offline_for() {
+                    info!(offline_ms = offline.as_millis() as u64, "Connected to server");
+                }
+                backoff.connected();
+                metrics().set_server_offline(Duration::ZERO);
+                connection.set(if fallback { ConnectionState::Degraded } else { ConnectionState::Connected });
+                info!("Spawning client tasks...");
+                let device_service_handle = tokio::spawn(run_device_service_client(transport, rx.resubscribe(), device_service.clone()));
+                let time_service_handle = match &replay {
+                    Some(entries) => Some(tokio::spawn(replay_time_sync(Arc::clone(entries), Arc::clone(&clock)))),
+                    None => time_client
+                        .map(|time_client| tokio::spawn(run_time_sync_client(time_client, Arc::clone(&clock), recorder.clone()))),
                 };
-                let time_service_handle =
-                    tokio::spawn(run_time_sync_client(time_client, time_offset.clone()));
 
                 // 両方のタスクが終了するのを待つ
-                let (device_result, time_result) = tokio::join!(device_service_handle, time_service_handle);
-
+                let device_result = device_service_handle.await;
                 if let Err(e) = device_result {
                     error!("Device service task failed: {}", e);
                 }
-                if let Err(e) = time_result {
-                    error!("Time service task failed: {}", e);
+                if shutdown.is_reached(ShutdownPhase::CloseStreams) {
+                    // 時刻同期は単発のRPCの繰り返しのため、待たずに止める
+                    if let Some(time_service_handle) = time_service_handle {
+                        time_service_handle.abort();
+                    }
+                    info!("Server streams closed for shutdown");
+                    return Ok(());
+                }
+                if let Some(time_service_handle) = time_service_handle {
+                    if let Err(e) = time_service_handle.await {
+                        error!("Time service task failed: {}", e);
+                    }
                 }
 
-                info!("gRPC client tasks finished. Retrying in 5 seconds...");
+                backoff.disconnected();
+                let delay = backoff.next_delay();
+                info!(delay_ms = delay.as_millis() as u64, "Client tasks finished. Reconnecting after backoff...");
+                connection.set(ConnectionState::Connecting);
 
                 // 接続が切れたので、システムを有効状態にしておく
-                if let Some(my_addr) = my_address.lock().unwrap().clone() {
-                    let state = SystemEnabledState {
-                        enabled: true,
-                        target_device_id: my_addr,
-                    };
-                    if let Err(e) = system_enabled_tx.send(state) {
-                        error!("Failed to send system enabled state: {}", e);
-                    }
+                if enable_state::reset(&enabled_tx) {
+                    info!("Connection lost - system re-enabled");
                 }
 
-                tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+                if wait_reconnect(&shutdown, delay).await {
+                    return Ok(());
+                }
             }
             Err(e) => {
+                backoff.disconnected();
+                let delay = backoff.next_delay();
                 error!(
-                    "Failed to connect to server: {:?}. Retrying in 5 seconds...",
+                    delay_ms = delay.as_millis() as u64,
+                    "Failed to connect to server: {:?}. Retrying after backoff...",
                     e
                 );
+                grpc_failures = grpc_failures.saturating_add(1);
+                Metrics::add(&metrics().server_connect_failures, 1);
+
+                // 切断が長く続いている場合は一度だけ通知する（Webhookなどで運用者に知らせる）
+                let offline = backoff.offline_for().unwrap_or_default();
+                metrics().set_server_offline(offline);
+                if backoff.should_alert() {
+                    error!(offline_ms = offline.as_millis() as u64, "Server has been unreachable for too long");
+                    events.publish(LocalEvent::ServerOffline { offline_ms: offline.as_millis() as u64 });
+                }
+                connection.set(if backoff.alerted() { ConnectionState::Offline } else { ConnectionState::Connecting });
 
                 // 接続失敗時も、システムを有効状態にしておく
-                if let Some(my_addr) = my_address.lock().unwrap().clone() {
-                    let state = SystemEnabledState {
-                        enabled: true,
-                        target_device_id: my_addr,
-                    };
-                    if let Err(e) = system_enabled_tx.send(state) {
-                        error!("Failed to send system enabled state: {}", e);
-                    }
+                if enable_state::reset(&enabled_tx) {
+                    info!("Connection failed - system re-enabled");
                 }
 
-                tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
+                if wait_reconnect(&shutdown, delay).await {
+                    return Ok(());
+                }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config_system::config_main::PointSeThreshold;
+
+    fn point_se() -> PointSeConfig {
+        PointSeConfig {
+            default: "se-point.mp3".to_string(),
+            thresholds: vec![PointSeThreshold { points: 10, file: "se-fanfare.mp3".to_string() }],
+            decrease: Some("se-decrease.mp3".to_string()),
+            reset: None,
+        }
+    }
+
+    #[test]
+    fn streamed_point_changes_play_the_se() {
+        let config = point_se();
+        assert_eq!(point_change_se(&config, 1, 2, true, PointsOrigin::Stream), Some("se-point.mp3"));
+        assert_eq!(point_change_se(&config, 9, 10, true, PointsOrigin::Stream), Some("se-fanfare.mp3"));
+        assert_eq!(point_change_se(&config, 5, 4, true, PointsOrigin::Stream), Some("se-decrease.mp3"));
+        // 初回の更新では鳴らさない
+        assert_eq!(point_change_se(&config, 0, 5, false, PointsOrigin::Stream), None);
+    }
+
+    #[test]
+    fn reconciled_point_changes_are_silent() {
+        let config = point_se();
+        assert_eq!(point_change_se(&config, 1, 2, true, PointsOrigin::Reconcile), None);
+        assert_eq!(point_change_se(&config, 9, 10, true, PointsOrigin::Reconcile), None);
+        assert_eq!(point_change_se(&config, 5, 4, true, PointsOrigin::Reconcile), None);
+    }
+}
@@ -0,0 +1,363 @@
+#[cfg(feature = "server")]
+use crate::proto::proto::MoonlightInfo;
+use crate::messages::EnabledState;
+use tokio::sync::watch;
+#[cfg(feature = "server")]
+use tracing::info;
+
+/// 全デバイス向けのMoonlightエントリを表すワイルドカードID
+///
+/// `device` または `address` がこの値のエントリは会場全体の一括フラグ（キルスイッチ）として扱う。
+#[cfg(feature = "server")]
+pub const WILDCARD_DEVICE_ID: &str = "*";
+
+/// 有効化状態を配信するwatchチャンネルを作成する
+///
+/// watchは常に最新値のみを保持するため、高速なトグルでも受信側が
+/// 中間状態を取りこぼして最終状態と食い違うことがない。
+pub fn channel() -> (watch::Sender<EnabledState>, watch::Receiver<EnabledState>) {
+    watch::channel(EnabledState::default())
+}
+
+/// MoonlightUpdateから取り出したフラグと、その更新番号
+#[cfg(feature = "server")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MoonlightFlag {
+    pub enabled: bool,
+    pub sequence: u64,
+}
+
+/// MoonlightUpdateのエントリ一覧から全体フラグと自デバイスのフラグを取り出す
+///
+/// MoonlightUpdateは全エントリのリストなので、ワイルドカードが含まれない場合は
+/// キルスイッチが解除されているものとみなす。自デバイスが含まれない場合は `None`。
+#[cfg(feature = "server")]
+pub fn resolve_moonlights(moonlights: &[MoonlightInfo], my_device_id: Option<&str>) -> (MoonlightFlag, Option<MoonlightFlag>) {
+    let wildcards = moonlights
+        .iter()
+        .filter(|m| m.device == WILDCARD_DEVICE_ID || m.address == WILDCARD_DEVICE_ID);
+    let global = wildcards.fold(MoonlightFlag { enabled: true, sequence: 0 }, |flag, m| MoonlightFlag {
+        enabled: flag.enabled && m.enabled,
+        sequence: flag.sequence.max(m.sequence),
+    });
+
+    let device = my_device_id.and_then(|id| {
+        moonlights
+            .iter()
+            .find(|m| m.device == id || m.address == id)
+            .map(|m| MoonlightFlag { enabled: m.enabled, sequence: m.sequence })
+    });
+
+    (global, device)
+}
+
+/// 受信したフラグが最後に適用したものより新しいか（番号なしは常に新しいとみなす）
+#[cfg(feature = "server")]
+fn is_current(incoming: u64, applied: u64) -> bool {
+    incoming == 0 || incoming >= applied
+}
+
+/// 適用後に覚えておく更新番号（番号なしのフラグでは直前の番号を維持する）
+#[cfg(feature = "server")]
+fn next_sequence(incoming: u64, applied: u64) -> u64 {
+    if incoming == 0 {
+        applied
+    } else {
+        incoming
+    }
+}
+
+/// MoonlightUpdateのフラグを更新番号を確かめながら適用する
+///
+/// 管理画面で素早く切り替えた場合に、遅れて届いた古い状態で上書きされないよう、
+/// 全体フラグ・個別フラグそれぞれについて最後に適用したものより古い番号は無視する。
+/// ワイルドカードを含まない更新（番号0）でキルスイッチを解除しても、それまでの番号は
+/// 維持するため、解除前に送られた古いキルスイッチが後から届いても再び無効にはならない。
+/// 実効値が変化した場合のみ受信側に通知し、変化したかどうかを返す。
+#[cfg(feature = "server")]
+pub fn apply_moonlights(tx: &watch::Sender<EnabledState>, global: MoonlightFlag, device: Option<MoonlightFlag>) -> bool {
+    tx.send_if_modified(|state| {
+        let previous = state.enabled;
+        if is_current(global.sequence, state.global_sequence) {
+            state.global_enabled = global.enabled;
+            state.global_sequence = next_sequence(global.sequence, state.global_sequence);
+        } else {
+            info!(sequence = global.sequence, applied = state.global_sequence, "Ignoring stale global moonlight flag");
+        }
+        if let Some(device) = device {
+            if is_current(device.sequence, state.device_sequence) {
+                state.device_enabled = device.enabled;
+                state.device_sequence = next_sequence(device.sequence, state.device_sequence);
+            } else {
+                info!(sequence = device.sequence, applied = state.device_sequence, "Ignoring stale device moonlight flag");
+            }
+        }
+        state.enabled = state.global_enabled && state.device_enabled;
+        state.enabled != previous
+    })
+}
+
+/// フラグを書き換えて実効値を計算し直す（実効値が変化したかどうかを返す）
+fn update(state: &mut EnabledState, global_enabled: bool, device_enabled: bool) -> bool {
+    let previous = state.enabled;
+    state.global_enabled = global_enabled;
+    state.device_enabled = device_enabled;
+    state.enabled = state.global_enabled && state.device_enabled;
+    state.enabled != previous
+}
+
+/// 全体フラグと個別フラグを更新する
+///
+/// 優先順位: 全体フラグが無効なら個別フラグに関わらず無効。全体フラグが有効な場合は
+/// 個別フラグに従う。`device_enabled` が `None` の場合は個別フラグを維持する。
+/// 実効値が変化した場合のみ受信側に通知し、変化したかどうかを返す。
+pub fn apply(tx: &watch::Sender<EnabledState>, global_enabled: bool, device_enabled: Option<bool>) -> bool {
+    tx.send_if_modified(|state| update(state, global_enabled, device_enabled.unwrap_or(state.device_enabled)))
+}
+
+/// 自デバイスの個別フラグを反転する（物理ボタン・ダッシュボードからの操作）
+///
+/// 読み取りと書き込みを1回の `send_if_modified` で行い、同時に届いたサーバーからの
+/// 全体フラグの変更を古い値で上書きしたり、同時の反転が打ち消し合ったりしないようにする。
+pub fn toggle_device(tx: &watch::Sender<EnabledState>) -> bool {
+    tx.send_if_modified(|state| update(state, state.global_enabled, !state.device_enabled))
+}
+
+/// 全体フラグ・個別フラグともに有効状態へ戻す（サーバー切断時など）
+///
+/// 再起動したサーバーが更新番号を振り直しても受け付けられるよう、番号も忘れる。
+#[cfg(feature = "server")]
+pub fn reset(tx: &watch::Sender<EnabledState>) -> bool {
+    tx.send_if_modified(|state| {
+        state.global_sequence = 0;
+        state.device_sequence = 0;
+        false
+    });
+    apply(tx, true, Some(true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn global_flag_overrides_device_flag() {
+        let (tx, rx) = channel();
+        assert!(apply(&tx, false, Some(true)));
+        assert!(!rx.borrow().enabled);
+        // 全体フラグが無効のままなら、個別フラグを有効にしても無効
+        assert!(!apply(&tx, false, Some(true)));
+        assert!(apply(&tx, true, None));
+        assert!(rx.borrow().enabled);
+        assert!(apply(&tx, true, Some(false)));
+        assert!(!rx.borrow().enabled);
+    }
+
+    #[test]
+    fn apply_without_device_flag_keeps_it() {
+        let (tx, rx) = channel();
+        apply(&tx, true, Some(false));
+        apply(&tx, false, None);
+        apply(&tx, true, None);
+        assert!(!rx.borrow().device_enabled);
+        assert!(!rx.borrow().enabled);
+    }
+
+    #[test]
+    fn unchanged_effective_state_is_not_notified() {
+        let (tx, mut rx) = channel();
+        rx.mark_unchanged();
+        assert!(!apply(&tx, true, Some(true)));
+        assert!(!rx.has_changed().unwrap());
+    }
+
+    #[test]
+    fn toggle_device_flips_only_the_device_flag() {
+        let (tx, rx) = channel();
+        assert!(toggle_device(&tx));
+        assert!(!rx.borrow().device_enabled);
+        assert!(rx.borrow().global_enabled);
+        assert!(toggle_device(&tx));
+        assert!(rx.borrow().enabled);
+
+        // 全体フラグが無効の間も個別フラグは切り替わるが、実効値は変わらない
+        apply(&tx, false, None);
+        assert!(!toggle_device(&tx));
+        assert!(!rx.borrow().device_enabled);
+        assert!(!rx.borrow().enabled);
+    }
+
+    #[test]
+    fn concurrent_toggles_are_not_lost() {
+        let (tx, rx) = channel();
+        let tx = std::sync::Arc::new(tx);
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let tx = std::sync::Arc::clone(&tx);
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        toggle_device(&tx);
+                    }
+                })
+            })
+            .collect();
+        // 反転の途中で全体フラグを変更しても失われない
+        apply(&tx, false, None);
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        // 反転の回数が偶数なので個別フラグは元に戻る
+        assert!(rx.borrow().device_enabled);
+        assert!(!rx.borrow().global_enabled);
+        assert!(!rx.borrow().enabled);
+    }
+
+    #[test]
+    fn rapid_toggling_settles_on_the_final_state() {
+        let (tx, mut rx) = channel();
+        for i in 0..101 {
+            apply(&tx, true, Some(i % 2 == 1));
+        }
+        // 受信側は中間状態を取りこぼしても最新値だけを見る
+        assert!(rx.has_changed().unwrap());
+        let state = *rx.borrow_and_update();
+        assert!(!state.device_enabled);
+        assert!(!state.enabled);
+    }
+
+    #[test]
+    fn rapid_device_toggles_settle_on_the_final_state() {
+        let (tx, mut rx) = channel();
+        for _ in 0..101 {
+            toggle_device(&tx);
+        }
+        // 反転の回数が奇数なので個別フラグは無効で終わる
+        assert!(rx.has_changed().unwrap());
+        let state = *rx.borrow_and_update();
+        assert!(!state.device_enabled);
+        assert!(state.global_enabled);
+        assert!(!state.enabled);
+    }
+
+    #[cfg(feature = "server")]
+    mod moonlights {
+        use super::*;
+
+        fn entry(device: &str, address: &str, enabled: bool, sequence: u64) -> MoonlightInfo {
+            MoonlightInfo { device: device.to_string(), address: address.to_string(), enabled, sequence, ..Default::default() }
+        }
+
+        const ME: &str = "AA:BB:CC:DD:EE:01";
+
+        #[test]
+        fn sequence_zero_is_always_current() {
+            assert!(is_current(0, 0));
+            assert!(is_current(0, 42));
+            assert!(is_current(5, 5));
+            assert!(is_current(6, 5));
+            assert!(!is_current(4, 5));
+        }
+
+        #[test]
+        fn missing_wildcard_means_kill_switch_released() {
+            let (global, device) = resolve_moonlights(&[entry("other", "", false, 3)], Some(ME));
+            assert_eq!(global, MoonlightFlag { enabled: true, sequence: 0 });
+            assert_eq!(device, None);
+        }
+
+        #[test]
+        fn wildcard_matches_device_or_address() {
+            for wildcard in [entry(WILDCARD_DEVICE_ID, "", false, 7), entry("", WILDCARD_DEVICE_ID, false, 7)] {
+                let (global, _) = resolve_moonlights(&[wildcard], Some(ME));
+                assert_eq!(global, MoonlightFlag { enabled: false, sequence: 7 });
+            }
+        }
+
+        #[test]
+        fn multiple_wildcards_must_all_be_enabled() {
+            let moonlights = [entry(WILDCARD_DEVICE_ID, "", true, 2), entry(WILDCARD_DEVICE_ID, "", false, 5)];
+            let (global, _) = resolve_moonlights(&moonlights, None);
+            assert_eq!(global, MoonlightFlag { enabled: false, sequence: 5 });
+        }
+
+        #[test]
+        fn own_entry_is_found_by_device_id_or_address() {
+            let by_address = [entry("unit-1", ME, false, 4)];
+            assert_eq!(resolve_moonlights(&by_address, Some(ME)).1, Some(MoonlightFlag { enabled: false, sequence: 4 }));
+            let by_device = [entry(ME, "", true, 9)];
+            assert_eq!(resolve_moonlights(&by_device, Some(ME)).1, Some(MoonlightFlag { enabled: true, sequence: 9 }));
+            // 自デバイスのIDが分からない場合は個別フラグなし
+            assert_eq!(resolve_moonlights(&by_device, None).1, None);
+        }
+
+        #[test]
+        fn stale_flags_are_ignored() {
+            let (tx, rx) = channel();
+            assert!(apply_moonlights(&tx, MoonlightFlag { enabled: false, sequence: 10 }, Some(MoonlightFlag { enabled: true, sequence: 3 })));
+            // 遅れて届いた古い全体フラグ（9 < 10）で有効に戻らない
+            assert!(!apply_moonlights(&tx, MoonlightFlag { enabled: true, sequence: 9 }, None));
+            assert!(!rx.borrow().enabled);
+            assert_eq!(rx.borrow().global_sequence, 10);
+
+            assert!(apply_moonlights(&tx, MoonlightFlag { enabled: true, sequence: 11 }, Some(MoonlightFlag { enabled: false, sequence: 2 })));
+            // 個別フラグも古い番号（2 < 3）は無視する
+            assert!(rx.borrow().device_enabled);
+            assert!(rx.borrow().enabled);
+        }
+
+        #[test]
+        fn rapid_toggles_delivered_out_of_order_settle_on_the_latest() {
+            let (tx, rx) = channel();
+            // 1..=20 の切り替え（奇数で無効・偶数で有効）が入れ替わって届く
+            let deliveries = [2, 1, 4, 3, 7, 5, 6, 9, 8, 12, 10, 11, 15, 13, 14, 20, 16, 19, 17, 18];
+            for sequence in deliveries {
+                let wildcard = entry(WILDCARD_DEVICE_ID, "", sequence % 2 == 0, sequence);
+                let (global, device) = resolve_moonlights(&[wildcard], Some(ME));
+                apply_moonlights(&tx, global, device);
+            }
+            let state = *rx.borrow();
+            assert_eq!(state.global_sequence, 20);
+            assert!(state.global_enabled);
+            assert!(state.enabled);
+        }
+
+        #[test]
+        fn release_without_wildcard_keeps_the_global_sequence() {
+            let (tx, rx) = channel();
+            let kill = [entry(WILDCARD_DEVICE_ID, "", false, 10)];
+            let (global, device) = resolve_moonlights(&kill, Some(ME));
+            assert!(apply_moonlights(&tx, global, device));
+
+            // ワイルドカードのエントリが削除されてキルスイッチが解除される
+            let (global, device) = resolve_moonlights(&[entry(ME, "", true, 0)], Some(ME));
+            assert!(apply_moonlights(&tx, global, device));
+            assert!(rx.borrow().enabled);
+            assert_eq!(rx.borrow().global_sequence, 10);
+
+            // 解除前に送られた古いキルスイッチが遅れて届いても無効に戻らない
+            let stale = [entry(WILDCARD_DEVICE_ID, "", false, 9)];
+            let (global, device) = resolve_moonlights(&stale, Some(ME));
+            assert!(!apply_moonlights(&tx, global, device));
+            assert!(rx.borrow().enabled);
+        }
+
+        #[test]
+        fn reset_enables_both_flags() {
+            let (tx, rx) = channel();
+            apply(&tx, false, Some(false));
+            assert!(reset(&tx));
+            assert_eq!(*rx.borrow(), EnabledState::default());
+        }
+
+        #[test]
+        fn reset_forgets_sequences() {
+            let (tx, rx) = channel();
+            apply_moonlights(&tx, MoonlightFlag { enabled: false, sequence: 100 }, Some(MoonlightFlag { enabled: false, sequence: 100 }));
+            assert!(reset(&tx));
+            assert!(rx.borrow().enabled);
+            // 再起動したサーバーが番号を振り直しても受け付ける
+            assert!(apply_moonlights(&tx, MoonlightFlag { enabled: false, sequence: 1 }, None));
+            assert!(!rx.borrow().enabled);
+        }
+    }
+}
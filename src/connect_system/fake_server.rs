@@ -0,0 +1,548 @@
+use crate::proto::proto::device_service_server::{DeviceService, DeviceServiceServer};
+use crate::proto::proto::fleet_command::Command;
+use crate::proto::proto::stream_device_info_response::Event;
+use crate::proto::proto::time_service_server::{TimeService, TimeServiceServer};
+use crate::proto::proto::{
+    BgmOverrideEvent, FleetAnnouncement, FleetCommand, FleetVolume, GetPointsRequest, GetPointsResponse, LanguageUpdate, LocationInfo, LocationUpdate, MoonlightInfo, MoonlightUpdate, PointUpdate, SePlayEvent,
+    SoundSetting, SoundSettingUpdate, StreamDeviceInfoRequest, StreamDeviceInfoResponse, SyncTimeRequest, SyncTimeResponse,
+    UploadThrottle, VolumeUpdate,
+};
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::{Stream, StreamExt};
+use tonic::codec::CompressionEncoding;
+use tonic::{Request, Response, Status, Streaming};
+use tracing::{debug, info, instrument, warn};
+
+/// 開発用のプロセス内フェイクgRPCサーバーの待ち受けアドレス
+pub const FAKE_SERVER_ADDR: &str = "127.0.0.1:50051";
+
+/// フェイクサーバーが接続中のクライアントへ順番に送るイベント
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ScriptStep {
+    /// 指定時間待機する
+    Wait { ms: u64 },
+    /// LocationUpdateを送る
+    Location { locations: Vec<ScriptLocation> },
+    /// PointUpdateを送る（user_id省略時はクライアントが名乗ったIDを使う）
+    Point {
+        #[serde(default)]
+        user_id: Option<String>,
+        points: i32,
+    },
+    /// SoundSettingUpdateを送る
+    SoundSetting {
+        max_volume_rssi: f64,
+        min_volume_rssi: f64,
+        max_volume: f64,
+        min_volume: f64,
+        #[serde(default)]
+        is_muted: bool,
+    },
+    /// MoonlightUpdateを送る（device省略時はワイルドカード = 全体フラグ）
+    Moonlight {
+        #[serde(default)]
+        device: Option<String>,
+        enabled: bool,
+        /// 更新番号（省略時は0 = 番号なし。素早い切り替えで順序が入れ替わる場合の再現に使う）
+        #[serde(default)]
+        sequence: u64,
+        /// 実行結果の報告に付けるID（省略時は報告させない）
+        #[serde(default)]
+        command_id: String,
+    },
+    /// SePlayEventを送る（devices省略時は全デバイス）
+    SePlay {
+        file: String,
+        #[serde(default)]
+        devices: Vec<String>,
+        #[serde(default)]
+        priority: bool,
+        #[serde(default)]
+        command_id: String,
+    },
+    /// BgmOverrideEventを送る（file・place_typeとも省略時は上書き解除）
+    BgmOverride {
+        #[serde(default)]
+        file: String,
+        #[serde(default)]
+        place_type: String,
+        #[serde(default)]
+        devices: Vec<String>,
+        #[serde(default)]
+        duration_ms: u32,
+        #[serde(default)]
+        command_id: String,
+    },
+    /// VolumeUpdateを送る（devices省略時は全デバイス）
+    Volume {
+        #[serde(default)]
+        devices: Vec<String>,
+        master_volume: f64,
+        #[serde(default)]
+        muted: bool,
+        #[serde(default)]
+        command_id: String,
+    },
+    /// LanguageUpdateを送る（language省略時はユニットの設定に戻す）
+    Language {
+        #[serde(default)]
+        devices: Vec<String>,
+        #[serde(default)]
+        language: String,
+    },
+    /// UploadThrottleを送る（batch_interval_ms省略時は抑制を解除）
+    UploadThrottle {
+        #[serde(default)]
+        batch_interval_ms: u32,
+        #[serde(default)]
+        duration_ms: u32,
+    },
+    /// 全ユニット向けのアナウンス（FleetCommand）を送る
+    FleetAnnouncement {
+        command_id: String,
+        file: String,
+        #[serde(default)]
+        gain: f64,
+    },
+    /// 全ユニット向けの音量（FleetCommand）を送る
+    FleetVolume {
+        command_id: String,
+        master_volume: f64,
+        #[serde(default)]
+        muted: bool,
+    },
+    /// サーバーイベントをprotoと同じJSONのまま送る
+    ///
+    /// ジャーナルの `Server settings received` の `event` や、状態APIの `server_messages` の項目を
+    /// `{"type": "event", "event": {"location_update": {...}}}` のように貼り付けて、会場で受け取った内容を再生する。
+    Event { event: Event },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScriptLocation {
+    pub address: String,
+    pub place_type: String,
+    /// 音源のURL（省略可）
+    #[serde(default)]
+    pub sound_url: String,
+}
+
+/// 台本ファイル（JSON配列）を読み込む
+pub fn load_script(path: &Path) -> Result<Vec<ScriptStep>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read fake server script: {}", path.display()))?;
+    serde_json::from_str(&text).with_context(|| format!("Failed to parse fake server script: {}", path.display()))
+}
+
+/// 台本ファイルが指定されない場合の組み込みシナリオ
+pub fn default_script() -> Vec<ScriptStep> {
+    vec![
+        ScriptStep::Wait { ms: 1000 },
+        ScriptStep::Location {
+            locations: vec![
+                ScriptLocation { address: "00:11:22:33:44:55".to_string(), place_type: "projection_mapping".to_string(), sound_url: String::new() },
+                ScriptLocation { address: "00:11:22:33:44:66".to_string(), place_type: "fire_rat_robe".to_string(), sound_url: String::new() },
+                ScriptLocation { address: "00:11:22:33:44:77".to_string(), place_type: "buddhas_bowl".to_string(), sound_url: String::new() },
+            ],
+        },
+        ScriptStep::Wait { ms: 2000 },
+        ScriptStep::Point { user_id: None, points: 1 },
+        ScriptStep::Wait { ms: 2000 },
+        ScriptStep::SoundSetting {
+            max_volume_rssi: -40.0,
+            min_volume_rssi: -90.0,
+            max_volume: 1.0,
+            min_volume: 0.0,
+            is_muted: false,
+        },
+        ScriptStep::Wait { ms: 2000 },
+        ScriptStep::Point { user_id: None, points: 2 },
+        ScriptStep::Wait { ms: 5000 },
+        ScriptStep::Moonlight { device: None, enabled: false, sequence: 1, command_id: "fake-disable".to_string() },
+        ScriptStep::Wait { ms: 3000 },
+        ScriptStep::Moonlight { device: None, enabled: true, sequence: 2, command_id: "fake-enable".to_string() },
+        ScriptStep::Wait { ms: 3000 },
+        ScriptStep::SePlay {
+            file: "se-point.mp3".to_string(),
+            devices: Vec::new(),
+            priority: true,
+            command_id: "fake-se".to_string(),
+        },
+    ]
+}
+
+fn now_ns() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos() as i64
+}
+
+type ResponseStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send + 'static>>;
+
+/// 台本どおりにイベントを送るDeviceService/TimeServiceの実装
+#[derive(Clone)]
+struct FakeBackend {
+    script: Arc<Vec<ScriptStep>>,
+}
+
+impl FakeBackend {
+    fn to_event(step: &ScriptStep, client_user_id: &Mutex<Option<String>>) -> Option<Event> {
+        match step {
+            ScriptStep::Wait { .. } => None,
+            ScriptStep::Event { event } => Some(event.clone()),
+            ScriptStep::Location { locations } => Some(Event::LocationUpdate(LocationUpdate {
+                locations: locations
+                    .iter()
+                    .enumerate()
+                    .map(|(i, loc)| LocationInfo {
+                        id: format!("fake-{}", i),
+                        name: loc.place_type.clone(),
+                        address: loc.address.clone(),
+                        place_type: loc.place_type.clone(),
+                        sound_url: loc.sound_url.clone(),
+                    })
+                    .collect(),
+            })),
+            ScriptStep::Point { user_id, points } => {
+                let user_id = user_id.clone().or_else(|| client_user_id.lock().unwrap().clone());
+                match user_id {
+                    Some(user_id) => Some(Event::PointUpdate(PointUpdate { user_id, points: *points })),
+                    None => {
+                        warn!("Skipping PointUpdate: client user_id is not known yet");
+                        None
+                    }
+                }
+            }
+            ScriptStep::SoundSetting { max_volume_rssi, min_volume_rssi, max_volume, min_volume, is_muted } => {
+                Some(Event::SoundSettingUpdate(SoundSettingUpdate {
+                    settings: Some(SoundSetting {
+                        id: "fake".to_string(),
+                        max_volume_rssi: *max_volume_rssi,
+                        min_volume_rssi: *min_volume_rssi,
+                        max_volume: *max_volume,
+                        min_volume: *min_volume,
+                        is_muted: *is_muted,
+                    }),
+                }))
+            }
+            ScriptStep::Moonlight { device, enabled, sequence, command_id } => {
+                let device = device.clone().unwrap_or_else(|| crate::connect_system::enable_state::WILDCARD_DEVICE_ID.to_string());
+                Some(Event::MoonlightUpdate(MoonlightUpdate {
+                    moonlights: vec![MoonlightInfo {
+                        id: "fake".to_string(),
+                        device: device.clone(),
+                        address: device,
+                        enabled: *enabled,
+                        sequence: *sequence,
+                    }],
+                    command_id: command_id.clone(),
+                }))
+            }
+            ScriptStep::SePlay { file, devices, priority, command_id } => Some(Event::SePlay(SePlayEvent {
+                file: file.clone(),
+                devices: devices.clone(),
+                priority: *priority,
+                command_id: command_id.clone(),
+            })),
+            ScriptStep::BgmOverride { file, place_type, devices, duration_ms, command_id } => {
+                Some(Event::BgmOverride(BgmOverrideEvent {
+                    file: file.clone(),
+                    place_type: place_type.clone(),
+                    devices: devices.clone(),
+                    duration_ms: *duration_ms,
+                    command_id: command_id.clone(),
+                }))
+            }
+            ScriptStep::Volume { devices, master_volume, muted, command_id } => Some(Event::VolumeUpdate(VolumeUpdate {
+                devices: devices.clone(),
+                master_volume: *master_volume,
+                muted: *muted,
+                command_id: command_id.clone(),
+            })),
+            ScriptStep::Language { devices, language } => Some(Event::LanguageUpdate(LanguageUpdate {
+                devices: devices.clone(),
+                language: language.clone(),
+            })),
+            ScriptStep::UploadThrottle { batch_interval_ms, duration_ms } => Some(Event::UploadThrottle(UploadThrottle {
+                batch_interval_ms: *batch_interval_ms,
+                duration_ms: *duration_ms,
+            })),
+            ScriptStep::FleetAnnouncement { command_id, file, gain } => Some(Event::FleetCommand(FleetCommand {
+                command_id: command_id.clone(),
+                command: Some(Command::Announcement(FleetAnnouncement { file: file.clone(), gain: *gain })),
+            })),
+            ScriptStep::FleetVolume { command_id, master_volume, muted } => Some(Event::FleetCommand(FleetCommand {
+                command_id: command_id.clone(),
+                command: Some(Command::Volume(FleetVolume { master_volume: *master_volume, muted: *muted })),
+            })),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl DeviceService for FakeBackend {
+    type StreamDeviceInfoStream = ResponseStream<StreamDeviceInfoResponse>;
+
+    async fn stream_device_info(
+        &self,
+        request: Request<Streaming<StreamDeviceInfoRequest>>,
+    ) -> Result<Response<Self::StreamDeviceInfoStream>, Status> {
+        info!("Fake server: client connected to DeviceService");
+        let mut incoming = request.into_inner();
+        let client_user_id = Arc::new(Mutex::new(None::<String>));
+
+        // クライアントからのRSSI送信を受け取り、user_idを記録する
+        let client_user_id_for_rx = Arc::clone(&client_user_id);
+        tokio::spawn(async move {
+            while let Some(Ok(req)) = incoming.next().await {
+                debug!(user_id = %req.user_id, locations = ?req.locations, status = ?req.status, "Fake server: received device info");
+                if let Some(ack) = &req.fleet_ack {
+                    info!(?ack, "Fake server: fleet command acknowledged");
+                }
+                if let Some(result) = &req.command_result {
+                    info!(?result, "Fake server: command result reported");
+                }
+                if !req.user_id.is_empty() {
+                    *client_user_id_for_rx.lock().unwrap() = Some(req.user_id);
+                }
+            }
+            info!("Fake server: client device stream closed");
+        });
+
+        // 台本を順番に再生する
+        let (tx, rx) = mpsc::channel(16);
+        let script = Arc::clone(&self.script);
+        tokio::spawn(async move {
+            for step in script.iter() {
+                if let ScriptStep::Wait { ms } = step {
+                    tokio::time::sleep(Duration::from_millis(*ms)).await;
+                    continue;
+                }
+                if let Some(event) = Self::to_event(step, &client_user_id) {
+                    info!(?step, "Fake server: sending scripted event");
+                    if tx.send(Ok(StreamDeviceInfoResponse { event: Some(event), ..Default::default() })).await.is_err() {
+                        break;
+                    }
+                }
+            }
+            info!("Fake server: script finished");
+            // クライアントの切断を避けるため、台本終了後もストリームは開いたままにする
+            tx.closed().await;
+        });
+
+        Ok(Response::new(Box::pin(ReceiverStream::new(rx))))
+    }
+
+    async fn get_points(&self, _request: Request<GetPointsRequest>) -> Result<Response<GetPointsResponse>, Status> {
+        // ポイントは台本のPointUpdateでのみ配信する（古いサーバーと同じ振る舞い）
+        Err(Status::unimplemented("GetPoints is not supported by the fake server"))
+    }
+}
+
+#[tonic::async_trait]
+impl TimeService for FakeBackend {
+    type SyncTimeStream = ResponseStream<SyncTimeResponse>;
+
+    async fn sync_time(
+        &self,
+        request: Request<Streaming<SyncTimeRequest>>,
+    ) -> Result<Response<Self::SyncTimeStream>, Status> {
+        let stream = request.into_inner().filter_map(|req| {
+            let req = req.ok()?;
+            let server_receive_time = now_ns();
+            Some(Ok(SyncTimeResponse {
+                client_send_time: req.client_send_time,
+                server_receive_time,
+                server_send_time: now_ns(),
+            }))
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+/// フェイクサーバーを起動する（終了しない）
+#[instrument(skip(script))]
+pub async fn run_fake_server(addr: SocketAddr, script: Vec<ScriptStep>) -> Result<()> {
+    info!(%addr, steps = script.len(), "Starting in-process fake gRPC server");
+    let backend = FakeBackend { script: Arc::new(script) };
+    tonic::transport::Server::builder()
+        .add_service(
+            DeviceServiceServer::new(backend.clone())
+                .accept_compressed(CompressionEncoding::Gzip)
+                .accept_compressed(CompressionEncoding::Zstd),
+        )
+        .add_service(TimeServiceServer::new(backend))
+        .serve(addr)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio_system::{asset_variant, master_volume, playback_status};
+    use crate::clock_system::clock_main::Clock;
+    use crate::clock_system::time_source::SystemTimeSource;
+    use crate::config_system::config_main::{InteractionConfig, ServerConfig, UploadConfig};
+    use crate::config_system::config_store::ConfigStore;
+    use crate::connect_system::calibration::CalibrationStore;
+    use crate::connect_system::connect_main::{connect_main, StatePaths};
+    use crate::connect_system::sound_map::SharedSoundMap;
+    use crate::connect_system::{enable_state, location_context, server_messages};
+    use crate::event_system::event_bus::EventBus;
+    use crate::messages::{DeviceSnapshot, EnabledState, SePlayRequest};
+    use crate::shutdown_system::shutdown_controller::ShutdownController;
+    use std::collections::HashMap;
+    use tokio::sync::{broadcast, watch};
+    use tokio::task::JoinHandle;
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    /// フェイクサーバーと、それに接続した `connect_main` のオーディオ側のチャンネル
+    struct Harness {
+        sound_setting_rx: mpsc::Receiver<SoundSetting>,
+        se_rx: mpsc::Receiver<SePlayRequest>,
+        enabled_rx: watch::Receiver<EnabledState>,
+        sound_map: Arc<SharedSoundMap>,
+        current_points: Arc<Mutex<i32>>,
+        server_messages_rx: watch::Receiver<server_messages::LastServerMessages>,
+        _device_tx: broadcast::Sender<Arc<DeviceSnapshot>>,
+        tasks: Vec<JoinHandle<()>>,
+    }
+
+    impl Drop for Harness {
+        fn drop(&mut self) {
+            for task in &self.tasks {
+                task.abort();
+            }
+        }
+    }
+
+    /// テストごとの作業ディレクトリ（設定・状態のファイルは作業ディレクトリではなくここに置く）
+    fn state_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("tsukimi-fake-server-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    async fn start(name: &str, script: Vec<ScriptStep>) -> Harness {
+        // 空いているポートを確保してから待ち受ける（接続に失敗しても `connect_main` が再試行する）
+        let addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            run_fake_server(addr, script).await.unwrap();
+        });
+
+        let events = EventBus::new(64);
+        let (device_tx, device_rx) = broadcast::channel(16);
+        let (sound_setting_tx, sound_setting_rx) = mpsc::channel(16);
+        let (se_tx, se_rx) = mpsc::channel(16);
+        let (bgm_override_tx, _bgm_override_rx) = mpsc::channel(16);
+        let (volume_tx, _volume_rx) = master_volume::channel();
+        let (language_tx, _language_rx) = asset_variant::channel();
+        let (enabled_tx, enabled_rx) = enable_state::channel();
+        let (connection_tx, _connection_rx) = watch::channel(None);
+        let (_status_tx, status_rx) = playback_status::channel();
+        let (location_tx, _location_rx) = location_context::channel();
+        let (server_messages_tx, server_messages_rx) = server_messages::channel();
+        let sound_map = Arc::new(SharedSoundMap::new(HashMap::new()));
+        let current_points = Arc::new(Mutex::new(0));
+        let dir = state_dir(name);
+        let state_paths = StatePaths { points_cache: dir.join("points.json"), interaction_state: dir.join("interactions.json") };
+        let config_store = ConfigStore::with_paths(&dir.join("config.json"), dir.join("server.json"), dir.join("local.json"));
+        let server_config = ServerConfig { grpc_url: format!("http://{}", addr), ..Default::default() };
+        let client = tokio::spawn({
+            let sound_map = Arc::clone(&sound_map);
+            let current_points = Arc::clone(&current_points);
+            async move {
+                let result = connect_main(
+                    server_config,
+                    UploadConfig::default(),
+                    InteractionConfig::default(),
+                    CalibrationStore::load(&dir.join("calibration.json")),
+                    state_paths,
+                    None,
+                    None,
+                    device_rx,
+                    Arc::new(Clock::new(Arc::new(SystemTimeSource), events.clone())),
+                    sound_setting_tx,
+                    se_tx,
+                    bgm_override_tx,
+                    volume_tx,
+                    language_tx,
+                    enabled_tx,
+                    events,
+                    connection_tx,
+                    status_rx,
+                    sound_map,
+                    Arc::new(Mutex::new(None)),
+                    current_points,
+                    location_tx,
+                    server_messages_tx,
+                    config_store,
+                    ShutdownController::new(),
+                )
+                .await;
+                result.unwrap();
+            }
+        });
+
+        Harness { sound_setting_rx, se_rx, enabled_rx, sound_map, current_points, server_messages_rx, _device_tx: device_tx, tasks: vec![server, client] }
+    }
+
+    fn location(address: &str, place_type: &str) -> ScriptLocation {
+        ScriptLocation { address: address.to_string(), place_type: place_type.to_string(), sound_url: String::new() }
+    }
+
+    #[tokio::test]
+    async fn scripted_updates_reach_the_audio_channels() {
+        let mut harness = start("scripted", vec![
+            ScriptStep::Location { locations: vec![location("00:11:22:33:44:55", "projection_mapping"), location("00:11:22:33:44:66", "fire_rat_robe")] },
+            ScriptStep::Point { user_id: Some("someone-else".to_string()), points: 5 },
+            ScriptStep::SoundSetting { max_volume_rssi: -40.0, min_volume_rssi: -90.0, max_volume: 0.8, min_volume: 0.1, is_muted: false },
+            ScriptStep::Moonlight { device: None, enabled: false, sequence: 1, command_id: String::new() },
+            ScriptStep::SePlay { file: "se-point.mp3".to_string(), devices: Vec::new(), priority: true, command_id: String::new() },
+            ScriptStep::Moonlight { device: None, enabled: true, sequence: 2, command_id: String::new() },
+        ])
+        .await;
+
+        let setting = tokio::time::timeout(TIMEOUT, harness.sound_setting_rx.recv()).await.unwrap().unwrap();
+        assert_eq!(setting.max_volume, 0.8);
+        assert_eq!(setting.min_volume_rssi, -90.0);
+
+        // LocationUpdate は SoundSettingUpdate より先に処理されている
+        let sound_map = harness.sound_map.load();
+        assert_eq!(sound_map.sounds.len(), 2);
+        assert_eq!(sound_map.place_types[&"00:11:22:33:44:66".parse().unwrap()], "fire_rat_robe");
+        // 他のユニット宛てのポイントでは音源のレベルを変えない
+        assert_eq!(*harness.current_points.lock().unwrap(), 0);
+
+        let se = tokio::time::timeout(TIMEOUT, harness.se_rx.recv()).await.unwrap().unwrap();
+        assert_eq!(se.file_path, "se-point.mp3");
+        assert!(se.priority);
+
+        let enabled = tokio::time::timeout(TIMEOUT, harness.enabled_rx.wait_for(|state| state.global_sequence == 2)).await.unwrap().unwrap();
+        assert!(enabled.enabled);
+
+        let last = harness.server_messages_rx.borrow();
+        assert!(last.location_update.is_some() && last.sound_setting_update.is_some());
+        assert_eq!(last.moonlight_update.as_ref().unwrap().moonlights[0].sequence, 2);
+    }
+
+    #[tokio::test]
+    async fn moonlight_disable_is_applied() {
+        let mut harness = start("moonlight", vec![ScriptStep::Moonlight { device: None, enabled: false, sequence: 1, command_id: String::new() }]).await;
+
+        let disabled = tokio::time::timeout(TIMEOUT, harness.enabled_rx.wait_for(|state| state.global_sequence == 1)).await.unwrap().unwrap();
+        assert!(!disabled.enabled);
+        assert!(!disabled.global_enabled);
+        assert!(harness.sound_setting_rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,127 @@
+use crate::audio_system::master_volume::MasterVolume;
+use crate::config_system::config_store::{ConfigLayer, ConfigStore};
+use crate::connect_system::sound_catalog::SoundCatalog;
+use crate::messages::SePlayRequest;
+use crate::proto::proto::fleet_command::Command;
+use crate::proto::proto::{FleetCommand, FleetCommandAck};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{mpsc, watch};
+use tracing::{info, warn};
+
+/// 実行済みとして覚えておくコマンドIDの数（再接続で再送されたコマンドを1回だけ実行するため）
+const REMEMBERED_COMMANDS: usize = 64;
+
+/// 全ユニット向けのコマンドを変換したローカルの操作
+#[derive(Debug, Clone)]
+pub enum FleetAction {
+    /// 再生中のSEに割り込んでアナウンスを流す
+    Announce(SePlayRequest),
+    /// マスター音量・ミュートを設定する
+    SetVolume(MasterVolume),
+    /// 設定のサーバーの層を変更する（次回の起動から効く）
+    OverrideConfig(serde_json::Value),
+}
+
+/// サーバーからの全ユニット向けコマンド（FleetCommand）をローカルの操作に変換して実行し、受領の通知を作る
+///
+/// デバイスを指定するイベントと違い、対象の判定は行わない。
+/// 実行済みのコマンドIDを覚えておくため、再接続をまたいで同じものを使う。
+pub struct FleetDispatcher {
+    se_tx: mpsc::Sender<SePlayRequest>,
+    volume_tx: watch::Sender<MasterVolume>,
+    config_store: Arc<ConfigStore>,
+    catalog: SoundCatalog,
+    executed: Mutex<VecDeque<String>>,
+}
+
+impl FleetDispatcher {
+    pub fn new(se_tx: mpsc::Sender<SePlayRequest>, volume_tx: watch::Sender<MasterVolume>, config_store: Arc<ConfigStore>) -> Arc<Self> {
+        Arc::new(Self { se_tx, volume_tx, config_store, catalog: SoundCatalog::default(), executed: Mutex::new(VecDeque::new()) })
+    }
+
+    /// コマンドをローカルの操作に変換する（不正なコマンドは理由を返す）
+    fn plan(&self, command: Option<Command>) -> Result<FleetAction, String> {
+        match command {
+            Some(Command::Announcement(announcement)) => {
+                if !self.catalog.is_valid_sound_file(&announcement.file) {
+                    return Err(format!("invalid announcement file: {}", announcement.file));
+                }
+                let gain = if announcement.gain == 0.0 {
+                    None
+                } else if announcement.gain.is_finite() && announcement.gain > 0.0 {
+                    Some(announcement.gain)
+                } else {
+                    return Err(format!("invalid announcement gain: {}", announcement.gain));
+                };
+                Ok(FleetAction::Announce(SePlayRequest { file_path: announcement.file, priority: true, gain, command_id: None }))
+            }
+            Some(Command::Volume(volume)) => {
+                if !(0.0..=1.0).contains(&volume.master_volume) {
+                    return Err(format!("master_volume out of range: {}", volume.master_volume));
+                }
+                Ok(FleetAction::SetVolume(MasterVolume { volume: volume.master_volume, muted: volume.muted }))
+            }
+            Some(Command::Config(config)) => match serde_json::from_str::<serde_json::Value>(&config.patch_json) {
+                Ok(patch) if patch.is_object() => Ok(FleetAction::OverrideConfig(patch)),
+                Ok(_) => Err("config patch must be a JSON object".to_string()),
+                Err(e) => Err(format!("invalid config patch: {}", e)),
+            },
+            None => Err("unknown or empty fleet command".to_string()),
+        }
+    }
+
+    async fn apply(&self, action: FleetAction) -> Result<(), String> {
+        match action {
+            FleetAction::Announce(request) => self.se_tx.send(request).await.map_err(|e| format!("failed to queue announcement: {}", e)),
+            FleetAction::SetVolume(master_volume) => {
+                self.volume_tx.send_if_modified(|current| {
+                    let changed = *current != master_volume;
+                    *current = master_volume;
+                    changed
+                });
+                Ok(())
+            }
+            FleetAction::OverrideConfig(patch) => self.config_store.update(ConfigLayer::Server, &patch).map_err(|e| format!("{:#}", e)),
+        }
+    }
+
+    /// コマンドを実行し、サーバーへ返す受領の通知を作る
+    pub async fn dispatch(&self, command: FleetCommand) -> FleetCommandAck {
+        let command_id = command.command_id;
+        let ack = |result: Result<(), String>, duplicate: bool| FleetCommandAck {
+            command_id: command_id.clone(),
+            ok: result.is_ok(),
+            error: result.err().unwrap_or_default(),
+            duplicate,
+        };
+        if command_id.is_empty() {
+            warn!("Rejected fleet command without command_id");
+            return ack(Err("missing command_id".to_string()), false);
+        }
+        if self.executed.lock().unwrap().contains(&command_id) {
+            info!(%command_id, "Fleet command already executed - acknowledging again");
+            return ack(Ok(()), true);
+        }
+
+        let result = match self.plan(command.command) {
+            Ok(action) => {
+                info!(%command_id, ?action, "Executing fleet command");
+                self.apply(action).await
+            }
+            Err(e) => Err(e),
+        };
+        match &result {
+            Ok(()) => {
+                // 失敗したコマンドはサーバーが同じIDで再送できるよう、成功したものだけ覚える
+                let mut executed = self.executed.lock().unwrap();
+                if executed.len() >= REMEMBERED_COMMANDS {
+                    executed.pop_front();
+                }
+                executed.push_back(command_id.clone());
+            }
+            Err(e) => warn!(%command_id, "Fleet command failed: {}", e),
+        }
+        ack(result, false)
+    }
+}
@@ -0,0 +1,420 @@
+use crate::clock_system::time_source::SharedTimeSource;
+use crate::config_system::config_main::InteractionConfig;
+use crate::connect_system::calibration::CalibrationStore;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+/// インタラクションの重複判定の状態を保存するファイル（作業ディレクトリからの相対パス）
+pub const DEFAULT_INTERACTION_STATE_PATH: &str = "tsukimi-interactions.json";
+
+/// 同じ場所・ビーコンで再びインタラクションできるまでの最短時間
+const INTERACTION_COOLDOWN: Duration = Duration::from_secs(10);
+
+/// 離れたことを確認できなくても、この時間が経てば再びインタラクションできる
+/// （Bluetoothを切ったまま立ち去った場合に永久に再アームされないのを防ぐ）
+const REARM_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// この時間サンプルが途切れたビーコンは平滑化・滞在判定をやり直す
+const SAMPLE_GAP_RESET: Duration = Duration::from_secs(3);
+
+/// (place_type, ビーコンアドレス) ごとの直近のインタラクション
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct InteractionEntry {
+    place_type: String,
+    address: String,
+    /// インタラクションした時刻（UNIXミリ秒。再起動をまたいで比較するため壁時計を使う）
+    triggered_at_ms: u64,
+    /// 一度離れたことを確認済みかどうか
+    armed: bool,
+    /// このインタラクションの冪等キー（POSTを再送しても同じ値を使う）
+    interaction_id: String,
+}
+
+/// インタラクションの重複判定
+///
+/// BLEの接続が切れるとRSSIが最小値に戻り、再接続時に閾値をまたいだように見えるため、
+/// 閾値の上下だけでは同じ来場者が何度もインタラクションできてしまう。そこで一度インタラクションした
+/// (place_type, ビーコン) は、RSSIが再アーム閾値を下回る（実際に離れた）まで再度トリガーしない。
+/// 状態はディスクに保存し、スキャナやプロセスの再起動でリセットされないようにする。
+pub struct InteractionState {
+    entries: HashMap<(String, String), InteractionEntry>,
+    path: PathBuf,
+    time: SharedTimeSource,
+    /// 最後に保存してから変更があったか
+    dirty: bool,
+}
+
+/// 保存待ちの状態（非同期タスクを止めないよう、`spawn_blocking` から `write` する）
+pub struct PendingSave {
+    path: PathBuf,
+    bytes: Vec<u8>,
+}
+
+impl PendingSave {
+    /// 書きかけのファイルが残らないよう、一時ファイルに書いてから置き換える
+    pub fn write(&self) {
+        let tmp_path = self.path.with_extension("json.tmp");
+        let result = std::fs::write(&tmp_path, &self.bytes).and_then(|()| std::fs::rename(&tmp_path, &self.path));
+        if let Err(e) = result {
+            warn!(path = %self.path.display(), "Failed to save interaction state: {}", e);
+        }
+    }
+}
+
+impl InteractionState {
+    /// 保存済みの状態を読み込む（無い・壊れている場合は空の状態から始める）
+    pub fn load(path: &Path, time: SharedTimeSource) -> Self {
+        let entries = std::fs::read_to_string(path)
+            .ok()
+            .and_then(|text| match serde_json::from_str::<Vec<InteractionEntry>>(&text) {
+                Ok(entries) => Some(entries),
+                Err(e) => {
+                    warn!(path = %path.display(), "Failed to parse interaction state: {}", e);
+                    None
+                }
+            })
+            .unwrap_or_default();
+        info!(path = %path.display(), entries = entries.len(), "Loaded interaction state");
+        Self {
+            entries: entries
+                .into_iter()
+                .map(|entry| ((entry.place_type.clone(), entry.address.clone()), entry))
+                .collect(),
+            path: path.to_path_buf(),
+            time,
+            dirty: false,
+        }
+    }
+
+    /// 前回から変更があれば、保存する内容を返す
+    pub fn take_pending_save(&mut self) -> Option<PendingSave> {
+        if !std::mem::take(&mut self.dirty) {
+            return None;
+        }
+        let entries: Vec<&InteractionEntry> = self.entries.values().collect();
+        match serde_json::to_vec(&entries) {
+            Ok(bytes) => Some(PendingSave { path: self.path.clone(), bytes }),
+            Err(e) => {
+                warn!("Failed to serialize interaction state: {}", e);
+                None
+            }
+        }
+    }
+
+    /// 閾値を上回ったときに呼ぶ。インタラクションしてよい場合は冪等キーを返す（保存は `take_pending_save` で行う）
+    ///
+    /// 冪等キーは1回のインタラクションごとに生成するランダムな値で、状態と一緒に保存する。
+    /// サーバーへのPOSTを再送するときも同じキーを付け、ポイントが二重に加算されないようにする。
+    pub fn try_trigger(&mut self, place_type: &str, address: &str) -> Option<String> {
+        let now = self.time.unix_ms();
+        let key = (place_type.to_string(), address.to_string());
+        if let Some(entry) = self.entries.get(&key) {
+            let elapsed = Duration::from_millis(now.saturating_sub(entry.triggered_at_ms));
+            if elapsed < INTERACTION_COOLDOWN || (!entry.armed && elapsed < REARM_TIMEOUT) {
+                debug!(place_type, address, armed = entry.armed, elapsed_ms = elapsed.as_millis() as u64, "Interaction deduplicated");
+                return None;
+            }
+        }
+        let interaction_id = uuid::Uuid::new_v4().to_string();
+        self.entries.insert(
+            key,
+            InteractionEntry {
+                place_type: place_type.to_string(),
+                address: address.to_string(),
+                triggered_at_ms: now,
+                armed: false,
+                interaction_id: interaction_id.clone(),
+            },
+        );
+        // 古いエントリは判定に影響しないため、保存のついでに捨てる
+        self.entries
+            .retain(|_, entry| Duration::from_millis(now.saturating_sub(entry.triggered_at_ms)) < REARM_TIMEOUT);
+        self.dirty = true;
+        Some(interaction_id)
+    }
+
+    /// RSSIが再アーム閾値を下回った（ビーコンから離れた）ときに呼ぶ
+    pub fn rearm(&mut self, address: &str) {
+        let mut changed = false;
+        for entry in self.entries.values_mut().filter(|entry| entry.address == address && !entry.armed) {
+            entry.armed = true;
+            changed = true;
+        }
+        if changed {
+            debug!(address, "Interaction re-armed");
+            self.dirty = true;
+        }
+    }
+}
+
+/// 1回分のRSSIサンプルを反映した結果
+#[derive(Debug, Clone, Copy)]
+pub struct ProximityUpdate {
+    /// 平滑化したRSSI
+    pub smoothed_rssi: f64,
+    /// 近づいてきて閾値を上回った状態が滞在時間に達した（1回の接近につき1度だけ `true`）
+    pub dwell_complete: bool,
+    /// 滞在判定を通知した後、RSSIが下がりながら閾値を下回った（立ち去った）
+    pub departed: bool,
+}
+
+#[derive(Debug)]
+struct BeaconProximity {
+    smoothed_rssi: f64,
+    /// 平滑化RSSIの傾き（dB/秒、指数移動平均）
+    slope: f64,
+    /// 平滑化・傾きの計算に使えるサンプルがまだ1つしかない
+    fresh: bool,
+    last_sample: Instant,
+    /// 平滑化RSSIが閾値を上回り始めた時刻
+    above_since: Option<Instant>,
+    /// 閾値を上回ったときにRSSIが上昇していたか（近づいてきたか）
+    approaching: bool,
+    /// 今回の接近ですでに滞在判定を通知したか
+    dwell_reported: bool,
+}
+
+/// ビーコンごとにRSSIを平滑化し、閾値を上回った状態が続いたか（滞在）を判定する
+///
+/// 1回の強い反射で閾値をまたいだだけの通行人にポイントを与えないよう、
+/// 平滑化したRSSIが閾値を上回った状態が `dwell_ms` 続いた場合のみインタラクションとする。
+/// また傾きを見て、近づいてきて閾値をまたいだ場合のみを対象にする（起動直後や
+/// Bluetoothを入れ直した直後から閾値を上回っている場合は対象外）。
+pub struct ProximityTracker {
+    config: InteractionConfig,
+    /// ビーコンごとの閾値（キャリブレーションしていないビーコンは `config` の閾値）
+    calibration: Arc<CalibrationStore>,
+    beacons: HashMap<String, BeaconProximity>,
+    time: SharedTimeSource,
+}
+
+impl ProximityTracker {
+    pub fn new(config: InteractionConfig, calibration: Arc<CalibrationStore>, time: SharedTimeSource) -> Self {
+        Self { config, calibration, beacons: HashMap::new(), time }
+    }
+
+    /// RSSIのサンプルを反映する
+    pub fn update(&mut self, address: &str, rssi: i16) -> ProximityUpdate {
+        let now = self.time.now();
+        let alpha = self.config.smoothing_alpha.clamp(0.0, 1.0);
+        let threshold = self.calibration.threshold(address, &self.config).rssi_threshold;
+        let beacon = self
+            .beacons
+            .entry(address.to_string())
+            .or_insert_with(|| BeaconProximity {
+                smoothed_rssi: rssi as f64,
+                slope: 0.0,
+                fresh: true,
+                last_sample: now,
+                above_since: None,
+                approaching: false,
+                dwell_reported: false,
+            });
+
+        let dt = now.duration_since(beacon.last_sample);
+        if dt > SAMPLE_GAP_RESET {
+            // 途切れていた間の状態は分からないため、最初のサンプルとして扱う
+            beacon.smoothed_rssi = rssi as f64;
+            beacon.slope = 0.0;
+            beacon.fresh = true;
+            beacon.above_since = None;
+            beacon.approaching = false;
+            beacon.dwell_reported = false;
+        } else if !dt.is_zero() {
+            let previous = beacon.smoothed_rssi;
+            beacon.smoothed_rssi = alpha * rssi as f64 + (1.0 - alpha) * previous;
+            let instant_slope = (beacon.smoothed_rssi - previous) / dt.as_secs_f64();
+            beacon.slope = if beacon.fresh { instant_slope } else { alpha * instant_slope + (1.0 - alpha) * beacon.slope };
+            beacon.fresh = false;
+        }
+        beacon.last_sample = now;
+
+        let mut dwell_complete = false;
+        let mut departed = false;
+        if beacon.smoothed_rssi > threshold as f64 {
+            if beacon.above_since.is_none() {
+                beacon.above_since = Some(now);
+                beacon.approaching = !beacon.fresh && beacon.slope > self.config.approach_min_slope;
+                debug!(address, slope = beacon.slope, approaching = beacon.approaching, "RSSI rose above threshold");
+            }
+            let dwelled = beacon
+                .above_since
+                .is_some_and(|since| now.duration_since(since) >= Duration::from_millis(self.config.dwell_ms));
+            if beacon.approaching && !beacon.dwell_reported && dwelled {
+                beacon.dwell_reported = true;
+                dwell_complete = true;
+            }
+        } else {
+            departed = beacon.dwell_reported && beacon.slope < 0.0;
+            beacon.above_since = None;
+            beacon.approaching = false;
+            beacon.dwell_reported = false;
+        }
+
+        ProximityUpdate { smoothed_rssi: beacon.smoothed_rssi, dwell_complete, departed }
+    }
+}
+
+/// 来場者トークンを最後に受信してから、近くにいるとみなす時間
+const VISITOR_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 近くにいる来場者（コンパニオンアプリのトークン）を追跡する
+///
+/// 1台のユニットを複数の来場者で使う展示向けに、インタラクションを最も近い来場者に紐づける。
+#[derive(Debug)]
+pub struct VisitorTracker {
+    visitors: HashMap<String, (i16, Instant)>,
+    time: SharedTimeSource,
+}
+
+impl VisitorTracker {
+    pub fn new(time: SharedTimeSource) -> Self {
+        Self { visitors: HashMap::new(), time }
+    }
+
+    pub fn observe(&mut self, token: &str, rssi: i16) {
+        let now = self.time.now();
+        self.visitors.insert(token.to_string(), (rssi, now));
+        self.visitors.retain(|_, (_, seen)| now.saturating_duration_since(*seen) < VISITOR_TIMEOUT);
+    }
+
+    /// 最近受信した中で最もRSSIが強い来場者のトークン（`eligible` が `true` を返す来場者のみ）
+    pub fn nearest(&self, eligible: impl Fn(&str) -> bool) -> Option<String> {
+        self.visitors
+            .iter()
+            .filter(|(_, (_, seen))| self.time.elapsed(*seen) < VISITOR_TIMEOUT)
+            .filter(|(token, _)| eligible(token))
+            .max_by_key(|(_, (rssi, _))| *rssi)
+            .map(|(token, _)| token.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock_system::time_source::MockTimeSource;
+    use std::sync::Arc;
+
+    const PLACE: &str = "fire_rat_robe";
+    const BEACON: &str = "AA:BB:CC:DD:EE:01";
+
+    /// テストごとに別の保存先（並列に実行しても衝突しない）
+    fn state_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("tsukimi-interactions-{}-{}.json", std::process::id(), name))
+    }
+
+    fn new_state(name: &str) -> (InteractionState, Arc<MockTimeSource>) {
+        let time = Arc::new(MockTimeSource::new());
+        let path = state_path(name);
+        let _ = std::fs::remove_file(&path);
+        (InteractionState::load(&path, time.clone()), time)
+    }
+
+    #[test]
+    fn cooldown_blocks_retrigger_even_after_rearm() {
+        let (mut state, time) = new_state("cooldown");
+        assert!(state.try_trigger(PLACE, BEACON).is_some());
+
+        time.advance(Duration::from_secs(1));
+        state.rearm(BEACON);
+        assert!(state.try_trigger(PLACE, BEACON).is_none());
+
+        time.advance(INTERACTION_COOLDOWN);
+        assert!(state.try_trigger(PLACE, BEACON).is_some());
+    }
+
+    #[test]
+    fn unarmed_beacon_waits_for_rearm_timeout() {
+        let (mut state, time) = new_state("rearm-timeout");
+        assert!(state.try_trigger(PLACE, BEACON).is_some());
+
+        // 離れたことを確認できないまま（切断・再接続でRSSIが戻っただけ）ではトリガーしない
+        time.advance(INTERACTION_COOLDOWN * 2);
+        assert!(state.try_trigger(PLACE, BEACON).is_none());
+
+        time.advance(REARM_TIMEOUT);
+        assert!(state.try_trigger(PLACE, BEACON).is_some());
+    }
+
+    #[test]
+    fn rearm_allows_retrigger_after_cooldown() {
+        let (mut state, time) = new_state("rearm");
+        assert!(state.try_trigger(PLACE, BEACON).is_some());
+
+        time.advance(INTERACTION_COOLDOWN);
+        // 別のビーコンから離れても再アームされない
+        state.rearm("AA:BB:CC:DD:EE:02");
+        assert!(state.try_trigger(PLACE, BEACON).is_none());
+
+        state.rearm(BEACON);
+        assert!(state.try_trigger(PLACE, BEACON).is_some());
+    }
+
+    #[test]
+    fn beacons_and_place_types_are_deduplicated_separately() {
+        let (mut state, _time) = new_state("keys");
+        assert!(state.try_trigger(PLACE, BEACON).is_some());
+        assert!(state.try_trigger(PLACE, "AA:BB:CC:DD:EE:02").is_some());
+        assert!(state.try_trigger("buddhas_bowl", BEACON).is_some());
+        assert!(state.try_trigger(PLACE, BEACON).is_none());
+    }
+
+    #[test]
+    fn idempotency_keys_are_unique_per_trigger() {
+        let (mut state, time) = new_state("idempotency");
+        let first = state.try_trigger(PLACE, BEACON).unwrap();
+        time.advance(REARM_TIMEOUT);
+        let second = state.try_trigger(PLACE, BEACON).unwrap();
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn pending_save_only_after_changes() {
+        let (mut state, time) = new_state("pending");
+        assert!(state.take_pending_save().is_none());
+
+        state.try_trigger(PLACE, BEACON);
+        assert!(state.take_pending_save().is_some());
+        assert!(state.take_pending_save().is_none());
+
+        // 再アーム済みのビーコンをもう一度再アームしても保存しない
+        time.advance(Duration::from_secs(1));
+        state.rearm(BEACON);
+        assert!(state.take_pending_save().is_some());
+        state.rearm(BEACON);
+        assert!(state.take_pending_save().is_none());
+    }
+
+    #[test]
+    fn state_round_trips_through_load() {
+        let (mut state, time) = new_state("round-trip");
+        state.try_trigger(PLACE, BEACON);
+        state.try_trigger("buddhas_bowl", BEACON);
+        time.advance(INTERACTION_COOLDOWN);
+        state.rearm(BEACON);
+        let key = state.try_trigger(PLACE, "AA:BB:CC:DD:EE:02").unwrap();
+        state.take_pending_save().unwrap().write();
+
+        // 再起動後も、再アームしていないビーコンは重複として扱い、再アーム済みのビーコンはトリガーできる
+        let path = state_path("round-trip");
+        let mut restored = InteractionState::load(&path, time.clone());
+        assert!(restored.try_trigger(PLACE, "AA:BB:CC:DD:EE:02").is_none());
+        assert_eq!(restored.entries[&(PLACE.to_string(), "AA:BB:CC:DD:EE:02".to_string())].interaction_id, key);
+        assert!(restored.try_trigger(PLACE, BEACON).is_some());
+        assert!(restored.try_trigger("buddhas_bowl", BEACON).is_some());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn corrupt_state_file_starts_empty() {
+        let path = state_path("corrupt");
+        std::fs::write(&path, "not json").unwrap();
+        let mut state = InteractionState::load(&path, Arc::new(MockTimeSource::new()));
+        assert!(state.try_trigger(PLACE, BEACON).is_some());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
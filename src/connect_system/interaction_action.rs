@@ -0,0 +1,148 @@
+use crate::config_system::config_main::{InteractionAction, InteractionConfig};
+use crate::config_system::unit_identity::unit_identity;
+use crate::metrics_system::metrics_main::{metrics, Metrics};
+use crate::net_system::egress::http_client;
+use crate::webhook_system::webhook_main::render_payload;
+use anyhow::{bail, Context};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// インタラクションAPIの応答を待つ時間
+const ACTION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// インタラクションAPIの送信を試みる回数（タイムアウト・サーバーエラーの場合は同じ冪等キーで再送する）
+const ACTION_MAX_ATTEMPTS: u32 = 3;
+
+/// インタラクションAPIの再送までの最初の待ち時間（再送のたびに倍にする）
+const ACTION_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Deserialize)]
+struct InteractionResponse {
+    success: bool,
+    #[serde(default)]
+    message: String,
+}
+
+/// 1回のインタラクションの内容（テンプレートに埋め込む値）
+#[derive(Debug, Clone)]
+pub struct InteractionContext {
+    pub user_id: String,
+    pub place_type: String,
+    pub address: String,
+    /// インタラクションした来場者（コンパニオンアプリを使っている場合のみ）
+    pub visitor_token: Option<String>,
+    /// インタラクション1回ごとに一意な値（サーバー側で重複加算を防ぐために使う）
+    pub idempotency_key: String,
+}
+
+impl InteractionContext {
+    fn fields(&self) -> Value {
+        serde_json::json!({
+            "user_id": self.user_id,
+            "place_type": self.place_type,
+            "address": self.address,
+            "visitor_token": self.visitor_token,
+        })
+    }
+}
+
+/// place_typeごとに設定されたAPIを呼び出す
+///
+/// 新しい展示で別のエンドポイントを呼ぶ場合も、設定の `interaction.actions` を書き足すだけでよい。
+pub struct InteractionDispatcher {
+    players_api_url: String,
+    actions: BTreeMap<String, InteractionAction>,
+    fallback: InteractionAction,
+}
+
+impl InteractionDispatcher {
+    pub fn new(players_api_url: String, config: &InteractionConfig) -> Self {
+        Self { players_api_url, actions: config.actions.clone(), fallback: InteractionAction::default() }
+    }
+
+    /// place_typeに対応するAPIを呼び出し、期待した応答でなければエラーを返す
+    pub async fn dispatch(&self, context: &InteractionContext) -> anyhow::Result<()> {
+        let action = self.actions.get(&context.place_type).unwrap_or(&self.fallback);
+        let fields = context.fields();
+
+        let method = reqwest::Method::from_bytes(action.method.as_bytes())
+            .with_context(|| format!("Invalid HTTP method for {}: {}", context.place_type, action.method))?;
+        // エンドポイントURLを構築: https://tsukimi.paon.dev/players/{user_id}/increment
+        let path = match render_payload(&Value::String(action.path.clone()), &fields) {
+            Value::String(path) => path,
+            other => other.to_string(),
+        };
+        let url = format!("{}/{}", self.players_api_url.trim_end_matches('/'), path.trim_start_matches('/'));
+        // 送信量を数えるため、ボディはここでシリアライズする
+        let body = match &action.body {
+            Some(template) => {
+                let mut body = render_payload(template, &fields);
+                if let Value::Object(map) = &mut body {
+                    map.retain(|_, value| !value.is_null());
+                }
+                Some(serde_json::to_vec(&body)?)
+            }
+            None => None,
+        };
+
+        let idempotency_key = format!("{}:{}", context.user_id, context.idempotency_key);
+        // 届いたかどうか分からない失敗（通信エラー・5xx）は同じキーで再送する
+        let mut retry_delay = ACTION_RETRY_DELAY;
+        let mut attempt = 1;
+        let response = loop {
+            info!(place_type = %context.place_type, %method, url = %url, %idempotency_key, attempt, "Sending interaction request");
+
+            let mut request = http_client().request(method.clone(), &url);
+            for (name, value) in unit_identity().headers() {
+                request = request.header(name, value);
+            }
+            request = request.header("Idempotency-Key", &idempotency_key).timeout(ACTION_TIMEOUT);
+            if let Some(body) = &body {
+                Metrics::add(&metrics().interaction_bytes, body.len() as u64);
+                request = request.header(reqwest::header::CONTENT_TYPE, "application/json").body(body.clone());
+            }
+
+            let result = request.send().await;
+            let retryable = match &result {
+                Ok(response) => response.status().is_server_error(),
+                Err(_) => true,
+            };
+            if !retryable || attempt == ACTION_MAX_ATTEMPTS {
+                break result.context("Failed to send interaction request")?;
+            }
+            match result {
+                Ok(response) => warn!(attempt, "Interaction request failed with status: {}", response.status()),
+                Err(e) => warn!(attempt, "Failed to send interaction request: {}", e),
+            }
+            tokio::time::sleep(retry_delay).await;
+            retry_delay *= 2;
+            attempt += 1;
+        };
+        let status = response.status();
+        let expected = if action.expected_status.is_empty() {
+            status.is_success()
+        } else {
+            action.expected_status.contains(&status.as_u16())
+        };
+        if !expected {
+            bail!("Interaction request to {} failed with status: {}", url, status);
+        }
+
+        if action.check_success_field {
+            let data = response.json::<InteractionResponse>().await.context("Failed to parse interaction response")?;
+            if !data.success {
+                bail!("Interaction request to {} was rejected: {}", url, data.message);
+            }
+            info!(?data, "Interaction request successful");
+        } else {
+            match response.text().await {
+                Ok(text) => info!(%status, response = %text, "Interaction request successful"),
+                Err(e) => warn!(%status, "Failed to read interaction response: {}", e),
+            }
+        }
+        Ok(())
+    }
+}
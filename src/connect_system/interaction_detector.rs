@@ -0,0 +1,334 @@
+use crate::bluetooth_system::address::Address;
+use crate::clock_system::time_source::SharedTimeSource;
+use crate::config_system::config_main::InteractionConfig;
+use crate::connect_system::calibration::CalibrationStore;
+use crate::connect_system::interaction::{InteractionState, PendingSave, ProximityTracker, VisitorTracker};
+use crate::connect_system::sound_catalog::SoundCatalog;
+use crate::connect_system::sound_map::{SharedSoundMap, SoundMap};
+use crate::messages::DeviceInfo;
+use crate::peer_system::visitor_fusion::VisitorFusion;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tracing::{debug, info, instrument, warn};
+
+/// インタラクション検知の結果（SE・インタラクションAPIの呼び出しは受け取った側が行う）
+#[derive(Debug, Clone, PartialEq)]
+pub enum InteractionEvent {
+    /// インタラクションできる場所に近づいて滞在した（重複判定済み）
+    Triggered {
+        place_type: String,
+        address: Address,
+        /// 平滑化したRSSI
+        rssi: f64,
+        /// インタラクションAPIに付ける冪等キー
+        idempotency_key: String,
+        /// 最も近くにいる来場者のトークン（来場者の融合が有効なら自ユニットが受け持つ来場者のみ）
+        visitor_token: Option<String>,
+    },
+    /// インタラクションできる場所から立ち去った
+    Departed { place_type: String, address: Address, rssi: f64 },
+}
+
+/// DeviceInfoの列から接近・滞在・離脱と重複判定を行い、インタラクションを検知する
+///
+/// 入力はRSSIのサンプルとその時点のsound_mapだけで、通信などの副作用を持たない。
+pub struct InteractionDetector {
+    proximity: ProximityTracker,
+    visitors: VisitorTracker,
+    state: InteractionState,
+    catalog: SoundCatalog,
+    config: InteractionConfig,
+    calibration: Arc<CalibrationStore>,
+    /// 隣のユニットが受け持つ来場者をインタラクションに紐づけないため（無効なら `None`）
+    visitor_fusion: Option<Arc<VisitorFusion>>,
+}
+
+impl InteractionDetector {
+    pub fn new(
+        config: InteractionConfig,
+        calibration: Arc<CalibrationStore>,
+        visitor_fusion: Option<Arc<VisitorFusion>>,
+        state: InteractionState,
+        time: SharedTimeSource,
+    ) -> Self {
+        Self {
+            proximity: ProximityTracker::new(config.clone(), Arc::clone(&calibration), Arc::clone(&time)),
+            config,
+            calibration,
+            visitor_fusion,
+            visitors: VisitorTracker::new(time),
+            state,
+            catalog: SoundCatalog::default(),
+        }
+    }
+
+    /// インタラクションできる場所ならそのplace_type
+    fn interactive_place_type(&self, address: &Address, sound_map: &SoundMap) -> Option<String> {
+        sound_map
+            .place_types
+            .get(address)
+            .filter(|place_type| self.catalog.is_interactive(place_type))
+            .cloned()
+    }
+
+    /// 重複判定の状態に変更があれば、保存する内容を返す
+    pub fn take_pending_save(&mut self) -> Option<PendingSave> {
+        self.state.take_pending_save()
+    }
+
+    /// 1件のDeviceInfoを反映する
+    pub fn observe(&mut self, device_info: &DeviceInfo, sound_map: &SoundMap) -> Option<InteractionEvent> {
+        // 来場者のスマートフォンはロケーションではないため、追跡だけ行う
+        if let Some(token) = &device_info.visitor_token {
+            self.visitors.observe(token, device_info.rssi);
+            return None;
+        }
+
+        // 平滑化したRSSIで接近・滞在・離脱を判定
+        let address = &device_info.address;
+        let update = self.proximity.update(address.as_str(), device_info.rssi);
+        let rssi = update.smoothed_rssi;
+        let threshold = self.calibration.threshold(address.as_str(), &self.config);
+
+        // 実際に離れたことを確認できた場合のみ再アームする（切断によるRSSIの欠落では再アームしない）
+        if rssi < threshold.rearm_rssi as f64 {
+            self.state.rearm(address.as_str());
+        }
+
+        // 近づいてきて、平滑化RSSIが閾値を上回った状態が滞在時間続いた場合
+        if update.dwell_complete {
+            info!(
+                %address,
+                rssi,
+                threshold = threshold.rssi_threshold,
+                "I stayed very close to a location (RSSI > {}), checking for interaction", threshold.rssi_threshold
+            );
+            let place_type = self.interactive_place_type(address, sound_map)?;
+            let Some(idempotency_key) = self.state.try_trigger(&place_type, address.as_str()) else {
+                debug!(%place_type, %address, "Interaction already triggered for this beacon");
+                return None;
+            };
+            info!(%place_type, %address, rssi, "Triggering interaction");
+            return Some(InteractionEvent::Triggered {
+                place_type,
+                address: address.clone(),
+                rssi,
+                idempotency_key,
+                visitor_token: self
+                    .visitors
+                    .nearest(|token| self.visitor_fusion.as_ref().is_none_or(|fusion| fusion.owns(token))),
+            });
+        }
+
+        // インタラクションできる場所から立ち去った場合
+        if update.departed {
+            let place_type = self.interactive_place_type(address, sound_map)?;
+            return Some(InteractionEvent::Departed { place_type, address: address.clone(), rssi });
+        }
+        None
+    }
+}
+
+/// 受け取ったDeviceInfoからインタラクションを検知して通知するタスク（入力のチャンネルが閉じると終了する）
+#[instrument(skip_all)]
+pub async fn interaction_detector_main(
+    mut detector: InteractionDetector,
+    mut device_rx: mpsc::Receiver<Arc<DeviceInfo>>,
+    sound_map: Arc<SharedSoundMap>,
+    event_tx: mpsc::Sender<InteractionEvent>,
+) {
+    while let Some(device_info) = device_rx.recv().await {
+        let event = detector.observe(&device_info, &sound_map.load());
+        // トリガー・再アームのたびに保存する（ファイルへの書き込みでランタイムのスレッドを止めない）
+        if let Some(save) = detector.take_pending_save() {
+            if let Err(e) = tokio::task::spawn_blocking(move || save.write()).await {
+                warn!("Interaction state save task failed: {}", e);
+            }
+        }
+        let Some(event) = event else {
+            continue;
+        };
+        if event_tx.send(event).await.is_err() {
+            break;
+        }
+    }
+    info!("Interaction detector stopped");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock_system::time_source::MockTimeSource;
+    use std::collections::HashMap;
+    use std::path::{Path, PathBuf};
+    use std::time::{Duration, Instant};
+
+    const BEACON: &str = "AA:BB:CC:DD:EE:01";
+    const SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
+
+    struct Harness {
+        detector: InteractionDetector,
+        sound_map: SoundMap,
+        time: Arc<MockTimeSource>,
+        state_path: PathBuf,
+    }
+
+    impl Harness {
+        fn new(name: &str, place_type: &str) -> Self {
+            let time = Arc::new(MockTimeSource::new());
+            let dir = std::env::temp_dir();
+            let state_path = dir.join(format!("tsukimi-detector-{}-{}.json", std::process::id(), name));
+            let _ = std::fs::remove_file(&state_path);
+            // 存在しないファイルを読むと、キャリブレーションなし（設定の閾値）になる
+            let calibration = CalibrationStore::load(&dir.join(format!("tsukimi-detector-calibration-{}.json", std::process::id())));
+            let state = InteractionState::load(&state_path, time.clone());
+            let detector = InteractionDetector::new(InteractionConfig::default(), calibration, None, state, time.clone());
+            let address: Address = BEACON.parse().unwrap();
+            let sound_map = SoundMap {
+                sounds: HashMap::from([(address.clone(), "tsukimi-nezumi_1.mp3".to_string())]),
+                place_types: HashMap::from([(address, place_type.to_string())]),
+                ..Default::default()
+            };
+            Self { detector, sound_map, time, state_path }
+        }
+
+        /// RSSIの列を100ミリ秒ごとのサンプルとして流し、検知したイベントを返す
+        fn play(&mut self, trace: impl IntoIterator<Item = i16>) -> Vec<InteractionEvent> {
+            trace
+                .into_iter()
+                .filter_map(|rssi| {
+                    self.time.advance(SAMPLE_INTERVAL);
+                    self.detector.observe(&device(rssi), &self.sound_map)
+                })
+                .collect()
+        }
+    }
+
+    impl Drop for Harness {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.state_path);
+        }
+    }
+
+    fn device(rssi: i16) -> DeviceInfo {
+        DeviceInfo {
+            address: BEACON.parse().unwrap(),
+            rssi,
+            last_seen: Instant::now(),
+            received_at: Instant::now(),
+            forwarded_at: None,
+            visitor_token: None,
+            local_name: None,
+            tx_power: None,
+            manufacturer_data: HashMap::new(),
+            service_data: HashMap::new(),
+        }
+    }
+
+    /// 遠く（-80dBm）から近づいて `hold` 秒とどまる
+    fn approach_and_stay(hold: Duration) -> Vec<i16> {
+        let far = std::iter::repeat_n(-80, 10);
+        let closing = (-80..=-30).rev().step_by(10).rev();
+        let near = std::iter::repeat_n(-30, (hold.as_millis() / SAMPLE_INTERVAL.as_millis()) as usize);
+        far.chain(closing).chain(near).collect()
+    }
+
+    /// 近く（-30dBm）から遠く（-90dBm）へ離れる
+    fn walk_away() -> Vec<i16> {
+        std::iter::repeat_n(-90, 30).collect()
+    }
+
+    fn triggered(events: &[InteractionEvent]) -> usize {
+        events.iter().filter(|event| matches!(event, InteractionEvent::Triggered { .. })).count()
+    }
+
+    #[test]
+    fn staying_below_threshold_never_triggers() {
+        let mut harness = Harness::new("below", "fire_rat_robe");
+        assert!(harness.play(std::iter::repeat_n(-60, 100)).is_empty());
+    }
+
+    #[test]
+    fn approach_triggers_once_after_dwell() {
+        let mut harness = Harness::new("dwell", "fire_rat_robe");
+        // 既定の滞在時間（2秒）に満たない間はトリガーしない
+        assert!(harness.play(approach_and_stay(Duration::from_millis(1500))).is_empty());
+
+        let events = harness.play(std::iter::repeat_n(-30, 10));
+        assert_eq!(triggered(&events), 1);
+        match &events[0] {
+            InteractionEvent::Triggered { place_type, address, rssi, .. } => {
+                assert_eq!(place_type, "fire_rat_robe");
+                assert_eq!(address.as_str(), BEACON);
+                assert!(*rssi > InteractionConfig::default().rssi_threshold as f64);
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+
+        // とどまり続けても1回の接近につき1度だけ
+        assert!(harness.play(std::iter::repeat_n(-30, 100)).is_empty());
+    }
+
+    #[test]
+    fn brief_spike_above_threshold_does_not_trigger() {
+        let mut harness = Harness::new("spike", "fire_rat_robe");
+        let trace: Vec<i16> = approach_and_stay(Duration::from_millis(500)).into_iter().chain(walk_away()).collect();
+        assert_eq!(triggered(&harness.play(trace)), 0);
+    }
+
+    #[test]
+    fn already_near_at_start_does_not_trigger() {
+        // 起動直後・Bluetoothを入れ直した直後から閾値を上回っている場合は近づいてきたとみなさない
+        let mut harness = Harness::new("already-near", "fire_rat_robe");
+        assert!(harness.play(std::iter::repeat_n(-30, 100)).is_empty());
+    }
+
+    #[test]
+    fn leaving_reports_departure_and_rearms_after_cooldown() {
+        let mut harness = Harness::new("depart", "fire_rat_robe");
+        assert_eq!(triggered(&harness.play(approach_and_stay(Duration::from_secs(3)))), 1);
+
+        let events = harness.play(walk_away());
+        assert!(events.iter().any(|event| matches!(event, InteractionEvent::Departed { .. })));
+
+        // 離れてもクールダウン中（前回から10秒未満）はトリガーしない
+        assert_eq!(triggered(&harness.play(approach_and_stay(Duration::from_secs(3)))), 0);
+
+        // 離れてからクールダウンが過ぎれば、もう一度インタラクションできる
+        harness.play(walk_away());
+        harness.time.advance(Duration::from_secs(10));
+        assert_eq!(triggered(&harness.play(approach_and_stay(Duration::from_secs(3)))), 1);
+    }
+
+    #[test]
+    fn adapter_flap_without_leaving_does_not_retrigger() {
+        let mut harness = Harness::new("flap", "fire_rat_robe");
+        assert_eq!(triggered(&harness.play(approach_and_stay(Duration::from_secs(3)))), 1);
+
+        // サンプルが途切れた後に遠い値から近い値へ戻る（切断・再接続）。再アーム閾値を下回る前に戻るため、離れたとはみなさない
+        harness.time.advance(Duration::from_secs(15));
+        let reconnect: Vec<i16> = std::iter::repeat_n(-50, 2).chain(std::iter::repeat_n(-30, 40)).collect();
+        assert_eq!(triggered(&harness.play(reconnect)), 0);
+    }
+
+    #[test]
+    fn non_interactive_place_type_is_ignored() {
+        let mut harness = Harness::new("non-interactive", "projection_mapping");
+        let trace: Vec<i16> = approach_and_stay(Duration::from_secs(3)).into_iter().chain(walk_away()).collect();
+        assert!(harness.play(trace).is_empty());
+    }
+
+    #[test]
+    fn trigger_is_persisted_for_the_next_detector() {
+        let mut harness = Harness::new("persist", "fire_rat_robe");
+        assert_eq!(triggered(&harness.play(approach_and_stay(Duration::from_secs(3)))), 1);
+        harness.detector.take_pending_save().unwrap().write();
+        assert!(Path::new(&harness.state_path).exists());
+
+        // 再起動（新しい検知器）してもクールダウンは引き継がれ、すぐに近づき直してもトリガーしない
+        let state = InteractionState::load(&harness.state_path, harness.time.clone());
+        let calibration = CalibrationStore::load(&std::env::temp_dir().join("tsukimi-detector-calibration-missing.json"));
+        harness.detector = InteractionDetector::new(InteractionConfig::default(), calibration, None, state, harness.time.clone());
+        assert_eq!(triggered(&harness.play(approach_and_stay(Duration::from_secs(3)))), 0);
+    }
+}
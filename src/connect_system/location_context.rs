@@ -0,0 +1,26 @@
+use crate::bluetooth_system::address::Address;
+use serde::Serialize;
+use tokio::sync::watch;
+
+/// 最も近い（RSSIが最も強い）ロケーションの情報
+///
+/// サーバーのLocationUpdateを受け取るたびに更新し、状態API・サーバーへの状態報告で参照する。
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LocationContext {
+    /// ビーコンのアドレス（まだ判定していなければ `None`）
+    pub address: Option<Address>,
+    pub place_type: String,
+    /// 音源の選択に使うベースロケーションタイプ（`main`・`ryu` など）
+    pub base_type: String,
+}
+
+impl Default for LocationContext {
+    fn default() -> Self {
+        Self { address: None, place_type: String::new(), base_type: "main".to_string() }
+    }
+}
+
+/// 現在のロケーションを配信するwatchチャンネルを作成する
+pub fn channel() -> (watch::Sender<LocationContext>, watch::Receiver<LocationContext>) {
+    watch::channel(LocationContext::default())
+}
@@ -0,0 +1,78 @@
+use crate::audio_system::remote_source::is_remote;
+use crate::bluetooth_system::address::Address;
+use crate::connect_system::sound_catalog::SoundCatalog;
+use crate::proto::proto::LocationInfo;
+use std::collections::HashSet;
+use tracing::warn;
+
+/// LocationUpdateのロケーション一覧を検証し、受け付けられない理由を返す（問題がなければ空）
+///
+/// 空の一覧・不正なアドレス・アドレスの重複は不完全な一覧として扱う。
+/// 未知のplace_typeはフォールバックの音源で再生できるため、警告のみとする。
+pub fn validate_locations(locations: &[LocationInfo], catalog: &SoundCatalog) -> Vec<String> {
+    if locations.is_empty() {
+        return vec!["location list is empty".to_string()];
+    }
+
+    let mut problems = Vec::new();
+    let mut seen = HashSet::new();
+    for location in locations {
+        // 表記の揺れ（大文字・小文字、区切り文字）は正規化してから重複を判定する
+        match location.address.parse::<Address>() {
+            Ok(address) => {
+                if !seen.insert(address) {
+                    problems.push(format!("duplicate address: {}", location.address));
+                }
+            }
+            Err(e) => problems.push(e.to_string()),
+        }
+        if catalog.base_type(&location.place_type).is_none() && !is_remote(&location.sound_url) {
+            warn!(address = %location.address, place_type = %location.place_type, "LocationUpdate contains unknown place_type");
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn location(address: &str, place_type: &str) -> LocationInfo {
+        LocationInfo { id: address.to_string(), address: address.to_string(), place_type: place_type.to_string(), ..Default::default() }
+    }
+
+    fn validate(locations: &[LocationInfo]) -> Vec<String> {
+        validate_locations(locations, &SoundCatalog::default())
+    }
+
+    #[test]
+    fn complete_list_is_accepted() {
+        let locations = [location("AA:BB:CC:DD:EE:01", "buddhas_bowl"), location("AA:BB:CC:DD:EE:02", "dragons_jewel")];
+        assert!(validate(&locations).is_empty());
+    }
+
+    #[test]
+    fn empty_list_is_rejected() {
+        assert_eq!(validate(&[]), vec!["location list is empty".to_string()]);
+    }
+
+    #[test]
+    fn invalid_address_is_rejected() {
+        let problems = validate(&[location("AA:BB:CC:DD:EE:01", "buddhas_bowl"), location("not-an-address", "dragons_jewel"), location("", "jeweled_branch")]);
+        assert_eq!(problems.len(), 2, "{:?}", problems);
+        assert!(problems[0].contains("not-an-address"), "{:?}", problems);
+    }
+
+    #[test]
+    fn duplicate_address_is_rejected_after_normalizing() {
+        let problems = validate(&[location("AA:BB:CC:DD:EE:01", "buddhas_bowl"), location("aa-bb-cc-dd-ee-01", "dragons_jewel")]);
+        assert_eq!(problems, vec!["duplicate address: aa-bb-cc-dd-ee-01".to_string()]);
+    }
+
+    #[test]
+    fn unknown_place_type_is_only_a_warning() {
+        assert!(validate(&[location("AA:BB:CC:DD:EE:01", "unknown-type")]).is_empty());
+        let remote = LocationInfo { sound_url: "https://example.com/bgm.mp3".to_string(), ..location("AA:BB:CC:DD:EE:02", "unknown-type") };
+        assert!(validate(&[remote]).is_empty());
+    }
+}
@@ -0,0 +1,184 @@
+use crate::config_system::config_main::MqttConfig;
+use crate::connect_system::transport::{InboundStream, OutboundStream, Transport};
+use crate::proto::proto::stream_device_info_response::Event;
+use crate::proto::proto::{
+    BgmOverrideEvent, FleetCommand, LanguageUpdate, LiveStreamEvent, LocationUpdate, MoonlightUpdate, PointUpdate, SePlayEvent, SoundSettingUpdate,
+    StreamDeviceInfoResponse, UploadThrottle, VolumeUpdate,
+};
+use anyhow::{anyhow, Result};
+use prost::Message;
+use rumqttc::{AsyncClient, MqttOptions, Packet, QoS};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+use tracing::{debug, error, info, warn};
+
+/// 購読するトピック（プレフィックスからの相対パス）
+const EVENT_TOPICS: &[&str] = &[
+    "location",
+    "point",
+    "sound_setting",
+    "moonlight",
+    "se_play",
+    "bgm_override",
+    "volume",
+    "live_stream",
+    "language",
+    "upload_throttle",
+    "fleet_command",
+];
+
+/// MQTTブローカー経由のトランスポート
+///
+/// - 送信: `{prefix}/device_info` に `StreamDeviceInfoRequest` をprotobufで publish
+/// - 受信: `{prefix}/{location,point,...,fleet_command}` を購読し、各イベントのprotobufをデコードする
+pub struct MqttTransport {
+    config: MqttConfig,
+}
+
+impl MqttTransport {
+    pub fn new(config: MqttConfig) -> Self {
+        Self { config }
+    }
+
+    fn topic(&self, name: &str) -> String {
+        format!("{}/{}", self.config.topic_prefix, name)
+    }
+
+    /// トピック名とペイロードからイベントをデコードする（未知のトピックは `None`）
+    fn decode_event(name: &str, payload: &[u8]) -> Result<Option<Event>> {
+        let event = match name {
+            "location" => Event::LocationUpdate(LocationUpdate::decode(payload)?),
+            "point" => Event::PointUpdate(PointUpdate::decode(payload)?),
+            "sound_setting" => Event::SoundSettingUpdate(SoundSettingUpdate::decode(payload)?),
+            "moonlight" => Event::MoonlightUpdate(MoonlightUpdate::decode(payload)?),
+            "se_play" => Event::SePlay(SePlayEvent::decode(payload)?),
+            "bgm_override" => Event::BgmOverride(BgmOverrideEvent::decode(payload)?),
+            "volume" => Event::VolumeUpdate(VolumeUpdate::decode(payload)?),
+            "live_stream" => Event::LiveStream(LiveStreamEvent::decode(payload)?),
+            "language" => Event::LanguageUpdate(LanguageUpdate::decode(payload)?),
+            "upload_throttle" => Event::UploadThrottle(UploadThrottle::decode(payload)?),
+            "fleet_command" => Event::FleetCommand(FleetCommand::decode(payload)?),
+            _ => return Ok(None),
+        };
+        Ok(Some(event))
+    }
+}
+
+#[tonic::async_trait]
+impl Transport for MqttTransport {
+    async fn open(&mut self, mut outbound: OutboundStream) -> Result<InboundStream> {
+        let client_id = if self.config.client_id.is_empty() {
+            format!("tsukimi-speaker-{}", std::process::id())
+        } else {
+            self.config.client_id.clone()
+        };
+        info!(host = %self.config.host, port = self.config.port, %client_id, "Connecting to MQTT broker");
+
+        let mut options = MqttOptions::new(client_id, self.config.host.clone(), self.config.port);
+        options.set_keep_alive(Duration::from_secs(self.config.keep_alive_secs.max(5)));
+        if let (Some(username), Some(password)) = (&self.config.username, &self.config.password) {
+            options.set_credentials(username.clone(), password.clone());
+        }
+
+        let (client, mut eventloop) = AsyncClient::new(options, 64);
+        for name in EVENT_TOPICS {
+            client.subscribe(self.topic(name), QoS::AtLeastOnce).await?;
+        }
+
+        // 送信ストリームをpublishするタスク
+        let device_info_topic = self.topic("device_info");
+        let publisher = tokio::spawn(async move {
+            while let Some(request) = outbound.next().await {
+                if let Err(e) = client
+                    .publish(device_info_topic.as_str(), QoS::AtMostOnce, false, request.encode_to_vec())
+                    .await
+                {
+                    error!("Failed to publish device info to MQTT: {}", e);
+                    break;
+                }
+            }
+            debug!("MQTT publisher finished");
+        });
+
+        // イベントループを回し、受信したメッセージをイベントに変換するタスク
+        let (tx, rx) = mpsc::channel(32);
+        let prefix = format!("{}/", self.config.topic_prefix);
+        tokio::spawn(async move {
+            loop {
+                match eventloop.poll().await {
+                    Ok(rumqttc::Event::Incoming(Packet::ConnAck(_))) => {
+                        info!("Connected to MQTT broker");
+                    }
+                    Ok(rumqttc::Event::Incoming(Packet::Publish(publish))) => {
+                        let Some(name) = publish.topic.strip_prefix(&prefix) else {
+                            continue;
+                        };
+                        let response = match Self::decode_event(name, &publish.payload) {
+                            Ok(Some(event)) => StreamDeviceInfoResponse { event: Some(event), ..Default::default() },
+                            Ok(None) => {
+                                warn!(topic = %publish.topic, "Received message on unknown MQTT topic");
+                                StreamDeviceInfoResponse::default()
+                            }
+                            Err(e) => {
+                                warn!(topic = %publish.topic, "Failed to decode MQTT payload: {}", e);
+                                continue;
+                            }
+                        };
+                        if tx.send(Ok(response)).await.is_err() {
+                            break;
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        let _ = tx.send(Err(anyhow!("MQTT connection error: {}", e))).await;
+                        break;
+                    }
+                }
+            }
+            publisher.abort();
+        });
+
+        Ok(Box::pin(ReceiverStream::new(rx)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_the_event_for_its_topic() {
+        let point = PointUpdate { user_id: "user-1".to_string(), points: 42 };
+        let event = MqttTransport::decode_event("point", &point.encode_to_vec()).unwrap();
+        assert_eq!(event, Some(Event::PointUpdate(point)));
+
+        let volume = VolumeUpdate { master_volume: 0.5, muted: true, ..Default::default() };
+        let event = MqttTransport::decode_event("volume", &volume.encode_to_vec()).unwrap();
+        assert_eq!(event, Some(Event::VolumeUpdate(volume)));
+    }
+
+    #[test]
+    fn every_subscribed_topic_is_decoded() {
+        // 購読しているのにデコードできないトピックがあると、そのイベントは届かない
+        for name in EVENT_TOPICS {
+            assert!(matches!(MqttTransport::decode_event(name, &[]), Ok(Some(_))), "{}", name);
+        }
+    }
+
+    #[test]
+    fn unknown_topic_is_not_an_event() {
+        assert!(matches!(MqttTransport::decode_event("device_info", &[]), Ok(None)));
+        assert!(matches!(MqttTransport::decode_event("unknown", &[0xff]), Ok(None)));
+    }
+
+    #[test]
+    fn malformed_payload_is_an_error() {
+        // フィールド1の長さが5バイトなのに1バイトしかない
+        let truncated = [0x0a, 0x05, 0x01];
+        assert!(MqttTransport::decode_event("point", &truncated).is_err());
+        // 不正なワイヤータイプ
+        assert!(MqttTransport::decode_event("moonlight", &[0x0f]).is_err());
+    }
+}
@@ -0,0 +1,91 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tracing::{debug, info, warn};
+
+/// 最後に受信したポイント数を保存するファイル（作業ディレクトリからの相対パス）
+pub const DEFAULT_POINTS_CACHE_PATH: &str = "tsukimi-points.json";
+
+/// ディスクに保存するポイント数（ユーザー＝ユニットのIDと対で保存する）
+///
+/// 再起動直後に最初のPointUpdateが届くまで誤ったレベルの音源を鳴らさないように使う。
+/// SDカードを別のユニットに差し替えた場合に他人のポイントを使わないよう、IDが一致する場合のみ復元する。
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PointsCache {
+    pub user_id: String,
+    pub points: i32,
+}
+
+impl PointsCache {
+    /// 指定ユーザーのポイント数を読み込む（ファイルが無い・壊れている・別ユーザーの場合は `None`）
+    pub fn load(path: &Path, user_id: &str) -> Option<i32> {
+        let text = std::fs::read_to_string(path).ok()?;
+        match serde_json::from_str::<PointsCache>(&text) {
+            Ok(cache) if cache.user_id == user_id => {
+                info!(user_id, points = cache.points, "Restored points from cache");
+                Some(cache.points)
+            }
+            Ok(cache) => {
+                info!(cached_user_id = %cache.user_id, user_id, "Ignoring points cache for another user");
+                None
+            }
+            Err(e) => {
+                warn!(path = %path.display(), "Failed to parse points cache: {}", e);
+                None
+            }
+        }
+    }
+
+    /// ポイント数を保存する（書きかけのファイルが残らないよう一時ファイルから置き換える）
+    pub fn save(path: &Path, user_id: &str, points: i32) -> Result<()> {
+        let cache = PointsCache { user_id: user_id.to_string(), points };
+        let tmp_path = path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec(&cache)?)
+            .with_context(|| format!("Failed to write points cache: {}", tmp_path.display()))?;
+        std::fs::rename(&tmp_path, path)
+            .with_context(|| format!("Failed to replace points cache: {}", path.display()))?;
+        debug!(user_id, points, "Saved points cache");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn cache_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("tsukimi-points-{}-{}.json", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn saved_points_are_restored_for_the_same_user() {
+        let path = cache_path("roundtrip");
+        PointsCache::save(&path, "AA:BB:CC:DD:EE:01", 3).unwrap();
+        assert_eq!(PointsCache::load(&path, "AA:BB:CC:DD:EE:01"), Some(3));
+        // 新しい値で置き換え、一時ファイルは残さない
+        PointsCache::save(&path, "AA:BB:CC:DD:EE:01", 7).unwrap();
+        assert_eq!(PointsCache::load(&path, "AA:BB:CC:DD:EE:01"), Some(7));
+        assert!(!path.with_extension("json.tmp").exists());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn points_of_another_user_are_ignored() {
+        let path = cache_path("other-user");
+        PointsCache::save(&path, "AA:BB:CC:DD:EE:01", 3).unwrap();
+        assert_eq!(PointsCache::load(&path, "AA:BB:CC:DD:EE:02"), None);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn missing_or_corrupt_cache_is_ignored() {
+        let path = cache_path("corrupt");
+        assert_eq!(PointsCache::load(&path, "AA:BB:CC:DD:EE:01"), None);
+        std::fs::write(&path, b"{\"user_id\": \"AA:BB:CC:DD:EE:01\", \"poi").unwrap();
+        assert_eq!(PointsCache::load(&path, "AA:BB:CC:DD:EE:01"), None);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,151 @@
+use crate::clock_system::time_source::SharedTimeSource;
+use crate::config_system::config_main::ReconnectConfig;
+use rand::Rng;
+use std::time::{Duration, Instant};
+
+/// サーバーへの再接続の待ち時間を決める（指数バックオフ + ジッター）
+///
+/// バックエンドの再起動後に全ユニットが同じ間隔で接続し直さないよう、待ち時間は
+/// `[上限の (1 - jitter) 倍, 上限]` の範囲でランダムに選ぶ。
+pub struct ReconnectBackoff {
+    config: ReconnectConfig,
+    time: SharedTimeSource,
+    /// 接続してから（または起動してから）の連続失敗回数
+    attempts: u32,
+    /// 最後に接続していた時刻（起動直後は起動時刻。接続中は `None`）
+    offline_since: Option<Instant>,
+    /// 長時間の切断を通知済み
+    alerted: bool,
+}
+
+impl ReconnectBackoff {
+    pub fn new(config: ReconnectConfig, time: SharedTimeSource) -> Self {
+        let offline_since = Some(time.now());
+        Self { config, time, attempts: 0, offline_since, alerted: false }
+    }
+
+    /// 接続できた（待ち時間を初期値に戻す）
+    pub fn connected(&mut self) {
+        self.attempts = 0;
+        self.offline_since = None;
+        self.alerted = false;
+    }
+
+    /// 接続が切れた・接続に失敗した（切断が始まった時刻を記録する）
+    pub fn disconnected(&mut self) {
+        if self.offline_since.is_none() {
+            self.offline_since = Some(self.time.now());
+        }
+    }
+
+    /// 次の接続までの待ち時間（呼ぶたびに上限が伸びる）
+    pub fn next_delay(&mut self) -> Duration {
+        let initial = self.config.initial_delay_ms.max(1) as f64;
+        let ceiling = (initial * self.config.multiplier.max(1.0).powi(self.attempts as i32)).min(self.config.max_delay_ms.max(1) as f64);
+        self.attempts = self.attempts.saturating_add(1);
+        let jitter = self.config.jitter.clamp(0.0, 1.0);
+        let delay_ms = ceiling * (1.0 - jitter * rand::thread_rng().gen::<f64>());
+        Duration::from_millis(delay_ms as u64)
+    }
+
+    /// 切断が続いている時間
+    pub fn offline_for(&self) -> Option<Duration> {
+        self.offline_since.map(|since| self.time.elapsed(since))
+    }
+
+    /// 切断が `alert_after_ms` を超えて続いていて、まだ通知していなければ `true`（1回の切断につき1回だけ）
+    pub fn should_alert(&mut self) -> bool {
+        let limit = Duration::from_millis(self.config.alert_after_ms);
+        if self.alerted || self.offline_for().is_none_or(|offline| offline < limit) {
+            return false;
+        }
+        self.alerted = true;
+        true
+    }
+
+    /// 今回の切断が長時間の切断として通知済みかどうか
+    pub fn alerted(&self) -> bool {
+        self.alerted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock_system::time_source::MockTimeSource;
+    use std::sync::Arc;
+
+    fn config(jitter: f64) -> ReconnectConfig {
+        ReconnectConfig { initial_delay_ms: 1000, multiplier: 2.0, max_delay_ms: 10_000, jitter, alert_after_ms: 60_000 }
+    }
+
+    fn backoff(jitter: f64) -> (ReconnectBackoff, Arc<MockTimeSource>) {
+        let time = Arc::new(MockTimeSource::new());
+        (ReconnectBackoff::new(config(jitter), time.clone()), time)
+    }
+
+    fn millis(delays: impl Iterator<Item = Duration>) -> Vec<u64> {
+        delays.map(|delay| delay.as_millis() as u64).collect()
+    }
+
+    #[test]
+    fn delay_grows_exponentially_up_to_the_cap() {
+        let (mut backoff, _) = backoff(0.0);
+        let delays = millis((0..7).map(|_| backoff.next_delay()));
+        assert_eq!(delays, vec![1000, 2000, 4000, 8000, 10_000, 10_000, 10_000]);
+    }
+
+    #[test]
+    fn jittered_delay_stays_between_the_bounds() {
+        let (mut backoff, _) = backoff(0.5);
+        for ceiling in [1000, 2000, 4000, 8000, 10_000, 10_000] {
+            let delay = backoff.next_delay().as_millis() as u64;
+            assert!((ceiling / 2..=ceiling).contains(&delay), "{} not in [{}, {}]", delay, ceiling / 2, ceiling);
+        }
+        for _ in 0..200 {
+            let delay = backoff.next_delay().as_millis() as u64;
+            assert!((5000..=10_000).contains(&delay), "{}", delay);
+        }
+    }
+
+    #[test]
+    fn connecting_resets_the_delay() {
+        let (mut backoff, time) = backoff(0.0);
+        for _ in 0..5 {
+            backoff.next_delay();
+        }
+        backoff.connected();
+        assert_eq!(backoff.offline_for(), None);
+        assert_eq!(backoff.next_delay(), Duration::from_millis(1000));
+
+        time.advance(Duration::from_secs(3));
+        backoff.disconnected();
+        time.advance(Duration::from_secs(2));
+        // 切断が続く間は最初に切れた時刻から数える
+        backoff.disconnected();
+        assert_eq!(backoff.offline_for(), Some(Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn alert_fires_once_per_outage() {
+        let (mut backoff, time) = backoff(0.0);
+        time.advance(Duration::from_millis(59_999));
+        assert!(!backoff.should_alert());
+        time.advance(Duration::from_millis(1));
+        assert!(backoff.should_alert());
+        assert!(backoff.alerted());
+        time.advance(Duration::from_secs(600));
+        assert!(!backoff.should_alert());
+
+        // 接続できたら次の切断で改めて通知する
+        backoff.connected();
+        assert!(!backoff.alerted());
+        assert!(!backoff.should_alert());
+        backoff.disconnected();
+        time.advance(Duration::from_secs(59));
+        assert!(!backoff.should_alert());
+        time.advance(Duration::from_secs(1));
+        assert!(backoff.should_alert());
+        assert!(!backoff.should_alert());
+    }
+}
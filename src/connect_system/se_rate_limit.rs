@@ -0,0 +1,137 @@
+use crate::clock_system::time_source::SharedTimeSource;
+use crate::config_system::config_main::SeRateLimitConfig;
+use crate::metrics_system::metrics_main::{metrics, Metrics};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// SE1つ分の直近の再生
+#[derive(Default)]
+struct SeWindow {
+    /// 上限を数える時間内に鳴らした時刻（古い順）
+    played: VecDeque<Instant>,
+    /// 上限を超えて捨てた回数（鳴らせるようになったら報告して0に戻す）
+    dropped: u64,
+}
+
+/// サーバーの指示で鳴らすSEを、SEのファイルごとに一定時間あたりの回数までに抑える
+///
+/// 再接続してもSEの嵐が続くことがあるため、接続をまたいで同じものを使う。
+pub struct SeRateLimiter {
+    config: SeRateLimitConfig,
+    time: SharedTimeSource,
+    windows: Mutex<HashMap<String, SeWindow>>,
+}
+
+impl SeRateLimiter {
+    pub fn new(config: SeRateLimitConfig, time: SharedTimeSource) -> Arc<Self> {
+        if config.enabled {
+            info!(max_triggers = config.max_triggers, window_ms = config.window_ms, "Server-driven SE rate limit enabled");
+        }
+        Arc::new(Self { config, time, windows: Mutex::new(HashMap::new()) })
+    }
+
+    /// SEを鳴らしてよいか（上限を超えた場合は捨てた回数を数えて `false`）
+    pub fn allow(&self, file: &str) -> bool {
+        if !self.config.enabled {
+            return true;
+        }
+        let now = self.time.now();
+        let window = Duration::from_millis(self.config.window_ms);
+        let mut windows = self.windows.lock().unwrap();
+        // 鳴らしていないSEの記録は残さない
+        windows.retain(|_, se| {
+            while se.played.front().is_some_and(|played| now.duration_since(*played) >= window) {
+                se.played.pop_front();
+            }
+            !se.played.is_empty() || se.dropped > 0
+        });
+
+        let se = windows.entry(file.to_string()).or_default();
+        if se.played.len() >= self.config.max_triggers as usize {
+            se.dropped += 1;
+            Metrics::add(&metrics().se_triggers_dropped, 1);
+            if se.dropped == 1 {
+                warn!(
+                    file,
+                    max_triggers = self.config.max_triggers,
+                    window_ms = self.config.window_ms,
+                    "Too many server-driven SE triggers - dropping until the rate falls"
+                );
+            }
+            return false;
+        }
+        if se.dropped > 0 {
+            info!(file, dropped = se.dropped, "Server-driven SE triggers back under the limit");
+            se.dropped = 0;
+        }
+        se.played.push_back(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock_system::time_source::MockTimeSource;
+    use std::sync::atomic::Ordering;
+
+    /// `se_triggers_dropped` はプロセス全体で共有するため、捨てる回数を数えるテストは順に動かす
+    static DROPPED_COUNTER: Mutex<()> = Mutex::new(());
+
+    fn limiter(max_triggers: u32) -> (Arc<SeRateLimiter>, Arc<MockTimeSource>) {
+        let time = Arc::new(MockTimeSource::new());
+        let config = SeRateLimitConfig { enabled: true, max_triggers, window_ms: 1000 };
+        (SeRateLimiter::new(config, time.clone()), time)
+    }
+
+    fn dropped() -> u64 {
+        metrics().se_triggers_dropped.load(Ordering::Relaxed)
+    }
+
+    #[test]
+    fn drops_triggers_over_the_limit_and_counts_them() {
+        let _serial = DROPPED_COUNTER.lock().unwrap_or_else(|e| e.into_inner());
+        let (limiter, _) = limiter(3);
+        let before = dropped();
+        assert_eq!((0..5).map(|_| limiter.allow("se-point.mp3")).collect::<Vec<_>>(), vec![true, true, true, false, false]);
+        assert_eq!(dropped() - before, 2);
+    }
+
+    #[test]
+    fn triggers_are_allowed_again_after_the_window() {
+        let _serial = DROPPED_COUNTER.lock().unwrap_or_else(|e| e.into_inner());
+        let (limiter, time) = limiter(2);
+        assert!(limiter.allow("se-point.mp3"));
+        time.advance(Duration::from_millis(600));
+        assert!(limiter.allow("se-point.mp3"));
+        assert!(!limiter.allow("se-point.mp3"));
+
+        // 最初の再生が時間外になった分だけ鳴らせる
+        time.advance(Duration::from_millis(400));
+        assert!(limiter.allow("se-point.mp3"));
+        assert!(!limiter.allow("se-point.mp3"));
+        time.advance(Duration::from_millis(1000));
+        assert!(limiter.allow("se-point.mp3"));
+        assert!(limiter.allow("se-point.mp3"));
+    }
+
+    #[test]
+    fn each_file_has_its_own_limit() {
+        let _serial = DROPPED_COUNTER.lock().unwrap_or_else(|e| e.into_inner());
+        let (limiter, _) = limiter(1);
+        assert!(limiter.allow("se-point.mp3"));
+        assert!(!limiter.allow("se-point.mp3"));
+        assert!(limiter.allow("se-nezumi.mp3"));
+        assert!(!limiter.allow("se-nezumi.mp3"));
+    }
+
+    #[test]
+    fn disabled_limiter_allows_everything() {
+        let time = Arc::new(MockTimeSource::new());
+        let config = SeRateLimitConfig { enabled: false, max_triggers: 1, window_ms: 1000 };
+        let limiter = SeRateLimiter::new(config, time);
+        assert!((0..10).all(|_| limiter.allow("se-point.mp3")));
+    }
+}
@@ -0,0 +1,63 @@
+use crate::audio_system::audio_main::SoundSetting;
+use crate::audio_system::playback_status::PlaybackStatus;
+use crate::connect_system::location_context::LocationContext;
+use crate::messages::{BgmOverrideRequest, ConnectionState};
+use anyhow::Result;
+use futures::future::BoxFuture;
+use tokio::sync::{mpsc, watch};
+
+/// サーバーとのやり取り（接続・デバイス情報の送信・指示の受信）
+///
+/// オーディオ・APIなどのタスクはチャンネル越しにしか関わらないため、`server` 機能の無いビルドでは
+/// `Standalone` に差し替える。
+pub trait ServerLink: Send {
+    /// ログに出す接続先の名前
+    fn name(&self) -> &'static str;
+
+    /// 終了手順がストリームを閉じる段階に達するまでサーバーとやり取りする
+    fn run(self: Box<Self>) -> BoxFuture<'static, Result<()>>;
+}
+
+/// サーバーの無いスタンドアロン構成（キオスクなど）
+///
+/// サーバーからの指示は届かないため、指示を受け取る側が待ち続けないよう送信側をすぐに閉じる。
+pub struct Standalone {
+    pub sound_setting_tx: mpsc::Sender<SoundSetting>,
+    pub bgm_override_tx: mpsc::Sender<BgmOverrideRequest>,
+    pub language_tx: watch::Sender<Option<String>>,
+    pub location_tx: watch::Sender<LocationContext>,
+    pub connection_tx: watch::Sender<Option<ConnectionState>>,
+    pub status_rx: watch::Receiver<PlaybackStatus>,
+}
+
+impl ServerLink for Standalone {
+    fn name(&self) -> &'static str {
+        "standalone"
+    }
+
+    fn run(self: Box<Self>) -> BoxFuture<'static, Result<()>> {
+        drop(self);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn standalone_closes_the_server_channels() {
+        let (sound_setting_tx, mut sound_setting_rx) = mpsc::channel(1);
+        let (bgm_override_tx, mut bgm_override_rx) = mpsc::channel(1);
+        let (language_tx, mut language_rx) = watch::channel(None);
+        let (location_tx, _location_rx) = watch::channel(LocationContext::default());
+        let (connection_tx, _connection_rx) = watch::channel(None);
+        let (_status_tx, status_rx) = watch::channel(PlaybackStatus::default());
+        let link: Box<dyn ServerLink> = Box::new(Standalone { sound_setting_tx, bgm_override_tx, language_tx, location_tx, connection_tx, status_rx });
+
+        link.run().await.unwrap();
+        assert!(sound_setting_rx.recv().await.is_none());
+        assert!(bgm_override_rx.recv().await.is_none());
+        assert!(language_rx.changed().await.is_err());
+    }
+}
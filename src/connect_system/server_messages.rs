@@ -0,0 +1,116 @@
+use crate::proto::proto::stream_device_info_response::Event;
+use crate::proto::proto::{LocationUpdate, MoonlightUpdate, SoundSettingUpdate};
+use serde::Serialize;
+use tokio::sync::watch;
+
+/// サーバーから最後に受け取った設定系のメッセージ（状態APIで公開する）
+///
+/// protoと同じJSON（省略したフィールドは既定値）で出すため、各項目はそのまま
+/// フェイクサーバーの台本の `{"type": "event", "event": {"<項目名>": ...}}` に貼り付けて再生できる。
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LastServerMessages {
+    pub sound_setting_update: Option<SoundSettingUpdate>,
+    pub location_update: Option<LocationUpdate>,
+    pub moonlight_update: Option<MoonlightUpdate>,
+}
+
+impl LastServerMessages {
+    /// 設定系のメッセージなら記録し、記録したかどうかを返す
+    pub fn record(&mut self, event: &Event) -> bool {
+        match event {
+            Event::SoundSettingUpdate(update) => self.sound_setting_update = Some(update.clone()),
+            Event::LocationUpdate(update) => self.location_update = Some(update.clone()),
+            Event::MoonlightUpdate(update) => self.moonlight_update = Some(update.clone()),
+            _ => return false,
+        }
+        true
+    }
+}
+
+pub fn channel() -> (watch::Sender<LastServerMessages>, watch::Receiver<LastServerMessages>) {
+    watch::channel(LastServerMessages::default())
+}
+
+/// ジャーナルに残すJSON（そのまま台本の `event` に貼り付けられる形）
+pub fn event_json(event: &Event) -> String {
+    serde_json::to_string(event).unwrap_or_else(|e| format!("<failed to serialize: {}>", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connect_system::fake_server::ScriptStep;
+    use crate::proto::proto::{LocationInfo, MoonlightInfo, PointUpdate, SoundSetting};
+    use serde_json::json;
+
+    fn events() -> Vec<Event> {
+        vec![
+            Event::SoundSettingUpdate(SoundSettingUpdate {
+                settings: Some(SoundSetting { id: "hall".to_string(), max_volume_rssi: -40.0, min_volume_rssi: -90.0, max_volume: 0.8, min_volume: 0.1, is_muted: false }),
+            }),
+            Event::LocationUpdate(LocationUpdate {
+                locations: vec![LocationInfo {
+                    id: "1".to_string(),
+                    name: "火鼠の裘".to_string(),
+                    address: "00:11:22:33:44:66".to_string(),
+                    place_type: "fire_rat_robe".to_string(),
+                    sound_url: String::new(),
+                }],
+            }),
+            Event::MoonlightUpdate(MoonlightUpdate {
+                moonlights: vec![MoonlightInfo { device: "*".to_string(), enabled: false, sequence: 3, ..Default::default() }],
+                ..Default::default()
+            }),
+        ]
+    }
+
+    fn replayed(event: serde_json::Value) -> Event {
+        match serde_json::from_value(json!({ "type": "event", "event": event })).unwrap() {
+            ScriptStep::Event { event } => event,
+            other => panic!("unexpected step: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn records_only_settings_messages() {
+        let mut last = LastServerMessages::default();
+        for event in events() {
+            assert!(last.record(&event));
+        }
+        assert!(!last.record(&Event::PointUpdate(PointUpdate::default())));
+        assert!(last.sound_setting_update.is_some() && last.location_update.is_some() && last.moonlight_update.is_some());
+    }
+
+    #[test]
+    fn journal_json_replays_as_the_same_event() {
+        for event in events() {
+            let json: serde_json::Value = serde_json::from_str(&event_json(&event)).unwrap();
+            assert!(replayed(json) == event);
+        }
+    }
+
+    #[test]
+    fn status_api_entries_replay_as_the_same_event() {
+        let mut last = LastServerMessages::default();
+        for event in events() {
+            last.record(&event);
+        }
+        let status = serde_json::to_value(&last).unwrap();
+        for (key, event) in ["sound_setting_update", "location_update", "moonlight_update"].into_iter().zip(events()) {
+            assert!(replayed(json!({ key: status[key] })) == event, "{}", key);
+        }
+    }
+
+    #[test]
+    fn hand_edited_json_may_omit_fields() {
+        let event = replayed(json!({ "moonlight_update": { "moonlights": [{ "device": "*", "enabled": true }] } }));
+        match event {
+            Event::MoonlightUpdate(update) => {
+                assert_eq!(update.moonlights.len(), 1);
+                assert!(update.moonlights[0].enabled);
+                assert_eq!(update.moonlights[0].sequence, 0);
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use crate::connect_system::transport::{InboundStream, OutboundStream, Transport};
+use crate::proto::proto::{StreamDeviceInfoRequest, StreamDeviceInfoResponse, SyncTimeRequest, SyncTimeResponse};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
+use tracing::{debug, info, warn};
+
+/// セッションで送受信したメッセージ（どのストリームのどちら向きか）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "stream", content = "message", rename_all = "snake_case")]
+pub enum SessionMessage {
+    /// 状態の報告を含むと他より大きいため、ボックス化する
+    DeviceSent(Box<StreamDeviceInfoRequest>),
+    DeviceReceived(StreamDeviceInfoResponse),
+    TimeSent(SyncTimeRequest),
+    TimeReceived(SyncTimeResponse),
+}
+
+/// 記録ファイルの1行（JSON Lines）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionEntry {
+    /// 記録を始めてからの経過時間（ミリ秒。再生時はこの間隔で処理する）
+    pub elapsed_ms: u64,
+    /// 記録したユニットのUNIX時刻（ナノ秒。時刻同期の再生で受信時刻として使う）
+    pub unix_ns: i64,
+    #[serde(flatten)]
+    pub message: SessionMessage,
+}
+
+/// 送受信したメッセージをファイルに書き出す
+///
+/// 会場で起きたバックエンド側の問題を手元で再現できるよう、再接続をまたいで1つのファイルに追記する。
+pub struct SessionRecorder {
+    started: Instant,
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl SessionRecorder {
+    pub fn create(path: &Path) -> Result<Arc<Self>> {
+        let file = File::create(path).with_context(|| format!("Failed to create session recording {}", path.display()))?;
+        info!(path = %path.display(), "Recording server session");
+        Ok(Arc::new(Self { started: Instant::now(), writer: Mutex::new(BufWriter::new(file)) }))
+    }
+
+    pub fn record(&self, message: SessionMessage) {
+        let entry = SessionEntry {
+            elapsed_ms: self.started.elapsed().as_millis() as u64,
+            unix_ns: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as i64,
+            message,
+        };
+        let mut writer = self.writer.lock().unwrap();
+        // 異常終了しても直前までのメッセージが残るよう、1行ごとに書き出す
+        let result = serde_json::to_writer(&mut *writer, &entry)
+            .map_err(anyhow::Error::from)
+            .and_then(|()| writer.write_all(b"\n").map_err(anyhow::Error::from))
+            .and_then(|()| writer.flush().map_err(anyhow::Error::from));
+        if let Err(e) = result {
+            warn!("Failed to write session recording: {:?}", e);
+        }
+    }
+}
+
+/// 記録ファイルを読み込む（読めない行は読み飛ばす）
+pub fn load_session(path: &Path) -> Result<Arc<Vec<SessionEntry>>> {
+    let file = File::open(path).with_context(|| format!("Failed to open session recording {}", path.display()))?;
+    let mut entries = Vec::new();
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str::<SessionEntry>(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => warn!(line = index + 1, "Skipping unreadable session entry: {}", e),
+        }
+    }
+    info!(path = %path.display(), entries = entries.len(), "Loaded session recording");
+    Ok(Arc::new(entries))
+}
+
+/// 記録の経過時間どおりに、条件に合うメッセージを順に送るタスクを起動する
+///
+/// 受信側が閉じると終了する。
+pub fn spawn_replay<T, F>(entries: Arc<Vec<SessionEntry>>, select: F) -> mpsc::Receiver<(SessionEntry, T)>
+where
+    T: Send + 'static,
+    F: Fn(&SessionMessage) -> Option<T> + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(32);
+    tokio::spawn(async move {
+        let started = tokio::time::Instant::now();
+        for entry in entries.iter() {
+            let Some(item) = select(&entry.message) else {
+                continue;
+            };
+            tokio::time::sleep_until(started + Duration::from_millis(entry.elapsed_ms)).await;
+            if tx.send((entry.clone(), item)).await.is_err() {
+                return;
+            }
+        }
+        info!("Session replay finished");
+    });
+    rx
+}
+
+/// 送受信したメッセージを記録するトランスポート（他のトランスポートを包む）
+pub struct RecordingTransport {
+    inner: Box<dyn Transport>,
+    recorder: Arc<SessionRecorder>,
+}
+
+impl RecordingTransport {
+    pub fn new(inner: Box<dyn Transport>, recorder: Arc<SessionRecorder>) -> Self {
+        Self { inner, recorder }
+    }
+}
+
+#[tonic::async_trait]
+impl Transport for RecordingTransport {
+    async fn open(&mut self, outbound: OutboundStream) -> Result<InboundStream> {
+        let recorder = Arc::clone(&self.recorder);
+        let outbound = outbound.map(move |request| {
+            recorder.record(SessionMessage::DeviceSent(Box::new(request.clone())));
+            request
+        });
+        let recorder = Arc::clone(&self.recorder);
+        let inbound = self.inner.open(Box::pin(outbound)).await?.map(move |item| {
+            if let Ok(response) = &item {
+                recorder.record(SessionMessage::DeviceReceived(response.clone()));
+            }
+            item
+        });
+        Ok(Box::pin(inbound))
+    }
+
+    async fn get_points(&mut self, user_id: &str) -> Result<Option<i32>> {
+        self.inner.get_points(user_id).await
+    }
+}
+
+/// 記録したセッションの受信メッセージを再生するトランスポート（サーバーには接続しない）
+///
+/// 送信ストリームは読み捨てる。再生し終えた後もストリームは閉じず、最後の状態のまま動作を続ける。
+pub struct ReplayTransport {
+    entries: Arc<Vec<SessionEntry>>,
+}
+
+impl ReplayTransport {
+    pub fn new(entries: Arc<Vec<SessionEntry>>) -> Self {
+        Self { entries }
+    }
+}
+
+#[tonic::async_trait]
+impl Transport for ReplayTransport {
+    async fn open(&mut self, mut outbound: OutboundStream) -> Result<InboundStream> {
+        tokio::spawn(async move {
+            while let Some(request) = outbound.next().await {
+                debug!(?request, "Discarding outbound message during session replay");
+            }
+        });
+
+        info!(entries = self.entries.len(), "Replaying recorded DeviceService session");
+        let replay = spawn_replay(Arc::clone(&self.entries), |message| match message {
+            SessionMessage::DeviceReceived(response) => Some(response.clone()),
+            _ => None,
+        });
+        let inbound = ReceiverStream::new(replay).map(|(_, response)| Ok(response)).chain(futures::stream::pending());
+        Ok(Box::pin(inbound))
+    }
+}
@@ -0,0 +1,270 @@
+use crate::config_system::config_main::PointSeConfig;
+use std::path::Path;
+use tracing::warn;
+
+/// place_typeが未知の場合に使うベースロケーションタイプ
+const FALLBACK_BASE_TYPE: &str = "main";
+
+/// place_typeとポイント数から再生する音源を決めるルール
+///
+/// - ポイントが1未満（0・負数）の場合は最小レベル（1）として扱う
+/// - ポイントが用意されている音源の範囲を超える場合は最大レベルに丸める
+/// - 未知のplace_typeは警告を出して `main` の音源を使う
+#[derive(Debug, Clone, Copy)]
+pub struct SoundCatalog {
+    /// 用意されている音源の最小レベル（`tsukimi-*_1.mp3`）
+    pub min_level: i32,
+    /// 用意されている音源の最大レベル（`tsukimi-*_5.mp3`）
+    pub max_level: i32,
+}
+
+impl Default for SoundCatalog {
+    fn default() -> Self {
+        Self { min_level: 1, max_level: 5 }
+    }
+}
+
+impl SoundCatalog {
+    /// place_typeに対応するベースロケーションタイプ（未知の場合は `None`）
+    pub fn base_type(&self, place_type: &str) -> Option<&'static str> {
+        match place_type {
+            "projection_mapping" => Some("main"),
+            "buddhas_bowl" => Some("hotoke"),
+            "jeweled_branch" => Some("eda"),
+            "fire_rat_robe" => Some("nezumi"),
+            "dragons_jewel" => Some("ryu"),
+            "swallows_cowry" => Some("kai"),
+            _ => None,
+        }
+    }
+
+    /// place_typeに対応するベースロケーションタイプ（未知の場合は `main`）
+    pub fn base_type_or_fallback(&self, place_type: &str) -> &'static str {
+        self.base_type(place_type).unwrap_or_else(|| {
+            warn!(place_type, fallback = FALLBACK_BASE_TYPE, "Unknown place_type, using fallback sounds");
+            FALLBACK_BASE_TYPE
+        })
+    }
+
+    /// ポイント数を音源レベルに変換する（範囲外は丸める）
+    pub fn level_for_points(&self, points: i32) -> i32 {
+        points.clamp(self.min_level, self.max_level)
+    }
+
+    /// place_typeとポイント数に基づいてサウンドファイル名を生成する
+    pub fn sound_file(&self, place_type: &str, points: i32) -> String {
+        let base_type = self.base_type_or_fallback(place_type);
+        format!("tsukimi-{}_{}.mp3", base_type, self.level_for_points(points))
+    }
+
+    /// place_typeとポイント数に基づいて、実際に存在するサウンドファイル名を探す
+    ///
+    /// 該当レベルの音源が無い場合は低いレベルから順に探し、それも無ければ高いレベルを探す。
+    /// どのレベルの音源も無い場合は `None`（呼び出し側は現在の音源を維持する）。
+    pub fn existing_sound_file(&self, place_type: &str, points: i32) -> Option<String> {
+        self.existing_sound_file_in(Path::new(""), place_type, points)
+    }
+
+    /// `existing_sound_file` の音源を `dir` から探す（返すのはファイル名のみ）
+    fn existing_sound_file_in(&self, dir: &Path, place_type: &str, points: i32) -> Option<String> {
+        let base_type = self.base_type_or_fallback(place_type);
+        let level = self.level_for_points(points);
+        (self.min_level..=level)
+            .rev()
+            .chain(level + 1..=self.max_level)
+            .map(|level| format!("tsukimi-{}_{}.mp3", base_type, level))
+            .find(|file| dir.join(file).exists())
+    }
+
+    /// place_typeに基づいてSEファイル名を決定する
+    pub fn se_file(&self, place_type: &str) -> Option<&'static str> {
+        match place_type {
+            "fire_rat_robe" => Some("se-nezumi.mp3"), // 火鼠の裘: 鼠のSE
+            "buddhas_bowl" => Some("se-hotoke.mp3"),  // 仏の御石の鉢: 仏のSE
+            _ => None,
+        }
+    }
+
+    /// サーバーから指定された音源ファイル名が再生可能な形式かどうかを判定
+    ///
+    /// カレントディレクトリ外のファイルを参照できないよう、パス区切りを含む名前は拒否する。
+    pub fn is_valid_sound_file(&self, file: &str) -> bool {
+        !file.contains(['/', '\\']) && file.ends_with(".mp3")
+    }
+
+    /// インタラクション可能なplace_typeかどうかを判定
+    pub fn is_interactive(&self, place_type: &str) -> bool {
+        matches!(place_type, "fire_rat_robe" | "buddhas_bowl")
+    }
+}
+
+/// ポイントが `old_points` から `new_points` に変化したときに鳴らすSE（鳴らさない場合は `None`）
+///
+/// 増えた場合は今回の増加で達した閾値のうち最も大きいもののSEを鳴らす（一度に複数の閾値を超えても1つだけ）。
+/// どの閾値にも達していなければ通常のSEを鳴らす。
+/// 0以下になった場合はリセット、それ以外で減った場合は減少のSEを鳴らす（未設定なら無音）。
+pub fn point_se_file(config: &PointSeConfig, old_points: i32, new_points: i32) -> Option<&str> {
+    if new_points > old_points {
+        let file = config
+            .thresholds
+            .iter()
+            .filter(|threshold| old_points < threshold.points && threshold.points <= new_points)
+            .max_by_key(|threshold| threshold.points)
+            .map_or(config.default.as_str(), |threshold| threshold.file.as_str());
+        Some(file)
+    } else if new_points < old_points && new_points <= 0 {
+        config.reset.as_deref()
+    } else if new_points < old_points {
+        config.decrease.as_deref()
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config_system::config_main::PointSeThreshold;
+    use proptest::prelude::*;
+    use std::path::PathBuf;
+
+    const KNOWN_PLACE_TYPES: [&str; 6] = ["projection_mapping", "buddhas_bowl", "jeweled_branch", "fire_rat_robe", "dragons_jewel", "swallows_cowry"];
+
+    fn known_place_type() -> impl Strategy<Value = &'static str> {
+        prop::sample::select(KNOWN_PLACE_TYPES.to_vec())
+    }
+
+    fn unknown_place_type() -> impl Strategy<Value = String> {
+        any::<String>().prop_filter("known place_type", |place_type| !KNOWN_PLACE_TYPES.contains(&place_type.as_str()))
+    }
+
+    #[test]
+    fn known_place_types_map_to_their_assets() {
+        let catalog = SoundCatalog::default();
+        assert_eq!(catalog.sound_file("fire_rat_robe", 3), "tsukimi-nezumi_3.mp3");
+        assert_eq!(catalog.sound_file("projection_mapping", 1), "tsukimi-main_1.mp3");
+        assert_eq!(catalog.se_file("buddhas_bowl"), Some("se-hotoke.mp3"));
+        assert_eq!(catalog.se_file("jeweled_branch"), None);
+    }
+
+    fn point_se(decrease: Option<&str>, reset: Option<&str>) -> PointSeConfig {
+        PointSeConfig {
+            default: "se-point.mp3".to_string(),
+            thresholds: vec![
+                PointSeThreshold { points: 3, file: "se-fanfare.mp3".to_string() },
+                PointSeThreshold { points: 5, file: "se-complete.mp3".to_string() },
+            ],
+            decrease: decrease.map(String::from),
+            reset: reset.map(String::from),
+        }
+    }
+
+    #[test]
+    fn increase_plays_the_highest_threshold_reached() {
+        let config = point_se(None, None);
+        assert_eq!(point_se_file(&config, 0, 1), Some("se-point.mp3"));
+        assert_eq!(point_se_file(&config, 2, 3), Some("se-fanfare.mp3"));
+        assert_eq!(point_se_file(&config, 1, 5), Some("se-complete.mp3"));
+        // 既に越えた閾値は鳴らさない
+        assert_eq!(point_se_file(&config, 3, 4), Some("se-point.mp3"));
+        // 減った後に再び閾値に達したら鳴らす
+        assert_eq!(point_se_file(&config, 2, 3), Some("se-fanfare.mp3"));
+    }
+
+    #[test]
+    fn decrease_and_reset_are_silent_unless_configured() {
+        let config = point_se(None, None);
+        assert_eq!(point_se_file(&config, 4, 2), None);
+        assert_eq!(point_se_file(&config, 4, 0), None);
+        assert_eq!(point_se_file(&config, 2, 2), None);
+    }
+
+    #[test]
+    fn decrease_and_reset_use_their_own_se() {
+        let config = point_se(Some("se-decrease.mp3"), Some("se-reset.mp3"));
+        assert_eq!(point_se_file(&config, 4, 2), Some("se-decrease.mp3"));
+        assert_eq!(point_se_file(&config, 4, 0), Some("se-reset.mp3"));
+        assert_eq!(point_se_file(&config, 1, 0), Some("se-reset.mp3"));
+        // 0のままなら何も鳴らさない
+        assert_eq!(point_se_file(&config, 0, 0), None);
+    }
+
+    /// 指定したレベルの `buddhas_bowl` の音源だけがある一時ディレクトリ
+    fn assets(name: &str, levels: &[i32]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tsukimi-assets-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for level in levels {
+            std::fs::write(dir.join(format!("tsukimi-hotoke_{}.mp3", level)), b"").unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn decrease_picks_an_existing_lower_level() {
+        let catalog = SoundCatalog::default();
+        let dir = assets("lower", &[1, 2, 5]);
+        assert_eq!(catalog.existing_sound_file_in(&dir, "buddhas_bowl", 5).as_deref(), Some("tsukimi-hotoke_5.mp3"));
+        // レベル4・3が無い場合は、それより低いレベルを使う
+        assert_eq!(catalog.existing_sound_file_in(&dir, "buddhas_bowl", 4).as_deref(), Some("tsukimi-hotoke_2.mp3"));
+        // リセット・負のポイントは最低レベル
+        assert_eq!(catalog.existing_sound_file_in(&dir, "buddhas_bowl", 0).as_deref(), Some("tsukimi-hotoke_1.mp3"));
+        assert_eq!(catalog.existing_sound_file_in(&dir, "buddhas_bowl", -3).as_deref(), Some("tsukimi-hotoke_1.mp3"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_low_levels_fall_back_upwards_or_keep_the_current_sound() {
+        let catalog = SoundCatalog::default();
+        let dir = assets("upper", &[3]);
+        assert_eq!(catalog.existing_sound_file_in(&dir, "buddhas_bowl", 0).as_deref(), Some("tsukimi-hotoke_3.mp3"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let dir = assets("none", &[]);
+        assert_eq!(catalog.existing_sound_file_in(&dir, "buddhas_bowl", 2), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    proptest! {
+        #[test]
+        fn points_below_one_use_the_minimum_level(points in i32::MIN..1) {
+            let catalog = SoundCatalog::default();
+            prop_assert_eq!(catalog.level_for_points(points), catalog.min_level);
+            prop_assert_eq!(catalog.sound_file("buddhas_bowl", points), "tsukimi-hotoke_1.mp3");
+        }
+
+        #[test]
+        fn points_above_the_asset_range_use_the_maximum_level(points in 6..=i32::MAX) {
+            let catalog = SoundCatalog::default();
+            prop_assert_eq!(catalog.level_for_points(points), catalog.max_level);
+            prop_assert_eq!(catalog.sound_file("dragons_jewel", points), "tsukimi-ryu_5.mp3");
+        }
+
+        #[test]
+        fn points_within_the_asset_range_are_used_as_is(points in 1..=5) {
+            prop_assert_eq!(SoundCatalog::default().level_for_points(points), points);
+        }
+
+        #[test]
+        fn level_stays_within_a_custom_range(min_level in -10..10, span in 0..10, points in any::<i32>()) {
+            let catalog = SoundCatalog { min_level, max_level: min_level + span };
+            let level = catalog.level_for_points(points);
+            prop_assert!(catalog.min_level <= level && level <= catalog.max_level);
+        }
+
+        #[test]
+        fn unknown_place_types_fall_back_to_main(place_type in unknown_place_type(), points in any::<i32>()) {
+            let catalog = SoundCatalog::default();
+            prop_assert_eq!(catalog.base_type(&place_type), None);
+            prop_assert_eq!(catalog.base_type_or_fallback(&place_type), FALLBACK_BASE_TYPE);
+            prop_assert!(catalog.sound_file(&place_type, points).starts_with("tsukimi-main_"));
+            prop_assert_eq!(catalog.se_file(&place_type), None);
+            prop_assert!(!catalog.is_interactive(&place_type));
+        }
+
+        #[test]
+        fn known_place_types_never_fall_back(place_type in known_place_type()) {
+            prop_assert!(SoundCatalog::default().base_type(place_type).is_some());
+        }
+    }
+}
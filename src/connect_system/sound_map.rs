@@ -0,0 +1,56 @@
+use crate::bluetooth_system::address::Address;
+use arc_swap::ArcSwap;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// ロケーションの割り当て（アドレス -> 音源・place_type）の不変なスナップショット
+///
+/// 読み手は1つのスナップショットだけを見て判断するため、更新の途中の状態を見ることはない。
+#[derive(Debug, Clone, Default)]
+pub struct SoundMap {
+    /// 公開するたびに増える世代番号
+    pub generation: u64,
+    /// アドレス -> 音源
+    pub sounds: HashMap<Address, String>,
+    /// アドレス -> place_type（サーバーのLocationUpdateで届いたロケーションのみ）
+    pub place_types: HashMap<Address, String>,
+}
+
+impl SoundMap {
+    pub fn contains(&self, address: &Address) -> bool {
+        self.sounds.contains_key(address)
+    }
+
+    /// 音源を割り当てているアドレス
+    pub fn address_of(&self, sound: &str) -> Option<&Address> {
+        self.sounds.iter().find(|(_, sound_file)| *sound_file == sound).map(|(address, _)| address)
+    }
+}
+
+/// スナップショットを差し替えて公開する共有のsound_map
+#[derive(Debug)]
+pub struct SharedSoundMap(ArcSwap<SoundMap>);
+
+impl SharedSoundMap {
+    pub fn new(sounds: HashMap<Address, String>) -> Self {
+        Self(ArcSwap::from_pointee(SoundMap { sounds, ..Default::default() }))
+    }
+
+    /// 現在のスナップショット
+    pub fn load(&self) -> Arc<SoundMap> {
+        self.0.load_full()
+    }
+
+    /// 現在のスナップショットを複製して変更し、世代番号を進めて公開する
+    ///
+    /// 他の更新と競合した場合は `update` をやり直す（書き込むのは接続タスクだけなので通常は1回）。
+    pub fn update(&self, update: impl Fn(&mut SoundMap)) -> Arc<SoundMap> {
+        self.0.rcu(|current| {
+            let mut next = SoundMap::clone(current);
+            update(&mut next);
+            next.generation = current.generation + 1;
+            next
+        });
+        self.load()
+    }
+}
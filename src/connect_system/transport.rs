@@ -0,0 +1,70 @@
+use crate::proto::proto::device_service_client::DeviceServiceClient;
+use crate::proto::proto::{GetPointsRequest, StreamDeviceInfoRequest, StreamDeviceInfoResponse};
+use anyhow::Result;
+use std::pin::Pin;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::{Stream, StreamExt};
+use tonic::transport::Channel;
+use tonic::Code;
+
+/// サーバーへ送るデバイス情報のストリーム
+pub type OutboundStream = Pin<Box<dyn Stream<Item = StreamDeviceInfoRequest> + Send + 'static>>;
+
+/// サーバーから受け取るイベントのストリーム
+pub type InboundStream = Pin<Box<dyn Stream<Item = Result<StreamDeviceInfoResponse>> + Send + 'static>>;
+
+/// DeviceServiceのやり取りを抽象化したトランスポート
+///
+/// gRPC以外（MQTTなど）でも、送受信するメッセージはprotoの型をそのまま使うため、
+/// イベント処理（`run_device_service_client`）はトランスポートに依存しない。
+#[tonic::async_trait]
+pub trait Transport: Send {
+    /// 送信ストリームを渡してセッションを開始し、受信イベントのストリームを返す
+    async fn open(&mut self, outbound: OutboundStream) -> Result<InboundStream>;
+
+    /// ユーザーの現在のポイント数を取得する（対応していないトランスポート・サーバーでは `None`）
+    async fn get_points(&mut self, _user_id: &str) -> Result<Option<i32>> {
+        Ok(None)
+    }
+}
+
+/// tonicによるgRPCトランスポート
+pub struct GrpcTransport {
+    client: DeviceServiceClient<Channel>,
+}
+
+impl GrpcTransport {
+    pub fn new(client: DeviceServiceClient<Channel>) -> Self {
+        Self { client }
+    }
+}
+
+#[tonic::async_trait]
+impl Transport for GrpcTransport {
+    async fn open(&mut self, outbound: OutboundStream) -> Result<InboundStream> {
+        // Box<dyn Stream>のままではtonicのSend境界を証明できないため、チャンネル経由で渡す
+        let (tx, rx) = mpsc::channel(32);
+        tokio::spawn(async move {
+            let mut outbound = outbound;
+            while let Some(request) = outbound.next().await {
+                if tx.send(request).await.is_err() {
+                    break;
+                }
+            }
+        });
+        let response = self.client.stream_device_info(ReceiverStream::new(rx)).await?;
+        let inbound = response.into_inner().map(|item| item.map_err(anyhow::Error::from));
+        Ok(Box::pin(inbound))
+    }
+
+    async fn get_points(&mut self, user_id: &str) -> Result<Option<i32>> {
+        let request = GetPointsRequest { user_id: user_id.to_string() };
+        match self.client.get_points(request).await {
+            Ok(response) => Ok(Some(response.into_inner().points)),
+            // 古いサーバーはGetPointsを実装していないため、PointUpdateを待つ
+            Err(status) if status.code() == Code::Unimplemented => Ok(None),
+            Err(status) => Err(status.into()),
+        }
+    }
+}
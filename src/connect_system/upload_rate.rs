@@ -0,0 +1,234 @@
+use crate::clock_system::time_source::SharedTimeSource;
+use crate::config_system::config_main::UploadConfig;
+use crate::messages::DeviceSnapshot;
+use crate::metrics_system::metrics_main::metrics;
+use futures::Stream;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio_stream::StreamExt;
+use tracing::info;
+
+/// デバイス情報をまとめて送る間隔を決める
+///
+/// 通常は `batch_interval_ms` ごとに送る。適応モードでは受信の多い時間帯ほど間隔を広げ、同じビーコンの
+/// 更新を1件にまとめる分だけ送信量を減らす。サーバーから抑制を指示されている間は、指示された間隔以上あける。
+pub struct UploadRate {
+    config: UploadConfig,
+    /// サーバーからの抑制の期限に使う時刻
+    time: SharedTimeSource,
+    state: Mutex<RateState>,
+}
+
+struct RateState {
+    /// 受信の多さから決めた間隔
+    adaptive_interval: Duration,
+    /// サーバーから指示された最小の間隔と、その期限
+    throttle: Option<(Duration, Instant)>,
+    /// 最後に公開した間隔（変わったときだけログ・メトリクスを更新する）
+    reported: Duration,
+}
+
+impl UploadRate {
+    pub fn new(config: UploadConfig, time: SharedTimeSource) -> Arc<Self> {
+        let base = Duration::from_millis(config.batch_interval_ms);
+        metrics().set_upload_batch_interval(base);
+        Arc::new(Self { config, time, state: Mutex::new(RateState { adaptive_interval: base, throttle: None, reported: base }) })
+    }
+
+    fn base_interval(&self) -> Duration {
+        Duration::from_millis(self.config.batch_interval_ms)
+    }
+
+    /// 現在のバッチの間隔
+    pub fn batch_interval(&self) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        let now = self.time.now();
+        if state.throttle.is_some_and(|(_, until)| now >= until) {
+            state.throttle = None;
+            info!("Upload throttle expired");
+        }
+        let interval = match state.throttle {
+            Some((min_interval, _)) => state.adaptive_interval.max(min_interval),
+            None => state.adaptive_interval,
+        };
+        if interval != state.reported {
+            info!(interval_ms = interval.as_millis() as u64, "Upload batch interval changed");
+            state.reported = interval;
+            metrics().set_upload_batch_interval(interval);
+        }
+        interval
+    }
+
+    /// 1バッチにまとめる最大数（間隔を広げた分だけ増やし、件数で先に送られないようにする）
+    pub fn batch_size(&self, interval: Duration) -> usize {
+        let scale = interval.as_secs_f64() / self.base_interval().as_secs_f64().max(0.001);
+        (self.config.batch_size.max(1) as f64 * scale.max(1.0)).ceil() as usize
+    }
+
+    /// まとめたバッチの更新数と所要時間から、次の間隔を決める（適応モードのみ）
+    fn record_batch(&self, updates: usize, elapsed: Duration) {
+        let adaptive = &self.config.adaptive;
+        if !adaptive.enabled {
+            return;
+        }
+        // 件数の上限で早く締め切ったバッチを過大に見積もらないよう、通常の間隔より短くは数えない
+        let seconds = elapsed.max(self.base_interval()).as_secs_f64().max(0.001);
+        let updates_per_sec = updates as f64 / seconds;
+        let factor = (updates_per_sec / adaptive.busy_updates_per_sec.max(1.0)).max(1.0);
+        let max_interval = Duration::from_millis(adaptive.max_batch_interval_ms).max(self.base_interval());
+        let interval = self.base_interval().mul_f64(factor).min(max_interval);
+        self.state.lock().unwrap().adaptive_interval = interval;
+    }
+
+    /// サーバーからの抑制を適用する（`min_interval` が0なら解除。`duration` が0なら設定の既定値）
+    pub fn throttle(&self, min_interval: Duration, duration: Duration) {
+        let mut state = self.state.lock().unwrap();
+        if min_interval.is_zero() {
+            if state.throttle.take().is_some() {
+                info!("Upload throttle cleared by server");
+            }
+            return;
+        }
+        let duration = if duration.is_zero() { Duration::from_millis(self.config.adaptive.throttle_duration_ms) } else { duration };
+        info!(
+            min_interval_ms = min_interval.as_millis() as u64,
+            duration_ms = duration.as_millis() as u64,
+            "Upload throttled by server"
+        );
+        state.throttle = Some((min_interval, self.time.now() + duration));
+    }
+}
+
+/// スナップショットを `UploadRate` が決める間隔・件数でまとめる
+///
+/// 最初のスナップショットが届いてから間隔が経過するか、件数が上限に達したらバッチを締め切る。
+/// 入力が終わった場合は、まとめ途中のものを最後のバッチとして返してから終わる。
+pub fn batch_snapshots<S>(snapshots: S, rate: Arc<UploadRate>) -> impl Stream<Item = Vec<Arc<DeviceSnapshot>>>
+where
+    S: Stream<Item = Arc<DeviceSnapshot>> + Send + 'static,
+{
+    futures::stream::unfold(Some(Box::pin(snapshots)), move |snapshots| {
+        let rate = Arc::clone(&rate);
+        async move {
+            let mut snapshots = snapshots?;
+            let first = snapshots.next().await?;
+            let started = tokio::time::Instant::now();
+            let interval = rate.batch_interval();
+            let batch_size = rate.batch_size(interval);
+            let deadline = started + interval;
+
+            let mut batch = vec![first];
+            let mut ended = false;
+            while batch.len() < batch_size {
+                match tokio::time::timeout_at(deadline, snapshots.next()).await {
+                    Ok(Some(snapshot)) => batch.push(snapshot),
+                    Ok(None) => {
+                        ended = true;
+                        break;
+                    }
+                    Err(_) => break,
+                }
+            }
+
+            let updates = batch.iter().map(|snapshot| snapshot.devices.len()).sum();
+            rate.record_batch(updates, started.elapsed());
+            Some((batch, (!ended).then_some(snapshots)))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock_system::time_source::MockTimeSource;
+    use crate::config_system::config_main::AdaptiveUploadConfig;
+
+    const BASE: Duration = Duration::from_millis(500);
+
+    fn rate(adaptive: bool) -> (Arc<UploadRate>, Arc<MockTimeSource>) {
+        let time = Arc::new(MockTimeSource::new());
+        let config = UploadConfig {
+            batch_size: 10,
+            batch_interval_ms: 500,
+            adaptive: AdaptiveUploadConfig { enabled: adaptive, busy_updates_per_sec: 20.0, max_batch_interval_ms: 5000, throttle_duration_ms: 60_000 },
+            ..Default::default()
+        };
+        (UploadRate::new(config, time.clone()), time)
+    }
+
+    #[test]
+    fn busy_batches_widen_the_interval_up_to_the_cap() {
+        let (rate, _) = rate(true);
+        assert_eq!(rate.batch_interval(), BASE);
+        // 1秒あたり20件までは通常の間隔
+        rate.record_batch(10, BASE);
+        assert_eq!(rate.batch_interval(), BASE);
+        // 1秒あたり80件なら4倍
+        rate.record_batch(40, BASE);
+        assert_eq!(rate.batch_interval(), BASE * 4);
+        rate.record_batch(100_000, BASE);
+        assert_eq!(rate.batch_interval(), Duration::from_millis(5000));
+        // 落ち着いたら戻る
+        rate.record_batch(1, BASE);
+        assert_eq!(rate.batch_interval(), BASE);
+    }
+
+    #[test]
+    fn batches_closed_early_are_counted_over_the_base_interval() {
+        let (rate, _) = rate(true);
+        // 件数の上限で10ミリ秒で締め切っても、500ミリ秒に40件として数える
+        rate.record_batch(40, Duration::from_millis(10));
+        assert_eq!(rate.batch_interval(), BASE * 4);
+        // 長くかかったバッチは実際の時間で数える
+        rate.record_batch(40, Duration::from_secs(2));
+        assert_eq!(rate.batch_interval(), BASE);
+    }
+
+    #[test]
+    fn fixed_rate_ignores_batches() {
+        let (rate, _) = rate(false);
+        rate.record_batch(100_000, BASE);
+        assert_eq!(rate.batch_interval(), BASE);
+    }
+
+    #[test]
+    fn batch_size_grows_with_the_interval() {
+        let (rate, _) = rate(true);
+        assert_eq!(rate.batch_size(BASE), 10);
+        assert_eq!(rate.batch_size(BASE / 2), 10);
+        assert_eq!(rate.batch_size(BASE * 4), 40);
+        assert_eq!(rate.batch_size(Duration::from_millis(750)), 15);
+    }
+
+    #[test]
+    fn server_throttle_expires_with_time() {
+        let (rate, time) = rate(true);
+        rate.throttle(Duration::from_secs(3), Duration::from_secs(10));
+        assert_eq!(rate.batch_interval(), Duration::from_secs(3));
+        time.advance(Duration::from_millis(9_999));
+        assert_eq!(rate.batch_interval(), Duration::from_secs(3));
+        time.advance(Duration::from_millis(1));
+        assert_eq!(rate.batch_interval(), BASE);
+
+        // 抑制より広い適応の間隔はそのまま
+        rate.throttle(Duration::from_secs(1), Duration::from_secs(10));
+        rate.record_batch(100_000, BASE);
+        assert_eq!(rate.batch_interval(), Duration::from_millis(5000));
+    }
+
+    #[test]
+    fn server_throttle_defaults_and_clearing() {
+        let (rate, time) = rate(false);
+        // 期間が0なら設定の既定値（60秒）
+        rate.throttle(Duration::from_secs(2), Duration::ZERO);
+        time.advance(Duration::from_secs(59));
+        assert_eq!(rate.batch_interval(), Duration::from_secs(2));
+        time.advance(Duration::from_secs(1));
+        assert_eq!(rate.batch_interval(), BASE);
+
+        // 最小の間隔が0なら解除
+        rate.throttle(Duration::from_secs(2), Duration::from_secs(10));
+        rate.throttle(Duration::ZERO, Duration::ZERO);
+        assert_eq!(rate.batch_interval(), BASE);
+    }
+}
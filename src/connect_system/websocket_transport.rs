@@ -0,0 +1,131 @@
+use crate::connect_system::transport::{InboundStream, OutboundStream, Transport};
+use crate::proto::proto::StreamDeviceInfoResponse;
+use anyhow::{anyhow, Result};
+use futures::{SinkExt, StreamExt};
+use prost::Message as _;
+use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+use tracing::{debug, error, info};
+
+/// WebSocketによるフォールバックトランスポート
+///
+/// HTTP/2（50051番ポート）が遮断されている会場向け。gRPCと同じprotoメッセージを
+/// そのままバイナリフレームで送受信する（1フレーム = 1メッセージ）。
+pub struct WebSocketTransport {
+    url: String,
+}
+
+impl WebSocketTransport {
+    pub fn new(url: String) -> Self {
+        Self { url }
+    }
+}
+
+#[tonic::async_trait]
+impl Transport for WebSocketTransport {
+    async fn open(&mut self, mut outbound: OutboundStream) -> Result<InboundStream> {
+        let (socket, _) = tokio_tungstenite::connect_async(self.url.as_str()).await?;
+        info!(url = %self.url, "Connected to WebSocket fallback endpoint");
+        let (mut sink, source) = socket.split();
+
+        // 送信ストリームをバイナリフレームとして送るタスク
+        tokio::spawn(async move {
+            while let Some(request) = outbound.next().await {
+                if let Err(e) = sink.send(Message::Binary(request.encode_to_vec())).await {
+                    error!("Failed to send device info over WebSocket: {}", e);
+                    break;
+                }
+            }
+            let _ = sink.close().await;
+            debug!("WebSocket sender finished");
+        });
+
+        let inbound = source.filter_map(|frame| async move { decode_frame(frame) });
+        Ok(Box::pin(inbound))
+    }
+}
+
+/// 受信したフレームをイベントに変換する（イベントでないフレームは `None`）
+fn decode_frame(frame: Result<Message, WsError>) -> Option<Result<StreamDeviceInfoResponse>> {
+    match frame {
+        Ok(Message::Binary(payload)) => Some(
+            StreamDeviceInfoResponse::decode(payload.as_slice()).map_err(|e| anyhow!("Failed to decode WebSocket frame: {}", e)),
+        ),
+        Ok(Message::Close(_)) => None,
+        // Ping/Pongはtungsteniteが処理する。テキストフレームは想定しない
+        Ok(_) => None,
+        Err(e) => Some(Err(anyhow!("WebSocket error: {}", e))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::proto::stream_device_info_response::Event;
+    use crate::proto::proto::{PointUpdate, StreamDeviceInfoRequest};
+    use tokio::net::TcpListener;
+
+    fn point_response() -> StreamDeviceInfoResponse {
+        let point = PointUpdate { user_id: "user-1".to_string(), points: 7 };
+        StreamDeviceInfoResponse { schema_version: 1, event: Some(Event::PointUpdate(point)) }
+    }
+
+    #[test]
+    fn binary_frame_is_decoded() {
+        let frame = Ok(Message::Binary(point_response().encode_to_vec()));
+        assert_eq!(decode_frame(frame).unwrap().unwrap(), point_response());
+    }
+
+    #[test]
+    fn malformed_binary_frame_is_an_error() {
+        let frame = Ok(Message::Binary(vec![0x12, 0x05, 0x01]));
+        assert!(decode_frame(frame).unwrap().is_err());
+    }
+
+    #[test]
+    fn non_event_frames_are_skipped() {
+        assert!(decode_frame(Ok(Message::Text("hello".to_string()))).is_none());
+        assert!(decode_frame(Ok(Message::Ping(vec![1]))).is_none());
+        assert!(decode_frame(Ok(Message::Close(None))).is_none());
+    }
+
+    #[test]
+    fn connection_error_is_passed_on() {
+        assert!(decode_frame(Err(WsError::ConnectionClosed)).unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn exchanges_binary_frames_with_the_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut socket = tokio_tungstenite::accept_async(stream).await.unwrap();
+            let request = match socket.next().await.unwrap().unwrap() {
+                Message::Binary(payload) => StreamDeviceInfoRequest::decode(payload.as_slice()).unwrap(),
+                other => panic!("unexpected frame: {:?}", other),
+            };
+            socket.send(Message::Binary(point_response().encode_to_vec())).await.unwrap();
+            socket.send(Message::Binary(vec![0x12, 0x05, 0x01])).await.unwrap();
+            socket.close(None).await.unwrap();
+            request
+        });
+
+        let request = StreamDeviceInfoRequest { user_id: "user-1".to_string(), schema_version: 1, ..Default::default() };
+        let outbound = Box::pin(futures::stream::iter([request.clone()]).chain(futures::stream::pending()));
+        let mut inbound = WebSocketTransport::new(url).open(outbound).await.unwrap();
+        assert_eq!(inbound.next().await.unwrap().unwrap(), point_response());
+        assert!(inbound.next().await.unwrap().is_err());
+        // サーバーが閉じたらストリームも終わる
+        assert!(inbound.next().await.is_none());
+        assert_eq!(server.await.unwrap(), request);
+    }
+
+    #[tokio::test]
+    async fn open_fails_when_the_server_is_unreachable() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        drop(listener);
+        let outbound = Box::pin(futures::stream::pending());
+        assert!(WebSocketTransport::new(url).open(outbound).await.is_err());
+    }
+}
@@ -0,0 +1,3 @@
+pub mod cue_list;
+pub mod dmx_main;
+pub mod packet;
\ No newline at end of file
@@ -0,0 +1,139 @@
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// DMXの1ユニバースのチャンネル数
+pub const DMX_CHANNELS: usize = 512;
+
+/// 再生位置に紐づく照明キュー1つ
+#[derive(Debug, Clone, Deserialize)]
+pub struct Cue {
+    /// 曲頭からの位置（ミリ秒）
+    pub at_ms: u64,
+    #[serde(default)]
+    pub universe: u16,
+    /// 書き込みを始めるチャンネル（1始まり）
+    pub channel: u16,
+    /// `channel` から順に設定する値
+    pub values: Vec<u8>,
+}
+
+impl Cue {
+    /// ユニバースのフレームにキューの値を書き込む（範囲外のチャンネルは無視する）
+    pub fn apply(&self, frame: &mut [u8; DMX_CHANNELS]) {
+        let start = (self.channel.max(1) - 1) as usize;
+        for (slot, value) in frame.iter_mut().skip(start).zip(&self.values) {
+            *slot = *value;
+        }
+    }
+}
+
+/// 音源ファイルごとのキューリスト
+///
+/// 音源と同じ場所に置いたサイドカーファイル（`bgm.mp3` なら `bgm.cues.json`）から読み込む。
+#[derive(Debug, Clone, Default)]
+pub struct CueList {
+    /// `at_ms` の昇順
+    cues: Vec<Cue>,
+}
+
+impl CueList {
+    /// 音源ファイルに対応するサイドカーファイルのパス
+    pub fn sidecar_path(sound: &str) -> PathBuf {
+        Path::new(sound).with_extension("cues.json")
+    }
+
+    /// 音源ファイルのキューリストを読み込む（サイドカーファイルが無い・壊れている場合は空）
+    pub fn load_for(sound: &str) -> Self {
+        let path = Self::sidecar_path(sound);
+        let Ok(text) = std::fs::read_to_string(&path) else {
+            return Self::default();
+        };
+        match serde_json::from_str::<Vec<Cue>>(&text) {
+            Ok(mut cues) => {
+                cues.sort_by_key(|cue| cue.at_ms);
+                info!(path = %path.display(), count = cues.len(), "Loaded DMX cue list");
+                Self { cues }
+            }
+            Err(e) => {
+                warn!(path = %path.display(), "Failed to parse DMX cue list: {}", e);
+                Self::default()
+            }
+        }
+    }
+
+    /// 再生位置が `after_ms` から `until_ms` まで進んだときに通過したキュー
+    ///
+    /// `after_ms` が `None` の場合は曲頭（0ミリ秒のキューを含む）から数える。
+    pub fn crossed(&self, after_ms: Option<u64>, until_ms: u64) -> impl Iterator<Item = &Cue> {
+        self.cues
+            .iter()
+            .skip_while(move |cue| after_ms.is_some_and(|after| cue.at_ms <= after))
+            .take_while(move |cue| cue.at_ms <= until_ms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cue(at_ms: u64, channel: u16, values: &[u8]) -> Cue {
+        Cue { at_ms, universe: 0, channel, values: values.to_vec() }
+    }
+
+    fn cue_list() -> CueList {
+        CueList { cues: vec![cue(0, 1, &[1]), cue(1000, 1, &[2]), cue(2000, 1, &[3])] }
+    }
+
+    fn crossed(list: &CueList, after_ms: Option<u64>, until_ms: u64) -> Vec<u64> {
+        list.crossed(after_ms, until_ms).map(|cue| cue.at_ms).collect()
+    }
+
+    #[test]
+    fn crossed_cues_at_boundaries() {
+        let list = cue_list();
+        // 曲頭からは0ミリ秒のキューも含む
+        assert_eq!(crossed(&list, None, 0), vec![0]);
+        assert_eq!(crossed(&list, None, 999), vec![0]);
+        // `after_ms` ちょうどのキューは前回通過済み、`until_ms` ちょうどのキューは今回通過する
+        assert_eq!(crossed(&list, Some(0), 1000), vec![1000]);
+        assert_eq!(crossed(&list, Some(1000), 1000), Vec::<u64>::new());
+        assert_eq!(crossed(&list, Some(999), 2000), vec![1000, 2000]);
+    }
+
+    #[test]
+    fn nothing_is_crossed_after_the_last_cue() {
+        let list = cue_list();
+        assert_eq!(crossed(&list, Some(2000), 60_000), Vec::<u64>::new());
+        assert_eq!(crossed(&list, Some(1500), u64::MAX), vec![2000]);
+        assert_eq!(crossed(&CueList::default(), None, u64::MAX), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn cue_values_stay_inside_the_frame() {
+        let mut frame = [0u8; DMX_CHANNELS];
+        cue(0, 1, &[10, 11]).apply(&mut frame);
+        assert_eq!(frame[..3], [10, 11, 0]);
+        // チャンネル0は1として扱う
+        cue(0, 0, &[20]).apply(&mut frame);
+        assert_eq!(frame[0], 20);
+        cue(0, DMX_CHANNELS as u16, &[30, 31, 32]).apply(&mut frame);
+        assert_eq!(frame[DMX_CHANNELS - 1], 30);
+        cue(0, DMX_CHANNELS as u16 + 1, &[40]).apply(&mut frame);
+        assert_eq!(frame[DMX_CHANNELS - 1], 30);
+    }
+
+    #[test]
+    fn sidecar_cues_are_sorted() {
+        let sound = std::env::temp_dir().join(format!("tsukimi-cues-{}.mp3", std::process::id()));
+        let sidecar = CueList::sidecar_path(sound.to_str().unwrap());
+        assert_eq!(sidecar.file_name().unwrap().to_str().unwrap(), format!("tsukimi-cues-{}.cues.json", std::process::id()));
+        std::fs::write(&sidecar, r#"[{"at_ms": 2000, "channel": 1, "values": [3]}, {"at_ms": 500, "channel": 2, "values": [1]}]"#).unwrap();
+        let list = CueList::load_for(sound.to_str().unwrap());
+        assert_eq!(crossed(&list, None, u64::MAX), vec![500, 2000]);
+
+        std::fs::write(&sidecar, "not json").unwrap();
+        assert_eq!(crossed(&CueList::load_for(sound.to_str().unwrap()), None, u64::MAX), Vec::<u64>::new());
+        let _ = std::fs::remove_file(&sidecar);
+    }
+}
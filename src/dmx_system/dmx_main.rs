@@ -0,0 +1,185 @@
+use crate::audio_system::playback_status::{PlaybackStatus, PositionAnchor};
+use crate::config_system::config_main::{DmxConfig, DmxProtocol};
+use crate::dmx_system::cue_list::{CueList, DMX_CHANNELS};
+use crate::dmx_system::packet::{artnet_dmx, sacn_dmx, SacnSource, ARTNET_PORT, SACN_PORT};
+use anyhow::{Context, Result};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::watch;
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, info, instrument, warn};
+
+/// 再生位置がこれ以上戻った場合はループ・シークとみなしてキューを追いかけ直す（ミリ秒）
+///
+/// これより小さい後退は位置の補間誤差なので、追いつかれるまで待つ。
+const BACKWARD_TOLERANCE_MS: u64 = 1000;
+
+/// 送信元名からsACNのCIDを作る（再起動しても同じ値になるようにする）
+fn sacn_cid(name: &str) -> [u8; 16] {
+    let mut cid = [0u8; 16];
+    for (i, chunk) in cid.chunks_mut(8).enumerate() {
+        let mut hasher = DefaultHasher::new();
+        (name, i).hash(&mut hasher);
+        chunk.copy_from_slice(&hasher.finish().to_be_bytes());
+    }
+    cid
+}
+
+/// ユニバースごとの現在の出力値と送信先
+struct DmxOutput {
+    socket: UdpSocket,
+    protocol: DmxProtocol,
+    target: Option<Ipv4Addr>,
+    source: SacnSource,
+    frames: BTreeMap<u16, [u8; DMX_CHANNELS]>,
+    sequences: BTreeMap<u16, u8>,
+}
+
+impl DmxOutput {
+    fn destination(&self, universe: u16) -> SocketAddr {
+        match (self.protocol, self.target) {
+            (DmxProtocol::ArtNet, Some(host)) => SocketAddrV4::new(host, ARTNET_PORT).into(),
+            (DmxProtocol::ArtNet, None) => SocketAddrV4::new(Ipv4Addr::BROADCAST, ARTNET_PORT).into(),
+            (DmxProtocol::Sacn, Some(host)) => SocketAddrV4::new(host, SACN_PORT).into(),
+            (DmxProtocol::Sacn, None) => {
+                let [hi, lo] = universe.to_be_bytes();
+                SocketAddrV4::new(Ipv4Addr::new(239, 255, hi, lo), SACN_PORT).into()
+            }
+        }
+    }
+
+    async fn send(&mut self, universe: u16) {
+        let Some(frame) = self.frames.get(&universe) else {
+            return;
+        };
+        let sequence = self.sequences.entry(universe).or_insert(0);
+        *sequence = sequence.wrapping_add(1);
+        let packet = match self.protocol {
+            // Art-Netのシーケンス番号0は「順序を使わない」ことを表すため飛ばす
+            DmxProtocol::ArtNet => artnet_dmx(universe, (*sequence).max(1), frame),
+            DmxProtocol::Sacn => sacn_dmx(&self.source, universe, *sequence, frame),
+        };
+        let destination = self.destination(universe);
+        if let Err(e) = self.socket.send_to(&packet, destination).await {
+            warn!(universe, %destination, "Failed to send DMX packet: {}", e);
+        }
+    }
+
+    async fn send_all(&mut self) {
+        let universes: Vec<u16> = self.frames.keys().copied().collect();
+        for universe in universes {
+            self.send(universe).await;
+        }
+    }
+}
+
+/// キューリストの進行状況
+struct CuePosition {
+    sound: String,
+    position_ms: u64,
+    cues: CueList,
+}
+
+/// 曲頭から `position_ms` までのキューを適用し直す（再生開始・ループ・シーク時）
+async fn chase(output: &mut DmxOutput, cues: &CueList, position_ms: u64) {
+    for frame in output.frames.values_mut() {
+        frame.fill(0);
+    }
+    for cue in cues.crossed(None, position_ms) {
+        cue.apply(output.frames.entry(cue.universe).or_insert([0; DMX_CHANNELS]));
+    }
+    output.send_all().await;
+}
+
+/// 再生位置が音源ごとのキューを通過したときにArt-Net・sACNでDMXを送るタスク
+///
+/// 再生停止中は最後の出力を維持し、定期的に送り直す。
+#[instrument(skip_all)]
+pub async fn dmx_main(config: DmxConfig, mut status_rx: watch::Receiver<PlaybackStatus>) -> Result<()> {
+    let target = match config.target.as_deref() {
+        Some(host) => {
+            let address = tokio::net::lookup_host((host, 0))
+                .await
+                .with_context(|| format!("Failed to resolve DMX target: {}", host))?
+                .find_map(|address| match address {
+                    SocketAddr::V4(v4) => Some(*v4.ip()),
+                    SocketAddr::V6(_) => None,
+                })
+                .with_context(|| format!("No IPv4 address found for DMX target: {}", host))?;
+            Some(address)
+        }
+        None => None,
+    };
+    let socket = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0)).await?;
+    socket.set_broadcast(true)?;
+    info!(protocol = ?config.protocol, ?target, "DMX output started");
+
+    let mut output = DmxOutput {
+        socket,
+        protocol: config.protocol,
+        target,
+        source: SacnSource { cid: sacn_cid(&config.source_name), name: config.source_name.clone() },
+        frames: BTreeMap::new(),
+        sequences: BTreeMap::new(),
+    };
+
+    let mut anchor = PositionAnchor::from_status(&status_rx.borrow_and_update());
+    let mut position: Option<CuePosition> = None;
+    let mut tick = tokio::time::interval(Duration::from_millis(config.tick_ms.max(5)));
+    tick.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut refresh = tokio::time::interval(Duration::from_millis(config.refresh_ms.max(100)));
+    refresh.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    loop {
+        tokio::select! {
+            changed = status_rx.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                anchor = PositionAnchor::from_status(&status_rx.borrow_and_update());
+            }
+            _ = tick.tick() => {
+                let Some(anchor) = anchor.as_ref() else {
+                    position = None;
+                    continue;
+                };
+                let now_ms = anchor.position_ns() / 1_000_000;
+                match position.as_mut() {
+                    Some(current) if current.sound == anchor.sound => {
+                        if now_ms >= current.position_ms {
+                            let mut touched = Vec::new();
+                            for cue in current.cues.crossed(Some(current.position_ms), now_ms) {
+                                debug!(at_ms = cue.at_ms, universe = cue.universe, "DMX cue crossed");
+                                cue.apply(output.frames.entry(cue.universe).or_insert([0; DMX_CHANNELS]));
+                                touched.push(cue.universe);
+                            }
+                            touched.sort_unstable();
+                            touched.dedup();
+                            for universe in touched {
+                                output.send(universe).await;
+                            }
+                            current.position_ms = now_ms;
+                        } else if current.position_ms - now_ms > BACKWARD_TOLERANCE_MS {
+                            debug!(from_ms = current.position_ms, to_ms = now_ms, "Playback looped or seeked - chasing DMX cues");
+                            chase(&mut output, &current.cues, now_ms).await;
+                            current.position_ms = now_ms;
+                        }
+                    }
+                    _ => {
+                        // 再生開始・曲の切り替え時はキューリストを読み直す（会場での編集をすぐ反映するため）
+                        let cues = CueList::load_for(&anchor.sound);
+                        chase(&mut output, &cues, now_ms).await;
+                        position = Some(CuePosition { sound: anchor.sound.clone(), position_ms: now_ms, cues });
+                    }
+                }
+            }
+            _ = refresh.tick() => output.send_all().await,
+        }
+    }
+    info!("DMX output stopped");
+    Ok(())
+}
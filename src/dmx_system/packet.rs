@@ -0,0 +1,160 @@
+use crate::dmx_system::cue_list::DMX_CHANNELS;
+
+/// Art-NetのUDPポート
+pub const ARTNET_PORT: u16 = 6454;
+
+/// sACN（E1.31）のUDPポート
+pub const SACN_PORT: u16 = 5568;
+
+/// Art-NetのArtDmxパケット（512チャンネル）をエンコードする
+///
+/// `universe` はNet（上位7ビット）とSub-Net・Universe（下位8ビット）を合わせた15ビットの番号。
+pub fn artnet_dmx(universe: u16, sequence: u8, data: &[u8; DMX_CHANNELS]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(18 + DMX_CHANNELS);
+    packet.extend_from_slice(b"Art-Net\0");
+    // OpDmx（リトルエンディアン）
+    packet.extend_from_slice(&0x5000u16.to_le_bytes());
+    // プロトコルバージョン14
+    packet.extend_from_slice(&14u16.to_be_bytes());
+    packet.push(sequence);
+    // Physical
+    packet.push(0);
+    packet.push((universe & 0xFF) as u8);
+    packet.push(((universe >> 8) & 0x7F) as u8);
+    packet.extend_from_slice(&(DMX_CHANNELS as u16).to_be_bytes());
+    packet.extend_from_slice(data);
+    packet
+}
+
+/// sACNで使う送信元の識別子
+pub struct SacnSource {
+    /// 送信元を一意に表すCID（UUID）
+    pub cid: [u8; 16],
+    /// 受信側に表示される送信元名（63バイトまで）
+    pub name: String,
+}
+
+/// sACN（E1.31）のデータパケット（512チャンネル）をエンコードする
+pub fn sacn_dmx(source: &SacnSource, universe: u16, sequence: u8, data: &[u8; DMX_CHANNELS]) -> Vec<u8> {
+    const PACKET_LEN: u16 = 126 + DMX_CHANNELS as u16;
+    let flags_and_length = |offset: u16| (0x7000 | (PACKET_LEN - offset)).to_be_bytes();
+
+    let mut packet = Vec::with_capacity(PACKET_LEN as usize);
+    // ルートレイヤー
+    packet.extend_from_slice(&0x0010u16.to_be_bytes());
+    packet.extend_from_slice(&0x0000u16.to_be_bytes());
+    packet.extend_from_slice(b"ASC-E1.17\0\0\0");
+    packet.extend_from_slice(&flags_and_length(16));
+    packet.extend_from_slice(&0x0000_0004u32.to_be_bytes());
+    packet.extend_from_slice(&source.cid);
+
+    // フレーミングレイヤー
+    packet.extend_from_slice(&flags_and_length(38));
+    packet.extend_from_slice(&0x0000_0002u32.to_be_bytes());
+    let mut name = [0u8; 64];
+    let name_bytes = source.name.as_bytes();
+    let name_len = name_bytes.len().min(63);
+    name[..name_len].copy_from_slice(&name_bytes[..name_len]);
+    packet.extend_from_slice(&name);
+    // 優先度（既定値100）
+    packet.push(100);
+    // 同期アドレス（使わない）
+    packet.extend_from_slice(&0u16.to_be_bytes());
+    packet.push(sequence);
+    // オプション
+    packet.push(0);
+    packet.extend_from_slice(&universe.to_be_bytes());
+
+    // DMPレイヤー
+    packet.extend_from_slice(&flags_and_length(115));
+    packet.push(0x02);
+    packet.push(0xA1);
+    packet.extend_from_slice(&0x0000u16.to_be_bytes());
+    packet.extend_from_slice(&0x0001u16.to_be_bytes());
+    // スタートコード + チャンネル数
+    packet.extend_from_slice(&(DMX_CHANNELS as u16 + 1).to_be_bytes());
+    packet.push(0);
+    packet.extend_from_slice(data);
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame() -> [u8; DMX_CHANNELS] {
+        let mut data = [0u8; DMX_CHANNELS];
+        data[0] = 0x11;
+        data[DMX_CHANNELS - 1] = 0xFF;
+        data
+    }
+
+    #[test]
+    fn artnet_header_layout() {
+        let packet = artnet_dmx(0x1234, 7, &frame());
+        assert_eq!(packet.len(), 18 + DMX_CHANNELS);
+        assert_eq!(
+            packet[..18],
+            [
+                b'A', b'r', b't', b'-', b'N', b'e', b't', 0, // ID
+                0x00, 0x50, // OpDmx（リトルエンディアン）
+                0x00, 0x0E, // プロトコルバージョン
+                7,    // Sequence
+                0,    // Physical
+                0x34, // SubUni
+                0x12, // Net
+                0x02, 0x00, // Length（ビッグエンディアン）
+            ]
+        );
+        assert_eq!(packet[18], 0x11);
+        assert_eq!(packet[18 + DMX_CHANNELS - 1], 0xFF);
+    }
+
+    #[test]
+    fn artnet_universe_is_limited_to_15_bits() {
+        let packet = artnet_dmx(0xFFFF, 0, &frame());
+        assert_eq!(packet[14..16], [0xFF, 0x7F]);
+    }
+
+    #[test]
+    fn sacn_layer_lengths_and_vectors() {
+        let source = SacnSource { cid: [0xC1; 16], name: "tsukimi".to_string() };
+        let packet = sacn_dmx(&source, 0x0102, 9, &frame());
+        assert_eq!(packet.len(), 638);
+
+        // ルートレイヤー
+        assert_eq!(packet[0..4], [0x00, 0x10, 0x00, 0x00]);
+        assert_eq!(&packet[4..16], b"ASC-E1.17\0\0\0");
+        assert_eq!(packet[16..18], [0x72, 0x6E]); // フラグ0x7 + 長さ622
+        assert_eq!(packet[18..22], [0, 0, 0, 0x04]);
+        assert_eq!(packet[22..38], [0xC1; 16]);
+
+        // フレーミングレイヤー
+        assert_eq!(packet[38..40], [0x72, 0x58]); // 長さ600
+        assert_eq!(packet[40..44], [0, 0, 0, 0x02]);
+        assert_eq!(&packet[44..51], b"tsukimi");
+        assert!(packet[51..108].iter().all(|byte| *byte == 0));
+        assert_eq!(packet[108], 100);
+        assert_eq!(packet[109..111], [0, 0]);
+        assert_eq!(packet[111], 9);
+        assert_eq!(packet[112], 0);
+        assert_eq!(packet[113..115], [0x01, 0x02]);
+
+        // DMPレイヤー
+        assert_eq!(packet[115..117], [0x72, 0x0B]); // 長さ523
+        assert_eq!(packet[117..125], [0x02, 0xA1, 0x00, 0x00, 0x00, 0x01, 0x02, 0x01]);
+        assert_eq!(packet[125], 0);
+        assert_eq!(packet[126], 0x11);
+        assert_eq!(packet[637], 0xFF);
+    }
+
+    #[test]
+    fn sacn_source_name_is_truncated_and_null_terminated() {
+        let source = SacnSource { cid: [0; 16], name: "x".repeat(100) };
+        let packet = sacn_dmx(&source, 1, 0, &frame());
+        assert_eq!(packet.len(), 638);
+        assert!(packet[44..107].iter().all(|byte| *byte == b'x'));
+        assert_eq!(packet[107], 0);
+        assert_eq!(packet[108], 100);
+    }
+}
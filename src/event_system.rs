@@ -0,0 +1 @@
+pub mod event_bus;
\ No newline at end of file
@@ -0,0 +1,82 @@
+use crate::clock_system::clock_main::ClockSource;
+use crate::messages::{CommandOutcome, ConnectionState};
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tracing::info;
+
+/// サブシステム間で共有するローカルイベント（Webhook・OSC・ダッシュボードなどが購読する）
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum LocalEvent {
+    /// ビーコンを新たに検知した（見えなくなった後の再検知を含む）
+    DeviceSeen { address: String, rssi: i16 },
+    /// BGMが切り替わった
+    SoundSwitched { sound: String },
+    /// SEの再生を開始した
+    SePlayed { file: String },
+    /// インタラクションが発生した
+    InteractionTriggered { place_type: String, address: String },
+    /// ポイント数が変わった
+    PointsChanged { points: i32 },
+    /// 検知していたビーコンが一定時間見えなくなった
+    BeaconLost { address: String },
+    /// システムの有効化状態が変わった
+    EnabledChanged { enabled: bool },
+    /// サーバーとの接続状態が変わった（`connected` は `state` が接続中かどうか）
+    ServerConnection { state: ConnectionState, connected: bool },
+    /// サーバーに接続できない状態が設定の時間を超えて続いている（切断1回につき1回）
+    ServerOffline { offline_ms: u64 },
+    /// ショー時刻の拠り所が変わった
+    SyncUpdated { source: ClockSource },
+    /// command_id付きのサーバーのコマンドを実行した（サーバーへの実行結果の報告に使う）
+    CommandExecuted { outcome: CommandOutcome },
+}
+
+impl LocalEvent {
+    /// 設定の `events` で指定するイベント名
+    pub fn name(&self) -> &'static str {
+        match self {
+            LocalEvent::DeviceSeen { .. } => "device_seen",
+            LocalEvent::SoundSwitched { .. } => "sound_switched",
+            LocalEvent::SePlayed { .. } => "se_played",
+            LocalEvent::InteractionTriggered { .. } => "interaction_triggered",
+            LocalEvent::PointsChanged { .. } => "points_changed",
+            LocalEvent::BeaconLost { .. } => "beacon_lost",
+            LocalEvent::EnabledChanged { .. } => "enabled_changed",
+            LocalEvent::ServerConnection { .. } => "server_connection",
+            LocalEvent::ServerOffline { .. } => "server_offline",
+            LocalEvent::SyncUpdated { .. } => "sync_updated",
+            LocalEvent::CommandExecuted { .. } => "command_executed",
+        }
+    }
+}
+
+/// ローカルイベントのバス
+///
+/// 各サブシステムは `publish` で発行し、任意のモジュールは `subscribe` で購読する。
+/// main.rsからモジュールごとに専用のチャンネルを配る必要はなく、バスを渡すだけでよい。
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    tx: broadcast::Sender<LocalEvent>,
+}
+
+impl EventBus {
+    pub fn new(capacity: usize) -> Self {
+        let (tx, _) = broadcast::channel(capacity);
+        Self { tx }
+    }
+
+    /// イベントを発行する（発行側をブロックしない）
+    ///
+    /// 購読しているモジュールが無い場合もあるため、送信失敗は無視する。
+    pub fn publish(&self, event: LocalEvent) {
+        let _ = self.tx.send(event);
+    }
+
+    /// 購読を登録する（`subscriber` はログ用の購読者名）
+    pub fn subscribe(&self, subscriber: &'static str) -> broadcast::Receiver<LocalEvent> {
+        let rx = self.tx.subscribe();
+        info!(subscriber, subscribers = self.tx.receiver_count(), "Event bus subscriber registered");
+        rx
+    }
+}
@@ -0,0 +1,3 @@
+pub mod coalescer;
+pub mod forward_control;
+pub mod forwarding_main;
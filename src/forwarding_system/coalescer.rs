@@ -0,0 +1,97 @@
+use crate::bluetooth_system::address::Address;
+use crate::messages::{DeviceInfo, DeviceSnapshot};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// 次の配信タイミングまでDeviceInfoを集約する（アドレスごとに最新値だけを残す）
+#[derive(Default)]
+pub struct Coalescer {
+    pending: HashMap<Address, Arc<DeviceInfo>>,
+}
+
+impl Coalescer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// DeviceInfoを加える（同じアドレスの未配信の値を置き換えた場合は `true`）
+    pub fn push(&mut self, device_info: Arc<DeviceInfo>) -> bool {
+        self.pending.insert(device_info.address.clone(), device_info).is_some()
+    }
+
+    /// 集約した分をスナップショットにして取り出す（無ければ `None`）
+    pub fn take_snapshot(&mut self, forwarded_at: Instant) -> Option<DeviceSnapshot> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        Some(DeviceSnapshot {
+            devices: self
+                .pending
+                .drain()
+                .map(|(_, mut device_info)| {
+                    // BLEのタスクは送信後に参照を持たないため、通常は複製せずに書き換えられる
+                    Arc::make_mut(&mut device_info).forwarded_at = Some(forwarded_at);
+                    device_info
+                })
+                .collect(),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.pending.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(address: &str, rssi: i16) -> Arc<DeviceInfo> {
+        Arc::new(DeviceInfo {
+            address: address.parse().unwrap(),
+            rssi,
+            last_seen: Instant::now(),
+            received_at: Instant::now(),
+            forwarded_at: None,
+            visitor_token: None,
+            local_name: None,
+            tx_power: None,
+            manufacturer_data: HashMap::new(),
+            service_data: HashMap::new(),
+        })
+    }
+
+    #[test]
+    fn keeps_only_the_latest_value_per_address() {
+        let mut coalescer = Coalescer::new();
+        assert!(!coalescer.push(device("AA:BB:CC:DD:EE:01", -70)));
+        assert!(coalescer.push(device("AA:BB:CC:DD:EE:01", -50)));
+        assert!(!coalescer.push(device("AA:BB:CC:DD:EE:02", -80)));
+
+        let forwarded_at = Instant::now();
+        let snapshot = coalescer.take_snapshot(forwarded_at).unwrap();
+        assert_eq!(snapshot.devices.len(), 2);
+        let latest = snapshot.devices.iter().find(|d| d.address.as_str() == "AA:BB:CC:DD:EE:01").unwrap();
+        assert_eq!(latest.rssi, -50);
+        assert!(snapshot.devices.iter().all(|d| d.forwarded_at == Some(forwarded_at)));
+    }
+
+    #[test]
+    fn snapshot_drains_pending_values() {
+        let mut coalescer = Coalescer::new();
+        assert!(coalescer.take_snapshot(Instant::now()).is_none());
+        coalescer.push(device("AA:BB:CC:DD:EE:01", -70));
+        assert!(coalescer.take_snapshot(Instant::now()).is_some());
+        assert!(coalescer.is_empty());
+        assert!(coalescer.take_snapshot(Instant::now()).is_none());
+    }
+}
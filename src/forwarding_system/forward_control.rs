@@ -0,0 +1,138 @@
+use crate::messages::EnabledState;
+use crate::shutdown_system::shutdown_controller::{ShutdownController, ShutdownPhase};
+use std::sync::Arc;
+use tokio::sync::{mpsc, watch};
+use tracing::{info, instrument};
+
+/// 転送タスクへの制御メッセージ
+///
+/// システムの無効化は一時停止であって終了ではない。終了は終了手順だけが指示する。
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForwardControl {
+    /// スナップショットの配信を止める（BLEのイベントの受信は続け、配信の周期ごとに捨てる）
+    Pause,
+    /// スナップショットの配信を再開する
+    Resume,
+    /// 転送タスクを終了する（BLEの受信側を閉じ、シミュレーションも止める）
+    Shutdown,
+}
+
+/// 制御メッセージのチャンネル（Pause/Resumeが連続しても送信側を待たせない容量）
+pub fn channel() -> (mpsc::Sender<ForwardControl>, mpsc::Receiver<ForwardControl>) {
+    mpsc::channel(8)
+}
+
+/// システムの有効化状態と終了手順を、転送タスクへの制御メッセージに変換するタスク
+///
+/// 起動時の有効化状態を最初に送り、以後は変化したときだけ送る。
+/// 終了手順がBLEの停止に達したら `Shutdown` を送って終了する。
+#[instrument(skip_all)]
+pub async fn forward_controller(
+    mut enabled_rx: watch::Receiver<EnabledState>,
+    shutdown: Arc<ShutdownController>,
+    control_tx: mpsc::Sender<ForwardControl>,
+) {
+    let stop = shutdown.reached(ShutdownPhase::StopBle);
+    tokio::pin!(stop);
+    let mut forwarding = true;
+    let mut enabled_open = true;
+
+    loop {
+        let enabled = enabled_rx.borrow_and_update().enabled;
+        if enabled != forwarding {
+            forwarding = enabled;
+            info!(enabled, "System enabled state changed - {} device snapshot forwarding", if enabled { "resuming" } else { "pausing" });
+            let control = if enabled { ForwardControl::Resume } else { ForwardControl::Pause };
+            if control_tx.send(control).await.is_err() {
+                return;
+            }
+        }
+
+        tokio::select! {
+            _ = &mut stop => {
+                let _ = control_tx.send(ForwardControl::Shutdown).await;
+                return;
+            }
+            result = enabled_rx.changed(), if enabled_open => {
+                // 送信側が無くなったら最後の状態のまま終了手順を待つ
+                enabled_open = result.is_ok();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connect_system::enable_state;
+    use std::time::Duration;
+    use tokio::task::JoinHandle;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn start() -> (watch::Sender<EnabledState>, Arc<ShutdownController>, mpsc::Receiver<ForwardControl>, JoinHandle<()>) {
+        let (enabled_tx, enabled_rx) = enable_state::channel();
+        let shutdown = ShutdownController::new();
+        let (control_tx, control_rx) = channel();
+        let handle = tokio::spawn(forward_controller(enabled_rx, Arc::clone(&shutdown), control_tx));
+        (enabled_tx, shutdown, control_rx, handle)
+    }
+
+    async fn next(control_rx: &mut mpsc::Receiver<ForwardControl>) -> ForwardControl {
+        tokio::time::timeout(TIMEOUT, control_rx.recv()).await.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn disabling_pauses_and_enabling_resumes() {
+        let (enabled_tx, _shutdown, mut control_rx, _handle) = start();
+        enable_state::apply(&enabled_tx, false, None);
+        assert_eq!(next(&mut control_rx).await, ForwardControl::Pause);
+        enable_state::apply(&enabled_tx, true, None);
+        assert_eq!(next(&mut control_rx).await, ForwardControl::Resume);
+    }
+
+    #[tokio::test]
+    async fn rapid_toggles_never_shut_down_and_end_in_the_final_state() {
+        let (enabled_tx, _shutdown, mut control_rx, _handle) = start();
+        for _ in 0..51 {
+            enable_state::toggle_device(&enabled_tx);
+            tokio::task::yield_now().await;
+        }
+        // 中間の状態を取りこぼしても、Pause/Resumeは交互で、最後は最終状態（無効）になる
+        let mut last = ForwardControl::Resume;
+        loop {
+            match tokio::time::timeout(Duration::from_millis(100), control_rx.recv()).await {
+                Ok(Some(control)) => {
+                    assert_ne!(control, ForwardControl::Shutdown);
+                    assert_ne!(control, last);
+                    last = control;
+                }
+                Ok(None) => panic!("controller exited"),
+                Err(_) => break,
+            }
+        }
+        assert!(!enabled_tx.borrow().enabled);
+        assert_eq!(last, ForwardControl::Pause);
+    }
+
+    #[tokio::test]
+    async fn shutdown_is_sent_only_by_the_shutdown_sequence() {
+        let (enabled_tx, shutdown, mut control_rx, handle) = start();
+        enable_state::apply(&enabled_tx, false, None);
+        assert_eq!(next(&mut control_rx).await, ForwardControl::Pause);
+        shutdown.enter(ShutdownPhase::StopBle);
+        assert_eq!(next(&mut control_rx).await, ForwardControl::Shutdown);
+        tokio::time::timeout(TIMEOUT, handle).await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn closed_enabled_channel_keeps_the_last_state() {
+        let (enabled_tx, shutdown, mut control_rx, _handle) = start();
+        enable_state::apply(&enabled_tx, false, None);
+        assert_eq!(next(&mut control_rx).await, ForwardControl::Pause);
+        drop(enabled_tx);
+        assert!(tokio::time::timeout(Duration::from_millis(100), control_rx.recv()).await.is_err());
+        shutdown.enter(ShutdownPhase::StopBle);
+        assert_eq!(next(&mut control_rx).await, ForwardControl::Shutdown);
+    }
+}
@@ -0,0 +1,68 @@
+use crate::config_system::config_main::ForwardingConfig;
+use crate::forwarding_system::coalescer::Coalescer;
+use crate::forwarding_system::forward_control::ForwardControl;
+use crate::messages::{DeviceInfo, DeviceSnapshot};
+use crate::metrics_system::metrics_main::{metrics, Metrics};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, mpsc};
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, info, instrument, warn};
+
+/// BLEスキャナ（またはシミュレーション）からのDeviceInfoを集約し、一定周期でbroadcastに配信するタスク
+///
+/// 配信の一時停止・再開・終了は `ForwardControl` で指示する（`forward_controller` が送る）。
+/// 制御の送信側が無くなった場合は、最後の指示のまま動作を続ける。
+#[instrument(skip_all)]
+pub async fn forwarding_main(
+    config: ForwardingConfig,
+    mut bt_rx: mpsc::Receiver<Arc<DeviceInfo>>,
+    bcast_tx: broadcast::Sender<Arc<DeviceSnapshot>>,
+    mut control_rx: mpsc::Receiver<ForwardControl>,
+) {
+    // アドレスごとの最新値（次の配信タイミングまで蓄積する）
+    let mut pending = Coalescer::new();
+    let mut tick = tokio::time::interval(Duration::from_millis(config.snapshot_interval_ms.max(1)));
+    tick.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    let mut paused = false;
+
+    loop {
+        tokio::select! {
+            Some(control) = control_rx.recv() => match control {
+                ForwardControl::Pause => paused = true,
+                ForwardControl::Resume => paused = false,
+                ForwardControl::Shutdown => {
+                    info!(discarded = pending.len(), "Forwarding task: stopped for shutdown");
+                    break;
+                }
+            },
+            device_info_opt = bt_rx.recv() => {
+                let Some(device_info) = device_info_opt else {
+                    break;
+                };
+                if pending.push(device_info) {
+                    Metrics::add(&metrics().device_info_coalesced, 1);
+                }
+            }
+            _ = tick.tick() => {
+                // 一時停止中は集約した分を配信せずに捨てる
+                if paused && !pending.is_empty() {
+                    debug!(count = pending.len(), "System disabled - skipping device snapshot forwarding");
+                    pending.clear();
+                    continue;
+                }
+                let Some(snapshot) = pending.take_snapshot(Instant::now()) else {
+                    continue;
+                };
+
+                let count = snapshot.devices.len() as u64;
+                debug!(?snapshot, "Forwarding device snapshot");
+                if bcast_tx.send(Arc::new(snapshot)).is_err() {
+                    warn!("Failed to send device snapshot to broadcast channel. No receivers?");
+                } else {
+                    Metrics::add(&metrics().device_info_forwarded, count);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,2 @@
+pub mod buttons;
+pub mod status_led;
\ No newline at end of file
@@ -0,0 +1,90 @@
+use crate::audio_system::audio_main::activation_se_request;
+use crate::audio_system::master_volume::MasterVolume;
+use crate::config_system::config_main::{ActivationSeConfig, ButtonAction, GpioConfig};
+use crate::connect_system::enable_state;
+use crate::messages::{EnabledState, SePlayRequest};
+use anyhow::Result;
+use rppal::gpio::{Gpio, InputPin};
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, watch};
+use tracing::{info, instrument, warn};
+
+/// ボタンの状態を読み取る周期
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// チャタリングを除去しながら押下を検出するボタン（プルアップ・押下でLow）
+struct Button {
+    pin: InputPin,
+    action: ButtonAction,
+    /// 確定済みの押下状態
+    pressed: bool,
+    /// 確定済みの状態と異なる値を最初に読んだ時刻
+    changed_at: Option<Instant>,
+}
+
+impl Button {
+    /// 状態を読み取り、押下が確定した瞬間だけ `true` を返す
+    fn poll(&mut self, debounce: Duration) -> bool {
+        let raw = self.pin.is_low();
+        if raw == self.pressed {
+            self.changed_at = None;
+            return false;
+        }
+        let changed_at = *self.changed_at.get_or_insert_with(Instant::now);
+        if changed_at.elapsed() < debounce {
+            return false;
+        }
+        self.pressed = raw;
+        self.changed_at = None;
+        self.pressed
+    }
+}
+
+/// 物理ボタンの入力を監視し、サーバーからの指示と同じチャンネルに流すタスク
+#[instrument(skip(activation_se, se_tx, volume_tx, enabled_tx, rescan_tx))]
+pub async fn buttons_main(
+    config: GpioConfig,
+    activation_se: ActivationSeConfig,
+    se_tx: mpsc::Sender<SePlayRequest>,
+    volume_tx: watch::Sender<MasterVolume>,
+    enabled_tx: watch::Sender<EnabledState>,
+    rescan_tx: mpsc::Sender<()>,
+) -> Result<()> {
+    let gpio = Gpio::new()?;
+    let mut buttons = Vec::with_capacity(config.buttons.len());
+    for button in &config.buttons {
+        let pin = gpio.get(button.pin)?.into_input_pullup();
+        info!(pin = button.pin, action = ?button.action, "Button initialized");
+        buttons.push(Button { pin, action: button.action, pressed: false, changed_at: None });
+    }
+    let debounce = Duration::from_millis(config.button_debounce_ms);
+
+    let mut tick = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        tick.tick().await;
+        for button in buttons.iter_mut() {
+            if !button.poll(debounce) {
+                continue;
+            }
+            info!(action = ?button.action, "🔘 Button pressed");
+            match button.action {
+                ButtonAction::ToggleMute => {
+                    volume_tx.send_modify(|volume| volume.muted = !volume.muted);
+                }
+                ButtonAction::ToggleEnabled => {
+                    enable_state::toggle_device(&enabled_tx);
+                }
+                ButtonAction::ReplayActivationSe => {
+                    let request = activation_se_request(&activation_se, true);
+                    if let Err(e) = se_tx.send(request).await {
+                        warn!("Failed to send activation SE request: {}", e);
+                    }
+                }
+                ButtonAction::ForceRescan => {
+                    // 前回のやり直しが未処理なら重ねて要求しない
+                    let _ = rescan_tx.try_send(());
+                }
+            }
+        }
+    }
+}
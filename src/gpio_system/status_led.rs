@@ -0,0 +1,141 @@
+use crate::audio_system::playback_fsm::PlaybackState;
+use crate::audio_system::playback_status::PlaybackStatus;
+use crate::clock_system::clock_main::Clock;
+use crate::config_system::config_main::GpioConfig;
+use crate::event_system::event_bus::LocalEvent;
+use crate::messages::{ConnectionState, DeviceSnapshot};
+use anyhow::Result;
+use rppal::gpio::Gpio;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, watch};
+use tracing::{info, instrument};
+
+/// LEDの点灯パターンを切り替える周期
+const LED_TICK: Duration = Duration::from_millis(50);
+
+/// LEDで表示するユニットの状態（上から優先）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnitStatus {
+    /// サーバーから無効化されている
+    Disabled,
+    /// サーバーに接続できていない・時刻同期が途絶えている
+    NoServer,
+    /// 代替の経路（WebSocket）でサーバーに接続している
+    Degraded,
+    /// 登録済みのビーコンを一定時間受信していない
+    NoBeacons,
+    /// 同期済みでBGMを再生中
+    Playing,
+    /// 起動直後・同期待ち・復旧中
+    Starting,
+}
+
+impl UnitStatus {
+    /// 点灯・消灯の時間（ミリ秒）。消灯0は常時点灯、点灯0は常時消灯
+    fn pattern(self) -> (u64, u64) {
+        match self {
+            UnitStatus::Playing => (1, 0),
+            UnitStatus::NoServer => (100, 100),
+            UnitStatus::Degraded => (1900, 100),
+            UnitStatus::NoBeacons => (500, 500),
+            UnitStatus::Disabled => (100, 1900),
+            UnitStatus::Starting => (0, 1),
+        }
+    }
+
+    /// パターン開始からの経過時間で点灯すべきかどうか
+    fn is_lit(self, elapsed: Duration) -> bool {
+        let (on_ms, off_ms) = self.pattern();
+        if off_ms == 0 || on_ms == 0 {
+            return off_ms == 0;
+        }
+        (elapsed.as_millis() as u64) % (on_ms + off_ms) < on_ms
+    }
+}
+
+/// 再生状況・接続状態・時刻同期・ビーコンの受信状況からユニットの状態を判定する
+///
+/// `connection` はイベントバスで最後に受け取った接続状態（まだ受け取っていなければ `None`）。
+fn unit_status(
+    status: &PlaybackStatus,
+    connection: Option<ConnectionState>,
+    backend_sync_age: Option<Duration>,
+    last_beacon: Option<Instant>,
+    config: &GpioConfig,
+) -> UnitStatus {
+    let server_stale = Duration::from_millis(config.no_server_timeout_ms);
+    let beacon_stale = Duration::from_millis(config.no_beacon_timeout_ms);
+    let no_server = match connection {
+        Some(state) if !state.is_connected() => true,
+        // 時刻同期はgRPCでのみ行うため、フォールバック中は同期の途絶を見ない
+        Some(ConnectionState::Degraded) => false,
+        _ => !backend_sync_age.is_some_and(|age| age < server_stale),
+    };
+    if !status.enabled {
+        UnitStatus::Disabled
+    } else if no_server {
+        UnitStatus::NoServer
+    } else if connection == Some(ConnectionState::Degraded) {
+        UnitStatus::Degraded
+    } else if !last_beacon.is_some_and(|t| t.elapsed() < beacon_stale) {
+        UnitStatus::NoBeacons
+    } else if matches!(status.state, PlaybackState::Playing | PlaybackState::Switching) {
+        UnitStatus::Playing
+    } else {
+        UnitStatus::Starting
+    }
+}
+
+/// ステータスLEDを駆動するタスク
+#[instrument(skip(clock, status_rx, beacon_rx, event_rx))]
+pub async fn status_led_main(
+    config: GpioConfig,
+    clock: Arc<Clock>,
+    status_rx: watch::Receiver<PlaybackStatus>,
+    mut beacon_rx: broadcast::Receiver<Arc<DeviceSnapshot>>,
+    mut event_rx: broadcast::Receiver<LocalEvent>,
+) -> Result<()> {
+    let mut led = Gpio::new()?.get(config.status_led_pin)?.into_output_low();
+    info!(pin = config.status_led_pin, "Status LED initialized");
+
+    let mut tick = tokio::time::interval(LED_TICK);
+    let mut last_beacon: Option<Instant> = None;
+    let mut connection: Option<ConnectionState> = None;
+    let mut current = UnitStatus::Starting;
+    let mut pattern_start = Instant::now();
+
+    loop {
+        tokio::select! {
+            _ = tick.tick() => {
+                let next = unit_status(&status_rx.borrow(), connection, clock.backend_sync_age(), last_beacon, &config);
+                if next != current {
+                    info!(from = ?current, to = ?next, "Status LED pattern changed");
+                    current = next;
+                    pattern_start = Instant::now();
+                }
+                if current.is_lit(pattern_start.elapsed()) {
+                    led.set_high();
+                } else {
+                    led.set_low();
+                }
+            }
+            result = beacon_rx.recv() => match result {
+                Ok(snapshot) => {
+                    if !snapshot.devices.is_empty() {
+                        last_beacon = Some(Instant::now());
+                    }
+                }
+                // 読み飛ばしが発生した = ビーコンは受信できている
+                Err(broadcast::error::RecvError::Lagged(_)) => last_beacon = Some(Instant::now()),
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            result = event_rx.recv() => match result {
+                Ok(LocalEvent::ServerConnection { state, .. }) => connection = Some(state),
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                Err(broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,34 @@
+// スタンドアロン構成ではサーバーから届く値（ポイント・サーバー時刻など）を扱う処理の多くが使われない
+#![cfg_attr(not(feature = "server"), allow(dead_code))]
+
+pub mod api_system;
+pub mod audio_system;
+pub mod bluetooth_system;
+#[cfg(feature = "chaos")]
+pub mod chaos_system;
+pub mod clock_system;
+pub mod config_system;
+pub mod connect_system;
+pub mod dmx_system;
+pub mod event_system;
+pub mod forwarding_system;
+#[cfg(feature = "gpio")]
+pub mod gpio_system;
+pub mod log_system;
+pub mod messages;
+pub mod metrics_system;
+#[cfg(feature = "midi")]
+pub mod midi_system;
+#[cfg(feature = "server")]
+pub mod net_system;
+pub mod osc_system;
+pub mod peer_system;
+#[cfg(feature = "server")]
+pub mod proto;
+pub mod schedule_system;
+#[cfg(feature = "scripting")]
+pub mod script_system;
+pub mod shutdown_system;
+pub mod survey_system;
+#[cfg(feature = "server")]
+pub mod webhook_system;
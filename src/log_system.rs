@@ -0,0 +1,4 @@
+pub mod crash_report;
+pub mod log_shipper;
+#[cfg(target_os = "linux")]
+pub mod minidump;
\ No newline at end of file
@@ -0,0 +1,447 @@
+use crate::config_system::config_main::CrashReportConfig;
+use crate::log_system::log_shipper::{LogBuffer, LogRecord};
+#[cfg(feature = "server")]
+use crate::net_system::egress::http_client;
+#[cfg(feature = "server")]
+use crate::config_system::unit_identity::unit_identity;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::backtrace::Backtrace;
+use std::panic::PanicHookInfo;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+#[cfg(feature = "server")]
+use std::time::Duration;
+use std::time::{SystemTime, UNIX_EPOCH};
+#[cfg(feature = "server")]
+use tracing::info;
+use tracing::warn;
+
+/// パニック時に書き出すクラッシュレポート
+#[derive(Debug, Serialize)]
+struct CrashReport {
+    /// UNIX時刻（ミリ秒）
+    timestamp_ms: u128,
+    version: &'static str,
+    thread: String,
+    message: String,
+    /// パニックが発生したソースの位置（`file:line:column`）
+    location: Option<String>,
+    backtrace: String,
+    /// パニック直前のログ（古い順）
+    recent_logs: Vec<LogRecord>,
+}
+
+/// パニックのペイロードを文字列として取り出す
+fn panic_message(info: &PanicHookInfo<'_>) -> String {
+    if let Some(message) = info.payload().downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = info.payload().downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "<non-string panic payload>".to_string()
+    }
+}
+
+fn write_report(dir: &Path, report: &CrashReport) -> Result<PathBuf> {
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create crash report directory: {}", dir.display()))?;
+    let path = dir.join(format!("crash-{}.json", report.timestamp_ms));
+    // 書きかけのファイルを送信しないよう一時ファイルから置き換える
+    let tmp_path = path.with_extension("json.tmp");
+    std::fs::write(&tmp_path, serde_json::to_vec_pretty(report)?)
+        .with_context(|| format!("Failed to write crash report: {}", tmp_path.display()))?;
+    std::fs::rename(&tmp_path, &path).with_context(|| format!("Failed to replace crash report: {}", path.display()))?;
+    Ok(path)
+}
+
+/// レポートと同じ名前（拡張子 `.dmp`）でミニダンプを書き出す
+#[cfg(target_os = "linux")]
+fn write_minidump(report_path: &Path) -> Result<PathBuf> {
+    use crate::log_system::minidump::{self, Crash};
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = report_path.with_extension("dmp");
+    let tmp_path = path.with_extension("dmp.tmp");
+    // SAFETY: ucontext_tはすべて0でもよい
+    let mut context = unsafe { std::mem::zeroed::<libc::ucontext_t>() };
+    let captured = minidump::capture_context(&mut context);
+    let crash = Crash { thread_id: minidump::current_thread_id(), signal: None, fault_address: 0, context: captured.then_some(&context) };
+    let mut buf = vec![0u8; minidump::BUFFER_CAPACITY];
+    if !minidump::write_file(&mut buf, &crash, &CString::new(tmp_path.as_os_str().as_bytes())?, &CString::new(path.as_os_str().as_bytes())?) {
+        anyhow::bail!("Failed to write minidump: {}", path.display());
+    }
+    Ok(path)
+}
+
+/// パニック時にバックトレースと直近のログをクラッシュレポートとして保存するフックを登録する
+///
+/// 既存のフック（標準エラー出力への表示）はそのまま呼び出す。
+/// Linuxではレポートと並べてミニダンプ（`.dmp`、minidump-stackwalkなどで読める）も書き出し、
+/// ネイティブのクラッシュ（GStreamerのプラグインなどでのSIGSEGV等）もシグナルハンドラで記録する。
+pub fn install_panic_hook(config: &CrashReportConfig, log_buffer: Arc<LogBuffer>) {
+    let dir = PathBuf::from(&config.dir);
+    #[cfg(target_os = "linux")]
+    native::install(&dir);
+    let previous_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let report = CrashReport {
+            timestamp_ms: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis(),
+            version: env!("CARGO_PKG_VERSION"),
+            thread: std::thread::current().name().unwrap_or("<unnamed>").to_string(),
+            message: panic_message(info),
+            location: info.location().map(|location| location.to_string()),
+            backtrace: Backtrace::force_capture().to_string(),
+            recent_logs: log_buffer.recent(),
+        };
+        // フックの中ではtracingを使わず、標準エラー出力に直接書く
+        match write_report(&dir, &report) {
+            Ok(path) => {
+                eprintln!("Crash report written to {}", path.display());
+                #[cfg(target_os = "linux")]
+                match write_minidump(&path) {
+                    Ok(path) => eprintln!("Minidump written to {}", path.display()),
+                    Err(e) => eprintln!("Failed to write minidump: {:?}", e),
+                }
+            }
+            Err(e) => eprintln!("Failed to write crash report: {:?}", e),
+        }
+        previous_hook(info);
+    }));
+}
+
+/// 送信するファイル（レポートとミニダンプ）の拡張子とContent-Type
+const UPLOAD_KINDS: [(&str, &str); 2] = [("json", "application/json"), ("dmp", "application/x-minidump")];
+
+/// 送信するファイルならそのContent-Type
+fn upload_content_type(path: &Path) -> Option<&'static str> {
+    let ext = path.extension()?;
+    UPLOAD_KINDS.iter().find(|(kind, _)| ext == *kind).map(|(_, content_type)| *content_type)
+}
+
+/// 前回までのクラッシュレポートとミニダンプを送信し、送れたものを削除する
+pub async fn upload_pending_reports(config: CrashReportConfig) {
+    let dir = PathBuf::from(&config.dir);
+    let Ok(entries) = std::fs::read_dir(&dir) else {
+        return;
+    };
+    let mut reports: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| upload_content_type(path).is_some())
+        .collect();
+    if reports.is_empty() {
+        return;
+    }
+    reports.sort();
+
+    let Some(upload_url) = config.upload_url else {
+        warn!(count = reports.len(), dir = %dir.display(), "Found crash reports from previous runs (upload is not configured)");
+        return;
+    };
+    #[cfg(feature = "server")]
+    upload(&upload_url, reports).await;
+    #[cfg(not(feature = "server"))]
+    warn!(
+        count = reports.len(),
+        dir = %dir.display(),
+        url = %upload_url,
+        "Crash report upload is configured but this build has no `server` feature - ignoring"
+    );
+}
+
+/// クラッシュレポートとミニダンプを古い順に送信し、送れたものを削除する
+#[cfg(feature = "server")]
+async fn upload(upload_url: &str, reports: Vec<PathBuf>) {
+    info!(count = reports.len(), url = %upload_url, "Uploading crash reports from previous runs");
+
+    let client = http_client();
+    for path in reports {
+        let result = async {
+            let body = std::fs::read(&path)?;
+            let content_type = upload_content_type(&path).unwrap_or("application/octet-stream");
+            let mut request = client.post(upload_url);
+            for (name, value) in unit_identity().headers() {
+                request = request.header(name, value);
+            }
+            request
+                .header(reqwest::header::CONTENT_TYPE, content_type)
+                .body(body)
+                .timeout(Duration::from_secs(30))
+                .send()
+                .await?
+                .error_for_status()?;
+            std::fs::remove_file(&path)?;
+            anyhow::Ok(())
+        }
+        .await;
+        match result {
+            Ok(()) => info!(path = %path.display(), "Crash report uploaded"),
+            Err(e) => {
+                // 次回の起動時に再送する
+                warn!(path = %path.display(), "Failed to upload crash report: {:?}", e);
+                break;
+            }
+        }
+    }
+}
+
+/// ネイティブのクラッシュを記録するシグナルハンドラ
+///
+/// シグナルハンドラの中ではメモリの確保・ロックができないため、書き出し先のパスは登録時に用意しておき、
+/// スタック上のバッファだけでパニック時と同じ形のレポート（バックトレース・ログは空）を書き、
+/// 登録時に確保しておいたバッファでミニダンプ（落ちたスレッドのレジスタとスタックを含む）を書く。
+/// 書き終えたら元の処理（Rustのスタックオーバーフローの検出・既定のコアダンプ）に戻してシグナルを送り直す。
+#[cfg(target_os = "linux")]
+mod native {
+    use crate::log_system::minidump::{self, Crash};
+    use std::ffi::{c_void, CStr};
+    use std::fmt::Write as _;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicPtr, Ordering};
+    use std::sync::OnceLock;
+
+    const SIGNALS: [libc::c_int; 5] = [libc::SIGSEGV, libc::SIGBUS, libc::SIGILL, libc::SIGFPE, libc::SIGABRT];
+
+    /// レポートのパスとJSONの上限（超える場合は書かない）
+    const PATH_CAPACITY: usize = 512;
+    const REPORT_CAPACITY: usize = 512;
+
+    /// `<dir>/native-crash-`（この後にUNIX時刻（ミリ秒）と拡張子を付ける）
+    static PATH_PREFIX: OnceLock<Vec<u8>> = OnceLock::new();
+    /// ミニダンプの書き出しに使うバッファ（大きさは `minidump::BUFFER_CAPACITY`、最初に落ちたスレッドが取り出して使う）
+    static DUMP_BUFFER: AtomicPtr<u8> = AtomicPtr::new(std::ptr::null_mut());
+    /// 登録前のハンドラ（シグナル番号と組にして持つ）
+    static PREVIOUS_ACTIONS: OnceLock<Vec<(libc::c_int, libc::sigaction)>> = OnceLock::new();
+
+    /// 固定長のバッファに書くWriter（溢れた場合はエラー）
+    pub(super) struct StackBuffer<const N: usize> {
+        buf: [u8; N],
+        len: usize,
+    }
+
+    impl<const N: usize> StackBuffer<N> {
+        pub(super) const fn new() -> Self {
+            Self { buf: [0; N], len: 0 }
+        }
+
+        fn push_bytes(&mut self, bytes: &[u8]) -> std::fmt::Result {
+            let end = self.len.checked_add(bytes.len()).filter(|&end| end <= N).ok_or(std::fmt::Error)?;
+            self.buf[self.len..end].copy_from_slice(bytes);
+            self.len = end;
+            Ok(())
+        }
+
+        pub(super) fn as_bytes(&self) -> &[u8] {
+            &self.buf[..self.len]
+        }
+    }
+
+    impl<const N: usize> std::fmt::Write for StackBuffer<N> {
+        fn write_str(&mut self, s: &str) -> std::fmt::Result {
+            self.push_bytes(s.as_bytes())
+        }
+    }
+
+    fn signal_name(signal: libc::c_int) -> &'static str {
+        match signal {
+            libc::SIGSEGV => "SIGSEGV",
+            libc::SIGBUS => "SIGBUS",
+            libc::SIGILL => "SIGILL",
+            libc::SIGFPE => "SIGFPE",
+            libc::SIGABRT => "SIGABRT",
+            _ => "unknown",
+        }
+    }
+
+    /// パニック時の `CrashReport` と同じ形のJSON（送信側・受信側で区別しなくてよいように）
+    pub(super) fn format_report(buf: &mut StackBuffer<REPORT_CAPACITY>, timestamp_ms: u64, signal: libc::c_int, fault_address: usize) -> std::fmt::Result {
+        write!(
+            buf,
+            r#"{{"timestamp_ms":{},"version":"{}","thread":"<native>","message":"Fatal signal {} ({}) at {:#x}","location":null,"backtrace":"","recent_logs":[]}}"#,
+            timestamp_ms,
+            env!("CARGO_PKG_VERSION"),
+            signal,
+            signal_name(signal),
+            fault_address
+        )
+    }
+
+    /// `<prefix><timestamp_ms>.json` に一時ファイルから書き出す（シグナルハンドラの中で呼ぶ）
+    pub(super) fn write_report(prefix: &[u8], timestamp_ms: u64, signal: libc::c_int, fault_address: usize) -> bool {
+        let mut report = StackBuffer::<REPORT_CAPACITY>::new();
+        let mut tmp_path = StackBuffer::<PATH_CAPACITY>::new();
+        let mut path = StackBuffer::<PATH_CAPACITY>::new();
+        let formatted = format_report(&mut report, timestamp_ms, signal, fault_address).is_ok()
+            && tmp_path.push_bytes(prefix).is_ok()
+            && write!(tmp_path, "{}.json.tmp\0", timestamp_ms).is_ok()
+            && path.push_bytes(prefix).is_ok()
+            && write!(path, "{}.json\0", timestamp_ms).is_ok();
+        if !formatted {
+            return false;
+        }
+        // SAFETY: パスはNUL終端済みで、open/write/close/renameはシグナルハンドラから呼べる
+        unsafe {
+            let fd = libc::open(tmp_path.as_bytes().as_ptr().cast(), libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC | libc::O_CLOEXEC, 0o644);
+            if fd < 0 {
+                return false;
+            }
+            let bytes = report.as_bytes();
+            let written = libc::write(fd, bytes.as_ptr().cast(), bytes.len());
+            libc::close(fd);
+            written == bytes.len() as isize && libc::rename(tmp_path.as_bytes().as_ptr().cast(), path.as_bytes().as_ptr().cast()) == 0
+        }
+    }
+
+    /// `<prefix><timestamp_ms>.dmp` にミニダンプを書き出す（シグナルハンドラの中で呼ぶ）
+    pub(super) fn write_minidump(prefix: &[u8], timestamp_ms: u64, buf: &mut [u8], crash: &Crash<'_>) -> bool {
+        let mut tmp_path = StackBuffer::<PATH_CAPACITY>::new();
+        let mut path = StackBuffer::<PATH_CAPACITY>::new();
+        let formatted = tmp_path.push_bytes(prefix).is_ok()
+            && write!(tmp_path, "{}.dmp.tmp\0", timestamp_ms).is_ok()
+            && path.push_bytes(prefix).is_ok()
+            && write!(path, "{}.dmp\0", timestamp_ms).is_ok();
+        match (CStr::from_bytes_with_nul(tmp_path.as_bytes()), CStr::from_bytes_with_nul(path.as_bytes())) {
+            (Ok(tmp_path), Ok(path)) if formatted => minidump::write_file(buf, crash, tmp_path, path),
+            _ => false,
+        }
+    }
+
+    /// 標準エラー出力に書く（シグナルハンドラの中で呼ぶ）
+    fn print(message: &str) {
+        // SAFETY: writeはシグナルハンドラから呼べる
+        unsafe { libc::write(libc::STDERR_FILENO, message.as_ptr().cast(), message.len()) };
+    }
+
+    extern "C" fn handle_signal(signal: libc::c_int, info: *mut libc::siginfo_t, context: *mut c_void) {
+        if let Some(prefix) = PATH_PREFIX.get() {
+            // SAFETY: clock_gettimeはシグナルハンドラから呼べ、`info` と `context` はカーネルが渡したもの
+            let (timestamp_ms, fault_address, code, context) = unsafe {
+                let mut now = std::mem::zeroed::<libc::timespec>();
+                libc::clock_gettime(libc::CLOCK_REALTIME, &mut now);
+                let (fault_address, code) = if info.is_null() { (0, 0) } else { ((*info).si_addr() as usize, (*info).si_code) };
+                (now.tv_sec as u64 * 1000 + now.tv_nsec as u64 / 1_000_000, fault_address, code, (context as *const libc::ucontext_t).as_ref())
+            };
+            print(if write_report(prefix, timestamp_ms, signal, fault_address) {
+                "Native crash report written\n"
+            } else {
+                "Failed to write native crash report\n"
+            });
+            let buf = DUMP_BUFFER.swap(std::ptr::null_mut(), Ordering::AcqRel);
+            if !buf.is_null() {
+                // SAFETY: バッファは登録時に確保して解放しておらず、取り出したスレッドだけが使う
+                let buf = unsafe { std::slice::from_raw_parts_mut(buf, minidump::BUFFER_CAPACITY) };
+                let crash = Crash { thread_id: minidump::current_thread_id(), signal: Some((signal, code)), fault_address: fault_address as u64, context };
+                print(if write_minidump(prefix, timestamp_ms, buf, &crash) {
+                    "Native crash minidump written\n"
+                } else {
+                    "Failed to write native crash minidump\n"
+                });
+            }
+        }
+        // SAFETY: 登録前のハンドラ（無ければ既定の処理）に戻し、同じシグナルを送り直す
+        unsafe {
+            match PREVIOUS_ACTIONS.get().and_then(|actions| actions.iter().find(|(s, _)| *s == signal)) {
+                Some((_, previous)) => libc::sigaction(signal, previous, std::ptr::null_mut()),
+                None => libc::signal(signal, libc::SIG_DFL) as libc::c_int,
+            };
+            libc::raise(signal);
+        }
+    }
+
+    /// シグナルハンドラを登録する（2回目以降は何もしない）
+    pub(super) fn install(dir: &Path) {
+        if let Err(e) = std::fs::create_dir_all(dir) {
+            eprintln!("Failed to create crash report directory for native crashes: {:?}", e);
+            return;
+        }
+        let mut prefix = dir.as_os_str().as_bytes().to_vec();
+        prefix.extend_from_slice(b"/native-crash-");
+        if PATH_PREFIX.set(prefix).is_err() {
+            return;
+        }
+        // シグナルハンドラの中では確保できないため、プロセスが終わるまで持ち続ける
+        DUMP_BUFFER.store(Box::leak(vec![0u8; minidump::BUFFER_CAPACITY].into_boxed_slice()).as_mut_ptr(), Ordering::Release);
+
+        let mut previous_actions = Vec::with_capacity(SIGNALS.len());
+        for signal in SIGNALS {
+            // SAFETY: ハンドラはシグナルハンドラから呼べる処理だけを行う
+            unsafe {
+                let mut action = std::mem::zeroed::<libc::sigaction>();
+                action.sa_sigaction = handle_signal as *const () as usize;
+                action.sa_flags = libc::SA_SIGINFO | libc::SA_ONSTACK;
+                libc::sigemptyset(&mut action.sa_mask);
+                let mut previous = std::mem::zeroed::<libc::sigaction>();
+                if libc::sigaction(signal, &action, &mut previous) == 0 {
+                    previous_actions.push((signal, previous));
+                }
+            }
+        }
+        let _ = PREVIOUS_ACTIONS.set(previous_actions);
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::native::{format_report, write_minidump, write_report, StackBuffer};
+    use super::upload_content_type;
+    use crate::log_system::minidump::{self, Crash};
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    #[test]
+    fn native_report_has_the_panic_report_shape() {
+        let mut buf = StackBuffer::new();
+        format_report(&mut buf, 1_700_000_000_000, libc::SIGSEGV, 0x10).unwrap();
+        let report: serde_json::Value = serde_json::from_slice(buf.as_bytes()).unwrap();
+        assert_eq!(report["timestamp_ms"], 1_700_000_000_000u64);
+        assert_eq!(report["message"], "Fatal signal 11 (SIGSEGV) at 0x10");
+        assert!(report["recent_logs"].as_array().unwrap().is_empty());
+    }
+
+    #[test]
+    fn native_report_is_written_where_the_uploader_looks() {
+        let dir = std::env::temp_dir().join(format!("tsukimi-native-crash-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut prefix = dir.as_os_str().as_bytes().to_vec();
+        prefix.extend_from_slice(b"/native-crash-");
+
+        assert!(write_report(&prefix, 42, libc::SIGABRT, 0));
+        let path = dir.join("native-crash-42.json");
+        let report: serde_json::Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(report["message"], "Fatal signal 6 (SIGABRT) at 0x0");
+        assert!(!dir.join("native-crash-42.json.tmp").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn native_minidump_is_written_next_to_the_report() {
+        let dir = std::env::temp_dir().join(format!("tsukimi-native-minidump-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut prefix = dir.as_os_str().as_bytes().to_vec();
+        prefix.extend_from_slice(b"/native-crash-");
+
+        let crash = Crash { thread_id: minidump::current_thread_id(), signal: Some((libc::SIGSEGV, 1)), fault_address: 0x10, context: None };
+        let mut buf = vec![0u8; minidump::BUFFER_CAPACITY];
+        assert!(write_minidump(&prefix, 42, &mut buf, &crash));
+        let path = dir.join("native-crash-42.dmp");
+        assert_eq!(&std::fs::read(&path).unwrap()[..4], b"MDMP");
+        assert_eq!(upload_content_type(&path), Some("application/x-minidump"));
+        assert!(!dir.join("native-crash-42.dmp.tmp").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn overlong_paths_are_not_written() {
+        assert!(!write_report(&[b'a'; 600], 42, libc::SIGSEGV, 0));
+        let crash = Crash { thread_id: 0, signal: Some((libc::SIGSEGV, 1)), fault_address: 0, context: None };
+        assert!(!write_minidump(&[b'a'; 600], 42, &mut vec![0u8; minidump::BUFFER_CAPACITY], &crash));
+    }
+
+    #[test]
+    fn only_reports_and_minidumps_are_uploaded() {
+        assert_eq!(upload_content_type(Path::new("crashes/crash-1.json")), Some("application/json"));
+        assert_eq!(upload_content_type(Path::new("crashes/crash-1.dmp")), Some("application/x-minidump"));
+        assert_eq!(upload_content_type(Path::new("crashes/crash-1.dmp.tmp")), None);
+    }
+}
@@ -0,0 +1,311 @@
+use crate::config_system::config_main::{LogShippingConfig, LogSink};
+use crate::config_system::unit_identity::{unit_identity, UnitIdentity};
+#[cfg(feature = "server")]
+use crate::net_system::egress::http_client;
+use anyhow::{Context as _, Result};
+#[cfg(not(feature = "server"))]
+use anyhow::anyhow;
+use serde::Serialize;
+#[cfg(feature = "server")]
+use serde_json::json;
+use serde_json::{Map, Value};
+#[cfg(feature = "server")]
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tracing::field::{Field, Visit};
+use tracing::{info, warn, Event, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// 設定を読み込むまでの間に溜めておく最大件数（起動直後のログを失わないため）
+const STARTUP_BUFFER_CAPACITY: usize = 10000;
+
+/// クラッシュレポートに含める直近のログの件数
+const RECENT_CAPACITY: usize = 200;
+
+/// 送信処理自身のログを再び送らないよう除外するターゲット
+const IGNORED_TARGETS: &[&str] = &["hyper", "reqwest", "h2", "rustls", "native_tls"];
+
+/// 転送するログ1件
+#[derive(Debug, Clone, Serialize)]
+pub struct LogRecord {
+    /// UNIX時刻（ナノ秒）
+    pub timestamp_ns: u128,
+    pub level: String,
+    pub target: String,
+    /// イベントを囲むスパン名（外側から順に `:` 区切り）
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub spans: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "Map::is_empty")]
+    pub fields: Map<String, Value>,
+}
+
+/// 送信待ちのログ（容量を超えたら古いものから捨てる）
+///
+/// 転送の有無に関わらず、クラッシュレポート用に直近のログも保持する。
+pub struct LogBuffer {
+    records: Mutex<VecDeque<LogRecord>>,
+    recent: Mutex<VecDeque<LogRecord>>,
+    capacity: Mutex<usize>,
+    /// 転送が無効な場合は溜めない
+    accepting: AtomicBool,
+}
+
+impl LogBuffer {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            records: Mutex::new(VecDeque::new()),
+            recent: Mutex::new(VecDeque::with_capacity(RECENT_CAPACITY)),
+            capacity: Mutex::new(STARTUP_BUFFER_CAPACITY),
+            accepting: AtomicBool::new(true),
+        })
+    }
+
+    /// 転送を使わない場合に、溜めたログを捨てて以後の記録をやめる
+    pub fn disable(&self) {
+        self.accepting.store(false, Ordering::Relaxed);
+        self.records.lock().unwrap().clear();
+    }
+
+    fn set_capacity(&self, capacity: usize) {
+        *self.capacity.lock().unwrap() = capacity.max(1);
+    }
+
+    /// 直近のログ（古い順）
+    ///
+    /// パニックフックから呼ばれるため、ロックが取れない場合は待たずに空を返す。
+    pub fn recent(&self) -> Vec<LogRecord> {
+        match self.recent.try_lock() {
+            Ok(recent) => recent.iter().cloned().collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    fn push(&self, record: LogRecord) {
+        {
+            let mut recent = self.recent.lock().unwrap();
+            if recent.len() >= RECENT_CAPACITY {
+                recent.pop_front();
+            }
+            recent.push_back(record.clone());
+        }
+        if !self.accepting.load(Ordering::Relaxed) {
+            return;
+        }
+        let capacity = *self.capacity.lock().unwrap();
+        let mut records = self.records.lock().unwrap();
+        while records.len() >= capacity {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+
+    /// 先頭から最大 `max` 件を取り出す
+    fn take_batch(&self, max: usize) -> Vec<LogRecord> {
+        let mut records = self.records.lock().unwrap();
+        let count = records.len().min(max);
+        records.drain(..count).collect()
+    }
+
+    /// 送信に失敗したバッチを先頭に戻す（その間に溢れた分は古い方から捨てる）
+    fn restore_batch(&self, batch: Vec<LogRecord>) {
+        let capacity = *self.capacity.lock().unwrap();
+        let mut records = self.records.lock().unwrap();
+        for record in batch.into_iter().rev() {
+            if records.len() >= capacity {
+                break;
+            }
+            records.push_front(record);
+        }
+    }
+}
+
+/// イベントのフィールドをJSONに変換する
+#[derive(Default)]
+struct FieldVisitor {
+    message: String,
+    fields: Map<String, Value>,
+}
+
+impl Visit for FieldVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message = value.to_string();
+        } else {
+            self.fields.insert(field.name().to_string(), Value::from(value));
+        }
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.fields.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.fields.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.fields.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.fields.insert(field.name().to_string(), Value::from(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            self.message = format!("{:?}", value);
+        } else {
+            self.fields.insert(field.name().to_string(), Value::from(format!("{:?}", value)));
+        }
+    }
+}
+
+/// ログを `LogBuffer` に溜めるtracingのレイヤー
+pub struct LogShipperLayer {
+    buffer: Arc<LogBuffer>,
+}
+
+impl LogShipperLayer {
+    pub fn new(buffer: Arc<LogBuffer>) -> Self {
+        Self { buffer }
+    }
+}
+
+impl<S> Layer<S> for LogShipperLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        if IGNORED_TARGETS.iter().any(|ignored| metadata.target().starts_with(ignored)) {
+            return;
+        }
+
+        let mut visitor = FieldVisitor::default();
+        event.record(&mut visitor);
+        let spans = ctx
+            .event_scope(event)
+            .map(|scope| scope.from_root().map(|span| span.name()).collect::<Vec<_>>().join(":"))
+            .unwrap_or_default();
+
+        self.buffer.push(LogRecord {
+            timestamp_ns: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos(),
+            level: metadata.level().to_string().to_lowercase(),
+            target: metadata.target().to_string(),
+            spans,
+            message: visitor.message,
+            fields: visitor.fields,
+        });
+    }
+}
+
+/// Lokiのpush APIにログを送る（レベルごとにストリームを分ける）
+#[cfg(feature = "server")]
+async fn push_loki(client: &reqwest::Client, endpoint: &str, unit: &UnitIdentity, batch: &[LogRecord]) -> Result<()> {
+    let mut streams: BTreeMap<&str, Vec<Value>> = BTreeMap::new();
+    for record in batch {
+        let line = serde_json::to_string(record)?;
+        streams
+            .entry(record.level.as_str())
+            .or_default()
+            .push(json!([record.timestamp_ns.to_string(), line]));
+    }
+    let body = json!({
+        "streams": streams
+            .into_iter()
+            .map(|(level, values)| {
+                let mut stream = json!({ "app": "tsukimi-speaker", "unit": unit.unit_id, "level": level });
+                for (label, value) in [("venue", &unit.venue), ("zone", &unit.zone), ("hardware_revision", &unit.hardware_revision)] {
+                    if let Some(value) = value {
+                        stream[label] = Value::from(value.as_str());
+                    }
+                }
+                json!({ "stream": stream, "values": values })
+            })
+            .collect::<Vec<_>>(),
+    });
+    client
+        .post(endpoint)
+        .json(&body)
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
+
+/// TCPで1行1レコードのJSONを送る（接続は失敗するまで使い回す）
+async fn push_tcp(connection: &mut Option<TcpStream>, endpoint: &str, unit: &UnitIdentity, batch: &[LogRecord]) -> Result<()> {
+    let mut payload = Vec::new();
+    for record in batch {
+        let mut value = serde_json::to_value(record)?;
+        value["unit"] = Value::from(unit.unit_id.as_str());
+        value["labels"] = serde_json::to_value(unit)?;
+        serde_json::to_writer(&mut payload, &value)?;
+        payload.push(b'\n');
+    }
+
+    if connection.is_none() {
+        let stream = TcpStream::connect(endpoint)
+            .await
+            .with_context(|| format!("Failed to connect to log sink: {}", endpoint))?;
+        *connection = Some(stream);
+    }
+    let stream = connection.as_mut().expect("connection was just established");
+    if let Err(e) = stream.write_all(&payload).await {
+        *connection = None;
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+/// 溜めたログを集約先へ送り続けるタスク
+///
+/// 送信に失敗したログは手元に残し、間隔を延ばしながら再試行する。
+/// 会場のネットワークが落ちている間のログも、復旧後に送られる。
+pub async fn log_shipper_main(config: LogShippingConfig, buffer: Arc<LogBuffer>) {
+    buffer.set_capacity(config.buffer_capacity);
+    let unit = unit_identity();
+    info!(sink = ?config.sink, endpoint = %config.endpoint, unit_id = %unit.unit_id, "Log shipping started");
+
+    #[cfg(feature = "server")]
+    let client = http_client();
+    let mut connection: Option<TcpStream> = None;
+    let flush_interval = Duration::from_millis(config.flush_interval_ms.max(100));
+    let max_retry_interval = Duration::from_millis(config.max_retry_interval_ms).max(flush_interval);
+    let mut wait = flush_interval;
+
+    loop {
+        tokio::time::sleep(wait).await;
+        loop {
+            let batch = buffer.take_batch(config.batch_size.max(1));
+            if batch.is_empty() {
+                wait = flush_interval;
+                break;
+            }
+            let result = match config.sink {
+                #[cfg(feature = "server")]
+                LogSink::Loki => push_loki(&client, &config.endpoint, unit, &batch).await,
+                // HTTPクライアントを含まないビルドではTCPにしか送れない
+                #[cfg(not(feature = "server"))]
+                LogSink::Loki => Err(anyhow!("Loki sink is not available in a build without the `server` feature")),
+                LogSink::Tcp => push_tcp(&mut connection, &config.endpoint, unit, &batch).await,
+            };
+            if let Err(e) = result {
+                buffer.restore_batch(batch);
+                wait = (wait * 2).min(max_retry_interval);
+                warn!(retry_in_ms = wait.as_millis() as u64, "Failed to ship logs: {:?}", e);
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,760 @@
+use std::ffi::CStr;
+
+/// "MDMP"
+const SIGNATURE: u32 = 0x504d_444d;
+const VERSION: u32 = 0xa793;
+
+const THREAD_LIST_STREAM: u32 = 3;
+const MODULE_LIST_STREAM: u32 = 4;
+const EXCEPTION_STREAM: u32 = 6;
+const SYSTEM_INFO_STREAM: u32 = 7;
+const MISC_INFO_STREAM: u32 = 15;
+/// Breakpadの拡張（`/proc` の内容をそのまま入れる）
+const LINUX_CPU_INFO_STREAM: u32 = 0x4767_0003;
+const LINUX_PROC_STATUS_STREAM: u32 = 0x4767_0004;
+const LINUX_CMD_LINE_STREAM: u32 = 0x4767_0006;
+const LINUX_AUXV_STREAM: u32 = 0x4767_0008;
+const LINUX_MAPS_STREAM: u32 = 0x4767_0009;
+const STREAM_COUNT: usize = 10;
+
+const PLATFORM_LINUX: u32 = 0x8201;
+/// シグナル以外（パニック）で書いたダンプの例外コード（Breakpadの `MD_EXCEPTION_CODE_LIN_DUMP_REQUESTED`）
+const DUMP_REQUESTED: u32 = 0xffff_ffff;
+/// ELFのビルドIDを入れるCodeViewレコードの署名（"BpEL"）
+const CV_ELF_SIGNATURE: u32 = 0x4270_454c;
+const PT_NOTE: u32 = 4;
+const NT_GNU_BUILD_ID: u32 = 3;
+
+const HEADER_SIZE: usize = 32;
+const DIRECTORY_ENTRY_SIZE: usize = 12;
+const SYSTEM_INFO_SIZE: usize = 56;
+const MISC_INFO_SIZE: usize = 24;
+const EXCEPTION_STREAM_SIZE: usize = 168;
+const THREAD_SIZE: usize = 48;
+const MODULE_SIZE: usize = 108;
+/// 保存するモジュールの上限
+const MAX_MODULES: usize = 1024;
+/// 落ちたスレッドのスタックとして保存する上限
+const STACK_CAPACITY: u64 = 32 * 1024;
+/// スタックポインタより下に残す分（x86_64のレッドゾーン）
+const STACK_RED_ZONE: u64 = 128;
+/// `/proc/self/maps` の1行の上限（超える行のパスは切り詰める）
+const MAPS_LINE_CAPACITY: usize = 4096;
+
+/// 書き出しに使うバッファの大きさ（`/proc/self/maps` とモジュール一覧が収まる程度）
+pub const BUFFER_CAPACITY: usize = 4 * 1024 * 1024;
+
+/// ダンプに記録するクラッシュ
+pub struct Crash<'a> {
+    /// 落ちたスレッドのID（`gettid`）
+    pub thread_id: u32,
+    /// シグナル番号と `si_code`（パニックなら `None`）
+    pub signal: Option<(libc::c_int, libc::c_int)>,
+    /// 不正なアクセスのアドレス（`si_addr`、パニックなら0）
+    pub fault_address: u64,
+    /// 落ちた時点のレジスタ（取れない構成では `None`）
+    pub context: Option<&'a libc::ucontext_t>,
+}
+
+/// 呼び出したスレッドのID
+pub fn current_thread_id() -> u32 {
+    // SAFETY: gettidは引数を取らず、シグナルハンドラからも呼べる
+    unsafe { libc::syscall(libc::SYS_gettid) as u32 }
+}
+
+/// ストリームの位置（`MINIDUMP_LOCATION_DESCRIPTOR`）
+#[derive(Debug, Clone, Copy, Default)]
+struct Location {
+    size: u32,
+    rva: u32,
+}
+
+/// 固定長のバッファに書くWriter（溢れた場合は `None`）
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl Writer<'_> {
+    /// 8バイト境界に揃えてから `size` バイトを0で確保し、その位置を返す
+    fn reserve(&mut self, size: usize) -> Option<usize> {
+        let start = self.len.checked_next_multiple_of(8)?;
+        let end = start.checked_add(size).filter(|&end| end <= self.buf.len() && end <= u32::MAX as usize)?;
+        self.buf[self.len..end].fill(0);
+        self.len = end;
+        Some(start)
+    }
+
+    fn put_at(&mut self, offset: usize, bytes: &[u8]) {
+        self.buf[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn put_u16(&mut self, offset: usize, value: u16) {
+        self.put_at(offset, &value.to_le_bytes());
+    }
+
+    fn put_u32(&mut self, offset: usize, value: u32) {
+        self.put_at(offset, &value.to_le_bytes());
+    }
+
+    fn put_u64(&mut self, offset: usize, value: u64) {
+        self.put_at(offset, &value.to_le_bytes());
+    }
+
+    fn put_location(&mut self, offset: usize, location: Location) {
+        self.put_u32(offset, location.size);
+        self.put_u32(offset + 4, location.rva);
+    }
+
+    fn location(&self, start: usize) -> Location {
+        Location { size: (self.len - start) as u32, rva: start as u32 }
+    }
+
+    /// `len` 文字の `MINIDUMP_STRING`（UTF-16）を書き、その位置を返す
+    ///
+    /// `byte_at` はバッファとi文字目を受け取ってその文字を返す（バッファ内のパスもそのまま写せるように）。
+    /// ASCII以外は `?` にする。
+    fn put_string(&mut self, len: usize, byte_at: impl Fn(&[u8], usize) -> u8) -> Option<u32> {
+        let offset = self.reserve(4 + len * 2 + 2)?;
+        self.put_u32(offset, (len * 2) as u32);
+        for i in 0..len {
+            let byte = byte_at(self.buf, i);
+            self.put_u16(offset + 4 + i * 2, if byte.is_ascii() { byte as u16 } else { b'?' as u16 });
+        }
+        Some(offset as u32)
+    }
+
+    /// ファイルの中身をそのまま書く（読めなければ空、入り切らなければ切り詰める）
+    fn put_file(&mut self, path: &CStr) -> Location {
+        let Some(start) = self.reserve(0) else {
+            return Location::default();
+        };
+        // SAFETY: パスはNUL終端済みで、open/read/closeはシグナルハンドラから呼べる
+        unsafe {
+            let fd = libc::open(path.as_ptr(), libc::O_RDONLY | libc::O_CLOEXEC);
+            if fd >= 0 {
+                while self.len < self.buf.len() {
+                    let rest = &mut self.buf[self.len..];
+                    let read = libc::read(fd, rest.as_mut_ptr().cast(), rest.len());
+                    if read <= 0 {
+                        break;
+                    }
+                    self.len += read as usize;
+                }
+                libc::close(fd);
+            }
+        }
+        self.location(start)
+    }
+}
+
+/// プロセス自身のメモリを `/proc/self/mem` から読む（解放済みの領域を読んでもクラッシュしない）
+struct Memory {
+    fd: libc::c_int,
+}
+
+impl Memory {
+    fn open() -> Option<Self> {
+        // SAFETY: パスはNUL終端済みで、openはシグナルハンドラから呼べる
+        let fd = unsafe { libc::open(c"/proc/self/mem".as_ptr(), libc::O_RDONLY | libc::O_CLOEXEC) };
+        (fd >= 0).then_some(Self { fd })
+    }
+
+    fn read(&self, address: u64, out: &mut [u8]) -> bool {
+        let Ok(offset) = libc::off64_t::try_from(address) else {
+            return false;
+        };
+        // SAFETY: `out` の長さだけ書き込ませる
+        let read = unsafe { libc::pread64(self.fd, out.as_mut_ptr().cast(), out.len(), offset) };
+        read == out.len() as isize
+    }
+}
+
+impl Drop for Memory {
+    fn drop(&mut self) {
+        // SAFETY: openで得たfdを一度だけ閉じる
+        unsafe { libc::close(self.fd) };
+    }
+}
+
+/// `/proc/self/maps` の1行
+#[derive(Debug, Clone, Copy)]
+struct Mapping {
+    start: u64,
+    end: u64,
+    offset: u64,
+    inode: u64,
+    /// 行の中のパスの範囲（無ければ空）
+    path: (usize, usize),
+}
+
+fn parse_hex(field: &[u8]) -> Option<u64> {
+    std::str::from_utf8(field).ok().and_then(|field| u64::from_str_radix(field, 16).ok())
+}
+
+/// `start-end perms offset dev inode path` を読む
+fn parse_mapping(line: &[u8]) -> Option<Mapping> {
+    let mut fields = line.split(|&b| b == b' ').filter(|field| !field.is_empty());
+    let (start, end) = fields.next()?.split_at(line.iter().position(|&b| b == b'-')?);
+    let offset = parse_hex(fields.nth(1)?)?;
+    let inode = std::str::from_utf8(fields.nth(1)?).ok()?.parse().ok()?;
+    let path = match line.iter().position(|&b| b == b'/') {
+        Some(path_start) => (path_start, line.len()),
+        None => (line.len(), line.len()),
+    };
+    Some(Mapping { start: parse_hex(start)?, end: parse_hex(&end[1..])?, offset, inode, path })
+}
+
+/// `maps` の `pos` から次の行を読み、次の行の位置と合わせて返す（読めない行は飛ばす）
+fn next_mapping(buf: &[u8], maps: Location, mut pos: usize) -> Option<(usize, Mapping)> {
+    let end = maps.rva as usize + maps.size as usize;
+    while pos < end {
+        let line_end = buf[pos..end].iter().position(|&b| b == b'\n').map_or(end, |i| pos + i);
+        let line = &buf[pos..line_end.min(pos + MAPS_LINE_CAPACITY)];
+        let mapping = parse_mapping(line).map(|mapping| Mapping { path: (pos + mapping.path.0, pos + mapping.path.1), ..mapping });
+        pos = line_end + 1;
+        if let Some(mapping) = mapping {
+            return Some((pos, mapping));
+        }
+    }
+    None
+}
+
+/// `maps` の `pos` 以降で最初のモジュール（ファイルの先頭から続けて読み込まれたマッピングの範囲）と、その次の位置を返す
+///
+/// `path` はモジュールの最初の行のパスの範囲。
+fn next_module(buf: &[u8], maps: Location, mut pos: usize) -> Option<(usize, Mapping)> {
+    loop {
+        let (next, mapping) = next_mapping(buf, maps, pos)?;
+        pos = next;
+        if mapping.offset != 0 || mapping.inode == 0 || mapping.path.0 == mapping.path.1 {
+            continue;
+        }
+        let mut module = mapping;
+        while let Some((next, following)) = next_mapping(buf, maps, pos) {
+            if following.inode != module.inode || following.start < module.end {
+                break;
+            }
+            module.end = following.end;
+            pos = next;
+        }
+        return Some((pos, module));
+    }
+}
+
+fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+/// GNUのノート（`NT_GNU_BUILD_ID`）を探し、ビルドIDを `out` に写して長さを返す
+fn find_build_id(notes: &[u8], out: &mut [u8; 64]) -> Option<usize> {
+    let mut pos = 0;
+    while pos + 12 <= notes.len() {
+        let name_size = u32_at(notes, pos) as usize;
+        let desc_size = u32_at(notes, pos + 4) as usize;
+        let kind = u32_at(notes, pos + 8);
+        let desc_start = pos + 12 + name_size.next_multiple_of(4);
+        let desc_end = desc_start.checked_add(desc_size)?;
+        if desc_end > notes.len() {
+            return None;
+        }
+        if kind == NT_GNU_BUILD_ID && notes[pos + 12..pos + 12 + name_size] == *b"GNU\0" {
+            let len = desc_size.min(out.len());
+            out[..len].copy_from_slice(&notes[desc_start..desc_start + len]);
+            return Some(len);
+        }
+        pos = desc_end.next_multiple_of(4);
+    }
+    None
+}
+
+/// メモリに読み込まれたELFのビルドIDを読む（ノートはファイルの先頭から続けて読み込まれた `base..base+len` の中にあるものだけ見る）
+fn read_build_id(memory: &Memory, base: u64, len: u64, out: &mut [u8; 64]) -> Option<usize> {
+    let mut header = [0u8; 64];
+    if !memory.read(base, &mut header) || header[..4] != *b"\x7fELF" || header[5] != 1 {
+        return None;
+    }
+    let is_64 = header[4] == 2;
+    let (ph_offset, ph_entry_size, ph_count) = if is_64 {
+        (u64_at(&header, 32), u16_at(&header, 54) as u64, u16_at(&header, 56))
+    } else {
+        (u32_at(&header, 28) as u64, u16_at(&header, 42) as u64, u16_at(&header, 44))
+    };
+    for i in 0..ph_count.min(64) as u64 {
+        let mut program_header = [0u8; 56];
+        let program_header = &mut program_header[..if is_64 { 56 } else { 32 }];
+        if !memory.read(base + ph_offset + i * ph_entry_size, program_header) || u32_at(program_header, 0) != PT_NOTE {
+            continue;
+        }
+        let (offset, size) = if is_64 {
+            (u64_at(program_header, 8), u64_at(program_header, 32))
+        } else {
+            (u32_at(program_header, 4) as u64, u32_at(program_header, 16) as u64)
+        };
+        let mut notes = [0u8; 1024];
+        let Some(notes) = notes.get_mut(..size as usize) else {
+            continue;
+        };
+        if offset + size <= len && memory.read(base + offset, notes) {
+            if let Some(len) = find_build_id(notes, out) {
+                return Some(len);
+            }
+        }
+    }
+    None
+}
+
+
+/// 汎用レジスタを書く。位置と、スタックポインタ・プログラムカウンタを返す
+#[cfg(target_arch = "x86_64")]
+mod context {
+    use super::{Location, Writer};
+
+    pub(super) const PROCESSOR_ARCHITECTURE: u16 = 9;
+    const CONTEXT_SIZE: usize = 1232;
+    /// `CONTEXT_AMD64 | CONTEXT_CONTROL | CONTEXT_INTEGER`
+    const CONTEXT_FLAGS: u32 = 0x0010_0003;
+    /// `CONTEXT_AMD64` の `Rax` から `R15` の並び
+    const REGISTERS: [libc::c_int; 16] = [
+        libc::REG_RAX,
+        libc::REG_RCX,
+        libc::REG_RDX,
+        libc::REG_RBX,
+        libc::REG_RSP,
+        libc::REG_RBP,
+        libc::REG_RSI,
+        libc::REG_RDI,
+        libc::REG_R8,
+        libc::REG_R9,
+        libc::REG_R10,
+        libc::REG_R11,
+        libc::REG_R12,
+        libc::REG_R13,
+        libc::REG_R14,
+        libc::REG_R15,
+    ];
+
+    pub(super) fn write(writer: &mut Writer<'_>, context: &libc::ucontext_t) -> Option<(Location, u64, u64)> {
+        let start = writer.reserve(CONTEXT_SIZE)?;
+        let registers = &context.uc_mcontext.gregs;
+        writer.put_u32(start + 48, CONTEXT_FLAGS);
+        writer.put_u16(start + 56, registers[libc::REG_CSGSFS as usize] as u16);
+        writer.put_u32(start + 68, registers[libc::REG_EFL as usize] as u32);
+        for (i, register) in REGISTERS.iter().enumerate() {
+            writer.put_u64(start + 120 + i * 8, registers[*register as usize] as u64);
+        }
+        let pc = registers[libc::REG_RIP as usize] as u64;
+        writer.put_u64(start + 248, pc);
+        Some((writer.location(start), registers[libc::REG_RSP as usize] as u64, pc))
+    }
+}
+
+/// 汎用レジスタを書く。位置と、スタックポインタ・プログラムカウンタを返す
+#[cfg(target_arch = "aarch64")]
+mod context {
+    use super::{Location, Writer};
+
+    pub(super) const PROCESSOR_ARCHITECTURE: u16 = 12;
+    const CONTEXT_SIZE: usize = 912;
+    /// `CONTEXT_ARM64 | CONTEXT_CONTROL | CONTEXT_INTEGER`
+    const CONTEXT_FLAGS: u32 = 0x0040_0003;
+
+    pub(super) fn write(writer: &mut Writer<'_>, context: &libc::ucontext_t) -> Option<(Location, u64, u64)> {
+        let start = writer.reserve(CONTEXT_SIZE)?;
+        let registers = &context.uc_mcontext;
+        writer.put_u32(start, CONTEXT_FLAGS);
+        writer.put_u32(start + 4, registers.pstate as u32);
+        for (i, register) in registers.regs.iter().enumerate() {
+            writer.put_u64(start + 8 + i * 8, *register);
+        }
+        writer.put_u64(start + 256, registers.sp);
+        writer.put_u64(start + 264, registers.pc);
+        Some((writer.location(start), registers.sp, registers.pc))
+    }
+}
+
+/// 汎用レジスタを書く。位置と、スタックポインタ・プログラムカウンタを返す
+#[cfg(target_arch = "arm")]
+mod context {
+    use super::{Location, Writer};
+
+    pub(super) const PROCESSOR_ARCHITECTURE: u16 = 5;
+    const CONTEXT_SIZE: usize = 368;
+    /// `CONTEXT_ARM | CONTEXT_INTEGER`
+    const CONTEXT_FLAGS: u32 = 0x4000_0002;
+
+    pub(super) fn write(writer: &mut Writer<'_>, context: &libc::ucontext_t) -> Option<(Location, u64, u64)> {
+        let start = writer.reserve(CONTEXT_SIZE)?;
+        let r = &context.uc_mcontext;
+        let registers = [
+            r.arm_r0, r.arm_r1, r.arm_r2, r.arm_r3, r.arm_r4, r.arm_r5, r.arm_r6, r.arm_r7, r.arm_r8, r.arm_r9, r.arm_r10, r.arm_fp, r.arm_ip, r.arm_sp, r.arm_lr,
+            r.arm_pc,
+        ];
+        writer.put_u32(start, CONTEXT_FLAGS);
+        for (i, register) in registers.iter().enumerate() {
+            writer.put_u32(start + 4 + i * 4, *register as u32);
+        }
+        writer.put_u32(start + 68, r.arm_cpsr as u32);
+        Some((writer.location(start), r.arm_sp as u64, r.arm_pc as u64))
+    }
+}
+
+/// レジスタの形式を知らない構成（スレッドのレジスタとスタックは書かない）
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64", target_arch = "arm")))]
+mod context {
+    use super::{Location, Writer};
+
+    /// `PROCESSOR_ARCHITECTURE_UNKNOWN`
+    pub(super) const PROCESSOR_ARCHITECTURE: u16 = 0xffff;
+
+    pub(super) fn write(_writer: &mut Writer<'_>, _context: &libc::ucontext_t) -> Option<(Location, u64, u64)> {
+        None
+    }
+}
+
+/// 呼び出した時点のレジスタを `context` に取る（`getcontext` の無い構成では `false`）
+pub fn capture_context(context: &mut libc::ucontext_t) -> bool {
+    #[cfg(all(target_env = "gnu", any(target_arch = "x86_64", target_arch = "aarch64")))]
+    // SAFETY: `context` は書き込み可能な `ucontext_t`
+    return unsafe { libc::getcontext(context) } == 0;
+    #[cfg(not(all(target_env = "gnu", any(target_arch = "x86_64", target_arch = "aarch64"))))]
+    {
+        let _ = context;
+        false
+    }
+}
+
+/// `MINIDUMP_SYSTEM_INFO`（CPUの種類・数とカーネルのバージョン）
+fn write_system_info(writer: &mut Writer<'_>) -> Option<Location> {
+    // SAFETY: uname/sysconfはシグナルハンドラから呼べる
+    let (uname, processors) = unsafe {
+        let mut uname = std::mem::zeroed::<libc::utsname>();
+        libc::uname(&mut uname);
+        (uname, libc::sysconf(libc::_SC_NPROCESSORS_ONLN))
+    };
+    // `6.6.31+rpt-rpi-v8` のような表記から数字の部分だけを読む
+    let mut version = [0u32; 3];
+    let mut part = 0;
+    for &c in uname.release.iter().take_while(|&&c| c != 0) {
+        match c as u8 {
+            digit @ b'0'..=b'9' => version[part] = version[part].saturating_mul(10).saturating_add((digit - b'0') as u32),
+            b'.' if part < 2 => part += 1,
+            _ => break,
+        }
+    }
+    // `MINIDUMP_SYSTEM_INFO` の `CSDVersion` に入れる `<release> <version> <machine>`
+    let mut description = [0u8; 3 * 65 + 2];
+    let mut description_len = 0;
+    for field in [&uname.release[..], &uname.version[..], &uname.machine[..]] {
+        if description_len > 0 {
+            description[description_len] = b' ';
+            description_len += 1;
+        }
+        for &c in field.iter().take_while(|&&c| c != 0).take(description.len() - description_len) {
+            description[description_len] = c as u8;
+            description_len += 1;
+        }
+    }
+
+    let start = writer.reserve(SYSTEM_INFO_SIZE)?;
+    writer.put_u16(start, context::PROCESSOR_ARCHITECTURE);
+    writer.buf[start + 6] = processors.clamp(0, u8::MAX as libc::c_long) as u8;
+    writer.put_u32(start + 8, version[0]);
+    writer.put_u32(start + 12, version[1]);
+    writer.put_u32(start + 16, version[2]);
+    writer.put_u32(start + 20, PLATFORM_LINUX);
+    let location = writer.location(start);
+    let description = writer.put_string(description_len, |_, i| description[i])?;
+    writer.put_u32(start + 24, description);
+    Some(location)
+}
+
+/// `MINIDUMP_MISC_INFO`（プロセスID）
+fn write_misc_info(writer: &mut Writer<'_>) -> Option<Location> {
+    let start = writer.reserve(MISC_INFO_SIZE)?;
+    writer.put_u32(start, MISC_INFO_SIZE as u32);
+    // `MINIDUMP_MISC1_PROCESS_ID`
+    writer.put_u32(start + 4, 1);
+    // SAFETY: getpidはシグナルハンドラから呼べる
+    writer.put_u32(start + 8, unsafe { libc::getpid() } as u32);
+    Some(writer.location(start))
+}
+
+/// スタックポインタから、そのスタックのマッピングの終わりまで（上限 `STACK_CAPACITY`）を書く
+fn write_stack(writer: &mut Writer<'_>, maps: Location, memory: &Memory, sp: u64) -> Option<(u64, Location)> {
+    let mut pos = maps.rva as usize;
+    let mapping = loop {
+        let (next, mapping) = next_mapping(writer.buf, maps, pos)?;
+        if (mapping.start..mapping.end).contains(&sp) {
+            break mapping;
+        }
+        pos = next;
+    };
+    let start = sp.saturating_sub(STACK_RED_ZONE).max(mapping.start);
+    let end = mapping.end.min(start + STACK_CAPACITY);
+    let offset = writer.reserve((end - start) as usize)?;
+    if !memory.read(start, &mut writer.buf[offset..writer.len]) {
+        writer.len = offset;
+        return None;
+    }
+    Some((start, writer.location(offset)))
+}
+
+/// `MINIDUMP_THREAD_LIST`（落ちたスレッドだけ）
+fn write_thread_list(writer: &mut Writer<'_>, crash: &Crash<'_>, context: Option<(Location, u64, u64)>, maps: Location, memory: Option<&Memory>) -> Option<Location> {
+    let stack = match (context, memory) {
+        (Some((_, sp, _)), Some(memory)) => write_stack(writer, maps, memory, sp),
+        _ => None,
+    };
+    let start = writer.reserve(4 + THREAD_SIZE)?;
+    writer.put_u32(start, 1);
+    let thread = start + 4;
+    writer.put_u32(thread, crash.thread_id);
+    if let Some((stack_start, stack)) = stack {
+        writer.put_u64(thread + 24, stack_start);
+        writer.put_location(thread + 32, stack);
+    }
+    if let Some((context, _, _)) = context {
+        writer.put_location(thread + 40, context);
+    }
+    Some(writer.location(start))
+}
+
+/// `MINIDUMP_EXCEPTION_STREAM`（シグナル、またはパニックでの書き出し）
+fn write_exception(writer: &mut Writer<'_>, crash: &Crash<'_>, context: Option<(Location, u64, u64)>) -> Option<Location> {
+    let start = writer.reserve(EXCEPTION_STREAM_SIZE)?;
+    writer.put_u32(start, crash.thread_id);
+    let (code, flags, address) = match crash.signal {
+        Some((signal, code)) => (signal as u32, code as u32, crash.fault_address),
+        None => (DUMP_REQUESTED, 0, context.map_or(0, |(_, _, pc)| pc)),
+    };
+    writer.put_u32(start + 8, code);
+    writer.put_u32(start + 12, flags);
+    writer.put_u64(start + 24, address);
+    if let Some((context, _, _)) = context {
+        writer.put_location(start + 160, context);
+    }
+    Some(writer.location(start))
+}
+
+/// `MINIDUMP_MODULE_LIST`（読み込まれているELFと、そのビルドID）
+fn write_module_list(writer: &mut Writer<'_>, maps: Location, memory: Option<&Memory>) -> Option<Location> {
+    let mut count = 0;
+    let mut pos = maps.rva as usize;
+    while let Some((next, _)) = next_module(writer.buf, maps, pos).filter(|_| count < MAX_MODULES) {
+        count += 1;
+        pos = next;
+    }
+
+    let start = writer.reserve(4 + count * MODULE_SIZE)?;
+    writer.put_u32(start, count as u32);
+    let mut pos = maps.rva as usize;
+    for i in 0..count {
+        let (next, module) = next_module(writer.buf, maps, pos)?;
+        pos = next;
+        let entry = start + 4 + i * MODULE_SIZE;
+        writer.put_u64(entry, module.start);
+        writer.put_u32(entry + 8, (module.end - module.start) as u32);
+        let (path_start, path_end) = module.path;
+        let name = writer.put_string(path_end - path_start, |buf, i| buf[path_start + i])?;
+        writer.put_u32(entry + 20, name);
+
+        let mut build_id = [0u8; 64];
+        let build_id_len = memory.and_then(|memory| read_build_id(memory, module.start, module.end - module.start, &mut build_id));
+        if let Some(len) = build_id_len {
+            let record = writer.reserve(4 + len)?;
+            writer.put_u32(record, CV_ELF_SIGNATURE);
+            writer.put_at(record + 4, &build_id[..len]);
+            writer.put_location(entry + 76, writer.location(record));
+        }
+    }
+    Some(writer.location(start))
+}
+
+/// ミニダンプ（Breakpad・rust-minidumpの形式）を `buf` に書き、書いた長さを返す（入り切らなければ `None`）
+///
+/// シグナルハンドラの中からも呼べるよう、メモリの確保・ロックはせず、`/proc` のファイルと
+/// プロセス自身のメモリ（`/proc/self/mem`）を読むだけにしている。
+/// スレッドは落ちたものだけを、そのレジスタとスタックの先頭 `STACK_CAPACITY` バイトと合わせて書く。
+pub fn write(buf: &mut [u8], crash: &Crash<'_>) -> Option<usize> {
+    let mut writer = Writer { buf, len: 0 };
+    let header = writer.reserve(HEADER_SIZE + STREAM_COUNT * DIRECTORY_ENTRY_SIZE)?;
+    let maps = writer.put_file(c"/proc/self/maps");
+    let memory = Memory::open();
+    let context = crash.context.and_then(|context| context::write(&mut writer, context));
+    let streams: [(u32, Location); STREAM_COUNT] = [
+        (SYSTEM_INFO_STREAM, write_system_info(&mut writer)?),
+        (MISC_INFO_STREAM, write_misc_info(&mut writer)?),
+        (THREAD_LIST_STREAM, write_thread_list(&mut writer, crash, context, maps, memory.as_ref())?),
+        (EXCEPTION_STREAM, write_exception(&mut writer, crash, context)?),
+        (MODULE_LIST_STREAM, write_module_list(&mut writer, maps, memory.as_ref())?),
+        (LINUX_MAPS_STREAM, maps),
+        (LINUX_CPU_INFO_STREAM, writer.put_file(c"/proc/cpuinfo")),
+        (LINUX_PROC_STATUS_STREAM, writer.put_file(c"/proc/self/status")),
+        (LINUX_CMD_LINE_STREAM, writer.put_file(c"/proc/self/cmdline")),
+        (LINUX_AUXV_STREAM, writer.put_file(c"/proc/self/auxv")),
+    ];
+
+    writer.put_u32(header, SIGNATURE);
+    writer.put_u32(header + 4, VERSION);
+    writer.put_u32(header + 8, STREAM_COUNT as u32);
+    writer.put_u32(header + 12, HEADER_SIZE as u32);
+    // SAFETY: timeはシグナルハンドラから呼べる
+    writer.put_u32(header + 20, unsafe { libc::time(std::ptr::null_mut()) } as u32);
+    for (i, (kind, location)) in streams.into_iter().enumerate() {
+        let entry = header + HEADER_SIZE + i * DIRECTORY_ENTRY_SIZE;
+        writer.put_u32(entry, kind);
+        writer.put_location(entry + 4, location);
+    }
+    Some(writer.len)
+}
+
+/// ミニダンプを `buf` に書き、一時ファイル `tmp_path` を経て `path` に書き出す（シグナルハンドラの中からも呼べる）
+pub fn write_file(buf: &mut [u8], crash: &Crash<'_>, tmp_path: &CStr, path: &CStr) -> bool {
+    let Some(len) = write(buf, crash) else {
+        return false;
+    };
+    // SAFETY: パスはNUL終端済みで、open/write/close/renameはシグナルハンドラから呼べる
+    unsafe {
+        let fd = libc::open(tmp_path.as_ptr(), libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC | libc::O_CLOEXEC, 0o644);
+        if fd < 0 {
+            return false;
+        }
+        let mut written = 0;
+        while written < len {
+            let n = libc::write(fd, buf[written..len].as_ptr().cast(), len - written);
+            if n <= 0 {
+                break;
+            }
+            written += n as usize;
+        }
+        libc::close(fd);
+        written == len && libc::rename(tmp_path.as_ptr(), path.as_ptr()) == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    /// ストリームの種類から、その中身を探す
+    fn stream(dump: &[u8], kind: u32) -> &[u8] {
+        let directory = u32_at(dump, 12) as usize;
+        let entry = (0..u32_at(dump, 8) as usize)
+            .map(|i| directory + i * DIRECTORY_ENTRY_SIZE)
+            .find(|&entry| u32_at(dump, entry) == kind)
+            .unwrap();
+        let (size, rva) = (u32_at(dump, entry + 4) as usize, u32_at(dump, entry + 8) as usize);
+        &dump[rva..rva + size]
+    }
+
+    fn string_at(dump: &[u8], rva: usize) -> String {
+        let units: Vec<u16> = (0..u32_at(dump, rva) as usize / 2).map(|i| u16_at(dump, rva + 4 + i * 2)).collect();
+        String::from_utf16(&units).unwrap()
+    }
+
+    /// パニック時と同じく、呼び出したスレッドのダンプを書く
+    fn dump_current_thread() -> Vec<u8> {
+        // SAFETY: ucontext_tはすべて0でもよい
+        let mut context = unsafe { std::mem::zeroed::<libc::ucontext_t>() };
+        let captured = capture_context(&mut context);
+        let crash = Crash { thread_id: current_thread_id(), signal: None, fault_address: 0, context: captured.then_some(&context) };
+        let mut buf = vec![0u8; BUFFER_CAPACITY];
+        let len = write(&mut buf, &crash).unwrap();
+        buf.truncate(len);
+        buf
+    }
+
+    #[test]
+    fn dump_has_the_header_and_the_process_streams() {
+        let dump = dump_current_thread();
+        assert_eq!(u32_at(&dump, 0), SIGNATURE);
+        assert_eq!(u32_at(&dump, 4), VERSION);
+        assert_eq!(u32_at(&dump, 8), STREAM_COUNT as u32);
+
+        let system_info = stream(&dump, SYSTEM_INFO_STREAM);
+        assert_eq!(u16_at(system_info, 0), context::PROCESSOR_ARCHITECTURE);
+        assert!(system_info[6] >= 1);
+        assert_eq!(u32_at(system_info, 20), PLATFORM_LINUX);
+        assert!(!string_at(&dump, u32_at(system_info, 24) as usize).is_empty());
+        assert_eq!(u32_at(stream(&dump, MISC_INFO_STREAM), 8), std::process::id());
+        assert!(std::str::from_utf8(stream(&dump, LINUX_MAPS_STREAM)).unwrap().contains("[stack]"));
+        assert!(!stream(&dump, LINUX_CMD_LINE_STREAM).is_empty());
+    }
+
+    #[test]
+    fn panic_dump_records_the_thread_with_its_registers_and_stack() {
+        let local = std::hint::black_box(0u8);
+        let dump = dump_current_thread();
+        let exception = stream(&dump, EXCEPTION_STREAM);
+        assert_eq!(u32_at(exception, 0), current_thread_id());
+        assert_eq!(u32_at(exception, 8), DUMP_REQUESTED);
+        let threads = stream(&dump, THREAD_LIST_STREAM);
+        assert_eq!(u32_at(threads, 0), 1);
+        assert_eq!(u32_at(threads, 4), current_thread_id());
+
+        if cfg!(all(target_env = "gnu", any(target_arch = "x86_64", target_arch = "aarch64"))) {
+            assert!(u32_at(threads, 44) > 0);
+            assert_eq!(u32_at(exception, 160), u32_at(threads, 44));
+            // 呼び出し元のフレームの変数まで保存されている
+            let (stack_start, stack_size, stack_rva) = (u64_at(threads, 28), u32_at(threads, 36) as usize, u32_at(threads, 40) as usize);
+            let address = &local as *const u8 as u64;
+            assert!((stack_start..stack_start + stack_size as u64).contains(&address));
+            assert_eq!(dump[stack_rva + (address - stack_start) as usize], local);
+        }
+    }
+
+    #[test]
+    fn module_list_names_the_loaded_binaries() {
+        let dump = dump_current_thread();
+        let modules = stream(&dump, MODULE_LIST_STREAM);
+        let entries: Vec<usize> = (0..u32_at(modules, 0) as usize).map(|i| 4 + i * MODULE_SIZE).collect();
+        let exe = std::env::current_exe().unwrap();
+        assert!(entries.iter().any(|&entry| Path::new(&string_at(&dump, u32_at(modules, entry + 20) as usize)) == exe));
+        // 共有ライブラリ（libcなど）にはビルドIDが付いている
+        assert!(entries.iter().any(|&entry| {
+            let (size, rva) = (u32_at(modules, entry + 76) as usize, u32_at(modules, entry + 80) as usize);
+            size > 4 && u32_at(&dump, rva) == CV_ELF_SIGNATURE
+        }));
+    }
+
+    #[test]
+    fn maps_lines_and_build_id_notes_are_parsed() {
+        let line = b"563f5a529000-563f5a52e000 r-xp 00002000 fe:00 317563                     /usr/bin/cat";
+        let mapping = parse_mapping(line).unwrap();
+        assert_eq!((mapping.start, mapping.end, mapping.offset, mapping.inode), (0x563f_5a52_9000, 0x563f_5a52_e000, 0x2000, 317_563));
+        assert_eq!(&line[mapping.path.0..mapping.path.1], b"/usr/bin/cat");
+        let anonymous = parse_mapping(b"7ffd10000000-7ffd10021000 rw-p 00000000 00:00 0                          [stack]").unwrap();
+        assert_eq!((anonymous.inode, anonymous.path.0 == anonymous.path.1), (0, true));
+
+        // GNU以外のノートは飛ばす
+        let mut notes = Vec::new();
+        for (name, kind, desc) in [(&b"Go\0\0"[..], 4u32, &b"abcdef"[..]), (&b"GNU\0"[..], NT_GNU_BUILD_ID, &[0xde, 0xad, 0xbe, 0xef][..])] {
+            notes.extend_from_slice(&(name.len() as u32).to_le_bytes());
+            notes.extend_from_slice(&(desc.len() as u32).to_le_bytes());
+            notes.extend_from_slice(&kind.to_le_bytes());
+            notes.extend_from_slice(name);
+            notes.extend_from_slice(desc);
+            notes.resize(notes.len().next_multiple_of(4), 0);
+        }
+        let mut build_id = [0u8; 64];
+        assert_eq!(find_build_id(&notes, &mut build_id), Some(4));
+        assert_eq!(build_id[..4], [0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(find_build_id(&notes[..notes.len() - 4], &mut build_id), None);
+    }
+
+    #[test]
+    fn dump_that_does_not_fit_is_not_written() {
+        let crash = Crash { thread_id: current_thread_id(), signal: Some((libc::SIGSEGV, 1)), fault_address: 0x10, context: None };
+        assert_eq!(write(&mut [0u8; 4096], &crash), None);
+    }
+}
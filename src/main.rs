@@ -1,30 +1,71 @@
-mod audio_system;
-mod bluetooth_system;
-mod connect_system;
-pub mod proto;
-
-use crate::audio_system::audio_main::audio_main;
-use crate::bluetooth_system::bluetooth_main::bluetooth_scanner;
-use crate::connect_system::connect_main::{connect_main, SystemEnabledState};
-use crate::proto::proto::SoundSetting;
+use tsukimi_speaker::api_system::api_main::api_main;
+use tsukimi_speaker::audio_system::audio_main::{AudioChannels, AudioContext, SharedReceiver};
+use tsukimi_speaker::audio_system::audio_sink::configured_bgm_sink;
+use tsukimi_speaker::audio_system::audio_supervisor::audio_supervisor;
+use tsukimi_speaker::audio_system::test_tone::{play_test_tone, TestToneRequest};
+use tsukimi_speaker::bluetooth_system::address::Address;
+#[cfg(feature = "ble")]
+use tsukimi_speaker::bluetooth_system::bluetooth_main::BleScanner;
+use tsukimi_speaker::bluetooth_system::device_source::{DeviceSource, SimulatedSource};
+#[cfg(feature = "server")]
+use tsukimi_speaker::connect_system::calibration::{CalibrationStore, DEFAULT_CALIBRATION_PATH};
+#[cfg(feature = "server")]
+use tsukimi_speaker::connect_system::connect_main::{ServerConnection, StatePaths};
+use tsukimi_speaker::connect_system::server_link::ServerLink;
+#[cfg(not(feature = "server"))]
+use tsukimi_speaker::connect_system::server_link::Standalone;
+use tsukimi_speaker::config_system::config_store::ConfigStore;
+use tsukimi_speaker::config_system::unit_identity::{self, unit_identity};
+use tsukimi_speaker::connect_system::sound_map::SharedSoundMap;
+use tsukimi_speaker::connect_system::enable_state;
+#[cfg(feature = "server")]
+use tsukimi_speaker::connect_system::fake_server;
+use tsukimi_speaker::log_system::crash_report::{install_panic_hook, upload_pending_reports};
+use tsukimi_speaker::log_system::log_shipper::{log_shipper_main, LogBuffer, LogShipperLayer};
+use tsukimi_speaker::metrics_system::drift_correction_bench;
+use tsukimi_speaker::metrics_system::metrics_main::metrics;
+use tsukimi_speaker::dmx_system::dmx_main::dmx_main;
+use tsukimi_speaker::osc_system::osc_main::osc_main;
+use tsukimi_speaker::peer_system::peer_main::peer_main;
+use tsukimi_speaker::peer_system::visitor_fusion::VisitorFusion;
+use tsukimi_speaker::clock_system::clock_main::{watch_system_ntp, Clock};
+use tsukimi_speaker::event_system::event_bus::EventBus;
+use tsukimi_speaker::forwarding_system::forward_control::forward_controller;
+use tsukimi_speaker::forwarding_system::forwarding_main::forwarding_main;
+use tsukimi_speaker::shutdown_system::shutdown_controller::{join_or_abort, run_with_shutdown_timeout, termination_signal, ShutdownController, ShutdownPhase};
+use tsukimi_speaker::survey_system::survey_main::{survey_main, Survey};
+#[cfg(feature = "server")]
+use tsukimi_speaker::webhook_system::webhook_main::webhook_main;
+use tsukimi_speaker::audio_system::audio_main::SoundSetting;
+use tsukimi_speaker::messages::{BgmOverrideRequest, ConnectionState, DeviceInfo, DeviceSnapshot, MixerCommand, SePlayRequest};
 use anyhow::Result;
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
-use tokio::sync::{broadcast, mpsc};
-use tracing::{debug, error, info, instrument, warn, Instrument};
-
-#[derive(Debug, Clone)]
-pub struct DeviceInfo {
-    pub address: String,
-    pub rssi: i16,
-    pub last_seen: std::time::Instant,
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, watch};
+use tracing::{error, info, warn, Instrument};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+
+/// 非同期のmainが戻ってから、残ったブロッキングタスクを待つ時間
+///
+/// オーディオスレッドは `shutdown.step_timeout_ms` まで待ってあるため、ここでは長く待たない。
+const RUNTIME_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+fn main() -> Result<()> {
+    // 固まったオーディオスレッドの終了を待ち続けないよう、ランタイムは手で作って時間を区切って止める
+    run_with_shutdown_timeout(run(), RUNTIME_SHUTDOWN_TIMEOUT)?
 }
 
-#[instrument]
-#[tokio::main]
-async fn main() -> Result<()> {
-    // tracingを初期化
-    tracing_subscriber::fmt::init();
+async fn run() -> Result<()> {
+    // tracingを初期化（ログ転送の設定を読む前のログも送れるよう、転送用のレイヤーも先に登録する）
+    let log_buffer = LogBuffer::new();
+    tracing_subscriber::registry()
+        .with(EnvFilter::from_default_env())
+        .with(tracing_subscriber::fmt::layer())
+        .with(LogShipperLayer::new(Arc::clone(&log_buffer)))
+        .init();
 
     // OSの判定をログに出力（コンパイル時）
     #[cfg(target_os = "linux")]
@@ -33,6 +74,112 @@ async fn main() -> Result<()> {
     #[cfg(not(target_os = "linux"))]
     info!("Application compiled for non-Linux");
 
+    // 設定を読み込む（デフォルト値・設定ファイル・サーバーからの上書き・ローカルAPIでの変更を重ねる）
+    let config_store = ConfigStore::load();
+    let mut config = config_store.effective();
+
+    // ユニットの識別情報（ログ・メトリクス・サーバーへの報告に付ける）
+    // 各タスクのスパンはこのスパンの下に作られるため、ここに記録すればすべてのタスクのログに付く。
+    // mainのフューチャーはblock_onのスレッドだけでポーリングされるので、awaitをまたいで入ったままにしてよい
+    unit_identity::init(&config);
+    let unit_span = tracing::info_span!(
+        "unit",
+        unit_id = tracing::field::Empty,
+        venue = tracing::field::Empty,
+        zone = tracing::field::Empty,
+        hardware_revision = tracing::field::Empty
+    );
+    unit_identity().record(&unit_span);
+    let _unit_span = unit_span.entered();
+
+    // 外向き通信のプロキシ・証明書のピンニング（クラッシュレポートの送信などHTTPの通信を始める前に設定する）
+    #[cfg(feature = "server")]
+    tsukimi_speaker::net_system::egress::init(&config.proxy, &config.pinning);
+    #[cfg(not(feature = "server"))]
+    if config.proxy.url.is_some() || !config.pinning.pins.is_empty() {
+        warn!("Proxy or certificate pinning is configured but this build has no `server` feature - ignoring");
+    }
+
+    // パニック時にクラッシュレポートを残し、前回までのレポートを送信する
+    install_panic_hook(&config.crash_report, Arc::clone(&log_buffer));
+    tokio::spawn(upload_pending_reports(config.crash_report.clone()).instrument(tracing::info_span!("crash_report_upload_task")));
+
+    // ログを集約先へ転送するタスク
+    if config.log_shipping.enabled {
+        info!("Spawning log shipping task");
+        tokio::spawn(log_shipper_main(config.log_shipping.clone(), log_buffer).instrument(tracing::info_span!("log_shipping_task")));
+    } else {
+        log_buffer.disable();
+    }
+
+    // 開発用: `--fake-server [script.json]` でプロセス内のフェイクgRPCサーバーに接続する
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    #[cfg(feature = "server")]
+    if let Some(pos) = args.iter().position(|a| a == "--fake-server") {
+        let script = match args.get(pos + 1).filter(|a| !a.starts_with("--")) {
+            Some(path) => fake_server::load_script(std::path::Path::new(path))?,
+            None => fake_server::default_script(),
+        };
+        let addr = fake_server::FAKE_SERVER_ADDR.parse()?;
+        tokio::spawn(
+            async move {
+                if let Err(e) = fake_server::run_fake_server(addr, script).await {
+                    error!("Fake server error: {:?}", e);
+                }
+            }
+            .instrument(tracing::info_span!("fake_server_task")),
+        );
+        config.server.grpc_url = format!("http://{}", fake_server::FAKE_SERVER_ADDR);
+    }
+    #[cfg(not(feature = "server"))]
+    if args.iter().any(|a| a == "--fake-server") {
+        warn!("`--fake-server` was given but this build has no `server` feature - ignoring");
+    }
+
+    // 開発用: `--record-session <file>` でサーバーとのやり取りを記録し、`--replay-session <file>` で再生する
+    for (flag, target) in [("--record-session", &mut config.server.record_session), ("--replay-session", &mut config.server.replay_session)] {
+        if let Some(pos) = args.iter().position(|a| a == flag) {
+            match args.get(pos + 1).filter(|a| !a.starts_with("--")) {
+                Some(path) => *target = Some(path.clone()),
+                None => warn!("`{}` needs a file path - ignoring", flag),
+            }
+        }
+    }
+    #[cfg(not(feature = "server"))]
+    if config.server.record_session.is_some() || config.server.replay_session.is_some() {
+        warn!("Session recording/replay is configured but this build has no `server` feature - ignoring");
+    }
+
+    // 設置前の調査: `--survey` でsound_mapに無いビーコンも含めてRSSIを記録する
+    if args.iter().any(|a| a == "--survey") {
+        config.survey.enabled = true;
+    }
+
+    // 設置時の配線確認: `--test-tone [pink_noise|channel_id] [--test-level <dBFS>]` で出力先にテストトーンを鳴らして終了する
+    if let Some(pos) = args.iter().position(|a| a == "--test-tone") {
+        let mut request = TestToneRequest::default();
+        if let Some(signal) = args.get(pos + 1).filter(|a| !a.starts_with("--")) {
+            request.signal = signal.parse()?;
+        }
+        if let Some(pos) = args.iter().position(|a| a == "--test-level") {
+            match args.get(pos + 1).map(|level| level.parse::<f64>()) {
+                Some(Ok(level_db)) => request.level_db = level_db,
+                _ => warn!("`--test-level` needs a level in dBFS - using {}", request.level_db),
+            }
+        }
+        let sink = configured_bgm_sink(&config.playback);
+        tokio::task::spawn_blocking(move || play_test_tone(&request, &sink)).await??;
+        return Ok(());
+    }
+
+    // 配備先での方式選び: `--benchmark-drift-correction` で補正方式ごとのCPU負荷の目安を測り、メトリクスに記録してから起動する
+    if args.iter().any(|a| a == "--benchmark-drift-correction") {
+        match tokio::task::spawn_blocking(drift_correction_bench::run).await? {
+            Ok(results) => info!(?results, "Drift correction benchmark finished (CPU % per core)"),
+            Err(e) => warn!("Drift correction benchmark failed: {:?}", e),
+        }
+    }
+
     info!("Spawning performance monitor task");
     tokio::spawn(
         async {
@@ -53,6 +200,8 @@ async fn main() -> Result<()> {
                     (0.0, 0)
                 };
 
+                metrics().set_process_cpu(process_cpu);
+
                 let total_mem = sys.total_memory();
                 let used_mem = sys.used_memory();
 
@@ -64,6 +213,7 @@ async fn main() -> Result<()> {
                 total_mem as f64 / 1_073_741_824.0,
                 process_mem as f64 / 1_048_576.0
             );
+                tracing::info!(metrics = ?metrics().snapshot(), "Metrics");
 
                 tokio::time::sleep(tokio::time::Duration::from_secs(5)).await;
             }
@@ -73,157 +223,560 @@ async fn main() -> Result<()> {
 
     info!("Starting application");
 
-    // --- sound_mapの作成 ---
+    // --- sound_mapの作成（設定の `locations` から） ---
     let mut sound_map = HashMap::new();
-    // TODO: ご自身の環境に合わせて、Bluetoothアドレスとサウンドファイル名を変更してください。
-    sound_map.insert(
-        "00:11:22:33:44:55".to_string(),
-        "tsukimi-main_1.mp3".to_string(),
-    );
-    let sound_map = Arc::new(Mutex::new(sound_map));
+    for location in &config.locations {
+        match location.address.parse::<Address>() {
+            Ok(address) => {
+                sound_map.insert(address, location.sound.clone());
+            }
+            Err(e) => warn!("Ignoring location in config: {}", e),
+        }
+    }
+    info!(count = sound_map.len(), "Loaded locations from config");
+    // サーバーのLocationUpdateで丸ごと差し替えて公開する
+    let sound_map = Arc::new(SharedSoundMap::new(sound_map));
     let current_points = Arc::new(Mutex::new(0_i32));
-    let current_location_type = Arc::new(Mutex::new(String::from("main")));
+    let (location_tx, location_rx) = tsukimi_speaker::connect_system::location_context::channel();
     let my_address = Arc::new(Mutex::new(None::<String>));
-    let time_offset = Arc::new(Mutex::new(0_i64)); // 時刻オフセット
+    let time = tsukimi_speaker::clock_system::time_source::system(); // 経過時間・壁時計の取得元
+
+    // サブシステム間のローカルイベントのバス（Webhook・OSC・ダッシュボードなどが購読する）
+    let events = EventBus::new(config.channels.command_capacity);
+    let clock = Arc::new(Clock::new(Arc::clone(&time), events.clone())); // ショー時刻（推定サーバー時刻）
 
     // Bluetoothスキャナからのデータを受け取るためのmpscチャンネル
-    let (bt_tx, mut bt_rx) = mpsc::channel::<Arc<DeviceInfo>>(32);
+    let (bt_tx, bt_rx) = mpsc::channel::<Arc<DeviceInfo>>(config.channels.device_info_capacity);
 
     // 各タスクにデータを配信するためのbroadcastチャンネル
-    let (bcast_tx, _) = broadcast::channel::<Arc<DeviceInfo>>(32);
+    let (bcast_tx, _) = broadcast::channel::<Arc<DeviceSnapshot>>(config.channels.broadcast_capacity);
 
-    // Bluetoothスキャナをバックグラウンドタスクとして実行
-    info!("Spawning bluetooth scanner task");
-    let bluetooth_handle = {
-        let my_address_clone = Arc::clone(&my_address);
-        let sound_map_clone = Arc::clone(&sound_map);
-        tokio::spawn(
-            async move {
-                if let Err(e) = bluetooth_scanner(bt_tx, my_address_clone, sound_map_clone).await {
-                    error!("Bluetooth scanner error: {:?}", e);
-                }
-            }
-            .instrument(tracing::info_span!("bluetooth_scanner_task")),
-        )
+    // BLEスキャンのやり直しを要求するmpscチャンネル（物理ボタンなどから）
+    let (rescan_tx, rescan_rx) = mpsc::channel::<()>(1);
+
+    // 終了手順（シグナルを受けたら、決められた順に各タスクを止める）
+    let shutdown = ShutdownController::new();
+
+    #[cfg(feature = "chaos")]
+    let chaos_bt_tx = bt_tx.clone();
+    // Bluetoothスキャナ（またはシミュレーション）をバックグラウンドタスクとして実行
+    let device_source: Box<dyn DeviceSource> = if config.simulation.enabled {
+        Box::new(SimulatedSource::new(config.simulation.clone(), time))
+    } else {
+        #[cfg(feature = "ble")]
+        {
+            Box::new(BleScanner {
+                my_address: Arc::clone(&my_address),
+                sound_map: Arc::clone(&sound_map),
+                time,
+                rescan_rx,
+                report_all: config.survey.enabled,
+                error_tone: config.chime.error_tone,
+                shutdown: Arc::clone(&shutdown),
+            })
+        }
+        #[cfg(not(feature = "ble"))]
+        {
+            warn!("This build has no `ble` feature - using simulated beacons only");
+            drop(rescan_rx);
+            Box::new(SimulatedSource::new(config.simulation.clone(), time))
+        }
     };
+    info!(source = device_source.name(), "Spawning device source task");
+    let bluetooth_handle = tokio::spawn(device_source.run(bt_tx).instrument(tracing::info_span!("device_source_task")));
 
     // サウンド設定のためのmpscチャンネル
-    let (sound_setting_tx, sound_setting_rx) = mpsc::channel::<SoundSetting>(32);
+    let (sound_setting_tx, sound_setting_rx) = mpsc::channel::<SoundSetting>(config.channels.command_capacity);
 
     // SE再生のためのmpscチャンネル
-    let (se_tx, se_rx) = mpsc::channel::<audio_system::audio_main::SePlayRequest>(32);
+    let (se_tx, se_rx) = mpsc::channel::<SePlayRequest>(config.channels.command_capacity);
+
+    // BGM上書きのためのmpscチャンネル
+    let (bgm_override_tx, bgm_override_rx) =
+        mpsc::channel::<BgmOverrideRequest>(config.channels.command_capacity);
+
+    // マスター音量のためのwatchチャンネル
+    let (volume_tx, volume_rx) = tsukimi_speaker::audio_system::master_volume::channel();
+    let (language_tx, language_rx) = tsukimi_speaker::audio_system::asset_variant::channel();
+
+    // バスごとの音量変更のためのmpscチャンネル
+    let (mixer_tx, mixer_rx) = mpsc::channel::<MixerCommand>(config.channels.command_capacity);
+
+    // 再生状況のためのwatchチャンネル（オーディオ → gRPC）
+    let (status_tx, status_rx) = tsukimi_speaker::audio_system::playback_status::channel();
 
-    // システム有効化状態のためのbroadcastチャンネル（複数の受信者に配信）
-    let (system_enabled_tx, _system_enabled_rx) = broadcast::channel::<SystemEnabledState>(32);
+    // システム有効化状態のためのwatchチャンネル（全サブシステムが最新値を参照）
+    let (enabled_tx, enabled_rx) = enable_state::channel();
 
-    // システム監視タスク用のAbortHandle
-    let (shutdown_tx, _shutdown_rx) = mpsc::channel::<()>(1);
+    // サーバーとの接続状態のためのwatchチャンネル（gRPC → ローカルAPI。サーバー無しの構成では `None` のまま）
+    let (connection_tx, connection_rx) = watch::channel(None::<ConnectionState>);
+    // サーバーから最後に受け取った設定系のメッセージ（gRPC → ローカルAPI）
+    #[cfg(feature = "server")]
+    let (server_messages_tx, server_messages_rx) = tsukimi_speaker::connect_system::server_messages::channel();
+    // オーディオは接続状態の変化をイベントバスで受け取る（再接続のSE用。接続タスクより先に購読する）
+    let audio_event_rx = events.subscribe("audio");
 
-    // mpscからbroadcastへデータを転送するタスク
+    // mpscからbroadcastへデータを転送するタスク（有効化状態・終了手順はコントローラーが制御メッセージにして送る）
     info!("Spawning data forwarding task");
-    let bcast_tx_clone = bcast_tx.clone();
-    let mut forward_system_enabled_rx = system_enabled_tx.subscribe();
-    let shutdown_tx_for_forward = shutdown_tx.clone();
+    let (forward_control_tx, forward_control_rx) = tsukimi_speaker::forwarding_system::forward_control::channel();
+    tokio::spawn(
+        forward_controller(enabled_rx.clone(), Arc::clone(&shutdown), forward_control_tx)
+            .instrument(tracing::info_span!("forward_controller_task")),
+    );
     let forward_handle = tokio::spawn(
-        async move {
-            let mut system_enabled = true;
+        forwarding_main(config.forwarding.clone(), bt_rx, bcast_tx.clone(), forward_control_rx)
+            .instrument(tracing::info_span!("forwarding_task")),
+    );
 
-            loop {
-                tokio::select! {
-                    device_info_opt = bt_rx.recv() => {
-                        if let Some(device_info) = device_info_opt {
-                            // システム有効化状態を確認
-                            while let Ok(state) = forward_system_enabled_rx.try_recv() {
-                                system_enabled = state.enabled;
-                                info!(enabled = system_enabled, "Forwarding task: System enabled state changed");
-
-                                if !system_enabled {
-                                    info!("System disabled - initiating shutdown");
-                                    let _ = shutdown_tx_for_forward.send(()).await;
-                                }
-                            }
-
-                            // システムが有効な場合のみデータを転送
-                            if system_enabled {
-                                debug!(?device_info, "Forwarding device info");
-                                if bcast_tx_clone.send(device_info).is_err() {
-                                    warn!("Failed to send device info to broadcast channel. No receivers?");
-                                }
-                            } else {
-                                debug!(?device_info, "System disabled - skipping device info forwarding");
-                            }
-                        } else {
-                            break;
-                        }
-                    }
-                    _ = forward_system_enabled_rx.recv() => {
-                        if let Ok(state) = forward_system_enabled_rx.try_recv() {
-                            system_enabled = state.enabled;
-                            info!(enabled = system_enabled, "Forwarding task: System enabled state changed");
-
-                            if !system_enabled {
-                                info!("System disabled - initiating shutdown");
-                                let _ = shutdown_tx_for_forward.send(()).await;
-                            }
-                        }
-                    }
+    // システムのNTP同期状態を時計に反映するタスク
+    info!("Spawning system NTP watcher task");
+    let ntp_handle = tokio::spawn(
+        watch_system_ntp(Arc::clone(&clock)).instrument(tracing::info_span!("system_ntp_task")),
+    );
+
+    // ソークテスト用に障害をランダムに注入するタスク
+    let chaos = args.iter().any(|a| a == "--chaos");
+    #[cfg(feature = "chaos")]
+    let chaos_handle = if chaos {
+        Some(tokio::spawn(
+            tsukimi_speaker::chaos_system::chaos_main::chaos_main(chaos_bt_tx, bcast_tx.subscribe(), enabled_rx.clone())
+                .instrument(tracing::info_span!("chaos_task")),
+        ))
+    } else {
+        drop(chaos_bt_tx);
+        None
+    };
+    #[cfg(not(feature = "chaos"))]
+    if chaos {
+        warn!("`--chaos` was given but this build has no `chaos` feature - ignoring");
+    }
+
+    // URLで指定された音源を先読みしてキャッシュするタスク
+    #[cfg(feature = "server")]
+    let remote_cache_handle = tokio::spawn(
+        tsukimi_speaker::audio_system::remote_source::remote_cache_main(config.playback.remote.clone(), Arc::clone(&sound_map))
+            .instrument(tracing::info_span!("remote_cache_task")),
+    );
+
+    // 時刻による番組の切り替えタスク（サーバーに依存しない）
+    let (program_tx, program_rx) = tsukimi_speaker::schedule_system::schedule_main::channel();
+    let schedule_handle = if config.schedule.enabled {
+        info!("Spawning show schedule task");
+        let schedule_config = config.schedule.clone();
+        Some(tokio::spawn(
+            async move {
+                if let Err(e) = tsukimi_speaker::schedule_system::schedule_main::schedule_main(schedule_config, program_tx).await {
+                    error!("Show schedule error: {:?}", e);
                 }
             }
-        }
-        .instrument(tracing::info_span!("forwarding_task")),
-    );
+            .instrument(tracing::info_span!("schedule_task")),
+        ))
+    } else {
+        None
+    };
 
-    // gRPC通信を行うタスク
-    info!("Spawning gRPC server task");
-    let grpc_rx = bcast_tx.subscribe();
-    let connect_handle = {
-        let sound_map_clone = Arc::clone(&sound_map);
+    // 隣り合うユニットのどちらが来場者を受け持つかを決める（来場者トークンのRSSIをピアと共有する）
+    let visitor_fusion = VisitorFusion::from_config(&config.peer, Arc::clone(&my_address), clock.time_source());
+
+    // 近くのユニットと時刻・再生状況を交換するタスク（バックエンド停止時の同期用）
+    let peer_handle = if config.peer.enabled {
+        info!("Spawning peer sync task");
+        let peer_config = config.peer.clone();
+        let clock_clone = Arc::clone(&clock);
+        let status_rx_clone = status_rx.clone();
         let my_address_clone = Arc::clone(&my_address);
-        let current_points_clone = Arc::clone(&current_points);
-        let current_location_type_clone = Arc::clone(&current_location_type);
-        let sound_setting_tx_clone = sound_setting_tx.clone();
+        let visitor_fusion_clone = visitor_fusion.clone();
+        let peer_rx = bcast_tx.subscribe();
+        Some(tokio::spawn(
+            async move {
+                if let Err(e) = peer_main(peer_config, clock_clone, status_rx_clone, my_address_clone, visitor_fusion_clone, peer_rx).await {
+                    error!("Peer sync error: {:?}", e);
+                }
+            }
+            .instrument(tracing::info_span!("peer_sync_task")),
+        ))
+    } else {
+        None
+    };
+
+    // 設置時の目視確認用にステータスLEDを駆動するタスク
+    #[cfg(feature = "gpio")]
+    let status_led_handle = if config.gpio.enabled {
+        info!("Spawning status LED task");
+        let gpio_config = config.gpio.clone();
+        let clock_clone = Arc::clone(&clock);
+        let status_rx_clone = status_rx.clone();
+        let beacon_rx = bcast_tx.subscribe();
+        let event_rx = events.subscribe("status_led");
+        Some(tokio::spawn(
+            async move {
+                if let Err(e) = tsukimi_speaker::gpio_system::status_led::status_led_main(gpio_config, clock_clone, status_rx_clone, beacon_rx, event_rx).await {
+                    error!("Status LED error: {:?}", e);
+                }
+            }
+            .instrument(tracing::info_span!("status_led_task")),
+        ))
+    } else {
+        None
+    };
+    // 物理ボタンの入力をサーバーからの指示と同じチャンネルに流すタスク
+    #[cfg(feature = "gpio")]
+    let buttons_handle = if config.gpio.enabled && !config.gpio.buttons.is_empty() {
+        info!("Spawning button input task");
+        let gpio_config = config.gpio.clone();
+        let se_tx_clone = se_tx.clone();
+        let volume_tx_clone = volume_tx.clone();
+        let enabled_tx_clone = enabled_tx.clone();
+        let activation_se = config.playback.activation_se.clone();
+        Some(tokio::spawn(
+            async move {
+                if let Err(e) = tsukimi_speaker::gpio_system::buttons::buttons_main(gpio_config, activation_se, se_tx_clone, volume_tx_clone, enabled_tx_clone, rescan_tx).await {
+                    error!("Button input error: {:?}", e);
+                }
+            }
+            .instrument(tracing::info_span!("buttons_task")),
+        ))
+    } else {
+        None
+    };
+    #[cfg(not(feature = "gpio"))]
+    {
+        drop(rescan_tx);
+        if config.gpio.enabled {
+            warn!("GPIO is enabled in config but this build has no `gpio` feature - ignoring");
+        }
+    }
+
+    // ローカルイベントをWebhookで通知するタスク
+    #[cfg(feature = "server")]
+    let webhook_handle = if config.webhook.targets.is_empty() {
+        None
+    } else {
+        info!("Spawning webhook dispatcher task");
+        let webhook_config = config.webhook.clone();
+        let webhook_enabled_rx = enabled_rx.clone();
+        Some(tokio::spawn(
+            webhook_main(webhook_config, events.clone(), webhook_enabled_rx).instrument(tracing::info_span!("webhook_task")),
+        ))
+    };
+    #[cfg(not(feature = "server"))]
+    if !config.webhook.targets.is_empty() {
+        warn!("Webhook targets are configured but this build has no `server` feature - ignoring");
+    }
+
+    // ショーコントロール機器へOSCを送信するタスク
+    let osc_handle = if config.osc.enabled {
+        info!("Spawning OSC output task");
+        let osc_config = config.osc.clone();
+        let osc_events = events.clone();
+        let status_rx_clone = status_rx.clone();
+        Some(tokio::spawn(
+            async move {
+                if let Err(e) = osc_main(osc_config, osc_events, status_rx_clone).await {
+                    error!("OSC output error: {:?}", e);
+                }
+            }
+            .instrument(tracing::info_span!("osc_task")),
+        ))
+    } else {
+        None
+    };
+
+    // 会場ごとのルールを書いたスクリプトを実行するタスク
+    #[cfg(feature = "scripting")]
+    let script_handle = if config.script.enabled {
+        info!(path = %config.script.path, "Spawning venue script task");
+        let script_config = config.script.clone();
+        let utc_offset_minutes = config.schedule.utc_offset_minutes;
+        let script_events = events.clone();
         let se_tx_clone = se_tx.clone();
-        let system_enabled_tx_clone = system_enabled_tx.clone();
-        let time_offset_clone = Arc::clone(&time_offset);
+        let bgm_override_tx_clone = bgm_override_tx.clone();
+        let current_points_clone = Arc::clone(&current_points);
+        Some(tokio::spawn(
+            async move {
+                if let Err(e) = tsukimi_speaker::script_system::script_main::script_main(script_config, utc_offset_minutes, script_events, se_tx_clone, bgm_override_tx_clone, current_points_clone).await {
+                    error!("Venue script error: {:?}", e);
+                }
+            }
+            .instrument(tracing::info_span!("script_task")),
+        ))
+    } else {
+        None
+    };
+    #[cfg(not(feature = "scripting"))]
+    if config.script.enabled {
+        warn!("Venue script is enabled in config but this build has no `scripting` feature - ignoring");
+    }
+
+    // インタラクションの閾値のキャリブレーション結果（ローカルAPIから更新し、インタラクション検知が参照する）
+    #[cfg(feature = "server")]
+    let calibration = CalibrationStore::load(std::path::Path::new(DEFAULT_CALIBRATION_PATH));
+
+    // ビーコンの電波の届き方を記録する調査モードのタスク
+    let survey = config.survey.enabled.then(Survey::new);
+    let survey_handle = survey.as_ref().map(|survey| {
+        info!("Spawning beacon survey task");
+        let survey_config = config.survey.clone();
+        let survey_clone = Arc::clone(survey);
+        let survey_rx = bcast_tx.subscribe();
+        let sound_map_clone = Arc::clone(&sound_map);
+        let shutdown_clone = Arc::clone(&shutdown);
         tokio::spawn(
             async move {
-                if let Err(e) =
-                    connect_main(grpc_rx, time_offset_clone, sound_setting_tx_clone, se_tx_clone, system_enabled_tx_clone, sound_map_clone, my_address_clone, current_points_clone, current_location_type_clone).await
-                {
-                    error!("Connect server error: {}", e);
+                if let Err(e) = survey_main(survey_config, survey_clone, survey_rx, sound_map_clone, shutdown_clone).await {
+                    error!("Beacon survey error: {:?}", e);
                 }
             }
-            .instrument(tracing::info_span!("grpc_server_task")),
+            .instrument(tracing::info_span!("survey_task")),
         )
+    });
+    if survey.is_some() && !config.api.enabled {
+        warn!("Survey mode without the local API - waypoints cannot be set");
+    }
+
+    // 現地デバッグ用のローカルAPI・ダッシュボードのタスク
+    let api_handle = if config.api.enabled {
+        info!("Spawning local API task");
+        let api_config = config.api.clone();
+        let snapshot_tx = bcast_tx.clone();
+        let api_events = events.clone();
+        let status_rx_clone = status_rx.clone();
+        let location_rx_clone = location_rx.clone();
+        let enabled_tx_clone = enabled_tx.clone();
+        let volume_tx_clone = volume_tx.clone();
+        let se_tx_clone = se_tx.clone();
+        let mixer_tx = mixer_tx.clone();
+        let sound_map_clone = Arc::clone(&sound_map);
+        let activation_se = config.playback.activation_se.clone();
+        let test_tone_sink = configured_bgm_sink(&config.playback);
+        let survey_clone = survey.clone();
+        let config_store_clone = Arc::clone(&config_store);
+        #[cfg(feature = "server")]
+        let calibration_clone = Arc::clone(&calibration);
+        Some(tokio::spawn(
+            async move {
+                if let Err(e) = api_main(api_config, activation_se, test_tone_sink, snapshot_tx, api_events, status_rx_clone, location_rx_clone, connection_rx, enabled_tx_clone, volume_tx_clone, se_tx_clone, mixer_tx, sound_map_clone, survey_clone, config_store_clone, #[cfg(feature = "server")] calibration_clone, #[cfg(feature = "server")] server_messages_rx).await {
+                    error!("Local API error: {:?}", e);
+                }
+            }
+            .instrument(tracing::info_span!("api_task")),
+        ))
+    } else {
+        None
+    };
+
+    // 音源のキューに合わせてDMX照明を制御するタスク
+    let dmx_handle = if config.dmx.enabled {
+        info!("Spawning DMX output task");
+        let dmx_config = config.dmx.clone();
+        let status_rx_clone = status_rx.clone();
+        Some(tokio::spawn(
+            async move {
+                if let Err(e) = dmx_main(dmx_config, status_rx_clone).await {
+                    error!("DMX output error: {:?}", e);
+                }
+            }
+            .instrument(tracing::info_span!("dmx_task")),
+        ))
+    } else {
+        None
     };
 
+    // MIDIタイムコード・クロックを出力するタスク（再生状況のチャンネルが閉じると終了する）
+    #[cfg(feature = "midi")]
+    if config.midi.enabled {
+        info!("Spawning MIDI sync output task");
+        let midi_config = config.midi.clone();
+        let status_rx_clone = status_rx.clone();
+        tokio::task::spawn_blocking(move || {
+            let _span = tracing::info_span!("midi_sync_task").entered();
+            if let Err(e) = tsukimi_speaker::midi_system::midi_main::midi_main(midi_config, status_rx_clone) {
+                error!("MIDI sync output error: {:?}", e);
+            }
+        });
+    }
+    #[cfg(not(feature = "midi"))]
+    if config.midi.enabled {
+        warn!("MIDI output is enabled in config but this build has no `midi` feature - ignoring");
+    }
+
+    // サーバーとやり取りするタスク（スタンドアロン構成ではサーバーからの指示の送信側を閉じるだけ）
+    #[cfg(feature = "server")]
+    let server_link: Box<dyn ServerLink> = Box::new(ServerConnection {
+        server: config.server.clone(),
+        upload: config.upload.clone(),
+        interaction: config.interaction.clone(),
+        calibration: Arc::clone(&calibration),
+        state_paths: StatePaths::default(),
+        visitor_fusion: visitor_fusion.clone(),
+        connected_chime: config.chime.connected.clone(),
+        rx: bcast_tx.subscribe(),
+        clock: Arc::clone(&clock),
+        sound_setting_tx: sound_setting_tx.clone(),
+        se_tx: se_tx.clone(),
+        bgm_override_tx: bgm_override_tx.clone(),
+        volume_tx: volume_tx.clone(),
+        language_tx,
+        enabled_tx: enabled_tx.clone(),
+        events: events.clone(),
+        connection_tx,
+        status_rx,
+        sound_map: Arc::clone(&sound_map),
+        my_address: Arc::clone(&my_address),
+        current_points: Arc::clone(&current_points),
+        location_tx,
+        server_messages_tx,
+        config_store: Arc::clone(&config_store),
+        shutdown: Arc::clone(&shutdown),
+    });
+    #[cfg(not(feature = "server"))]
+    let server_link: Box<dyn ServerLink> = Box::new(Standalone { sound_setting_tx, bgm_override_tx, language_tx, location_tx, connection_tx, status_rx });
+    info!(link = server_link.name(), "Spawning server link task");
+    let connect_handle = tokio::spawn(
+        async move {
+            if let Err(e) = server_link.run().await {
+                error!("Connect server error: {}", e);
+            }
+        }
+        .instrument(tracing::info_span!("grpc_server_task")),
+    );
+
     // 同期的なaudio_main関数をspawn_blockingで実行
     info!("Spawning audio playback task");
+    let heartbeat = tsukimi_speaker::audio_system::watchdog::AudioHeartbeat::new();
+    let (stall_tx, stall_rx) = tsukimi_speaker::audio_system::watchdog::stall_channel();
     let audio_rx = bcast_tx.subscribe();
-    let audio_system_enabled_rx = system_enabled_tx.subscribe();
+    let audio_enabled_rx = enabled_rx.clone();
     let audio_handle = {
-        let sound_map_clone = Arc::clone(&sound_map);
-        let my_address_clone = Arc::clone(&my_address);
-        let current_points_clone = Arc::clone(&current_points);
-        let time_offset_clone = Arc::clone(&time_offset);
+        let context = AudioContext {
+            clock: Arc::clone(&clock),
+            events,
+            heartbeat: Arc::clone(&heartbeat),
+            sound_map: Arc::clone(&sound_map),
+            current_points: Arc::clone(&current_points),
+            chime: config.chime.clone(),
+            config: config.playback.clone(),
+        };
+        let shutdown_clone = Arc::clone(&shutdown);
         tokio::task::spawn_blocking(move || {
             let _span = tracing::info_span!("audio_playback_task").entered();
-            audio_main(audio_rx, time_offset_clone, sound_setting_rx, se_rx, audio_system_enabled_rx, sound_map_clone, my_address_clone, current_points_clone)
+            let channels = AudioChannels {
+                rx: audio_rx,
+                sound_setting_rx: SharedReceiver::new(sound_setting_rx),
+                se_rx: SharedReceiver::new(se_rx),
+                bgm_override_rx: SharedReceiver::new(bgm_override_rx),
+                enabled_rx: audio_enabled_rx,
+                volume_rx,
+                mixer_rx: SharedReceiver::new(mixer_rx),
+                program_rx,
+                language_rx,
+                status_tx,
+                event_rx: audio_event_rx,
+                shutdown: shutdown_clone,
+            };
+            audio_supervisor(channels, stall_rx, context)
         })
     };
 
-    // オーディオ再生タスクの結果を待つ
-    match audio_handle.await {
-        Ok(Ok(_)) => info!("Audio playback finished successfully."),
-        Ok(Err(e)) => error!("Audio playback error: {}", format!("{:?}", e)),
-        Err(e) => error!("Audio task panicked: {e}"),
+    // オーディオスレッドが止まっていないか監視するタスク
+    let watchdog_handle = if config.playback.watchdog.enabled {
+        info!("Spawning audio watchdog task");
+        Some(tokio::spawn(
+            tsukimi_speaker::audio_system::watchdog::audio_watchdog_main(config.playback.watchdog.clone(), heartbeat, stall_tx)
+                .instrument(tracing::info_span!("audio_watchdog_task")),
+        ))
+    } else {
+        None
+    };
+
+    // 起動チャイム（SEとして優先再生し、オーディオ初期化後に鳴る）
+    if let Some(file_path) = config.chime.boot.clone() {
+        info!(file = %file_path, "Queueing boot chime");
+        let request = SePlayRequest { file_path, priority: true, gain: None, command_id: None };
+        if let Err(e) = se_tx.try_send(request) {
+            warn!("Failed to queue boot chime: {}", e);
+        }
+    }
+
+    // オーディオ再生タスクの終了か、終了のシグナルを待つ
+    let mut audio_handle = audio_handle;
+    let audio_result = tokio::select! {
+        result = &mut audio_handle => Some(result),
+        _ = termination_signal() => None,
+    };
+
+    // 決められた順に止める（GStreamerの状態遷移の途中でタスクを中断しないため）
+    info!("Shutting down");
+    let step_timeout = Duration::from_millis(config.shutdown.step_timeout_ms);
+    shutdown.enter(ShutdownPhase::StopBle);
+    join_or_abort("bluetooth", bluetooth_handle, step_timeout).await;
+    join_or_abort("forwarding", forward_handle, step_timeout).await;
+    if let Some(survey_handle) = survey_handle {
+        join_or_abort("survey", survey_handle, step_timeout).await;
     }
+    #[cfg(feature = "server")]
+    shutdown.advance(ShutdownPhase::FlushUploads, step_timeout).await;
+    // オーディオループが既に終了している場合はフェードアウトする音が無い
+    if audio_result.is_none() {
+        let fade_timeout = Duration::from_millis(config.playback.shutdown_fade_ms) + step_timeout;
+        shutdown.advance(ShutdownPhase::FadeAudio, fade_timeout).await;
+    }
+    shutdown.enter(ShutdownPhase::CloseStreams);
+    join_or_abort("connect", connect_handle, step_timeout).await;
+    shutdown.enter(ShutdownPhase::DropPipelines);
+    let audio_result = match audio_result {
+        Some(result) => Some(result),
+        // ブロッキングスレッドは中断できないため、戻らなければ待たずに終了する（ランタイムも待たずに止める）
+        None => tokio::time::timeout(step_timeout, audio_handle).await.ok(),
+    };
 
-    // アプリケーション終了時に各タスクを停止
-    info!("Aborting tasks");
-    bluetooth_handle.abort();
-    forward_handle.abort();
-    connect_handle.abort();
+    // オーディオ再生タスクの結果
+    match audio_result {
+        Some(Ok(Ok(_))) => info!("Audio playback finished successfully."),
+        Some(Ok(Err(e))) => error!("Audio playback error: {}", format!("{:?}", e)),
+        Some(Err(e)) => error!(crash_report_dir = %config.crash_report.dir, "Audio task panicked: {e}"),
+        None => warn!(timeout_ms = step_timeout.as_millis() as u64, "Audio thread did not stop in time"),
+    }
+
+    // 残りのタスク（GStreamer・サーバーとのストリームを持たないもの）を停止
+    info!("Aborting remaining tasks");
+    ntp_handle.abort();
+    if let Some(watchdog_handle) = watchdog_handle {
+        watchdog_handle.abort();
+    }
+    #[cfg(feature = "server")]
+    remote_cache_handle.abort();
+    if let Some(schedule_handle) = schedule_handle {
+        schedule_handle.abort();
+    }
+    if let Some(peer_handle) = peer_handle {
+        peer_handle.abort();
+    }
+    #[cfg(feature = "server")]
+    if let Some(webhook_handle) = webhook_handle {
+        webhook_handle.abort();
+    }
+    if let Some(osc_handle) = osc_handle {
+        osc_handle.abort();
+    }
+    if let Some(dmx_handle) = dmx_handle {
+        dmx_handle.abort();
+    }
+    if let Some(api_handle) = api_handle {
+        api_handle.abort();
+    }
+    #[cfg(feature = "scripting")]
+    if let Some(script_handle) = script_handle {
+        script_handle.abort();
+    }
+    #[cfg(feature = "chaos")]
+    if let Some(chaos_handle) = chaos_handle {
+        chaos_handle.abort();
+    }
+    #[cfg(feature = "gpio")]
+    for handle in [status_led_handle, buttons_handle].into_iter().flatten() {
+        handle.abort();
+    }
 
     info!("Application finished");
     Ok(())
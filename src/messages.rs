@@ -0,0 +1,181 @@
+// サブシステム間でチャンネル越しにやり取りするメッセージの型
+// 送信側・受信側のどちらのモジュールにも属さないため、ここにまとめる（ログ・ローカルAPIでそのまま出力できるよう、いずれもシリアライズできる）
+
+use crate::bluetooth_system::address::Address;
+use crate::config_system::config_main::MixBus;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// BLEスキャナ（またはシミュレーション）が受信したデバイスの広告
+#[derive(Debug, Clone, Serialize)]
+pub struct DeviceInfo {
+    pub address: Address,
+    pub rssi: i16,
+    #[serde(skip)]
+    pub last_seen: std::time::Instant,
+    /// BLEのイベントを受け取った時刻（広告から音が変わるまでの遅延の計測用）
+    #[serde(skip)]
+    pub received_at: std::time::Instant,
+    /// 転送タスクがスナップショットとして配信した時刻（配信前は `None`）
+    #[serde(skip)]
+    pub forwarded_at: Option<std::time::Instant>,
+    /// コンパニオンアプリ（来場者のスマートフォン）が広告する来場者トークン
+    ///
+    /// `visitor-tokens` フィーチャー有効時のみ設定される。ビーコンの場合は `None`。
+    pub visitor_token: Option<String>,
+    /// 広告のローカル名
+    pub local_name: Option<String>,
+    /// 広告の送信電力（dBm、距離の推定用）
+    pub tx_power: Option<i16>,
+    /// 広告のメーカー固有データ（カンパニーID -> データ、iBeaconの解析用）
+    pub manufacturer_data: HashMap<u16, Vec<u8>>,
+    /// 広告のサービスデータ（サービスUUID -> データ）
+    pub service_data: HashMap<Uuid, Vec<u8>>,
+}
+
+/// 転送タスクが一定周期でまとめて配信するDeviceInfoの集合
+///
+/// 前回の配信以降に更新があったデバイスのみを、アドレスごとに最新値1件ずつ含む。
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct DeviceSnapshot {
+    pub devices: Vec<Arc<DeviceInfo>>,
+}
+
+/// システム有効化状態（自デバイス向けに解決済みの値）
+///
+/// 対象デバイスの判定は送信側（connect_main）で行うため、
+/// 受信側は `enabled` をそのまま適用すればよい。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EnabledState {
+    /// 実効的な有効化状態（`global_enabled && device_enabled`）
+    pub enabled: bool,
+    /// 全デバイス向けの一括フラグ
+    pub global_enabled: bool,
+    /// 自デバイス向けの個別フラグ
+    pub device_enabled: bool,
+    /// 最後に適用した全体フラグ・個別フラグの更新番号（古いMoonlightUpdateを無視するため）
+    pub global_sequence: u64,
+    pub device_sequence: u64,
+}
+
+impl Default for EnabledState {
+    fn default() -> Self {
+        // サーバーから指示があるまでは有効状態で動作する
+        Self {
+            enabled: true,
+            global_enabled: true,
+            device_enabled: true,
+            global_sequence: 0,
+            device_sequence: 0,
+        }
+    }
+}
+
+/// SE再生リクエスト
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SePlayRequest {
+    pub file_path: String,
+    /// trueの場合は再生中のSEに割り込み、再生が終わるまで通常のSEを受け付けない
+    #[serde(default)]
+    pub priority: bool,
+    /// このSEだけに掛ける倍率（未指定なら1.0）
+    #[serde(default)]
+    pub gain: Option<f64>,
+    /// サーバーのコマンドのID（再生の結果を `LocalEvent::CommandExecuted` で報告する）
+    #[serde(skip)]
+    pub command_id: Option<String>,
+}
+
+/// BGM上書きリクエスト（サーバーからの演出用の強制再生）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BgmOverrideRequest {
+    /// 強制的に再生するサウンドファイル（`None` の場合は上書きを解除）
+    pub sound: Option<String>,
+    /// 上書きを維持する時間
+    pub duration: Duration,
+}
+
+/// ミキサーのバスの設定を変更するコマンド（指定した項目だけ変更する）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MixerCommand {
+    pub bus: MixBus,
+    pub gain: Option<f64>,
+    pub muted: Option<bool>,
+}
+
+/// サーバーとの接続状態（イベントバス・ローカルAPIで公開し、LED・ダッシュボード・SEに反映する）
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionState {
+    /// 接続を試みている（起動直後・切断後の再接続中）
+    Connecting,
+    /// 接続している
+    Connected,
+    /// 代替の経路（WebSocketへのフォールバック）で接続している。時刻同期は行えない
+    Degraded,
+    /// 接続できない状態が `alert_after_ms` を超えて続いている
+    Offline,
+}
+
+impl ConnectionState {
+    /// サーバーからの指示を受け取れる状態かどうか
+    pub fn is_connected(self) -> bool {
+        matches!(self, ConnectionState::Connected | ConnectionState::Degraded)
+    }
+}
+
+/// 実行結果をサーバーに報告するコマンドの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandKind {
+    /// 有効化状態（MoonlightUpdate）
+    Enable,
+    SePlay,
+    BgmOverride,
+    Volume,
+}
+
+/// コマンドが効かなかった理由
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandFailure {
+    /// ファイル名などの指定が不正
+    InvalidRequest,
+    /// 指定された音源がユニットに無い
+    AssetMissing,
+    /// 再生パイプラインを構築・開始できなかった
+    PipelineError,
+    /// 優先SEの再生中で受け付けられなかった
+    Busy,
+    /// 更新番号が古く、より新しい指示が適用済みだった
+    Stale,
+    /// 再生側のサブシステムが止まっていた
+    Unavailable,
+    /// 同じSEの指示が多すぎるため鳴らさなかった
+    RateLimited,
+}
+
+/// サーバーのコマンドの実行結果（command_id付きで届いたコマンドのみ）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommandOutcome {
+    pub command_id: String,
+    pub command: CommandKind,
+    /// 効かなかった理由（成功した場合は `None`）
+    pub failure: Option<CommandFailure>,
+    /// 失敗の詳細（エラーメッセージなど）
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub detail: String,
+}
+
+impl CommandOutcome {
+    pub fn ok(command_id: String, command: CommandKind) -> Self {
+        Self { command_id, command, failure: None, detail: String::new() }
+    }
+
+    pub fn failed(command_id: String, command: CommandKind, failure: CommandFailure, detail: impl Into<String>) -> Self {
+        Self { command_id, command, failure: Some(failure), detail: detail.into() }
+    }
+}
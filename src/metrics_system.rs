@@ -0,0 +1,2 @@
+pub mod drift_correction_bench;
+pub mod metrics_main;
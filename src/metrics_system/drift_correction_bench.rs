@@ -0,0 +1,98 @@
+use crate::audio_system::drift_correction::{element_description, TempoControl};
+use crate::config_system::config_main::DriftCorrection;
+use crate::metrics_system::metrics_main::metrics;
+use anyhow::{anyhow, Result};
+use gstreamer as gst;
+use gstreamer::prelude::*;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+const SAMPLE_RATE: u32 = 44_100;
+const SAMPLES_PER_BUFFER: u32 = 1_024;
+/// 1回の計測で流す音声の長さ（秒）
+pub const AUDIO_SECONDS: u32 = 10;
+/// 補正中の再生速度
+const RATE: f64 = 1.02;
+/// 流し切るまで待つ最長の時間
+const PLAY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// BGMのパイプラインと同じ形式の音声を、補正の要素を通して捨てるパイプライン（要素のプラグインが無ければ `None`）
+fn pipeline(mode: DriftCorrection) -> Option<gst::Pipeline> {
+    let description = format!(
+        "audiotestsrc wave=pink-noise num-buffers={} samplesperbuffer={} ! audio/x-raw,rate={},channels=2 ! audioconvert ! capsfilter caps=\"audio/x-raw,format=F32LE,rate=44100,channels=2\"{} ! audioconvert ! audioresample ! fakesink sync=false",
+        SAMPLE_RATE * AUDIO_SECONDS / SAMPLES_PER_BUFFER,
+        SAMPLES_PER_BUFFER,
+        SAMPLE_RATE,
+        element_description(mode)
+    );
+    gst::parse::launch(&description).ok()?.downcast::<gst::Pipeline>().ok()
+}
+
+/// その方式の要素が使えるかどうか（`pitch` はgst-plugins-badのsoundtouch、`scaletempo` はgst-plugins-good）
+pub fn is_available(mode: DriftCorrection) -> bool {
+    pipeline(mode).is_some()
+}
+
+/// 補正中の速度（1.02倍）にした音声 `AUDIO_SECONDS` 秒分を、同期なし（`sync=false`）で流し切るまでの時間を測る
+pub fn play_through(mode: DriftCorrection) -> Result<Duration> {
+    let pipeline = pipeline(mode).ok_or_else(|| anyhow!("{:?} element not available", mode))?;
+    let bus = pipeline.bus().ok_or_else(|| anyhow!("Failed to get bus from pipeline"))?;
+    pipeline.set_state(gst::State::Paused)?;
+    let _ = pipeline.state(gst::ClockTime::from_seconds(5));
+    TempoControl::new(mode, &pipeline).set(&pipeline, RATE);
+
+    let start = Instant::now();
+    pipeline.set_state(gst::State::Playing)?;
+    let msg = bus.timed_pop_filtered(
+        gst::ClockTime::from_nseconds(PLAY_TIMEOUT.as_nanos() as u64),
+        &[gst::MessageType::Eos, gst::MessageType::Error],
+    );
+    let elapsed = start.elapsed();
+    let _ = pipeline.set_state(gst::State::Null);
+    match msg.as_ref().map(|msg| msg.view()) {
+        Some(gst::MessageView::Eos(_)) => Ok(elapsed),
+        Some(gst::MessageView::Error(err)) => Err(anyhow!("{:?} pipeline failed: {}", mode, err.error())),
+        _ => Err(anyhow!("{:?} pipeline did not finish within {:?}", mode, PLAY_TIMEOUT)),
+    }
+}
+
+/// 流し切るのにかかった時間から、その方式で再生したときの1コアあたりのCPU使用率の目安（%）を求める
+///
+/// 処理はほぼストリーミングスレッド1本で進むため、音声の長さに対する割合がそのままCPU使用率になる
+/// （例: 10秒分に200msなら約2%）。
+pub fn cpu_percent(elapsed: Duration) -> f64 {
+    elapsed.as_secs_f64() / AUDIO_SECONDS as f64 * 100.0
+}
+
+/// 使える方式をすべて計測し、メトリクスの `drift_correction_benchmark` に記録する（ブロッキング）
+///
+/// 配備先と同じハードウェアで実行し、方式ごとの値を比べて `playback.drift_correction` を選ぶ。
+/// 要素のプラグインが無い方式は飛ばす。
+pub fn run() -> Result<BTreeMap<DriftCorrection, f64>> {
+    gst::init()?;
+    let mut results = BTreeMap::new();
+    for mode in DriftCorrection::ALL {
+        if !is_available(mode) {
+            warn!(?mode, "Skipping drift correction benchmark: element not available");
+            continue;
+        }
+        let percent = cpu_percent(play_through(mode)?);
+        info!(?mode, cpu_percent = percent, "⏱️  Drift correction benchmark");
+        metrics().record_drift_correction_benchmark(mode, percent);
+        results.insert(mode, percent);
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpu_percent_is_the_share_of_the_audio_length() {
+        assert_eq!(cpu_percent(Duration::ZERO), 0.0);
+        assert!((cpu_percent(Duration::from_millis(200)) - 2.0).abs() < 1e-9);
+        assert!((cpu_percent(Duration::from_secs(AUDIO_SECONDS as u64)) - 100.0).abs() < 1e-9);
+    }
+}
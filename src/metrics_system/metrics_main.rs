@@ -0,0 +1,389 @@
+use crate::audio_system::sync_quality::SyncQualitySnapshot;
+use crate::config_system::config_main::DriftCorrection;
+use crate::config_system::unit_identity::{unit_identity, UnitIdentity};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tracing::warn;
+
+/// ヒストグラムのバケット上限（ミリ秒）。最後のバケットはそれ以上すべて
+pub const LATENCY_BUCKETS_MS: [u64; 6] = [50, 100, 200, 300, 500, 1000];
+
+/// 遅延の分布を記録する固定バケットのヒストグラム
+pub struct LatencyHistogram {
+    buckets: [AtomicU64; LATENCY_BUCKETS_MS.len() + 1],
+    count: AtomicU64,
+    sum_ms: AtomicU64,
+    max_ms: AtomicU64,
+}
+
+/// ヒストグラムのスナップショット（`buckets[i]` は `LATENCY_BUCKETS_MS[i]` ms以下の件数）
+#[derive(Debug, Clone, Serialize)]
+pub struct HistogramSnapshot {
+    pub buckets: Vec<u64>,
+    pub count: u64,
+    pub mean_ms: u64,
+    pub max_ms: u64,
+}
+
+impl LatencyHistogram {
+    const fn new() -> Self {
+        Self {
+            buckets: [const { AtomicU64::new(0) }; LATENCY_BUCKETS_MS.len() + 1],
+            count: AtomicU64::new(0),
+            sum_ms: AtomicU64::new(0),
+            max_ms: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, elapsed: Duration) {
+        let ms = elapsed.as_millis() as u64;
+        let index = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|&upper| ms <= upper)
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_ms.fetch_add(ms, Ordering::Relaxed);
+        self.max_ms.fetch_max(ms, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        let count = self.count.load(Ordering::Relaxed);
+        HistogramSnapshot {
+            buckets: self.buckets.iter().map(|b| b.load(Ordering::Relaxed)).collect(),
+            count,
+            mean_ms: self.sum_ms.load(Ordering::Relaxed).checked_div(count).unwrap_or(0),
+            max_ms: self.max_ms.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// 補正方式ごとのプロセスのCPU使用率（その方式で動いている間の測定値だけを集める）
+pub struct CpuGauge {
+    latest_permille: AtomicU64,
+    sum_permille: AtomicU64,
+    samples: AtomicU64,
+}
+
+/// `CpuGauge` のスナップショット
+#[derive(Debug, Clone, Serialize)]
+pub struct CpuGaugeSnapshot {
+    pub latest_percent: f64,
+    pub mean_percent: f64,
+    pub samples: u64,
+}
+
+impl CpuGauge {
+    const fn new() -> Self {
+        Self {
+            latest_permille: AtomicU64::new(0),
+            sum_permille: AtomicU64::new(0),
+            samples: AtomicU64::new(0),
+        }
+    }
+
+    fn observe(&self, permille: u64) {
+        self.latest_permille.store(permille, Ordering::Relaxed);
+        self.sum_permille.fetch_add(permille, Ordering::Relaxed);
+        self.samples.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 一度も測っていなければ `None`
+    fn snapshot(&self) -> Option<CpuGaugeSnapshot> {
+        let samples = self.samples.load(Ordering::Relaxed);
+        let mean_permille = self.sum_permille.load(Ordering::Relaxed).checked_div(samples)?;
+        Some(CpuGaugeSnapshot {
+            latest_percent: self.latest_permille.load(Ordering::Relaxed) as f64 / 10.0,
+            mean_percent: mean_permille as f64 / 10.0,
+            samples,
+        })
+    }
+}
+
+/// アプリケーション全体で共有するカウンタ群
+///
+/// 各タスクから `metrics()` 経由で加算し、パフォーマンスモニタが定期的にログへ出力する。
+pub struct Metrics {
+    /// 転送タスクがbroadcastしたDeviceInfoの数
+    pub device_info_forwarded: AtomicU64,
+    /// 転送前に同一アドレスの新しい値で置き換えられたDeviceInfoの数
+    pub device_info_coalesced: AtomicU64,
+    /// broadcastの受信遅延（Lagged）で読み飛ばされたメッセージ数
+    pub lagged_audio: AtomicU64,
+    pub lagged_interaction: AtomicU64,
+    pub lagged_upload: AtomicU64,
+    pub lagged_api: AtomicU64,
+    pub lagged_survey: AtomicU64,
+    pub lagged_peer: AtomicU64,
+    /// サーバーから受信した未知（またはイベント未設定）のメッセージ数
+    pub unknown_events: AtomicU64,
+    /// 検証に失敗して受け付けなかったLocationUpdateの数
+    pub rejected_location_updates: AtomicU64,
+    /// 回数の上限を超えたため鳴らさなかった、サーバーの指示によるSEの数
+    pub se_triggers_dropped: AtomicU64,
+    /// サーバーへの接続の失敗回数
+    pub server_connect_failures: AtomicU64,
+    /// サーバーに接続できない状態が続いている時間（ミリ秒。接続中は0）
+    server_offline_ms: AtomicU64,
+    /// デバイス情報のストリームで送ったバイト数（protobufの長さ。圧縮・通信路のオーバーヘッドは含まない）
+    pub upload_bytes: AtomicU64,
+    /// インタラクションAPIに送ったバイト数（リクエストボディの長さ）
+    pub interaction_bytes: AtomicU64,
+    /// デバイス情報をまとめて送る現在の間隔（ミリ秒。適応モード・サーバーからの抑制で変わる）
+    upload_batch_interval_ms: AtomicU64,
+    /// BGM切り替えの各段階の所要時間
+    pub switch_build: LatencyHistogram,
+    pub switch_seek: LatencyHistogram,
+    pub switch_apply: LatencyHistogram,
+    /// 切り替え判断から新しい音源の再生開始まで
+    pub switch_total: LatencyHistogram,
+    /// BLEの広告を受け取ってから、それをきっかけに切り替えたBGMの再生開始まで（RSSIによる切り替えのみ）
+    pub reaction_total: LatencyHistogram,
+    /// その内訳: 受信から転送タスクの配信まで・配信から切り替え判断まで（判断から再生開始までは `switch_total`）
+    pub reaction_forward: LatencyHistogram,
+    pub reaction_select: LatencyHistogram,
+    /// BGMのずれの補正方式
+    drift_correction: OnceLock<DriftCorrection>,
+    /// 同期の品質（オーディオループが再生状況の公開のたびに更新する）
+    sync_score: AtomicU64,
+    sync_drift_ema_us: AtomicU64,
+    sync_reseeks: AtomicU64,
+    sync_tempo_excursions: AtomicU64,
+    /// パフォーマンスモニタが最後に測ったプロセスのCPU使用率（0.1%単位）
+    process_cpu_permille: AtomicU64,
+    /// プロセスのCPU使用率を補正方式のラベル付きで集めたもの（`DriftCorrection` の順）
+    ///
+    /// 同じ会場・音源で方式だけを変えて起動し、集計側で `process_cpu_by_drift_correction` を並べて比べる。
+    process_cpu_by_drift_correction: [CpuGauge; 3],
+    /// `--benchmark-drift-correction` で測った補正方式ごとのCPU使用率の目安（`DriftCorrection` の順）
+    drift_correction_benchmark: [CpuGauge; 3],
+}
+
+/// Lagged を記録する受信側の識別子
+#[derive(Debug, Clone, Copy)]
+pub enum LagReceiver {
+    Audio,
+    Interaction,
+    Upload,
+    Api,
+    Survey,
+    Peer,
+}
+
+/// ログ出力・シリアライズ用のスナップショット
+#[derive(Debug, Clone, Serialize)]
+pub struct MetricsSnapshot {
+    /// 集計側でユニットをまとめるためのラベル
+    pub labels: &'static UnitIdentity,
+    pub device_info_forwarded: u64,
+    pub device_info_coalesced: u64,
+    pub lagged_audio: u64,
+    pub lagged_interaction: u64,
+    pub lagged_upload: u64,
+    pub lagged_api: u64,
+    pub lagged_survey: u64,
+    pub lagged_peer: u64,
+    pub unknown_events: u64,
+    pub rejected_location_updates: u64,
+    pub se_triggers_dropped: u64,
+    pub server_connect_failures: u64,
+    pub server_offline_ms: u64,
+    pub upload_bytes: u64,
+    pub interaction_bytes: u64,
+    pub upload_batch_interval_ms: u64,
+    pub switch_build: HistogramSnapshot,
+    pub switch_seek: HistogramSnapshot,
+    pub switch_apply: HistogramSnapshot,
+    pub switch_total: HistogramSnapshot,
+    pub reaction_total: HistogramSnapshot,
+    pub reaction_forward: HistogramSnapshot,
+    pub reaction_select: HistogramSnapshot,
+    pub drift_correction: Option<DriftCorrection>,
+    pub sync_score: u64,
+    pub sync_drift_ema_ms: f64,
+    pub sync_reseeks: u64,
+    pub sync_tempo_excursions: u64,
+    pub process_cpu_percent: f64,
+    /// 補正方式（`pitch` / `scaletempo` / `seek_only`）ごとのCPU使用率（測ったことのある方式だけ）
+    pub process_cpu_by_drift_correction: BTreeMap<DriftCorrection, CpuGaugeSnapshot>,
+    /// 起動時のベンチマークで測った方式ごとのCPU使用率の目安（測った方式だけ）
+    pub drift_correction_benchmark: BTreeMap<DriftCorrection, CpuGaugeSnapshot>,
+}
+
+static METRICS: Metrics = Metrics {
+    device_info_forwarded: AtomicU64::new(0),
+    device_info_coalesced: AtomicU64::new(0),
+    lagged_audio: AtomicU64::new(0),
+    lagged_interaction: AtomicU64::new(0),
+    lagged_upload: AtomicU64::new(0),
+    lagged_api: AtomicU64::new(0),
+    lagged_survey: AtomicU64::new(0),
+    lagged_peer: AtomicU64::new(0),
+    unknown_events: AtomicU64::new(0),
+    rejected_location_updates: AtomicU64::new(0),
+    se_triggers_dropped: AtomicU64::new(0),
+    server_connect_failures: AtomicU64::new(0),
+    server_offline_ms: AtomicU64::new(0),
+    upload_bytes: AtomicU64::new(0),
+    interaction_bytes: AtomicU64::new(0),
+    upload_batch_interval_ms: AtomicU64::new(0),
+    switch_build: LatencyHistogram::new(),
+    switch_seek: LatencyHistogram::new(),
+    switch_apply: LatencyHistogram::new(),
+    switch_total: LatencyHistogram::new(),
+    reaction_total: LatencyHistogram::new(),
+    reaction_forward: LatencyHistogram::new(),
+    reaction_select: LatencyHistogram::new(),
+    drift_correction: OnceLock::new(),
+    sync_score: AtomicU64::new(100),
+    sync_drift_ema_us: AtomicU64::new(0),
+    sync_reseeks: AtomicU64::new(0),
+    sync_tempo_excursions: AtomicU64::new(0),
+    process_cpu_permille: AtomicU64::new(0),
+    process_cpu_by_drift_correction: [const { CpuGauge::new() }; 3],
+    drift_correction_benchmark: [const { CpuGauge::new() }; 3],
+};
+
+/// グローバルなカウンタ群を取得する
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+impl Metrics {
+    pub fn add(counter: &AtomicU64, n: u64) {
+        counter.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// broadcastの受信遅延を記録する（全受信側で共通の扱い）
+    pub fn record_lag(&self, receiver: LagReceiver, skipped: u64) {
+        let counter = match receiver {
+            LagReceiver::Audio => &self.lagged_audio,
+            LagReceiver::Interaction => &self.lagged_interaction,
+            LagReceiver::Upload => &self.lagged_upload,
+            LagReceiver::Api => &self.lagged_api,
+            LagReceiver::Survey => &self.lagged_survey,
+            LagReceiver::Peer => &self.lagged_peer,
+        };
+        let total = counter.fetch_add(skipped, Ordering::Relaxed) + skipped;
+        warn!(?receiver, skipped, total, "Broadcast receiver lagged");
+    }
+
+    /// オーディオタスクの起動時に補正方式を記録する
+    pub fn set_drift_correction(&self, mode: DriftCorrection) {
+        let _ = self.drift_correction.set(mode);
+    }
+
+    pub fn set_sync_quality(&self, quality: &SyncQualitySnapshot) {
+        self.sync_score.store(quality.score as u64, Ordering::Relaxed);
+        self.sync_drift_ema_us.store((quality.ema_abs_drift_ms * 1000.0) as u64, Ordering::Relaxed);
+        self.sync_reseeks.store(quality.reseeks, Ordering::Relaxed);
+        self.sync_tempo_excursions.store(quality.tempo_excursions, Ordering::Relaxed);
+    }
+
+    /// プロセスのCPU使用率を記録する（補正方式が決まっていれば、その方式のゲージにも加える）
+    pub fn set_process_cpu(&self, percent: f32) {
+        let permille = (percent.max(0.0) * 10.0) as u64;
+        self.process_cpu_permille.store(permille, Ordering::Relaxed);
+        if let Some(&mode) = self.drift_correction.get() {
+            self.process_cpu_by_drift_correction[mode as usize].observe(permille);
+        }
+    }
+
+    /// 補正方式のベンチマークの結果（1コアあたりのCPU使用率の目安）を記録する
+    pub fn record_drift_correction_benchmark(&self, mode: DriftCorrection, percent: f64) {
+        self.drift_correction_benchmark[mode as usize].observe((percent.max(0.0) * 10.0) as u64);
+    }
+
+    pub fn set_server_offline(&self, offline: Duration) {
+        self.server_offline_ms.store(offline.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    pub fn set_upload_batch_interval(&self, interval: Duration) {
+        self.upload_batch_interval_ms.store(interval.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            device_info_forwarded: self.device_info_forwarded.load(Ordering::Relaxed),
+            device_info_coalesced: self.device_info_coalesced.load(Ordering::Relaxed),
+            lagged_audio: self.lagged_audio.load(Ordering::Relaxed),
+            lagged_interaction: self.lagged_interaction.load(Ordering::Relaxed),
+            lagged_upload: self.lagged_upload.load(Ordering::Relaxed),
+            lagged_api: self.lagged_api.load(Ordering::Relaxed),
+            lagged_survey: self.lagged_survey.load(Ordering::Relaxed),
+            lagged_peer: self.lagged_peer.load(Ordering::Relaxed),
+            unknown_events: self.unknown_events.load(Ordering::Relaxed),
+            rejected_location_updates: self.rejected_location_updates.load(Ordering::Relaxed),
+            se_triggers_dropped: self.se_triggers_dropped.load(Ordering::Relaxed),
+            server_connect_failures: self.server_connect_failures.load(Ordering::Relaxed),
+            server_offline_ms: self.server_offline_ms.load(Ordering::Relaxed),
+            upload_bytes: self.upload_bytes.load(Ordering::Relaxed),
+            interaction_bytes: self.interaction_bytes.load(Ordering::Relaxed),
+            upload_batch_interval_ms: self.upload_batch_interval_ms.load(Ordering::Relaxed),
+            labels: unit_identity(),
+            switch_build: self.switch_build.snapshot(),
+            switch_seek: self.switch_seek.snapshot(),
+            switch_apply: self.switch_apply.snapshot(),
+            switch_total: self.switch_total.snapshot(),
+            reaction_total: self.reaction_total.snapshot(),
+            reaction_forward: self.reaction_forward.snapshot(),
+            reaction_select: self.reaction_select.snapshot(),
+            drift_correction: self.drift_correction.get().copied(),
+            sync_score: self.sync_score.load(Ordering::Relaxed),
+            sync_drift_ema_ms: self.sync_drift_ema_us.load(Ordering::Relaxed) as f64 / 1000.0,
+            sync_reseeks: self.sync_reseeks.load(Ordering::Relaxed),
+            sync_tempo_excursions: self.sync_tempo_excursions.load(Ordering::Relaxed),
+            process_cpu_percent: self.process_cpu_permille.load(Ordering::Relaxed) as f64 / 10.0,
+            process_cpu_by_drift_correction: DriftCorrection::ALL
+                .into_iter()
+                .filter_map(|mode| Some((mode, self.process_cpu_by_drift_correction[mode as usize].snapshot()?)))
+                .collect(),
+            drift_correction_benchmark: DriftCorrection::ALL
+                .into_iter()
+                .filter_map(|mode| Some((mode, self.drift_correction_benchmark[mode as usize].snapshot()?)))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpu_gauge_is_empty_until_observed() {
+        let gauge = CpuGauge::new();
+        assert!(gauge.snapshot().is_none());
+        gauge.observe(200);
+        gauge.observe(400);
+        let snapshot = gauge.snapshot().unwrap();
+        assert_eq!(snapshot.latest_percent, 40.0);
+        assert_eq!(snapshot.mean_percent, 30.0);
+        assert_eq!(snapshot.samples, 2);
+    }
+
+    #[test]
+    fn process_cpu_is_labelled_by_drift_correction() {
+        let metrics = metrics();
+        metrics.set_drift_correction(DriftCorrection::Scaletempo);
+        let mode = metrics.drift_correction.get().copied().unwrap();
+        metrics.set_process_cpu(12.5);
+        let json = serde_json::to_value(metrics.snapshot()).unwrap();
+        let gauges = json["process_cpu_by_drift_correction"].as_object().unwrap();
+        assert_eq!(gauges.keys().collect::<Vec<_>>(), [serde_json::to_value(mode).unwrap().as_str().unwrap()]);
+        assert!(gauges.values().all(|gauge| gauge["samples"].as_u64().unwrap() >= 1));
+    }
+
+    #[test]
+    fn drift_correction_benchmark_is_reported_per_mode() {
+        let metrics = metrics();
+        metrics.record_drift_correction_benchmark(DriftCorrection::SeekOnly, 1.25);
+        let json = serde_json::to_value(metrics.snapshot()).unwrap();
+        let gauge = &json["drift_correction_benchmark"]["seek_only"];
+        assert!(gauge["samples"].as_u64().unwrap() >= 1);
+        assert!(json["drift_correction_benchmark"].get("pitch").is_none());
+    }
+}
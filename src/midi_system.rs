@@ -0,0 +1 @@
+pub mod midi_main;
\ No newline at end of file
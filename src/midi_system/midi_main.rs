@@ -0,0 +1,219 @@
+use crate::audio_system::playback_status::{PlaybackStatus, PositionAnchor};
+use crate::config_system::config_main::{MidiConfig, MidiSyncMode};
+use anyhow::{anyhow, bail, Result};
+use midir::os::unix::VirtualOutput;
+use midir::{MidiOutput, MidiOutputConnection};
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{debug, info, instrument, warn};
+
+/// 再生位置を確認する間隔（クォーターフレーム・クロックの間隔より十分短くする）
+const POLL_INTERVAL: Duration = Duration::from_millis(2);
+
+/// この数を超えてクォーターフレーム・クロックを飛び越えたら、追いかけずに位置を送り直す
+const MAX_CATCH_UP: u64 = 8;
+
+const MIDI_QUARTER_FRAME: u8 = 0xF1;
+const MIDI_SONG_POSITION: u8 = 0xF2;
+const MIDI_CLOCK: u8 = 0xF8;
+const MIDI_CONTINUE: u8 = 0xFB;
+const MIDI_STOP: u8 = 0xFC;
+
+/// MIDIクロックの4分音符あたりのパルス数
+const CLOCKS_PER_BEAT: f64 = 24.0;
+
+/// MTCの時・分・秒・フレーム
+#[derive(Debug, Clone, Copy)]
+struct Timecode {
+    hours: u8,
+    minutes: u8,
+    seconds: u8,
+    frames: u8,
+}
+
+impl Timecode {
+    fn from_frames(total_frames: u64, fps: u8) -> Self {
+        let fps = fps as u64;
+        let total_seconds = total_frames / fps;
+        Self {
+            hours: ((total_seconds / 3600) % 24) as u8,
+            minutes: ((total_seconds / 60) % 60) as u8,
+            seconds: (total_seconds % 60) as u8,
+            frames: (total_frames % fps) as u8,
+        }
+    }
+
+    /// 位置を知らせるフルフレームメッセージ（SysEx）
+    fn full_frame(&self, rate: u8) -> [u8; 10] {
+        [0xF0, 0x7F, 0x7F, 0x01, 0x01, (rate << 5) | self.hours, self.minutes, self.seconds, self.frames, 0xF7]
+    }
+
+    /// 8分割したタイムコードのうち `piece` 番目を運ぶクォーターフレームメッセージ
+    fn quarter_frame(&self, rate: u8, piece: u8) -> [u8; 2] {
+        let value = match piece {
+            0 => self.frames & 0x0F,
+            1 => self.frames >> 4,
+            2 => self.seconds & 0x0F,
+            3 => self.seconds >> 4,
+            4 => self.minutes & 0x0F,
+            5 => self.minutes >> 4,
+            6 => self.hours & 0x0F,
+            _ => (self.hours >> 4) | (rate << 1),
+        };
+        [MIDI_QUARTER_FRAME, (piece << 4) | value]
+    }
+}
+
+/// MTCのフレームレートを表すコード
+fn mtc_rate_code(fps: u8) -> Result<u8> {
+    match fps {
+        24 => Ok(0),
+        25 => Ok(1),
+        30 => Ok(3),
+        other => bail!("Unsupported MTC frame rate: {} (use 24, 25 or 30)", other),
+    }
+}
+
+/// 再生位置を同期信号に変換して送る
+///
+/// MTCはクォーターフレーム、MIDIクロックはクロックパルスを1単位とし、
+/// 再生位置から求めた単位の通し番号が進むたびに1つずつ送る。
+struct SyncOutput {
+    connection: MidiOutputConnection,
+    mode: MidiSyncMode,
+    fps: u8,
+    rate: u8,
+    /// 送信中のタイムコード（クォーターフレームの0番目で更新する）
+    latched: Option<Timecode>,
+    running: bool,
+}
+
+impl SyncOutput {
+    fn send(&mut self, message: &[u8]) {
+        if let Err(e) = self.connection.send(message) {
+            warn!("Failed to send MIDI message: {}", e);
+        }
+    }
+
+    /// 指定した単位の位置から送り直す（再生開始・シーク・曲の切り替え時）
+    fn locate(&mut self, index: u64) {
+        debug!(index, mode = ?self.mode, "MIDI sync: locating");
+        match self.mode {
+            MidiSyncMode::Mtc => {
+                let timecode = Timecode::from_frames(index / 4, self.fps);
+                let message = timecode.full_frame(self.rate);
+                self.send(&message);
+                // 次の0番目のクォーターフレームから送り始める
+                self.latched = None;
+            }
+            MidiSyncMode::Clock => {
+                // ソングポジションは16分音符（6クロック）単位
+                let position = (index / 6).min(0x3FFF) as u16;
+                self.send(&[MIDI_STOP]);
+                self.send(&[MIDI_SONG_POSITION, (position & 0x7F) as u8, (position >> 7) as u8]);
+                self.send(&[MIDI_CONTINUE]);
+            }
+        }
+        self.running = true;
+    }
+
+    /// 1単位進める
+    fn step(&mut self, index: u64) {
+        match self.mode {
+            MidiSyncMode::Mtc => {
+                let piece = (index % 8) as u8;
+                if piece == 0 {
+                    self.latched = Some(Timecode::from_frames(index / 4, self.fps));
+                }
+                if let Some(timecode) = self.latched {
+                    let message = timecode.quarter_frame(self.rate, piece);
+                    self.send(&message);
+                }
+            }
+            MidiSyncMode::Clock => self.send(&[MIDI_CLOCK]),
+        }
+    }
+
+    /// 再生停止を知らせる（MTCは送信を止めるだけでよい）
+    fn stop(&mut self) {
+        if !self.running {
+            return;
+        }
+        debug!("MIDI sync: stopped");
+        if self.mode == MidiSyncMode::Clock {
+            self.send(&[MIDI_STOP]);
+        }
+        self.running = false;
+    }
+}
+
+/// 同期した再生位置をALSAの仮想MIDIポートにMTCまたはMIDIクロックとして出力する
+///
+/// タイミングの精度を保つため、専用のスレッドで短い間隔のポーリングを行う。
+/// 再生状況のチャンネルが閉じられたら終了する。
+#[instrument(skip_all)]
+pub fn midi_main(config: MidiConfig, mut status_rx: watch::Receiver<PlaybackStatus>) -> Result<()> {
+    let rate = mtc_rate_code(config.fps)?;
+    if config.mode == MidiSyncMode::Clock && config.bpm <= 0.0 {
+        bail!("MIDI clock tempo must be positive: {}", config.bpm);
+    }
+    let units_per_second = match config.mode {
+        MidiSyncMode::Mtc => config.fps as f64 * 4.0,
+        MidiSyncMode::Clock => config.bpm * CLOCKS_PER_BEAT / 60.0,
+    };
+
+    let output = MidiOutput::new("tsukimi-speaker").map_err(|e| anyhow!("Failed to initialize MIDI output: {}", e))?;
+    let connection = output
+        .create_virtual(&config.port_name)
+        .map_err(|e| anyhow!("Failed to create virtual MIDI port {}: {}", config.port_name, e))?;
+    info!(port = %config.port_name, mode = ?config.mode, "MIDI sync output started");
+
+    let mut sync = SyncOutput {
+        connection,
+        mode: config.mode,
+        fps: config.fps,
+        rate,
+        latched: None,
+        running: false,
+    };
+    let mut anchor = PositionAnchor::from_status(&status_rx.borrow_and_update());
+    // 直前に送った単位（サウンドファイル, 通し番号）
+    let mut last: Option<(String, u64)> = None;
+
+    loop {
+        match status_rx.has_changed() {
+            Ok(true) => anchor = PositionAnchor::from_status(&status_rx.borrow_and_update()),
+            Ok(false) => {}
+            Err(_) => break,
+        }
+
+        match anchor.as_ref() {
+            None => {
+                sync.stop();
+                last = None;
+            }
+            Some(anchor) => {
+                let index = (anchor.position_ns() as f64 / 1e9 * units_per_second) as u64;
+                match last.as_mut() {
+                    Some((sound, last_index)) if *sound == anchor.sound && index.abs_diff(*last_index) <= MAX_CATCH_UP => {
+                        // 補間した位置が実際の位置より進んでいた場合は、追いつかれるまで待つ
+                        while *last_index < index {
+                            *last_index += 1;
+                            sync.step(*last_index);
+                        }
+                    }
+                    _ => {
+                        sync.locate(index);
+                        last = Some((anchor.sound.clone(), index));
+                    }
+                }
+            }
+        }
+
+        std::thread::sleep(POLL_INTERVAL);
+    }
+
+    sync.stop();
+    info!("MIDI sync output stopped");
+    Ok(())
+}
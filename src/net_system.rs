@@ -0,0 +1,3 @@
+pub mod egress;
+pub mod pinning;
+pub mod proxy;
\ No newline at end of file
@@ -0,0 +1,121 @@
+use crate::config_system::config_main::{PinningConfig, ProxyConfig};
+use crate::net_system::pinning;
+use crate::net_system::proxy::Proxy;
+use anyhow::{anyhow, Result};
+use hyper_util::rt::TokioIo;
+use rustls::{ClientConfig, ServerName};
+use std::sync::{Arc, OnceLock};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use tonic::transport::{Channel, Endpoint, Uri};
+use tracing::{info, warn};
+
+static EGRESS: OnceLock<Egress> = OnceLock::new();
+
+/// gRPCのチャンネルに使う接続（平文のTCPまたはTLS）
+trait Io: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Io for T {}
+
+/// 外向き通信の設定（プロキシ・証明書のピンニング）を反映した接続の作り方
+///
+/// 起動時に `init` で1回だけ作り、HTTPクライアントはプロセス全体で共有する。
+struct Egress {
+    proxy: Option<Proxy>,
+    /// `https` のgRPCに使うTLSの設定（ALPNで `h2` を合意する）
+    grpc_tls: Arc<ClientConfig>,
+    http_client: reqwest::Client,
+}
+
+impl Egress {
+    fn new(proxy_config: &ProxyConfig, pinning_config: &PinningConfig) -> Self {
+        let proxy = proxy_config.url.as_deref().filter(|url| !url.is_empty()).and_then(|url| match Proxy::parse(url, &proxy_config.no_proxy) {
+            Ok(proxy) => {
+                info!(%proxy, "Using outbound proxy");
+                Some(proxy)
+            }
+            Err(e) => {
+                warn!("Ignoring proxy config - connecting directly: {:?}", e);
+                None
+            }
+        });
+
+        // ピンが不正なホストへの接続は拒否する（`report_only` の場合は警告だけ出して接続する）
+        let grpc_tls = Arc::new(pinning::client_config(pinning_config, &[b"h2"]));
+        if !pinning_config.pins.is_empty() {
+            let hosts: Vec<&String> = pinning_config.pins.keys().collect();
+            info!(?hosts, report_only = pinning_config.report_only, "Certificate pinning enabled");
+        }
+
+        let mut builder = reqwest::Client::builder();
+        if let Some(proxy) = &proxy {
+            match proxy.reqwest_proxy() {
+                Ok(reqwest_proxy) => builder = builder.proxy(reqwest_proxy),
+                Err(e) => warn!("HTTP client will not use the proxy: {}", e),
+            }
+        }
+        // ピンが無い場合はOSの証明書ストアを使う通常のTLSのまま
+        if !pinning_config.pins.is_empty() {
+            let mut tls = (*grpc_tls).clone();
+            tls.alpn_protocols.clear();
+            builder = builder.use_preconfigured_tls(tls);
+        }
+        let http_client = builder.build().unwrap_or_else(|e| {
+            warn!("Failed to build HTTP client - using defaults: {}", e);
+            reqwest::Client::new()
+        });
+
+        Self { proxy, grpc_tls, http_client }
+    }
+
+    /// gRPCの接続先へのTCP接続を張り、`https` ならTLSを確立する
+    async fn connect(&self, uri: Uri) -> Result<TokioIo<Box<dyn Io>>> {
+        let host = uri.host().ok_or_else(|| anyhow!("gRPC URL has no host: {}", uri))?.trim_start_matches('[').trim_end_matches(']').to_string();
+        let https = uri.scheme_str() == Some("https");
+        let port = uri.port_u16().unwrap_or(if https { 443 } else { 80 });
+        let stream = match &self.proxy {
+            Some(proxy) => proxy.connect(&host, port).await?,
+            None => TcpStream::connect((host.as_str(), port)).await?,
+        };
+        stream.set_nodelay(true)?;
+        if !https {
+            return Ok(TokioIo::new(Box::new(stream)));
+        }
+
+        let tls = self.grpc_tls.clone();
+        let server_name = ServerName::try_from(host.as_str()).map_err(|e| anyhow!("Invalid server name `{}`: {}", host, e))?;
+        let stream = TlsConnector::from(tls).connect(server_name, stream).await?;
+        Ok(TokioIo::new(Box::new(stream)))
+    }
+}
+
+fn egress() -> &'static Egress {
+    EGRESS.get_or_init(|| Egress::new(&ProxyConfig::default(), &PinningConfig::default()))
+}
+
+/// 設定から外向き通信の設定を作る（起動時、HTTPの通信を始める前に1回だけ呼ぶ）
+pub fn init(proxy_config: &ProxyConfig, pinning_config: &PinningConfig) {
+    if EGRESS.set(Egress::new(proxy_config, pinning_config)).is_err() {
+        warn!("Outbound connection settings are already initialized - ignoring");
+    }
+}
+
+/// 設定されたプロキシ（未設定なら `None`）
+pub fn proxy() -> Option<&'static Proxy> {
+    egress().proxy.as_ref()
+}
+
+/// プロキシ・証明書のピンニングを反映したHTTPクライアント
+pub fn http_client() -> reqwest::Client {
+    egress().http_client.clone()
+}
+
+/// gRPCのチャンネルを接続する（プロキシ経由・`https` の場合は独自のコネクタを使う）
+pub async fn connect_grpc(endpoint: Endpoint) -> Result<Channel> {
+    if proxy().is_none() && endpoint.uri().scheme_str() != Some("https") {
+        return Ok(endpoint.connect().await?);
+    }
+    let connector = tower::service_fn(|uri: Uri| egress().connect(uri));
+    Ok(endpoint.connect_with_connector(connector).await?)
+}
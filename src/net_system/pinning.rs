@@ -0,0 +1,308 @@
+use crate::config_system::config_main::PinningConfig;
+use anyhow::{anyhow, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use rustls::client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier};
+use rustls::{Certificate, ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tracing::{error, warn};
+
+/// ピンの書式の接頭辞（curlの `--pinnedpubkey` やHPKPと同じ）
+const PIN_PREFIX: &str = "sha256/";
+
+/// 公開鍵のハッシュ（SubjectPublicKeyInfoのSHA-256）
+type SpkiHash = [u8; 32];
+
+/// 通常の証明書チェーンの検証に加えて、ピンを設定したホストの公開鍵を確かめる
+struct PinnedVerifier {
+    inner: WebPkiVerifier,
+    pins: HashMap<String, Vec<SpkiHash>>,
+    report_only: bool,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(end_entity, intermediates, server_name, scts, ocsp_response, now)?;
+        let host = match server_name {
+            ServerName::DnsName(name) => name.as_ref().to_ascii_lowercase(),
+            ServerName::IpAddress(address) => address.to_string(),
+            _ => return Ok(verified),
+        };
+        let Some(pins) = self.pins.get(&host) else {
+            return Ok(verified);
+        };
+
+        if pinned_key_in_chain(pins, end_entity, intermediates, now) {
+            return Ok(verified);
+        }
+        let presented = spki_hash(&end_entity.0).map(|hash| format!("{}{}", PIN_PREFIX, BASE64.encode(hash)));
+        if self.report_only {
+            warn!(%host, ?presented, "Certificate pin mismatch (report only) - connecting anyway");
+            return Ok(verified);
+        }
+        error!(%host, ?presented, "Certificate pin mismatch - refusing connection (possible TLS interception)");
+        Err(rustls::Error::General(format!("certificate pin mismatch for {}", host)))
+    }
+}
+
+/// 通常の検証と同じ署名方式（rustlsの既定と同じ）
+static SIGNATURE_ALGORITHMS: &[&webpki::SignatureAlgorithm] = &[
+    &webpki::ECDSA_P256_SHA256,
+    &webpki::ECDSA_P256_SHA384,
+    &webpki::ECDSA_P384_SHA256,
+    &webpki::ECDSA_P384_SHA384,
+    &webpki::ED25519,
+    &webpki::RSA_PSS_2048_8192_SHA256_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA384_LEGACY_KEY,
+    &webpki::RSA_PSS_2048_8192_SHA512_LEGACY_KEY,
+    &webpki::RSA_PKCS1_2048_8192_SHA256,
+    &webpki::RSA_PKCS1_2048_8192_SHA384,
+    &webpki::RSA_PKCS1_2048_8192_SHA512,
+    &webpki::RSA_PKCS1_3072_8192_SHA384,
+];
+
+/// サーバー証明書、またはサーバー証明書から署名をたどれる証明書の公開鍵がピンと一致するか
+///
+/// サーバーは検証に使わない証明書も中間証明書として送れるため、送られてきた証明書を並べて
+/// 照合するだけでは、本物の証明書を添えるだけでピンを通ってしまう。中間証明書は、それを
+/// トラストアンカーとしてサーバー証明書を検証し直し、署名のつながりを確かめたものだけ照合する。
+fn pinned_key_in_chain(pins: &[SpkiHash], end_entity: &Certificate, intermediates: &[Certificate], now: SystemTime) -> bool {
+    if spki_hash(&end_entity.0).is_some_and(|hash| pins.contains(&hash)) {
+        return true;
+    }
+    let Ok(end_entity) = webpki::EndEntityCert::try_from(end_entity.0.as_slice()) else {
+        return false;
+    };
+    let Ok(time) = webpki::Time::try_from(now) else {
+        return false;
+    };
+    let chain: Vec<&[u8]> = intermediates.iter().map(|certificate| certificate.0.as_slice()).collect();
+    intermediates.iter().filter(|certificate| spki_hash(&certificate.0).is_some_and(|hash| pins.contains(&hash))).any(|certificate| {
+        let Ok(anchor) = webpki::TrustAnchor::try_from_cert_der(&certificate.0) else {
+            return false;
+        };
+        end_entity.verify_for_usage(SIGNATURE_ALGORITHMS, &[anchor], &chain, time, webpki::KeyUsage::server_auth(), &[]).is_ok()
+    })
+}
+
+/// DERの要素を1つ読み、(タグ, 要素全体の長さ, ヘッダーの長さ) を返す
+fn der_element(data: &[u8]) -> Option<(u8, usize, usize)> {
+    let tag = *data.first()?;
+    let first = *data.get(1)? as usize;
+    let (length, header) = if first < 0x80 {
+        (first, 2)
+    } else {
+        let count = first & 0x7f;
+        if count == 0 || count > 4 {
+            return None;
+        }
+        let bytes = data.get(2..2 + count)?;
+        (bytes.iter().fold(0_usize, |length, byte| (length << 8) | *byte as usize), 2 + count)
+    };
+    let total = header.checked_add(length)?;
+    (total <= data.len()).then_some((tag, total, header))
+}
+
+/// 証明書（DER）からSubjectPublicKeyInfoを取り出す
+fn subject_public_key_info(certificate: &[u8]) -> Option<&[u8]> {
+    let (_, total, header) = der_element(certificate)?;
+    let certificate = &certificate[header..total];
+    let (_, total, header) = der_element(certificate)?;
+    let mut fields = &certificate[header..total];
+    // version（[0]）は省略されることがある
+    if fields.first() == Some(&0xa0) {
+        let (_, total, _) = der_element(fields)?;
+        fields = &fields[total..];
+    }
+    // serialNumber・signature・issuer・validity・subject の次がSubjectPublicKeyInfo
+    for _ in 0..5 {
+        let (_, total, _) = der_element(fields)?;
+        fields = &fields[total..];
+    }
+    let (tag, total, _) = der_element(fields)?;
+    (tag == 0x30).then(|| &fields[..total])
+}
+
+fn spki_hash(certificate: &[u8]) -> Option<SpkiHash> {
+    subject_public_key_info(certificate).map(|spki| Sha256::digest(spki).into())
+}
+
+fn parse_pin(pin: &str) -> Result<SpkiHash> {
+    let encoded = pin.trim().strip_prefix(PIN_PREFIX).unwrap_or(pin.trim());
+    let decoded = BASE64.decode(encoded).map_err(|e| anyhow!("Invalid pin `{}`: {}", pin, e))?;
+    decoded.try_into().map_err(|_| anyhow!("Invalid pin `{}`: not a SHA-256 hash", pin))
+}
+
+/// ホストごとのピンを読む
+///
+/// ピンが無い・読めないホストはどの鍵とも一致しない空のリストにし、`report_only` でなければ
+/// そのホストへの接続を拒否する（設定の誤りでピンニングが外れて接続しないようにする）。
+fn parse_pins(config: &PinningConfig) -> HashMap<String, Vec<SpkiHash>> {
+    config
+        .pins
+        .iter()
+        .map(|(host, host_pins)| {
+            let hashes = if host_pins.is_empty() {
+                Err(anyhow!("No pins for `{}`", host))
+            } else {
+                host_pins.iter().map(|pin| parse_pin(pin)).collect::<Result<Vec<_>>>()
+            };
+            let hashes = hashes.unwrap_or_else(|e| {
+                error!(%host, report_only = config.report_only, "Invalid certificate pins - no certificate will match this host: {:?}", e);
+                Vec::new()
+            });
+            (host.to_ascii_lowercase(), hashes)
+        })
+        .collect()
+}
+
+fn pinned_verifier(roots: RootCertStore, config: &PinningConfig) -> PinnedVerifier {
+    PinnedVerifier { inner: WebPkiVerifier::new(roots, None), pins: parse_pins(config), report_only: config.report_only }
+}
+
+/// Mozillaのルート証明書で検証するTLSの設定（ピンがあればその確認も行う）
+///
+/// `alpn` はgRPC（`h2`）のように、接続時に合意するプロトコル。
+pub fn client_config(config: &PinningConfig, alpn: &[&[u8]]) -> ClientConfig {
+    let mut roots = RootCertStore::empty();
+    roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+        OwnedTrustAnchor::from_subject_spki_name_constraints(anchor.subject, anchor.spki, anchor.name_constraints)
+    }));
+
+    let mut client_config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(pinned_verifier(roots, config)))
+        .with_no_client_auth();
+    client_config.alpn_protocols = alpn.iter().map(|protocol| protocol.to_vec()).collect();
+    client_config
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    // testdata はopensslで作ったテスト用のCAと、`pinned.example`・`other.example` のサーバー証明書（有効期限は2126年まで）。
+    // ピンは `openssl x509 -pubkey -noout | openssl pkey -pubin -outform der | openssl dgst -sha256 -binary | base64` で求めた。
+    const CA: &[u8] = include_bytes!("testdata/ca.der");
+    const LEAF: &[u8] = include_bytes!("testdata/leaf.der");
+    /// 別のテスト用のCA（これも信頼する）と、それが署名した `pinned.example` の証明書（ピンの無い鍵）
+    const OTHER_CA: &[u8] = include_bytes!("testdata/other-ca.der");
+    const OTHER_LEAF: &[u8] = include_bytes!("testdata/other-leaf.der");
+    const CA_PIN: &str = "sha256/sWWgFk91Fmgfu7h6+NqeDHAd/nzCGMPzA75uvjlAAhA=";
+    const LEAF_PIN: &str = "sha256/Fs2nrJnqAPREjNpwjD+4QEFXzyWn3/HGDS4MSw1Hlr8=";
+    /// 別の鍵のピン（SHA-256の長さだけ合わせたもの）
+    const OTHER_PIN: &str = "sha256/AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+
+    fn config(pins: &[&str], report_only: bool) -> PinningConfig {
+        let pins = pins.iter().map(|pin| pin.to_string()).collect();
+        PinningConfig { pins: BTreeMap::from([("pinned.example".to_string(), pins)]), report_only }
+    }
+
+    /// テスト用のCAを両方信頼して、`end_entity` と中間証明書 `intermediates` を検証する
+    fn verify_chain(config: &PinningConfig, host: &str, end_entity: &[u8], intermediates: &[&[u8]]) -> Result<ServerCertVerified, rustls::Error> {
+        let mut roots = RootCertStore::empty();
+        roots.add(&Certificate(CA.to_vec())).unwrap();
+        roots.add(&Certificate(OTHER_CA.to_vec())).unwrap();
+        let intermediates: Vec<Certificate> = intermediates.iter().map(|certificate| Certificate(certificate.to_vec())).collect();
+        let server_name = ServerName::try_from(host).unwrap();
+        let now = UNIX_EPOCH + Duration::from_secs(1_900_000_000);
+        pinned_verifier(roots, config).verify_server_cert(&Certificate(end_entity.to_vec()), &intermediates, &server_name, &mut std::iter::empty(), &[], now)
+    }
+
+    /// サーバー証明書（中間証明書としてCAを添える）を検証する
+    fn verify(config: &PinningConfig, host: &str) -> Result<ServerCertVerified, rustls::Error> {
+        verify_chain(config, host, LEAF, &[CA])
+    }
+
+    #[test]
+    fn spki_hash_matches_openssl() {
+        assert_eq!(spki_hash(LEAF), Some(parse_pin(LEAF_PIN).unwrap()));
+        assert_eq!(spki_hash(CA), Some(parse_pin(CA_PIN).unwrap()));
+    }
+
+    #[test]
+    fn der_element_reads_short_and_long_lengths() {
+        assert_eq!(der_element(&[0x04, 0x02, 0xaa, 0xbb]), Some((0x04, 4, 2)));
+        let long = [&[0x04, 0x81, 0x80][..], &[0; 0x80]].concat();
+        assert_eq!(der_element(&long), Some((0x04, 0x83, 3)));
+        // 証明書全体は2バイトの長さを持つSEQUENCE
+        assert_eq!(der_element(LEAF), Some((0x30, LEAF.len(), 4)));
+    }
+
+    #[test]
+    fn malformed_der_is_rejected() {
+        assert_eq!(der_element(&[]), None);
+        assert_eq!(der_element(&[0x30]), None);
+        // 長さのバイト数が0・5以上・不足
+        assert_eq!(der_element(&[0x30, 0x80]), None);
+        assert_eq!(der_element(&[0x30, 0x85, 0, 0, 0, 0, 1]), None);
+        assert_eq!(der_element(&[0x30, 0x82, 0x01]), None);
+        // 長さが中身より長い
+        assert_eq!(der_element(&[0x04, 0x03, 0xaa]), None);
+
+        for length in [0, 1, 10, LEAF.len() / 2, LEAF.len() - 1] {
+            assert_eq!(subject_public_key_info(&LEAF[..length]), None, "truncated to {}", length);
+        }
+        assert_eq!(spki_hash(b"not a certificate"), None);
+    }
+
+    #[test]
+    fn pins_are_parsed_with_or_without_the_prefix() {
+        assert_eq!(parse_pin(LEAF_PIN).unwrap(), parse_pin(LEAF_PIN.trim_start_matches(PIN_PREFIX)).unwrap());
+        assert!(parse_pin("sha256/not base64!").is_err());
+        // SHA-1の長さ（20バイト）
+        assert!(parse_pin("sha256/AAAAAAAAAAAAAAAAAAAAAAAAAAA=").is_err());
+    }
+
+    #[test]
+    fn matching_pin_in_the_chain_is_accepted() {
+        assert!(verify(&config(&[LEAF_PIN], false), "pinned.example").is_ok());
+        // 予備の鍵と並べたピン・中間証明書（CA）のピンでもよい
+        assert!(verify(&config(&[OTHER_PIN, LEAF_PIN], false), "pinned.example").is_ok());
+        assert!(verify(&config(&[CA_PIN], false), "pinned.example").is_ok());
+    }
+
+    #[test]
+    fn mismatching_pin_is_refused() {
+        assert!(verify(&config(&[OTHER_PIN], false), "pinned.example").is_err());
+        // ピンの無いホストは通常の検証だけ
+        assert!(verify(&config(&[OTHER_PIN], false), "other.example").is_ok());
+    }
+
+    #[test]
+    fn pinned_certificate_outside_the_chain_is_refused() {
+        // 別の信頼されたCAの証明書に、本物のサーバー証明書やピンのCAを中間証明書として添えても通さない
+        assert!(verify_chain(&config(&[], false), "other.example", OTHER_LEAF, &[LEAF, CA]).is_ok());
+        assert!(verify_chain(&config(&[LEAF_PIN], false), "pinned.example", OTHER_LEAF, &[LEAF]).is_err());
+        assert!(verify_chain(&config(&[CA_PIN], false), "pinned.example", OTHER_LEAF, &[CA]).is_err());
+        assert!(verify_chain(&config(&[CA_PIN], false), "pinned.example", OTHER_LEAF, &[OTHER_CA, CA]).is_err());
+        // 署名をたどれるCAのピンなら、余分な証明書が混ざっていても通す
+        assert!(verify_chain(&config(&[CA_PIN], false), "pinned.example", LEAF, &[OTHER_CA, CA]).is_ok());
+    }
+
+    #[test]
+    fn mismatching_pin_is_accepted_in_report_only_mode() {
+        assert!(verify(&config(&[OTHER_PIN], true), "pinned.example").is_ok());
+    }
+
+    #[test]
+    fn invalid_pins_refuse_the_host() {
+        assert!(verify(&config(&[LEAF_PIN, "sha256/not base64!"], false), "pinned.example").is_err());
+        assert!(verify(&config(&[], false), "pinned.example").is_err());
+        assert!(verify(&config(&["sha256/not base64!"], true), "pinned.example").is_ok());
+        assert!(verify(&config(&["sha256/not base64!"], false), "other.example").is_ok());
+    }
+}
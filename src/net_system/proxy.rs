@@ -0,0 +1,130 @@
+use anyhow::{anyhow, bail, Context, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use std::fmt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_socks::tcp::Socks5Stream;
+use tracing::debug;
+
+/// CONNECTの応答ヘッダーの上限（これを超える応答はプロキシの異常として扱う）
+const MAX_CONNECT_RESPONSE: usize = 8 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ProxyScheme {
+    /// HTTP CONNECTでトンネルを張る
+    Http,
+    /// SOCKS5（接続先の名前解決はプロキシ側で行う）
+    Socks5,
+}
+
+/// 外向き通信のプロキシ
+#[derive(Clone)]
+pub struct Proxy {
+    /// 設定に書かれたURL（reqwestにそのまま渡す）
+    url: String,
+    scheme: ProxyScheme,
+    host: String,
+    port: u16,
+    credentials: Option<(String, String)>,
+    no_proxy: Vec<String>,
+}
+
+/// ログ用の表示（認証情報は含めない）
+impl fmt::Display for Proxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = match self.scheme {
+            ProxyScheme::Http => "http",
+            ProxyScheme::Socks5 => "socks5",
+        };
+        write!(f, "{}://{}:{}", scheme, self.host, self.port)?;
+        if !self.no_proxy.is_empty() {
+            write!(f, " (no_proxy: {})", self.no_proxy.join(","))?;
+        }
+        Ok(())
+    }
+}
+
+impl Proxy {
+    /// プロキシのURLと、プロキシを経由しないホストの一覧から作る
+    pub fn parse(url: &str, no_proxy: &[String]) -> Result<Self> {
+        let parsed = reqwest::Url::parse(url).with_context(|| format!("Invalid proxy URL: {}", url))?;
+        let scheme = match parsed.scheme() {
+            "http" => ProxyScheme::Http,
+            "socks5" | "socks5h" => ProxyScheme::Socks5,
+            other => bail!("Unsupported proxy scheme `{}` (use http, socks5 or socks5h)", other),
+        };
+        let host = parsed.host_str().ok_or_else(|| anyhow!("Proxy URL has no host: {}", url))?.to_string();
+        let port = parsed.port().unwrap_or(match scheme {
+            ProxyScheme::Http => 8080,
+            ProxyScheme::Socks5 => 1080,
+        });
+        let credentials = (!parsed.username().is_empty())
+            .then(|| (parsed.username().to_string(), parsed.password().unwrap_or_default().to_string()));
+        let no_proxy = no_proxy.iter().map(|host| host.trim().trim_start_matches('.').to_ascii_lowercase()).filter(|host| !host.is_empty()).collect();
+        Ok(Self { url: url.to_string(), scheme, host, port, credentials, no_proxy })
+    }
+
+    /// HTTPクライアントに設定するプロキシ
+    pub fn reqwest_proxy(&self) -> Result<reqwest::Proxy> {
+        let no_proxy = reqwest::NoProxy::from_string(&self.no_proxy.join(","));
+        Ok(reqwest::Proxy::all(&self.url)?.no_proxy(no_proxy))
+    }
+
+    /// プロキシを経由せずに接続するホストかどうか
+    fn bypasses(&self, host: &str) -> bool {
+        let host = host.trim_start_matches('[').trim_end_matches(']').to_ascii_lowercase();
+        self.no_proxy.iter().any(|entry| entry == "*" || host == *entry || host.ends_with(&format!(".{}", entry)))
+    }
+
+    /// プロキシ経由で `host:port` へのTCP接続を張る（`no_proxy` のホストには直接接続する）
+    pub async fn connect(&self, host: &str, port: u16) -> Result<TcpStream> {
+        if self.bypasses(host) {
+            debug!(host, port, "Connecting directly (no_proxy)");
+            return Ok(TcpStream::connect((host, port)).await?);
+        }
+        debug!(host, port, proxy = %self.host, "Connecting through proxy");
+        match self.scheme {
+            ProxyScheme::Http => self.connect_http(host, port).await,
+            ProxyScheme::Socks5 => {
+                let proxy = (self.host.as_str(), self.port);
+                let stream = match &self.credentials {
+                    Some((user, password)) => Socks5Stream::connect_with_password(proxy, (host, port), user, password).await,
+                    None => Socks5Stream::connect(proxy, (host, port)).await,
+                }
+                .with_context(|| format!("SOCKS5 proxy {}:{} failed to connect to {}:{}", self.host, self.port, host, port))?;
+                Ok(stream.into_inner())
+            }
+        }
+    }
+
+    async fn connect_http(&self, host: &str, port: u16) -> Result<TcpStream> {
+        let mut stream = TcpStream::connect((self.host.as_str(), self.port))
+            .await
+            .with_context(|| format!("Failed to connect to proxy {}:{}", self.host, self.port))?;
+
+        let mut request = format!("CONNECT {host}:{port} HTTP/1.1\r\nHost: {host}:{port}\r\n");
+        if let Some((user, password)) = &self.credentials {
+            request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", BASE64.encode(format!("{}:{}", user, password))));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await?;
+
+        // トンネルの先のデータを読み込まないよう、ヘッダーの終わりまで1バイトずつ読む
+        let mut response = Vec::new();
+        while !response.ends_with(b"\r\n\r\n") {
+            if response.len() >= MAX_CONNECT_RESPONSE {
+                bail!("Proxy CONNECT response is too long");
+            }
+            let byte = stream.read_u8().await.context("Proxy closed the connection during CONNECT")?;
+            response.push(byte);
+        }
+        let response = String::from_utf8_lossy(&response);
+        let status_line = response.lines().next().unwrap_or_default();
+        let status = status_line.split_whitespace().nth(1).unwrap_or_default();
+        if !status.starts_with('2') {
+            bail!("Proxy refused CONNECT to {}:{}: {}", host, port, status_line);
+        }
+        Ok(stream)
+    }
+}
@@ -0,0 +1 @@
+pub mod osc_main;
\ No newline at end of file
@@ -0,0 +1,138 @@
+use crate::audio_system::playback_status::{PlaybackStatus, PositionAnchor};
+use crate::config_system::config_main::OscConfig;
+use crate::event_system::event_bus::{EventBus, LocalEvent};
+use anyhow::{Context, Result};
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::{broadcast, watch};
+use tracing::{debug, info, instrument, warn};
+
+/// OSCメッセージの引数
+#[derive(Debug, Clone)]
+enum OscArg {
+    Int(i32),
+    Float(f32),
+    Str(String),
+}
+
+/// OSCの文字列（NUL終端し、4バイト境界までNULで埋める）を書き込む
+fn write_osc_string(buf: &mut Vec<u8>, text: &str) {
+    buf.extend_from_slice(text.as_bytes());
+    let padding = 4 - text.len() % 4;
+    buf.resize(buf.len() + padding, 0);
+}
+
+/// OSC 1.0のメッセージをエンコードする
+fn encode_message(address: &str, args: &[OscArg]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    write_osc_string(&mut buf, address);
+
+    let mut type_tags = String::from(",");
+    for arg in args {
+        type_tags.push(match arg {
+            OscArg::Int(_) => 'i',
+            OscArg::Float(_) => 'f',
+            OscArg::Str(_) => 's',
+        });
+    }
+    write_osc_string(&mut buf, &type_tags);
+
+    for arg in args {
+        match arg {
+            OscArg::Int(value) => buf.extend_from_slice(&value.to_be_bytes()),
+            OscArg::Float(value) => buf.extend_from_slice(&value.to_be_bytes()),
+            OscArg::Str(value) => write_osc_string(&mut buf, value),
+        }
+    }
+    buf
+}
+
+/// OSCメッセージの送信先
+struct OscSender {
+    socket: UdpSocket,
+    target: SocketAddr,
+    unit: String,
+}
+
+impl OscSender {
+    async fn send(&self, address_template: &str, args: &[OscArg]) {
+        let address = address_template.replace("{unit}", &self.unit);
+        let packet = encode_message(&address, args);
+        if let Err(e) = self.socket.send_to(&packet, self.target).await {
+            warn!(%address, "Failed to send OSC message: {}", e);
+        }
+    }
+}
+
+/// BGM切り替え・SE再生・再生位置・拍をOSCで送信するタスク
+///
+/// 各スピーカーの再生位置に照明や映像を追従させるため、ショーコントロール機器へ
+/// UDPでOSCメッセージを送る。再生位置は1秒ごとの再生状況を基準に補間する。
+#[instrument(skip_all)]
+pub async fn osc_main(
+    config: OscConfig,
+    events: EventBus,
+    mut status_rx: watch::Receiver<PlaybackStatus>,
+) -> Result<()> {
+    let mut event_rx = events.subscribe("osc");
+    let target = tokio::net::lookup_host((config.host.as_str(), config.port))
+        .await
+        .with_context(|| format!("Failed to resolve OSC target: {}:{}", config.host, config.port))?
+        .next()
+        .with_context(|| format!("No address found for OSC target: {}", config.host))?;
+    let socket = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0)).await?;
+    let sender = OscSender { socket, target, unit: config.unit.clone() };
+    info!(%target, unit = %config.unit, "OSC output started");
+
+    let mut anchor = PositionAnchor::from_status(&status_rx.borrow_and_update());
+    let mut last_beat: Option<(String, i64)> = None;
+    let mut tick = tokio::time::interval(Duration::from_millis(config.position_interval_ms.max(10)));
+    tick.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+    loop {
+        tokio::select! {
+            event = event_rx.recv() => match event {
+                Ok(LocalEvent::SoundSwitched { sound }) => {
+                    debug!(%sound, "OSC: BGM switched");
+                    sender.send(&config.addresses.bgm, &[OscArg::Str(sound)]).await;
+                }
+                Ok(LocalEvent::SePlayed { file }) => {
+                    debug!(%file, "OSC: SE played");
+                    sender.send(&config.addresses.se, &[OscArg::Str(file)]).await;
+                }
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!(skipped, "OSC output lagged - some events were not sent");
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            changed = status_rx.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                anchor = PositionAnchor::from_status(&status_rx.borrow_and_update());
+            }
+            _ = tick.tick() => {
+                let Some(anchor) = anchor.as_ref() else {
+                    last_beat = None;
+                    continue;
+                };
+                let position_ns = anchor.position_ns();
+                let position_s = position_ns as f64 / 1e9;
+                sender.send(&config.addresses.position, &[OscArg::Float(position_s as f32)]).await;
+
+                if let Some(bpm) = config.bpm.filter(|bpm| *bpm > 0.0) {
+                    let beat = (position_s * bpm / 60.0).floor() as i64;
+                    let current = (anchor.sound.clone(), beat);
+                    if last_beat.as_ref() != Some(&current) {
+                        sender.send(&config.addresses.beat, &[OscArg::Int(beat as i32)]).await;
+                        last_beat = Some(current);
+                    }
+                }
+            }
+        }
+    }
+    info!("OSC output stopped");
+    Ok(())
+}
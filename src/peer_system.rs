@@ -0,0 +1,2 @@
+pub mod peer_main;
+pub mod visitor_fusion;
\ No newline at end of file
@@ -0,0 +1,333 @@
+use crate::audio_system::playback_status::PlaybackStatus;
+use crate::clock_system::clock_main::Clock;
+use crate::clock_system::time_source::TimeSource;
+use crate::config_system::config_main::PeerConfig;
+use crate::messages::DeviceSnapshot;
+use crate::metrics_system::metrics_main::{metrics, LagReceiver};
+use crate::peer_system::visitor_fusion::{VisitorFusion, VisitorReading};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+use tokio::sync::{broadcast, watch};
+use tracing::{debug, info, instrument, warn};
+
+/// LAN経由で受け取った時刻の誤差の想定値
+const PEER_UNCERTAINTY_NS: u64 = 5_000_000;
+
+/// 受信するアナウンスの最大サイズ（来場者のRSSIを含めても収まる大きさ）
+const MAX_DATAGRAM_BYTES: usize = 8192;
+
+/// 近くのユニットと交換する状態（UDPマルチキャストでJSONを送る）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerAnnouncement {
+    /// ユニットID（BluetoothアドレスまたはプロセスID）
+    pub unit_id: String,
+    /// 送信時点での推定サーバー時刻（ナノ秒）
+    pub server_time_ns: i64,
+    /// 最後にバックエンドと時刻同期してからの経過時間（同期していない場合は `None`）
+    pub backend_sync_age_ms: Option<u64>,
+    pub sound: String,
+    pub position_ns: u64,
+    /// オフライン時の時刻マスターとして振る舞っているか
+    #[serde(default)]
+    pub time_master: bool,
+    /// 自ユニットが受信している来場者のRSSI（来場者の融合が有効な場合のみ）
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub visitors: Vec<VisitorReading>,
+}
+
+/// 自ユニットの時刻の拠り所
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TimeRole {
+    /// バックエンドのTimeServiceに同期している
+    Backend,
+    /// バックエンドと同期しているピアに従っている
+    BackendPeer(String),
+    /// オフライン時の時刻マスター（自分の推定時刻をそのまま使う）
+    Master,
+    /// 選出された時刻マスターに従っている
+    Follower(String),
+}
+
+/// 受信したピアの状態
+struct PeerState {
+    announcement: PeerAnnouncement,
+    /// 受信時点のローカル時刻（ナノ秒）
+    received_at_ns: i64,
+    received_at: Instant,
+}
+
+/// ピア同期タスク
+///
+/// バックエンドとの時刻同期が途絶えている間は、バックエンドと最も新しく同期している
+/// ピアの推定サーバー時刻を採用し、会場内のユニット同士のずれを抑える。
+/// どのユニットもバックエンドと同期していない場合は、ユニットIDが最小のユニットを
+/// 時刻マスターに選出し、他のユニットはマスターに従う。TimeServiceが復帰すると
+/// マスターは自動的に役割を返上する。
+///
+/// 来場者の融合が有効な場合は、受信した来場者のRSSIもアナウンスに載せて交換する。
+#[instrument(skip_all)]
+pub async fn peer_main(
+    config: PeerConfig,
+    clock: Arc<Clock>,
+    status_rx: watch::Receiver<PlaybackStatus>,
+    my_address: Arc<Mutex<Option<String>>>,
+    fusion: Option<Arc<VisitorFusion>>,
+    mut snapshot_rx: broadcast::Receiver<Arc<DeviceSnapshot>>,
+) -> Result<()> {
+    let group: SocketAddrV4 = config
+        .multicast_addr
+        .parse()
+        .with_context(|| format!("Invalid peer multicast address: {}", config.multicast_addr))?;
+    let socket = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, group.port())).await?;
+    socket.join_multicast_v4(*group.ip(), Ipv4Addr::UNSPECIFIED)?;
+    info!(%group, "Peer sync started");
+
+    let announce_interval = Duration::from_millis(config.announce_interval_ms.max(100));
+    let backend_stale = Duration::from_millis(config.backend_stale_ms);
+    let peer_timeout = announce_interval * 5;
+
+    let time = clock.time_source();
+    let mut peers: HashMap<String, PeerState> = HashMap::new();
+    let mut role = TimeRole::Backend;
+    let mut tick = tokio::time::interval(announce_interval);
+    let mut buf = vec![0u8; MAX_DATAGRAM_BYTES];
+    let mut snapshots_open = fusion.is_some();
+
+    loop {
+        tokio::select! {
+            _ = tick.tick() => {
+                let unit_id = unit_id(&my_address);
+                let backend_sync_age = clock.backend_sync_age();
+                let status = status_rx.borrow().clone();
+                let announcement = PeerAnnouncement {
+                    unit_id: unit_id.clone(),
+                    server_time_ns: time.unix_ns() + clock.offset_ns().unwrap_or(0),
+                    backend_sync_age_ms: backend_sync_age.map(|age| age.as_millis() as u64),
+                    sound: status.sound,
+                    position_ns: status.position_ns,
+                    time_master: role == TimeRole::Master,
+                    visitors: fusion.as_ref().map(|fusion| fusion.local_readings()).unwrap_or_default(),
+                };
+                match serde_json::to_vec(&announcement) {
+                    Ok(payload) => {
+                        if let Err(e) = socket.send_to(&payload, group).await {
+                            warn!("Failed to send peer announcement: {}", e);
+                        }
+                    }
+                    Err(e) => warn!("Failed to serialize peer announcement: {}", e),
+                }
+
+                expire_peers(&mut peers, time.as_ref(), peer_timeout);
+                let (new_role, source) = time_role(&peers, &unit_id, backend_sync_age, backend_stale);
+
+                if let Some(source) = source {
+                    let offset = source.announcement.server_time_ns - source.received_at_ns;
+                    clock.update_from_peer(offset, PEER_UNCERTAINTY_NS);
+                    debug!(
+                        source = %source.announcement.unit_id,
+                        offset_ms = offset / 1_000_000,
+                        "Adopted time from peer while backend is unavailable"
+                    );
+                }
+                if new_role != role {
+                    info!(from = ?role, to = ?new_role, peers = peers.len(), "Time authority changed");
+                    role = new_role;
+                }
+            }
+            result = socket.recv_from(&mut buf) => {
+                let (len, from) = match result {
+                    Ok(received) => received,
+                    Err(e) => {
+                        warn!("Failed to receive peer announcement: {}", e);
+                        continue;
+                    }
+                };
+                let received_at_ns = time.unix_ns();
+                match serde_json::from_slice::<PeerAnnouncement>(&buf[..len]) {
+                    Ok(announcement) => {
+                        if announcement.unit_id == unit_id(&my_address) {
+                            continue;
+                        }
+                        debug!(%from, ?announcement, "Peer announcement received");
+                        if !peers.contains_key(&announcement.unit_id) {
+                            info!(unit_id = %announcement.unit_id, %from, "Discovered peer unit");
+                        }
+                        if let Some(fusion) = &fusion {
+                            fusion.observe_peer(&announcement.unit_id, &announcement.visitors);
+                        }
+                        peers.insert(
+                            announcement.unit_id.clone(),
+                            PeerState { announcement, received_at_ns, received_at: time.now() },
+                        );
+                    }
+                    Err(e) => debug!(%from, "Ignoring malformed peer datagram: {}", e),
+                }
+            }
+            result = snapshot_rx.recv(), if snapshots_open => match result {
+                Ok(snapshot) => {
+                    if let Some(fusion) = &fusion {
+                        fusion.observe_local(&snapshot);
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    metrics().record_lag(LagReceiver::Peer, skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => snapshots_open = false,
+            },
+        }
+    }
+}
+
+/// 自ユニットのID（Bluetoothアドレスが未取得の場合はプロセスID）
+pub fn unit_id(my_address: &Mutex<Option<String>>) -> String {
+    my_address
+        .lock()
+        .unwrap()
+        .clone()
+        .unwrap_or_else(|| format!("pid-{}", std::process::id()))
+}
+
+/// 一定時間アナウンスが届いていないピアを忘れる
+fn expire_peers(peers: &mut HashMap<String, PeerState>, time: &dyn TimeSource, peer_timeout: Duration) {
+    peers.retain(|_, peer| time.elapsed(peer.received_at) < peer_timeout);
+}
+
+/// 自ユニットの時刻の拠り所と、時刻を採用するピアを決める
+///
+/// バックエンドとの同期が途絶えている場合のみピアの時刻を使う。
+fn time_role<'a>(
+    peers: &'a HashMap<String, PeerState>,
+    my_unit_id: &str,
+    backend_sync_age: Option<Duration>,
+    backend_stale: Duration,
+) -> (TimeRole, Option<&'a PeerState>) {
+    if backend_sync_age.is_some_and(|age| age < backend_stale) {
+        (TimeRole::Backend, None)
+    } else if let Some(source) = freshest_backend_peer(peers, my_unit_id, backend_stale) {
+        (TimeRole::BackendPeer(source.announcement.unit_id.clone()), Some(source))
+    } else {
+        // 誰もバックエンドと同期していないので、ユニットIDが最小のユニットをマスターにする
+        match elect_master(peers, my_unit_id) {
+            Some(leader) => (TimeRole::Follower(leader.announcement.unit_id.clone()), Some(leader)),
+            None => (TimeRole::Master, None),
+        }
+    }
+}
+
+/// オフライン時の時刻マスターを選出する（自分が選ばれた場合は `None`）
+///
+/// ユニットIDが最小のユニットがマスター。全ユニットが同じ規則で選ぶため、
+/// ピア一覧が揃っていれば合意のためのやり取りは不要。
+fn elect_master<'a>(peers: &'a HashMap<String, PeerState>, my_unit_id: &str) -> Option<&'a PeerState> {
+    peers
+        .values()
+        .filter(|peer| peer.announcement.unit_id.as_str() < my_unit_id)
+        .min_by(|a, b| a.announcement.unit_id.cmp(&b.announcement.unit_id))
+}
+
+/// バックエンドと最も新しく同期しているピアを選ぶ
+fn freshest_backend_peer<'a>(
+    peers: &'a HashMap<String, PeerState>,
+    my_unit_id: &str,
+    backend_stale: Duration,
+) -> Option<&'a PeerState> {
+    peers
+        .values()
+        .filter(|peer| peer.announcement.unit_id != my_unit_id)
+        .filter(|peer| {
+            peer.announcement
+                .backend_sync_age_ms
+                .is_some_and(|age| Duration::from_millis(age) < backend_stale)
+        })
+        .min_by_key(|peer| peer.announcement.backend_sync_age_ms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock_system::time_source::MockTimeSource;
+
+    const STALE: Duration = Duration::from_secs(30);
+
+    fn peer(unit_id: &str, backend_sync_age_ms: Option<u64>, received_at: Instant) -> (String, PeerState) {
+        let announcement = PeerAnnouncement {
+            unit_id: unit_id.to_string(),
+            server_time_ns: 0,
+            backend_sync_age_ms,
+            sound: String::new(),
+            position_ns: 0,
+            time_master: false,
+            visitors: Vec::new(),
+        };
+        (unit_id.to_string(), PeerState { announcement, received_at_ns: 0, received_at })
+    }
+
+    fn peers(list: Vec<(String, PeerState)>) -> HashMap<String, PeerState> {
+        list.into_iter().collect()
+    }
+
+    #[test]
+    fn lowest_unit_id_is_elected_master() {
+        let now = Instant::now();
+        let peers = peers(vec![peer("unit-c", None, now), peer("unit-a", None, now), peer("unit-d", None, now)]);
+        assert_eq!(elect_master(&peers, "unit-b").unwrap().announcement.unit_id, "unit-a");
+        assert_eq!(time_role(&peers, "unit-b", None, STALE).0, TimeRole::Follower("unit-a".to_string()));
+        // 自分のIDが最小なら自分がマスター
+        assert!(elect_master(&peers, "unit-0").is_none());
+        assert_eq!(time_role(&peers, "unit-0", None, STALE).0, TimeRole::Master);
+    }
+
+    #[test]
+    fn backend_synced_peer_wins_over_master() {
+        let now = Instant::now();
+        let synced = peers(vec![
+            peer("unit-a", None, now),
+            peer("unit-c", Some(20_000), now),
+            peer("unit-d", Some(5_000), now),
+            peer("unit-e", Some(40_000), now),
+        ]);
+        assert_eq!(freshest_backend_peer(&synced, "unit-b", STALE).unwrap().announcement.unit_id, "unit-d");
+        assert_eq!(time_role(&synced, "unit-0", None, STALE).0, TimeRole::BackendPeer("unit-d".to_string()));
+        // 同期が古すぎるピアしかいなければ選出に戻る
+        let stale = peers(vec![peer("unit-a", None, now), peer("unit-e", Some(40_000), now)]);
+        assert!(freshest_backend_peer(&stale, "unit-b", STALE).is_none());
+        assert_eq!(time_role(&stale, "unit-b", None, STALE).0, TimeRole::Follower("unit-a".to_string()));
+    }
+
+    #[test]
+    fn master_hands_back_authority_when_time_service_is_fresh() {
+        let now = Instant::now();
+        let peers = peers(vec![peer("unit-b", None, now)]);
+        assert_eq!(time_role(&peers, "unit-a", None, STALE).0, TimeRole::Master);
+        assert_eq!(time_role(&peers, "unit-a", Some(STALE + Duration::from_secs(1)), STALE).0, TimeRole::Master);
+
+        let (role, source) = time_role(&peers, "unit-a", Some(Duration::from_secs(1)), STALE);
+        assert_eq!(role, TimeRole::Backend);
+        assert!(source.is_none());
+    }
+
+    #[test]
+    fn timed_out_peers_are_ignored() {
+        let time = MockTimeSource::new();
+        let timeout = Duration::from_secs(5);
+        let mut peers = peers(vec![peer("unit-a", Some(1_000), time.now())]);
+        time.advance(Duration::from_secs(3));
+        peers.extend([peer("unit-b", None, time.now())]);
+
+        time.advance(Duration::from_secs(2));
+        expire_peers(&mut peers, &time, timeout);
+        assert!(!peers.contains_key("unit-a"));
+        // 同期していたピアが消えたので、残ったピアの中から選出する
+        assert_eq!(time_role(&peers, "unit-c", None, STALE).0, TimeRole::Follower("unit-b".to_string()));
+
+        time.advance(Duration::from_secs(3));
+        expire_peers(&mut peers, &time, timeout);
+        assert!(peers.is_empty());
+        assert_eq!(time_role(&peers, "unit-c", None, STALE).0, TimeRole::Master);
+    }
+}
@@ -0,0 +1,326 @@
+use crate::clock_system::time_source::SharedTimeSource;
+use crate::config_system::config_main::{PeerConfig, VisitorFusionStrategy};
+use crate::messages::DeviceSnapshot;
+use crate::peer_system::peer_main::unit_id;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, warn};
+
+/// 1回のアナウンスで共有する来場者の数の上限（RSSIの強い順。1つのデータグラムに収めるため）
+const MAX_SHARED_VISITORS: usize = 32;
+
+/// ユニットが受信した来場者の広告のRSSI（ピアのアナウンスで共有する）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VisitorReading {
+    pub token: String,
+    pub rssi: i16,
+}
+
+/// 来場者を受け持つユニットを選ぶ方式
+pub trait FusionStrategy: Send + Sync {
+    /// 各ユニットのRSSI（`(ユニットID, RSSI)`）から担当ユニットを選ぶ（`current` は前回選んだユニット）
+    fn select<'a>(&self, readings: &[(&'a str, i16)], current: Option<&str>) -> Option<&'a str>;
+}
+
+/// 最もRSSIが強いユニットが受け持つ（同じ値ならユニットIDが小さい方）
+pub struct StrongestRssi;
+
+impl FusionStrategy for StrongestRssi {
+    fn select<'a>(&self, readings: &[(&'a str, i16)], _current: Option<&str>) -> Option<&'a str> {
+        readings
+            .iter()
+            .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(a.0)))
+            .map(|(unit_id, _)| *unit_id)
+    }
+}
+
+/// 担当ユニットより `margin_db` 以上強く受信したユニットが現れるまで担当を変えない
+///
+/// 2台の中間に立つ来場者の担当が、RSSIの揺らぎで行き来しないようにする。
+pub struct StickyOwner {
+    pub margin_db: i16,
+}
+
+impl FusionStrategy for StickyOwner {
+    fn select<'a>(&self, readings: &[(&'a str, i16)], current: Option<&str>) -> Option<&'a str> {
+        let strongest = StrongestRssi.select(readings, current)?;
+        let Some(&(owner, owner_rssi)) = readings.iter().find(|(unit_id, _)| Some(*unit_id) == current) else {
+            return Some(strongest);
+        };
+        let strongest_rssi = readings.iter().find(|(unit_id, _)| *unit_id == strongest).map_or(owner_rssi, |(_, rssi)| *rssi);
+        if strongest_rssi >= owner_rssi.saturating_add(self.margin_db) {
+            Some(strongest)
+        } else {
+            Some(owner)
+        }
+    }
+}
+
+struct Reading {
+    rssi: i16,
+    at: Instant,
+}
+
+struct FusionState {
+    /// 来場者トークン -> ユニットID -> 最新のRSSI（自ユニットの分も含む）
+    readings: HashMap<String, HashMap<String, Reading>>,
+    /// 来場者トークン -> 前回選んだ担当ユニット
+    owners: HashMap<String, String>,
+}
+
+/// 近くのユニットと来場者のRSSIを持ち寄り、来場者ごとに受け持つユニットを1台に決める
+///
+/// 隣り合うユニットが同じ来場者を受信しても、パーソナライズしたインタラクションは担当のユニットだけが行う。
+/// 自ユニットが受信していない来場者は受け持たない。
+pub struct VisitorFusion {
+    strategy: Box<dyn FusionStrategy>,
+    ttl: Duration,
+    my_address: Arc<Mutex<Option<String>>>,
+    time: SharedTimeSource,
+    state: Mutex<FusionState>,
+}
+
+impl VisitorFusion {
+    /// 設定で来場者の融合が有効な場合のみ作る（ピア同期が無効なら共有できないので作らない）
+    pub fn from_config(config: &PeerConfig, my_address: Arc<Mutex<Option<String>>>, time: SharedTimeSource) -> Option<Arc<Self>> {
+        if !config.enabled {
+            if config.visitor_fusion != VisitorFusionStrategy::Off {
+                warn!("Visitor RSSI fusion requires peer sync (peer.enabled) - ignoring");
+            }
+            return None;
+        }
+        let strategy: Box<dyn FusionStrategy> = match config.visitor_fusion {
+            VisitorFusionStrategy::Off => return None,
+            VisitorFusionStrategy::StrongestRssi => Box::new(StrongestRssi),
+            VisitorFusionStrategy::Sticky => Box::new(StickyOwner { margin_db: config.visitor_handover_db }),
+        };
+        info!(strategy = ?config.visitor_fusion, "Visitor RSSI fusion enabled");
+        Some(Arc::new(Self {
+            strategy,
+            ttl: Duration::from_millis(config.visitor_reading_ttl_ms),
+            my_address,
+            time,
+            state: Mutex::new(FusionState { readings: HashMap::new(), owners: HashMap::new() }),
+        }))
+    }
+
+    /// 自ユニットが受信した来場者の広告を記録する
+    pub fn observe_local(&self, snapshot: &DeviceSnapshot) {
+        let my_unit_id = unit_id(&self.my_address);
+        let now = self.time.now();
+        let mut state = self.state.lock().unwrap();
+        for device in &snapshot.devices {
+            if let Some(token) = &device.visitor_token {
+                state
+                    .readings
+                    .entry(token.clone())
+                    .or_default()
+                    .insert(my_unit_id.clone(), Reading { rssi: device.rssi, at: now });
+            }
+        }
+    }
+
+    /// ピアのアナウンスに含まれていた読み取りを記録する（含まれなくなった来場者は期限切れで消える）
+    pub fn observe_peer(&self, peer_unit_id: &str, readings: &[VisitorReading]) {
+        let now = self.time.now();
+        let mut state = self.state.lock().unwrap();
+        for reading in readings {
+            state
+                .readings
+                .entry(reading.token.clone())
+                .or_default()
+                .insert(peer_unit_id.to_string(), Reading { rssi: reading.rssi, at: now });
+        }
+    }
+
+    /// アナウンスで共有する自ユニットの読み取り（期限切れの読み取りもここで捨てる）
+    pub fn local_readings(&self) -> Vec<VisitorReading> {
+        let my_unit_id = unit_id(&self.my_address);
+        let now = self.time.now();
+        let mut state = self.state.lock().unwrap();
+        let FusionState { readings, owners } = &mut *state;
+        readings.retain(|_, units| {
+            units.retain(|_, reading| now.saturating_duration_since(reading.at) < self.ttl);
+            !units.is_empty()
+        });
+        owners.retain(|token, _| readings.contains_key(token));
+
+        let mut local: Vec<VisitorReading> = readings
+            .iter()
+            .filter_map(|(token, units)| units.get(&my_unit_id).map(|reading| VisitorReading { token: token.clone(), rssi: reading.rssi }))
+            .collect();
+        local.sort_by_key(|reading| std::cmp::Reverse(reading.rssi));
+        local.truncate(MAX_SHARED_VISITORS);
+        local
+    }
+
+    /// 自ユニットがこの来場者を受け持つか
+    pub fn owns(&self, token: &str) -> bool {
+        let my_unit_id = unit_id(&self.my_address);
+        let now = self.time.now();
+        let mut state = self.state.lock().unwrap();
+        let FusionState { readings, owners } = &mut *state;
+        let Some(units) = readings.get(token) else {
+            return false;
+        };
+        let candidates: Vec<(&str, i16)> = units
+            .iter()
+            .filter(|(_, reading)| now.saturating_duration_since(reading.at) < self.ttl)
+            .map(|(unit_id, reading)| (unit_id.as_str(), reading.rssi))
+            .collect();
+        if !candidates.iter().any(|(unit_id, _)| *unit_id == my_unit_id) {
+            return false;
+        }
+        let Some(owner) = self.strategy.select(&candidates, owners.get(token).map(String::as_str)) else {
+            return false;
+        };
+        if owners.get(token).map(String::as_str) != Some(owner) {
+            debug!(token, owner, units = candidates.len(), "Visitor owner changed");
+            owners.insert(token.to_string(), owner.to_string());
+        }
+        owner == my_unit_id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock_system::time_source::MockTimeSource;
+    use crate::messages::DeviceInfo;
+
+    const ME: &str = "unit-a";
+    const TTL: Duration = Duration::from_millis(3000);
+
+    fn fusion(strategy: VisitorFusionStrategy) -> (Arc<VisitorFusion>, Arc<MockTimeSource>) {
+        let time = Arc::new(MockTimeSource::new());
+        let config = PeerConfig { enabled: true, visitor_fusion: strategy, visitor_handover_db: 4, visitor_reading_ttl_ms: TTL.as_millis() as u64, ..Default::default() };
+        let fusion = VisitorFusion::from_config(&config, Arc::new(Mutex::new(Some(ME.to_string()))), time.clone()).unwrap();
+        (fusion, time)
+    }
+
+    fn visitor(token: &str, rssi: i16) -> Arc<DeviceInfo> {
+        Arc::new(DeviceInfo {
+            address: "AA:BB:CC:DD:EE:01".parse().unwrap(),
+            rssi,
+            last_seen: Instant::now(),
+            received_at: Instant::now(),
+            forwarded_at: None,
+            visitor_token: Some(token.to_string()),
+            local_name: None,
+            tx_power: None,
+            manufacturer_data: HashMap::new(),
+            service_data: HashMap::new(),
+        })
+    }
+
+    fn observe(fusion: &VisitorFusion, token: &str, rssi: i16) {
+        fusion.observe_local(&DeviceSnapshot { devices: vec![visitor(token, rssi)] });
+    }
+
+    fn peer(token: &str, rssi: i16) -> Vec<VisitorReading> {
+        vec![VisitorReading { token: token.to_string(), rssi }]
+    }
+
+    #[test]
+    fn strongest_unit_owns_the_visitor() {
+        let (fusion, _) = fusion(VisitorFusionStrategy::StrongestRssi);
+        observe(&fusion, "visitor-1", -60);
+        assert!(fusion.owns("visitor-1"));
+
+        fusion.observe_peer("unit-b", &peer("visitor-1", -50));
+        assert!(!fusion.owns("visitor-1"));
+        fusion.observe_peer("unit-b", &peer("visitor-1", -70));
+        assert!(fusion.owns("visitor-1"));
+    }
+
+    #[test]
+    fn equal_rssi_goes_to_the_lower_unit_id() {
+        assert_eq!(StrongestRssi.select(&[("unit-b", -60), ("unit-a", -60), ("unit-c", -61)], None), Some("unit-a"));
+
+        let (fusion, _) = fusion(VisitorFusionStrategy::StrongestRssi);
+        observe(&fusion, "visitor-1", -60);
+        fusion.observe_peer("unit-0", &peer("visitor-1", -60));
+        assert!(!fusion.owns("visitor-1"));
+        fusion.observe_peer("unit-0", &peer("visitor-1", -61));
+        assert!(fusion.owns("visitor-1"));
+    }
+
+    #[test]
+    fn sticky_owner_keeps_the_visitor_within_the_margin() {
+        let sticky = StickyOwner { margin_db: 4 };
+        assert_eq!(sticky.select(&[("unit-a", -60), ("unit-b", -57)], Some("unit-a")), Some("unit-a"));
+        assert_eq!(sticky.select(&[("unit-a", -60), ("unit-b", -56)], Some("unit-a")), Some("unit-b"));
+        // 前回の担当がいなければ最も強いユニット
+        assert_eq!(sticky.select(&[("unit-a", -60), ("unit-b", -57)], Some("unit-c")), Some("unit-b"));
+
+        let (fusion, _) = fusion(VisitorFusionStrategy::Sticky);
+        observe(&fusion, "visitor-1", -60);
+        assert!(fusion.owns("visitor-1"));
+        fusion.observe_peer("unit-b", &peer("visitor-1", -58));
+        assert!(fusion.owns("visitor-1"));
+        fusion.observe_peer("unit-b", &peer("visitor-1", -55));
+        assert!(!fusion.owns("visitor-1"));
+        // 担当が移った後は、元のユニットが少し強くなっても戻らない
+        observe(&fusion, "visitor-1", -53);
+        assert!(!fusion.owns("visitor-1"));
+    }
+
+    #[test]
+    fn stale_readings_expire() {
+        let (fusion, time) = fusion(VisitorFusionStrategy::StrongestRssi);
+        observe(&fusion, "visitor-1", -60);
+        fusion.observe_peer("unit-b", &peer("visitor-1", -40));
+        assert!(!fusion.owns("visitor-1"));
+
+        // ピアのアナウンスが途絶えたら、自ユニットの新しい読み取りだけで判断する
+        time.advance(TTL - Duration::from_millis(1));
+        observe(&fusion, "visitor-1", -60);
+        assert!(!fusion.owns("visitor-1"));
+        time.advance(Duration::from_millis(1));
+        assert!(fusion.owns("visitor-1"));
+
+        // 自ユニットも受信しなくなったら受け持たず、共有もしない
+        time.advance(TTL);
+        assert!(!fusion.owns("visitor-1"));
+        assert!(fusion.local_readings().is_empty());
+    }
+
+    #[test]
+    fn no_readings_own_nothing() {
+        assert_eq!(StrongestRssi.select(&[], None), None);
+        assert_eq!(StickyOwner { margin_db: 4 }.select(&[], Some("unit-a")), None);
+
+        let (fusion, _) = fusion(VisitorFusionStrategy::StrongestRssi);
+        assert!(fusion.local_readings().is_empty());
+        assert!(!fusion.owns("visitor-1"));
+        fusion.observe_local(&DeviceSnapshot::default());
+        fusion.observe_peer("unit-b", &[]);
+        assert!(!fusion.owns("visitor-1"));
+        // ピアだけが受信している来場者は受け持たない
+        fusion.observe_peer("unit-b", &peer("visitor-2", -40));
+        assert!(!fusion.owns("visitor-2"));
+        assert!(fusion.local_readings().is_empty());
+    }
+
+    #[test]
+    fn local_readings_are_shared_strongest_first() {
+        let (fusion, _) = fusion(VisitorFusionStrategy::StrongestRssi);
+        let devices = (0..40).map(|i| visitor(&format!("visitor-{}", i), -90 + i as i16)).collect();
+        fusion.observe_local(&DeviceSnapshot { devices });
+        fusion.observe_peer("unit-b", &peer("visitor-peer", -30));
+        let shared = fusion.local_readings();
+        assert_eq!(shared.len(), MAX_SHARED_VISITORS);
+        assert_eq!((shared[0].token.as_str(), shared[0].rssi), ("visitor-39", -51));
+        assert!(shared.windows(2).all(|pair| pair[0].rssi >= pair[1].rssi));
+    }
+
+    #[test]
+    fn fusion_needs_peer_sync() {
+        let config = PeerConfig { enabled: false, visitor_fusion: VisitorFusionStrategy::StrongestRssi, ..Default::default() };
+        assert!(VisitorFusion::from_config(&config, Arc::new(Mutex::new(None)), Arc::new(MockTimeSource::new())).is_none());
+        let config = PeerConfig { enabled: true, ..Default::default() };
+        assert!(VisitorFusion::from_config(&config, Arc::new(Mutex::new(None)), Arc::new(MockTimeSource::new())).is_none());
+    }
+}
@@ -1 +1,11 @@
-pub mod proto;
\ No newline at end of file
+// 通常はbuild.rsがOUT_DIRに生成したコードを使う
+#[cfg(not(pregenerated_proto))]
+#[allow(clippy::module_inception)]
+pub mod proto {
+    tonic::include_proto!("proto");
+}
+
+// protocが使えない環境向けのコミット済みコード（src/proto/proto.rs）
+#[cfg(pregenerated_proto)]
+#[allow(clippy::module_inception)]
+pub mod proto;
@@ -1,5 +1,7 @@
 // This file is @generated by prost-build.
 /// LocationのRSSI情報
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 #[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
 pub struct LocationRssi {
     /// LocationのAddress
@@ -8,7 +10,65 @@ pub struct LocationRssi {
     #[prost(int32, tag = "2")]
     pub rssi: i32,
 }
+/// ユニットの識別情報（ダッシュボードで会場・エリアごとにまとめるため。未設定の項目は空）
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct UnitLabels {
+    #[prost(string, tag = "1")]
+    pub unit_id: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub venue: ::prost::alloc::string::String,
+    #[prost(string, tag = "3")]
+    pub zone: ::prost::alloc::string::String,
+    #[prost(string, tag = "4")]
+    pub hardware_revision: ::prost::alloc::string::String,
+}
+/// ユニットの再生状況（会場全体の同期確認用）
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct PlaybackStatus {
+    /// 再生中のサウンドファイル
+    #[prost(string, tag = "1")]
+    pub sound_id: ::prost::alloc::string::String,
+    /// 再生位置（ナノ秒）
+    #[prost(uint64, tag = "2")]
+    pub position_ns: u64,
+    /// サーバー時刻とのずれの推定値（ナノ秒）
+    #[prost(int64, tag = "3")]
+    pub drift_ns: i64,
+    /// システム有効化状態
+    #[prost(bool, tag = "4")]
+    pub enabled: bool,
+    /// 再生ライフサイクルの状態
+    #[prost(string, tag = "5")]
+    pub state: ::prost::alloc::string::String,
+    /// 時刻の拠り所（Unsynced / SystemNtp / Peer / TimeService）
+    #[prost(string, tag = "6")]
+    pub clock_source: ::prost::alloc::string::String,
+    /// 時刻の推定誤差（±ナノ秒）
+    #[prost(uint64, tag = "7")]
+    pub clock_uncertainty_ns: u64,
+    /// 最も近いロケーションのplace_type（未判定の場合は空）
+    #[prost(string, tag = "8")]
+    pub place_type: ::prost::alloc::string::String,
+    /// 報告したユニットの識別情報
+    #[prost(message, optional, tag = "9")]
+    pub unit: ::core::option::Option<UnitLabels>,
+    /// 同期の品質のスコア（0〜100、100が最良）
+    #[prost(uint32, tag = "10")]
+    pub sync_score: u32,
+    /// 大きなずれのため再シークした回数
+    #[prost(uint64, tag = "11")]
+    pub sync_reseeks: u64,
+    /// ずれを補正するためにテンポを変えた回数
+    #[prost(uint64, tag = "12")]
+    pub sync_tempo_excursions: u64,
+}
 /// クライアントからストリーミングされるメッセージ
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct StreamDeviceInfoRequest {
     /// ユーザーのID
@@ -16,8 +76,28 @@ pub struct StreamDeviceInfoRequest {
     pub user_id: ::prost::alloc::string::String,
     #[prost(message, repeated, tag = "2")]
     pub locations: ::prost::alloc::vec::Vec<LocationRssi>,
+    /// クライアントが理解できるスキーマのバージョン（ストリーム開始時のハンドシェイクで送信）
+    #[prost(uint32, tag = "3")]
+    pub schema_version: u32,
+    /// クライアントのバージョン文字列
+    #[prost(string, tag = "4")]
+    pub client_version: ::prost::alloc::string::String,
+    /// 定期的な再生状況の報告（locationsが空のメッセージで送信）
+    #[prost(message, optional, tag = "5")]
+    pub status: ::core::option::Option<PlaybackStatus>,
+    /// 最も近くにいる来場者のトークン（コンパニオンアプリが広告している場合のみ）
+    #[prost(string, tag = "6")]
+    pub visitor_token: ::prost::alloc::string::String,
+    /// FleetCommandの受領の通知（locationsが空のメッセージで送信）
+    #[prost(message, optional, tag = "7")]
+    pub fleet_ack: ::core::option::Option<FleetCommandAck>,
+    /// command_id付きのコマンドの実行結果（locationsが空のメッセージで送信）
+    #[prost(message, optional, tag = "8")]
+    pub command_result: ::core::option::Option<CommandResult>,
 }
 /// Locationの完全な情報を表すメッセージ
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 #[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
 pub struct LocationInfo {
     #[prost(string, tag = "1")]
@@ -28,15 +108,37 @@ pub struct LocationInfo {
     pub address: ::prost::alloc::string::String,
     #[prost(string, tag = "4")]
     pub place_type: ::prost::alloc::string::String,
+    /// このロケーションで再生する音源のURL（http(s)。空ならplace_typeとポイント数から決める）
+    #[prost(string, tag = "5")]
+    pub sound_url: ::prost::alloc::string::String,
 }
 /// Location更新イベント
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct LocationUpdate {
     /// 全ロケーションのリスト
     #[prost(message, repeated, tag = "1")]
     pub locations: ::prost::alloc::vec::Vec<LocationInfo>,
 }
+/// ポイント数の取得リクエスト
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct GetPointsRequest {
+    #[prost(string, tag = "1")]
+    pub user_id: ::prost::alloc::string::String,
+}
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct GetPointsResponse {
+    #[prost(int32, tag = "1")]
+    pub points: i32,
+}
 /// Point更新イベント
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 #[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
 pub struct PointUpdate {
     #[prost(string, tag = "1")]
@@ -45,6 +147,8 @@ pub struct PointUpdate {
     pub points: i32,
 }
 /// サウンド設定メッセージ
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct SoundSetting {
     #[prost(string, tag = "1")]
@@ -61,12 +165,16 @@ pub struct SoundSetting {
     pub is_muted: bool,
 }
 /// サウンド設定更新イベント
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct SoundSettingUpdate {
     #[prost(message, optional, tag = "1")]
     pub settings: ::core::option::Option<SoundSetting>,
 }
 /// Moonlight状態情報
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 #[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
 pub struct MoonlightInfo {
     #[prost(string, tag = "1")]
@@ -77,22 +185,228 @@ pub struct MoonlightInfo {
     pub address: ::prost::alloc::string::String,
     #[prost(bool, tag = "4")]
     pub enabled: bool,
+    /// エントリごとの更新番号（サーバーが変更のたびに増やす。0は番号なしの古いサーバー）
+    #[prost(uint64, tag = "5")]
+    pub sequence: u64,
 }
 /// Moonlight更新イベント（Webから変更された時にクライアントへ通知）
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct MoonlightUpdate {
     /// 全Moonlightのリスト
     #[prost(message, repeated, tag = "1")]
     pub moonlights: ::prost::alloc::vec::Vec<MoonlightInfo>,
+    /// 実行結果の報告に付けるID（空の場合は報告しない）
+    #[prost(string, tag = "2")]
+    pub command_id: ::prost::alloc::string::String,
+}
+/// SE再生イベント（管制室から任意のSE・アナウンスを再生させる）
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct SePlayEvent {
+    /// 再生するファイル名
+    #[prost(string, tag = "1")]
+    pub file: ::prost::alloc::string::String,
+    /// 対象デバイスのリスト（空または"\*"を含む場合は全デバイス）
+    #[prost(string, repeated, tag = "2")]
+    pub devices: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// trueの場合は再生中のSEに割り込んで優先的に再生する
+    #[prost(bool, tag = "3")]
+    pub priority: bool,
+    /// 実行結果の報告に付けるID（空の場合は報告しない）
+    #[prost(string, tag = "4")]
+    pub command_id: ::prost::alloc::string::String,
+}
+/// BGM上書きイベント（演出用にRSSIに関わらず特定の音源を再生させる）
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct BgmOverrideEvent {
+    /// 再生するファイル名（place_typeより優先）
+    #[prost(string, tag = "1")]
+    pub file: ::prost::alloc::string::String,
+    /// 再生するロケーションのplace_type（現在のポイント数に応じた音源を使う）
+    #[prost(string, tag = "2")]
+    pub place_type: ::prost::alloc::string::String,
+    /// 対象デバイスのリスト（空または"\*"を含む場合は全デバイス）
+    #[prost(string, repeated, tag = "3")]
+    pub devices: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// 上書きを維持する時間（0またはfile・place_typeが空の場合は上書きを解除）
+    #[prost(uint32, tag = "4")]
+    pub duration_ms: u32,
+    /// 実行結果の報告に付けるID（空の場合は報告しない）
+    #[prost(string, tag = "5")]
+    pub command_id: ::prost::alloc::string::String,
+}
+/// ライブ配信イベント（コントロールルームからのナレーションなどをファイルのBGMの代わりに流す）
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct LiveStreamEvent {
+    /// 配信のURI（srt:// または rtp://）。空の場合は配信を終えて同期したファイル再生に戻る
+    #[prost(string, tag = "1")]
+    pub uri: ::prost::alloc::string::String,
+    /// 対象デバイスのリスト（空または"\*"を含む場合は全デバイス）
+    #[prost(string, repeated, tag = "2")]
+    pub devices: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// 配信を続ける最大時間（終了イベントが届かない場合の安全策。0の場合はクライアントの既定値）
+    #[prost(uint32, tag = "3")]
+    pub max_duration_ms: u32,
+}
+/// 音量更新イベント（ユニットのマスター音量・ミュートを絶対値で指定）
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct VolumeUpdate {
+    /// 対象デバイスのリスト（空または"\*"を含む場合は全デバイス）
+    #[prost(string, repeated, tag = "1")]
+    pub devices: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// マスター音量（0.0〜1.0、BGMとSEの両方に適用）
+    #[prost(double, tag = "2")]
+    pub master_volume: f64,
+    #[prost(bool, tag = "3")]
+    pub muted: bool,
+    /// 実行結果の報告に付けるID（空の場合は報告しない）
+    #[prost(string, tag = "4")]
+    pub command_id: ::prost::alloc::string::String,
+}
+/// 言語更新イベント（BGM・SEの言語バリアントを切り替える）
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct LanguageUpdate {
+    /// 対象デバイスのリスト（空または"\*"を含む場合は全デバイス）
+    #[prost(string, repeated, tag = "1")]
+    pub devices: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    /// 言語（"ja"、"en"など。空の場合はユニットの設定に戻す）
+    #[prost(string, tag = "2")]
+    pub language: ::prost::alloc::string::String,
+}
+/// アップロード抑制イベント（サーバーが混雑しているときに、デバイス情報の送信間隔を広げさせる）
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct UploadThrottle {
+    /// バッチを送信するまでの最小の待ち時間（ミリ秒。0の場合は抑制を解除）
+    #[prost(uint32, tag = "1")]
+    pub batch_interval_ms: u32,
+    /// 抑制を続ける時間（0の場合はクライアントの既定値）
+    #[prost(uint32, tag = "2")]
+    pub duration_ms: u32,
+}
+/// 全ユニット向けのアナウンス（避難誘導など。再生中のSEに割り込んで流す）
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct FleetAnnouncement {
+    /// 再生するファイル名
+    #[prost(string, tag = "1")]
+    pub file: ::prost::alloc::string::String,
+    /// このアナウンスだけに掛ける音量の倍率（0の場合は1.0）
+    #[prost(double, tag = "2")]
+    pub gain: f64,
+}
+/// 全ユニットのマスター音量・ミュート
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, Copy, PartialEq, ::prost::Message)]
+pub struct FleetVolume {
+    /// マスター音量（0.0〜1.0）
+    #[prost(double, tag = "1")]
+    pub master_volume: f64,
+    #[prost(bool, tag = "2")]
+    pub muted: bool,
+}
+/// 全ユニットの設定の上書き（再起動後も残る。ローカルAPIでの変更の方が優先）
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct FleetConfig {
+    /// 設定に重ねるJSON（JSON Merge Patch。nullの項目は上書きをやめる）
+    #[prost(string, tag = "1")]
+    pub patch_json: ::prost::alloc::string::String,
+}
+/// 全ユニット向けのコマンド（デバイスごとのMoonlightUpdateとは別に、会場全体へ一斉に指示する）
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct FleetCommand {
+    /// コマンドのID（受領の通知に付けて返す。再接続で再送されても1回だけ実行する）
+    #[prost(string, tag = "1")]
+    pub command_id: ::prost::alloc::string::String,
+    #[prost(oneof = "fleet_command::Command", tags = "2, 3, 4")]
+    pub command: ::core::option::Option<fleet_command::Command>,
+}
+/// Nested message and enum types in `FleetCommand`.
+pub mod fleet_command {
+    #[derive(serde::Serialize, serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    #[derive(Clone, PartialEq, ::prost::Oneof)]
+    pub enum Command {
+        #[prost(message, tag = "2")]
+        Announcement(super::FleetAnnouncement),
+        #[prost(message, tag = "3")]
+        Volume(super::FleetVolume),
+        #[prost(message, tag = "4")]
+        Config(super::FleetConfig),
+    }
+}
+/// FleetCommandの受領の通知
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct FleetCommandAck {
+    #[prost(string, tag = "1")]
+    pub command_id: ::prost::alloc::string::String,
+    /// 実行した（または以前に実行済みだった）
+    #[prost(bool, tag = "2")]
+    pub ok: bool,
+    /// 実行できなかった理由（okがfalseの場合）
+    #[prost(string, tag = "3")]
+    pub error: ::prost::alloc::string::String,
+    /// 以前に受け取ったコマンドの再送だった
+    #[prost(bool, tag = "4")]
+    pub duplicate: bool,
+}
+/// command_id付きのコマンド（MoonlightUpdate・SePlayEvent・BgmOverrideEvent・VolumeUpdate）の実行結果
+/// 対象デバイスに含まれていないユニットは報告しない
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+#[derive(Clone, PartialEq, Eq, Hash, ::prost::Message)]
+pub struct CommandResult {
+    #[prost(string, tag = "1")]
+    pub command_id: ::prost::alloc::string::String,
+    #[prost(enumeration = "CommandKind", tag = "2")]
+    pub command: i32,
+    /// コマンドが効いた
+    #[prost(bool, tag = "3")]
+    pub ok: bool,
+    #[prost(enumeration = "CommandFailure", tag = "4")]
+    pub failure: i32,
+    /// 失敗の詳細（エラーメッセージなど）
+    #[prost(string, tag = "5")]
+    pub detail: ::prost::alloc::string::String,
 }
 /// サーバーからストリーミングされるメッセージ
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 #[derive(Clone, PartialEq, ::prost::Message)]
 pub struct StreamDeviceInfoResponse {
-    #[prost(oneof = "stream_device_info_response::Event", tags = "2, 3, 4, 5")]
+    /// サーバーが使用しているスキーマのバージョン（0は未設定）
+    #[prost(uint32, tag = "1")]
+    pub schema_version: u32,
+    #[prost(
+        oneof = "stream_device_info_response::Event",
+        tags = "2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12"
+    )]
     pub event: ::core::option::Option<stream_device_info_response::Event>,
 }
 /// Nested message and enum types in `StreamDeviceInfoResponse`.
 pub mod stream_device_info_response {
+    #[derive(serde::Serialize, serde::Deserialize)]
+    #[serde(rename_all = "snake_case")]
     #[derive(Clone, PartialEq, ::prost::Oneof)]
     pub enum Event {
         #[prost(message, tag = "2")]
@@ -104,6 +418,118 @@ pub mod stream_device_info_response {
         /// Moonlight更新イベント
         #[prost(message, tag = "5")]
         MoonlightUpdate(super::MoonlightUpdate),
+        /// SE再生イベント
+        #[prost(message, tag = "6")]
+        SePlay(super::SePlayEvent),
+        /// BGM上書きイベント
+        #[prost(message, tag = "7")]
+        BgmOverride(super::BgmOverrideEvent),
+        /// 音量更新イベント
+        #[prost(message, tag = "8")]
+        VolumeUpdate(super::VolumeUpdate),
+        /// ライブ配信イベント
+        #[prost(message, tag = "9")]
+        LiveStream(super::LiveStreamEvent),
+        /// 言語更新イベント
+        #[prost(message, tag = "10")]
+        LanguageUpdate(super::LanguageUpdate),
+        /// アップロード抑制イベント
+        #[prost(message, tag = "11")]
+        UploadThrottle(super::UploadThrottle),
+        /// 全ユニット向けのコマンド
+        #[prost(message, tag = "12")]
+        FleetCommand(super::FleetCommand),
+    }
+}
+/// 実行結果を報告するコマンドの種類
+#[derive(serde::Serialize, serde::Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum CommandKind {
+    Unspecified = 0,
+    Enable = 1,
+    SePlay = 2,
+    BgmOverride = 3,
+    Volume = 4,
+}
+impl CommandKind {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            Self::Unspecified => "COMMAND_KIND_UNSPECIFIED",
+            Self::Enable => "COMMAND_KIND_ENABLE",
+            Self::SePlay => "COMMAND_KIND_SE_PLAY",
+            Self::BgmOverride => "COMMAND_KIND_BGM_OVERRIDE",
+            Self::Volume => "COMMAND_KIND_VOLUME",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "COMMAND_KIND_UNSPECIFIED" => Some(Self::Unspecified),
+            "COMMAND_KIND_ENABLE" => Some(Self::Enable),
+            "COMMAND_KIND_SE_PLAY" => Some(Self::SePlay),
+            "COMMAND_KIND_BGM_OVERRIDE" => Some(Self::BgmOverride),
+            "COMMAND_KIND_VOLUME" => Some(Self::Volume),
+            _ => None,
+        }
+    }
+}
+/// コマンドが効かなかった理由
+#[derive(serde::Serialize, serde::Deserialize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum CommandFailure {
+    /// 成功
+    None = 0,
+    /// ファイル名などの指定が不正
+    InvalidRequest = 1,
+    /// 指定された音源がユニットに無い
+    AssetMissing = 2,
+    /// 再生パイプラインを構築・開始できなかった
+    PipelineError = 3,
+    /// 優先SEの再生中で受け付けられなかった
+    Busy = 4,
+    /// 更新番号が古く、より新しい指示が適用済みだった
+    Stale = 5,
+    /// 再生側のサブシステムが止まっていた
+    Unavailable = 6,
+    /// 同じSEの指示が多すぎるため鳴らさなかった
+    RateLimited = 7,
+}
+impl CommandFailure {
+    /// String value of the enum field names used in the ProtoBuf definition.
+    ///
+    /// The values are not transformed in any way and thus are considered stable
+    /// (if the ProtoBuf definition does not change) and safe for programmatic use.
+    pub fn as_str_name(&self) -> &'static str {
+        match self {
+            Self::None => "COMMAND_FAILURE_NONE",
+            Self::InvalidRequest => "COMMAND_FAILURE_INVALID_REQUEST",
+            Self::AssetMissing => "COMMAND_FAILURE_ASSET_MISSING",
+            Self::PipelineError => "COMMAND_FAILURE_PIPELINE_ERROR",
+            Self::Busy => "COMMAND_FAILURE_BUSY",
+            Self::Stale => "COMMAND_FAILURE_STALE",
+            Self::Unavailable => "COMMAND_FAILURE_UNAVAILABLE",
+            Self::RateLimited => "COMMAND_FAILURE_RATE_LIMITED",
+        }
+    }
+    /// Creates an enum from field names used in the ProtoBuf definition.
+    pub fn from_str_name(value: &str) -> ::core::option::Option<Self> {
+        match value {
+            "COMMAND_FAILURE_NONE" => Some(Self::None),
+            "COMMAND_FAILURE_INVALID_REQUEST" => Some(Self::InvalidRequest),
+            "COMMAND_FAILURE_ASSET_MISSING" => Some(Self::AssetMissing),
+            "COMMAND_FAILURE_PIPELINE_ERROR" => Some(Self::PipelineError),
+            "COMMAND_FAILURE_BUSY" => Some(Self::Busy),
+            "COMMAND_FAILURE_STALE" => Some(Self::Stale),
+            "COMMAND_FAILURE_UNAVAILABLE" => Some(Self::Unavailable),
+            "COMMAND_FAILURE_RATE_LIMITED" => Some(Self::RateLimited),
+            _ => None,
+        }
     }
 }
 /// Generated client implementations.
@@ -224,6 +650,31 @@ pub mod device_service_client {
                 .insert(GrpcMethod::new("proto.DeviceService", "StreamDeviceInfo"));
             self.inner.streaming(req, path, codec).await
         }
+        /// ユーザーの現在のポイント数を取得（起動直後、PointUpdateを待たずに音源を決めるため）
+        pub async fn get_points(
+            &mut self,
+            request: impl tonic::IntoRequest<super::GetPointsRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::GetPointsResponse>,
+            tonic::Status,
+        > {
+            self.inner
+                .ready()
+                .await
+                .map_err(|e| {
+                    tonic::Status::unknown(
+                        format!("Service was not ready: {}", e.into()),
+                    )
+                })?;
+            let codec = tonic_prost::ProstCodec::default();
+            let path = http::uri::PathAndQuery::from_static(
+                "/proto.DeviceService/GetPoints",
+            );
+            let mut req = request.into_request();
+            req.extensions_mut()
+                .insert(GrpcMethod::new("proto.DeviceService", "GetPoints"));
+            self.inner.unary(req, path, codec).await
+        }
     }
 }
 /// Generated server implementations.
@@ -256,6 +707,14 @@ pub mod device_service_server {
             tonic::Response<Self::StreamDeviceInfoStream>,
             tonic::Status,
         >;
+        /// ユーザーの現在のポイント数を取得（起動直後、PointUpdateを待たずに音源を決めるため）
+        async fn get_points(
+            &self,
+            request: tonic::Request<super::GetPointsRequest>,
+        ) -> std::result::Result<
+            tonic::Response<super::GetPointsResponse>,
+            tonic::Status,
+        >;
     }
     #[derive(Debug)]
     pub struct DeviceServiceServer<T> {
@@ -382,6 +841,51 @@ pub mod device_service_server {
                     };
                     Box::pin(fut)
                 }
+                "/proto.DeviceService/GetPoints" => {
+                    #[allow(non_camel_case_types)]
+                    struct GetPointsSvc<T: DeviceService>(pub Arc<T>);
+                    impl<
+                        T: DeviceService,
+                    > tonic::server::UnaryService<super::GetPointsRequest>
+                    for GetPointsSvc<T> {
+                        type Response = super::GetPointsResponse;
+                        type Future = BoxFuture<
+                            tonic::Response<Self::Response>,
+                            tonic::Status,
+                        >;
+                        fn call(
+                            &mut self,
+                            request: tonic::Request<super::GetPointsRequest>,
+                        ) -> Self::Future {
+                            let inner = Arc::clone(&self.0);
+                            let fut = async move {
+                                <T as DeviceService>::get_points(&inner, request).await
+                            };
+                            Box::pin(fut)
+                        }
+                    }
+                    let accept_compression_encodings = self.accept_compression_encodings;
+                    let send_compression_encodings = self.send_compression_encodings;
+                    let max_decoding_message_size = self.max_decoding_message_size;
+                    let max_encoding_message_size = self.max_encoding_message_size;
+                    let inner = self.inner.clone();
+                    let fut = async move {
+                        let method = GetPointsSvc(inner);
+                        let codec = tonic_prost::ProstCodec::default();
+                        let mut grpc = tonic::server::Grpc::new(codec)
+                            .apply_compression_config(
+                                accept_compression_encodings,
+                                send_compression_encodings,
+                            )
+                            .apply_max_message_size_config(
+                                max_decoding_message_size,
+                                max_encoding_message_size,
+                            );
+                        let res = grpc.unary(method, req).await;
+                        Ok(res)
+                    };
+                    Box::pin(fut)
+                }
                 _ => {
                     Box::pin(async move {
                         let mut response = http::Response::new(
@@ -423,12 +927,16 @@ pub mod device_service_server {
     }
 }
 /// SyncTimeのリクエストメッセージ
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 #[derive(Clone, Copy, PartialEq, Eq, Hash, ::prost::Message)]
 pub struct SyncTimeRequest {
     #[prost(int64, tag = "1")]
     pub client_send_time: i64,
 }
 /// SyncTimeのレスポンスメッセージ
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(default)]
 #[derive(Clone, Copy, PartialEq, Eq, Hash, ::prost::Message)]
 pub struct SyncTimeResponse {
     #[prost(int64, tag = "1")]
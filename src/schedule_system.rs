@@ -0,0 +1 @@
+pub mod schedule_main;
\ No newline at end of file
@@ -0,0 +1,198 @@
+use crate::config_system::config_main::{ScheduleConfig, ShowProgram};
+use anyhow::{bail, Context, Result};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+use tracing::{info, instrument};
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// 番組の確認間隔（時刻の切り替わりはこの精度で反映される）
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// BGM選択に対する番組の上書き
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum ProgramOverride {
+    /// 上書きしない（通常のショー）
+    #[default]
+    None,
+    /// RSSIに関わらずこの音源を再生する
+    Sound(String),
+    /// BGMを鳴らさない（SEは鳴らす）
+    Silence,
+}
+
+/// 番組表を配信するwatchチャンネルを作成する
+pub fn channel() -> (watch::Sender<ProgramOverride>, watch::Receiver<ProgramOverride>) {
+    watch::channel(ProgramOverride::default())
+}
+
+/// `HH:MM` を0時からの秒数に変換する
+fn parse_time_of_day(text: &str) -> Result<u64> {
+    let (hours, minutes) = text.split_once(':').with_context(|| format!("Invalid schedule time (expected HH:MM): {}", text))?;
+    let hours: u64 = hours.trim().parse().with_context(|| format!("Invalid schedule hour: {}", text))?;
+    let minutes: u64 = minutes.trim().parse().with_context(|| format!("Invalid schedule minute: {}", text))?;
+    if hours >= 24 || minutes >= 60 {
+        bail!("Schedule time out of range: {}", text);
+    }
+    Ok(hours * 3600 + minutes * 60)
+}
+
+/// 会場の時刻（0時からの秒数）
+pub fn venue_time_of_day(utc_offset_minutes: i32) -> u64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64;
+    (now + utc_offset_minutes as i64 * 60).rem_euclid(SECONDS_PER_DAY as i64) as u64
+}
+
+/// 番組の切り替え表（開始時刻順）
+struct Timetable {
+    entries: Vec<(u64, ShowProgram)>,
+}
+
+impl Timetable {
+    fn from_config(config: &ScheduleConfig) -> Result<Self> {
+        let mut entries = config
+            .entries
+            .iter()
+            .map(|entry| Ok((parse_time_of_day(&entry.at)?, entry.program)))
+            .collect::<Result<Vec<_>>>()?;
+        entries.sort_by_key(|(at, _)| *at);
+        Ok(Self { entries })
+    }
+
+    /// 時刻における番組（その日の最初の切り替え前は前日の最後の番組が続く）
+    fn program_at(&self, time_of_day: u64) -> Option<ShowProgram> {
+        self.entries
+            .iter()
+            .rev()
+            .find(|(at, _)| *at <= time_of_day)
+            .or_else(|| self.entries.last())
+            .map(|(_, program)| *program)
+    }
+}
+
+/// 番組をBGM選択への上書きに変換する
+fn program_override(config: &ScheduleConfig, program: ShowProgram) -> ProgramOverride {
+    let sound = match program {
+        ShowProgram::Show => return ProgramOverride::None,
+        ShowProgram::Silence => return ProgramOverride::Silence,
+        ShowProgram::PreOpen => &config.sounds.pre_open,
+        ShowProgram::Closing => &config.sounds.closing,
+    };
+    match sound {
+        Some(sound) => ProgramOverride::Sound(sound.clone()),
+        // 音源が設定されていない番組は無音にする
+        None => ProgramOverride::Silence,
+    }
+}
+
+/// 設定された時刻に番組（開場前・ショー・閉場・無音）を切り替えるタスク
+///
+/// サーバーからの指示がなくても動作する。サーバーからのBGM上書きは番組より優先される。
+#[instrument(skip_all)]
+pub async fn schedule_main(config: ScheduleConfig, program_tx: watch::Sender<ProgramOverride>) -> Result<()> {
+    let timetable = Timetable::from_config(&config)?;
+    info!(entries = timetable.entries.len(), utc_offset_minutes = config.utc_offset_minutes, "Show schedule started");
+
+    let mut current: Option<ShowProgram> = None;
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let program = timetable.program_at(venue_time_of_day(config.utc_offset_minutes));
+        if program == current {
+            continue;
+        }
+        current = program;
+        let Some(program) = program else {
+            continue;
+        };
+        let program_override = program_override(&config, program);
+        info!(?program, ?program_override, "Show program changed");
+        program_tx.send_replace(program_override);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config_system::config_main::ScheduleEntry;
+
+    fn at(hours: u64, minutes: u64) -> u64 {
+        hours * 3600 + minutes * 60
+    }
+
+    fn timetable(entries: &[(&str, ShowProgram)]) -> Timetable {
+        let config = ScheduleConfig {
+            entries: entries.iter().map(|(at, program)| ScheduleEntry { at: at.to_string(), program: *program }).collect(),
+            ..Default::default()
+        };
+        Timetable::from_config(&config).unwrap()
+    }
+
+    /// 10:00に開場前、12:00にショー、23:00から翌2:00まで閉場の音楽、2:00から無音（順不同で設定）
+    fn overnight() -> Timetable {
+        timetable(&[("02:00", ShowProgram::Silence), ("23:00", ShowProgram::Closing), ("10:00", ShowProgram::PreOpen), ("12:00", ShowProgram::Show)])
+    }
+
+    #[test]
+    fn slot_wraps_past_midnight() {
+        let timetable = overnight();
+        assert_eq!(timetable.program_at(at(23, 30)), Some(ShowProgram::Closing));
+        assert_eq!(timetable.program_at(at(0, 0)), Some(ShowProgram::Closing));
+        assert_eq!(timetable.program_at(at(1, 0)), Some(ShowProgram::Closing));
+        assert_eq!(timetable.program_at(at(3, 0)), Some(ShowProgram::Silence));
+        assert_eq!(timetable.program_at(at(11, 0)), Some(ShowProgram::PreOpen));
+    }
+
+    #[test]
+    fn program_changes_on_the_exact_minute() {
+        let timetable = overnight();
+        assert_eq!(timetable.program_at(at(22, 59) + 59), Some(ShowProgram::Show));
+        assert_eq!(timetable.program_at(at(23, 0)), Some(ShowProgram::Closing));
+        assert_eq!(timetable.program_at(at(1, 59) + 59), Some(ShowProgram::Closing));
+        assert_eq!(timetable.program_at(at(2, 0)), Some(ShowProgram::Silence));
+        assert_eq!(timetable.program_at(SECONDS_PER_DAY - 1), Some(ShowProgram::Closing));
+    }
+
+    #[test]
+    fn later_entry_wins_at_the_same_time() {
+        let timetable = timetable(&[("09:00", ShowProgram::PreOpen), ("18:00", ShowProgram::Closing), ("18:00", ShowProgram::Silence)]);
+        assert_eq!(timetable.program_at(at(17, 59)), Some(ShowProgram::PreOpen));
+        assert_eq!(timetable.program_at(at(18, 0)), Some(ShowProgram::Silence));
+        assert_eq!(timetable.program_at(at(8, 0)), Some(ShowProgram::Silence));
+    }
+
+    #[test]
+    fn empty_timetable_has_no_program() {
+        let timetable = timetable(&[]);
+        assert_eq!(timetable.program_at(0), None);
+        assert_eq!(timetable.program_at(at(12, 0)), None);
+    }
+
+    #[test]
+    fn single_entry_runs_all_day() {
+        let timetable = timetable(&[("12:00", ShowProgram::Show)]);
+        assert_eq!(timetable.program_at(0), Some(ShowProgram::Show));
+        assert_eq!(timetable.program_at(SECONDS_PER_DAY - 1), Some(ShowProgram::Show));
+    }
+
+    #[test]
+    fn times_are_parsed_strictly() {
+        assert_eq!(parse_time_of_day("00:00").unwrap(), 0);
+        assert_eq!(parse_time_of_day(" 9:05").unwrap(), at(9, 5));
+        assert_eq!(parse_time_of_day("23:59").unwrap(), at(23, 59));
+        for invalid in ["24:00", "12:60", "1200", "ab:cd", ""] {
+            assert!(parse_time_of_day(invalid).is_err(), "{}", invalid);
+        }
+        let config = ScheduleConfig { entries: vec![ScheduleEntry { at: "25:00".to_string(), program: ShowProgram::Show }], ..Default::default() };
+        assert!(Timetable::from_config(&config).is_err());
+    }
+
+    #[test]
+    fn programs_without_a_sound_fall_back_to_silence() {
+        let mut config = ScheduleConfig::default();
+        assert_eq!(program_override(&config, ShowProgram::Show), ProgramOverride::None);
+        assert_eq!(program_override(&config, ShowProgram::PreOpen), ProgramOverride::Silence);
+        config.sounds.closing = Some("closing.mp3".to_string());
+        assert_eq!(program_override(&config, ShowProgram::Closing), ProgramOverride::Sound("closing.mp3".to_string()));
+    }
+}
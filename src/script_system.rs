@@ -0,0 +1 @@
+pub mod script_main;
\ No newline at end of file
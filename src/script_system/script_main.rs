@@ -0,0 +1,125 @@
+use crate::messages::{BgmOverrideRequest, SePlayRequest};
+use crate::config_system::config_main::ScriptConfig;
+use crate::event_system::event_bus::EventBus;
+use crate::schedule_system::schedule_main::venue_time_of_day;
+use anyhow::{anyhow, Result};
+use rhai::{Dynamic, Engine, Scope, AST};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{broadcast, mpsc};
+use tracing::{info, instrument, warn};
+
+/// スクリプトでイベントを受け取る関数の名前
+const EVENT_HANDLER: &str = "on_event";
+
+/// スクリプトから使える関数を登録したエンジンを作る
+///
+/// - `play_se(file)` / `play_se(file, gain)`: SEを鳴らす
+/// - `override_bgm(sound, seconds)` / `clear_bgm_override()`: BGMを上書きする・解除する
+/// - `points()`: 現在のポイント数
+/// - `hour()` / `minute()`: 会場の時刻（`schedule.utc_offset_minutes` で補正）
+fn build_engine(
+    config: &ScriptConfig,
+    utc_offset_minutes: i32,
+    se_tx: mpsc::Sender<SePlayRequest>,
+    bgm_override_tx: mpsc::Sender<BgmOverrideRequest>,
+    current_points: Arc<Mutex<i32>>,
+) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(config.max_operations);
+    engine.on_print(|text| info!(script = true, "{}", text));
+    engine.on_debug(|text, _, position| info!(script = true, %position, "{}", text));
+
+    let play = move |file: &str, gain: Option<f64>| {
+        let request = SePlayRequest { file_path: file.to_string(), priority: false, gain, command_id: None };
+        if let Err(e) = se_tx.try_send(request) {
+            warn!(file, "Script failed to queue SE: {}", e);
+        }
+    };
+    let play_with_gain = play.clone();
+    engine.register_fn("play_se", move |file: &str| play(file, None));
+    engine.register_fn("play_se", move |file: &str, gain: f64| play_with_gain(file, Some(gain)));
+
+    let override_bgm = move |sound: Option<String>, seconds: i64| {
+        let request = BgmOverrideRequest { sound, duration: Duration::from_secs(seconds.max(0) as u64) };
+        if let Err(e) = bgm_override_tx.try_send(request) {
+            warn!("Script failed to queue BGM override: {}", e);
+        }
+    };
+    let clear_bgm_override = override_bgm.clone();
+    engine.register_fn("override_bgm", move |sound: &str, seconds: i64| override_bgm(Some(sound.to_string()), seconds));
+    engine.register_fn("clear_bgm_override", move || clear_bgm_override(None, 0));
+
+    engine.register_fn("points", move || *current_points.lock().unwrap() as i64);
+    engine.register_fn("hour", move || (venue_time_of_day(utc_offset_minutes) / 3600) as i64);
+    engine.register_fn("minute", move || (venue_time_of_day(utc_offset_minutes) / 60 % 60) as i64);
+    engine
+}
+
+/// スクリプトを読み込み、トップレベルの文を一度だけ実行する（グローバルな設定の初期化用）
+fn load_script(engine: &Engine, path: &Path, scope: &mut Scope) -> Result<AST> {
+    let ast = engine
+        .compile_file(path.to_path_buf())
+        .map_err(|e| anyhow!("Failed to compile script {}: {}", path.display(), e))?;
+    engine
+        .run_ast_with_scope(scope, &ast)
+        .map_err(|e| anyhow!("Failed to run script {}: {}", path.display(), e))?;
+    Ok(ast)
+}
+
+/// 会場ごとのルールを書いたスクリプトにローカルイベントを渡すタスク
+///
+/// スクリプトの `on_event(event)` に、Webhookと同じ形（`event` にイベント名）のマップを渡す。
+///
+/// ```rhai
+/// fn on_event(event) {
+///     if event.event == "interaction_triggered" && event.place_type == "dragons_jewel"
+///         && hour() >= 19 && points() >= 3 {
+///         play_se("se-ryu.mp3");
+///     }
+/// }
+/// ```
+#[instrument(skip_all)]
+pub async fn script_main(
+    config: ScriptConfig,
+    utc_offset_minutes: i32,
+    events: EventBus,
+    se_tx: mpsc::Sender<SePlayRequest>,
+    bgm_override_tx: mpsc::Sender<BgmOverrideRequest>,
+    current_points: Arc<Mutex<i32>>,
+) -> Result<()> {
+    let path = PathBuf::from(&config.path);
+    let engine = build_engine(&config, utc_offset_minutes, se_tx, bgm_override_tx, current_points);
+    let mut scope = Scope::new();
+    let ast = load_script(&engine, &path, &mut scope)?;
+    info!(path = %path.display(), "Venue script loaded");
+    if !ast.iter_functions().any(|function| function.name == EVENT_HANDLER && function.params.len() == 1) {
+        warn!(path = %path.display(), "Script has no `{}(event)` function - no events will be handled", EVENT_HANDLER);
+        return Ok(());
+    }
+
+    let mut event_rx = events.subscribe("script");
+    loop {
+        let event = match event_rx.recv().await {
+            Ok(event) => event,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                warn!(skipped, "Venue script lagged - some events were not handled");
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+        let argument = match rhai::serde::to_dynamic(&event) {
+            Ok(argument) => argument,
+            Err(e) => {
+                warn!(event = event.name(), "Failed to convert event for script: {}", e);
+                continue;
+            }
+        };
+        // スクリプトのエラーはログに残し、次のイベントは引き続き処理する
+        if let Err(e) = engine.call_fn::<Dynamic>(&mut scope, &ast, EVENT_HANDLER, (argument,)) {
+            warn!(event = event.name(), "Venue script error: {}", e);
+        }
+    }
+    Ok(())
+}
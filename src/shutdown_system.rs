@@ -0,0 +1 @@
+pub mod shutdown_controller;
\ No newline at end of file
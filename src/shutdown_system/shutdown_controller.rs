@@ -0,0 +1,162 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::runtime::Builder;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// 終了手順の段階（この順に進む）
+///
+/// GStreamerの状態遷移の途中でタスクを中断するとPulseAudioのストリームが残ることがあるため、
+/// 入力を止めてから送信を終え、音を絞ってからパイプラインを破棄する。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ShutdownPhase {
+    Running,
+    /// BLEのイベントの受け付けを止める
+    StopBle,
+    /// 溜まっているデバイス情報をサーバーに送り切る
+    FlushUploads,
+    /// BGM・SEをフェードアウトする
+    FadeAudio,
+    /// サーバーとのストリームを閉じる
+    CloseStreams,
+    /// パイプラインを破棄してオーディオスレッドを終える
+    DropPipelines,
+}
+
+/// 終了手順を進め、各タスクに現在の段階を知らせる
+///
+/// 各段階の担当タスクは段階に達したら処理し、完了を `complete` で報告する。
+pub struct ShutdownController {
+    phase: watch::Sender<ShutdownPhase>,
+    completed: watch::Sender<ShutdownPhase>,
+}
+
+impl ShutdownController {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            phase: watch::Sender::new(ShutdownPhase::Running),
+            completed: watch::Sender::new(ShutdownPhase::Running),
+        })
+    }
+
+    /// 現在の段階
+    pub fn phase(&self) -> ShutdownPhase {
+        *self.phase.borrow()
+    }
+
+    pub fn is_reached(&self, phase: ShutdownPhase) -> bool {
+        self.phase() >= phase
+    }
+
+    /// 終了手順がこの段階に達するまで待つ
+    pub async fn reached(&self, phase: ShutdownPhase) {
+        let mut phase_rx = self.phase.subscribe();
+        // 送信側はこの構造体が持っているため、閉じることはない
+        let _ = phase_rx.wait_for(|current| *current >= phase).await;
+    }
+
+    /// 担当する段階の処理を終えたことを報告する
+    pub fn complete(&self, phase: ShutdownPhase) {
+        self.completed.send_if_modified(|completed| {
+            if *completed >= phase {
+                return false;
+            }
+            *completed = phase;
+            true
+        });
+    }
+
+    /// 次の段階に進み、担当タスクの完了を `timeout` まで待つ（完了の報告があれば `true`）
+    pub async fn advance(&self, phase: ShutdownPhase, timeout: Duration) -> bool {
+        self.enter(phase);
+        let mut completed_rx = self.completed.subscribe();
+        let completed = tokio::time::timeout(timeout, completed_rx.wait_for(|completed| *completed >= phase)).await;
+        if completed.is_err() {
+            warn!(?phase, timeout_ms = timeout.as_millis() as u64, "Shutdown phase timed out");
+        }
+        completed.is_ok()
+    }
+
+    /// 段階を進めるだけで、完了は待たない（担当タスクの終了を別に待つ場合）
+    pub fn enter(&self, phase: ShutdownPhase) {
+        info!(?phase, "Shutdown phase");
+        self.phase.send_if_modified(|current| {
+            if *current >= phase {
+                return false;
+            }
+            *current = phase;
+            true
+        });
+    }
+}
+
+/// タスクの終了を `timeout` まで待ち、終わらなければ中断する
+pub async fn join_or_abort<T>(name: &str, mut handle: JoinHandle<T>, timeout: Duration) {
+    if tokio::time::timeout(timeout, &mut handle).await.is_err() {
+        warn!(task = name, timeout_ms = timeout.as_millis() as u64, "Task did not stop in time - aborting");
+        handle.abort();
+    }
+}
+
+/// 終了のシグナル（Ctrl+C・SIGTERM）を待つ
+pub async fn termination_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => info!("Received Ctrl+C"),
+                    _ = sigterm.recv() => info!("Received SIGTERM"),
+                }
+                return;
+            }
+            Err(e) => warn!("Failed to listen for SIGTERM: {}", e),
+        }
+    }
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        warn!("Failed to listen for Ctrl+C: {}", e);
+        std::future::pending::<()>().await;
+    }
+    info!("Received Ctrl+C");
+}
+
+/// ランタイムを作って `main` を最後まで動かし、残ったタスクを `timeout` まで待ってから戻る
+///
+/// `#[tokio::main]` のランタイムは破棄するときにすべてのブロッキングタスクが戻るまで待つため、
+/// GStreamerの状態遷移で固まったオーディオスレッドがあると終了できなくなる。ここでは待ちきれない
+/// スレッドを残したまま戻り、プロセスの終了とともに片付ける。
+pub fn run_with_shutdown_timeout<F: Future>(main: F, timeout: Duration) -> std::io::Result<F::Output> {
+    let runtime = Builder::new_multi_thread().enable_all().build()?;
+    let output = runtime.block_on(main);
+    runtime.shutdown_timeout(timeout);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Instant;
+
+    #[test]
+    fn wedged_blocking_thread_does_not_hang_shutdown() {
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let start = Instant::now();
+        let result = run_with_shutdown_timeout(
+            async move {
+                // 固まったオーディオスレッドの代わり（`release_tx` が落ちるまで戻らない）
+                let wedged = tokio::task::spawn_blocking(move || release_rx.recv());
+                // mainと同じく、戻らなければ待たずに先へ進む
+                tokio::time::timeout(Duration::from_millis(50), wedged).await.is_err()
+            },
+            Duration::from_millis(100),
+        )
+        .unwrap();
+        assert!(result);
+        assert!(start.elapsed() < Duration::from_secs(5), "shutdown waited {:?}", start.elapsed());
+        drop(release_tx);
+    }
+}
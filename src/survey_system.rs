@@ -0,0 +1 @@
+pub mod survey_main;
\ No newline at end of file
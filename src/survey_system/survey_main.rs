@@ -0,0 +1,235 @@
+use crate::bluetooth_system::address::Address;
+use crate::config_system::config_main::SurveyConfig;
+use crate::connect_system::sound_map::SharedSoundMap;
+use crate::messages::DeviceSnapshot;
+use crate::metrics_system::metrics_main::{metrics, LagReceiver};
+use crate::shutdown_system::shutdown_controller::{ShutdownController, ShutdownPhase};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::broadcast;
+use tracing::{info, instrument, warn};
+
+/// CSVの見出し行
+const CSV_HEADER: &str = "unix_ms,elapsed_ms,waypoint,address,name,rssi,tx_power,sound\n";
+
+/// 地点・ビーコンごとのRSSIの集計
+#[derive(Debug, Clone, Copy)]
+struct RssiStats {
+    count: u64,
+    sum: i64,
+    min: i16,
+    max: i16,
+}
+
+impl RssiStats {
+    fn new(rssi: i16) -> Self {
+        Self { count: 1, sum: rssi as i64, min: rssi, max: rssi }
+    }
+
+    fn add(&mut self, rssi: i16) {
+        self.count += 1;
+        self.sum += rssi as i64;
+        self.min = self.min.min(rssi);
+        self.max = self.max.max(rssi);
+    }
+}
+
+/// 集計（地点・ビーコン1組分）
+#[derive(Debug, Clone, Serialize)]
+pub struct SurveyCell {
+    /// 地点の名前（未設定の間に記録したものは `None`）
+    pub waypoint: Option<String>,
+    pub address: Address,
+    /// ビーコンに割り当てられた音源（sound_mapに無ければ `None`）
+    pub sound: Option<String>,
+    pub count: u64,
+    pub mean_rssi: f64,
+    pub min_rssi: i16,
+    pub max_rssi: i16,
+}
+
+/// `/api/survey` のレスポンス・書き出す集計
+#[derive(Debug, Clone, Serialize)]
+pub struct SurveyReport {
+    pub started_unix_ms: u64,
+    /// 現在の地点の名前
+    pub waypoint: Option<String>,
+    /// 記録したRSSIの件数
+    pub samples: u64,
+    /// 地点ごと・RSSIの強い順
+    pub cells: Vec<SurveyCell>,
+}
+
+struct SurveyState {
+    waypoint: Option<String>,
+    samples: u64,
+    cells: BTreeMap<(Option<String>, Address), RssiStats>,
+}
+
+/// 調査中の状態（ローカルAPIから地点の名前を設定し、集計を取得する）
+pub struct Survey {
+    started: Instant,
+    started_unix_ms: u64,
+    state: Mutex<SurveyState>,
+}
+
+impl Survey {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            started: Instant::now(),
+            started_unix_ms: unix_ms(),
+            state: Mutex::new(SurveyState { waypoint: None, samples: 0, cells: BTreeMap::new() }),
+        })
+    }
+
+    /// オペレーターが今いる地点の名前を設定する（`None` で解除）
+    pub fn set_waypoint(&self, waypoint: Option<String>) {
+        let waypoint = waypoint.map(|label| label.trim().to_string()).filter(|label| !label.is_empty());
+        info!(?waypoint, "Survey waypoint changed");
+        self.state.lock().unwrap().waypoint = waypoint;
+    }
+
+    /// スナップショットのビーコンを集計に加え、CSVの行を返す（来場者の端末は含めない）
+    fn record(&self, snapshot: &DeviceSnapshot, sound_map: &SharedSoundMap) -> String {
+        let sound_map = sound_map.load();
+        let now_ms = unix_ms();
+        let elapsed_ms = self.started.elapsed().as_millis() as u64;
+        let mut state = self.state.lock().unwrap();
+        let waypoint = state.waypoint.clone();
+        let mut rows = String::new();
+        for device in snapshot.devices.iter().filter(|device| device.visitor_token.is_none()) {
+            state.samples += 1;
+            state
+                .cells
+                .entry((waypoint.clone(), device.address.clone()))
+                .and_modify(|stats| stats.add(device.rssi))
+                .or_insert_with(|| RssiStats::new(device.rssi));
+            let fields = [
+                now_ms.to_string(),
+                elapsed_ms.to_string(),
+                csv_field(waypoint.as_deref().unwrap_or("")),
+                device.address.to_string(),
+                csv_field(device.local_name.as_deref().unwrap_or("")),
+                device.rssi.to_string(),
+                device.tx_power.map(|power| power.to_string()).unwrap_or_default(),
+                csv_field(sound_map.sounds.get(&device.address).map(String::as_str).unwrap_or("")),
+            ];
+            rows.push_str(&fields.join(","));
+            rows.push('\n');
+        }
+        rows
+    }
+
+    pub fn report(&self, sound_map: &SharedSoundMap) -> SurveyReport {
+        let sound_map = sound_map.load();
+        let state = self.state.lock().unwrap();
+        let mut cells: Vec<SurveyCell> = state
+            .cells
+            .iter()
+            .map(|((waypoint, address), stats)| SurveyCell {
+                waypoint: waypoint.clone(),
+                address: address.clone(),
+                sound: sound_map.sounds.get(address).cloned(),
+                count: stats.count,
+                mean_rssi: stats.sum as f64 / stats.count as f64,
+                min_rssi: stats.min,
+                max_rssi: stats.max,
+            })
+            .collect();
+        cells.sort_by(|a, b| a.waypoint.cmp(&b.waypoint).then(b.mean_rssi.total_cmp(&a.mean_rssi)));
+        SurveyReport { started_unix_ms: self.started_unix_ms, waypoint: state.waypoint.clone(), samples: state.samples, cells }
+    }
+}
+
+fn unix_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+/// カンマ・引用符・改行を含む値を引用符で囲む
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// 集計をJSONで書き出す（書きかけのファイルが残らないよう一時ファイルから置き換える）
+async fn export_report(path: &Path, report: &SurveyReport) -> Result<()> {
+    let tmp_path = path.with_extension("json.tmp");
+    tokio::fs::write(&tmp_path, serde_json::to_vec_pretty(report)?)
+        .await
+        .with_context(|| format!("Failed to write survey report: {}", tmp_path.display()))?;
+    tokio::fs::rename(&tmp_path, path)
+        .await
+        .with_context(|| format!("Failed to replace survey report: {}", path.display()))?;
+    Ok(())
+}
+
+/// 受信したビーコンのRSSIをCSVに記録し続け、地点・ビーコンごとの集計をJSONに書き出すタスク
+///
+/// 記録は `<dir>/survey-<開始時のUNIX時刻>.csv`、集計は同じ名前の `.json` に書き出す。
+/// 終了手順がBLEの停止に達したら、最後の集計を書き出して終了する。
+#[instrument(skip_all)]
+pub async fn survey_main(
+    config: SurveyConfig,
+    survey: Arc<Survey>,
+    mut rx: broadcast::Receiver<Arc<DeviceSnapshot>>,
+    sound_map: Arc<SharedSoundMap>,
+    shutdown: Arc<ShutdownController>,
+) -> Result<()> {
+    let dir = PathBuf::from(&config.dir);
+    tokio::fs::create_dir_all(&dir)
+        .await
+        .with_context(|| format!("Failed to create survey directory: {}", dir.display()))?;
+    let csv_path = dir.join(format!("survey-{}.csv", survey.started_unix_ms));
+    let json_path = csv_path.with_extension("json");
+    let file = tokio::fs::File::create(&csv_path)
+        .await
+        .with_context(|| format!("Failed to create survey log: {}", csv_path.display()))?;
+    let mut csv = tokio::io::BufWriter::new(file);
+    csv.write_all(CSV_HEADER.as_bytes()).await?;
+    info!(csv = %csv_path.display(), json = %json_path.display(), "Survey started - set waypoints via POST /api/survey/waypoint");
+
+    let mut export = tokio::time::interval(Duration::from_millis(config.export_interval_ms.max(1000)));
+    export.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    let stop = shutdown.reached(ShutdownPhase::StopBle);
+    tokio::pin!(stop);
+
+    loop {
+        tokio::select! {
+            result = rx.recv() => match result {
+                Ok(snapshot) => {
+                    let rows = survey.record(&snapshot, &sound_map);
+                    if rows.is_empty() {
+                        continue;
+                    }
+                    // 異常終了しても直前までの記録が残るよう、スナップショットごとに書き出す
+                    csv.write_all(rows.as_bytes()).await?;
+                    csv.flush().await?;
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    metrics().record_lag(LagReceiver::Survey, skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            _ = export.tick() => {
+                if let Err(e) = export_report(&json_path, &survey.report(&sound_map)).await {
+                    warn!("Failed to export survey report: {:?}", e);
+                }
+            }
+            _ = &mut stop => break,
+        }
+    }
+
+    csv.flush().await?;
+    let report = survey.report(&sound_map);
+    export_report(&json_path, &report).await?;
+    info!(samples = report.samples, cells = report.cells.len(), json = %json_path.display(), "Survey finished");
+    Ok(())
+}
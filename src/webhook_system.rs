@@ -0,0 +1 @@
+pub mod webhook_main;
\ No newline at end of file
@@ -0,0 +1,112 @@
+use crate::config_system::config_main::{WebhookConfig, WebhookTarget};
+use crate::messages::EnabledState;
+use crate::event_system::event_bus::{EventBus, LocalEvent};
+use crate::net_system::egress::http_client;
+use serde_json::Value;
+use std::time::Duration;
+use tokio::sync::{broadcast, watch};
+use tracing::{debug, info, instrument, warn};
+
+/// ペイロードのテンプレート中の `${フィールド名}` をイベントの値で置き換える
+pub fn render_payload(template: &Value, event: &Value) -> Value {
+    match template {
+        Value::String(text) => {
+            // 文字列全体が1つのプレースホルダの場合は、元の型（数値・真偽値）のまま埋め込む
+            if let Some(value) = text
+                .strip_prefix("${")
+                .and_then(|rest| rest.strip_suffix('}'))
+                .and_then(|field| event.get(field))
+            {
+                return value.clone();
+            }
+            let mut rendered = text.clone();
+            if let Some(fields) = event.as_object() {
+                for (field, value) in fields {
+                    let replacement = match value {
+                        Value::String(s) => s.clone(),
+                        other => other.to_string(),
+                    };
+                    rendered = rendered.replace(&format!("${{{}}}", field), &replacement);
+                }
+            }
+            Value::String(rendered)
+        }
+        Value::Array(items) => Value::Array(items.iter().map(|item| render_payload(item, event)).collect()),
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(key, value)| (key.clone(), render_payload(value, event)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+/// 1つのWebhookにイベントをPOSTする
+async fn dispatch(client: &reqwest::Client, target: &WebhookTarget, event: &Value, timeout: Duration) {
+    let body = match &target.payload {
+        Some(template) => render_payload(template, event),
+        None => event.clone(),
+    };
+    debug!(url = %target.url, %body, "Sending webhook");
+    match client.post(&target.url).json(&body).timeout(timeout).send().await {
+        Ok(response) if response.status().is_success() => {}
+        Ok(response) => warn!(url = %target.url, status = %response.status(), "Webhook returned an error status"),
+        Err(e) => warn!(url = %target.url, "Failed to send webhook: {}", e),
+    }
+}
+
+/// ローカルイベントを設定されたWebhookへ配信するタスク
+#[instrument(skip(config, events, enabled_rx))]
+pub async fn webhook_main(
+    config: WebhookConfig,
+    events: EventBus,
+    mut enabled_rx: watch::Receiver<EnabledState>,
+) {
+    let mut rx = events.subscribe("webhook");
+    info!(targets = config.targets.len(), "Webhook dispatcher started");
+    let client = http_client();
+    let timeout = Duration::from_millis(config.timeout_ms);
+    let mut enabled = enabled_rx.borrow_and_update().enabled;
+
+    loop {
+        let event = tokio::select! {
+            event = rx.recv() => match event {
+                Ok(event) => event,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!(skipped, "Webhook dispatcher lagged - some events were not sent");
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+            Ok(()) = enabled_rx.changed() => {
+                let now_enabled = enabled_rx.borrow_and_update().enabled;
+                if now_enabled == enabled {
+                    continue;
+                }
+                enabled = now_enabled;
+                LocalEvent::EnabledChanged { enabled }
+            }
+        };
+
+        let name = event.name();
+        let value = match serde_json::to_value(&event) {
+            Ok(value) => value,
+            Err(e) => {
+                warn!(event = name, "Failed to serialize webhook event: {}", e);
+                continue;
+            }
+        };
+        for target in config
+            .targets
+            .iter()
+            .filter(|target| target.events.is_empty() || target.events.iter().any(|e| e == name))
+        {
+            // 照明コントローラなどの応答待ちで後続のイベントを遅らせないよう、送信ごとにタスクを分ける
+            let client = client.clone();
+            let target = target.clone();
+            let value = value.clone();
+            tokio::spawn(async move { dispatch(&client, &target, &value, timeout).await });
+        }
+    }
+    info!("Webhook dispatcher stopped");
+}